@@ -0,0 +1,297 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Measures how many blocks per second a sync verifies the BABE consensus of, the blocks being
+//! verified one after the other in the same way as `OptimisticFullSync::process_one` does.
+//!
+//! - Before: every block is entirely verified on the sync task.
+//! - After: batches of blocks are pre-verified on a thread pool, in the same way as the full node
+//!   does by means of `OptimisticFullSync::next_pre_verification`. Blocks are dispatched as soon as
+//!   they are queued, the sync downloading up to `DOWNLOAD_AHEAD` blocks ahead of the one being
+//!   verified. The sync task uses the outcome of the pre-verification if it is ready by the time a
+//!   block is verified, and entirely verifies the block otherwise.
+//!
+//! Since pre-verification doesn't reduce the total amount of work, only the number of CPU cores
+//! makes the "after" number higher than the "before" one. The upper bound, reached when the
+//! thread pool always finishes ahead of the sync task, is measured by verifying blocks whose
+//! seals have all been pre-verified beforehand.
+//!
+//! Run with `cargo bench --bench babe_pre_verify`.
+
+use futures::{channel::oneshot, executor::ThreadPool, FutureExt as _};
+use parity_scale_codec::Encode as _;
+use std::{
+    convert::TryFrom as _,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use substrate_lite::{
+    chain::chain_information::babe::BabeGenesisConfiguration, header, verify::babe,
+};
+
+const NUM_AUTHORITIES: u8 = 100;
+const NUM_BLOCKS: u64 = 4000;
+const BATCH_SIZE: usize = 32;
+/// Same value as the `download_ahead_blocks` of the full node.
+const DOWNLOAD_AHEAD: usize = 1024;
+const EPOCH_RANDOMNESS: [u8; 32] = [0x2a; 32];
+
+fn main() {
+    let genesis_config = BabeGenesisConfiguration::from_components(
+        6000,
+        NUM_BLOCKS * 2,
+        (1, 4),
+        keypairs()
+            .iter()
+            .map(|keypair| (keypair.public.to_bytes(), 1))
+            .collect(),
+        EPOCH_RANDOMNESS,
+        header::BabeAllowedSlots::PrimaryAndSecondaryVRFSlots,
+    );
+    let headers = generate_blocks();
+
+    let before = {
+        let now = Instant::now();
+        verify_chain(&genesis_config, &headers, |_| None);
+        headers.len() as f64 / now.elapsed().as_secs_f64()
+    };
+
+    let after = {
+        let threads_pool = ThreadPool::new().unwrap();
+        let headers = Arc::new(headers.clone());
+        let epoch = Arc::new(epoch0());
+
+        let now = Instant::now();
+        let mut pre_verifications = Vec::new();
+        let mut pre_verified = vec![None; headers.len()];
+        let num_pre_verified = verify_chain(&genesis_config, &headers, |index| {
+            // Dispatch the blocks that have been queued since the previous block.
+            let num_queued = (index + DOWNLOAD_AHEAD).min(headers.len());
+            while pre_verifications.len() * BATCH_SIZE < num_queued {
+                let start = pre_verifications.len() * BATCH_SIZE;
+                let end = (start + BATCH_SIZE).min(headers.len());
+                let headers = headers.clone();
+                let epoch = epoch.clone();
+                let (tx, rx) = oneshot::channel();
+                threads_pool.spawn_ok(async move {
+                    let _ = tx.send(
+                        headers[start..end]
+                            .iter()
+                            .map(|h| pre_verify(&epoch, h))
+                            .collect::<Vec<_>>(),
+                    );
+                });
+                pre_verifications.push(Some(rx));
+            }
+
+            let batch = index / BATCH_SIZE;
+            if let Some(rx) = &mut pre_verifications[batch] {
+                if let Some(Ok(outcome)) = rx.now_or_never() {
+                    for (n, seal) in outcome.into_iter().enumerate() {
+                        pre_verified[batch * BATCH_SIZE + n] = seal;
+                    }
+                    pre_verifications[batch] = None;
+                }
+            }
+            pre_verified[index].take()
+        });
+        let blocks_per_sec = headers.len() as f64 / now.elapsed().as_secs_f64();
+        println!(
+            "{} blocks out of {} were pre-verified in time",
+            num_pre_verified,
+            headers.len()
+        );
+        blocks_per_sec
+    };
+
+    let upper_bound = {
+        let epoch = epoch0();
+        let mut pre_verified = headers
+            .iter()
+            .map(|h| pre_verify(&epoch, h))
+            .collect::<Vec<_>>();
+        let now = Instant::now();
+        verify_chain(&genesis_config, &headers, |index| {
+            pre_verified[index].take()
+        });
+        headers.len() as f64 / now.elapsed().as_secs_f64()
+    };
+
+    println!("before:      {:.0} blocks/sec", before);
+    println!("after:       {:.0} blocks/sec", after);
+    println!("upper bound: {:.0} blocks/sec", upper_bound);
+}
+
+/// Verifies the given chain of SCALE-encoded headers in order, using the outcome of the
+/// pre-verification of each block returned by `pre_verified`. Returns the number of blocks for
+/// which a pre-verification was available.
+fn verify_chain(
+    genesis_config: &BabeGenesisConfiguration,
+    headers: &[Vec<u8>],
+    mut pre_verified: impl FnMut(usize) -> Option<babe::PreVerifiedSeal>,
+) -> usize {
+    let genesis = genesis_header();
+    let mut block1_slot_number = None;
+    let mut parent_randomness_accumulator = None;
+    let mut num_pre_verified = 0;
+
+    for (index, scale_encoded_header) in headers.iter().enumerate() {
+        let pre_verified_seal = pre_verified(index);
+        if pre_verified_seal.is_some() {
+            num_pre_verified += 1;
+        }
+
+        let parent = if index == 0 {
+            &genesis
+        } else {
+            &headers[index - 1]
+        };
+
+        let success = match babe::start_verify_header(babe::VerifyConfig {
+            header: header::decode(scale_encoded_header).unwrap(),
+            now_from_unix_epoch: Duration::from_secs(1 << 32),
+            parent_block_header: header::decode(parent).unwrap(),
+            genesis_configuration: genesis_config,
+            block1_slot_number,
+            parent_disabled_authorities: &[],
+            parent_randomness_accumulator: parent_randomness_accumulator.as_ref(),
+            pre_verified_seal: pre_verified_seal.as_ref(),
+        }) {
+            Ok(babe::SuccessOrPending::Success(success)) => success,
+            _ => panic!(),
+        };
+
+        block1_slot_number = block1_slot_number.or(Some(success.slot_number));
+        parent_randomness_accumulator = success.randomness_accumulator;
+    }
+
+    num_pre_verified
+}
+
+/// Pre-verifies the given SCALE-encoded header, belonging to epoch 0.
+fn pre_verify(epoch: &[u8], scale_encoded_header: &[u8]) -> Option<babe::PreVerifiedSeal> {
+    babe::pre_verify_seal(babe::PreVerifyConfig {
+        header: header::decode(scale_encoded_header).unwrap(),
+        epoch_number: 0,
+        epoch_info: header::BabeNextEpochRef::from_slice(epoch).unwrap(),
+    })
+}
+
+fn keypairs() -> Vec<schnorrkel::Keypair> {
+    (0..NUM_AUTHORITIES)
+        .map(|n| {
+            schnorrkel::MiniSecretKey::from_bytes(&[n; 32])
+                .unwrap()
+                .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+        })
+        .collect()
+}
+
+/// Returns the SCALE encoding of the information about epoch 0.
+fn epoch0() -> Vec<u8> {
+    let mut epoch = parity_scale_codec::Compact(u64::from(NUM_AUTHORITIES)).encode();
+    for keypair in &keypairs() {
+        epoch.extend_from_slice(&keypair.public.to_bytes());
+        epoch.extend_from_slice(&1u64.to_le_bytes());
+    }
+    epoch.extend_from_slice(&EPOCH_RANDOMNESS);
+    epoch
+}
+
+fn genesis_header() -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&[0; 32]);
+    parity_scale_codec::Compact(0u64).encode_to(&mut out);
+    out.extend_from_slice(&[1; 32]);
+    out.extend_from_slice(&[2; 32]);
+    parity_scale_codec::Compact(0u64).encode_to(&mut out);
+    out
+}
+
+/// Generates a chain of SCALE-encoded headers of blocks of epoch 0 using BABE secondary VRF slot
+/// claims, each authored by the authority the slot is assigned to.
+fn generate_blocks() -> Vec<Vec<u8>> {
+    let keypairs = keypairs();
+    let mut parent_hash = header::hash_from_scale_encoded_header(genesis_header());
+
+    (1..=NUM_BLOCKS)
+        .map(|block_number| {
+            let slot_number = 1000 + block_number;
+            let authority_index = {
+                let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
+                hash.update(&EPOCH_RANDOMNESS);
+                hash.update(&slot_number.to_le_bytes());
+                let hash = primitive_types::U256::from_big_endian(hash.finalize().as_bytes());
+                (hash % primitive_types::U256::from(NUM_AUTHORITIES)).as_u32()
+            };
+            let keypair = &keypairs[usize::try_from(authority_index).unwrap()];
+
+            let (vrf_in_out, vrf_proof, _) = keypair.vrf_sign({
+                let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
+                transcript.append_u64(b"slot number", slot_number);
+                transcript.append_u64(b"current epoch", 0);
+                transcript.append_message(b"chain randomness", &EPOCH_RANDOMNESS[..]);
+                transcript
+            });
+
+            let mut pre_digest = vec![3];
+            pre_digest.extend_from_slice(&authority_index.to_le_bytes());
+            pre_digest.extend_from_slice(&slot_number.to_le_bytes());
+            pre_digest.extend_from_slice(&vrf_in_out.to_output().to_bytes());
+            pre_digest.extend_from_slice(&vrf_proof.to_bytes());
+
+            // Block #1 announces the information about epoch 1, which is identical to epoch 0.
+            let next_epoch = if block_number == 1 {
+                let mut log = vec![1];
+                log.extend_from_slice(&epoch0());
+                Some(log)
+            } else {
+                None
+            };
+
+            let encode = |seal: Option<&[u8; 64]>| {
+                let mut out = Vec::new();
+                out.extend_from_slice(&parent_hash);
+                parity_scale_codec::Compact(block_number).encode_to(&mut out);
+                out.extend_from_slice(&[1; 32]);
+                out.extend_from_slice(&[2; 32]);
+                let num_items = 1 + u64::from(next_epoch.is_some()) + u64::from(seal.is_some());
+                parity_scale_codec::Compact(num_items).encode_to(&mut out);
+                out.push(6);
+                out.extend_from_slice(b"BABE");
+                pre_digest.encode_to(&mut out);
+                if let Some(next_epoch) = &next_epoch {
+                    out.push(4);
+                    out.extend_from_slice(b"BABE");
+                    next_epoch.encode_to(&mut out);
+                }
+                if let Some(seal) = seal {
+                    out.push(5);
+                    out.extend_from_slice(b"BABE");
+                    seal[..].encode_to(&mut out);
+                }
+                out
+            };
+
+            let pre_seal_hash = header::hash_from_scale_encoded_header(encode(None));
+            let seal = keypair.sign_simple(b"substrate", &pre_seal_hash).to_bytes();
+            let scale_encoded_header = encode(Some(&seal));
+            parent_hash = header::hash_from_scale_encoded_header(&scale_encoded_header);
+            scale_encoded_header
+        })
+        .collect()
+}
@@ -26,12 +26,15 @@ use futures::{
 };
 use libp2p::wasm_ext::{ffi, ExtTransport};
 use std::{
+    cmp,
     collections::HashMap,
     num::{NonZeroU32, NonZeroU64},
+    time::Duration,
 };
 use substrate_lite::{
-    chain, chain::chain_information::babe, chain::sync::headers_optimistic, chain_spec, database,
-    header, json_rpc, network,
+    chain,
+    chain::sync::{headers_optimistic, light},
+    chain_spec, database, header, json_rpc, metadata, network,
 };
 use wasm_bindgen::prelude::*;
 
@@ -42,6 +45,10 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 #[wasm_bindgen]
 pub struct BrowserLightClient {
     chain_spec: chain_spec::ChainSpec,
+    /// Channel used to send on-demand requests to the sync task.
+    to_sync: mpsc::Sender<ToSync>,
+    /// Channel used to modify the reserved peers of the network task.
+    to_network: mpsc::Sender<ToNetwork>,
 }
 
 // TODO: several places in this module where we unwrap when we shouldn't
@@ -79,21 +86,12 @@ pub async fn start_client(chain_spec: String) -> Result<BrowserLightClient, JsVa
 
     // Load the information about the chain from the local storage, or build the information of
     // the genesis block.
-    let chain_information = match local_storage.chain_information() {
-        Ok(Some(i)) => {
-            let babe_genesis_config = babe::BabeGenesisConfiguration::from_genesis_storage(|k| {
-                chain_spec
-                    .genesis_storage()
-                    .find(|(k2, _)| *k2 == k)
-                    .map(|(_, v)| v.to_owned())
-            })
-            .unwrap();
-
-            chain::chain_information::ChainInformationConfig {
-                chain_information: i,
-                babe_genesis_config,
-            }
-        }
+    let mut chain_information = match local_storage.chain_information() {
+        Ok(Some(i)) => chain::chain_information::ChainInformationConfig {
+            chain_information: i,
+            bad_blocks: Default::default(),
+            fork_blocks: Default::default(),
+        },
         Err(database::local_storage_light::AccessError::StorageAccess(err)) => {
             return Err(err.into())
         }
@@ -106,30 +104,57 @@ pub async fn start_client(chain_spec: String) -> Result<BrowserLightClient, JsVa
         }
     };
 
+    // Load the blocks that had been verified but not finalized yet, in order to not download and
+    // verify them again. The snapshot is only restored if it matches the chain information
+    // loaded above.
+    // TODO: log why storage access failed?
+    let non_finalized_blocks = local_storage.non_finalized_blocks().unwrap_or(None);
+
+    // Blocks that must be rejected or followed, according to the chain specs.
+    chain_information
+        .bad_blocks
+        .extend(chain_spec.bad_blocks().copied());
+    chain_information
+        .fork_blocks
+        .extend(chain_spec.fork_blocks().map(|(n, h)| (n, *h)));
+
     let (to_sync_tx, to_sync_rx) = mpsc::channel(64);
     let (to_network_tx, to_network_rx) = mpsc::channel(64);
     let (to_db_save_tx, mut to_db_save_rx) = mpsc::channel(16);
 
-    wasm_bindgen_futures::spawn_local(start_network(&chain_spec, to_network_rx, to_sync_tx).await);
+    wasm_bindgen_futures::spawn_local(
+        start_network(&chain_spec, to_network_rx, to_sync_tx.clone()).await,
+    );
     wasm_bindgen_futures::spawn_local(
         start_sync(
             &chain_spec,
             chain_information,
+            non_finalized_blocks,
             to_sync_rx,
-            to_network_tx,
+            to_network_tx.clone(),
             to_db_save_tx,
         )
         .await,
     );
 
     wasm_bindgen_futures::spawn_local(async move {
-        while let Some(info) = to_db_save_rx.next().await {
+        while let Some((info, non_finalized_blocks)) = to_db_save_rx.next().await {
             // TODO: how to handle errors?
             local_storage.set_chain_information((&info).into()).unwrap();
+            // If the non-finalized blocks have been discarded, the previously-saved snapshot is
+            // kept. It either still matches the finalized block and is valid, or is rejected
+            // when loaded.
+            if let Some(snapshot) = non_finalized_blocks {
+                local_storage.set_non_finalized_blocks(&snapshot).unwrap();
+            }
         }
     });
 
-    Ok(BrowserLightClient { chain_spec })
+    Ok(BrowserLightClient {
+        chain_spec,
+        to_sync: to_sync_tx,
+        to_network: to_network_tx,
+    })
 }
 
 #[wasm_bindgen]
@@ -145,26 +170,135 @@ impl BrowserLightClient {
             .map_err(|err| JsValue::from_str(&err.to_string()))?;
 
         let response = match call {
+            json_rpc::methods::MethodCall::state_getMetadata {} => {
+                let request_id = request_id.to_owned();
+                let response = self.on_demand(
+                    None,
+                    OnDemandKind::Call {
+                        method: "Metadata_metadata".to_owned(),
+                        parameter: Vec::new(),
+                    },
+                );
+                async move {
+                    let output = match response.await? {
+                        light::OnDemandResponse::Call(output) => output,
+                        _ => unreachable!(),
+                    };
+                    let metadata =
+                        metadata::remove_length_prefix(&output).map_err(|err| err.to_string())?;
+                    Ok(json_rpc::methods::Response::state_getMetadata(
+                        json_rpc::methods::HexString(metadata.to_vec()),
+                    )
+                    .to_json_response(&request_id))
+                }
+                .boxed()
+            }
+            json_rpc::methods::MethodCall::state_getStorage { key, hash } => {
+                let request_id = request_id.to_owned();
+                let response = self.on_demand(
+                    hash.map(|h| h.0),
+                    OnDemandKind::StorageRead { keys: vec![key.0] },
+                );
+                async move {
+                    let value = match response.await? {
+                        light::OnDemandResponse::StorageRead(mut values) => values.pop().unwrap(),
+                        _ => unreachable!(),
+                    };
+                    // TODO: should return `null` if there is no storage value
+                    Ok(
+                        json_rpc::methods::Response::state_getStorage(
+                            json_rpc::methods::HexString(value.unwrap_or_default()),
+                        )
+                        .to_json_response(&request_id),
+                    )
+                }
+                .boxed()
+            }
             json_rpc::methods::MethodCall::system_chain {} => {
                 let value = json_rpc::methods::Response::system_chain(self.chain_spec.name())
                     .to_json_response(request_id);
-                async move { value }.boxed()
+                async move { Ok(value) }.boxed()
             }
             json_rpc::methods::MethodCall::system_chainType {} => {
                 let value =
                     json_rpc::methods::Response::system_chainType(self.chain_spec.chain_type())
                         .to_json_response(request_id);
-                async move { value }.boxed()
+                async move { Ok(value) }.boxed()
             }
             json_rpc::methods::MethodCall::system_name {} => {
                 let value = json_rpc::methods::Response::system_name("Polkadot ✨ lite ✨")
                     .to_json_response(request_id);
-                async move { value }.boxed()
+                async move { Ok(value) }.boxed()
+            }
+            json_rpc::methods::MethodCall::system_addReservedPeer { peer } => {
+                let request_id = request_id.to_owned();
+                let mut to_network = self.to_network.clone();
+                async move {
+                    let (peer_id, address) =
+                        network::parse_str_addr(&peer).map_err(|err| err.to_string())?;
+                    to_network
+                        .send(ToNetwork::AddReservedPeer { peer_id, address })
+                        .await
+                        .map_err(|_| "Network task has stopped".to_owned())?;
+                    Ok(json_rpc::methods::Response::system_addReservedPeer(())
+                        .to_json_response(&request_id))
+                }
+                .boxed()
+            }
+            json_rpc::methods::MethodCall::system_removeReservedPeer { peer_id } => {
+                let request_id = request_id.to_owned();
+                let mut to_network = self.to_network.clone();
+                async move {
+                    let peer_id = peer_id
+                        .parse::<network::PeerId>()
+                        .map_err(|_| "Invalid peer id".to_owned())?;
+                    to_network
+                        .send(ToNetwork::RemoveReservedPeer { peer_id })
+                        .await
+                        .map_err(|_| "Network task has stopped".to_owned())?;
+                    Ok(json_rpc::methods::Response::system_removeReservedPeer(())
+                        .to_json_response(&request_id))
+                }
+                .boxed()
+            }
+            json_rpc::methods::MethodCall::system_networkState {} => {
+                let request_id = request_id.to_owned();
+                let network_state = self.network_state();
+                async move {
+                    Ok(
+                        json_rpc::methods::Response::system_networkState(network_state.await?)
+                            .to_json_response(&request_id),
+                    )
+                }
+                .boxed()
+            }
+            json_rpc::methods::MethodCall::system_peers {} => {
+                let request_id = request_id.to_owned();
+                let network_state = self.network_state();
+                async move {
+                    // Only the peers that have sent us their `Status` are reported.
+                    let peers = network_state
+                        .await?
+                        .connected_peers
+                        .into_iter()
+                        .filter_map(|(peer_id, peer)| {
+                            Some(json_rpc::methods::SystemPeer {
+                                peer_id,
+                                roles: peer.roles?,
+                                best_hash: json_rpc::methods::HashHexString(peer.best_hash?.0),
+                                best_number: peer.best_number?,
+                            })
+                        })
+                        .collect();
+                    Ok(json_rpc::methods::Response::system_peers(peers)
+                        .to_json_response(&request_id))
+                }
+                .boxed()
             }
             json_rpc::methods::MethodCall::system_version {} => {
                 let value =
                     json_rpc::methods::Response::system_version("??").to_json_response(request_id);
-                async move { value }.boxed()
+                async move { Ok(value) }.boxed()
             }
             // TODO: implement the rest
             _ => {
@@ -176,7 +310,10 @@ impl BrowserLightClient {
         };
 
         Ok(wasm_bindgen_futures::future_to_promise(async move {
-            Ok(JsValue::from_str(&response.await))
+            match response.await {
+                Ok(response) => Ok(JsValue::from_str(&response)),
+                Err(err) => Err(JsValue::from_str(&err)),
+            }
         }))
     }
 
@@ -193,35 +330,114 @@ impl BrowserLightClient {
     }
 }
 
+impl BrowserLightClient {
+    /// Sends an on-demand request to the sync task, and returns a future that yields the
+    /// verified response. If `block_hash` is `None`, the request targets the current best block.
+    fn on_demand(
+        &self,
+        block_hash: Option<[u8; 32]>,
+        request: OnDemandKind,
+    ) -> impl Future<Output = Result<light::OnDemandResponse, String>> {
+        let mut to_sync = self.to_sync.clone();
+        async move {
+            let (tx, rx) = oneshot::channel();
+            to_sync
+                .send(ToSync::OnDemand {
+                    block_hash,
+                    request,
+                    send_back: tx,
+                })
+                .await
+                .map_err(|_| "Sync task has stopped".to_owned())?;
+            rx.await.map_err(|_| "Sync task has stopped".to_owned())?
+        }
+    }
+
+    /// Asks the network task for a snapshot of the state of the network.
+    fn network_state(&self) -> impl Future<Output = Result<network::NetworkState, String>> {
+        let mut to_network = self.to_network.clone();
+        async move {
+            let (tx, rx) = oneshot::channel();
+            to_network
+                .send(ToNetwork::NetworkState { send_back: tx })
+                .await
+                .map_err(|_| "Network task has stopped".to_owned())?;
+            rx.await.map_err(|_| "Network task has stopped".to_owned())
+        }
+    }
+}
+
 async fn start_sync(
     chain_spec: &chain_spec::ChainSpec,
     chain_information_config: chain::chain_information::ChainInformationConfig,
+    non_finalized_blocks: Option<Vec<u8>>,
     mut to_sync: mpsc::Receiver<ToSync>,
     mut to_network: mpsc::Sender<ToNetwork>,
-    mut to_db_save_tx: mpsc::Sender<chain::chain_information::ChainInformation>,
+    mut to_db_save_tx: mpsc::Sender<(chain::chain_information::ChainInformation, Option<Vec<u8>>)>,
 ) -> impl Future<Output = ()> {
-    let mut sync = headers_optimistic::OptimisticHeadersSync::<_, network::PeerId>::new(
-        headers_optimistic::Config {
-            chain_information_config,
-            sources_capacity: 32,
-            source_selection_randomness_seed: rand::random(),
-            blocks_request_granularity: NonZeroU32::new(128).unwrap(),
-            download_ahead_blocks: {
-                // Assuming a verification speed of 1k blocks/sec and a 95% latency of one second,
-                // the number of blocks to download ahead of time in order to not block is 1000.
-                1024
+    let config = |chain_information_config: chain::chain_information::ChainInformationConfig| {
+        light::Config {
+            headers_sync: headers_optimistic::Config {
+                chain_information_config,
+                sources_capacity: 32,
+                source_selection_randomness_seed: rand::random(),
+                blocks_request_granularity: NonZeroU32::new(128).unwrap(),
+                download_ahead_blocks: {
+                    // Assuming a verification speed of 1k blocks/sec and a 95% latency of one
+                    // second, the number of blocks to download ahead of time in order to not
+                    // block is 1000.
+                    1024
+                },
+                generate_events: false,
+                // Number of blocks that must be verified again after a restart if the finality
+                // stalls.
+                max_non_finalized_blocks: 2048,
             },
-        },
-    );
+            headers_capacity: 2048,
+            cache_capacity: 256,
+            on_demand_max_attempts: NonZeroU32::new(3).unwrap(),
+        }
+    };
+
+    let restored =
+        non_finalized_blocks.and_then(|snapshot| {
+            match light::LightSync::from_snapshot(
+                config(chain_information_config.clone()),
+                &snapshot,
+            ) {
+                Ok(sync) => Some(sync),
+                Err(err) => {
+                    web_sys::console::warn_1(&JsValue::from_str(&format!(
+                        "Failed to restore the non-finalized blocks: {}",
+                        err
+                    )));
+                    None
+                }
+            }
+        });
+    let mut sync = match restored {
+        Some(sync) => sync,
+        None => light::LightSync::<_, network::PeerId>::new(config(chain_information_config)),
+    };
 
     async move {
         let mut peers_source_id_map = HashMap::new();
         let mut block_requests_finished = stream::FuturesUnordered::new();
+        let mut on_demand_requests_finished = stream::FuturesUnordered::new();
+        // Channels where to send back the response of the on-demand requests in progress.
+        let mut on_demand_send_back = HashMap::new();
+        // At most one finality proof request is in progress at any given time.
+        let mut finality_proof_requests_finished = stream::FuturesUnordered::new();
+        // Time, since the Unix Epoch, before which no finality proof request is sent, and delay
+        // to wait after the next failed request. The delay doubles after each failure, in order
+        // to not flood the peers with requests they can't answer, and is reset after a success.
+        let mut finality_proof_next_attempt = Duration::new(0, 0);
+        let mut finality_proof_backoff = Duration::from_secs(2);
 
         loop {
             while let Some(action) = sync.next_request_action() {
                 match action {
-                    headers_optimistic::RequestAction::Start {
+                    light::RequestAction::Start {
                         start,
                         block_height,
                         source,
@@ -242,17 +458,35 @@ async fn start_sync(
                         let request_id = start.start(abort);
                         block_requests_finished.push(rx.map(move |r| (request_id, r)));
                     }
-                    headers_optimistic::RequestAction::Cancel { user_data, .. } => {
+                    light::RequestAction::Cancel { user_data, .. } => {
                         user_data.abort();
                     }
                 }
             }
 
+            while let Some(start) = sync.next_on_demand_request() {
+                let (tx, rx) = oneshot::channel();
+                let _ = to_network
+                    .send(ToNetwork::StartOnDemandRequest {
+                        peer_id: start.source().clone(),
+                        request: start.request().clone(),
+                        send_back: tx,
+                    })
+                    .await;
+
+                let (rx, abort) = future::abortable(rx);
+                let id = start.id();
+                start.start(abort);
+                on_demand_requests_finished.push(rx.map(move |r| (id, r)));
+            }
+
             // Verify blocks that have been fetched from queries.
             loop {
-                match sync.process_one() {
-                    headers_optimistic::ProcessOneOutcome::Idle => break,
-                    headers_optimistic::ProcessOneOutcome::Updated {
+                // `std::time::SystemTime` isn't available in the browser.
+                let now_from_unix_epoch = Duration::from_secs_f64(js_sys::Date::now() / 1000.0);
+                match sync.process_one(now_from_unix_epoch) {
+                    light::ProcessOneOutcome::Idle => break,
+                    light::ProcessOneOutcome::Updated {
                         best_block_hash,
                         best_block_number,
                         ..
@@ -261,12 +495,26 @@ async fn start_sync(
                             "Chain state update: #{} {:?}",
                             best_block_number, best_block_hash
                         )));
+                        let _ = to_network
+                            .send(ToNetwork::SetLocalBestBlock {
+                                number: best_block_number,
+                                hash: best_block_hash,
+                            })
+                            .await;
                     }
-                    headers_optimistic::ProcessOneOutcome::Reset {
+                    light::ProcessOneOutcome::Reset {
                         reason,
                         new_best_block_hash,
                         new_best_block_number,
+                        punished_source,
                     } => {
+                        if let Some(punishment) = punished_source {
+                            let peer_id = sync.source_user_data(punishment.source_id).clone();
+                            let _ = to_network
+                                .send(ToNetwork::report_peer(peer_id, &punishment))
+                                .await;
+                        }
+
                         web_sys::console::warn_1(&JsValue::from_str(&format!(
                             "⚠️ Sync error ⚠️ {}",
                             reason
@@ -275,6 +523,12 @@ async fn start_sync(
                             "Chain state update: #{} {:?}",
                             new_best_block_number, new_best_block_hash
                         )));
+                        let _ = to_network
+                            .send(ToNetwork::SetLocalBestBlock {
+                                number: new_best_block_number,
+                                hash: new_best_block_hash,
+                            })
+                            .await;
                     }
                 }
 
@@ -285,8 +539,36 @@ async fn start_sync(
                 yield_once().await;
             }
 
+            // If the finality lags behind, which happens if the justification of a block that
+            // changes the list of GrandPa authorities is missing, ask a peer for a finality proof
+            // of the best block.
+            let now_from_unix_epoch = Duration::from_secs_f64(js_sys::Date::now() / 1000.0);
+            if finality_proof_requests_finished.is_empty()
+                && !peers_source_id_map.is_empty()
+                && now_from_unix_epoch >= finality_proof_next_attempt
+            {
+                if let Some(request) = sync.finality_proof_request() {
+                    let peer_id = peers_source_id_map
+                        .keys()
+                        .nth(rand::random::<usize>() % peers_source_id_map.len())
+                        .unwrap()
+                        .clone();
+                    let (tx, rx) = oneshot::channel();
+                    let _ = to_network
+                        .send(ToNetwork::StartFinalityProofRequest {
+                            peer_id,
+                            request,
+                            send_back: tx,
+                        })
+                        .await;
+                    finality_proof_requests_finished.push(rx);
+                }
+            }
+
             // TODO: save less often
-            let _ = to_db_save_tx.send(sync.as_chain_information().into()).await;
+            let _ = to_db_save_tx
+                .send((sync.as_chain_information().into(), sync.snapshot()))
+                .await;
 
             futures::select! {
                 message = to_sync.next() => {
@@ -303,10 +585,40 @@ async fn start_sync(
                         ToSync::PeerDisconnected(peer_id) => {
                             let id = peers_source_id_map.remove(&peer_id).unwrap();
                             let (_, rq_list) = sync.remove_source(id);
-                            for (_, rq) in rq_list {
+                            for rq in rq_list {
                                 rq.abort();
                             }
                         },
+                        ToSync::PeerBestBlock { peer_id, best_number } => {
+                            // The status of a peer might be received before the peer has been
+                            // added as a source.
+                            if let Some(id) = peers_source_id_map.get(&peer_id) {
+                                sync.set_source_best_block(*id, best_number);
+                            }
+                        },
+                        ToSync::OnDemand { block_hash, request, send_back } => {
+                            let block_hash = block_hash.unwrap_or_else(|| sync.best_block_hash());
+                            let request = match request {
+                                OnDemandKind::StorageRead { keys } => {
+                                    light::OnDemandRequest::StorageRead { block_hash, keys }
+                                }
+                                OnDemandKind::Call { method, parameter } => {
+                                    light::OnDemandRequest::Call { block_hash, method, parameter }
+                                }
+                            };
+
+                            match sync.queue_request(request) {
+                                Ok(light::Queued::Cached(response)) => {
+                                    let _ = send_back.send(Ok(response));
+                                }
+                                Ok(light::Queued::Pending(id)) => {
+                                    on_demand_send_back.insert(id, send_back);
+                                }
+                                Err(err) => {
+                                    let _ = send_back.send(Err(err.to_string()));
+                                }
+                            }
+                        },
                     }
                 },
 
@@ -314,7 +626,78 @@ async fn start_sync(
                     // `result` is an error if the block request got cancelled by the sync state
                     // machine.
                     if let Ok(result) = result {
-                        let _ = sync.finish_request(request_id, result.unwrap().map(|v| v.into_iter()));
+                        let outcome = sync.finish_request(request_id, result.unwrap().map(|v| v.into_iter()));
+                        if let light::FinishRequestOutcome::SourcePunished { source, punishment } = outcome {
+                            let message = ToNetwork::report_peer(source.clone(), &punishment);
+                            let _ = to_network.send(message).await;
+                        }
+                    }
+                },
+
+                result = finality_proof_requests_finished.select_next_some() => {
+                    // `result` is an error if the network task has shut down. The inner result is
+                    // `Ok(None)` if the peer couldn't generate a proof. In case of failure,
+                    // another request is sent after a delay.
+                    let now_from_unix_epoch = Duration::from_secs_f64(js_sys::Date::now() / 1000.0);
+                    let success = match result {
+                        Ok(Ok(Some(proof))) => {
+                            match sync.apply_finality_proof(&proof, now_from_unix_epoch) {
+                                Ok(success) => Some(success),
+                                Err(err) => {
+                                    web_sys::console::warn_1(&JsValue::from_str(&format!(
+                                        "Failed to apply finality proof: {}",
+                                        err
+                                    )));
+                                    None
+                                }
+                            }
+                        }
+                        _ => None,
+                    };
+
+                    if let Some(success) = success {
+                        web_sys::console::log_1(&JsValue::from_str(&format!(
+                            "Finalized #{} {:?} through a finality proof",
+                            success.finalized_block_number, success.finalized_block_hash
+                        )));
+                        // The proof might contain blocks that descend from the former best block.
+                        if let Some(best_header) = success.imported_headers.last() {
+                            let _ = to_network
+                                .send(ToNetwork::SetLocalBestBlock {
+                                    number: header::decode(best_header).unwrap().number,
+                                    hash: header::hash_from_scale_encoded_header(best_header),
+                                })
+                                .await;
+                        }
+                        finality_proof_backoff = Duration::from_secs(2);
+                    } else {
+                        finality_proof_next_attempt = now_from_unix_epoch + finality_proof_backoff;
+                        finality_proof_backoff =
+                            cmp::min(finality_proof_backoff * 2, Duration::from_secs(120));
+                    }
+                },
+
+                (id, result) = on_demand_requests_finished.select_next_some() => {
+                    // `result` is an error if the request got cancelled because the source has
+                    // been removed.
+                    if let Ok(result) = result {
+                        let (_, outcome) = sync.finish_on_demand_request(id, result.unwrap());
+                        let (punishment, response) = match outcome {
+                            light::FinishOnDemandOutcome::Success(response) => (None, Some(Ok(response))),
+                            light::FinishOnDemandOutcome::Retry { punishment, .. } => (Some(punishment), None),
+                            light::FinishOnDemandOutcome::Failed { punishment, error } => {
+                                (punishment, Some(Err(error.to_string())))
+                            }
+                        };
+
+                        if let Some(punishment) = punishment {
+                            let peer_id = sync.source_user_data(punishment.source_id).clone();
+                            let _ = to_network.send(ToNetwork::report_peer(peer_id, &punishment)).await;
+                        }
+                        if let Some(response) = response {
+                            let send_back = on_demand_send_back.remove(&id).unwrap();
+                            let _ = send_back.send(response);
+                        }
                     }
                 },
             }
@@ -325,6 +708,24 @@ async fn start_sync(
 enum ToSync {
     NewPeer(network::PeerId),
     PeerDisconnected(network::PeerId),
+    /// A peer has reported a new best block, either through its status or a block announce.
+    PeerBestBlock {
+        peer_id: network::PeerId,
+        best_number: u64,
+    },
+    /// Perform an on-demand request.
+    OnDemand {
+        /// Block targetted by the request, or `None` for the current best block.
+        block_hash: Option<[u8; 32]>,
+        request: OnDemandKind,
+        send_back: oneshot::Sender<Result<light::OnDemandResponse, String>>,
+    },
+}
+
+/// Same as [`light::OnDemandRequest`], but without the block hash.
+enum OnDemandKind {
+    StorageRead { keys: Vec<Vec<u8>> },
+    Call { method: String, parameter: Vec<u8> },
 }
 
 async fn start_network(
@@ -348,7 +749,24 @@ async fn start_network(
         ));
 
         network::Network::start(network::Config {
+            // TODO: persist the node key in the local storage
+            node_key: rand::random(),
+            authority_discovery_key: None,
+            // Browsers can't listen for incoming connections.
+            listen_addresses: Vec::new(),
+            public_addresses: Vec::new(),
             known_addresses,
+            // TODO: persist the address book in the local storage
+            address_book: Vec::new(),
+            reserved_peers: Vec::new(),
+            reserved_only: false,
+            // Browsers can't receive incoming connections anyway.
+            in_peers: 0,
+            out_peers: 25,
+            role: network::Role::Light,
+            answer_finality_proof_requests: false,
+            answer_blocks_requests: false,
+            memory_only: false,
             chain_spec_protocol_id: chain_spec.protocol_id().as_bytes().to_vec(),
             tasks_executor: Box::new(|fut| wasm_bindgen_futures::spawn_local(fut)),
             local_genesis_hash: substrate_lite::calculate_genesis_block_header(
@@ -358,11 +776,16 @@ async fn start_network(
             wasm_external_transport: Some(ExtTransport::new(ffi::websocket_transport())),
         })
         .await
+        // Can only fail when listening, which the browser node never does.
+        .unwrap()
     };
 
     async move {
         // TODO: store send back channel in a network user data rather than having this hashmap
         let mut block_requests = HashMap::new();
+        let mut body_requests = HashMap::new();
+        let mut on_demand_requests = HashMap::new();
+        let mut finality_proof_requests = HashMap::new();
 
         loop {
             futures::select! {
@@ -392,38 +815,167 @@ async fn start_network(
                                 }
                                 Err(()) => {
                                     // TODO: better error
-                                    let _ = send_back.send(Err(headers_optimistic::RequestFail::BlocksUnavailable));
+                                    let _ = send_back.send(Err(light::RequestFail::BlocksUnavailable));
+                                }
+                            };
+                        },
+                        ToNetwork::StartOnDemandRequest { peer_id, request, send_back } => {
+                            let result = match request {
+                                light::OnDemandRequest::StorageRead { block_hash, keys } => {
+                                    network.start_storage_proof_request(network::StorageProofRequestConfig {
+                                        peer_id,
+                                        block: block_hash,
+                                        keys,
+                                    }).await.map(|id| (id, false))
+                                }
+                                light::OnDemandRequest::Call { block_hash, method, parameter } => {
+                                    network.start_call_request(network::CallRequestConfig {
+                                        peer_id,
+                                        block: block_hash,
+                                        method_name: method,
+                                        encoded_input_parameter: parameter,
+                                    }).await.map(|id| (id, false))
+                                }
+                                light::OnDemandRequest::Body { block_hash } => {
+                                    network.start_block_request(network::BlocksRequestConfig {
+                                        start: network::BlocksRequestConfigStart::Hash(block_hash.into()),
+                                        peer_id,
+                                        desired_count: 1,
+                                        direction: network::BlocksRequestDirection::Ascending,
+                                        fields: network::BlocksRequestFields {
+                                            header: false,
+                                            body: true,
+                                            justification: false,
+                                        },
+                                    }).await.map(|id| (id, true))
+                                }
+                            };
+
+                            match result {
+                                Ok((id, true)) => {
+                                    body_requests.insert(id, send_back);
+                                }
+                                Ok((id, false)) => {
+                                    on_demand_requests.insert(id, send_back);
+                                }
+                                Err(()) => {
+                                    // TODO: better error
+                                    let _ = send_back.send(Err(light::RequestFail::BlocksUnavailable));
+                                }
+                            };
+                        },
+                        ToNetwork::StartFinalityProofRequest { peer_id, request, send_back } => {
+                            let result = network.start_finality_proof_request(network::FinalityProofRequestConfig {
+                                peer_id,
+                                block_hash: request.block_hash,
+                                authorities_set_id: request.authorities_set_id,
+                                last_finalized_hash: request.last_finalized_hash,
+                            }).await;
+
+                            match result {
+                                Ok(id) => {
+                                    finality_proof_requests.insert(id, send_back);
+                                }
+                                Err(()) => {
+                                    // TODO: better error
+                                    let _ = send_back.send(Err(light::RequestFail::BlocksUnavailable));
                                 }
                             };
                         },
+                        ToNetwork::ReportPeer { peer_id, reputation_change, reason, disconnect } => {
+                            network.report_peer(&peer_id, reputation_change, reason);
+                            if disconnect {
+                                network.disconnect_peer(&peer_id);
+                            }
+                        },
+                        ToNetwork::SetLocalBestBlock { number, hash } => {
+                            network.set_local_best_block(number, hash);
+                        },
+                        ToNetwork::AddReservedPeer { peer_id, address } => {
+                            network.add_reserved_peer(peer_id, address);
+                        },
+                        ToNetwork::RemoveReservedPeer { peer_id } => {
+                            network.remove_reserved_peer(&peer_id);
+                        },
+                        ToNetwork::NetworkState { send_back } => {
+                            let _ = send_back.send(network.network_state());
+                        },
                     }
                 },
 
                 event = network.next_event().fuse() => {
                     match event {
-                        network::Event::BlockAnnounce(header) => {
-                            // TODO:
+                        network::Event::BlockAnnounce { peer_id, header, is_best } => {
                             if let Ok(decoded) = header::decode(&header.0) {
                                 web_sys::console::log_1(&JsValue::from_str(&format!(
                                     "Received block announce: {}", decoded.number
                                 )));
+                                if is_best {
+                                    let _ = to_sync.send(ToSync::PeerBestBlock { peer_id, best_number: decoded.number }).await;
+                                }
                             }
                         }
+                        network::Event::BlocksRequestFinished { id, result } if body_requests.contains_key(&id) => {
+                                let send_back = body_requests.remove(&id).unwrap();
+                                let _: Result<_, _> = send_back.send(result
+                                    .map_err(|err| match err {
+                                        network::BlocksRequestError::Timeout => light::RequestFail::Timeout,
+                                        network::BlocksRequestError::Unavailable => light::RequestFail::BlocksUnavailable,
+                                        network::BlocksRequestError::InvalidResponse => light::RequestFail::InvalidResponse,
+                                    })
+                                    .and_then(|list| {
+                                        let body = list.into_iter().next().and_then(|block| block.body);
+                                        body.map(|body| body.into_iter().map(|e| e.0).collect())
+                                            .ok_or(light::RequestFail::BlocksUnavailable)
+                                    })
+                                );
+                        }
                         network::Event::BlocksRequestFinished { id, result } => {
                             let send_back = block_requests.remove(&id).unwrap();
                             let _: Result<_, _> = send_back.send(result
                                 .map(|list| {
                                     list.into_iter().map(|block| {
-                                        headers_optimistic::RequestSuccessBlock {
+                                        light::RequestSuccessBlock {
                                             scale_encoded_header: block.header.unwrap().0,
                                             scale_encoded_justification: block.justification,
                                         }
                                     }).collect()
                                 })
-                                .map_err(|()| headers_optimistic::RequestFail::BlocksUnavailable) // TODO:
+                                .map_err(|err| match err {
+                                    network::BlocksRequestError::Timeout => light::RequestFail::Timeout,
+                                    network::BlocksRequestError::Unavailable => light::RequestFail::BlocksUnavailable,
+                                    network::BlocksRequestError::InvalidResponse => light::RequestFail::InvalidResponse,
+                                })
+                            );
+                        }
+                        network::Event::CallRequestFinished { id, result } |
+                        network::Event::StorageProofRequestFinished { id, result } => {
+                            let send_back = on_demand_requests.remove(&id).unwrap();
+                            let _: Result<_, _> = send_back.send(result
+                                .map_err(|err| match err {
+                                    network::LightRequestError::Timeout => light::RequestFail::Timeout,
+                                    network::LightRequestError::Unavailable => light::RequestFail::BlocksUnavailable,
+                                    network::LightRequestError::InvalidResponse => light::RequestFail::InvalidResponse,
+                                })
                             );
                         }
-                        network::Event::CallRequestFinished { .. } => todo!(),
+                        network::Event::PeerStatus { peer_id, best_number, .. } => {
+                            let _ = to_sync.send(ToSync::PeerBestBlock { peer_id, best_number }).await;
+                        }
+                        network::Event::FinalityProofRequestFinished { id, result } => {
+                            let send_back = finality_proof_requests.remove(&id).unwrap();
+                            let _: Result<_, _> = send_back.send(result
+                                .map_err(|err| match err {
+                                    network::LightRequestError::Timeout => light::RequestFail::Timeout,
+                                    network::LightRequestError::Unavailable => light::RequestFail::BlocksUnavailable,
+                                    network::LightRequestError::InvalidResponse => light::RequestFail::InvalidResponse,
+                                })
+                            );
+                        }
+                        network::Event::FinalityProofRequest { .. } => unreachable!(),
+                        network::Event::BlocksRequest { .. } => unreachable!(),
+                        network::Event::AuthorityAddresses { .. } => unreachable!(),
+                        network::Event::Transactions { .. } => {}
                         network::Event::Connected(peer_id) => {
                             let _ = to_sync.send(ToSync::NewPeer(peer_id)).await;
                         }
@@ -442,10 +994,55 @@ enum ToNetwork {
         peer_id: network::PeerId,
         block_height: NonZeroU64,
         num_blocks: u32,
-        send_back: oneshot::Sender<
-            Result<Vec<headers_optimistic::RequestSuccessBlock>, headers_optimistic::RequestFail>,
-        >,
+        send_back: oneshot::Sender<Result<Vec<light::RequestSuccessBlock>, light::RequestFail>>,
+    },
+    /// Start an on-demand request. On success, the response contains the list of trie node
+    /// values of the proof, or the list of extrinsics for bodies.
+    StartOnDemandRequest {
+        peer_id: network::PeerId,
+        request: light::OnDemandRequest,
+        send_back: oneshot::Sender<Result<Vec<Vec<u8>>, light::RequestFail>>,
+    },
+    /// Start a finality proof request. On success, the response contains the SCALE-encoded
+    /// proof, or `None` if the peer couldn't generate one.
+    StartFinalityProofRequest {
+        peer_id: network::PeerId,
+        request: light::FinalityProofRequest,
+        send_back: oneshot::Sender<Result<Option<Vec<u8>>, light::RequestFail>>,
     },
+    /// A peer has misbehaved and its reputation must be lowered.
+    ReportPeer {
+        peer_id: network::PeerId,
+        reputation_change: i32,
+        reason: &'static str,
+        /// If true, the peer must also be disconnected.
+        disconnect: bool,
+    },
+    /// The local best block has changed.
+    SetLocalBestBlock { number: u64, hash: [u8; 32] },
+    /// A peer must be added to the list of reserved peers.
+    AddReservedPeer {
+        peer_id: network::PeerId,
+        address: network::Multiaddr,
+    },
+    /// A peer must be removed from the list of reserved peers.
+    RemoveReservedPeer { peer_id: network::PeerId },
+    /// Request a snapshot of the state of the network.
+    NetworkState {
+        send_back: oneshot::Sender<network::NetworkState>,
+    },
+}
+
+impl ToNetwork {
+    /// Builds a [`ToNetwork::ReportPeer`] from a punishment reported by the sync state machine.
+    fn report_peer(peer_id: network::PeerId, punishment: &light::SourcePunishment) -> Self {
+        ToNetwork::ReportPeer {
+            peer_id,
+            reputation_change: punishment.misbehaviour.reputation_change(),
+            reason: punishment.misbehaviour.reason(),
+            disconnect: punishment.verdict != chain::sync::reputation::Verdict::Keep,
+        }
+    }
 }
 
 /// Use in an asynchronous context to interrupt the current task execution and schedule it back.
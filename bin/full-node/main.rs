@@ -25,20 +25,22 @@ use futures::{
 };
 use std::{
     borrow::Cow,
-    collections::BTreeMap,
-    fs,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs, io,
     net::{SocketAddr, ToSocketAddrs as _},
     num::{NonZeroU32, NonZeroU64},
     path::PathBuf,
     pin::Pin,
     sync::{atomic::Ordering, Arc},
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 use structopt::StructOpt as _;
 use substrate_lite::{
     chain::{self, sync::full_optimistic},
-    chain_spec, header, network,
+    chain_spec,
+    database::network_address_book,
+    finality, header, network,
 };
 
 fn main() {
@@ -57,6 +59,35 @@ struct CliOptions {
     /// Coloring: auto, always, never
     #[structopt(long, default_value = "auto")]
     color: ColorChoice,
+    /// Hash of a block to reject, in addition to the bad blocks of the chain specs. Can be
+    /// passed multiple times.
+    #[structopt(long = "bad-block")]
+    bad_blocks: Vec<CliBlockHash>,
+    /// Ed25519 secret key of the node, encoded in hexadecimal. If not passed, the key is loaded
+    /// from the data directory, or generated and saved there if it doesn't exist yet.
+    #[structopt(long = "node-key")]
+    node_key: Option<CliNodeKey>,
+    /// Multiaddress to listen on. Can be passed multiple times. Defaults to
+    /// `/ip4/0.0.0.0/tcp/30333`.
+    #[structopt(long = "listen-addr")]
+    listen_addresses: Vec<network::Multiaddr>,
+    /// Multiaddress under which the node is reachable and that is advertised to other peers.
+    /// Can be passed multiple times.
+    #[structopt(long = "public-addr")]
+    public_addresses: Vec<network::Multiaddr>,
+    /// Address of a node, ending with `/p2p/<peer-id>`, that we always try to stay connected to.
+    /// Can be passed multiple times.
+    #[structopt(long = "reserved-node")]
+    reserved_nodes: Vec<CliPeerAddress>,
+    /// Only connect to reserved nodes, and refuse connections from other nodes.
+    #[structopt(long)]
+    reserved_only: bool,
+    /// Maximum number of incoming connections from non-reserved nodes.
+    #[structopt(long, default_value = "25")]
+    in_peers: u32,
+    /// Maximum number of outgoing connections to non-reserved nodes.
+    #[structopt(long, default_value = "25")]
+    out_peers: u32,
 }
 
 #[derive(Debug)]
@@ -110,6 +141,54 @@ impl core::str::FromStr for ColorChoice {
 #[display(fmt = "Color must be one of: always, auto, never")]
 struct ColorChoiceParseError;
 
+#[derive(Debug)]
+struct CliBlockHash([u8; 32]);
+
+impl core::str::FromStr for CliBlockHash {
+    type Err = CliBlockHashParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        let mut hash = [0; 32];
+        hex::decode_to_slice(s, &mut hash).map_err(|_| CliBlockHashParseError)?;
+        Ok(CliBlockHash(hash))
+    }
+}
+
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Block hash must be 32 bytes encoded in hexadecimal")]
+struct CliBlockHashParseError;
+
+#[derive(Debug)]
+struct CliNodeKey([u8; 32]);
+
+impl core::str::FromStr for CliNodeKey {
+    type Err = CliNodeKeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        let mut key = [0; 32];
+        hex::decode_to_slice(s, &mut key).map_err(|_| CliNodeKeyParseError)?;
+        Ok(CliNodeKey(key))
+    }
+}
+
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Node key must be 32 bytes encoded in hexadecimal")]
+struct CliNodeKeyParseError;
+
+#[derive(Debug)]
+struct CliPeerAddress(network::PeerId, network::Multiaddr);
+
+impl core::str::FromStr for CliPeerAddress {
+    type Err = network::ParseErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (peer_id, address) = network::parse_str_addr(s)?;
+        Ok(CliPeerAddress(peer_id, address))
+    }
+}
+
 async fn async_main() {
     let cli_options = CliOptions::from_args();
 
@@ -133,7 +212,7 @@ async fn async_main() {
     // Load the information about the chain from the database, or build the information of the
     // genesis block.
     // TODO:
-    let chain_information = /*match local_storage.chain_information() {
+    let mut chain_information = /*match local_storage.chain_information() {
         Ok(Some(i)) => i,
         Err(database::local_storage_light::AccessError::StorageAccess(err)) => return Err(err),
         // TODO: log why storage access failed?
@@ -145,6 +224,17 @@ async fn async_main() {
         //}
     ; //};
 
+    // Blocks that must be rejected or followed, from the chain specs and the command line.
+    chain_information
+        .bad_blocks
+        .extend(chain_spec.bad_blocks().copied());
+    chain_information
+        .bad_blocks
+        .extend(cli_options.bad_blocks.iter().map(|h| h.0));
+    chain_information
+        .fork_blocks
+        .extend(chain_spec.fork_blocks().map(|(n, h)| (n, *h)));
+
     // TODO: remove; just for testing
     /*let metadata = substrate_lite::metadata::metadata_from_runtime_code(
         chain_spec
@@ -167,23 +257,68 @@ async fn async_main() {
     let network_state = Arc::new(NetworkState {
         best_network_block_height: Atomic::new(0),
         num_network_connections: Atomic::new(0),
+        bandwidth_download_per_sec: Atomic::new(0),
+        bandwidth_upload_per_sec: Atomic::new(0),
     });
 
-    threads_pool.spawn_ok({
+    let node_key = match cli_options.node_key {
+        Some(key) => key.0,
+        None => match load_or_generate_node_key(&chain_spec) {
+            Ok(key) => key,
+            Err(err) => {
+                eprintln!("{}", err);
+                eprintln!(
+                    "Remove this file in order to generate a new node key, or pass one with \
+                     --node-key"
+                );
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let network_options = NetworkOptions {
+        node_key,
+        listen_addresses: if cli_options.listen_addresses.is_empty() {
+            vec!["/ip4/0.0.0.0/tcp/30333".parse().unwrap()]
+        } else {
+            cli_options.listen_addresses
+        },
+        public_addresses: cli_options.public_addresses,
+        reserved_peers: cli_options
+            .reserved_nodes
+            .into_iter()
+            .map(|CliPeerAddress(peer_id, address)| (peer_id, address))
+            .collect(),
+        reserved_only: cli_options.reserved_only,
+        in_peers: cli_options.in_peers,
+        out_peers: cli_options.out_peers,
+    };
+
+    let network_task = {
         let tasks_executor = Box::new({
             let threads_pool = threads_pool.clone();
             move |f| threads_pool.spawn_ok(f)
         });
 
-        start_network(
+        match start_network(
             &chain_spec,
+            network_options,
             tasks_executor,
             network_state.clone(),
             to_network_rx,
             to_sync_tx.clone(), // TODO: don't clone
         )
         .await
-    });
+        {
+            Ok(task) => task,
+            Err(err) => {
+                eprintln!("Failed to start the network: {}", err);
+                eprintln!("Use --listen-addr in order to listen on a different address");
+                std::process::exit(1);
+            }
+        }
+    };
+    threads_pool.spawn_ok(network_task);
 
     let sync_state = Arc::new(Mutex::new(SyncState {
         best_block_hash: [0; 32],      // TODO:
@@ -197,9 +332,11 @@ async fn async_main() {
             &chain_spec,
             chain_information,
             sync_state.clone(),
+            network_state.clone(),
             to_sync_rx,
             to_network_tx,
             to_db_save_tx,
+            threads_pool.clone(),
         )
         .await,
     );
@@ -261,6 +398,8 @@ async fn async_main() {
                             0 => None,
                             n => Some(n)
                         },
+                        network_download_per_sec: network_state.bandwidth_download_per_sec.load(Ordering::Relaxed),
+                        network_upload_per_sec: network_state.bandwidth_upload_per_sec.load(Ordering::Relaxed),
                     });
                 }
             },
@@ -288,8 +427,8 @@ async fn async_main() {
                     },
                     memory: None,
                     cpu: None,
-                    bandwidth_upload: Some(0.0), // TODO:
-                    bandwidth_download: Some(0.0), // TODO:
+                    bandwidth_upload: Some(network_state.bandwidth_upload_per_sec.load(Ordering::Relaxed) as f64),
+                    bandwidth_download: Some(network_state.bandwidth_download_per_sec.load(Ordering::Relaxed) as f64),
                     finalized_height: Some(sync_state.finalized_block_number),
                     finalized_hash: Some(sync_state.finalized_block_hash.into()),
                     block: substrate_lite::telemetry::message::Block {
@@ -310,9 +449,11 @@ async fn start_sync(
     chain_spec: &chain_spec::ChainSpec,
     chain_information_config: chain::chain_information::ChainInformationConfig,
     sync_state: Arc<Mutex<SyncState>>,
+    network_state: Arc<NetworkState>,
     mut to_sync: mpsc::Receiver<ToSync>,
     mut to_network: mpsc::Sender<ToNetwork>,
     mut to_db_save_tx: mpsc::Sender<chain::chain_information::ChainInformation>,
+    threads_pool: futures::executor::ThreadPool,
 ) -> impl Future<Output = ()> {
     let mut sync =
         full_optimistic::OptimisticFullSync::<_, network::PeerId>::new(full_optimistic::Config {
@@ -331,6 +472,8 @@ async fn start_sync(
             },
         });
 
+    let mut recent_finalized_blocks = RecentFinalizedBlocks::new(sync.as_chain_information());
+
     let mut finalized_block_storage = BTreeMap::<Vec<u8>, Vec<u8>>::new();
     // TODO: doesn't necessarily match chain_information; pass this as part of the params of `start_sync` instead
     for (key, value) in chain_spec.genesis_storage() {
@@ -340,10 +483,31 @@ async fn start_sync(
     async move {
         let mut peers_source_id_map = hashbrown::HashMap::<_, _, fnv::FnvBuildHasher>::default();
         let mut block_requests_finished = stream::FuturesUnordered::new();
+        let mut pre_verifications = stream::FuturesUnordered::new();
 
         loop {
+            // Verify the signatures of the blocks that have been fetched from queries on the
+            // threads pool, while the blocks are executed below one after the other. Blocks whose
+            // pre-verification isn't finished yet when they are processed are simply entirely
+            // verified by `process_one`.
+            while let Some(pre_verification) =
+                sync.next_pre_verification(NonZeroU32::new(32).unwrap())
+            {
+                let (tx, rx) = oneshot::channel();
+                threads_pool.spawn_ok(async move {
+                    let _ = tx.send(pre_verification.run());
+                });
+                pre_verifications.push(rx);
+            }
+            while let Some(Some(Ok(pre_verified))) = pre_verifications.next().now_or_never() {
+                sync.inject_pre_verified(pre_verified);
+            }
+
             // Verify blocks that have been fetched from queries.
-            let mut process = sync.process_one();
+            let now_from_unix_epoch = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap();
+            let mut process = sync.process_one(now_from_unix_epoch);
             loop {
                 match process {
                     full_optimistic::ProcessOne::Idle { sync: s } => {
@@ -353,15 +517,17 @@ async fn start_sync(
                     full_optimistic::ProcessOne::Finished {
                         sync: s,
                         finalized_blocks,
+                        punished_sources,
                     } => {
-                        process = s.process_one();
-
-                        if let Some(last_finalized) = finalized_blocks.last() {
-                            let mut lock = sync_state.lock().await;
-                            lock.finalized_block_hash = last_finalized.header.hash();
-                            lock.finalized_block_number = last_finalized.header.number;
+                        for punishment in punished_sources {
+                            let peer_id = s.source_user_data(punishment.source_id).clone();
+                            let _ = to_network
+                                .send(ToNetwork::report_peer(peer_id, &punishment))
+                                .await;
                         }
 
+                        process = s.process_one(now_from_unix_epoch);
+
                         for block in finalized_blocks {
                             for (key, value) in block.storage_top_trie_changes {
                                 if let Some(value) = value {
@@ -372,6 +538,8 @@ async fn start_sync(
                                     // assert!(_was_there.is_some());
                                 }
                             }
+
+                            recent_finalized_blocks.push(&block.header, block.justification);
                         }
                     }
 
@@ -421,11 +589,55 @@ async fn start_sync(
                 }
             }
 
-            // Update the current best block, used for CLI-related purposes.
-            {
-                let mut lock = sync_state.lock().await;
-                lock.best_block_hash = sync.best_block_hash();
-                lock.best_block_number = sync.best_block_number();
+            // Update the current best and finalized blocks, used for CLI-related purposes.
+            while let Some(event) = sync.next_chain_event() {
+                match event {
+                    chain::blocks_tree::ChainEvent::BestBlockChanged(change) => {
+                        let mut lock = sync_state.lock().await;
+                        lock.best_block_hash = change.new_best_hash;
+                        lock.best_block_number = change.new_best_number;
+                        drop(lock);
+
+                        // Announcing blocks while we're still catching up with the rest of the
+                        // network would be pointless, as peers already know about them.
+                        // A height of 0 means that it is unknown.
+                        // Chain events are reported after the fact, and the best block might have
+                        // changed again since this one. Only the current best block, whose header
+                        // is known, is announced. Later events supersede earlier ones.
+                        let best_network_block_height = network_state
+                            .best_network_block_height
+                            .load(Ordering::Relaxed);
+                        let message = if best_network_block_height != 0
+                            && change.new_best_number >= best_network_block_height
+                            && sync.best_block_hash() == change.new_best_hash
+                        {
+                            let scale_encoded_header = sync
+                                .best_block_header()
+                                .scale_encoding()
+                                .fold(Vec::new(), |mut a, b| {
+                                    a.extend_from_slice(b.as_ref());
+                                    a
+                                });
+                            ToNetwork::AnnounceBlock {
+                                scale_encoded_header,
+                                is_best: true,
+                            }
+                        } else {
+                            ToNetwork::SetLocalBestBlock {
+                                number: change.new_best_number,
+                                hash: change.new_best_hash,
+                            }
+                        };
+                        let _ = to_network.send(message).await;
+                    }
+                    chain::blocks_tree::ChainEvent::Finalized { hash, number, .. } => {
+                        let mut lock = sync_state.lock().await;
+                        lock.finalized_block_hash = hash;
+                        lock.finalized_block_number = number;
+                    }
+                    chain::blocks_tree::ChainEvent::BlockImported { .. }
+                    | chain::blocks_tree::ChainEvent::ForksPruned { .. } => {}
+                }
             }
 
             // Start requests that need to be started.
@@ -479,6 +691,16 @@ async fn start_sync(
                                 rq.abort();
                             }
                         },
+                        ToSync::PeerBestBlock { peer_id, best_number } => {
+                            // The status of a peer might be received before the peer has been
+                            // added as a source.
+                            if let Some(id) = peers_source_id_map.get(&peer_id) {
+                                sync.set_source_best_block(*id, best_number);
+                            }
+                        },
+                        ToSync::FinalityProofRequest { block_hash, last_finalized_hash, responder } => {
+                            responder.respond(recent_finalized_blocks.finality_proof(&block_hash, &last_finalized_hash));
+                        },
                     }
                 },
 
@@ -486,7 +708,11 @@ async fn start_sync(
                     // `result` is an error if the block request got cancelled by the sync state
                     // machine.
                     if let Ok(result) = result {
-                        let _ = sync.finish_request(request_id, result.unwrap().map(|v| v.into_iter()));
+                        let outcome = sync.finish_request(request_id, result.unwrap().map(|v| v.into_iter()));
+                        if let full_optimistic::FinishRequestOutcome::SourcePunished { source, punishment } = outcome {
+                            let message = ToNetwork::report_peer(source.clone(), &punishment);
+                            let _ = to_network.send(message).await;
+                        }
                     }
                 },
             }
@@ -497,6 +723,126 @@ async fn start_sync(
 enum ToSync {
     NewPeer(network::PeerId),
     PeerDisconnected(network::PeerId),
+    /// A peer has reported a new best block, either through its status or a block announce.
+    PeerBestBlock {
+        peer_id: network::PeerId,
+        best_number: u64,
+    },
+    /// A peer has sent a finality proof request, which must be answered with the given
+    /// responder.
+    FinalityProofRequest {
+        block_hash: [u8; 32],
+        last_finalized_hash: [u8; 32],
+        responder: network::FinalityProofResponder,
+    },
+}
+
+/// Maximum number of finalized blocks kept in memory by [`RecentFinalizedBlocks`].
+const MAX_RECENT_FINALIZED_BLOCKS: usize = 4096;
+
+/// Most recently finalized blocks and their justifications, kept in memory in order to answer
+/// finality proof requests.
+///
+/// Peers whose latest finalized block is older than these blocks can't be given a proof.
+struct RecentFinalizedBlocks {
+    /// Blocks ordered by increasing block number, starting with the finalized block the syncing
+    /// has started from.
+    blocks: VecDeque<RecentFinalizedBlock>,
+    /// Heights of the blocks that trigger a change of GrandPa authorities scheduled by one of
+    /// the finalized blocks.
+    grandpa_changes_heights: BTreeSet<u64>,
+}
+
+/// Finalized block stored in [`RecentFinalizedBlocks`].
+struct RecentFinalizedBlock {
+    hash: [u8; 32],
+    scale_encoded_header: Vec<u8>,
+    scale_encoded_justification: Option<Vec<u8>>,
+    enacts_authorities_change: bool,
+}
+
+impl RecentFinalizedBlocks {
+    /// Builds a new [`RecentFinalizedBlocks`] containing the finalized block of the given chain
+    /// information.
+    fn new(chain_information: chain::chain_information::ChainInformationRef) -> Self {
+        let mut blocks = VecDeque::with_capacity(MAX_RECENT_FINALIZED_BLOCKS);
+        blocks.push_back(RecentFinalizedBlock {
+            hash: chain_information.finalized_block_header.hash(),
+            scale_encoded_header: chain_information
+                .finalized_block_header
+                .scale_encoding()
+                .fold(Vec::new(), |mut a, b| {
+                    a.extend_from_slice(b.as_ref());
+                    a
+                }),
+            scale_encoded_justification: None,
+            enacts_authorities_change: false,
+        });
+
+        RecentFinalizedBlocks {
+            blocks,
+            grandpa_changes_heights: chain_information
+                .grandpa_finalized_scheduled_change
+                .map(|(height, _)| height)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Adds the block that has been finalized after the latest one, removing the oldest block if
+    /// necessary.
+    fn push(&mut self, header: &header::Header, scale_encoded_justification: Option<Vec<u8>>) {
+        // Forced changes aren't taken into account, as the blocks that trigger them generally
+        // don't have a justification.
+        for log in header.digest.logs() {
+            if let header::DigestItemRef::GrandpaConsensus(
+                header::GrandpaConsensusLogRef::ScheduledChange(change),
+            ) = log
+            {
+                self.grandpa_changes_heights
+                    .insert(header.number + u64::from(change.delay));
+            }
+        }
+
+        if self.blocks.len() >= MAX_RECENT_FINALIZED_BLOCKS {
+            self.blocks.pop_front();
+        }
+
+        self.blocks.push_back(RecentFinalizedBlock {
+            hash: header.hash(),
+            scale_encoded_header: header.scale_encoding().fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            }),
+            scale_encoded_justification,
+            enacts_authorities_change: self.grandpa_changes_heights.remove(&header.number),
+        });
+    }
+
+    /// Builds a finality proof of the given block for a peer whose latest finalized block is
+    /// `last_finalized_hash`. Returns `None` if no proof can be built.
+    fn finality_proof(
+        &self,
+        block_hash: &[u8; 32],
+        last_finalized_hash: &[u8; 32],
+    ) -> Option<Vec<u8>> {
+        let start = self
+            .blocks
+            .iter()
+            .position(|block| block.hash == *last_finalized_hash)?;
+
+        let blocks = self
+            .blocks
+            .iter()
+            .skip(start + 1)
+            .map(|block| finality::proof::BuildBlock {
+                scale_encoded_header: &block.scale_encoded_header,
+                scale_encoded_justification: block.scale_encoded_justification.as_deref(),
+                enacts_authorities_change: block.enacts_authorities_change,
+            });
+
+        finality::proof::build(block_hash, blocks).ok().flatten()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -507,13 +853,107 @@ struct SyncState {
     finalized_block_hash: [u8; 32],
 }
 
+/// Returns the directory where the network-related data of the chain is stored, creating it if
+/// necessary.
+fn network_data_dir(chain_spec: &chain_spec::ChainSpec) -> PathBuf {
+    app_dirs::app_dir(
+        app_dirs::AppDataType::UserData,
+        &app_dirs::AppInfo {
+            name: "substrate-lite",
+            author: "paritytech",
+        },
+        &format!("chains/{}/network", chain_spec.id()),
+    )
+    .expect("Failed to create data directory")
+}
+
+/// Loads the secret key of the node from the data directory of the chain, or generates a new
+/// one and saves it there if there isn't any.
+///
+/// On Unix, a newly-generated key file can only be read and written by its owner.
+fn load_or_generate_node_key(chain_spec: &chain_spec::ChainSpec) -> Result<[u8; 32], NodeKeyError> {
+    let path = network_data_dir(chain_spec).join("secret_ed25519");
+
+    match fs::read(&path) {
+        Ok(bytes) => {
+            let mut key = [0; 32];
+            if bytes.len() != key.len() {
+                return Err(NodeKeyError::InvalidLength {
+                    path,
+                    len: bytes.len(),
+                });
+            }
+            key.copy_from_slice(&bytes);
+            Ok(key)
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let key: [u8; 32] = rand::random();
+
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            options
+                .open(&path)
+                .and_then(|mut file| io::Write::write_all(&mut file, &key))
+                .map_err(|err| NodeKeyError::Write { path, err })?;
+            Ok(key)
+        }
+        Err(err) => Err(NodeKeyError::Read { path, err }),
+    }
+}
+
+/// Error potentially returned by [`load_or_generate_node_key`].
+#[derive(Debug, derive_more::Display)]
+enum NodeKeyError {
+    /// Failed to read the node key file.
+    #[display(fmt = "Failed to read node key file {}: {}", "path.display()", err)]
+    Read { path: PathBuf, err: io::Error },
+    /// The node key file doesn't contain a key of the expected length.
+    #[display(
+        fmt = "Invalid node key file {}: expected 32 bytes, found {}",
+        "path.display()",
+        len
+    )]
+    InvalidLength { path: PathBuf, len: usize },
+    /// Failed to write the newly-generated node key.
+    #[display(fmt = "Failed to write node key file {}: {}", "path.display()", err)]
+    Write { path: PathBuf, err: io::Error },
+}
+
+/// Network-related options passed to [`start_network`].
+struct NetworkOptions {
+    node_key: [u8; 32],
+    listen_addresses: Vec<network::Multiaddr>,
+    public_addresses: Vec<network::Multiaddr>,
+    reserved_peers: Vec<(network::PeerId, network::Multiaddr)>,
+    reserved_only: bool,
+    in_peers: u32,
+    out_peers: u32,
+}
+
 async fn start_network(
     chain_spec: &chain_spec::ChainSpec,
+    options: NetworkOptions,
     tasks_executor: Box<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
     network_state: Arc<NetworkState>,
     mut to_network: mpsc::Receiver<ToNetwork>,
     mut to_sync: mpsc::Sender<ToSync>,
-) -> impl Future<Output = ()> {
+) -> Result<impl Future<Output = ()>, network::StartError> {
+    let address_book_path = network_data_dir(chain_spec).join("address_book.json");
+    let address_book = match network_address_book::load(&address_book_path) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!(
+                "Ignoring address book {}: {}",
+                address_book_path.display(),
+                err
+            );
+            Vec::new()
+        }
+    };
+
     let mut network = {
         let mut known_addresses = chain_spec
             .boot_nodes()
@@ -531,6 +971,7 @@ async fn start_network(
 
         network::Network::start(network::Config {
             known_addresses,
+            address_book,
             chain_spec_protocol_id: chain_spec.protocol_id().as_bytes().to_vec(),
             tasks_executor,
             local_genesis_hash: substrate_lite::calculate_genesis_block_header(
@@ -538,20 +979,74 @@ async fn start_network(
             )
             .hash(),
             wasm_external_transport: None,
+            node_key: options.node_key,
+            authority_discovery_key: None,
+            listen_addresses: options.listen_addresses,
+            public_addresses: options.public_addresses,
+            reserved_peers: options.reserved_peers,
+            reserved_only: options.reserved_only,
+            in_peers: options.in_peers,
+            out_peers: options.out_peers,
+            role: network::Role::Full,
+            // Proofs are built by the sync task from the recently-finalized blocks.
+            answer_finality_proof_requests: true,
+            // TODO: answer blocks requests once the finalized blocks are stored
+            answer_blocks_requests: false,
+            memory_only: false,
         })
-        .await
+        .await?
     };
 
-    async move {
+    Ok(async move {
         // TODO: store send back channel in a network user data rather than having this hashmap
         let mut block_requests = hashbrown::HashMap::<_, _, fnv::FnvBuildHasher>::default();
 
+        // Best block number reported by each connected peer. `best_network_block_height` is
+        // derived from this map, so that peers that have disconnected are no longer taken into
+        // account.
+        let mut peers_best_numbers =
+            hashbrown::HashMap::<network::PeerId, u64, fnv::FnvBuildHasher>::default();
+
+        let mut bandwidth_timer = stream::unfold((), move |_| {
+            futures_timer::Delay::new(Duration::from_secs(1)).map(|_| Some(((), ())))
+        })
+        .map(|_| ());
+
+        let mut address_book_timer = stream::unfold((), move |_| {
+            futures_timer::Delay::new(Duration::from_secs(60)).map(|_| Some(((), ())))
+        })
+        .map(|_| ());
+
+        let save_address_book = |network: &mut network::Network| {
+            if let Err(err) =
+                network_address_book::save(&address_book_path, &network.address_book())
+            {
+                eprintln!(
+                    "Failed to save address book {}: {}",
+                    address_book_path.display(),
+                    err
+                );
+            }
+        };
+
         loop {
             futures::select! {
+                _ = bandwidth_timer.next() => {
+                    network_state.bandwidth_download_per_sec.store(network.average_download_per_sec(), Ordering::Relaxed);
+                    network_state.bandwidth_upload_per_sec.store(network.average_upload_per_sec(), Ordering::Relaxed);
+                },
+
+                _ = address_book_timer.next() => {
+                    save_address_book(&mut network);
+                },
+
                 message = to_network.next() => {
                     let message = match message {
                         Some(m) => m,
-                        None => return,
+                        None => {
+                            save_address_book(&mut network);
+                            return;
+                        }
                     };
 
                     match message {
@@ -578,17 +1073,47 @@ async fn start_network(
                                 }
                             };
                         },
+                        ToNetwork::ReportPeer { peer_id, reputation_change, reason, disconnect } => {
+                            network.report_peer(&peer_id, reputation_change, reason);
+                            if disconnect {
+                                network.disconnect_peer(&peer_id);
+                            }
+                        },
+                        ToNetwork::SetLocalBestBlock { number, hash } => {
+                            network.set_local_best_block(number, hash);
+                        },
+                        ToNetwork::AnnounceBlock { scale_encoded_header, is_best } => {
+                            // The header comes from the sync state machine and is always valid.
+                            let _ = network.announce_block(&scale_encoded_header, is_best);
+                        },
                     }
                 },
 
                 event = network.next_event().fuse() => {
                     match event {
-                        network::Event::BlockAnnounce(header) => {
+                        network::Event::BlockAnnounce { peer_id, header, is_best: true } => {
                             if let Ok(header) = header::decode(&header.0) {
-                                network_state.best_network_block_height.store(header.number, Ordering::Relaxed);
+                                peers_best_numbers.insert(peer_id.clone(), header.number);
+                                update_best_network_block_height(&network_state, &peers_best_numbers);
+                                let _ = to_sync.send(ToSync::PeerBestBlock { peer_id, best_number: header.number }).await;
                             }
                         }
+                        network::Event::BlockAnnounce { is_best: false, .. } => {}
+                        network::Event::PeerStatus { peer_id, best_number, .. } => {
+                            peers_best_numbers.insert(peer_id.clone(), best_number);
+                            update_best_network_block_height(&network_state, &peers_best_numbers);
+                            let _ = to_sync.send(ToSync::PeerBestBlock { peer_id, best_number }).await;
+                        }
                         network::Event::CallRequestFinished { .. } => unreachable!(),
+                        network::Event::StorageProofRequestFinished { .. } => unreachable!(),
+                        network::Event::FinalityProofRequestFinished { .. } => unreachable!(),
+                        network::Event::FinalityProofRequest { block_hash, last_finalized_hash, responder, .. } => {
+                            let _ = to_sync.send(ToSync::FinalityProofRequest { block_hash, last_finalized_hash, responder }).await;
+                        }
+                        network::Event::BlocksRequest { .. } => unreachable!(),
+                        network::Event::AuthorityAddresses { .. } => unreachable!(),
+                        // TODO: no transactions pool yet
+                        network::Event::Transactions { .. } => {}
                         network::Event::BlocksRequestFinished { id, result } => {
                             let send_back = block_requests.remove(&id).unwrap();
                             let _: Result<_, _> = send_back.send(result
@@ -601,7 +1126,11 @@ async fn start_network(
                                         }
                                     }).collect()
                                 })
-                                .map_err(|()| full_optimistic::RequestFail::BlocksUnavailable) // TODO:
+                                .map_err(|err| match err {
+                                    network::BlocksRequestError::Timeout => full_optimistic::RequestFail::Timeout,
+                                    network::BlocksRequestError::Unavailable => full_optimistic::RequestFail::BlocksUnavailable,
+                                    network::BlocksRequestError::InvalidResponse => full_optimistic::RequestFail::InvalidResponse,
+                                })
                             );
                         }
                         network::Event::Connected(peer_id) => {
@@ -610,20 +1139,39 @@ async fn start_network(
                         }
                         network::Event::Disconnected(peer_id) => {
                             network_state.num_network_connections.fetch_sub(1, Ordering::Relaxed);
+                            if peers_best_numbers.remove(&peer_id).is_some() {
+                                update_best_network_block_height(&network_state, &peers_best_numbers);
+                            }
                             let _ = to_sync.send(ToSync::PeerDisconnected(peer_id)).await;
                         }
                     }
                 },
             }
         }
-    }
+    })
+}
+
+/// Sets [`NetworkState::best_network_block_height`] to the highest best block number among the
+/// peers that are currently connected, or 0 if none of them has reported its best block.
+fn update_best_network_block_height(
+    network_state: &NetworkState,
+    peers_best_numbers: &hashbrown::HashMap<network::PeerId, u64, fnv::FnvBuildHasher>,
+) {
+    let best = peers_best_numbers.values().copied().max().unwrap_or(0);
+    network_state
+        .best_network_block_height
+        .store(best, Ordering::Relaxed);
 }
 
 #[derive(Debug)]
 struct NetworkState {
-    /// 0 means "unknown".
+    /// Highest best block number among the currently connected peers. 0 means "unknown".
     best_network_block_height: Atomic<u64>,
     num_network_connections: Atomic<u64>,
+    /// Average number of bytes per second received from the network, updated every second.
+    bandwidth_download_per_sec: Atomic<u64>,
+    /// Average number of bytes per second sent to the network, updated every second.
+    bandwidth_upload_per_sec: Atomic<u64>,
 }
 
 enum ToNetwork {
@@ -635,4 +1183,34 @@ enum ToNetwork {
             Result<Vec<full_optimistic::RequestSuccessBlock>, full_optimistic::RequestFail>,
         >,
     },
+    /// A peer has misbehaved and its reputation must be lowered.
+    ReportPeer {
+        peer_id: network::PeerId,
+        reputation_change: i32,
+        reason: &'static str,
+        /// If true, the peer must also be disconnected.
+        disconnect: bool,
+    },
+    /// The local best block has changed, but must not be announced.
+    SetLocalBestBlock { number: u64, hash: [u8; 32] },
+    /// A block must be announced to the peers we're connected to.
+    AnnounceBlock {
+        scale_encoded_header: Vec<u8>,
+        is_best: bool,
+    },
+}
+
+impl ToNetwork {
+    /// Builds a [`ToNetwork::ReportPeer`] from a punishment reported by the sync state machine.
+    fn report_peer(
+        peer_id: network::PeerId,
+        punishment: &full_optimistic::SourcePunishment,
+    ) -> Self {
+        ToNetwork::ReportPeer {
+            peer_id,
+            reputation_change: punishment.misbehaviour.reputation_change(),
+            reason: punishment.misbehaviour.reason(),
+            disconnect: punishment.verdict != chain::sync::reputation::Verdict::Keep,
+        }
+    }
 }
@@ -17,6 +17,7 @@
 
 const PROTOS: &[&str] = &[
     "src/network/schema/api.v1.proto",
+    "src/network/schema/authority_discovery.v1.proto",
     "src/network/schema/finality.v1.proto",
     "src/network/schema/light.v1.proto",
 ];
@@ -55,6 +55,16 @@
 //! >           should wait for a block to be finalized if they want to be certain that it will
 //! >           forever remain part of the chain.
 //!
+//! # Equivocations
+//!
+//! The [`NonFinalizedTree`] keeps track of the BABE blocks that have been inserted during the
+//! most recent slots, even after they have been finalized or pruned. If a BABE authority
+//! produces two different blocks during the same slot, the proof of this equivocation can be
+//! retrieved with [`HeaderInsert::babe_equivocation`] or [`BodyInsert::babe_equivocation`].
+//!
+//! Similarly, the GrandPa authorities that have signed pre-commits for more than one block can
+//! be retrieved with [`JustificationApply::equivocations`].
+//!
 //! Additionally, a [`NonFinalizedTree::verify_justification`] method is provided in order to
 //! verify the correctness of a [justification](crate::finality::justification).
 
@@ -64,15 +74,29 @@
 use crate::{
     chain::{chain_information, fork_tree},
     executor,
-    finality::justification,
+    finality::{justification, proof},
     header,
-    trie::calculate_root,
+    trie::{calculate_root, node_store},
     verify::{self, babe},
 };
 
-use alloc::sync::Arc;
-use core::{cmp, convert::TryFrom as _, fmt, mem};
-use hashbrown::HashMap;
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+use core::{convert::TryFrom as _, fmt, mem, num::NonZeroU64, time::Duration};
+use hashbrown::{HashMap, HashSet};
+
+mod grandpa;
+mod snapshot;
+pub(crate) mod tests;
+
+pub use grandpa::GrandpaChangeError;
+pub use snapshot::SnapshotDecodeError;
+
+/// Number of slots, counting backwards from the highest known slot, during which BABE blocks
+/// are remembered for the purpose of detecting equivocations.
+const BABE_SEEN_SLOTS_WINDOW: u64 = 1000;
 
 /// Configuration for the [`NonFinalizedTree`].
 #[derive(Debug, Clone)]
@@ -82,6 +106,120 @@ pub struct Config {
 
     /// Pre-allocated size of the chain, in number of non-finalized blocks.
     pub blocks_capacity: usize,
+
+    /// Rule used to determine which block is the best block.
+    pub fork_choice: ForkChoice,
+
+    /// If `true`, the [`NonFinalizedTree`] generates [`ChainEvent`]s that can be retrieved with
+    /// [`NonFinalizedTree::next_event`].
+    ///
+    /// > **Note**: Events are buffered. If this is `true`, [`NonFinalizedTree::next_event`]
+    /// >           must be called regularly in order to avoid this buffer growing forever.
+    pub generate_events: bool,
+}
+
+/// Rule used to determine the best block amongst the non-finalized blocks.
+///
+/// When inserting a new block, it becomes the new best block only if it is *strictly* better
+/// than the current best block according to this rule. In case of equality, the current best
+/// block is kept.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ForkChoice {
+    /// The best block is the one with the highest number of BABE primary slot claims between the
+    /// finalized block and itself. In case of equality, the block with the highest number wins.
+    ///
+    /// This is the rule used by Substrate-based chains that use BABE.
+    BabePrimarySlots,
+    /// The best block is the one with the highest number.
+    ///
+    /// This is the rule used by Substrate-based chains that use Aura.
+    LongestChain,
+}
+
+impl ForkChoice {
+    /// Returns the rule used by Substrate-based chains that use the given consensus engine.
+    pub fn from_consensus(consensus: &chain_information::ChainInformationConsensus) -> Self {
+        match consensus {
+            chain_information::ChainInformationConsensus::Aura { .. } => ForkChoice::LongestChain,
+            chain_information::ChainInformationConsensus::Babe { .. } => {
+                ForkChoice::BabePrimarySlots
+            }
+        }
+    }
+
+    /// Returns true if a block with the given weight and number is better than a block with the
+    /// given other weight and number.
+    fn is_better(
+        &self,
+        (new_weight, new_number): (u64, u64),
+        (old_weight, old_number): (u64, u64),
+    ) -> bool {
+        match self {
+            ForkChoice::BabePrimarySlots => (new_weight, new_number) > (old_weight, old_number),
+            ForkChoice::LongestChain => new_number > old_number,
+        }
+    }
+}
+
+/// Change in the best block of the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BestBlockChange {
+    /// Hash of the best block before the change.
+    pub old_best_hash: [u8; 32],
+    /// Hash of the best block after the change.
+    pub new_best_hash: [u8; 32],
+    /// Number of the best block after the change.
+    pub new_best_number: u64,
+    /// Hashes of the blocks that are no longer part of the best chain, starting from the old best
+    /// block and going towards the common ancestor with the new best block. The common ancestor
+    /// isn't included.
+    pub retracted: Vec<[u8; 32]>,
+    /// Hashes of the blocks that are now part of the best chain, starting from the child of the
+    /// common ancestor and going towards the new best block. The common ancestor isn't included.
+    pub enacted: Vec<[u8; 32]>,
+}
+
+/// Event that happened on the [`NonFinalizedTree`]. See [`NonFinalizedTree::next_event`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// A block has been inserted in the tree.
+    ///
+    /// If this block is the new best block, this event is immediately followed with a
+    /// [`ChainEvent::BestBlockChanged`].
+    BlockImported {
+        /// Hash of the block.
+        hash: [u8; 32],
+        /// Number of the block.
+        number: u64,
+        /// Hash of the parent of the block.
+        parent_hash: [u8; 32],
+    },
+
+    /// The best block has changed.
+    BestBlockChanged(BestBlockChange),
+
+    /// Blocks have been finalized.
+    ///
+    /// If the best block has changed as a result of the finalization, this event is immediately
+    /// preceded with a [`ChainEvent::BestBlockChanged`].
+    Finalized {
+        /// Hash of the new finalized block.
+        hash: [u8; 32],
+        /// Number of the new finalized block.
+        number: u64,
+        /// Hashes of all the blocks that have been finalized, in increasing block number. The
+        /// last element is always equal to `hash`.
+        finalized: Vec<[u8; 32]>,
+    },
+
+    /// Blocks have been removed from the tree because they aren't descendants of the finalized
+    /// block.
+    ///
+    /// Generated after [`ChainEvent::Finalized`], or after calling [`NonFinalizedTree::clear`].
+    ForksPruned {
+        /// Hashes of the removed blocks, in no specific order.
+        pruned: Vec<[u8; 32]>,
+    },
 }
 
 /// Holds state about the current state of the chain for the purpose of verifying headers.
@@ -90,34 +228,67 @@ pub struct NonFinalizedTree<T> {
     finalized_block_header: header::Header,
     /// Hash of [`NonFinalizedTree::finalized_block_header`].
     finalized_block_hash: [u8; 32],
-    /// Grandpa authorities set ID of the block right after the finalized block.
-    grandpa_after_finalized_block_authorities_set_id: u64,
-    /// List of GrandPa authorities that need to finalize the block right after the finalized
-    /// block.
-    grandpa_finalized_triggered_authorities: Vec<header::GrandpaAuthority>,
-    /// Change in the GrandPa authorities list that has been scheduled by a block that is already
-    /// finalized but not triggered yet. These changes will for sure happen. Contains the block
-    /// number where the changes are to be triggered.
-    grandpa_finalized_scheduled_change: Option<(u64, Vec<header::GrandpaAuthority>)>,
-
-    /// Configuration for BABE, retrieved from the genesis block.
-    babe_genesis_config: chain_information::babe::BabeGenesisConfiguration,
-
-    /// See [`chain_information::ChainInformation::babe_finalized_block_epoch_information`].
-    babe_finalized_block_epoch_information:
-        Option<Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
-
-    /// See [`chain_information::ChainInformation::babe_finalized_next_epoch_transition`].
-    babe_finalized_next_epoch_transition:
-        Option<Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
-
-    /// If block 1 is finalized, contains its slot number.
-    babe_finalized_block1_slot_number: Option<u64>,
+    /// GrandPa state of the chain right after the finalized block. Contains, amongst other
+    /// things, the list of authorities that need to finalize the block right after the finalized
+    /// block, and the changes to this list that have been scheduled by finalized blocks.
+    finalized_grandpa: grandpa::GrandpaState,
+    /// State of the consensus engine of the chain right after the finalized block.
+    finalized_consensus: FinalizedConsensus,
+    /// Number of BABE primary slot claims of the finalized block and its ancestors, used to
+    /// determine the best block. Only differences between weights matter, and this value is
+    /// therefore arbitrarily set to 0 when the [`NonFinalizedTree`] is created.
+    babe_finalized_block_weight: u64,
+    /// See [`Config::fork_choice`].
+    fork_choice: ForkChoice,
     /// Container for non-finalized blocks.
     blocks: fork_tree::ForkTree<Block<T>>,
     /// Index within [`NonFinalizedTree::blocks`] of the current best block. `None` if and only
     /// if the fork tree is empty.
     current_best: Option<fork_tree::NodeIndex>,
+    /// Queue of events to return from [`NonFinalizedTree::next_event`]. `None` if
+    /// [`Config::generate_events`] was `false`.
+    events: Option<VecDeque<ChainEvent>>,
+    /// Header of the first block inserted for each combination of slot number and BABE
+    /// authority public key. Used in order to detect equivocations. Entries older than
+    /// [`BABE_SEEN_SLOTS_WINDOW`] slots compared to the most recent entry are removed.
+    babe_seen_slots: BTreeMap<(u64, [u8; 32]), header::Header>,
+    /// See [`chain_information::ChainInformationConfig::bad_blocks`].
+    bad_blocks: HashSet<[u8; 32], fnv::FnvBuildHasher>,
+    /// See [`chain_information::ChainInformationConfig::fork_blocks`].
+    fork_blocks: HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+    /// See [`NonFinalizedTree::set_node_store`].
+    node_store: Option<node_store::NodeStore>,
+}
+
+/// State of the consensus engine of the chain right after the finalized block.
+enum FinalizedConsensus {
+    Aura {
+        /// List of authorities that must validate the children of the finalized block.
+        authorities_list: Arc<Vec<header::AuraAuthority>>,
+        /// Duration, in milliseconds, of a slot.
+        slot_duration: NonZeroU64,
+    },
+    Babe {
+        /// Configuration for BABE, retrieved from the genesis block.
+        genesis_config: chain_information::babe::BabeGenesisConfiguration,
+        /// See [`chain_information::ChainInformationConsensus::Babe::finalized_block_epoch_information`].
+        block_epoch_information: Option<Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
+        /// See [`chain_information::ChainInformationConsensus::Babe::finalized_next_epoch_transition`].
+        next_epoch_transition: Option<Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
+        /// If block 1 is finalized, contains its slot number.
+        block1_slot_number: Option<u64>,
+        /// Indices of the BABE authorities disabled by the finalized block or by its ancestors
+        /// of the same epoch.
+        ///
+        /// > **Note**: This information isn't part of the
+        /// >           [`chain_information::ChainInformation`] and is therefore empty when the
+        /// >           [`NonFinalizedTree`] is created.
+        disabled_authorities: Vec<u32>,
+        /// State of the calculation of the randomness of an upcoming BABE epoch, after the
+        /// finalized block. `None` if unknown, which is the case when the [`NonFinalizedTree`]
+        /// is created.
+        randomness_accumulator: Option<babe::RandomnessAccumulator>,
+    },
 }
 
 struct Block<T> {
@@ -126,18 +297,73 @@ struct Block<T> {
     /// Cache of the hash of the block. Always equal to the hash of the header stored in this
     /// same struct.
     hash: [u8; 32],
-    /// If this block is block #1 of the chain, contains its babe slot number. Otherwise, contains
-    /// the slot number of the block #1 that is an ancestor of this block.
-    babe_block1_slot_number: u64,
-    /// Information about the Babe epoch the block belongs to. `None` if the block belongs to
-    /// epoch #0.
-    babe_current_epoch: Option<Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
-    /// Information about the Babe epoch the block belongs to.
-    babe_next_epoch: Arc<(header::BabeNextEpoch, header::BabeNextConfig)>,
+    /// State of the consensus engine of the chain right after this block.
+    consensus: BlockConsensus,
+    /// Weight of the parent, plus one if this block is a BABE primary slot claim. See
+    /// [`NonFinalizedTree::babe_finalized_block_weight`].
+    babe_primary_slots_weight: u64,
+    /// GrandPa state of the chain right after this block.
+    grandpa: grandpa::GrandpaState,
+    /// Change of GrandPa authorities triggered by this block, if any.
+    grandpa_triggered_change: Option<grandpa::TriggeredChange>,
     /// Opaque data decided by the user.
     user_data: T,
 }
 
+/// State of the consensus engine of the chain right after a non-finalized block.
+enum BlockConsensus {
+    Aura {
+        /// List of authorities that must validate the children of this block.
+        authorities_list: Arc<Vec<header::AuraAuthority>>,
+    },
+    Babe {
+        /// If this block is block #1 of the chain, contains its babe slot number. Otherwise,
+        /// contains the slot number of the block #1 that is an ancestor of this block.
+        block1_slot_number: u64,
+        /// Information about the Babe epoch the block belongs to. `None` if the block belongs
+        /// to epoch #0.
+        current_epoch: Option<Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
+        /// Information about the Babe epoch the block belongs to.
+        next_epoch: Arc<(header::BabeNextEpoch, header::BabeNextConfig)>,
+        /// Indices of the BABE authorities disabled by this block or by its ancestors of the
+        /// same epoch.
+        disabled_authorities: Vec<u32>,
+        /// State of the calculation of the randomness of an upcoming BABE epoch, after this
+        /// block.
+        randomness_accumulator: Option<babe::RandomnessAccumulator>,
+    },
+}
+
+/// State of the consensus engine of the chain right after a block, which is either the finalized
+/// block or a non-finalized block. See [`NonFinalizedTree::consensus_after`].
+enum ConsensusAfterRef<'a> {
+    Aura {
+        /// List of authorities that must validate the children of the block.
+        authorities_list: &'a Arc<Vec<header::AuraAuthority>>,
+        /// Duration, in milliseconds, of a slot.
+        slot_duration: NonZeroU64,
+    },
+    Babe {
+        /// Configuration for BABE, retrieved from the genesis block.
+        genesis_config: &'a chain_information::babe::BabeGenesisConfiguration,
+        /// Slot number of the block #1 that is an ancestor of the block or the block itself.
+        /// `None` only if the block is the block #0.
+        block1_slot_number: Option<u64>,
+        /// Information about the Babe epoch the block belongs to. `None` if the block belongs
+        /// to epoch #0.
+        current_epoch: Option<&'a Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
+        /// Information about the Babe epoch after the one the block belongs to. `None` only if
+        /// the block is the block #0.
+        next_epoch: Option<&'a Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
+        /// Indices of the BABE authorities disabled by the block or by its ancestors of the same
+        /// epoch.
+        disabled_authorities: &'a [u32],
+        /// State of the calculation of the randomness of an upcoming BABE epoch, after the
+        /// block.
+        randomness_accumulator: Option<&'a babe::RandomnessAccumulator>,
+    },
+}
+
 impl<T> NonFinalizedTree<T> {
     /// Initializes a new queue.
     ///
@@ -146,24 +372,13 @@ impl<T> NonFinalizedTree<T> {
     /// Panics if the chain information is incorrect.
     ///
     pub fn new(config: Config) -> Self {
-        if config
+        let finalized_block_number = config
             .chain_information_config
             .chain_information
             .finalized_block_header
-            .number
-            >= 1
-        {
-            assert!(config
-                .chain_information_config
-                .chain_information
-                .babe_finalized_block1_slot_number
-                .is_some());
-            assert!(config
-                .chain_information_config
-                .chain_information
-                .babe_finalized_next_epoch_transition
-                .is_some());
-        } else {
+            .number;
+
+        if finalized_block_number == 0 {
             assert_eq!(
                 config
                     .chain_information_config
@@ -171,19 +386,43 @@ impl<T> NonFinalizedTree<T> {
                     .grandpa_after_finalized_block_authorities_set_id,
                 0
             );
-            assert!(config
-                .chain_information_config
-                .chain_information
-                .babe_finalized_next_epoch_transition
-                .is_none());
-            assert!(config
-                .chain_information_config
-                .chain_information
-                .babe_finalized_block_epoch_information
-                .is_none());
         }
 
-        // TODO: also check that babe_finalized_block_epoch_information is None if and only if block is in epoch #0
+        let finalized_consensus = match config.chain_information_config.chain_information.consensus
+        {
+            chain_information::ChainInformationConsensus::Aura {
+                finalized_authorities_list,
+                slot_duration,
+            } => FinalizedConsensus::Aura {
+                authorities_list: Arc::new(finalized_authorities_list),
+                slot_duration,
+            },
+            chain_information::ChainInformationConsensus::Babe {
+                genesis_config,
+                finalized_block1_slot_number,
+                finalized_block_epoch_information,
+                finalized_next_epoch_transition,
+            } => {
+                if finalized_block_number >= 1 {
+                    assert!(finalized_block1_slot_number.is_some());
+                    assert!(finalized_next_epoch_transition.is_some());
+                } else {
+                    assert!(finalized_next_epoch_transition.is_none());
+                    assert!(finalized_block_epoch_information.is_none());
+                }
+
+                // TODO: also check that finalized_block_epoch_information is None if and only if block is in epoch #0
+
+                FinalizedConsensus::Babe {
+                    genesis_config,
+                    block_epoch_information: finalized_block_epoch_information.map(Arc::new),
+                    next_epoch_transition: finalized_next_epoch_transition.map(Arc::new),
+                    block1_slot_number: finalized_block1_slot_number,
+                    disabled_authorities: Vec::new(),
+                    randomness_accumulator: None,
+                }
+            }
+        };
 
         let finalized_block_hash = config
             .chain_information_config
@@ -191,14 +430,21 @@ impl<T> NonFinalizedTree<T> {
             .finalized_block_header
             .hash();
 
-        if let Some(scheduled) = config
-            .chain_information_config
-            .chain_information
-            .grandpa_finalized_scheduled_change
-            .as_ref()
+        for change in [
+            &config
+                .chain_information_config
+                .chain_information
+                .grandpa_finalized_scheduled_change,
+            &config
+                .chain_information_config
+                .chain_information
+                .grandpa_finalized_forced_change,
+        ]
+        .iter()
+        .filter_map(|c| c.as_ref())
         {
             assert!(
-                scheduled.0
+                change.0
                     > config
                         .chain_information_config
                         .chain_information
@@ -207,50 +453,124 @@ impl<T> NonFinalizedTree<T> {
             );
         }
 
-        NonFinalizedTree {
-            finalized_block_header: config
+        assert!(
+            config
                 .chain_information_config
                 .chain_information
-                .finalized_block_header,
-            finalized_block_hash,
-            grandpa_after_finalized_block_authorities_set_id: config
+                .grandpa_finalized_scheduled_change
+                .is_none()
+                || config
+                    .chain_information_config
+                    .chain_information
+                    .grandpa_finalized_forced_change
+                    .is_none()
+        );
+
+        let finalized_grandpa = grandpa::GrandpaState {
+            authorities_set_id: config
                 .chain_information_config
                 .chain_information
                 .grandpa_after_finalized_block_authorities_set_id,
-            grandpa_finalized_triggered_authorities: config
-                .chain_information_config
-                .chain_information
-                .grandpa_finalized_triggered_authorities,
-            grandpa_finalized_scheduled_change: config
+            triggered_authorities: Arc::new(
+                config
+                    .chain_information_config
+                    .chain_information
+                    .grandpa_finalized_triggered_authorities,
+            ),
+            scheduled_change: config
                 .chain_information_config
                 .chain_information
-                .grandpa_finalized_scheduled_change,
-            babe_genesis_config: config.chain_information_config.babe_genesis_config,
-            babe_finalized_block1_slot_number: config
+                .grandpa_finalized_scheduled_change
+                .map(|(n, list)| (n, Arc::new(list))),
+            forced_change: config
                 .chain_information_config
                 .chain_information
-                .babe_finalized_block1_slot_number,
-            babe_finalized_block_epoch_information: config
+                .grandpa_finalized_forced_change
+                .map(|(n, list)| (n, Arc::new(list))),
+            pause_state: config
                 .chain_information_config
                 .chain_information
-                .babe_finalized_block_epoch_information
-                .map(Arc::new),
-            babe_finalized_next_epoch_transition: config
+                .grandpa_finalized_pause_state,
+        };
+
+        NonFinalizedTree {
+            finalized_block_header: config
                 .chain_information_config
                 .chain_information
-                .babe_finalized_next_epoch_transition
-                .map(Arc::new),
+                .finalized_block_header,
+            finalized_block_hash,
+            finalized_grandpa,
+            finalized_consensus,
+            babe_finalized_block_weight: 0,
+            fork_choice: config.fork_choice,
             blocks: fork_tree::ForkTree::with_capacity(config.blocks_capacity),
             current_best: None,
+            events: if config.generate_events {
+                Some(VecDeque::new())
+            } else {
+                None
+            },
+            babe_seen_slots: BTreeMap::new(),
+            bad_blocks: config.chain_information_config.bad_blocks,
+            fork_blocks: config.chain_information_config.fork_blocks,
+            node_store: None,
         }
     }
 
+    /// Initializes a new tree containing the non-finalized blocks found in a snapshot
+    /// previously generated with [`NonFinalizedTree::snapshot`].
+    ///
+    /// The finalized block found in the configuration must be the same as the one of the tree
+    /// the snapshot has been taken from. The `user_data` function is called for each restored
+    /// block in order to generate its user data.
+    ///
+    /// No [`ChainEvent`] is generated for the restored blocks.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the chain information is incorrect.
+    ///
+    pub fn from_snapshot(
+        config: Config,
+        snapshot: &[u8],
+        user_data: impl FnMut(header::HeaderRef) -> T,
+    ) -> Result<Self, SnapshotDecodeError> {
+        snapshot::decode(config, snapshot, user_data)
+    }
+
     /// Removes all non-finalized blocks from the tree.
     pub fn clear(&mut self) {
+        if let Some(events) = &mut self.events {
+            if self.current_best.is_some() {
+                events.push_back(ChainEvent::BestBlockChanged(best_block_change(
+                    &self.blocks,
+                    (
+                        self.finalized_block_header.number,
+                        self.finalized_block_hash,
+                    ),
+                    self.current_best,
+                    None,
+                )));
+            }
+            if !self.blocks.is_empty() {
+                events.push_back(ChainEvent::ForksPruned {
+                    pruned: self.blocks.iter().map(|b| b.hash).collect(),
+                });
+            }
+        }
+
         self.blocks.clear();
         self.current_best = None;
     }
 
+    /// Returns the next event that happened on the tree, if any.
+    ///
+    /// Events are returned in the order in which they happened. Always returns `None` if
+    /// [`Config::generate_events`] was `false`.
+    pub fn next_event(&mut self) -> Option<ChainEvent> {
+        self.events.as_mut()?.pop_front()
+    }
+
     /// Returns true if there isn't any non-finalized block in the chain.
     pub fn is_empty(&self) -> bool {
         self.blocks.is_empty()
@@ -271,27 +591,64 @@ impl<T> NonFinalizedTree<T> {
         self.blocks.shrink_to_fit()
     }
 
+    /// Serializes the non-finalized blocks of the tree, alongside with their consensus-related
+    /// state, into a versioned format. The tree can later be restored with
+    /// [`NonFinalizedTree::from_snapshot`], which avoids downloading and verifying these blocks
+    /// again.
+    ///
+    /// The user data of the blocks isn't part of the snapshot. Neither is the finalized block,
+    /// which should be stored separately by means of [`NonFinalizedTree::as_chain_information`].
+    pub fn snapshot(&self) -> Vec<u8> {
+        snapshot::encode(self)
+    }
+
     /// Builds a [`chain_information::ChainInformationRef`] struct that might later be used to
     /// build a new [`NonFinalizedTree`].
     pub fn as_chain_information(&self) -> chain_information::ChainInformationRef {
         chain_information::ChainInformationRef {
             finalized_block_header: (&self.finalized_block_header).into(),
-            babe_finalized_block1_slot_number: self.babe_finalized_block1_slot_number,
-            babe_finalized_block_epoch_information: self
-                .babe_finalized_block_epoch_information
-                .as_ref()
-                .map(|info| ((&info.0).into(), info.1)),
-            babe_finalized_next_epoch_transition: self
-                .babe_finalized_next_epoch_transition
-                .as_ref()
-                .map(|info| ((&info.0).into(), info.1)),
+            consensus: match &self.finalized_consensus {
+                FinalizedConsensus::Aura {
+                    authorities_list,
+                    slot_duration,
+                } => chain_information::ChainInformationConsensusRef::Aura {
+                    finalized_authorities_list: header::AuraAuthoritiesIter::from_slice(
+                        authorities_list,
+                    ),
+                    slot_duration: *slot_duration,
+                },
+                FinalizedConsensus::Babe {
+                    genesis_config,
+                    block_epoch_information,
+                    next_epoch_transition,
+                    block1_slot_number,
+                    ..
+                } => chain_information::ChainInformationConsensusRef::Babe {
+                    genesis_config,
+                    finalized_block1_slot_number: *block1_slot_number,
+                    finalized_block_epoch_information: block_epoch_information
+                        .as_ref()
+                        .map(|info| ((&info.0).into(), info.1)),
+                    finalized_next_epoch_transition: next_epoch_transition
+                        .as_ref()
+                        .map(|info| ((&info.0).into(), info.1)),
+                },
+            },
             grandpa_after_finalized_block_authorities_set_id: self
-                .grandpa_after_finalized_block_authorities_set_id,
-            grandpa_finalized_triggered_authorities: &self.grandpa_finalized_triggered_authorities,
+                .finalized_grandpa
+                .authorities_set_id,
+            grandpa_finalized_triggered_authorities: &self.finalized_grandpa.triggered_authorities,
             grandpa_finalized_scheduled_change: self
-                .grandpa_finalized_scheduled_change
+                .finalized_grandpa
+                .scheduled_change
+                .as_ref()
+                .map(|(n, l)| (*n, &l[..])),
+            grandpa_finalized_forced_change: self
+                .finalized_grandpa
+                .forced_change
                 .as_ref()
                 .map(|(n, l)| (*n, &l[..])),
+            grandpa_finalized_pause_state: self.finalized_grandpa.pause_state,
         }
     }
 
@@ -305,6 +662,37 @@ impl<T> NonFinalizedTree<T> {
         self.finalized_block_hash
     }
 
+    /// Attaches a [`node_store::NodeStore`] to the tree, replacing the previous one if any.
+    ///
+    /// Whenever a block is finalized, [`node_store::NodeStore::set_finalized_block`] is called
+    /// with the new finalized block, or with its closest ancestor whose state is in the store.
+    /// The state of the blocks must be inserted in the store with
+    /// [`node_store::NodeStore::insert_block`], using [`NonFinalizedTree::node_store_mut`].
+    ///
+    /// Returns an error, and leaves the tree untouched, if the latest finalized block of the
+    /// store isn't the latest finalized block of the tree.
+    pub fn set_node_store(
+        &mut self,
+        node_store: node_store::NodeStore,
+    ) -> Result<(), SetNodeStoreError> {
+        if node_store.finalized_block_hash() != self.finalized_block_hash {
+            return Err(SetNodeStoreError::FinalizedBlockMismatch(node_store));
+        }
+
+        self.node_store = Some(node_store);
+        Ok(())
+    }
+
+    /// Returns the [`node_store::NodeStore`] passed to [`NonFinalizedTree::set_node_store`].
+    pub fn node_store(&self) -> Option<&node_store::NodeStore> {
+        self.node_store.as_ref()
+    }
+
+    /// Returns the [`node_store::NodeStore`] passed to [`NonFinalizedTree::set_node_store`].
+    pub fn node_store_mut(&mut self) -> Option<&mut node_store::NodeStore> {
+        self.node_store.as_mut()
+    }
+
     /// Returns the header of the best block.
     pub fn best_block_header(&self) -> header::HeaderRef {
         if let Some(index) = self.current_best {
@@ -323,6 +711,82 @@ impl<T> NonFinalizedTree<T> {
         }
     }
 
+    /// Returns the identifier of the GrandPa authorities set that is expected to finalize the
+    /// children of the current best block. This value can be used to verify ahead of time the
+    /// signatures of the justifications of descendants of the best block with
+    /// [`justification::verify::pre_verify_signatures`].
+    pub fn best_block_grandpa_authorities_set_id(&self) -> u64 {
+        self.grandpa_state(self.current_best).authorities_set_id
+    }
+
+    /// Returns the information about the BABE epochs that the children of the current best
+    /// block can belong to. This information can be used to verify ahead of time the seals of
+    /// descendants of the best block with [`verify::babe::pre_verify_seal`].
+    ///
+    /// Returns `None` if the chain doesn't use BABE.
+    pub fn babe_best_block_epochs(&self) -> Option<BabeBestBlockEpochs> {
+        let (genesis_config, block1_slot_number, current_epoch, next_epoch) =
+            match self.consensus_after(self.current_best) {
+                ConsensusAfterRef::Babe {
+                    genesis_config,
+                    block1_slot_number,
+                    current_epoch,
+                    next_epoch,
+                    ..
+                } => (
+                    genesis_config,
+                    block1_slot_number,
+                    current_epoch.cloned(),
+                    next_epoch.cloned(),
+                ),
+                ConsensusAfterRef::Aura { .. } => return None,
+            };
+
+        let best_header = match self.current_best {
+            Some(index) => &self.blocks.get(index).unwrap().header,
+            None => &self.finalized_block_header,
+        };
+
+        let epoch0 = || {
+            Arc::new((
+                header::BabeNextEpoch::from(genesis_config.epoch0_information()),
+                genesis_config.epoch0_configuration(),
+            ))
+        };
+
+        let mut epochs = Vec::with_capacity(2);
+        if best_header.number == 0 {
+            // The children of the genesis block belong to epoch #0.
+            epochs.push((0, epoch0()));
+        } else if let (Some(pre_digest), Some(block1_slot_number)) =
+            (best_header.digest.babe_pre_runtime(), block1_slot_number)
+        {
+            if let Some(epoch_number) = pre_digest
+                .slot_number()
+                .checked_sub(block1_slot_number)
+                .and_then(|diff| diff.checked_div(genesis_config.slots_per_epoch()))
+            {
+                epochs.push((epoch_number, current_epoch.unwrap_or_else(epoch0)));
+                if let Some(next_epoch) = next_epoch {
+                    epochs.push((epoch_number + 1, next_epoch));
+                }
+            }
+        }
+
+        Some(BabeBestBlockEpochs {
+            slots_per_epoch: genesis_config.slots_per_epoch(),
+            block1_slot_number,
+            epochs,
+        })
+    }
+
+    /// Returns true if the block with the given hash is in the tree of non-finalized blocks.
+    ///
+    /// Always returns `false` for the latest finalized block.
+    pub fn contains_non_finalized_block(&self, hash: &[u8; 32]) -> bool {
+        self.blocks.find(|b| b.hash == *hash).is_some()
+    }
+
     /// Verifies the given block.
     ///
     /// The verification is performed in the context of the chain. In particular, the
@@ -330,10 +794,14 @@ impl<T> NonFinalizedTree<T> {
     ///
     /// If the verification succeeds, an [`HeaderInsert`] object might be returned which can be
     /// used to then insert the block in the chain.
+    ///
+    /// `now_from_unix_epoch` must be the time elapsed since the Unix Epoch. It is used in order
+    /// to reject blocks whose slot is in the future.
     #[must_use]
     pub fn verify_header(
         &mut self,
         scale_encoded_header: Vec<u8>,
+        now_from_unix_epoch: Duration,
     ) -> Result<HeaderVerifySuccess<T>, HeaderVerifyError> {
         // TODO: lots of code here is duplicated from verify_body
 
@@ -350,6 +818,17 @@ impl<T> NonFinalizedTree<T> {
             return Ok(HeaderVerifySuccess::Duplicate);
         }
 
+        if self.bad_blocks.contains(&hash) {
+            return Err(HeaderVerifyError::BadBlock);
+        }
+        if let Some(expected_hash) = self.fork_blocks.get(&decoded_header.number) {
+            if *expected_hash != hash {
+                return Err(HeaderVerifyError::ForkBlockMismatch {
+                    expected_hash: *expected_hash,
+                });
+            }
+        }
+
         // Try to find the parent block in the tree of known blocks.
         // `Some` with an index of the parent within the tree of unfinalized blocks.
         // `None` means that the parent is the finalized block.
@@ -372,31 +851,6 @@ impl<T> NonFinalizedTree<T> {
             }
         };
 
-        // Try to find the slot number of block 1.
-        // If block 1 is finalized, this information is found in
-        // `babe_finalized_block1_slot_number`, otherwise the information is found in the parent
-        // node in the fork tree.
-        let block1_slot_number = if let Some(val) = self.babe_finalized_block1_slot_number {
-            debug_assert!(parent_tree_index.map_or(true, |p_idx| self
-                .blocks
-                .get(p_idx)
-                .unwrap()
-                .babe_block1_slot_number
-                == val));
-            Some(val)
-        } else if let Some(parent_tree_index) = parent_tree_index {
-            Some(
-                self.blocks
-                    .get(parent_tree_index)
-                    .unwrap()
-                    .babe_block1_slot_number,
-            )
-        } else {
-            // Can only happen if parent is the block #0.
-            assert_eq!(decoded_header.number, 1);
-            None
-        };
-
         let parent_block_header = if let Some(parent_tree_index) = parent_tree_index {
             &self.blocks.get(parent_tree_index).unwrap().header
         } else {
@@ -404,14 +858,8 @@ impl<T> NonFinalizedTree<T> {
         };
 
         let mut process = verify::header_only::verify(verify::header_only::Config {
-            babe_genesis_configuration: &self.babe_genesis_config,
-            block1_slot_number,
-            now_from_unix_epoch: {
-                // TODO: is it reasonable to use the stdlib here?
-                //std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap()
-                // TODO: this is commented out because of Wasm support
-                core::time::Duration::new(0, 0)
-            },
+            consensus: self.verify_config_consensus(parent_tree_index),
+            now_from_unix_epoch,
             block_header: decoded_header.clone(),
             parent_block_header: parent_block_header.into(),
         });
@@ -424,21 +872,10 @@ impl<T> NonFinalizedTree<T> {
                 }
                 verify::header_only::Verify::ReadyToRun(run) => process = run.run(),
                 verify::header_only::Verify::BabeEpochInformation(epoch_info_rq) => {
-                    let epoch_info = if let Some(parent_tree_index) = parent_tree_index {
-                        let parent = self.blocks.get(parent_tree_index).unwrap();
-                        if epoch_info_rq.same_epoch_as_parent() {
-                            parent.babe_current_epoch.as_ref().unwrap()
-                        } else {
-                            &parent.babe_next_epoch
-                        }
-                    } else if epoch_info_rq.same_epoch_as_parent() {
-                        self.babe_finalized_block_epoch_information
-                            .as_ref()
-                            .unwrap()
-                    } else {
-                        self.babe_finalized_next_epoch_transition.as_ref().unwrap()
-                    };
-
+                    let epoch_info = self.babe_epoch_information(
+                        parent_tree_index,
+                        epoch_info_rq.same_epoch_as_parent(),
+                    );
                     process = epoch_info_rq
                         .inject_epoch((From::from(&epoch_info.0), epoch_info.1))
                         .run();
@@ -446,86 +883,24 @@ impl<T> NonFinalizedTree<T> {
             }
         };
 
-        let is_new_best = if let Some(current_best) = self.current_best {
-            is_better_block(
-                &self.blocks,
-                current_best,
-                parent_tree_index,
-                decoded_header.clone(),
-            )
-        } else {
-            true
-        };
+        let (grandpa, grandpa_triggered_change) = self
+            .child_grandpa_state(parent_tree_index, &decoded_header)
+            .map_err(HeaderVerifyError::GrandpaChange)?;
 
-        let babe_block1_slot_number = block1_slot_number.unwrap_or_else(|| {
-            debug_assert_eq!(decoded_header.number, 1);
-            result.slot_number
-        });
-
-        let babe_current_epoch = if result.babe_epoch_transition_target.is_some() {
-            if let Some(parent_tree_index) = parent_tree_index {
-                Some(
-                    self.blocks
-                        .get(parent_tree_index)
-                        .unwrap()
-                        .babe_next_epoch
-                        .clone(),
-                )
-            } else {
-                self.babe_finalized_next_epoch_transition.clone()
-            }
-        } else if let Some(parent_tree_index) = parent_tree_index {
-            self.blocks
-                .get(parent_tree_index)
-                .unwrap()
-                .babe_current_epoch
-                .clone()
-        } else {
-            self.babe_finalized_block_epoch_information.clone()
-        };
+        let babe_primary_slots_weight =
+            self.block_weight(parent_tree_index) + babe_primary_slot_weight(&decoded_header);
 
-        let babe_next_epoch = match (
-            decoded_header.digest.babe_epoch_information(),
+        let best_block_change = self.new_block_best_change(
             parent_tree_index,
-            &self.babe_finalized_next_epoch_transition,
-        ) {
-            (Some((ref new_epoch, Some(new_config))), _, _) => {
-                Arc::new((new_epoch.clone().into(), new_config))
-            }
-            (Some((ref new_epoch, None)), Some(parent_tree_index), _) => {
-                let new_config = self
-                    .blocks
-                    .get(parent_tree_index)
-                    .unwrap()
-                    .babe_next_epoch
-                    .1
-                    .clone();
-                Arc::new((new_epoch.clone().into(), new_config))
-            }
-            (Some((ref new_epoch, None)), None, Some(finalized_next)) => {
-                Arc::new((new_epoch.clone().into(), finalized_next.1))
-            }
-            (Some((ref new_epoch, None)), None, None) => Arc::new((
-                new_epoch.clone().into(),
-                self.babe_genesis_config.epoch0_configuration(),
-            )),
-            (None, Some(parent_tree_index), _) => self
-                .blocks
-                .get(parent_tree_index)
-                .unwrap()
-                .babe_next_epoch
-                .clone(),
-            (None, None, _) => {
-                // Block 1 always contains a Babe epoch transition. Consequently, this block
-                // can't be reached for block 1.
-                // `babe_finalized_next_epoch_transition` is `None` only if the finalized
-                // block is 0.
-                // `babe_finalized_next_epoch_transition` is therefore always `Some`
-                // Q.E.D.
-                debug_assert_ne!(decoded_header.number, 1);
-                self.babe_finalized_next_epoch_transition.clone().unwrap()
-            }
-        };
+            hash,
+            decoded_header.number,
+            babe_primary_slots_weight,
+        );
+        let is_new_best = best_block_change.is_some();
+
+        let consensus =
+            self.child_block_consensus(parent_tree_index, &decoded_header, result.consensus);
+        let babe_equivocation = self.babe_check_equivocation(&decoded_header, &consensus);
 
         Ok(HeaderVerifySuccess::Insert {
             block_height: decoded_header.number,
@@ -533,12 +908,14 @@ impl<T> NonFinalizedTree<T> {
             insert: HeaderInsert {
                 chain: self,
                 parent_tree_index,
-                is_new_best,
+                best_block_change,
                 header: decoded_header.into(),
                 hash,
-                babe_block1_slot_number,
-                babe_current_epoch,
-                babe_next_epoch,
+                consensus,
+                babe_primary_slots_weight,
+                babe_equivocation,
+                grandpa,
+                grandpa_triggered_change,
             },
         })
     }
@@ -553,7 +930,15 @@ impl<T> NonFinalizedTree<T> {
     /// finished or the process aborted, at which point the [`NonFinalizedTree`] can be retrieved
     /// back. The state of the [`NonFinalizedTree`] isn't modified until [`BodyInsert::insert`] is
     /// called after the end of the verification.
-    pub fn verify_body<I, E>(self, scale_encoded_header: Vec<u8>, body: I) -> BodyVerifyStep1<T, I>
+    ///
+    /// `now_from_unix_epoch` must be the time elapsed since the Unix Epoch. It is used in order
+    /// to reject blocks whose slot is in the future.
+    pub fn verify_body<I, E>(
+        self,
+        scale_encoded_header: Vec<u8>,
+        body: I,
+        now_from_unix_epoch: Duration,
+    ) -> BodyVerifyStep1<T, I>
     where
         I: ExactSizeIterator<Item = E> + Clone,
         E: AsRef<[u8]> + Clone,
@@ -569,6 +954,19 @@ impl<T> NonFinalizedTree<T> {
             return BodyVerifyStep1::Duplicate(self);
         }
 
+        if self.bad_blocks.contains(&hash) {
+            return BodyVerifyStep1::BadBlock(self);
+        }
+        if let Some(expected_hash) = self.fork_blocks.get(&decoded_header.number) {
+            if *expected_hash != hash {
+                let expected_hash = *expected_hash;
+                return BodyVerifyStep1::ForkBlockMismatch {
+                    chain: self,
+                    expected_hash,
+                };
+            }
+        }
+
         // Try to find the parent block in the tree of known blocks.
         // `Some` with an index of the parent within the tree of unfinalized blocks.
         // `None` means that the parent is the finalized block.
@@ -594,37 +992,21 @@ impl<T> NonFinalizedTree<T> {
             }
         };
 
-        // Try to find the slot number of block 1.
-        // If block 1 is finalized, this information is found in
-        // `babe_finalized_block1_slot_number`, otherwise the information is found in the parent
-        // node in the fork tree.
-        let block1_slot_number = if let Some(val) = self.babe_finalized_block1_slot_number {
-            debug_assert!(parent_tree_index.map_or(true, |p_idx| self
-                .blocks
-                .get(p_idx)
-                .unwrap()
-                .babe_block1_slot_number
-                == val));
-            Some(val)
-        } else if let Some(parent_tree_index) = parent_tree_index {
-            Some(
-                self.blocks
-                    .get(parent_tree_index)
-                    .unwrap()
-                    .babe_block1_slot_number,
-            )
-        } else {
-            // Can only happen if parent is the block #0.
-            assert_eq!(decoded_header.number, 1);
-            None
-        };
+        let (grandpa, grandpa_triggered_change) =
+            match self.child_grandpa_state(parent_tree_index, &decoded_header) {
+                Ok(v) => v,
+                Err(err) => return BodyVerifyStep1::BadGrandpaChange(self, err),
+            };
 
         BodyVerifyStep1::ParentRuntimeRequired(BodyVerifyRuntimeRequired {
             chain: self,
             header: decoded_header.into(),
             parent_tree_index,
             body,
-            block1_slot_number,
+            now_from_unix_epoch,
+            grandpa,
+            grandpa_triggered_change,
+            babe_pre_verified_seal: None,
         })
     }
 
@@ -635,10 +1017,26 @@ impl<T> NonFinalizedTree<T> {
     ///
     /// If the verification succeeds, a [`JustificationApply`] object will be returned which can
     /// be used to apply the finalization.
-    // TODO: expand the documentation about how blocks with authorities changes have to be finalized before any further block can be finalized
+    ///
+    /// A block that triggers a GrandPa scheduled change of authorities must be finalized before
+    /// any of its descendants can be, as the authorities that finalize its descendants are only
+    /// known for sure once the change is finalized. Trying to verify a justification targeting
+    /// one of these descendants returns [`JustificationVerifyError::TooFarAhead`].
     pub fn verify_justification(
         &mut self,
         scale_encoded_justification: &[u8],
+    ) -> Result<JustificationApply<T>, JustificationVerifyError> {
+        self.verify_justification_pre_verified(scale_encoded_justification, None)
+    }
+
+    /// Same as [`NonFinalizedTree::verify_justification`], but is passed the outcome of calling
+    /// [`justification::verify::pre_verify_signatures`] on the justification, if any.
+    ///
+    /// See [`justification::verify::Config::pre_verified_signatures`].
+    pub fn verify_justification_pre_verified(
+        &mut self,
+        scale_encoded_justification: &[u8],
+        pre_verified_signatures: Option<&justification::verify::PreVerifiedSignatures>,
     ) -> Result<JustificationApply<T>, JustificationVerifyError> {
         // Turn justification into a strongly-typed struct.
         let decoded = justification::decode::decode(&scale_encoded_justification)
@@ -655,95 +1053,52 @@ impl<T> NonFinalizedTree<T> {
             }
         };
 
-        // If any block between the latest finalized one and the target block trigger any GrandPa
-        // authorities change, then we need to finalize that triggering block (or any block
-        // after or including the one that schedules these changes) before finalizing the one
-        // targeted by the justification.
-        // TODO: rethink and reexplain this ^
+        // If any block between the latest finalized one and the target block triggers a
+        // scheduled GrandPa authorities change, then this triggering block must be finalized
+        // before any of its descendants can be. This isn't the case for forced changes, which
+        // are triggered when the block is imported, and after which the blocks that haven't been
+        // finalized by the previous authorities can only be finalized by the new ones.
+        let mut block_to_finalize_first = None;
+        for node in self.blocks.root_to_node_path(block_index) {
+            if node == block_index {
+                break;
+            }
 
-        // Find out the next block height where an authority change will be triggered.
-        let earliest_trigger = {
-            // Scheduled change that is already finalized.
-            let scheduled = self
-                .grandpa_finalized_scheduled_change
-                .as_ref()
-                .map(|(n, _)| *n);
-
-            // First change that would be scheduled if we finalize the target block.
-            let would_happen = {
-                let mut trigger_height = None;
-                // TODO: lot of boilerplate code here
-                for node in self.blocks.root_to_node_path(block_index) {
-                    let header = &self.blocks.get(node).unwrap().header;
-                    for grandpa_digest_item in header.digest.logs().filter_map(|d| match d {
-                        header::DigestItemRef::GrandpaConsensus(gp) => Some(gp),
-                        _ => None,
-                    }) {
-                        match grandpa_digest_item {
-                            header::GrandpaConsensusLogRef::ScheduledChange(change) => {
-                                let trigger_block_height =
-                                    header.number.checked_add(u64::from(change.delay)).unwrap();
-                                match trigger_height {
-                                    Some(_) => panic!("invalid block!"), // TODO: this problem is not checked during block verification
-                                    None => trigger_height = Some(trigger_block_height),
-                                }
-                            }
-                            _ => {} // TODO: unimplemented
-                        }
-                    }
+            match self.blocks.get(node).unwrap().grandpa_triggered_change {
+                Some(grandpa::TriggeredChange::Scheduled) if block_to_finalize_first.is_none() => {
+                    block_to_finalize_first = Some(node)
                 }
-                trigger_height
-            };
-
-            match (scheduled, would_happen) {
-                (Some(a), Some(b)) => Some(cmp::min(a, b)),
-                (Some(a), None) => Some(a),
-                (None, Some(b)) => Some(b),
-                (None, None) => None,
+                Some(grandpa::TriggeredChange::Forced) => block_to_finalize_first = None,
+                _ => {}
             }
-        };
+        }
 
-        // As explained above, `target_number` must be <= `earliest_trigger`, otherwise the
-        // finalization is unsecure.
-        if let Some(earliest_trigger) = earliest_trigger {
-            if u64::from(decoded.target_number) > earliest_trigger {
-                let block_to_finalize_hash = self
-                    .blocks
-                    .node_to_root_path(block_index)
-                    .filter_map(|b| {
-                        let b = self.blocks.get(b).unwrap();
-                        if b.header.number == earliest_trigger {
-                            Some(b.hash)
-                        } else {
-                            None
-                        }
-                    })
-                    .next()
-                    .unwrap();
-                return Err(JustificationVerifyError::TooFarAhead {
-                    justification_block_number: u64::from(decoded.target_number),
-                    justification_block_hash: *decoded.target_hash,
-                    block_to_finalize_number: earliest_trigger,
-                    block_to_finalize_hash,
-                });
-            }
+        if let Some(block_to_finalize_first) = block_to_finalize_first {
+            let block = self.blocks.get(block_to_finalize_first).unwrap();
+            return Err(JustificationVerifyError::TooFarAhead {
+                justification_block_number: u64::from(decoded.target_number),
+                justification_block_hash: *decoded.target_hash,
+                block_to_finalize_number: block.header.number,
+                block_to_finalize_hash: block.hash,
+            });
         }
 
-        // Find which authorities are supposed to finalize the target block.
-        let authorities_list = self
-            .grandpa_finalized_scheduled_change
-            .as_ref()
-            .filter(|(trigger_height, _)| *trigger_height < u64::from(decoded.target_number))
-            .map(|(_, list)| list)
-            .unwrap_or(&self.grandpa_finalized_triggered_authorities);
-
-        // As per above check, we know that the authorities of the target block are either the
-        // same as the ones of the latest finalized block, or the ones contained in the header of
-        // the latest finalized block.
-        justification::verify::verify(justification::verify::Config {
+        // The authorities that are supposed to finalize the target block are found in the
+        // GrandPa state of its parent.
+        let parent_grandpa = self.grandpa_state(self.blocks.parent(block_index));
+
+        if !parent_grandpa.authorities_voting(u64::from(decoded.target_number)) {
+            return Err(JustificationVerifyError::AuthoritiesPaused);
+        }
+
+        let success = justification::verify::verify(justification::verify::Config {
             justification: decoded,
-            authorities_set_id: self.grandpa_after_finalized_block_authorities_set_id,
-            authorities_list: authorities_list.iter().map(|a| a.public_key),
+            authorities_set_id: parent_grandpa.authorities_set_id,
+            authorities_list: parent_grandpa
+                .triggered_authorities
+                .iter()
+                .map(header::GrandpaAuthorityRef::from),
+            pre_verified_signatures,
         })
         .map_err(JustificationVerifyError::VerificationFailed)?;
 
@@ -751,9 +1106,95 @@ impl<T> NonFinalizedTree<T> {
         Ok(JustificationApply {
             chain: self,
             to_finalize: block_index,
+            equivocations: success.equivocations,
         })
     }
 
+    /// Verifies the given finality proof and applies it.
+    ///
+    /// The headers contained in the proof that aren't in the chain yet are verified and inserted
+    /// with the user data returned by `user_data`. The justifications of the fragments are then
+    /// verified and applied one by one, in order, as if passed to
+    /// [`NonFinalizedTree::verify_justification`].
+    ///
+    /// On success, returns the user datas of the newly-finalized blocks, ordered by increasing
+    /// block number.
+    ///
+    /// If the verification of a fragment fails, the headers and fragments that precede it stay
+    /// inserted and applied. The error is then returned alongside with the user datas of the
+    /// blocks that these fragments have finalized, ordered by increasing block number.
+    pub fn verify_finality_proof(
+        &mut self,
+        scale_encoded_proof: &[u8],
+        now_from_unix_epoch: Duration,
+        user_data: impl FnMut(header::HeaderRef) -> T,
+    ) -> Result<Vec<T>, (FinalityProofVerifyError, Vec<T>)> {
+        let mut finalized = Vec::new();
+        match self.verify_finality_proof_inner(
+            scale_encoded_proof,
+            now_from_unix_epoch,
+            user_data,
+            &mut finalized,
+        ) {
+            Ok(()) => Ok(finalized),
+            Err(err) => Err((err, finalized)),
+        }
+    }
+
+    /// Private function that does the same as [`NonFinalizedTree::verify_finality_proof`]. The
+    /// user datas of the finalized blocks are pushed to `finalized`.
+    fn verify_finality_proof_inner(
+        &mut self,
+        scale_encoded_proof: &[u8],
+        now_from_unix_epoch: Duration,
+        mut user_data: impl FnMut(header::HeaderRef) -> T,
+        finalized: &mut Vec<T>,
+    ) -> Result<(), FinalityProofVerifyError> {
+        let fragments =
+            proof::decode(scale_encoded_proof).map_err(FinalityProofVerifyError::InvalidProof)?;
+        if fragments.is_empty() {
+            return Err(FinalityProofVerifyError::EmptyProof);
+        }
+
+        for fragment in fragments {
+            for unknown_header in fragment.unknown_headers {
+                if unknown_header.number <= self.finalized_block_header.number {
+                    continue;
+                }
+
+                let scale_encoded_header =
+                    unknown_header
+                        .scale_encoding()
+                        .fold(Vec::new(), |mut a, b| {
+                            a.extend_from_slice(b.as_ref());
+                            a
+                        });
+                match self
+                    .verify_header(scale_encoded_header, now_from_unix_epoch)
+                    .map_err(FinalityProofVerifyError::InvalidHeader)?
+                {
+                    HeaderVerifySuccess::Duplicate => {}
+                    HeaderVerifySuccess::Insert { insert, .. } => {
+                        insert.insert(user_data(unknown_header))
+                    }
+                }
+            }
+
+            let apply = self
+                .verify_justification(fragment.scale_encoded_justification)
+                .map_err(FinalityProofVerifyError::InvalidJustification)?;
+            if apply.chain.blocks.get(apply.to_finalize).unwrap().hash != *fragment.block_hash {
+                return Err(FinalityProofVerifyError::BlockHashMismatch);
+            }
+
+            let start = finalized.len();
+            finalized.extend(apply.apply());
+            finalized[start..].reverse();
+        }
+
+        Ok(())
+    }
+
     /// Sets the latest known finalized block. Trying to verify a block that isn't a descendant of
     /// that block will fail.
     ///
@@ -786,47 +1227,111 @@ impl<T> NonFinalizedTree<T> {
         &mut self,
         block_index: fork_tree::NodeIndex,
     ) -> SetFinalizedBlockIter<T> {
-        let target_block_height = self.blocks.get_mut(block_index).unwrap().header.number;
-
-        // Update the scheduled GrandPa change with the latest scheduled-but-non-finalized change
-        // that could be found.
-        self.grandpa_finalized_scheduled_change = None;
-        for node in self.blocks.root_to_node_path(block_index) {
-            let node = self.blocks.get(node).unwrap();
-            //node.header.number
-            for grandpa_digest_item in node.header.digest.logs().filter_map(|d| match d {
-                header::DigestItemRef::GrandpaConsensus(gp) => Some(gp),
-                _ => None,
-            }) {
-                match grandpa_digest_item {
-                    header::GrandpaConsensusLogRef::ScheduledChange(change) => {
-                        let trigger_block_height = node
-                            .header
-                            .number
-                            .checked_add(u64::from(change.delay))
-                            .unwrap();
-                        if trigger_block_height > target_block_height {
-                            self.grandpa_finalized_scheduled_change = Some((
-                                trigger_block_height,
-                                change.next_authorities.map(Into::into).collect(),
-                            ));
-                        } else {
-                            self.grandpa_finalized_triggered_authorities =
-                                change.next_authorities.map(Into::into).collect();
-                            self.grandpa_after_finalized_block_authorities_set_id += 1;
-                        }
-                    }
-                    _ => {} // TODO: unimplemented
-                }
+        // Prune the node store, if any. The state of the new finalized block might not have been
+        // inserted yet, in which case its closest ancestor in the store is finalized instead.
+        if let Some(node_store) = &mut self.node_store {
+            let blocks = &self.blocks;
+            let to_finalize = blocks
+                .node_to_root_path(block_index)
+                .map(|idx| blocks.get(idx).unwrap().hash)
+                .find(|hash| node_store.contains_block(hash));
+            if let Some(to_finalize) = to_finalize {
+                let _result = node_store.set_finalized_block(&to_finalize);
+                debug_assert!(_result.is_ok());
             }
         }
 
+        // Determine the best block after the finalization. If the current best block descends
+        // from the new finalized block, it stays the best block. Otherwise, the new best block is
+        // chosen amongst the new finalized block and its descendants.
+        let new_best = match self.current_best {
+            Some(best) if self.blocks.is_ancestor(block_index, best) => best,
+            _ => self
+                .blocks
+                .iter_with_indices()
+                .filter(|(index, _)| self.blocks.is_ancestor(block_index, *index))
+                .fold(block_index, |best, (index, block)| {
+                    let best_block = self.blocks.get(best).unwrap();
+                    if self.fork_choice.is_better(
+                        (block.babe_primary_slots_weight, block.header.number),
+                        (
+                            best_block.babe_primary_slots_weight,
+                            best_block.header.number,
+                        ),
+                    ) {
+                        index
+                    } else {
+                        best
+                    }
+                }),
+        };
+        let best_block_change = if Some(new_best) != self.current_best {
+            Some(best_block_change(
+                &self.blocks,
+                (
+                    self.finalized_block_header.number,
+                    self.finalized_block_hash,
+                ),
+                self.current_best,
+                Some(new_best),
+            ))
+        } else {
+            None
+        };
+        self.current_best = if new_best == block_index {
+            None
+        } else {
+            Some(new_best)
+        };
+
         let new_finalized_block = self.blocks.get_mut(block_index).unwrap();
 
-        self.babe_finalized_block_epoch_information =
-            new_finalized_block.babe_current_epoch.clone();
-        self.babe_finalized_next_epoch_transition =
-            Some(new_finalized_block.babe_next_epoch.clone());
+        self.finalized_grandpa = new_finalized_block.grandpa.clone();
+        self.babe_finalized_block_weight = new_finalized_block.babe_primary_slots_weight;
+
+        match (
+            &mut self.finalized_consensus,
+            &mut new_finalized_block.consensus,
+        ) {
+            (
+                FinalizedConsensus::Aura {
+                    authorities_list, ..
+                },
+                BlockConsensus::Aura {
+                    authorities_list: new_list,
+                },
+            ) => {
+                *authorities_list = new_list.clone();
+            }
+            (
+                FinalizedConsensus::Babe {
+                    block_epoch_information,
+                    next_epoch_transition,
+                    block1_slot_number,
+                    disabled_authorities,
+                    randomness_accumulator,
+                    ..
+                },
+                BlockConsensus::Babe {
+                    block1_slot_number: new_block1_slot_number,
+                    current_epoch,
+                    next_epoch,
+                    disabled_authorities: new_disabled_authorities,
+                    randomness_accumulator: new_randomness_accumulator,
+                },
+            ) => {
+                *block_epoch_information = current_epoch.clone();
+                *next_epoch_transition = Some(next_epoch.clone());
+                *disabled_authorities = mem::take(new_disabled_authorities);
+                *randomness_accumulator = new_randomness_accumulator.take();
+                if block1_slot_number.is_none() {
+                    debug_assert!(new_finalized_block.header.number >= 1);
+                    *block1_slot_number = Some(*new_block1_slot_number);
+                }
+            }
+            // The consensus of a block is always the same as the one of the finalized block.
+            _ => unreachable!(),
+        }
 
         mem::swap(
             &mut self.finalized_block_header,
@@ -834,17 +1339,416 @@ impl<T> NonFinalizedTree<T> {
         );
         self.finalized_block_hash = self.finalized_block_header.hash();
 
-        if self.babe_finalized_block1_slot_number.is_none() {
-            debug_assert!(self.finalized_block_header.number >= 1);
-            self.babe_finalized_block1_slot_number =
-                Some(new_finalized_block.babe_block1_slot_number);
+        if let (Some(events), Some(best_block_change)) = (&mut self.events, &best_block_change) {
+            events.push_back(ChainEvent::BestBlockChanged(best_block_change.clone()));
         }
 
+        let finalized_hash = self.finalized_block_hash;
+        let finalized_number = self.finalized_block_header.number;
         SetFinalizedBlockIter {
             iter: self.blocks.prune_ancestors(block_index),
-            current_best: &mut self.current_best,
+            best_block_change,
+            events: self.events.as_mut().map(|events| SetFinalizedEvents {
+                events,
+                finalized_hash,
+                finalized_number,
+                finalized: Vec::new(),
+                pruned: Vec::new(),
+            }),
+        }
+    }
+
+    /// Returns the fork choice weight of the block with the given index, or of the finalized
+    /// block if `None`.
+    fn block_weight(&self, tree_index: Option<fork_tree::NodeIndex>) -> u64 {
+        if let Some(tree_index) = tree_index {
+            self.blocks
+                .get(tree_index)
+                .unwrap()
+                .babe_primary_slots_weight
+        } else {
+            self.babe_finalized_block_weight
+        }
+    }
+
+    /// Returns the consensus state right after the block with the given index, or after the
+    /// finalized block if `None`.
+    fn consensus_after(&self, tree_index: Option<fork_tree::NodeIndex>) -> ConsensusAfterRef<'_> {
+        let block_consensus = tree_index.map(|idx| &self.blocks.get(idx).unwrap().consensus);
+
+        match (&self.finalized_consensus, block_consensus) {
+            (
+                FinalizedConsensus::Aura {
+                    authorities_list,
+                    slot_duration,
+                },
+                None,
+            ) => ConsensusAfterRef::Aura {
+                authorities_list,
+                slot_duration: *slot_duration,
+            },
+            (
+                FinalizedConsensus::Aura { slot_duration, .. },
+                Some(BlockConsensus::Aura { authorities_list }),
+            ) => ConsensusAfterRef::Aura {
+                authorities_list,
+                slot_duration: *slot_duration,
+            },
+            (
+                FinalizedConsensus::Babe {
+                    genesis_config,
+                    block_epoch_information,
+                    next_epoch_transition,
+                    block1_slot_number,
+                    disabled_authorities,
+                    randomness_accumulator,
+                },
+                None,
+            ) => ConsensusAfterRef::Babe {
+                genesis_config,
+                block1_slot_number: *block1_slot_number,
+                current_epoch: block_epoch_information.as_ref(),
+                next_epoch: next_epoch_transition.as_ref(),
+                disabled_authorities,
+                randomness_accumulator: randomness_accumulator.as_ref(),
+            },
+            (
+                FinalizedConsensus::Babe {
+                    genesis_config,
+                    block1_slot_number: finalized_block1_slot_number,
+                    ..
+                },
+                Some(BlockConsensus::Babe {
+                    block1_slot_number,
+                    current_epoch,
+                    next_epoch,
+                    disabled_authorities,
+                    randomness_accumulator,
+                }),
+            ) => {
+                debug_assert!(
+                    finalized_block1_slot_number.map_or(true, |n| n == *block1_slot_number)
+                );
+                ConsensusAfterRef::Babe {
+                    genesis_config,
+                    block1_slot_number: Some(*block1_slot_number),
+                    current_epoch: current_epoch.as_ref(),
+                    next_epoch: Some(next_epoch),
+                    disabled_authorities,
+                    randomness_accumulator: randomness_accumulator.as_ref(),
+                }
+            }
+            // The consensus of a block is always the same as the one of the finalized block.
+            (FinalizedConsensus::Aura { .. }, Some(BlockConsensus::Babe { .. }))
+            | (FinalizedConsensus::Babe { .. }, Some(BlockConsensus::Aura { .. })) => {
+                unreachable!()
+            }
+        }
+    }
+
+    /// Returns the consensus state right after the block with the given index, or after the
+    /// finalized block if `None`, in the format expected by the verification functions.
+    fn verify_config_consensus(
+        &self,
+        parent_tree_index: Option<fork_tree::NodeIndex>,
+    ) -> verify::header_only::ConfigConsensus {
+        match self.consensus_after(parent_tree_index) {
+            ConsensusAfterRef::Aura {
+                authorities_list,
+                slot_duration,
+            } => verify::header_only::ConfigConsensus::Aura {
+                current_authorities: header::AuraAuthoritiesIter::from_slice(authorities_list),
+                slot_duration,
+            },
+            ConsensusAfterRef::Babe {
+                genesis_config,
+                block1_slot_number,
+                disabled_authorities,
+                randomness_accumulator,
+                ..
+            } => verify::header_only::ConfigConsensus::Babe {
+                genesis_configuration: genesis_config,
+                // `None` only if the parent is the block #0.
+                block1_slot_number,
+                parent_disabled_authorities: disabled_authorities,
+                parent_randomness_accumulator: randomness_accumulator,
+                pre_verified_seal: None,
+            },
+        }
+    }
+
+    /// Returns the information about the BABE epoch of the block with the given index, or of
+    /// the finalized block if `None`, if `same_epoch` is `true`. Returns the information about
+    /// the epoch after it if `same_epoch` is `false`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the chain doesn't use BABE, or if the requested epoch isn't known.
+    ///
+    fn babe_epoch_information(
+        &self,
+        tree_index: Option<fork_tree::NodeIndex>,
+        same_epoch: bool,
+    ) -> &(header::BabeNextEpoch, header::BabeNextConfig) {
+        match self.consensus_after(tree_index) {
+            ConsensusAfterRef::Babe { current_epoch, .. } if same_epoch => current_epoch.unwrap(),
+            ConsensusAfterRef::Babe { next_epoch, .. } => next_epoch.unwrap(),
+            ConsensusAfterRef::Aura { .. } => panic!("not a BABE chain"),
+        }
+    }
+
+    /// Builds the consensus state right after a block whose parent is `parent_tree_index`, and
+    /// whose verification has yielded `verify_success`.
+    fn child_block_consensus(
+        &self,
+        parent_tree_index: Option<fork_tree::NodeIndex>,
+        header: &header::HeaderRef,
+        verify_success: verify::header_only::SuccessConsensus,
+    ) -> BlockConsensus {
+        // The verification has been performed using the consensus state of the parent, and the
+        // outcome is therefore always of the same consensus engine.
+        match (verify_success, self.consensus_after(parent_tree_index)) {
+            (
+                verify::header_only::SuccessConsensus::Aura { authorities_change },
+                ConsensusAfterRef::Aura {
+                    authorities_list: parent_authorities_list,
+                    ..
+                },
+            ) => {
+                let authorities_list = if authorities_change {
+                    let new_list = header
+                        .digest
+                        .logs()
+                        .find_map(|item| match item {
+                            header::DigestItemRef::AuraConsensus(
+                                header::AuraConsensusLogRef::AuthoritiesChange(list),
+                            ) => Some(list),
+                            _ => None,
+                        })
+                        .unwrap();
+                    Arc::new(new_list.map(Into::into).collect())
+                } else {
+                    parent_authorities_list.clone()
+                };
+
+                BlockConsensus::Aura { authorities_list }
+            }
+
+            (
+                verify::header_only::SuccessConsensus::Babe {
+                    epoch_transition_target,
+                    slot_number,
+                    disabled_authorities,
+                    randomness_accumulator,
+                    ..
+                },
+                ConsensusAfterRef::Babe {
+                    genesis_config,
+                    block1_slot_number: parent_block1_slot_number,
+                    current_epoch: parent_current_epoch,
+                    next_epoch: parent_next_epoch,
+                    ..
+                },
+            ) => {
+                let block1_slot_number = parent_block1_slot_number.unwrap_or_else(|| {
+                    debug_assert_eq!(header.number, 1);
+                    slot_number
+                });
+
+                let current_epoch = if epoch_transition_target.is_some() {
+                    parent_next_epoch.cloned()
+                } else {
+                    parent_current_epoch.cloned()
+                };
+
+                let next_epoch = match (header.digest.babe_epoch_information(), parent_next_epoch) {
+                    (Some((ref new_epoch, Some(new_config))), _) => {
+                        Arc::new((new_epoch.clone().into(), new_config))
+                    }
+                    (Some((ref new_epoch, None)), Some(parent_next_epoch)) => {
+                        Arc::new((new_epoch.clone().into(), parent_next_epoch.1))
+                    }
+                    (Some((ref new_epoch, None)), None) => Arc::new((
+                        new_epoch.clone().into(),
+                        genesis_config.epoch0_configuration(),
+                    )),
+                    (None, Some(parent_next_epoch)) => parent_next_epoch.clone(),
+                    (None, None) => {
+                        // Block 1 always contains a Babe epoch transition. Consequently, this
+                        // block can't be reached for block 1.
+                        // The next epoch transition of the finalized block is `None` only if the
+                        // finalized block is 0.
+                        // Q.E.D.
+                        unreachable!()
+                    }
+                };
+
+                BlockConsensus::Babe {
+                    block1_slot_number,
+                    current_epoch,
+                    next_epoch,
+                    disabled_authorities,
+                    randomness_accumulator,
+                }
+            }
+
+            (
+                verify::header_only::SuccessConsensus::Aura { .. },
+                ConsensusAfterRef::Babe { .. },
+            )
+            | (
+                verify::header_only::SuccessConsensus::Babe { .. },
+                ConsensusAfterRef::Aura { .. },
+            ) => {
+                unreachable!()
+            }
+        }
+    }
+
+    /// Returns the slot number and the public key of the author of the given BABE block, or
+    /// `None` if the block isn't a BABE block.
+    ///
+    /// `consensus` must be the consensus state of the block.
+    fn babe_block_author(
+        &self,
+        header: &header::HeaderRef,
+        consensus: &BlockConsensus,
+    ) -> Option<(u64, [u8; 32])> {
+        let pre_digest = header.digest.babe_pre_runtime()?;
+        let authority_index = usize::try_from(pre_digest.authority_index()).ok()?;
+
+        let public_key = match (consensus, &self.finalized_consensus) {
+            (
+                BlockConsensus::Babe {
+                    current_epoch: Some(current_epoch),
+                    ..
+                },
+                _,
+            ) => current_epoch.0.authorities.get(authority_index)?.public_key,
+            (
+                BlockConsensus::Babe {
+                    current_epoch: None,
+                    ..
+                },
+                FinalizedConsensus::Babe { genesis_config, .. },
+            ) => {
+                *genesis_config
+                    .epoch0_information()
+                    .authorities
+                    .nth(authority_index)?
+                    .public_key
+            }
+            _ => return None,
+        };
+
+        Some((pre_digest.slot_number(), public_key))
+    }
+
+    /// Returns a proof of equivocation if the author of the given block has already produced a
+    /// different block during the same slot.
+    ///
+    /// `consensus` must be the consensus state of the block.
+    fn babe_check_equivocation(
+        &self,
+        header: &header::HeaderRef,
+        consensus: &BlockConsensus,
+    ) -> Option<babe::EquivocationProof> {
+        let (slot_number, author) = self.babe_block_author(header, consensus)?;
+        let previous = self.babe_seen_slots.get(&(slot_number, author))?;
+        babe::check_equivocation(previous.into(), header.clone(), &author)
+    }
+
+    /// Records the given block in [`NonFinalizedTree::babe_seen_slots`], and removes the entries
+    /// that are too old.
+    fn babe_record_block(&mut self, header: &header::Header, consensus: &BlockConsensus) {
+        let (slot_number, author) = match self.babe_block_author(&header.into(), consensus) {
+            Some(a) => a,
+            None => return,
+        };
+
+        self.babe_seen_slots
+            .entry((slot_number, author))
+            .or_insert_with(|| header.clone());
+
+        let highest_slot = self
+            .babe_seen_slots
+            .keys()
+            .next_back()
+            .map_or(0, |(slot, _)| *slot);
+        let oldest_slot = highest_slot.saturating_sub(BABE_SEEN_SLOTS_WINDOW);
+        if self
+            .babe_seen_slots
+            .keys()
+            .next()
+            .map_or(false, |(slot, _)| *slot < oldest_slot)
+        {
+            self.babe_seen_slots = self.babe_seen_slots.split_off(&(oldest_slot, [0; 32]));
+        }
+    }
+
+    /// Returns the GrandPa state right after the block with the given index, or after the
+    /// finalized block if `None`.
+    fn grandpa_state(&self, tree_index: Option<fork_tree::NodeIndex>) -> &grandpa::GrandpaState {
+        if let Some(tree_index) = tree_index {
+            &self.blocks.get(tree_index).unwrap().grandpa
+        } else {
+            &self.finalized_grandpa
         }
     }
+
+    /// Returns the GrandPa state right after a block whose parent is `parent_tree_index`, and
+    /// the change of authorities that this block triggers.
+    fn child_grandpa_state(
+        &self,
+        parent_tree_index: Option<fork_tree::NodeIndex>,
+        header: &header::HeaderRef,
+    ) -> Result<(grandpa::GrandpaState, Option<grandpa::TriggeredChange>), GrandpaChangeError> {
+        self.grandpa_state(parent_tree_index).apply_block(
+            header.number,
+            header.digest.logs().filter_map(|d| match d {
+                header::DigestItemRef::GrandpaConsensus(gp) => Some(gp),
+                _ => None,
+            }),
+        )
+    }
+
+    /// Determines whether a block, child of `parent_tree_index` and with the given hash, number
+    /// and weight, would become the new best block once inserted. If so, returns the
+    /// corresponding change.
+    fn new_block_best_change(
+        &self,
+        parent_tree_index: Option<fork_tree::NodeIndex>,
+        hash: [u8; 32],
+        number: u64,
+        babe_primary_slots_weight: u64,
+    ) -> Option<BestBlockChange> {
+        if let Some(current_best) = self.current_best {
+            let current_best = self.blocks.get(current_best).unwrap();
+            if !self.fork_choice.is_better(
+                (babe_primary_slots_weight, number),
+                (
+                    current_best.babe_primary_slots_weight,
+                    current_best.header.number,
+                ),
+            ) {
+                return None;
+            }
+        }
+
+        let mut change = best_block_change(
+            &self.blocks,
+            (
+                self.finalized_block_header.number,
+                self.finalized_block_hash,
+            ),
+            self.current_best,
+            parent_tree_index,
+        );
+        change.new_best_hash = hash;
+        change.new_best_number = number;
+        change.enacted.push(hash);
+        Some(change)
+    }
 }
 
 impl<T> fmt::Debug for NonFinalizedTree<T>
@@ -858,6 +1762,46 @@ where
     }
 }
 
+/// Information about the BABE epochs that the children of the best block can belong to.
+///
+/// Obtained with [`NonFinalizedTree::babe_best_block_epochs`]. Doesn't borrow the
+/// [`NonFinalizedTree`] and can be sent to other threads.
+#[derive(Debug, Clone)]
+pub struct BabeBestBlockEpochs {
+    /// Number of slots in each epoch.
+    slots_per_epoch: u64,
+    /// Slot number of block #1, if known.
+    block1_slot_number: Option<u64>,
+    /// List of known epochs and their number.
+    epochs: Vec<(u64, Arc<(header::BabeNextEpoch, header::BabeNextConfig)>)>,
+}
+
+impl BabeBestBlockEpochs {
+    /// Returns the number and the information of the epoch the given block belongs to, or
+    /// `None` if unknown.
+    ///
+    /// The value returned is only correct if the block is a descendant of the best block at
+    /// the time when [`NonFinalizedTree::babe_best_block_epochs`] was called.
+    pub fn block_epoch(
+        &self,
+        header: &header::HeaderRef,
+    ) -> Option<(u64, header::BabeNextEpochRef)> {
+        let slot_number = header.digest.babe_pre_runtime()?.slot_number();
+        let epoch_number = if header.number == 1 {
+            0
+        } else {
+            slot_number
+                .checked_sub(self.block1_slot_number?)?
+                .checked_div(self.slots_per_epoch)?
+        };
+
+        self.epochs
+            .iter()
+            .find(|(n, _)| *n == epoch_number)
+            .map(|(n, epoch)| (*n, From::from(&epoch.0)))
+    }
+}
+
 /// Block verification, either just finished or still in progress.
 ///
 /// Holds ownership of both the block to verify and the [`NonFinalizedTree`].
@@ -870,6 +1814,9 @@ pub enum BodyVerifyStep1<T, I> {
     /// Error while decoding the header.
     InvalidHeader(NonFinalizedTree<T>, header::Error),
 
+    /// The GrandPa log items of the header are incompatible with the state of the chain.
+    BadGrandpaChange(NonFinalizedTree<T>, GrandpaChangeError),
+
     /// The parent of the block isn't known.
     BadParent {
         chain: NonFinalizedTree<T>,
@@ -877,6 +1824,18 @@ pub enum BodyVerifyStep1<T, I> {
         parent_hash: [u8; 32],
     },
 
+    /// The hash of the block is in the list of bad blocks.
+    /// See [`chain_information::ChainInformationConfig::bad_blocks`].
+    BadBlock(NonFinalizedTree<T>),
+
+    /// The number of the block is in the list of fork blocks, but its hash doesn't match.
+    /// See [`chain_information::ChainInformationConfig::fork_blocks`].
+    ForkBlockMismatch {
+        chain: NonFinalizedTree<T>,
+        /// Hash that the block was expected to have.
+        expected_hash: [u8; 32],
+    },
+
     /// Verification is pending. In order to continue, a [`executor::WasmVmPrototype`] of the
     /// runtime of the parent block must be provided.
     ParentRuntimeRequired(BodyVerifyRuntimeRequired<T, I>),
@@ -891,7 +1850,10 @@ pub struct BodyVerifyRuntimeRequired<T, I> {
     header: header::Header,
     parent_tree_index: Option<fork_tree::NodeIndex>,
     body: I,
-    block1_slot_number: Option<u64>,
+    now_from_unix_epoch: Duration,
+    grandpa: grandpa::GrandpaState,
+    grandpa_triggered_change: Option<grandpa::TriggeredChange>,
+    babe_pre_verified_seal: Option<verify::babe::PreVerifiedSeal>,
 }
 
 impl<T, I, E> BodyVerifyRuntimeRequired<T, I>
@@ -932,6 +1894,14 @@ where
         u64::try_from(self.chain.blocks.node_to_root_path(parent_index).count()).unwrap()
     }
 
+    /// Provides the outcome of calling [`verify::babe::pre_verify_seal`] on the block being
+    /// verified, in which case its signature and VRF proof aren't verified again.
+    ///
+    /// See [`verify::babe::VerifyConfig::pre_verified_seal`].
+    pub fn set_babe_pre_verified_seal(&mut self, pre_verified_seal: verify::babe::PreVerifiedSeal) {
+        self.babe_pre_verified_seal = Some(pre_verified_seal);
+    }
+
     /// Resume the verification process by passing the requested information.
     ///
     /// `parent_runtime` must be a Wasm virtual machine containing the runtime code of the parent
@@ -954,16 +1924,18 @@ where
             &self.chain.finalized_block_header
         };
 
+        let mut consensus = self.chain.verify_config_consensus(self.parent_tree_index);
+        if let verify::header_body::ConfigConsensus::Babe {
+            pre_verified_seal, ..
+        } = &mut consensus
+        {
+            *pre_verified_seal = self.babe_pre_verified_seal.as_ref();
+        }
+
         let process = verify::header_body::verify(verify::header_body::Config {
             parent_runtime,
-            babe_genesis_configuration: &self.chain.babe_genesis_config,
-            block1_slot_number: self.block1_slot_number,
-            now_from_unix_epoch: {
-                // TODO: is it reasonable to use the stdlib here?
-                //std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap()
-                // TODO: this is commented out because of Wasm support
-                core::time::Duration::new(0, 0)
-            },
+            consensus,
+            now_from_unix_epoch: self.now_from_unix_epoch,
             block_header: (&self.header).into(),
             parent_block_header: parent_block_header.into(),
             block_body: self.body,
@@ -976,7 +1948,8 @@ where
                 chain: self.chain,
                 parent_tree_index: self.parent_tree_index,
                 header: self.header,
-                block1_slot_number: self.block1_slot_number,
+                grandpa: self.grandpa,
+                grandpa_triggered_change: self.grandpa_triggered_change,
             },
         )
     }
@@ -992,7 +1965,7 @@ where
 /// Holds ownership of both the block to verify and the [`NonFinalizedTree`].
 #[must_use]
 pub enum BodyVerifyStep2<T> {
-    /// Verification is over.
+    /// Verification is over and successful.
     ///
     /// Use the provided [`BodyInsert`] to insert the block in the chain if desired.
     Finished {
@@ -1006,8 +1979,15 @@ pub enum BodyVerifyStep2<T> {
         /// Pass this value to [`BodyVerifyRuntimeRequired::resume`] when verifying a children of
         /// this block in order to considerably speed up the verification.
         top_trie_root_calculation_cache: calculate_root::CalculationCache,
-        /// Outcome of the verification.
-        result: Result<BodyInsert<T>, HeaderVerifyError>, // TODO: BodyVerifyError, or rename the error to be common
+        /// Object to use in order to insert the block in the chain.
+        insert: BodyInsert<T>,
+    },
+    /// Verification is over and the block is invalid. It should be thrown away.
+    Error {
+        /// Chain yielded back, unmodified.
+        chain: NonFinalizedTree<T>,
+        /// Problem that happened.
+        error: verify::header_body::Error,
     },
     /// Loading a storage value is required in order to continue.
     StorageGet(StorageGet<T>),
@@ -1021,7 +2001,8 @@ struct BodyVerifyShared<T> {
     chain: NonFinalizedTree<T>,
     parent_tree_index: Option<fork_tree::NodeIndex>,
     header: header::Header,
-    block1_slot_number: Option<u64>,
+    grandpa: grandpa::GrandpaState,
+    grandpa_triggered_change: Option<grandpa::TriggeredChange>,
 }
 
 impl<T> BodyVerifyStep2<T> {
@@ -1032,138 +2013,56 @@ impl<T> BodyVerifyStep2<T> {
                     // TODO: lots of code in common with header verification
 
                     // Block verification is successful!
-                    let is_new_best = if let Some(current_best) = chain.chain.current_best {
-                        is_better_block(
-                            &chain.chain.blocks,
-                            current_best,
-                            chain.parent_tree_index,
-                            (&chain.header).into(),
-                        )
-                    } else {
-                        true
-                    };
-
-                    let babe_block1_slot_number = chain.block1_slot_number.unwrap_or_else(|| {
-                        debug_assert_eq!(chain.header.number, 1);
-                        success.slot_number
-                    });
-
-                    let babe_current_epoch = if success.babe_epoch_transition_target.is_some() {
-                        if let Some(parent_tree_index) = chain.parent_tree_index {
-                            Some(
-                                chain
-                                    .chain
-                                    .blocks
-                                    .get(parent_tree_index)
-                                    .unwrap()
-                                    .babe_next_epoch
-                                    .clone(),
-                            )
-                        } else {
-                            chain.chain.babe_finalized_next_epoch_transition.clone()
-                        }
-                    } else if let Some(parent_tree_index) = chain.parent_tree_index {
-                        chain
-                            .chain
-                            .blocks
-                            .get(parent_tree_index)
-                            .unwrap()
-                            .babe_current_epoch
-                            .clone()
-                    } else {
-                        chain.chain.babe_finalized_block_epoch_information.clone()
-                    };
-
-                    let babe_next_epoch = match (
-                        chain.header.digest.babe_epoch_information(),
+                    let hash = chain.header.hash();
+                    let babe_primary_slots_weight =
+                        chain.chain.block_weight(chain.parent_tree_index)
+                            + babe_primary_slot_weight(&(&chain.header).into());
+                    let best_block_change = chain.chain.new_block_best_change(
                         chain.parent_tree_index,
-                        &chain.chain.babe_finalized_next_epoch_transition,
-                    ) {
-                        (Some((ref new_epoch, Some(new_config))), _, _) => {
-                            Arc::new((new_epoch.clone().into(), new_config))
-                        }
-                        (Some((ref new_epoch, None)), Some(parent_tree_index), _) => {
-                            let new_config = chain
-                                .chain
-                                .blocks
-                                .get(parent_tree_index)
-                                .unwrap()
-                                .babe_next_epoch
-                                .1
-                                .clone();
-                            Arc::new((new_epoch.clone().into(), new_config))
-                        }
-                        (Some((ref new_epoch, None)), None, Some(finalized_next)) => {
-                            Arc::new((new_epoch.clone().into(), finalized_next.1))
-                        }
-                        (Some((ref new_epoch, None)), None, None) => Arc::new((
-                            new_epoch.clone().into(),
-                            chain.chain.babe_genesis_config.epoch0_configuration(),
-                        )),
-                        (None, Some(parent_tree_index), _) => chain
-                            .chain
-                            .blocks
-                            .get(parent_tree_index)
-                            .unwrap()
-                            .babe_next_epoch
-                            .clone(),
-                        (None, None, _) => {
-                            // Block 1 always contains a Babe epoch transition. Consequently, this block
-                            // can't be reached for block 1.
-                            // `babe_finalized_next_epoch_transition` is `None` only if the finalized
-                            // block is 0.
-                            // `babe_finalized_next_epoch_transition` is therefore always `Some`
-                            // Q.E.D.
-                            debug_assert_ne!(chain.header.number, 1);
-                            chain
-                                .chain
-                                .babe_finalized_next_epoch_transition
-                                .clone()
-                                .unwrap()
-                        }
-                    };
+                        hash,
+                        chain.header.number,
+                        babe_primary_slots_weight,
+                    );
 
-                    let hash = chain.header.hash();
+                    let consensus = chain.chain.child_block_consensus(
+                        chain.parent_tree_index,
+                        &(&chain.header).into(),
+                        success.consensus,
+                    );
+                    let babe_equivocation = chain
+                        .chain
+                        .babe_check_equivocation(&(&chain.header).into(), &consensus);
 
                     return BodyVerifyStep2::Finished {
                         parent_runtime: success.parent_runtime,
                         storage_top_trie_changes: success.storage_top_trie_changes,
                         offchain_storage_changes: success.offchain_storage_changes,
                         top_trie_root_calculation_cache: success.top_trie_root_calculation_cache,
-                        result: Ok(BodyInsert {
+                        insert: BodyInsert {
                             chain: chain.chain,
                             parent_tree_index: chain.parent_tree_index,
-                            is_new_best,
+                            best_block_change,
                             header: chain.header,
                             hash,
-                            babe_block1_slot_number,
-                            babe_current_epoch,
-                            babe_next_epoch,
-                        }),
+                            consensus,
+                            babe_primary_slots_weight,
+                            babe_equivocation,
+                            grandpa: chain.grandpa,
+                            grandpa_triggered_change: chain.grandpa_triggered_change,
+                        },
                     };
                 }
-                verify::header_body::Verify::Finished(Err(err)) => todo!("verify err: {:?}", err),
-                verify::header_body::Verify::BabeEpochInformation(epoch_info_rq) => {
-                    let epoch_info = if let Some(parent_tree_index) = chain.parent_tree_index {
-                        let parent = chain.chain.blocks.get(parent_tree_index).unwrap();
-                        if epoch_info_rq.same_epoch_as_parent() {
-                            parent.babe_current_epoch.as_ref().unwrap()
-                        } else {
-                            &parent.babe_next_epoch
-                        }
-                    } else if epoch_info_rq.same_epoch_as_parent() {
-                        chain
-                            .chain
-                            .babe_finalized_block_epoch_information
-                            .as_ref()
-                            .unwrap()
-                    } else {
-                        chain
-                            .chain
-                            .babe_finalized_next_epoch_transition
-                            .as_ref()
-                            .unwrap()
+                verify::header_body::Verify::Finished(Err(error)) => {
+                    return BodyVerifyStep2::Error {
+                        chain: chain.chain,
+                        error,
                     };
+                }
+                verify::header_body::Verify::BabeEpochInformation(epoch_info_rq) => {
+                    let epoch_info = chain.chain.babe_epoch_information(
+                        chain.parent_tree_index,
+                        epoch_info_rq.same_epoch_as_parent(),
+                    );
 
                     inner = epoch_info_rq.inject_epoch((From::from(&epoch_info.0), epoch_info.1));
                 }
@@ -1383,35 +2282,68 @@ pub enum HeaderVerifySuccess<'c, T> {
 #[must_use]
 pub struct HeaderInsert<'c, T> {
     chain: &'c mut NonFinalizedTree<T>,
-    /// Copy of the value in [`HeaderVerifySuccess::is_new_best`].
-    is_new_best: bool,
+    /// `Some` if and only if [`HeaderVerifySuccess::Insert::is_new_best`] is `true`.
+    best_block_change: Option<BestBlockChange>,
     /// Index of the parent in [`NonFinalizedTree::blocks`].
     parent_tree_index: Option<fork_tree::NodeIndex>,
     header: header::Header,
     hash: [u8; 32],
-    babe_block1_slot_number: u64,
-    babe_current_epoch: Option<Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
-    babe_next_epoch: Arc<(header::BabeNextEpoch, header::BabeNextConfig)>,
+    consensus: BlockConsensus,
+    babe_primary_slots_weight: u64,
+    /// Proof that the author of the block has already produced another block in the same slot.
+    babe_equivocation: Option<babe::EquivocationProof>,
+    grandpa: grandpa::GrandpaState,
+    grandpa_triggered_change: Option<grandpa::TriggeredChange>,
 }
 
 impl<'c, T> HeaderInsert<'c, T> {
+    /// Returns the change of best block that inserting the block would cause, or `None` if the
+    /// block doesn't become the new best block.
+    pub fn best_block_change(&self) -> Option<&BestBlockChange> {
+        self.best_block_change.as_ref()
+    }
+
+    /// Returns a proof of equivocation if the author of the block has already produced a
+    /// different block during the same slot. The block is nonetheless valid.
+    ///
+    /// The proof can be used in order to report the offender to the runtime. See
+    /// [`babe::EquivocationProof`].
+    pub fn babe_equivocation(&self) -> Option<&babe::EquivocationProof> {
+        self.babe_equivocation.as_ref()
+    }
+
     /// Inserts the block with the given user data.
     pub fn insert(self, user_data: T) {
+        let header_number = self.header.number;
+        let header_parent_hash = self.header.parent_hash;
+        self.chain.babe_record_block(&self.header, &self.consensus);
         let new_node_index = self.chain.blocks.insert(
             self.parent_tree_index,
             Block {
                 header: self.header,
                 hash: self.hash,
-                babe_block1_slot_number: self.babe_block1_slot_number,
-                babe_current_epoch: self.babe_current_epoch,
-                babe_next_epoch: self.babe_next_epoch,
+                consensus: self.consensus,
+                babe_primary_slots_weight: self.babe_primary_slots_weight,
+                grandpa: self.grandpa,
+                grandpa_triggered_change: self.grandpa_triggered_change,
                 user_data,
             },
         );
 
-        if self.is_new_best {
+        if self.best_block_change.is_some() {
             self.chain.current_best = Some(new_node_index);
         }
+
+        if let Some(events) = &mut self.chain.events {
+            events.push_back(ChainEvent::BlockImported {
+                hash: self.hash,
+                number: header_number,
+                parent_hash: header_parent_hash,
+            });
+            if let Some(best_block_change) = self.best_block_change {
+                events.push_back(ChainEvent::BestBlockChanged(best_block_change));
+            }
+        }
     }
 
     /// Destroys the object without inserting the block in the chain. Returns the block header.
@@ -1439,6 +2371,19 @@ pub enum HeaderVerifyError {
     },
     /// The block verification has failed. The block is invalid and should be thrown away.
     VerificationFailed(verify::header_only::Error),
+    /// The GrandPa log items of the header are incompatible with the state of the chain.
+    GrandpaChange(GrandpaChangeError),
+    /// The hash of the block is in the list of bad blocks.
+    /// See [`chain_information::ChainInformationConfig::bad_blocks`].
+    #[display(fmt = "The block is in the list of bad blocks.")]
+    BadBlock,
+    /// The number of the block is in the list of fork blocks, but its hash doesn't match.
+    /// See [`chain_information::ChainInformationConfig::fork_blocks`].
+    #[display(fmt = "The block doesn't match the expected fork block.")]
+    ForkBlockMismatch {
+        /// Hash that the block was expected to have.
+        expected_hash: [u8; 32],
+    },
 }
 
 /// Returned by [`NonFinalizedTree::verify_justification`] on success.
@@ -1449,6 +2394,7 @@ pub enum HeaderVerifyError {
 pub struct JustificationApply<'c, T> {
     chain: &'c mut NonFinalizedTree<T>,
     to_finalize: fork_tree::NodeIndex,
+    equivocations: Vec<justification::verify::Equivocation>,
 }
 
 impl<'c, T> JustificationApply<'c, T> {
@@ -1474,6 +2420,12 @@ impl<'c, T> JustificationApply<'c, T> {
     pub fn is_current_best_block(&self) -> bool {
         Some(self.to_finalize) == self.chain.current_best
     }
+
+    /// Returns the list of authorities that have signed pre-commits for more than one block in
+    /// the justification. The justification is nonetheless valid.
+    pub fn equivocations(&self) -> &[justification::verify::Equivocation] {
+        &self.equivocations
+    }
 }
 
 impl<'c, T> fmt::Debug for JustificationApply<'c, T> {
@@ -1511,16 +2463,63 @@ pub enum JustificationVerifyError {
         /// Hash of the block to finalize first.
         block_to_finalize_hash: [u8; 32],
     },
+    /// The GrandPa authorities aren't voting for the block targeted by the justification.
+    #[display(fmt = "The GrandPa authorities aren't voting for the justified block")]
+    AuthoritiesPaused,
     /// The justification verification has failed. The justification is invalid and should be
     /// thrown away.
     VerificationFailed(justification::verify::Error),
 }
 
+/// Error that can happen when verifying a finality proof.
+#[derive(Debug, derive_more::Display)]
+pub enum FinalityProofVerifyError {
+    /// Error while decoding the proof.
+    InvalidProof(proof::Error),
+    /// The proof doesn't contain any fragment.
+    #[display(fmt = "The finality proof is empty.")]
+    EmptyProof,
+    /// One of the headers of the proof is invalid.
+    InvalidHeader(HeaderVerifyError),
+    /// One of the justifications of the proof is invalid.
+    InvalidJustification(JustificationVerifyError),
+    /// A justification doesn't target the block indicated in its fragment.
+    #[display(fmt = "A justification doesn't target the block indicated in its fragment.")]
+    BlockHashMismatch,
+}
+
 /// Iterator producing the newly-finalized blocks removed from the state when the finalized block
 /// is updated.
 pub struct SetFinalizedBlockIter<'a, T> {
     iter: fork_tree::PruneAncestorsIter<'a, Block<T>>,
-    current_best: &'a mut Option<fork_tree::NodeIndex>,
+    best_block_change: Option<BestBlockChange>,
+    /// `None` if [`Config::generate_events`] was `false`.
+    events: Option<SetFinalizedEvents<'a>>,
+}
+
+/// Events-related state of a [`SetFinalizedBlockIter`].
+struct SetFinalizedEvents<'a> {
+    /// Queue where to push the events once the pruning is over.
+    events: &'a mut VecDeque<ChainEvent>,
+    /// Hash of the new finalized block.
+    finalized_hash: [u8; 32],
+    /// Number of the new finalized block.
+    finalized_number: u64,
+    /// Hashes of the blocks finalized so far, in decreasing block number.
+    finalized: Vec<[u8; 32]>,
+    /// Hashes of the blocks pruned so far.
+    pruned: Vec<[u8; 32]>,
+}
+
+impl<'a, T> SetFinalizedBlockIter<'a, T> {
+    /// Returns the change of best block caused by the finalization, or `None` if the best block
+    /// is the same as before.
+    ///
+    /// The best block changes if the previous best block wasn't a descendant of the newly
+    /// finalized block.
+    pub fn best_block_change(&self) -> Option<&BestBlockChange> {
+        self.best_block_change.as_ref()
+    }
 }
 
 impl<'a, T> Iterator for SetFinalizedBlockIter<'a, T> {
@@ -1529,8 +2528,12 @@ impl<'a, T> Iterator for SetFinalizedBlockIter<'a, T> {
     fn next(&mut self) -> Option<Self::Item> {
         loop {
             let pruned = self.iter.next()?;
-            if Some(pruned.index) == *self.current_best {
-                *self.current_best = None;
+            if let Some(events) = &mut self.events {
+                if pruned.is_prune_target_ancestor {
+                    events.finalized.push(pruned.user_data.hash);
+                } else {
+                    events.pruned.push(pruned.user_data.hash);
+                }
             }
             if !pruned.is_prune_target_ancestor {
                 continue;
@@ -1549,10 +2552,34 @@ impl<'a, T> Drop for SetFinalizedBlockIter<'a, T> {
         // Make sure the iteration goes to the end.
         while let Some(_) = self.next() {}
 
-        // TODO: update current_best with the new best block
+        if let Some(events) = &mut self.events {
+            // `finalized` has been filled from child to parent.
+            let mut finalized = mem::replace(&mut events.finalized, Vec::new());
+            finalized.reverse();
+            debug_assert_eq!(finalized.last(), Some(&events.finalized_hash));
+            events.events.push_back(ChainEvent::Finalized {
+                hash: events.finalized_hash,
+                number: events.finalized_number,
+                finalized,
+            });
+
+            if !events.pruned.is_empty() {
+                let pruned = mem::replace(&mut events.pruned, Vec::new());
+                events.events.push_back(ChainEvent::ForksPruned { pruned });
+            }
+        }
     }
 }
 
+/// Error potentially returned by [`NonFinalizedTree::set_node_store`].
+#[derive(Debug, derive_more::Display)]
+pub enum SetNodeStoreError {
+    /// Latest finalized block of the store isn't the latest finalized block of the tree. Contains
+    /// the store that has been passed.
+    #[display(fmt = "The finalized block of the store doesn't match the one of the tree.")]
+    FinalizedBlockMismatch(node_store::NodeStore),
+}
+
 /// Error that can happen when setting the finalized block.
 #[derive(Debug, derive_more::Display)]
 pub enum SetFinalizedError {
@@ -1564,14 +2591,18 @@ pub enum SetFinalizedError {
 #[must_use]
 pub struct BodyInsert<T> {
     chain: NonFinalizedTree<T>,
-    is_new_best: bool,
+    /// `Some` if the block is going to become the new best block.
+    best_block_change: Option<BestBlockChange>,
     /// Index of the parent in [`NonFinalizedTree::blocks`].
     parent_tree_index: Option<fork_tree::NodeIndex>,
     header: header::Header,
     hash: [u8; 32],
-    babe_block1_slot_number: u64,
-    babe_current_epoch: Option<Arc<(header::BabeNextEpoch, header::BabeNextConfig)>>,
-    babe_next_epoch: Arc<(header::BabeNextEpoch, header::BabeNextConfig)>,
+    consensus: BlockConsensus,
+    babe_primary_slots_weight: u64,
+    /// Proof that the author of the block has already produced another block in the same slot.
+    babe_equivocation: Option<babe::EquivocationProof>,
+    grandpa: grandpa::GrandpaState,
+    grandpa_triggered_change: Option<grandpa::TriggeredChange>,
 }
 
 impl<T> BodyInsert<T> {
@@ -1580,24 +2611,54 @@ impl<T> BodyInsert<T> {
         (&self.header).into()
     }
 
+    /// Returns the change of best block that inserting the block would cause, or `None` if the
+    /// block doesn't become the new best block.
+    pub fn best_block_change(&self) -> Option<&BestBlockChange> {
+        self.best_block_change.as_ref()
+    }
+
+    /// Returns a proof of equivocation if the author of the block has already produced a
+    /// different block during the same slot. The block is nonetheless valid.
+    ///
+    /// The proof can be used in order to report the offender to the runtime. See
+    /// [`babe::EquivocationProof`].
+    pub fn babe_equivocation(&self) -> Option<&babe::EquivocationProof> {
+        self.babe_equivocation.as_ref()
+    }
+
     /// Inserts the block with the given user data.
     pub fn insert(mut self, user_data: T) -> NonFinalizedTree<T> {
+        let header_number = self.header.number;
+        let header_parent_hash = self.header.parent_hash;
+        self.chain.babe_record_block(&self.header, &self.consensus);
         let new_node_index = self.chain.blocks.insert(
             self.parent_tree_index,
             Block {
                 header: self.header,
                 hash: self.hash,
-                babe_block1_slot_number: self.babe_block1_slot_number,
-                babe_current_epoch: self.babe_current_epoch,
-                babe_next_epoch: self.babe_next_epoch,
+                consensus: self.consensus,
+                babe_primary_slots_weight: self.babe_primary_slots_weight,
+                grandpa: self.grandpa,
+                grandpa_triggered_change: self.grandpa_triggered_change,
                 user_data,
             },
         );
 
-        if self.is_new_best {
+        if self.best_block_change.is_some() {
             self.chain.current_best = Some(new_node_index);
         }
 
+        if let Some(events) = &mut self.chain.events {
+            events.push_back(ChainEvent::BlockImported {
+                hash: self.hash,
+                number: header_number,
+                parent_hash: header_parent_hash,
+            });
+            if let Some(best_block_change) = self.best_block_change {
+                events.push_back(ChainEvent::BestBlockChanged(best_block_change));
+            }
+        }
+
         self.chain
     }
 
@@ -1650,87 +2711,67 @@ impl<'a, T> BlockAccess<'a, T> {
     }
 }
 
-/// Accepts as parameter a container of blocks and indices within this container.
-///
-/// Returns true if `maybe_new_best` on top of `maybe_new_best_parent` is a better block compared
-/// to `old_best`.
-fn is_better_block<T>(
-    blocks: &fork_tree::ForkTree<Block<T>>,
-    old_best: fork_tree::NodeIndex,
-    maybe_new_best_parent: Option<fork_tree::NodeIndex>,
-    maybe_new_best: header::HeaderRef,
-) -> bool {
-    debug_assert!(
-        maybe_new_best_parent.map_or(true, |p_idx| blocks.get(p_idx).unwrap().hash
-            == *maybe_new_best.parent_hash)
-    );
-
-    if maybe_new_best_parent.map_or(false, |p_idx| blocks.is_ancestor(old_best, p_idx)) {
-        // A descendant is always preferred to its ancestor.
-        true
+/// Returns `1` if the given header is a BABE primary slot claim, `0` otherwise.
+fn babe_primary_slot_weight(header: &header::HeaderRef) -> u64 {
+    // TODO: what if there's a mix of Babe and non-Babe blocks?
+    if header
+        .digest
+        .babe_pre_runtime()
+        .map_or(false, |pr| pr.is_primary())
+    {
+        1
     } else {
-        // In order to determine whether the new block is our new best:
-        //
-        // - Find the common ancestor between the current best and the new block's parent.
-        // - Count the number of Babe primary slot claims between the common ancestor and
-        //   the current best.
-        // - Count the number of Babe primary slot claims between the common ancestor and
-        //   the new block's parent. Add one if the new block has a Babe primary slot
-        //   claim.
-        // - If the number for the new block is strictly superior, then the new block is
-        //   out new best.
-        //
-        let (ascend, descend) = blocks.ascend_and_descend(old_best, maybe_new_best_parent.unwrap());
-
-        // TODO: what if there's a mix of Babe and non-Babe blocks here?
-
-        let curr_best_primary_slots: usize = ascend
-            .map(|i| {
-                if blocks
-                    .get(i)
-                    .unwrap()
-                    .header
-                    .digest
-                    .babe_pre_runtime()
-                    .map_or(false, |pr| pr.is_primary())
-                {
-                    1
-                } else {
-                    0
-                }
-            })
-            .sum();
-
-        let new_block_primary_slots = {
-            if maybe_new_best
-                .digest
-                .babe_pre_runtime()
-                .map_or(false, |pr| pr.is_primary())
-            {
-                1
-            } else {
-                0
-            }
-        };
-
-        let parent_primary_slots: usize = descend
-            .map(|i| {
-                if blocks
-                    .get(i)
-                    .unwrap()
-                    .header
-                    .digest
-                    .babe_pre_runtime()
-                    .map_or(false, |pr| pr.is_primary())
-                {
-                    1
-                } else {
-                    0
-                }
-            })
-            .sum();
+        0
+    }
+}
 
-        // Note the strictly superior. If there is an equality, we keep the current best.
-        parent_primary_slots + new_block_primary_slots > curr_best_primary_slots
+/// Builds the [`BestBlockChange`] corresponding to the best block going from `old_best` to
+/// `new_best`. A value of `None` designates the finalized block, whose number and hash are
+/// `finalized`.
+fn best_block_change<T>(
+    blocks: &fork_tree::ForkTree<Block<T>>,
+    (finalized_number, finalized_hash): (u64, [u8; 32]),
+    old_best: Option<fork_tree::NodeIndex>,
+    new_best: Option<fork_tree::NodeIndex>,
+) -> BestBlockChange {
+    let hash = |index: Option<fork_tree::NodeIndex>| {
+        index.map_or(finalized_hash, |i| blocks.get(i).unwrap().hash)
+    };
+
+    let (retracted, mut enacted) = match (old_best, new_best) {
+        (Some(old_best), Some(new_best)) => {
+            let (ascend, descend) = blocks.ascend_and_descend(old_best, new_best);
+            (
+                ascend.map(|i| hash(Some(i))).collect::<Vec<_>>(),
+                descend.map(|i| hash(Some(i))).collect::<Vec<_>>(),
+            )
+        }
+        (Some(old_best), None) => (
+            blocks
+                .node_to_root_path(old_best)
+                .map(|i| hash(Some(i)))
+                .collect(),
+            Vec::new(),
+        ),
+        (None, Some(new_best)) => (
+            Vec::new(),
+            blocks
+                .node_to_root_path(new_best)
+                .map(|i| hash(Some(i)))
+                .collect(),
+        ),
+        (None, None) => (Vec::new(), Vec::new()),
+    };
+
+    // `enacted` has been built from the new best block towards the common ancestor.
+    enacted.reverse();
+
+    BestBlockChange {
+        old_best_hash: hash(old_best),
+        new_best_hash: hash(new_best),
+        new_best_number: new_best
+            .map_or(finalized_number, |i| blocks.get(i).unwrap().header.number),
+        retracted,
+        enacted,
     }
 }
@@ -0,0 +1,406 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! GrandPa-related state of the chain, tracked for each block of the tree.
+//!
+//! The GrandPa consensus log items found in block headers modify the list of authorities that
+//! are allowed to finalize blocks:
+//!
+//! - `ScheduledChange` schedules a change of authorities that is triggered once the block at the
+//!   trigger height is *finalized*.
+//! - `ForcedChange` schedules a change of authorities that is triggered once the block at the
+//!   trigger height is *imported*. This is used to recover from situations where the current
+//!   authorities are unable to finalize blocks.
+//! - `Pause` and `Resume` respectively make the authorities stop and resume voting.
+//! - `OnDisabled` notifies that one of the authorities has been disabled.
+//!
+//! Similar to Substrate, only one scheduled change and one forced change can be pending at any
+//! given time. A forced change can be scheduled while a scheduled change is pending, in which
+//! case the scheduled change is discarded when the forced change is triggered, as the new
+//! authorities set starts without any pending change.
+//!
+//! Because forced changes and resumes are triggered when blocks are imported, the GrandPa state
+//! can differ between forks. A [`GrandpaState`] is therefore stored for each block, and
+//! corresponds to the state of the chain right after this block, assuming that this block gets
+//! finalized.
+
+use crate::{chain::chain_information::GrandpaPauseState, header};
+
+use alloc::{sync::Arc, vec::Vec};
+use core::convert::TryFrom as _;
+
+/// GrandPa-related state of the chain right after a certain block.
+#[derive(Debug, Clone)]
+pub(super) struct GrandpaState {
+    /// Identifier of the authorities set that finalizes the children of the block.
+    pub authorities_set_id: u64,
+    /// List of authorities that finalize the children of the block.
+    pub triggered_authorities: Arc<Vec<header::GrandpaAuthority>>,
+    /// Change of authorities scheduled but not triggered yet. Contains the height of the block
+    /// whose finalization triggers the change. Always strictly superior to the height of the
+    /// block.
+    pub scheduled_change: Option<(u64, Arc<Vec<header::GrandpaAuthority>>)>,
+    /// Forced change of authorities scheduled but not triggered yet. Contains the height of the
+    /// block whose import triggers the change. Always strictly superior to the height of the
+    /// block.
+    pub forced_change: Option<(u64, Arc<Vec<header::GrandpaAuthority>>)>,
+    /// Whether the authorities are voting.
+    pub pause_state: GrandpaPauseState,
+}
+
+/// Change of authorities that has been triggered by a block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum TriggeredChange {
+    /// A scheduled change has been triggered. The block that triggers the change must be
+    /// finalized before any of its descendants.
+    Scheduled,
+    /// A forced change has been triggered.
+    Forced,
+}
+
+impl GrandpaState {
+    /// Returns the state of the chain right after the child of the block this state corresponds
+    /// to, given the number and GrandPa log items of the header of this child.
+    ///
+    /// Also returns the change of authorities that the child triggers, if any.
+    pub fn apply_block<'a>(
+        &self,
+        block_number: u64,
+        logs: impl Iterator<Item = header::GrandpaConsensusLogRef<'a>>,
+    ) -> Result<(GrandpaState, Option<TriggeredChange>), GrandpaChangeError> {
+        let mut new_state = self.clone();
+
+        // Only the first scheduled change and the first forced change of each block are taken
+        // into account, and a forced change takes precedence over a scheduled change.
+        let mut scheduled_change = None;
+        let mut forced_change = None;
+
+        for log in logs {
+            match log {
+                header::GrandpaConsensusLogRef::ScheduledChange(change) => {
+                    if scheduled_change.is_none() {
+                        scheduled_change = Some(change);
+                    }
+                }
+                header::GrandpaConsensusLogRef::ForcedChange { change, .. } => {
+                    // The `reset_block_height` is only used by full nodes in order to determine
+                    // which unfinalized blocks to revert, and is ignored here.
+                    if forced_change.is_none() {
+                        forced_change = Some(change);
+                    }
+                }
+                header::GrandpaConsensusLogRef::Pause(delay) => {
+                    if new_state.pause_state != GrandpaPauseState::Live {
+                        return Err(GrandpaChangeError::UnexpectedPause);
+                    }
+                    new_state.pause_state = GrandpaPauseState::PendingPause {
+                        trigger_block_height: block_number.saturating_add(u64::from(delay)),
+                    };
+                }
+                header::GrandpaConsensusLogRef::Resume(delay) => {
+                    if new_state.pause_state != GrandpaPauseState::Paused {
+                        return Err(GrandpaChangeError::UnexpectedResume);
+                    }
+                    new_state.pause_state = GrandpaPauseState::PendingResume {
+                        trigger_block_height: block_number.saturating_add(u64::from(delay)),
+                    };
+                }
+                header::GrandpaConsensusLogRef::OnDisabled(authority_index) => {
+                    // Disabled authorities are still part of the authorities set, and their
+                    // votes keep being counted when verifying justifications. This log item
+                    // only serves as an indication to the voters.
+                    if usize::try_from(authority_index)
+                        .map_or(true, |idx| idx >= new_state.triggered_authorities.len())
+                    {
+                        return Err(GrandpaChangeError::BadDisabledAuthorityIndex(
+                            authority_index,
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(change) = forced_change {
+            if new_state.forced_change.is_some() {
+                return Err(GrandpaChangeError::ChangeAlreadyPending);
+            }
+            new_state.forced_change = Some((
+                block_number.saturating_add(u64::from(change.delay)),
+                Arc::new(change.next_authorities.map(Into::into).collect()),
+            ));
+        } else if let Some(change) = scheduled_change {
+            if new_state.scheduled_change.is_some() || new_state.forced_change.is_some() {
+                return Err(GrandpaChangeError::ChangeAlreadyPending);
+            }
+            new_state.scheduled_change = Some((
+                block_number.saturating_add(u64::from(change.delay)),
+                Arc::new(change.next_authorities.map(Into::into).collect()),
+            ));
+        }
+
+        // Now apply the changes that this block triggers, including the changes with a delay of
+        // zero that it has just scheduled.
+        let mut triggered = None;
+
+        if let Some((trigger_height, _)) = new_state.forced_change {
+            if trigger_height <= block_number {
+                let (_, list) = new_state.forced_change.take().unwrap();
+                new_state.triggered_authorities = list;
+                new_state.authorities_set_id += 1;
+                new_state.scheduled_change = None;
+                triggered = Some(TriggeredChange::Forced);
+            }
+        }
+
+        if let Some((trigger_height, _)) = new_state.scheduled_change {
+            if trigger_height <= block_number {
+                let (_, list) = new_state.scheduled_change.take().unwrap();
+                new_state.triggered_authorities = list;
+                new_state.authorities_set_id += 1;
+                triggered = Some(TriggeredChange::Scheduled);
+            }
+        }
+
+        match new_state.pause_state {
+            GrandpaPauseState::PendingPause {
+                trigger_block_height,
+            } if trigger_block_height <= block_number => {
+                new_state.pause_state = GrandpaPauseState::Paused;
+            }
+            GrandpaPauseState::PendingResume {
+                trigger_block_height,
+            } if trigger_block_height <= block_number => {
+                new_state.pause_state = GrandpaPauseState::Live;
+            }
+            _ => {}
+        }
+
+        Ok((new_state, triggered))
+    }
+
+    /// Returns `true` if the authorities are voting for the child of the block, whose height is
+    /// passed as parameter.
+    pub fn authorities_voting(&self, child_block_number: u64) -> bool {
+        match self.pause_state {
+            GrandpaPauseState::Live | GrandpaPauseState::PendingPause { .. } => true,
+            GrandpaPauseState::Paused => false,
+            GrandpaPauseState::PendingResume {
+                trigger_block_height,
+            } => child_block_number >= trigger_block_height,
+        }
+    }
+}
+
+/// Error that can happen when applying the GrandPa log items of a block header.
+#[derive(Debug, derive_more::Display)]
+pub enum GrandpaChangeError {
+    /// The block schedules a change of authorities while a previous change is still pending.
+    #[display(fmt = "GrandPa authorities change scheduled while another change is pending")]
+    ChangeAlreadyPending,
+    /// The block pauses the authorities while they aren't voting or already about to pause.
+    #[display(fmt = "GrandPa authorities paused while not live")]
+    UnexpectedPause,
+    /// The block resumes the authorities while they aren't paused.
+    #[display(fmt = "GrandPa authorities resumed while not paused")]
+    UnexpectedResume,
+    /// The block disables an authority whose index is out of range.
+    #[display(fmt = "Disabled GrandPa authority index out of range: {}", _0)]
+    BadDisabledAuthorityIndex(u64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GrandpaChangeError, GrandpaState, TriggeredChange};
+    use crate::{chain::chain_information::GrandpaPauseState, header};
+
+    use alloc::sync::Arc;
+
+    fn authorities(seed: u8) -> Vec<header::GrandpaAuthority> {
+        vec![header::GrandpaAuthority {
+            public_key: [seed; 32],
+            weight: 1,
+        }]
+    }
+
+    fn initial_state() -> GrandpaState {
+        GrandpaState {
+            authorities_set_id: 0,
+            triggered_authorities: Arc::new(authorities(0)),
+            scheduled_change: None,
+            forced_change: None,
+            pause_state: GrandpaPauseState::Live,
+        }
+    }
+
+    fn apply(
+        state: &GrandpaState,
+        block_number: u64,
+        logs: &[header::GrandpaConsensusLog],
+    ) -> Result<(GrandpaState, Option<TriggeredChange>), GrandpaChangeError> {
+        state.apply_block(block_number, logs.iter().map(Into::into))
+    }
+
+    fn scheduled(seed: u8, delay: u32) -> header::GrandpaConsensusLog {
+        header::GrandpaConsensusLog::ScheduledChange(header::GrandpaScheduledChange {
+            next_authorities: authorities(seed),
+            delay,
+        })
+    }
+
+    fn forced(seed: u8, delay: u32) -> header::GrandpaConsensusLog {
+        header::GrandpaConsensusLog::ForcedChange {
+            reset_block_height: 0,
+            change: header::GrandpaScheduledChange {
+                next_authorities: authorities(seed),
+                delay,
+            },
+        }
+    }
+
+    #[test]
+    fn scheduled_change() {
+        let (state, triggered) = apply(&initial_state(), 1, &[scheduled(1, 2)]).unwrap();
+        assert_eq!(triggered, None);
+        assert_eq!(state.scheduled_change.as_ref().unwrap().0, 3);
+
+        let (state, triggered) = apply(&state, 2, &[]).unwrap();
+        assert_eq!(triggered, None);
+        assert_eq!(state.authorities_set_id, 0);
+
+        let (state, triggered) = apply(&state, 3, &[]).unwrap();
+        assert_eq!(triggered, Some(TriggeredChange::Scheduled));
+        assert_eq!(state.authorities_set_id, 1);
+        assert_eq!(*state.triggered_authorities, authorities(1));
+        assert!(state.scheduled_change.is_none());
+    }
+
+    #[test]
+    fn zero_delay_change() {
+        let (state, triggered) = apply(&initial_state(), 1, &[scheduled(1, 0)]).unwrap();
+        assert_eq!(triggered, Some(TriggeredChange::Scheduled));
+        assert_eq!(state.authorities_set_id, 1);
+
+        // A new change can immediately be scheduled.
+        assert!(apply(&state, 2, &[scheduled(2, 0)]).is_ok());
+    }
+
+    #[test]
+    fn conflicting_changes() {
+        let (state, _) = apply(&initial_state(), 1, &[scheduled(1, 5)]).unwrap();
+        assert!(matches!(
+            apply(&state, 2, &[scheduled(2, 5)]),
+            Err(GrandpaChangeError::ChangeAlreadyPending)
+        ));
+
+        let (state, _) = apply(&initial_state(), 1, &[forced(1, 5)]).unwrap();
+        assert!(matches!(
+            apply(&state, 2, &[forced(2, 5)]),
+            Err(GrandpaChangeError::ChangeAlreadyPending)
+        ));
+
+        // A change can't be scheduled by the block that triggers the previous one.
+        let (state, _) = apply(&initial_state(), 1, &[scheduled(1, 5)]).unwrap();
+        assert!(matches!(
+            apply(&state, 6, &[scheduled(2, 5)]),
+            Err(GrandpaChangeError::ChangeAlreadyPending)
+        ));
+    }
+
+    #[test]
+    fn forced_change_precedence() {
+        let (state, triggered) =
+            apply(&initial_state(), 1, &[scheduled(1, 0), forced(2, 0)]).unwrap();
+        assert_eq!(triggered, Some(TriggeredChange::Forced));
+        assert_eq!(state.authorities_set_id, 1);
+        assert_eq!(*state.triggered_authorities, authorities(2));
+
+        // Only the first change of each kind is taken into account.
+        let (state, _) = apply(&initial_state(), 1, &[scheduled(1, 3), scheduled(2, 0)]).unwrap();
+        assert_eq!(state.scheduled_change.as_ref().unwrap().0, 4);
+        assert_eq!(*state.scheduled_change.as_ref().unwrap().1, authorities(1));
+    }
+
+    #[test]
+    fn forced_change_while_scheduled_pending() {
+        let (state, _) = apply(&initial_state(), 1, &[scheduled(1, 5)]).unwrap();
+
+        // A forced change is accepted while a scheduled change is pending.
+        let (state, triggered) = apply(&state, 2, &[forced(2, 1)]).unwrap();
+        assert_eq!(triggered, None);
+        assert_eq!(state.scheduled_change.as_ref().unwrap().0, 6);
+        assert_eq!(state.forced_change.as_ref().unwrap().0, 3);
+
+        // Triggering the forced change discards the scheduled change.
+        let (state, triggered) = apply(&state, 3, &[]).unwrap();
+        assert_eq!(triggered, Some(TriggeredChange::Forced));
+        assert_eq!(state.authorities_set_id, 1);
+        assert_eq!(*state.triggered_authorities, authorities(2));
+        assert!(state.scheduled_change.is_none());
+
+        let (state, triggered) = apply(&state, 6, &[]).unwrap();
+        assert_eq!(triggered, None);
+        assert_eq!(state.authorities_set_id, 1);
+    }
+
+    #[test]
+    fn pause_resume() {
+        let (state, _) = apply(
+            &initial_state(),
+            1,
+            &[header::GrandpaConsensusLog::Pause(1)],
+        )
+        .unwrap();
+        assert!(state.authorities_voting(2));
+        assert!(matches!(
+            apply(&state, 2, &[header::GrandpaConsensusLog::Pause(1)]),
+            Err(GrandpaChangeError::UnexpectedPause)
+        ));
+        assert!(matches!(
+            apply(&state, 2, &[header::GrandpaConsensusLog::Resume(1)]),
+            Err(GrandpaChangeError::UnexpectedResume)
+        ));
+
+        let (state, _) = apply(&state, 2, &[]).unwrap();
+        assert_eq!(state.pause_state, GrandpaPauseState::Paused);
+        assert!(!state.authorities_voting(3));
+
+        let (state, _) = apply(&state, 3, &[header::GrandpaConsensusLog::Resume(2)]).unwrap();
+        assert!(!state.authorities_voting(4));
+        let (state, _) = apply(&state, 4, &[]).unwrap();
+        assert!(state.authorities_voting(5));
+        let (state, _) = apply(&state, 5, &[]).unwrap();
+        assert_eq!(state.pause_state, GrandpaPauseState::Live);
+    }
+
+    #[test]
+    fn on_disabled() {
+        assert!(apply(
+            &initial_state(),
+            1,
+            &[header::GrandpaConsensusLog::OnDisabled(0)]
+        )
+        .is_ok());
+        assert!(matches!(
+            apply(
+                &initial_state(),
+                1,
+                &[header::GrandpaConsensusLog::OnDisabled(1)]
+            ),
+            Err(GrandpaChangeError::BadDisabledAuthorityIndex(1))
+        ));
+    }
+}
@@ -0,0 +1,624 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Serialization of the non-finalized blocks of a [`NonFinalizedTree`].
+//!
+//! A snapshot is made of a version byte, followed with the SCALE encoding of a
+//! [`SnapshotV1`], followed with the 32 bytes BLAKE2 hash of everything that precedes it.
+//!
+//! The lists of BABE epochs and of authorities are typically shared between a lot of blocks.
+//! In order to keep the size of snapshots reasonable, these lists are stored only once in the
+//! snapshot, and each block refers to them by index.
+//!
+//! The state of the calculation of the randomness of upcoming BABE epochs isn't included in
+//! snapshots. Restored blocks are in the same situation as the finalized block of a newly-created
+//! [`NonFinalizedTree`], and the randomness of the epochs whose calculation is in progress will
+//! not be verified.
+
+use super::{grandpa, Block, BlockConsensus, Config, FinalizedConsensus, NonFinalizedTree};
+use crate::{
+    chain::{chain_information::GrandpaPauseState, fork_tree},
+    header,
+};
+
+use alloc::{sync::Arc, vec::Vec};
+use core::convert::TryFrom as _;
+use hashbrown::HashMap;
+use parity_scale_codec::{Decode, Encode};
+
+/// Version of the format produced by [`encode`].
+const SNAPSHOT_VERSION: u8 = 1;
+
+/// Serializes the non-finalized blocks of the given tree. See [`NonFinalizedTree::snapshot`].
+pub(super) fn encode<T>(tree: &NonFinalizedTree<T>) -> Vec<u8> {
+    let mut babe_epochs = Pool::default();
+    let mut aura_authorities_lists = Pool::default();
+    let mut grandpa_authorities_lists = Pool::default();
+
+    // Parents must always be found before their children, which is guaranteed by ordering the
+    // blocks by number.
+    let mut blocks = tree.blocks.iter().collect::<Vec<_>>();
+    blocks.sort_by_key(|block| block.header.number);
+
+    let blocks = blocks
+        .into_iter()
+        .map(|block| SerializedBlockV1 {
+            scale_encoded_header: encode_header(&block.header),
+            consensus: match &block.consensus {
+                BlockConsensus::Aura { authorities_list } => SerializedBlockConsensusV1::Aura {
+                    authorities_list: aura_authorities_lists.index(authorities_list),
+                },
+                BlockConsensus::Babe {
+                    block1_slot_number,
+                    current_epoch,
+                    next_epoch,
+                    disabled_authorities,
+                    randomness_accumulator: _,
+                } => SerializedBlockConsensusV1::Babe {
+                    block1_slot_number: *block1_slot_number,
+                    current_epoch: current_epoch.as_ref().map(|e| babe_epochs.index(e)),
+                    next_epoch: babe_epochs.index(next_epoch),
+                    disabled_authorities: disabled_authorities.clone(),
+                },
+            },
+            babe_primary_slots_weight: block.babe_primary_slots_weight,
+            grandpa: SerializedGrandpaStateV1 {
+                authorities_set_id: block.grandpa.authorities_set_id,
+                triggered_authorities: grandpa_authorities_lists
+                    .index(&block.grandpa.triggered_authorities),
+                scheduled_change: block
+                    .grandpa
+                    .scheduled_change
+                    .as_ref()
+                    .map(|(n, list)| (*n, grandpa_authorities_lists.index(list))),
+                forced_change: block
+                    .grandpa
+                    .forced_change
+                    .as_ref()
+                    .map(|(n, list)| (*n, grandpa_authorities_lists.index(list))),
+                pause_state: block.grandpa.pause_state.into(),
+            },
+            grandpa_triggered_change: block.grandpa_triggered_change.map(|change| match change {
+                grandpa::TriggeredChange::Scheduled => SerializedTriggeredChangeV1::Scheduled,
+                grandpa::TriggeredChange::Forced => SerializedTriggeredChangeV1::Forced,
+            }),
+        })
+        .collect();
+
+    let snapshot = SnapshotV1 {
+        finalized_block_hash: tree.finalized_block_hash,
+        babe_finalized_block_weight: tree.babe_finalized_block_weight,
+        babe_finalized_disabled_authorities: match &tree.finalized_consensus {
+            FinalizedConsensus::Aura { .. } => None,
+            FinalizedConsensus::Babe {
+                disabled_authorities,
+                ..
+            } => Some(disabled_authorities.clone()),
+        },
+        blocks,
+        best_block_hash: tree
+            .current_best
+            .map(|idx| tree.blocks.get(idx).unwrap().hash),
+        babe_seen_slots: tree
+            .babe_seen_slots
+            .iter()
+            .map(|((slot_number, author), header)| (*slot_number, *author, encode_header(header)))
+            .collect(),
+        babe_epochs: babe_epochs
+            .list
+            .into_iter()
+            .map(|epoch| SerializedBabeEpochV1 {
+                authorities: epoch
+                    .0
+                    .authorities
+                    .iter()
+                    .map(|a| (a.public_key, a.weight))
+                    .collect(),
+                randomness: epoch.0.randomness,
+                config: epoch.1,
+            })
+            .collect(),
+        aura_authorities_lists: aura_authorities_lists
+            .list
+            .into_iter()
+            .map(|list| list.iter().map(|a| a.public_key).collect())
+            .collect(),
+        grandpa_authorities_lists: grandpa_authorities_lists
+            .list
+            .into_iter()
+            .map(|list| list.iter().map(|a| (a.public_key, a.weight)).collect())
+            .collect(),
+    };
+
+    let mut out = Vec::with_capacity(1 + snapshot.size_hint() + 32);
+    out.push(SNAPSHOT_VERSION);
+    snapshot.encode_to(&mut out);
+    let checksum = blake2_rfc::blake2b::blake2b(32, &[], &out);
+    out.extend_from_slice(checksum.as_bytes());
+    out
+}
+
+/// Rebuilds a tree from a snapshot. See [`NonFinalizedTree::from_snapshot`].
+pub(super) fn decode<T>(
+    config: Config,
+    snapshot: &[u8],
+    mut user_data: impl FnMut(header::HeaderRef) -> T,
+) -> Result<NonFinalizedTree<T>, SnapshotDecodeError> {
+    if snapshot.len() < 1 + 32 {
+        return Err(SnapshotDecodeError::BadChecksum);
+    }
+
+    let (content, checksum) = snapshot.split_at(snapshot.len() - 32);
+    if blake2_rfc::blake2b::blake2b(32, &[], content).as_bytes() != checksum {
+        return Err(SnapshotDecodeError::BadChecksum);
+    }
+
+    // The `unwrap()` can't panic, as the length has been checked above.
+    let (version, mut content) = content.split_first().unwrap();
+    if *version != SNAPSHOT_VERSION {
+        return Err(SnapshotDecodeError::UnsupportedVersion(*version));
+    }
+
+    let snapshot =
+        SnapshotV1::decode(&mut content).map_err(|_| SnapshotDecodeError::InvalidFormat)?;
+    if !content.is_empty() {
+        return Err(SnapshotDecodeError::InvalidFormat);
+    }
+
+    let mut tree = NonFinalizedTree::new(config);
+
+    if snapshot.finalized_block_hash != tree.finalized_block_hash {
+        return Err(SnapshotDecodeError::FinalizedBlockMismatch);
+    }
+
+    tree.babe_finalized_block_weight = snapshot.babe_finalized_block_weight;
+    match (
+        &mut tree.finalized_consensus,
+        snapshot.babe_finalized_disabled_authorities,
+    ) {
+        (FinalizedConsensus::Aura { .. }, None) => {}
+        (
+            FinalizedConsensus::Babe {
+                disabled_authorities,
+                ..
+            },
+            Some(list),
+        ) => *disabled_authorities = list,
+        _ => return Err(SnapshotDecodeError::ConsensusMismatch),
+    }
+
+    let babe_epochs = snapshot
+        .babe_epochs
+        .into_iter()
+        .map(|epoch| {
+            Arc::new((
+                header::BabeNextEpoch {
+                    authorities: epoch
+                        .authorities
+                        .into_iter()
+                        .map(|(public_key, weight)| header::BabeAuthority { public_key, weight })
+                        .collect(),
+                    randomness: epoch.randomness,
+                },
+                epoch.config,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let aura_authorities_lists = snapshot
+        .aura_authorities_lists
+        .into_iter()
+        .map(|list| {
+            Arc::new(
+                list.into_iter()
+                    .map(|public_key| header::AuraAuthority { public_key })
+                    .collect(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let grandpa_authorities_lists = snapshot
+        .grandpa_authorities_lists
+        .into_iter()
+        .map(|list| {
+            Arc::new(
+                list.into_iter()
+                    .map(|(public_key, weight)| header::GrandpaAuthority { public_key, weight })
+                    .collect(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // Maps block hashes to their index within `tree.blocks`.
+    let mut indices =
+        HashMap::<[u8; 32], fork_tree::NodeIndex, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+            snapshot.blocks.len(),
+            Default::default(),
+        );
+
+    for block in snapshot.blocks {
+        let decoded_header = header::decode(&block.scale_encoded_header)
+            .map_err(SnapshotDecodeError::InvalidHeader)?;
+        let hash = header::hash_from_scale_encoded_header(&block.scale_encoded_header);
+
+        if indices.contains_key(&hash) {
+            return Err(SnapshotDecodeError::DuplicateBlock);
+        }
+
+        if tree.bad_blocks.contains(&hash)
+            || tree
+                .fork_blocks
+                .get(&decoded_header.number)
+                .map_or(false, |expected| *expected != hash)
+        {
+            return Err(SnapshotDecodeError::ForbiddenBlock);
+        }
+
+        let (parent_tree_index, parent_number) =
+            if *decoded_header.parent_hash == tree.finalized_block_hash {
+                (None, tree.finalized_block_header.number)
+            } else {
+                let parent_tree_index = *indices
+                    .get(decoded_header.parent_hash)
+                    .ok_or(SnapshotDecodeError::UnknownParent)?;
+                let parent_number = tree.blocks.get(parent_tree_index).unwrap().header.number;
+                (Some(parent_tree_index), parent_number)
+            };
+
+        if parent_number.checked_add(1) != Some(decoded_header.number) {
+            return Err(SnapshotDecodeError::BadBlockNumber);
+        }
+
+        let consensus = match (block.consensus, &tree.finalized_consensus) {
+            (
+                SerializedBlockConsensusV1::Aura { authorities_list },
+                FinalizedConsensus::Aura { .. },
+            ) => BlockConsensus::Aura {
+                authorities_list: pool_get(&aura_authorities_lists, authorities_list)?,
+            },
+            (
+                SerializedBlockConsensusV1::Babe {
+                    block1_slot_number,
+                    current_epoch,
+                    next_epoch,
+                    disabled_authorities,
+                },
+                FinalizedConsensus::Babe { .. },
+            ) => BlockConsensus::Babe {
+                block1_slot_number,
+                current_epoch: match current_epoch {
+                    Some(idx) => Some(pool_get(&babe_epochs, idx)?),
+                    None => None,
+                },
+                next_epoch: pool_get(&babe_epochs, next_epoch)?,
+                disabled_authorities,
+                randomness_accumulator: None,
+            },
+            _ => return Err(SnapshotDecodeError::ConsensusMismatch),
+        };
+
+        let grandpa = grandpa::GrandpaState {
+            authorities_set_id: block.grandpa.authorities_set_id,
+            triggered_authorities: pool_get(
+                &grandpa_authorities_lists,
+                block.grandpa.triggered_authorities,
+            )?,
+            scheduled_change: match block.grandpa.scheduled_change {
+                Some((n, idx)) => Some((n, pool_get(&grandpa_authorities_lists, idx)?)),
+                None => None,
+            },
+            forced_change: match block.grandpa.forced_change {
+                Some((n, idx)) => Some((n, pool_get(&grandpa_authorities_lists, idx)?)),
+                None => None,
+            },
+            pause_state: block.grandpa.pause_state.into(),
+        };
+
+        let user_data = user_data(decoded_header.clone());
+
+        let tree_index = tree.blocks.insert(
+            parent_tree_index,
+            Block {
+                header: decoded_header.into(),
+                hash,
+                consensus,
+                babe_primary_slots_weight: block.babe_primary_slots_weight,
+                grandpa,
+                grandpa_triggered_change: block.grandpa_triggered_change.map(
+                    |change| match change {
+                        SerializedTriggeredChangeV1::Scheduled => {
+                            grandpa::TriggeredChange::Scheduled
+                        }
+                        SerializedTriggeredChangeV1::Forced => grandpa::TriggeredChange::Forced,
+                    },
+                ),
+                user_data,
+            },
+        );
+
+        indices.insert(hash, tree_index);
+    }
+
+    tree.current_best = match snapshot.best_block_hash {
+        Some(hash) => Some(
+            *indices
+                .get(&hash)
+                .ok_or(SnapshotDecodeError::UnknownBestBlock)?,
+        ),
+        None if indices.is_empty() => None,
+        None => return Err(SnapshotDecodeError::UnknownBestBlock),
+    };
+
+    for (slot_number, author, scale_encoded_header) in snapshot.babe_seen_slots {
+        let decoded_header =
+            header::decode(&scale_encoded_header).map_err(SnapshotDecodeError::InvalidHeader)?;
+        tree.babe_seen_slots
+            .insert((slot_number, author), decoded_header.into());
+    }
+
+    Ok(tree)
+}
+
+/// Error when restoring a [`NonFinalizedTree`] from a snapshot.
+#[derive(Debug, derive_more::Display)]
+pub enum SnapshotDecodeError {
+    /// The checksum of the snapshot doesn't match its content. The snapshot has likely been
+    /// truncated or corrupted.
+    BadChecksum,
+    /// The snapshot has been produced by a version of the format that isn't supported.
+    #[display(fmt = "Unsupported snapshot version: {}", _0)]
+    UnsupportedVersion(u8),
+    /// Failed to decode the content of the snapshot.
+    InvalidFormat,
+    /// The snapshot has been taken while a different block was finalized.
+    FinalizedBlockMismatch,
+    /// The consensus engine of the blocks of the snapshot doesn't match the one of the chain.
+    ConsensusMismatch,
+    /// Error while decoding the header of a block of the snapshot.
+    InvalidHeader(header::Error),
+    /// The parent of a block is neither the finalized block nor a block found earlier in the
+    /// snapshot.
+    UnknownParent,
+    /// The number of a block isn't equal to the number of its parent plus one.
+    BadBlockNumber,
+    /// The same block is found multiple times in the snapshot.
+    DuplicateBlock,
+    /// A block of the snapshot is in the list of bad blocks, or doesn't match the expected fork
+    /// block.
+    ForbiddenBlock,
+    /// A block refers to an epoch or a list of authorities that isn't in the snapshot.
+    InvalidIndex,
+    /// The best block isn't one of the blocks of the snapshot.
+    UnknownBestBlock,
+}
+
+/// Returns a clone of the element of `pool` at the given index.
+fn pool_get<T>(pool: &[Arc<T>], index: u32) -> Result<Arc<T>, SnapshotDecodeError> {
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| pool.get(index))
+        .cloned()
+        .ok_or(SnapshotDecodeError::InvalidIndex)
+}
+
+fn encode_header(header: &header::Header) -> Vec<u8> {
+    header.scale_encoding().fold(Vec::new(), |mut a, b| {
+        a.extend_from_slice(b.as_ref());
+        a
+    })
+}
+
+/// List of values shared between multiple blocks, deduplicated by pointer.
+struct Pool<'a, T> {
+    list: Vec<&'a T>,
+    indices: HashMap<*const T, u32, fnv::FnvBuildHasher>,
+}
+
+impl<'a, T> Pool<'a, T> {
+    /// Inserts the value in the list if it isn't present yet, and returns its index.
+    fn index(&mut self, value: &'a Arc<T>) -> u32 {
+        let list = &mut self.list;
+        *self.indices.entry(Arc::as_ptr(value)).or_insert_with(|| {
+            list.push(&**value);
+            u32::try_from(list.len() - 1).unwrap()
+        })
+    }
+}
+
+impl<'a, T> Default for Pool<'a, T> {
+    fn default() -> Self {
+        Pool {
+            list: Vec::new(),
+            indices: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Encode, Decode)]
+struct SnapshotV1 {
+    finalized_block_hash: [u8; 32],
+    babe_finalized_block_weight: u64,
+    /// `Some` if and only if the chain uses BABE.
+    babe_finalized_disabled_authorities: Option<Vec<u32>>,
+    /// Ordered such that parents are always found before their children.
+    blocks: Vec<SerializedBlockV1>,
+    /// `None` if and only if `blocks` is empty.
+    best_block_hash: Option<[u8; 32]>,
+    babe_seen_slots: Vec<(u64, [u8; 32], Vec<u8>)>,
+    babe_epochs: Vec<SerializedBabeEpochV1>,
+    aura_authorities_lists: Vec<Vec<[u8; 32]>>,
+    grandpa_authorities_lists: Vec<Vec<([u8; 32], u64)>>,
+}
+
+#[derive(Debug, Encode, Decode)]
+struct SerializedBlockV1 {
+    scale_encoded_header: Vec<u8>,
+    consensus: SerializedBlockConsensusV1,
+    babe_primary_slots_weight: u64,
+    grandpa: SerializedGrandpaStateV1,
+    grandpa_triggered_change: Option<SerializedTriggeredChangeV1>,
+}
+
+#[derive(Debug, Encode, Decode)]
+enum SerializedBlockConsensusV1 {
+    Aura {
+        /// Index within [`SnapshotV1::aura_authorities_lists`].
+        authorities_list: u32,
+    },
+    Babe {
+        block1_slot_number: u64,
+        /// Index within [`SnapshotV1::babe_epochs`].
+        current_epoch: Option<u32>,
+        /// Index within [`SnapshotV1::babe_epochs`].
+        next_epoch: u32,
+        disabled_authorities: Vec<u32>,
+    },
+}
+
+#[derive(Debug, Encode, Decode)]
+struct SerializedBabeEpochV1 {
+    authorities: Vec<([u8; 32], u64)>,
+    randomness: [u8; 32],
+    config: header::BabeNextConfig,
+}
+
+#[derive(Debug, Encode, Decode)]
+struct SerializedGrandpaStateV1 {
+    authorities_set_id: u64,
+    /// Index within [`SnapshotV1::grandpa_authorities_lists`].
+    triggered_authorities: u32,
+    /// Block height and index within [`SnapshotV1::grandpa_authorities_lists`].
+    scheduled_change: Option<(u64, u32)>,
+    /// Block height and index within [`SnapshotV1::grandpa_authorities_lists`].
+    forced_change: Option<(u64, u32)>,
+    pause_state: SerializedGrandpaPauseStateV1,
+}
+
+#[derive(Debug, Encode, Decode)]
+enum SerializedTriggeredChangeV1 {
+    Scheduled,
+    Forced,
+}
+
+#[derive(Debug, Encode, Decode)]
+enum SerializedGrandpaPauseStateV1 {
+    Live,
+    PendingPause(u64),
+    Paused,
+    PendingResume(u64),
+}
+
+impl From<GrandpaPauseState> for SerializedGrandpaPauseStateV1 {
+    fn from(state: GrandpaPauseState) -> Self {
+        match state {
+            GrandpaPauseState::Live => SerializedGrandpaPauseStateV1::Live,
+            GrandpaPauseState::PendingPause {
+                trigger_block_height,
+            } => SerializedGrandpaPauseStateV1::PendingPause(trigger_block_height),
+            GrandpaPauseState::Paused => SerializedGrandpaPauseStateV1::Paused,
+            GrandpaPauseState::PendingResume {
+                trigger_block_height,
+            } => SerializedGrandpaPauseStateV1::PendingResume(trigger_block_height),
+        }
+    }
+}
+
+impl From<SerializedGrandpaPauseStateV1> for GrandpaPauseState {
+    fn from(state: SerializedGrandpaPauseStateV1) -> Self {
+        match state {
+            SerializedGrandpaPauseStateV1::Live => GrandpaPauseState::Live,
+            SerializedGrandpaPauseStateV1::PendingPause(trigger_block_height) => {
+                GrandpaPauseState::PendingPause {
+                    trigger_block_height,
+                }
+            }
+            SerializedGrandpaPauseStateV1::Paused => GrandpaPauseState::Paused,
+            SerializedGrandpaPauseStateV1::PendingResume(trigger_block_height) => {
+                GrandpaPauseState::PendingResume {
+                    trigger_block_height,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        tests::{aura_header, config, import},
+        Config, NonFinalizedTree,
+    };
+    use super::SnapshotDecodeError;
+
+    /// Same as [`config`], but with a finalized block whose state root is the given one.
+    fn config_with_state_root(state_root: [u8; 32]) -> Config {
+        let mut config = config();
+        config
+            .chain_information_config
+            .chain_information
+            .finalized_block_header
+            .state_root = state_root;
+        config
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut tree = NonFinalizedTree::new(config());
+        let genesis_hash = tree.finalized_block_hash();
+
+        // Build a chain of three blocks, plus a fork of one block.
+        let block1 = import(&mut tree, aura_header(genesis_hash, 1, 1));
+        let block2 = import(&mut tree, aura_header(block1, 2, 2));
+        let block3 = import(&mut tree, aura_header(block2, 3, 3));
+        let _fork2 = import(&mut tree, aura_header(block1, 2, 4));
+        assert_eq!(tree.best_block_hash(), block3);
+
+        let snapshot = tree.snapshot();
+        let mut restored = NonFinalizedTree::from_snapshot(config(), &snapshot, |_| ()).unwrap();
+        assert_eq!(restored.len(), 4);
+        assert_eq!(restored.best_block_hash(), block3);
+        assert_eq!(restored.snapshot(), snapshot);
+
+        // The restored tree must be able to verify children of the restored blocks.
+        let block4 = import(&mut restored, aura_header(block3, 4, 5));
+        assert_eq!(restored.best_block_hash(), block4);
+    }
+
+    #[test]
+    fn integrity_checked() {
+        let mut tree = NonFinalizedTree::new(config());
+        let genesis_hash = tree.finalized_block_hash();
+        import(&mut tree, aura_header(genesis_hash, 1, 1));
+        let snapshot = tree.snapshot();
+
+        let mut corrupted = snapshot.clone();
+        corrupted[10] ^= 1;
+        assert!(matches!(
+            NonFinalizedTree::from_snapshot(config(), &corrupted, |_| ()),
+            Err(SnapshotDecodeError::BadChecksum)
+        ));
+
+        assert!(matches!(
+            NonFinalizedTree::from_snapshot(config(), &snapshot[..snapshot.len() - 1], |_| ()),
+            Err(SnapshotDecodeError::BadChecksum)
+        ));
+
+        // Different finalized block.
+        assert!(matches!(
+            NonFinalizedTree::from_snapshot(config_with_state_root([9; 32]), &snapshot, |_| ()),
+            Err(SnapshotDecodeError::FinalizedBlockMismatch)
+        ));
+    }
+}
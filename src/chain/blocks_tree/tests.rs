@@ -0,0 +1,547 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tests of the [`NonFinalizedTree`], using a chain with a single Aura authority, or a BABE
+//! chain built by the functions of [`babe::tests`].
+//!
+//! The functions that build the Aura chain are also used by the tests of the sync state
+//! machines.
+
+#![cfg(test)]
+
+use super::{
+    BestBlockChange, ChainEvent, Config, FinalityProofVerifyError, ForkChoice, GrandpaChangeError,
+    HeaderVerifyError, HeaderVerifySuccess, JustificationVerifyError, NonFinalizedTree,
+    BABE_SEEN_SLOTS_WINDOW,
+};
+use crate::{chain::chain_information, finality::proof, header, trie::node_store, verify::babe};
+
+use core::{convert::TryFrom as _, num::NonZeroU64, time::Duration};
+use parity_scale_codec::Encode as _;
+
+pub(crate) fn keypair() -> schnorrkel::Keypair {
+    schnorrkel::MiniSecretKey::from_bytes(&[7; 32])
+        .unwrap()
+        .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+}
+
+pub(crate) fn config() -> Config {
+    Config {
+        chain_information_config: chain_information::ChainInformationConfig {
+            chain_information: chain_information::ChainInformation {
+                finalized_block_header: header::Header {
+                    parent_hash: [0; 32],
+                    number: 0,
+                    state_root: [0; 32],
+                    extrinsics_root: [0; 32],
+                    digest: header::DigestRef::empty().into(),
+                },
+                consensus: chain_information::ChainInformationConsensus::Aura {
+                    finalized_authorities_list: vec![header::AuraAuthority {
+                        public_key: keypair().public.to_bytes(),
+                    }],
+                    slot_duration: NonZeroU64::new(6000).unwrap(),
+                },
+                grandpa_after_finalized_block_authorities_set_id: 0,
+                grandpa_finalized_triggered_authorities: vec![header::GrandpaAuthority {
+                    public_key: grandpa_public_key(),
+                    weight: 1,
+                }],
+                grandpa_finalized_scheduled_change: None,
+                grandpa_finalized_forced_change: None,
+                grandpa_finalized_pause_state: chain_information::GrandpaPauseState::Live,
+            },
+            bad_blocks: Default::default(),
+            fork_blocks: Default::default(),
+        },
+        blocks_capacity: 16,
+        fork_choice: ForkChoice::LongestChain,
+        generate_events: true,
+    }
+}
+
+/// Same as [`config`], but with a BABE chain whose genesis is [`babe::tests::genesis_header`].
+fn babe_config() -> Config {
+    let mut config = config();
+    let chain_information_config = &mut config.chain_information_config;
+    chain_information_config
+        .chain_information
+        .finalized_block_header = header::decode(&babe::tests::genesis_header())
+        .unwrap()
+        .into();
+    chain_information_config.chain_information.consensus =
+        chain_information::ChainInformationConsensus::Babe {
+            genesis_config: babe::tests::genesis_configuration(),
+            finalized_block1_slot_number: None,
+            finalized_block_epoch_information: None,
+            finalized_next_epoch_transition: None,
+        };
+    config.fork_choice = ForkChoice::BabePrimarySlots;
+    config
+}
+
+fn grandpa_secret_key() -> ed25519_dalek::SecretKey {
+    ed25519_dalek::SecretKey::from_bytes(&[9; 32]).unwrap()
+}
+
+fn grandpa_public_key() -> [u8; 32] {
+    ed25519_dalek::PublicKey::from(&grandpa_secret_key()).to_bytes()
+}
+
+/// Builds a justification of the given block, signed by the only GrandPa authority.
+pub(crate) fn justification(target_hash: [u8; 32], target_number: u32) -> Vec<u8> {
+    const ROUND: u64 = 1;
+    const SET_ID: u64 = 0;
+
+    let secret = grandpa_secret_key();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let mut msg = vec![1u8];
+    msg.extend_from_slice(&target_hash);
+    msg.extend_from_slice(&target_number.to_le_bytes());
+    msg.extend_from_slice(&ROUND.to_le_bytes());
+    msg.extend_from_slice(&SET_ID.to_le_bytes());
+    let signature = ed25519_dalek::ExpandedSecretKey::from(&secret).sign(&msg, &public);
+
+    let mut out = ROUND.to_le_bytes().to_vec();
+    out.extend_from_slice(&target_hash);
+    out.extend_from_slice(&target_number.to_le_bytes());
+    parity_scale_codec::Compact(1u32).encode_to(&mut out);
+    out.extend_from_slice(&target_hash);
+    out.extend_from_slice(&target_number.to_le_bytes());
+    out.extend_from_slice(&signature.to_bytes());
+    out.extend_from_slice(public.as_bytes());
+    parity_scale_codec::Compact(0u32).encode_to(&mut out);
+    out
+}
+
+/// Builds a header signed by the only Aura authority.
+pub(crate) fn aura_header(parent_hash: [u8; 32], number: u64, slot_number: u64) -> Vec<u8> {
+    aura_header_with_grandpa_logs(parent_hash, number, slot_number, &[])
+}
+
+/// Same as [`aura_header`], but the digest additionally contains the given GrandPa log items.
+fn aura_header_with_grandpa_logs(
+    parent_hash: [u8; 32],
+    number: u64,
+    slot_number: u64,
+    grandpa_logs: &[header::GrandpaConsensusLog],
+) -> Vec<u8> {
+    let encode = |seal: Option<&[u8; 64]>| {
+        let mut out = Vec::new();
+        out.extend_from_slice(&parent_hash);
+        parity_scale_codec::Compact(number).encode_to(&mut out);
+        out.extend_from_slice(&[1; 32]);
+        out.extend_from_slice(&[2; 32]);
+        let num_items = 1 + grandpa_logs.len() + if seal.is_some() { 1 } else { 0 };
+        parity_scale_codec::Compact(u64::try_from(num_items).unwrap()).encode_to(&mut out);
+        out.push(6);
+        out.extend_from_slice(b"aura");
+        slot_number.to_le_bytes()[..].encode_to(&mut out);
+        for log in grandpa_logs {
+            for buffer in header::DigestItemRef::GrandpaConsensus(log.into()).scale_encoding() {
+                out.extend_from_slice(buffer.as_ref());
+            }
+        }
+        if let Some(seal) = seal {
+            out.push(5);
+            out.extend_from_slice(b"aura");
+            seal[..].encode_to(&mut out);
+        }
+        out
+    };
+
+    let pre_seal_hash = header::hash_from_scale_encoded_header(encode(None));
+    let seal = keypair()
+        .sign_simple(b"substrate", &pre_seal_hash)
+        .to_bytes();
+    encode(Some(&seal))
+}
+
+pub(crate) fn import(tree: &mut NonFinalizedTree<()>, scale_encoded_header: Vec<u8>) -> [u8; 32] {
+    let hash = header::hash_from_scale_encoded_header(&scale_encoded_header);
+    match tree
+        .verify_header(scale_encoded_header, Duration::from_secs(1 << 32))
+        .unwrap()
+    {
+        HeaderVerifySuccess::Insert { insert, .. } => insert.insert(()),
+        HeaderVerifySuccess::Duplicate => panic!(),
+    }
+    hash
+}
+
+#[test]
+fn node_store_pruned_on_finalization() {
+    let node = |n: u8| vec![n; 40];
+    let hash = |n: u8| {
+        <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], &node(n)).as_bytes()).unwrap()
+    };
+
+    let mut tree = NonFinalizedTree::new(config());
+    let genesis_hash = tree.finalized_block_hash();
+
+    // A store whose finalized block isn't the one of the tree is refused.
+    assert!(tree
+        .set_node_store(node_store::NodeStore::new(node_store::Config {
+            finalized_block_hash: [0xff; 32],
+            finalized_block_state_root: hash(0),
+            finalized_block_nodes: vec![node(0)].into_iter(),
+            num_finalized_blocks_to_keep: 0,
+        }))
+        .is_err());
+    assert!(tree.node_store().is_none());
+
+    tree.set_node_store(node_store::NodeStore::new(node_store::Config {
+        finalized_block_hash: genesis_hash,
+        finalized_block_state_root: hash(0),
+        finalized_block_nodes: vec![node(0)].into_iter(),
+        num_finalized_blocks_to_keep: 0,
+    }))
+    .unwrap();
+
+    let block1 = import(&mut tree, aura_header(genesis_hash, 1, 1));
+    let fork1 = import(&mut tree, aura_header(genesis_hash, 1, 2));
+    let block2 = import(&mut tree, aura_header(block1, 2, 3));
+
+    // Only the states of block 1 and of the fork are inserted in the store.
+    for (block_hash, n) in &[(block1, 1), (fork1, 2)] {
+        tree.node_store_mut()
+            .unwrap()
+            .insert_block(
+                *block_hash,
+                &genesis_hash,
+                hash(*n),
+                vec![node(*n)].into_iter(),
+                vec![hash(0)].into_iter(),
+            )
+            .unwrap();
+    }
+    assert_eq!(tree.node_store().unwrap().num_nodes(), 3);
+
+    // Finalizing block 2 finalizes block 1 in the store, which discards the fork and prunes the
+    // genesis block.
+    let _ = tree.set_finalized_block(&block2).unwrap().count();
+    let node_store = tree.node_store().unwrap();
+    assert_eq!(node_store.finalized_block_hash(), block1);
+    assert_eq!(node_store.num_blocks(), 1);
+    assert_eq!(node_store.num_nodes(), 1);
+    assert!(node_store.node_value(&hash(1)).is_some());
+}
+
+#[test]
+fn reorg() {
+    let mut tree = NonFinalizedTree::new(config());
+    let genesis_hash = tree.finalized_block_hash();
+    let a1 = import(&mut tree, aura_header(genesis_hash, 1, 1));
+    let a2 = import(&mut tree, aura_header(a1, 2, 2));
+    let b1 = import(&mut tree, aura_header(genesis_hash, 1, 3));
+    let b2 = import(&mut tree, aura_header(b1, 2, 4));
+
+    // In case of equality, the current best block is kept.
+    assert_eq!(tree.best_block_hash(), a2);
+    while tree.next_event().is_some() {}
+
+    let b3 = import(&mut tree, aura_header(b2, 3, 5));
+    assert_eq!(tree.best_block_hash(), b3);
+    assert_eq!(
+        tree.next_event(),
+        Some(ChainEvent::BlockImported {
+            hash: b3,
+            number: 3,
+            parent_hash: b2,
+        })
+    );
+    assert_eq!(
+        tree.next_event(),
+        Some(ChainEvent::BestBlockChanged(BestBlockChange {
+            old_best_hash: a2,
+            new_best_hash: b3,
+            new_best_number: 3,
+            retracted: vec![a2, a1],
+            enacted: vec![b1, b2, b3],
+        }))
+    );
+    assert_eq!(tree.next_event(), None);
+
+    // Finalizing a block of the other fork switches back to that fork.
+    let _ = tree.set_finalized_block(&a1).unwrap().count();
+    assert_eq!(tree.best_block_hash(), a2);
+    match tree.next_event() {
+        Some(ChainEvent::BestBlockChanged(change)) => {
+            assert_eq!(change.old_best_hash, b3);
+            assert_eq!(change.new_best_hash, a2);
+            assert_eq!(change.retracted, vec![b3, b2, b1]);
+            assert_eq!(change.enacted, vec![a1, a2]);
+        }
+        ev => panic!("{:?}", ev),
+    }
+}
+
+#[test]
+fn grandpa_forced_change_while_scheduled_pending() {
+    let change = |delay| header::GrandpaScheduledChange {
+        next_authorities: vec![header::GrandpaAuthority {
+            public_key: [3; 32],
+            weight: 1,
+        }],
+        delay,
+    };
+    let forced = |delay| header::GrandpaConsensusLog::ForcedChange {
+        reset_block_height: 0,
+        change: change(delay),
+    };
+
+    // A forced change is accepted while a scheduled change is pending.
+    let mut tree = NonFinalizedTree::new(config());
+    let genesis_hash = tree.finalized_block_hash();
+    let block1 = import(
+        &mut tree,
+        aura_header_with_grandpa_logs(
+            genesis_hash,
+            1,
+            1,
+            &[header::GrandpaConsensusLog::ScheduledChange(change(5))],
+        ),
+    );
+    let block2 = import(
+        &mut tree,
+        aura_header_with_grandpa_logs(block1, 2, 2, &[forced(5)]),
+    );
+
+    // Only one forced change can be pending at a time.
+    assert!(matches!(
+        tree.verify_header(
+            aura_header_with_grandpa_logs(block2, 3, 3, &[forced(5)]),
+            Duration::from_secs(1 << 32)
+        ),
+        Err(HeaderVerifyError::GrandpaChange(
+            GrandpaChangeError::ChangeAlreadyPending
+        ))
+    ));
+}
+
+#[test]
+fn justification_finalizes() {
+    let mut tree = NonFinalizedTree::new(config());
+    let genesis_hash = tree.finalized_block_hash();
+    let block1 = import(&mut tree, aura_header(genesis_hash, 1, 1));
+    let block2 = import(&mut tree, aura_header(block1, 2, 2));
+
+    let _ = tree
+        .verify_justification(&justification(block2, 2))
+        .unwrap()
+        .apply()
+        .count();
+    assert_eq!(tree.finalized_block_hash(), block2);
+}
+
+#[test]
+fn finality_proof() {
+    let mut hashes = vec![NonFinalizedTree::<()>::new(config()).finalized_block_hash()];
+    let headers = (1..=4)
+        .map(|number| {
+            let header = aura_header(*hashes.last().unwrap(), number, number);
+            hashes.push(header::hash_from_scale_encoded_header(&header));
+            header
+        })
+        .collect::<Vec<_>>();
+    hashes.remove(0);
+
+    // Proof of block 4, with a first fragment for block 2 and a second one for block 4.
+    let build_proof = |justification4: &[u8]| {
+        let justification2 = justification(hashes[1], 2);
+        let blocks = headers
+            .iter()
+            .enumerate()
+            .map(|(n, header)| proof::BuildBlock {
+                scale_encoded_header: header,
+                scale_encoded_justification: match n {
+                    1 => Some(&justification2[..]),
+                    3 => Some(justification4),
+                    _ => None,
+                },
+                enacts_authorities_change: n == 1,
+            })
+            .collect::<Vec<_>>();
+        let proof = proof::build(&hashes[3], blocks.into_iter())
+            .unwrap()
+            .unwrap();
+        assert_eq!(proof::decode(&proof).unwrap().len(), 2);
+        proof
+    };
+
+    let mut tree = NonFinalizedTree::<u64>::new(config());
+    let finalized = tree
+        .verify_finality_proof(
+            &build_proof(&justification(hashes[3], 4)),
+            Duration::from_secs(1 << 32),
+            |header| header.number,
+        )
+        .unwrap();
+    assert_eq!(finalized, vec![1, 2, 3, 4]);
+    assert_eq!(tree.finalized_block_hash(), hashes[3]);
+
+    // Invalid signature in the second fragment. The first fragment stays applied, and the
+    // blocks it has finalized are returned alongside with the error.
+    let mut bad_justification = justification(hashes[3], 4);
+    bad_justification[81] ^= 1;
+    let mut tree = NonFinalizedTree::<u64>::new(config());
+    match tree.verify_finality_proof(
+        &build_proof(&bad_justification),
+        Duration::from_secs(1 << 32),
+        |header| header.number,
+    ) {
+        Err((
+            FinalityProofVerifyError::InvalidJustification(
+                JustificationVerifyError::VerificationFailed(_),
+            ),
+            finalized,
+        )) => assert_eq!(finalized, vec![1, 2]),
+        _ => panic!(),
+    }
+    assert_eq!(tree.finalized_block_hash(), hashes[1]);
+
+    // Second fragment whose justification targets a different block than the fragment.
+    let mut tree = NonFinalizedTree::<u64>::new(config());
+    assert!(matches!(
+        tree.verify_finality_proof(
+            &build_proof(&justification(hashes[2], 3)),
+            Duration::from_secs(1 << 32),
+            |header| header.number,
+        ),
+        Err((FinalityProofVerifyError::BlockHashMismatch, _))
+    ));
+}
+
+#[test]
+fn bad_blocks_and_fork_blocks() {
+    let genesis_hash = NonFinalizedTree::<()>::new(config()).finalized_block_hash();
+    let a1 = aura_header(genesis_hash, 1, 1);
+    let b1 = aura_header(genesis_hash, 1, 2);
+    let b1_hash = header::hash_from_scale_encoded_header(&b1);
+    let b2 = aura_header(b1_hash, 2, 3);
+    let b2_hash = header::hash_from_scale_encoded_header(&b2);
+
+    let mut config = config();
+    config
+        .chain_information_config
+        .bad_blocks
+        .insert(header::hash_from_scale_encoded_header(&a1));
+    config
+        .chain_information_config
+        .fork_blocks
+        .insert(2, b2_hash);
+    let mut tree = NonFinalizedTree::new(config);
+
+    assert!(matches!(
+        tree.verify_header(a1, Duration::from_secs(1 << 32)),
+        Err(HeaderVerifyError::BadBlock)
+    ));
+    assert_eq!(import(&mut tree, b1), b1_hash);
+
+    // A block at height 2 other than the fork block is rejected.
+    assert!(matches!(
+        tree.verify_header(aura_header(b1_hash, 2, 4), Duration::from_secs(1 << 32)),
+        Err(HeaderVerifyError::ForkBlockMismatch { expected_hash }) if expected_hash == b2_hash
+    ));
+    assert_eq!(tree.best_block_hash(), b1_hash);
+    assert_eq!(import(&mut tree, b2), b2_hash);
+    assert_eq!(tree.best_block_hash(), b2_hash);
+}
+
+#[test]
+fn babe_equivocation() {
+    use babe::tests::{
+        babe_header, babe_primary_header, genesis_header, keypair, next_epoch_log, primary_slot,
+        secondary_slot, GENESIS_RANDOMNESS,
+    };
+
+    let equivocation = |tree: &mut NonFinalizedTree<()>, scale_encoded_header: &[u8]| match tree
+        .verify_header(scale_encoded_header.to_vec(), Duration::from_secs(1 << 32))
+        .unwrap()
+    {
+        HeaderVerifySuccess::Insert { insert, .. } => insert.babe_equivocation().cloned(),
+        HeaderVerifySuccess::Duplicate => panic!(),
+    };
+
+    let mut tree = NonFinalizedTree::new(babe_config());
+    let genesis = genesis_header();
+
+    // Slot assigned to the authority 0, and that the authority 1 can claim as well.
+    let slot1 = (1000..)
+        .find(|slot| {
+            secondary_slot(&GENESIS_RANDOMNESS, 0, *slot) == *slot
+                && primary_slot(1, 0, &GENESIS_RANDOMNESS, *slot) == *slot
+        })
+        .unwrap();
+    let block1 = babe_header(
+        &genesis,
+        slot1,
+        0,
+        0,
+        &GENESIS_RANDOMNESS,
+        &[next_epoch_log(&[1; 32])],
+    );
+    assert!(equivocation(&mut tree, &block1).is_none());
+    import(&mut tree, block1.clone());
+
+    // Two different authorities producing a block in the same slot isn't an equivocation.
+    let fork1 = babe_primary_header(
+        &genesis,
+        slot1,
+        1,
+        0,
+        &GENESIS_RANDOMNESS,
+        &[next_epoch_log(&[1; 32])],
+    );
+    assert!(equivocation(&mut tree, &fork1).is_none());
+    import(&mut tree, fork1);
+
+    // The same authority producing two different blocks in the same slot is.
+    let fork2 = babe_header(
+        &genesis,
+        slot1,
+        0,
+        0,
+        &GENESIS_RANDOMNESS,
+        &[next_epoch_log(&[2; 32])],
+    );
+    let proof = equivocation(&mut tree, &fork2).unwrap();
+    assert_eq!(proof.offender, keypair(0).public.to_bytes());
+    assert_eq!(proof.slot_number, slot1);
+    assert_eq!(
+        proof.first_header.hash(),
+        header::hash_from_scale_encoded_header(&block1)
+    );
+    assert_eq!(
+        proof.second_header.hash(),
+        header::hash_from_scale_encoded_header(&fork2)
+    );
+
+    // Blocks that are too old compared to the most recent slot are forgotten.
+    let slot2 = secondary_slot(&[1; 32], 0, slot1 + BABE_SEEN_SLOTS_WINDOW + 1);
+    let block2 = babe_header(
+        &block1,
+        slot2,
+        0,
+        (slot2 - slot1) / babe::tests::EPOCH_LENGTH,
+        &[1; 32],
+        &[next_epoch_log(&[3; 32])],
+    );
+    import(&mut tree, block2);
+    assert!(tree.babe_seen_slots.keys().all(|(slot, _)| *slot > slot1));
+    assert!(equivocation(&mut tree, &fork2).is_none());
+}
@@ -38,10 +38,13 @@
 //! They also do not contain the past history of the chain. It is, however, similarly possible to
 //! for instance download the history from other nodes.
 
-use crate::{finality::grandpa, header};
+use crate::{executor, finality::grandpa, header};
 
 use alloc::vec::Vec;
+use core::{convert::TryFrom as _, num::NonZeroU64};
+use hashbrown::{HashMap, HashSet};
 
+pub mod aura;
 pub mod babe;
 
 /// Information about the latest finalized block and state found in its ancestors.
@@ -50,22 +53,8 @@ pub struct ChainInformation {
     /// Header of the highest known finalized block.
     pub finalized_block_header: header::Header,
 
-    /// If the number in [`ChainInformation::finalized_block_header`] is superior or equal to 1,
-    /// then this field must contain the slot number of the block whose number is 1 and is an
-    /// ancestor of the finalized block.
-    pub babe_finalized_block1_slot_number: Option<u64>,
-
-    /// Babe epoch information about the epoch the finalized block belongs to.
-    ///
-    /// Must be `None` if and only if the finalized block is block #0 or belongs to epoch #0.
-    pub babe_finalized_block_epoch_information:
-        Option<(header::BabeNextEpoch, header::BabeNextConfig)>,
-
-    /// Babe epoch information about the epoch right after the one the finalized block belongs to.
-    ///
-    /// Must be `None` if and only if the finalized block is block #0.
-    pub babe_finalized_next_epoch_transition:
-        Option<(header::BabeNextEpoch, header::BabeNextConfig)>,
+    /// Extra items that depend on the consensus engine.
+    pub consensus: ChainInformationConsensus,
 
     /// Grandpa authorities set ID of the block right after finalized block.
     ///
@@ -93,6 +82,23 @@ pub struct ChainInformation {
     /// >           `height(block_with_log_item) + N`. If `N` is 0, then the block where the
     /// >           change is triggered is the same as the one where it is scheduled.
     pub grandpa_finalized_scheduled_change: Option<(u64, Vec<header::GrandpaAuthority>)>,
+
+    /// Forced change in the GrandPa authorities list that has been scheduled by a block that is
+    /// already finalized, but the change is not triggered yet. Contains the block number where
+    /// the changes are to be triggered.
+    ///
+    /// Contrary to [`ChainInformation::grandpa_finalized_scheduled_change`], a forced change is
+    /// triggered as soon as the block at the given height is *imported*, rather than finalized.
+    /// The blocks after the one at the given height must be finalized using the new list of
+    /// authorities.
+    ///
+    /// At most one of [`ChainInformation::grandpa_finalized_scheduled_change`] and this field
+    /// can be `Some`. The block height must always be strictly superior to the height found in
+    /// [`ChainInformation::finalized_block_header`].
+    pub grandpa_finalized_forced_change: Option<(u64, Vec<header::GrandpaAuthority>)>,
+
+    /// Whether the GrandPa authorities are voting, as of the finalized block.
+    pub grandpa_finalized_pause_state: GrandpaPauseState,
 }
 
 impl ChainInformation {
@@ -103,22 +109,80 @@ impl ChainInformation {
     pub fn from_genesis_storage<'a>(
         genesis_storage: impl Iterator<Item = (&'a [u8], &'a [u8])> + Clone,
     ) -> Result<Self, FromGenesisStorageError> {
-        let grandpa_genesis_config =
-            grandpa::chain_config::GrandpaGenesisConfiguration::from_genesis_storage(|key| {
-                genesis_storage
-                    .clone()
-                    .find(|(k, _)| *k == key)
-                    .map(|(_, v)| v.to_owned())
-            })
-            .unwrap();
+        let genesis_storage_access = |key: &[u8]| {
+            genesis_storage
+                .clone()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_owned())
+        };
+
+        // The runtime is compiled only once, and the same virtual machine prototype is used to
+        // retrieve the configuration of all the consensus engines.
+        let wasm_code =
+            genesis_storage_access(b":code").ok_or(FromGenesisStorageError::RuntimeNotFound)?;
+        let heap_pages = if let Some(bytes) = genesis_storage_access(b":heappages") {
+            u64::from_le_bytes(
+                <[u8; 8]>::try_from(&bytes[..])
+                    .map_err(FromGenesisStorageError::HeapPagesDecode)?,
+            )
+        } else {
+            1024 // TODO: default heap pages
+        };
+        let vm = executor::WasmVmPrototype::new(&wasm_code, heap_pages)
+            .map_err(FromGenesisStorageError::VmInitialization)?;
+
+        // The list of runtime APIs that the runtime implements is found in its version. Each API
+        // is identified by the blake2 64bits hash of its name.
+        let (version, vm) =
+            executor::core_version(vm).map_err(|()| FromGenesisStorageError::CoreVersion)?;
+        let aura_api_id = blake2_rfc::blake2b::blake2b(8, &[], b"AuraApi");
+        let is_aura_chain = version
+            .apis
+            .iter()
+            .any(|(id, _)| id[..] == aura_api_id.as_bytes()[..]);
+
+        let (consensus, vm) = if is_aura_chain {
+            let (config, vm) = aura::AuraGenesisConfiguration::from_virtual_machine_prototype(
+                vm,
+                genesis_storage_access,
+            )
+            .map_err(FromGenesisStorageError::AuraConfigLoad)?;
+            let consensus = ChainInformationConsensus::Aura {
+                finalized_authorities_list: config.authorities_list,
+                slot_duration: config.slot_duration,
+            };
+            (consensus, vm)
+        } else {
+            // Chains that don't use Aura are assumed to use BABE.
+            let (genesis_config, vm) =
+                babe::BabeGenesisConfiguration::from_virtual_machine_prototype(
+                    vm,
+                    genesis_storage_access,
+                )
+                .map_err(FromGenesisStorageError::BabeConfigLoad)?;
+            let consensus = ChainInformationConsensus::Babe {
+                genesis_config,
+                finalized_block1_slot_number: None,
+                finalized_block_epoch_information: None,
+                finalized_next_epoch_transition: None,
+            };
+            (consensus, vm)
+        };
+
+        let (grandpa_genesis_config, _) =
+            grandpa::chain_config::GrandpaGenesisConfiguration::from_virtual_machine_prototype(
+                vm,
+                genesis_storage_access,
+            )
+            .map_err(FromGenesisStorageError::GrandpaConfigLoad)?;
 
         Ok(ChainInformation {
             finalized_block_header: crate::calculate_genesis_block_header(genesis_storage),
-            babe_finalized_block1_slot_number: None,
-            babe_finalized_block_epoch_information: None,
-            babe_finalized_next_epoch_transition: None,
+            consensus,
             grandpa_after_finalized_block_authorities_set_id: 0,
             grandpa_finalized_scheduled_change: None,
+            grandpa_finalized_forced_change: None,
+            grandpa_finalized_pause_state: GrandpaPauseState::Live,
             grandpa_finalized_triggered_authorities: grandpa_genesis_config.initial_authorities,
         })
     }
@@ -128,13 +192,7 @@ impl<'a> From<ChainInformationRef<'a>> for ChainInformation {
     fn from(info: ChainInformationRef<'a>) -> ChainInformation {
         ChainInformation {
             finalized_block_header: info.finalized_block_header.into(),
-            babe_finalized_block1_slot_number: info.babe_finalized_block1_slot_number,
-            babe_finalized_block_epoch_information: info
-                .babe_finalized_block_epoch_information
-                .map(|(e, c)| (e.clone().into(), c)),
-            babe_finalized_next_epoch_transition: info
-                .babe_finalized_next_epoch_transition
-                .map(|(e, c)| (e.clone().into(), c)),
+            consensus: info.consensus.into(),
             grandpa_after_finalized_block_authorities_set_id: info
                 .grandpa_after_finalized_block_authorities_set_id,
             grandpa_finalized_triggered_authorities: info
@@ -143,15 +201,118 @@ impl<'a> From<ChainInformationRef<'a>> for ChainInformation {
             grandpa_finalized_scheduled_change: info
                 .grandpa_finalized_scheduled_change
                 .map(|(n, l)| (n, l.into())),
+            grandpa_finalized_forced_change: info
+                .grandpa_finalized_forced_change
+                .map(|(n, l)| (n, l.into())),
+            grandpa_finalized_pause_state: info.grandpa_finalized_pause_state,
         }
     }
 }
 
+/// Extra items of [`ChainInformation`] that depend on the consensus engine.
+#[derive(Debug, Clone)]
+pub enum ChainInformationConsensus {
+    /// Chain is using the Aura consensus engine.
+    Aura {
+        /// List of authorities that must validate children of the block referred to by
+        /// [`ChainInformation::finalized_block_header`].
+        finalized_authorities_list: Vec<header::AuraAuthority>,
+
+        /// Duration, in milliseconds, of an Aura slot.
+        slot_duration: NonZeroU64,
+    },
+
+    /// Chain is using the Babe consensus engine.
+    Babe {
+        /// Configuration for BABE, retrieved from the genesis block.
+        genesis_config: babe::BabeGenesisConfiguration,
+
+        /// If the number in [`ChainInformation::finalized_block_header`] is superior or equal to
+        /// 1, then this field must contain the slot number of the block whose number is 1 and is
+        /// an ancestor of the finalized block.
+        finalized_block1_slot_number: Option<u64>,
+
+        /// Babe epoch information about the epoch the finalized block belongs to.
+        ///
+        /// Must be `None` if and only if the finalized block is block #0 or belongs to epoch #0.
+        finalized_block_epoch_information: Option<(header::BabeNextEpoch, header::BabeNextConfig)>,
+
+        /// Babe epoch information about the epoch right after the one the finalized block
+        /// belongs to.
+        ///
+        /// Must be `None` if and only if the finalized block is block #0.
+        finalized_next_epoch_transition: Option<(header::BabeNextEpoch, header::BabeNextConfig)>,
+    },
+}
+
+impl<'a> From<ChainInformationConsensusRef<'a>> for ChainInformationConsensus {
+    fn from(info: ChainInformationConsensusRef<'a>) -> ChainInformationConsensus {
+        match info {
+            ChainInformationConsensusRef::Aura {
+                finalized_authorities_list,
+                slot_duration,
+            } => ChainInformationConsensus::Aura {
+                finalized_authorities_list: finalized_authorities_list.map(Into::into).collect(),
+                slot_duration,
+            },
+            ChainInformationConsensusRef::Babe {
+                genesis_config,
+                finalized_block1_slot_number,
+                finalized_block_epoch_information,
+                finalized_next_epoch_transition,
+            } => ChainInformationConsensus::Babe {
+                genesis_config: genesis_config.clone(),
+                finalized_block1_slot_number,
+                finalized_block_epoch_information: finalized_block_epoch_information
+                    .map(|(e, c)| (e.into(), c)),
+                finalized_next_epoch_transition: finalized_next_epoch_transition
+                    .map(|(e, c)| (e.into(), c)),
+            },
+        }
+    }
+}
+
+/// State of the GrandPa authorities with regards to pausing and resuming voting.
+///
+/// The transitions between these states are driven by the `Pause` and `Resume` GrandPa consensus
+/// log items found in block headers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GrandpaPauseState {
+    /// Authorities are voting normally.
+    Live,
+    /// Authorities will stop voting after the block whose height is given. Similar to
+    /// [`GrandpaPauseState::PendingResume`], the pause takes effect as soon as this block is
+    /// imported, in the fork it belongs to.
+    PendingPause {
+        /// Height of the last block that the authorities will finalize before pausing.
+        trigger_block_height: u64,
+    },
+    /// Authorities have stopped voting. No block can be finalized.
+    Paused,
+    /// Authorities will resume voting after the block whose height is given has been imported.
+    PendingResume {
+        /// Height of the block after which authorities resume voting.
+        trigger_block_height: u64,
+    },
+}
+
 /// Error when building the chain information from the genesis storage.
 #[derive(Debug, derive_more::Display)]
 pub enum FromGenesisStorageError {
+    /// Runtime couldn't be found in the genesis storage.
+    RuntimeNotFound,
+    /// Failed to decode heap pages from the genesis storage.
+    HeapPagesDecode(core::array::TryFromSliceError),
+    /// Error when initializing the virtual machine.
+    VmInitialization(executor::NewErr),
+    /// Failed to retrieve the version of the runtime.
+    CoreVersion,
     /// Error when retrieving the GrandPa configuration.
-    GrandpaConfigLoad(grandpa::chain_config::FromGenesisStorageError),
+    GrandpaConfigLoad(grandpa::chain_config::FromVmPrototypeError),
+    /// Error when retrieving the Aura configuration.
+    AuraConfigLoad(aura::FromVmPrototypeError),
+    /// Error when retrieving the Babe configuration.
+    BabeConfigLoad(babe::FromVmPrototypeError),
 }
 
 #[derive(Debug, Clone)]
@@ -167,39 +328,29 @@ pub struct ChainInformationRef<'a> {
     pub finalized_block_header: header::HeaderRef<'a>,
 
     /// See equivalent field in [`ChainInformation`].
-    pub babe_finalized_block1_slot_number: Option<u64>,
+    pub consensus: ChainInformationConsensusRef<'a>,
 
     /// See equivalent field in [`ChainInformation`].
-    pub babe_finalized_block_epoch_information:
-        Option<(header::BabeNextEpochRef<'a>, header::BabeNextConfig)>,
+    pub grandpa_after_finalized_block_authorities_set_id: u64,
 
     /// See equivalent field in [`ChainInformation`].
-    pub babe_finalized_next_epoch_transition:
-        Option<(header::BabeNextEpochRef<'a>, header::BabeNextConfig)>,
+    pub grandpa_finalized_triggered_authorities: &'a [header::GrandpaAuthority],
 
     /// See equivalent field in [`ChainInformation`].
-    pub grandpa_after_finalized_block_authorities_set_id: u64,
+    pub grandpa_finalized_scheduled_change: Option<(u64, &'a [header::GrandpaAuthority])>,
 
     /// See equivalent field in [`ChainInformation`].
-    pub grandpa_finalized_triggered_authorities: &'a [header::GrandpaAuthority],
+    pub grandpa_finalized_forced_change: Option<(u64, &'a [header::GrandpaAuthority])>,
 
     /// See equivalent field in [`ChainInformation`].
-    pub grandpa_finalized_scheduled_change: Option<(u64, &'a [header::GrandpaAuthority])>,
+    pub grandpa_finalized_pause_state: GrandpaPauseState,
 }
 
 impl<'a> From<&'a ChainInformation> for ChainInformationRef<'a> {
     fn from(info: &'a ChainInformation) -> ChainInformationRef<'a> {
         ChainInformationRef {
             finalized_block_header: (&info.finalized_block_header).into(),
-            babe_finalized_block1_slot_number: info.babe_finalized_block1_slot_number,
-            babe_finalized_block_epoch_information: info
-                .babe_finalized_block_epoch_information
-                .as_ref()
-                .map(|(i, c)| (i.into(), *c)),
-            babe_finalized_next_epoch_transition: info
-                .babe_finalized_next_epoch_transition
-                .as_ref()
-                .map(|(i, c)| (i.into(), *c)),
+            consensus: (&info.consensus).into(),
             grandpa_after_finalized_block_authorities_set_id: info
                 .grandpa_after_finalized_block_authorities_set_id,
             grandpa_finalized_triggered_authorities: &info.grandpa_finalized_triggered_authorities,
@@ -207,6 +358,69 @@ impl<'a> From<&'a ChainInformation> for ChainInformationRef<'a> {
                 .grandpa_finalized_scheduled_change
                 .as_ref()
                 .map(|(n, l)| (*n, &l[..])),
+            grandpa_finalized_forced_change: info
+                .grandpa_finalized_forced_change
+                .as_ref()
+                .map(|(n, l)| (*n, &l[..])),
+            grandpa_finalized_pause_state: info.grandpa_finalized_pause_state,
+        }
+    }
+}
+
+/// Equivalent to a [`ChainInformationConsensus`] but referencing an existing structure. Cheap to
+/// copy.
+#[derive(Debug, Clone)]
+pub enum ChainInformationConsensusRef<'a> {
+    /// See equivalent variant in [`ChainInformationConsensus`].
+    Aura {
+        /// See equivalent field in [`ChainInformationConsensus`].
+        finalized_authorities_list: header::AuraAuthoritiesIter<'a>,
+        /// See equivalent field in [`ChainInformationConsensus`].
+        slot_duration: NonZeroU64,
+    },
+
+    /// See equivalent variant in [`ChainInformationConsensus`].
+    Babe {
+        /// See equivalent field in [`ChainInformationConsensus`].
+        genesis_config: &'a babe::BabeGenesisConfiguration,
+        /// See equivalent field in [`ChainInformationConsensus`].
+        finalized_block1_slot_number: Option<u64>,
+        /// See equivalent field in [`ChainInformationConsensus`].
+        finalized_block_epoch_information:
+            Option<(header::BabeNextEpochRef<'a>, header::BabeNextConfig)>,
+        /// See equivalent field in [`ChainInformationConsensus`].
+        finalized_next_epoch_transition:
+            Option<(header::BabeNextEpochRef<'a>, header::BabeNextConfig)>,
+    },
+}
+
+impl<'a> From<&'a ChainInformationConsensus> for ChainInformationConsensusRef<'a> {
+    fn from(info: &'a ChainInformationConsensus) -> ChainInformationConsensusRef<'a> {
+        match info {
+            ChainInformationConsensus::Aura {
+                finalized_authorities_list,
+                slot_duration,
+            } => ChainInformationConsensusRef::Aura {
+                finalized_authorities_list: header::AuraAuthoritiesIter::from_slice(
+                    finalized_authorities_list,
+                ),
+                slot_duration: *slot_duration,
+            },
+            ChainInformationConsensus::Babe {
+                genesis_config,
+                finalized_block1_slot_number,
+                finalized_block_epoch_information,
+                finalized_next_epoch_transition,
+            } => ChainInformationConsensusRef::Babe {
+                genesis_config,
+                finalized_block1_slot_number: *finalized_block1_slot_number,
+                finalized_block_epoch_information: finalized_block_epoch_information
+                    .as_ref()
+                    .map(|(i, c)| (i.into(), *c)),
+                finalized_next_epoch_transition: finalized_next_epoch_transition
+                    .as_ref()
+                    .map(|(i, c)| (i.into(), *c)),
+            },
         }
     }
 }
@@ -217,8 +431,17 @@ pub struct ChainInformationConfig {
     /// Information about the latest finalized block.
     pub chain_information: ChainInformation,
 
-    /// Configuration for BABE, retrieved from the genesis block.
-    pub babe_genesis_config: babe::BabeGenesisConfiguration,
+    /// Hashes of blocks that are known to be invalid. A block whose hash is in this list is
+    /// always rejected, and none of its descendants can therefore be imported.
+    ///
+    /// Typically filled from the chain specification, but can also be extended manually for
+    /// example in order to blacklist a block during an incident.
+    pub bad_blocks: HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
+    /// For each block number in this map, the hash that the block with this number must have.
+    /// Blocks with this number but a different hash are rejected. Used in order to force the
+    /// chain to follow a specific fork.
+    pub fork_blocks: HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
 }
 
 impl ChainInformationConfig {
@@ -229,19 +452,10 @@ impl ChainInformationConfig {
     pub fn from_genesis_storage<'a>(
         genesis_storage: impl Iterator<Item = (&'a [u8], &'a [u8])> + Clone,
     ) -> Result<Self, FromGenesisStorageError> {
-        let babe_genesis_config = babe::BabeGenesisConfiguration::from_genesis_storage(|k| {
-            genesis_storage
-                .clone()
-                .find(|(k2, _)| *k2 == k)
-                .map(|(_, v)| v.to_owned())
-        })
-        .unwrap(); // TODO: no unwrap
-
-        let chain_information = ChainInformation::from_genesis_storage(genesis_storage)?;
-
         Ok(ChainInformationConfig {
-            chain_information,
-            babe_genesis_config,
+            chain_information: ChainInformation::from_genesis_storage(genesis_storage)?,
+            bad_blocks: Default::default(),
+            fork_blocks: Default::default(),
         })
     }
 }
@@ -0,0 +1,171 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{executor, header};
+
+use core::{convert::TryFrom as _, num::NonZeroU64};
+use parity_scale_codec::DecodeAll as _;
+
+/// Aura configuration of a chain, as extracted from the genesis block.
+///
+/// The way a chain configures Aura is stored in its runtime.
+#[derive(Debug, Clone)]
+pub struct AuraGenesisConfiguration {
+    /// List of authorities that can validate block #1.
+    pub authorities_list: Vec<header::AuraAuthority>,
+
+    /// Duration, in milliseconds, of each slot.
+    pub slot_duration: NonZeroU64,
+}
+
+impl AuraGenesisConfiguration {
+    /// Retrieves the configuration from the storage of the genesis block.
+    ///
+    /// Must be passed a closure that returns the storage value corresponding to the given key in
+    /// the genesis block storage.
+    pub fn from_genesis_storage(
+        mut genesis_storage_access: impl FnMut(&[u8]) -> Option<Vec<u8>>,
+    ) -> Result<Self, FromGenesisStorageError> {
+        let wasm_code =
+            genesis_storage_access(b":code").ok_or(FromGenesisStorageError::RuntimeNotFound)?;
+        let heap_pages = if let Some(bytes) = genesis_storage_access(b":heappages") {
+            u64::from_le_bytes(
+                <[u8; 8]>::try_from(&bytes[..])
+                    .map_err(FromGenesisStorageError::HeapPagesDecode)?,
+            )
+        } else {
+            1024 // TODO: default heap pages
+        };
+        let vm = executor::WasmVmPrototype::new(&wasm_code, heap_pages)
+            .map_err(FromVmPrototypeError::VmInitialization)
+            .map_err(FromGenesisStorageError::VmError)?;
+        let (cfg, _) = Self::from_virtual_machine_prototype(vm, genesis_storage_access)
+            .map_err(FromGenesisStorageError::VmError)?;
+        Ok(cfg)
+    }
+
+    /// Retrieves the configuration from the given virtual machine prototype.
+    ///
+    /// Must be passed a closure that returns the storage value corresponding to the given key in
+    /// the genesis block storage.
+    ///
+    /// Returns back the same virtual machine prototype as was passed as parameter.
+    ///
+    /// Returns [`FromVmPrototypeError::NotAuraChain`] if the runtime doesn't implement the
+    /// Aura runtime API.
+    pub fn from_virtual_machine_prototype(
+        vm: executor::WasmVmPrototype,
+        mut genesis_storage_access: impl FnMut(&[u8]) -> Option<Vec<u8>>,
+    ) -> Result<(Self, executor::WasmVmPrototype), FromVmPrototypeError> {
+        // The list of runtime APIs that the runtime implements is found in its version. Each API
+        // is identified by the blake2 64bits hash of its name.
+        let (version, vm) =
+            executor::core_version(vm).map_err(|()| FromVmPrototypeError::CoreVersion)?;
+        let aura_api_id = blake2_rfc::blake2b::blake2b(8, &[], b"AuraApi");
+        if !version
+            .apis
+            .iter()
+            .any(|(id, _)| id[..] == aura_api_id.as_bytes()[..])
+        {
+            return Err(FromVmPrototypeError::NotAuraChain);
+        }
+
+        let (slot_duration, vm) = run_vm(vm, "AuraApi_slot_duration", &mut genesis_storage_access)?;
+        let slot_duration = NonZeroU64::new(
+            u64::decode_all(&slot_duration).map_err(FromVmPrototypeError::OutputDecode)?,
+        )
+        .ok_or(FromVmPrototypeError::NullSlotDuration)?;
+
+        let (authorities_list, vm) =
+            run_vm(vm, "AuraApi_authorities", &mut genesis_storage_access)?;
+        let authorities_list = header::AuraAuthoritiesIter::decode(&authorities_list)
+            .map_err(FromVmPrototypeError::AuthoritiesListDecode)?
+            .map(header::AuraAuthority::from)
+            .collect();
+
+        let outcome = AuraGenesisConfiguration {
+            authorities_list,
+            slot_duration,
+        };
+
+        Ok((outcome, vm))
+    }
+}
+
+/// Calls the given runtime function, which doesn't take any parameter, and returns its output.
+fn run_vm(
+    vm: executor::WasmVmPrototype,
+    function_to_call: &str,
+    genesis_storage_access: &mut impl FnMut(&[u8]) -> Option<Vec<u8>>,
+) -> Result<(Vec<u8>, executor::WasmVmPrototype), FromVmPrototypeError> {
+    let mut vm: executor::WasmVm = vm
+        .run_no_param(function_to_call)
+        .map_err(FromVmPrototypeError::VmInitialization)?
+        .into();
+
+    loop {
+        match vm {
+            executor::WasmVm::ReadyToRun(r) => vm = r.run(),
+            executor::WasmVm::Finished(finished) => {
+                let output = finished.value().to_vec();
+                break Ok((output, finished.into_prototype()));
+            }
+            executor::WasmVm::Error { .. } => return Err(FromVmPrototypeError::Trapped),
+
+            executor::WasmVm::ExternalStorageGet(req) => {
+                let value = genesis_storage_access(req.key());
+                vm = req.resume_full_value(value.as_ref().map(|v| &v[..]));
+            }
+
+            executor::WasmVm::LogEmit(req) => vm = req.resume(),
+
+            _ => return Err(FromVmPrototypeError::ExternalityNotAllowed),
+        }
+    }
+}
+
+/// Error when retrieving the Aura configuration.
+#[derive(Debug, derive_more::Display)]
+pub enum FromGenesisStorageError {
+    /// Runtime couldn't be found in the genesis storage.
+    RuntimeNotFound,
+    /// Failed to decode heap pages from the genesis storage.
+    HeapPagesDecode(core::array::TryFromSliceError),
+    /// Error while executing the runtime.
+    VmError(FromVmPrototypeError),
+}
+
+/// Error when retrieving the Aura configuration.
+#[derive(Debug, derive_more::Display)]
+pub enum FromVmPrototypeError {
+    /// Error when initializing the virtual machine.
+    VmInitialization(executor::NewErr),
+    /// Failed to retrieve the version of the runtime.
+    CoreVersion,
+    /// Runtime doesn't implement the Aura runtime API.
+    NotAuraChain,
+    /// Crash while running the virtual machine.
+    Trapped,
+    /// Virtual machine tried to call an externality that isn't valid in this context.
+    ExternalityNotAllowed,
+    /// Error while decoding the output of the virtual machine.
+    OutputDecode(parity_scale_codec::Error),
+    /// Error while decoding the list of authorities returned by the virtual machine.
+    AuthoritiesListDecode(header::Error),
+    /// The slot duration returned by the runtime is 0.
+    NullSlotDuration,
+}
@@ -112,11 +112,53 @@ impl BabeGenesisConfiguration {
         Ok((outcome, vm_prototype))
     }
 
+    /// Builds a configuration from its individual components.
+    ///
+    /// The configuration is normally retrieved from the runtime of the genesis block. This
+    /// function is meant for chains whose runtime isn't available, such as in tests and
+    /// benchmarks.
+    pub fn from_components(
+        slot_duration: u64,
+        epoch_length: u64,
+        c: (u64, u64),
+        genesis_authorities: Vec<([u8; 32], u64)>,
+        randomness: [u8; 32],
+        allowed_slots: header::BabeAllowedSlots,
+    ) -> Self {
+        let epoch0_information = header::BabeNextEpoch {
+            randomness,
+            authorities: genesis_authorities
+                .iter()
+                .map(|(public_key, weight)| header::BabeAuthority {
+                    public_key: *public_key,
+                    weight: *weight,
+                })
+                .collect(),
+        };
+
+        BabeGenesisConfiguration {
+            inner: OwnedGenesisConfiguration {
+                slot_duration,
+                epoch_length,
+                c,
+                genesis_authorities,
+                randomness,
+                allowed_slots,
+            },
+            epoch0_information,
+        }
+    }
+
     /// Returns the number of slots contained in each epoch.
     pub fn slots_per_epoch(&self) -> u64 {
         self.inner.epoch_length
     }
 
+    /// Returns the duration of a slot, in milliseconds.
+    pub fn slot_duration(&self) -> u64 {
+        self.inner.slot_duration
+    }
+
     /// Returns the configuration of epoch number 0.
     pub fn epoch0_configuration(&self) -> header::BabeNextConfig {
         header::BabeNextConfig {
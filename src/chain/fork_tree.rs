@@ -126,6 +126,11 @@ impl<T> ForkTree<T> {
         self.nodes.iter().map(|n| &n.1.data)
     }
 
+    /// Returns an iterator to all the node indices and values without any specific order.
+    pub fn iter_with_indices(&self) -> impl Iterator<Item = (NodeIndex, &T)> {
+        self.nodes.iter().map(|(i, n)| (NodeIndex(i), &n.data))
+    }
+
     /// Returns the value of the node with the given index.
     pub fn get(&self, index: NodeIndex) -> Option<&T> {
         self.nodes.get(index.0).map(|n| &n.data)
@@ -136,6 +141,17 @@ impl<T> ForkTree<T> {
         self.nodes.get_mut(index.0).map(|n| &mut n.data)
     }
 
+    /// Returns the index of the parent of the node with the given index, or `None` if the node
+    /// is a root.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`NodeIndex`] is invalid.
+    ///
+    pub fn parent(&self, index: NodeIndex) -> Option<NodeIndex> {
+        self.nodes[index.0].parent.map(NodeIndex)
+    }
+
     /// Removes from the tree:
     ///
     /// - The node passed as parameter.
@@ -514,6 +530,10 @@ mod tests {
         assert_eq!(tree.find(|v| *v == 4), Some(node4));
         assert_eq!(tree.find(|v| *v == 5), Some(node5));
 
+        assert_eq!(tree.parent(node0), None);
+        assert_eq!(tree.parent(node3), Some(node2));
+        assert_eq!(tree.parent(node5), Some(node0));
+
         assert_eq!(
             tree.node_to_root_path(node3).collect::<Vec<_>>(),
             &[node3, node2, node1, node0]
@@ -44,12 +44,20 @@
 //! context of a peer-to-peer network, it is important to establish outgoing connections to other
 //! nodes and not only rely on incoming connections, as there is otherwise the possibility of a
 //! single actor controlling all said incoming connections.
+//!
+//! # Chain events
+//!
+//! The syncing state machines report the changes to the best and finalized blocks of the local
+//! chain as [`ChainEvent`](super::blocks_tree::ChainEvent)s, retrieved with their
+//! `next_chain_event` method.
+//!
+//! Events are buffered. When events are generated, `next_chain_event` must be called regularly,
+//! for example after every call to `process_one`, in order to avoid this buffer growing forever.
 
-// TODO: this doc ^ is correct but assumes that there exist multiple syncing strategies, while at
-//       the time of writing only one is implemented
-
-// TODO: pub mod full_all_forks;
+pub mod all_forks;
 pub mod full_optimistic;
 pub mod headers_optimistic;
+pub mod light;
 // TODO: maybe shouldn't be pub, but creates doc-link errors if private
 pub mod optimistic;
+pub mod reputation;
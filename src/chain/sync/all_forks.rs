@@ -0,0 +1,1223 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! *All forks* headers-only syncing.
+//!
+//! Contrary to the optimistic syncing strategies, which only download a single chain starting
+//! from the finalized block, the [`AllForksSync`] tracks every block that sources announce,
+//! including blocks belonging to forks that aren't the best chain.
+//!
+//! This strategy is meant to be used once the local chain is close to the head of the chain,
+//! typically after an optimistic syncing has finished. Downloading a long chain of blocks with
+//! this strategy is possible but inefficient.
+//!
+//! # Usage
+//!
+//! Whenever a source announces a block (see
+//! [`network::worker::Event::BlockAnnounce`](crate::network::worker::Event::BlockAnnounce)),
+//! call [`AllForksSync::block_announce`]. If the parent of the announced block isn't known
+//! locally, the [`AllForksSync`] will request, through [`AllForksSync::next_request_action`],
+//! the missing ancestors from the sources that are known to have them.
+//!
+//! Blocks whose parent is known are verified and inserted in the local
+//! [`NonFinalizedTree`](blocks_tree::NonFinalizedTree) when calling
+//! [`AllForksSync::process_one`].
+//!
+//! When a request fails, no other request is sent to the same source for a certain amount of
+//! time, which doubles after each consecutive failure.
+//!
+//! > **Note**: This strategy is experimental. It isn't used by the full node or the browser node
+//! >           yet, which rely on the optimistic syncing strategies.
+
+use super::super::{blocks_tree, chain_information};
+use super::reputation;
+use crate::header;
+
+use alloc::{vec, vec::Vec};
+use core::{
+    convert::TryFrom as _, fmt, iter, marker::PhantomData, num::NonZeroU32, time::Duration,
+};
+use hashbrown::HashMap;
+
+/// Time during which no request is sent to a source after one of its requests has failed.
+/// Doubled after each consecutive failure, up to [`MAX_REQUEST_BACKOFF`].
+const INITIAL_REQUEST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Maximum time during which no request is sent to a source after one of its requests has
+/// failed.
+const MAX_REQUEST_BACKOFF: Duration = Duration::from_secs(64);
+
+/// Configuration for the [`AllForksSync`].
+#[derive(Debug)]
+pub struct Config {
+    /// Information about the latest finalized block and its ancestors.
+    pub chain_information_config: chain_information::ChainInformationConfig,
+
+    /// Pre-allocated capacity for the number of block sources.
+    pub sources_capacity: usize,
+
+    /// Pre-allocated capacity for the number of non-finalized blocks.
+    pub blocks_capacity: usize,
+
+    /// Maximum number of blocks whose ancestry is unknown or not verified yet. Announced or
+    /// downloaded blocks are discarded if this limit is reached.
+    ///
+    /// This value protects against sources announcing a large number of fake blocks.
+    pub max_disjoint_headers: usize,
+
+    /// Maximum number of blocks returned by a response.
+    ///
+    /// > **Note**: If blocks are requested from the network, this should match the network
+    /// >           protocol enforced limit.
+    pub blocks_request_granularity: NonZeroU32,
+}
+
+/// *All forks* headers-only syncing. See [the module-level documentation](self).
+pub struct AllForksSync<TRq, TSrc> {
+    /// Chain containing the verified blocks.
+    chain: blocks_tree::NonFinalizedTree<()>,
+
+    /// List of sources of blocks.
+    sources: slab::Slab<Source<TSrc>>,
+
+    /// Blocks that have been announced or downloaded but haven't been inserted in
+    /// [`AllForksSync::chain`] yet, indexed by their hash.
+    disjoint_headers: HashMap<[u8; 32], DisjointBlock, fnv::FnvBuildHasher>,
+
+    /// Blocks that have failed to verify, and the blocks descending from them, indexed by hash.
+    /// Contains the number of each block and the misbehaviour of the sources that have provided
+    /// it. Entries whose number is inferior or equal to the one of the finalized block are
+    /// removed.
+    ///
+    /// Sources that announce or provide one of these blocks are punished.
+    bad_blocks: HashMap<[u8; 32], (u64, reputation::Misbehaviour), fnv::FnvBuildHasher>,
+
+    /// List of requests in progress.
+    requests: slab::Slab<Request<TRq>>,
+
+    /// See [`Config::max_disjoint_headers`].
+    max_disjoint_headers: usize,
+
+    /// See [`Config::blocks_request_granularity`].
+    blocks_request_granularity: NonZeroU32,
+}
+
+struct Source<TSrc> {
+    user_data: TSrc,
+    /// Number of the best block of this source, as reported by the source.
+    best_block_number: u64,
+    /// Hash of the best block of this source, as reported by the source.
+    best_block_hash: [u8; 32],
+    /// Reputation of the source. Requests are only sent to sources whose verdict is
+    /// [`reputation::Verdict::Keep`].
+    reputation: reputation::Reputation,
+    /// Number of requests sent to this source that have failed in a row.
+    num_consecutive_failures: u32,
+    /// If `Some`, no request is sent to this source before this time, expressed as a duration
+    /// since the Unix Epoch.
+    backoff_until: Option<Duration>,
+}
+
+impl<TSrc> Source<TSrc> {
+    /// Returns true if a request can be sent to this source at the given time.
+    fn can_request(&self, now_from_unix_epoch: Duration) -> bool {
+        self.reputation.verdict() == reputation::Verdict::Keep
+            && !matches!(self.backoff_until, Some(until) if until > now_from_unix_epoch)
+    }
+}
+
+struct DisjointBlock {
+    /// SCALE-encoded header of the block.
+    scale_encoded_header: Vec<u8>,
+    /// Number of the block. Cache of the value in the header.
+    number: u64,
+    /// Hash of the parent of the block. Cache of the value in the header.
+    parent_hash: [u8; 32],
+    /// SCALE-encoded justification of this block, if any.
+    scale_encoded_justification: Option<Vec<u8>>,
+    /// Index within [`AllForksSync::sources`] of the source that has provided
+    /// [`DisjointBlock::scale_encoded_justification`], if it is still connected.
+    justification_source: Option<usize>,
+    /// Indices within [`AllForksSync::sources`] of the sources known to have this block.
+    known_by: Vec<usize>,
+}
+
+struct Request<TRq> {
+    /// Index within [`AllForksSync::sources`] of the source the request is sent to.
+    source: usize,
+    /// Hash of the first block requested. The other blocks are its ancestors.
+    first_block_hash: [u8; 32],
+    /// User-chosen data for this request.
+    user_data: TRq,
+}
+
+impl<TRq, TSrc> AllForksSync<TRq, TSrc> {
+    /// Builds a new [`AllForksSync`].
+    pub fn new(config: Config) -> Self {
+        let fork_choice = blocks_tree::ForkChoice::from_consensus(
+            &config.chain_information_config.chain_information.consensus,
+        );
+
+        AllForksSync {
+            chain: blocks_tree::NonFinalizedTree::new(blocks_tree::Config {
+                chain_information_config: config.chain_information_config,
+                blocks_capacity: config.blocks_capacity,
+                fork_choice,
+                generate_events: true,
+            }),
+            sources: slab::Slab::with_capacity(config.sources_capacity),
+            disjoint_headers: HashMap::with_capacity_and_hasher(
+                config.max_disjoint_headers,
+                Default::default(),
+            ),
+            bad_blocks: HashMap::default(),
+            requests: slab::Slab::with_capacity(config.sources_capacity),
+            max_disjoint_headers: config.max_disjoint_headers,
+            blocks_request_granularity: config.blocks_request_granularity,
+        }
+    }
+
+    /// Builds a [`chain_information::ChainInformationRef`] struct corresponding to the current
+    /// latest finalized block. Can later be used to reconstruct a chain.
+    pub fn as_chain_information(&self) -> chain_information::ChainInformationRef {
+        self.chain.as_chain_information()
+    }
+
+    /// Returns the header of the finalized block.
+    pub fn finalized_block_header(&self) -> header::HeaderRef {
+        self.chain.finalized_block_header()
+    }
+
+    /// Returns the header of the best block.
+    pub fn best_block_header(&self) -> header::HeaderRef {
+        self.chain.best_block_header()
+    }
+
+    /// Returns the number of the best block.
+    pub fn best_block_number(&self) -> u64 {
+        self.chain.best_block_header().number
+    }
+
+    /// Returns the hash of the best block.
+    pub fn best_block_hash(&self) -> [u8; 32] {
+        self.chain.best_block_hash()
+    }
+
+    /// Returns the next event that happened on the chain, if any. See also
+    /// [the documentation of the `sync` module](super#chain-events).
+    pub fn next_chain_event(&mut self) -> Option<blocks_tree::ChainEvent> {
+        self.chain.next_event()
+    }
+
+    /// Returns the number of blocks that have been announced or downloaded but not inserted in
+    /// the chain yet.
+    pub fn num_disjoint_blocks(&self) -> usize {
+        self.disjoint_headers.len()
+    }
+
+    /// Inform the [`AllForksSync`] of a new potential source of blocks.
+    ///
+    /// The `best_block_number` and `best_block_hash` are the values reported by the source, for
+    /// example in its handshake. If the best block of the source isn't known locally, it will
+    /// later be requested.
+    pub fn add_source(
+        &mut self,
+        source: TSrc,
+        best_block_number: u64,
+        best_block_hash: [u8; 32],
+    ) -> SourceId {
+        SourceId(self.sources.insert(Source {
+            user_data: source,
+            best_block_number,
+            best_block_hash,
+            reputation: reputation::Reputation::new(),
+            num_consecutive_failures: 0,
+            backoff_until: None,
+        }))
+    }
+
+    /// Inform the [`AllForksSync`] that a source of blocks is no longer available.
+    ///
+    /// This automatically cancels all the requests that have been emitted for this source.
+    /// This list of requests is returned as part of this function.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn remove_source(
+        &mut self,
+        source_id: SourceId,
+    ) -> (TSrc, impl Iterator<Item = (RequestId, TRq)>) {
+        let source = self.sources.remove(source_id.0);
+
+        for block in self.disjoint_headers.values_mut() {
+            block.known_by.retain(|s| *s != source_id.0);
+            if block.justification_source == Some(source_id.0) {
+                block.justification_source = None;
+            }
+        }
+
+        let to_remove = self
+            .requests
+            .iter()
+            .filter(|(_, rq)| rq.source == source_id.0)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        let requests = to_remove
+            .into_iter()
+            .map(|id| (RequestId(id), self.requests.remove(id).user_data))
+            .collect::<Vec<_>>();
+
+        (source.user_data, requests.into_iter())
+    }
+
+    /// Returns the user data of the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_user_data_mut(&mut self, source_id: SourceId) -> &mut TSrc {
+        &mut self.sources[source_id.0].user_data
+    }
+
+    /// Returns the reputation of the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_reputation(&self, source_id: SourceId) -> &reputation::Reputation {
+        &self.sources[source_id.0].reputation
+    }
+
+    /// Returns the number and hash of the best block of the given source, as reported by the
+    /// source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_best_block(&self, source_id: SourceId) -> (u64, [u8; 32]) {
+        let source = &self.sources[source_id.0];
+        (source.best_block_number, source.best_block_hash)
+    }
+
+    /// Inform the [`AllForksSync`] that a source has announced a block.
+    ///
+    /// `is_best` must be true if the source indicates that this block is its new best block.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn block_announce(
+        &mut self,
+        source_id: SourceId,
+        scale_encoded_header: Vec<u8>,
+        is_best: bool,
+    ) -> BlockAnnounceOutcome {
+        let decoded = match header::decode(&scale_encoded_header) {
+            Ok(h) => h,
+            Err(err) => return BlockAnnounceOutcome::InvalidHeader(err),
+        };
+
+        let hash = header::hash_from_scale_encoded_header(&scale_encoded_header);
+
+        if is_best {
+            let source = &mut self.sources[source_id.0];
+            source.best_block_number = decoded.number;
+            source.best_block_hash = hash;
+        }
+
+        if decoded.number <= self.chain.finalized_block_header().number {
+            return BlockAnnounceOutcome::TooOld;
+        }
+
+        if self.chain.contains_non_finalized_block(&hash) {
+            return BlockAnnounceOutcome::AlreadyInChain;
+        }
+
+        // Blocks whose parent is known to be bad are bad as well.
+        if let Some(&(_, misbehaviour)) = self
+            .bad_blocks
+            .get(&hash)
+            .or_else(|| self.bad_blocks.get(decoded.parent_hash))
+        {
+            if self.bad_blocks.len() < self.max_disjoint_headers {
+                self.bad_blocks.insert(hash, (decoded.number, misbehaviour));
+            }
+            let punishment = self
+                .punish_sources(iter::once(source_id.0), misbehaviour)
+                .remove(0);
+            return BlockAnnounceOutcome::BadBlock(punishment);
+        }
+
+        if let Some(block) = self.disjoint_headers.get_mut(&hash) {
+            if !block.known_by.contains(&source_id.0) {
+                block.known_by.push(source_id.0);
+            }
+            return BlockAnnounceOutcome::Queued;
+        }
+
+        if self.disjoint_headers.len() >= self.max_disjoint_headers {
+            return BlockAnnounceOutcome::QueueFull;
+        }
+
+        let number = decoded.number;
+        let parent_hash = *decoded.parent_hash;
+        self.disjoint_headers.insert(
+            hash,
+            DisjointBlock {
+                scale_encoded_header,
+                number,
+                parent_hash,
+                scale_encoded_justification: None,
+                justification_source: None,
+                known_by: vec![source_id.0],
+            },
+        );
+
+        BlockAnnounceOutcome::Queued
+    }
+
+    /// Returns the next request that should be started, if any.
+    ///
+    /// Requests target blocks whose existence is known, because they are the parent of an
+    /// announced block or the best block of a source, but whose header isn't known.
+    ///
+    /// Must be passed the time elapsed since the Unix Epoch. Sources whose latest request has
+    /// failed recently aren't picked.
+    pub fn next_request_action(
+        &mut self,
+        now_from_unix_epoch: Duration,
+    ) -> Option<RequestAction<'_, TRq, TSrc>> {
+        let finalized_number = self.chain.finalized_block_header().number;
+
+        // List of `(block_hash, block_number, source)` of the blocks to potentially request.
+        // The parents of disjoint blocks are tried first, as they are necessary in order to make
+        // progress, followed with the best blocks of the sources.
+        let candidates = self
+            .disjoint_headers
+            .values()
+            .filter_map(|block| {
+                let source = *block
+                    .known_by
+                    .iter()
+                    .find(|s| self.sources[**s].can_request(now_from_unix_epoch))?;
+                Some((block.parent_hash, block.number - 1, source))
+            })
+            .chain(
+                self.sources
+                    .iter()
+                    .filter(|(_, src)| src.can_request(now_from_unix_epoch))
+                    .map(|(idx, src)| (src.best_block_hash, src.best_block_number, idx)),
+            );
+
+        let mut selected = None;
+        for (hash, number, source) in candidates {
+            if number <= finalized_number
+                || self.bad_blocks.contains_key(&hash)
+                || self.disjoint_headers.contains_key(&hash)
+                || self.chain.contains_non_finalized_block(&hash)
+                || self
+                    .requests
+                    .iter()
+                    .any(|(_, rq)| rq.first_block_hash == hash)
+            {
+                continue;
+            }
+
+            selected = Some((hash, number, source));
+            break;
+        }
+
+        let (first_block_hash, first_block_number, source) = selected?;
+
+        // Request the block and as many of its ancestors as possible, down to the block right
+        // after the finalized block.
+        let num_blocks = NonZeroU32::new(
+            u32::try_from(first_block_number - finalized_number)
+                .unwrap_or(u32::max_value())
+                .min(self.blocks_request_granularity.get()),
+        )
+        .unwrap();
+
+        Some(RequestAction::Start {
+            source_id: SourceId(source),
+            source: &mut self.sources[source].user_data,
+            first_block_hash,
+            first_block_number,
+            num_blocks,
+            start: Start {
+                requests: &mut self.requests,
+                source,
+                first_block_hash,
+                marker: PhantomData,
+            },
+        })
+    }
+
+    /// Update the [`AllForksSync`] with the outcome of a request.
+    ///
+    /// The blocks must be ordered by decreasing block number, the first block being the one
+    /// designated by the request.
+    ///
+    /// Must be passed the time elapsed since the Unix Epoch, from which the time during which no
+    /// request is sent to the source is counted if the request has failed.
+    ///
+    /// Returns the user data that was associated to that request.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`RequestId`] is invalid.
+    ///
+    pub fn finish_request(
+        &mut self,
+        request_id: RequestId,
+        outcome: Result<impl Iterator<Item = RequestSuccessBlock>, RequestFail>,
+        now_from_unix_epoch: Duration,
+    ) -> (TRq, FinishRequestOutcome<TSrc>) {
+        let request = self.requests.remove(request_id.0);
+
+        let blocks = match outcome {
+            Ok(blocks) => blocks,
+            Err(err) => {
+                return (
+                    request.user_data,
+                    self.punish_request_source(
+                        request.source,
+                        now_from_unix_epoch,
+                        err.misbehaviour(),
+                    ),
+                )
+            }
+        };
+
+        let finalized_number = self.chain.finalized_block_header().number;
+        let mut expected_hash = request.first_block_hash;
+        let mut num_blocks = 0;
+
+        for block in blocks {
+            // Each block must be the parent of the previous one. A source that sends back
+            // unrelated or undecodable blocks is punished.
+            let hash = header::hash_from_scale_encoded_header(&block.scale_encoded_header);
+            let decoded = match header::decode(&block.scale_encoded_header) {
+                Ok(h) if hash == expected_hash => h,
+                _ => {
+                    return (
+                        request.user_data,
+                        self.punish_request_source(
+                            request.source,
+                            now_from_unix_epoch,
+                            reputation::Misbehaviour::InvalidResponse,
+                        ),
+                    );
+                }
+            };
+
+            num_blocks += 1;
+
+            // A source that provides a block known to be bad is punished.
+            if let Some(&(_, misbehaviour)) = self.bad_blocks.get(&hash) {
+                return (
+                    request.user_data,
+                    self.punish_request_source(request.source, now_from_unix_epoch, misbehaviour),
+                );
+            }
+
+            // Stop once a block already known is reached.
+            if decoded.number <= finalized_number || self.chain.contains_non_finalized_block(&hash)
+            {
+                break;
+            }
+
+            let number = decoded.number;
+            let parent_hash = *decoded.parent_hash;
+            expected_hash = parent_hash;
+
+            if let Some(existing) = self.disjoint_headers.get_mut(&hash) {
+                if existing.scale_encoded_justification.is_none()
+                    && block.scale_encoded_justification.is_some()
+                {
+                    existing.scale_encoded_justification = block.scale_encoded_justification;
+                    existing.justification_source = Some(request.source);
+                }
+                if !existing.known_by.contains(&request.source) {
+                    existing.known_by.push(request.source);
+                }
+                continue;
+            }
+
+            if self.disjoint_headers.len() >= self.max_disjoint_headers {
+                break;
+            }
+
+            self.disjoint_headers.insert(
+                hash,
+                DisjointBlock {
+                    scale_encoded_header: block.scale_encoded_header,
+                    number,
+                    parent_hash,
+                    justification_source: block
+                        .scale_encoded_justification
+                        .as_ref()
+                        .map(|_| request.source),
+                    scale_encoded_justification: block.scale_encoded_justification,
+                    known_by: vec![request.source],
+                },
+            );
+        }
+
+        if num_blocks == 0 {
+            return (
+                request.user_data,
+                self.punish_request_source(
+                    request.source,
+                    now_from_unix_epoch,
+                    reputation::Misbehaviour::EmptyResponse,
+                ),
+            );
+        }
+
+        let source = &mut self.sources[request.source];
+        source.num_consecutive_failures = 0;
+        source.backoff_until = None;
+
+        (request.user_data, FinishRequestOutcome::Queued)
+    }
+
+    /// Lowers the reputation of the source with the given index following a failed request,
+    /// prevents any request from being sent to it for a while, and builds the corresponding
+    /// [`FinishRequestOutcome`].
+    fn punish_request_source(
+        &mut self,
+        source_index: usize,
+        now_from_unix_epoch: Duration,
+        misbehaviour: reputation::Misbehaviour,
+    ) -> FinishRequestOutcome<TSrc> {
+        let source = &mut self.sources[source_index];
+        let verdict = source.reputation.punish(misbehaviour);
+
+        let backoff = INITIAL_REQUEST_BACKOFF
+            .checked_mul(1 << source.num_consecutive_failures.min(16))
+            .map_or(MAX_REQUEST_BACKOFF, |b| b.min(MAX_REQUEST_BACKOFF));
+        source.num_consecutive_failures = source.num_consecutive_failures.saturating_add(1);
+        source.backoff_until = Some(now_from_unix_epoch + backoff);
+
+        FinishRequestOutcome::SourcePunished {
+            source: &mut source.user_data,
+            punishment: SourcePunishment {
+                source_id: SourceId(source_index),
+                misbehaviour,
+                verdict,
+            },
+        }
+    }
+
+    /// Lowers the reputation of the sources with the given indices.
+    fn punish_sources(
+        &mut self,
+        sources: impl Iterator<Item = usize>,
+        misbehaviour: reputation::Misbehaviour,
+    ) -> Vec<SourcePunishment> {
+        sources
+            .map(|source_index| SourcePunishment {
+                source_id: SourceId(source_index),
+                misbehaviour,
+                verdict: self.sources[source_index].reputation.punish(misbehaviour),
+            })
+            .collect()
+    }
+
+    /// Verifies and inserts in the chain one block whose parent is already in the chain.
+    ///
+    /// It is encouraged to call this method multiple times in a row until
+    /// [`ProcessOneOutcome::Idle`] is returned, interleaving any necessary high-priority
+    /// operations (e.g. processing network sockets) in-between two calls.
+    ///
+    /// Must be passed the time elapsed since the Unix Epoch, in order to reject blocks whose
+    /// slot is in the future and to update the reputation of the sources.
+    pub fn process_one(&mut self, now_from_unix_epoch: Duration) -> ProcessOneOutcome {
+        for (_, source) in self.sources.iter_mut() {
+            source.reputation.decay(now_from_unix_epoch);
+        }
+
+        let finalized_hash = self.chain.finalized_block_hash();
+
+        // Find a block whose parent is in the chain. Lower blocks are processed first, so that
+        // the forks are built in order.
+        let to_process = self
+            .disjoint_headers
+            .iter()
+            .filter(|(_, b)| {
+                b.parent_hash == finalized_hash
+                    || self.chain.contains_non_finalized_block(&b.parent_hash)
+            })
+            .min_by_key(|(_, b)| b.number)
+            .map(|(h, _)| *h);
+
+        let hash = match to_process {
+            Some(h) => h,
+            None => return ProcessOneOutcome::Idle,
+        };
+
+        let block = self.disjoint_headers.remove(&hash).unwrap();
+
+        let (is_new_best, number) = match self
+            .chain
+            .verify_header(block.scale_encoded_header, now_from_unix_epoch)
+        {
+            Ok(blocks_tree::HeaderVerifySuccess::Insert {
+                block_height,
+                is_new_best,
+                insert,
+            }) => {
+                insert.insert(());
+                (is_new_best, block_height)
+            }
+            Ok(blocks_tree::HeaderVerifySuccess::Duplicate) => (false, block.number),
+            Err(error) => {
+                // All the descendants of an invalid block are invalid as well.
+                let descendants = self.remove_disjoint_descendants(&hash);
+                // Every source that has announced or provided the block is at fault.
+                let punished_sources = match reputation::header_verify_misbehaviour(&error) {
+                    Some(misbehaviour) => {
+                        // The block is remembered, so that sources announcing it again are
+                        // punished as well. Errors that can't be attributed to the sources, such
+                        // as a slot in the future, might not be permanent.
+                        self.bad_blocks.insert(hash, (block.number, misbehaviour));
+                        self.bad_blocks.extend(
+                            descendants
+                                .into_iter()
+                                .map(|(hash, number)| (hash, (number, misbehaviour))),
+                        );
+                        self.punish_sources(block.known_by.iter().copied(), misbehaviour)
+                    }
+                    None => Vec::new(),
+                };
+                return ProcessOneOutcome::HeaderVerifyError {
+                    hash,
+                    number: block.number,
+                    error,
+                    punished_sources,
+                };
+            }
+        };
+
+        let mut finalized_block = None;
+        let mut justification_error = None;
+        let mut punished_sources = Vec::new();
+        if let Some(justification) = block.scale_encoded_justification {
+            match self.chain.verify_justification(&justification) {
+                Ok(apply) => {
+                    drop(apply.apply());
+                    finalized_block = Some((
+                        self.chain.finalized_block_header().number,
+                        self.chain.finalized_block_hash(),
+                    ));
+                }
+                Err(err) => {
+                    if let Some(misbehaviour) = reputation::justification_verify_misbehaviour(&err)
+                    {
+                        punished_sources = self
+                            .punish_sources(block.justification_source.into_iter(), misbehaviour);
+                    }
+                    justification_error = Some(err);
+                }
+            }
+        }
+
+        // Blocks that are no longer descendants of the finalized block can never be inserted.
+        if finalized_block.is_some() {
+            let finalized_number = self.chain.finalized_block_header().number;
+            self.disjoint_headers
+                .retain(|_, b| b.number > finalized_number);
+            self.bad_blocks.retain(|_, (n, _)| *n > finalized_number);
+        }
+
+        ProcessOneOutcome::HeaderVerified {
+            hash,
+            number,
+            is_new_best,
+            finalized_block,
+            justification_error,
+            punished_sources,
+        }
+    }
+
+    /// Removes from [`AllForksSync::disjoint_headers`] all the blocks that descend from the
+    /// given block. Returns the hashes and numbers of the removed blocks.
+    fn remove_disjoint_descendants(&mut self, hash: &[u8; 32]) -> Vec<([u8; 32], u64)> {
+        let mut removed = Vec::new();
+        let mut to_remove = vec![*hash];
+        while let Some(parent) = to_remove.pop() {
+            let children = self
+                .disjoint_headers
+                .iter()
+                .filter(|(_, b)| b.parent_hash == parent)
+                .map(|(h, _)| *h)
+                .collect::<Vec<_>>();
+            for child in children {
+                let block = self.disjoint_headers.remove(&child).unwrap();
+                removed.push((child, block.number));
+                to_remove.push(child);
+            }
+        }
+        removed
+    }
+}
+
+impl<TRq, TSrc> fmt::Debug for AllForksSync<TRq, TSrc> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AllForksSync")
+            .field("chain", &self.chain)
+            .field("num_disjoint_headers", &self.disjoint_headers.len())
+            .finish()
+    }
+}
+
+/// Identifier for an ongoing request in the [`AllForksSync`].
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct RequestId(usize);
+
+/// Identifier for a source in the [`AllForksSync`].
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SourceId(usize);
+
+/// Request that should be emitted towards a certain source.
+#[derive(Debug)]
+pub enum RequestAction<'a, TRq, TSrc> {
+    /// A request must be emitted for the given source.
+    ///
+    /// The request has **not** been acknowledged when this event is emitted. You **must** call
+    /// [`Start::start`] to notify the [`AllForksSync`] that the request has been sent
+    /// out.
+    Start {
+        /// Source where to request blocks from.
+        source_id: SourceId,
+        /// User data of source where to request blocks from.
+        source: &'a mut TSrc,
+        /// Must be used to accept the request.
+        start: Start<'a, TRq, TSrc>,
+        /// Hash of the first block to request.
+        first_block_hash: [u8; 32],
+        /// Number of the first block to request.
+        first_block_number: u64,
+        /// Number of blocks to request, starting from `first_block_hash` and going towards
+        /// lower block numbers. Always smaller than the value passed through
+        /// [`Config::blocks_request_granularity`].
+        num_blocks: NonZeroU32,
+    },
+}
+
+/// Must be used to accept the request.
+#[must_use]
+pub struct Start<'a, TRq, TSrc> {
+    requests: &'a mut slab::Slab<Request<TRq>>,
+    source: usize,
+    first_block_hash: [u8; 32],
+    marker: PhantomData<&'a TSrc>,
+}
+
+impl<'a, TRq, TSrc> Start<'a, TRq, TSrc> {
+    /// Updates the [`AllForksSync`] with the fact that the request has actually been
+    /// started. Returns the identifier for the request that must later be passed back to
+    /// [`AllForksSync::finish_request`].
+    pub fn start(self, user_data: TRq) -> RequestId {
+        RequestId(self.requests.insert(Request {
+            source: self.source,
+            first_block_hash: self.first_block_hash,
+            user_data,
+        }))
+    }
+}
+
+impl<'a, TRq, TSrc> fmt::Debug for Start<'a, TRq, TSrc> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Start").finish()
+    }
+}
+
+/// Single block in the outcome of a request. A list of these must be passed to
+/// [`AllForksSync::finish_request`].
+#[derive(Debug)]
+pub struct RequestSuccessBlock {
+    /// SCALE-encoded block header.
+    pub scale_encoded_header: Vec<u8>,
+    /// SCALE-encoded justification of this block, or `None` if none is available.
+    pub scale_encoded_justification: Option<Vec<u8>>,
+}
+
+/// Reason why a request has failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RequestFail {
+    /// Requested blocks aren't available from this source.
+    BlocksUnavailable,
+    /// The source hasn't answered in time.
+    Timeout,
+    /// The source has sent back a response that couldn't be decoded.
+    InvalidResponse,
+}
+
+impl RequestFail {
+    /// Returns the misbehaviour that the failure corresponds to.
+    fn misbehaviour(&self) -> reputation::Misbehaviour {
+        match self {
+            RequestFail::BlocksUnavailable => reputation::Misbehaviour::BlocksUnavailable,
+            RequestFail::Timeout => reputation::Misbehaviour::Timeout,
+            RequestFail::InvalidResponse => reputation::Misbehaviour::InvalidResponse,
+        }
+    }
+}
+
+/// Outcome of calling [`AllForksSync::finish_request`].
+#[derive(Debug)]
+pub enum FinishRequestOutcome<'a, TSrc> {
+    /// The blocks have been added to the queue of blocks to verify.
+    Queued,
+    /// The source has sent back an invalid response, or no response at all, and its reputation
+    /// has been lowered.
+    SourcePunished {
+        /// User data of the source.
+        source: &'a mut TSrc,
+        /// Details about the punishment.
+        punishment: SourcePunishment,
+    },
+}
+
+/// Information about a source whose reputation has been lowered following a misbehaviour.
+///
+/// If the [`SourcePunishment::verdict`] isn't [`reputation::Verdict::Keep`], no request will be
+/// sent to this source anymore until its reputation has recovered. It is recommended to
+/// disconnect from the source and to remove it with [`AllForksSync::remove_source`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SourcePunishment {
+    /// Source that has misbehaved.
+    pub source_id: SourceId,
+    /// Misbehaviour that the source has committed.
+    pub misbehaviour: reputation::Misbehaviour,
+    /// Recommended action towards the source, following the punishment.
+    pub verdict: reputation::Verdict,
+}
+
+/// Outcome of calling [`AllForksSync::block_announce`].
+#[derive(Debug)]
+pub enum BlockAnnounceOutcome {
+    /// Block has been added to the list of blocks to verify, or was already in that list.
+    Queued,
+    /// Block is already in the chain.
+    AlreadyInChain,
+    /// Block number is inferior or equal to the number of the finalized block.
+    TooOld,
+    /// Block has been discarded because the limit of [`Config::max_disjoint_headers`] has been
+    /// reached.
+    QueueFull,
+    /// Failed to decode the announced header.
+    InvalidHeader(header::Error),
+    /// The block, or one of its ancestors, has previously failed to verify. The source has been
+    /// punished.
+    BadBlock(SourcePunishment),
+}
+
+/// Outcome of calling [`AllForksSync::process_one`].
+#[derive(Debug)]
+pub enum ProcessOneOutcome {
+    /// There was nothing to do.
+    Idle,
+
+    /// A block has been successfully verified and inserted in the chain.
+    HeaderVerified {
+        /// Hash of the block.
+        hash: [u8; 32],
+        /// Number of the block.
+        number: u64,
+        /// True if the block is the new best block.
+        is_new_best: bool,
+        /// Number and hash of the new finalized block, if the justification of the block has
+        /// finalized a block.
+        finalized_block: Option<(u64, [u8; 32])>,
+        /// If the block came with a justification that has failed to verify, contains the
+        /// error. The block itself is still inserted in the chain.
+        justification_error: Option<blocks_tree::JustificationVerifyError>,
+        /// If the justification has failed to verify, contains the punishment that has been
+        /// applied to the source that has provided it.
+        punished_sources: Vec<SourcePunishment>,
+    },
+
+    /// A block has failed to verify. It has been discarded, along with all its descendants
+    /// whose ancestry wasn't verified yet.
+    HeaderVerifyError {
+        /// Hash of the block.
+        hash: [u8; 32],
+        /// Number of the block.
+        number: u64,
+        /// Problem that happened.
+        error: blocks_tree::HeaderVerifyError,
+        /// Sources that have announced or provided the block, and whose reputation has been
+        /// lowered as a consequence. Empty if the problem can't be attributed to the sources
+        /// with certainty.
+        punished_sources: Vec<SourcePunishment>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::blocks_tree::tests::{aura_header, config};
+
+    const NOW: Duration = Duration::from_secs(1 << 32);
+
+    fn new_sync() -> AllForksSync<(), ()> {
+        AllForksSync::new(Config {
+            chain_information_config: config().chain_information_config,
+            sources_capacity: 4,
+            blocks_capacity: 16,
+            max_disjoint_headers: 16,
+            blocks_request_granularity: NonZeroU32::new(16).unwrap(),
+        })
+    }
+
+    fn hash(scale_encoded_header: &[u8]) -> [u8; 32] {
+        header::hash_from_scale_encoded_header(scale_encoded_header)
+    }
+
+    /// Starts the next request, and checks that it targets the given block and source.
+    fn start_request(
+        sync: &mut AllForksSync<(), ()>,
+        expected_block: [u8; 32],
+        expected_source: SourceId,
+    ) -> RequestId {
+        start_request_at(sync, NOW, expected_block, expected_source)
+    }
+
+    /// Same as [`start_request`], at the given time.
+    fn start_request_at(
+        sync: &mut AllForksSync<(), ()>,
+        now: Duration,
+        expected_block: [u8; 32],
+        expected_source: SourceId,
+    ) -> RequestId {
+        match sync.next_request_action(now) {
+            Some(RequestAction::Start {
+                source_id,
+                first_block_hash,
+                start,
+                ..
+            }) => {
+                assert_eq!(source_id, expected_source);
+                assert_eq!(first_block_hash, expected_block);
+                start.start(())
+            }
+            None => panic!(),
+        }
+    }
+
+    fn response(headers: &[&Vec<u8>]) -> impl Iterator<Item = RequestSuccessBlock> {
+        headers
+            .iter()
+            .map(|h| RequestSuccessBlock {
+                scale_encoded_header: (*h).clone(),
+                scale_encoded_justification: None,
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn announce_request_insert() {
+        let mut sync = new_sync();
+        let genesis_hash = sync.best_block_hash();
+        let block1 = aura_header(genesis_hash, 1, 1);
+        let block2 = aura_header(hash(&block1), 2, 2);
+
+        let source = sync.add_source((), 0, genesis_hash);
+        assert!(sync.next_request_action(NOW).is_none());
+
+        // The parent of the announced block is unknown and must be requested.
+        assert!(matches!(
+            sync.block_announce(source, block2.clone(), true),
+            BlockAnnounceOutcome::Queued
+        ));
+        assert_eq!(sync.source_best_block(source), (2, hash(&block2)));
+        assert!(matches!(sync.process_one(NOW), ProcessOneOutcome::Idle));
+        let request = start_request(&mut sync, hash(&block1), source);
+        assert!(sync.next_request_action(NOW).is_none());
+
+        assert!(matches!(
+            sync.finish_request(request, Ok(response(&[&block1])), NOW)
+                .1,
+            FinishRequestOutcome::Queued
+        ));
+        assert_eq!(sync.num_disjoint_blocks(), 2);
+
+        // Blocks are inserted from the lowest to the highest.
+        for expected in &[&block1, &block2] {
+            match sync.process_one(NOW) {
+                ProcessOneOutcome::HeaderVerified {
+                    hash: h,
+                    is_new_best,
+                    ..
+                } => {
+                    assert_eq!(h, hash(expected));
+                    assert!(is_new_best);
+                }
+                _ => panic!(),
+            }
+        }
+        assert!(matches!(sync.process_one(NOW), ProcessOneOutcome::Idle));
+        assert_eq!(sync.best_block_hash(), hash(&block2));
+        assert_eq!(sync.num_disjoint_blocks(), 0);
+
+        assert!(matches!(
+            sync.block_announce(source, block2, true),
+            BlockAnnounceOutcome::AlreadyInChain
+        ));
+    }
+
+    #[test]
+    fn bad_responses_punished() {
+        let mut sync = new_sync();
+        let genesis_hash = sync.best_block_hash();
+        let block1 = aura_header(genesis_hash, 1, 1);
+        let block2 = aura_header(hash(&block1), 2, 2);
+        let unrelated = aura_header(genesis_hash, 1, 3);
+        let mut truncated = block1.clone();
+        truncated.truncate(10);
+
+        // Each response is sent by a different source, as punished sources are no longer
+        // queried.
+        let cases: [(&[&Vec<u8>], _); 3] = [
+            (&[&unrelated], reputation::Misbehaviour::InvalidResponse),
+            (&[&truncated], reputation::Misbehaviour::InvalidResponse),
+            (&[], reputation::Misbehaviour::EmptyResponse),
+        ];
+        for (blocks, expected_misbehaviour) in &cases {
+            let source = sync.add_source((), 0, genesis_hash);
+            sync.block_announce(source, block2.clone(), true);
+            let request = start_request(&mut sync, hash(&block1), source);
+            match sync.finish_request(request, Ok(response(blocks)), NOW).1 {
+                FinishRequestOutcome::SourcePunished { punishment, .. } => {
+                    assert_eq!(punishment.source_id, source);
+                    assert_eq!(punishment.misbehaviour, *expected_misbehaviour);
+                }
+                _ => panic!(),
+            }
+            assert_eq!(sync.num_disjoint_blocks(), 1);
+        }
+    }
+
+    #[test]
+    fn bad_blocks_remembered() {
+        let mut sync = new_sync();
+        let genesis_hash = sync.best_block_hash();
+
+        // Block whose seal has been tampered with.
+        let bad_block1 = {
+            let mut header = aura_header(genesis_hash, 1, 1);
+            *header.last_mut().unwrap() ^= 1;
+            header
+        };
+        let block2 = aura_header(hash(&bad_block1), 2, 2);
+
+        let source1 = sync.add_source((), 0, genesis_hash);
+        let source2 = sync.add_source((), 0, genesis_hash);
+        let source3 = sync.add_source((), 0, genesis_hash);
+        sync.block_announce(source1, block2.clone(), true);
+        let request = start_request(&mut sync, hash(&bad_block1), source1);
+        sync.finish_request(request, Ok(response(&[&bad_block1])), NOW);
+
+        // The block fails to verify, its child is discarded, and the source is punished.
+        match sync.process_one(NOW) {
+            ProcessOneOutcome::HeaderVerifyError {
+                hash: h,
+                punished_sources,
+                ..
+            } => {
+                assert_eq!(h, hash(&bad_block1));
+                assert_eq!(punished_sources.len(), 1);
+                assert_eq!(punished_sources[0].source_id, source1);
+                assert_eq!(punished_sources[0].verdict, reputation::Verdict::Ban);
+            }
+            _ => panic!(),
+        }
+        assert_eq!(sync.num_disjoint_blocks(), 0);
+        assert!(matches!(sync.process_one(NOW), ProcessOneOutcome::Idle));
+
+        // Announcing the bad block or its child again is punished.
+        for (source, header) in &[(source2, &bad_block1), (source3, &block2)] {
+            match sync.block_announce(*source, (*header).clone(), true) {
+                BlockAnnounceOutcome::BadBlock(punishment) => {
+                    assert_eq!(punishment.source_id, *source);
+                    assert_eq!(
+                        punishment.misbehaviour,
+                        reputation::Misbehaviour::InvalidBlock
+                    );
+                }
+                _ => panic!(),
+            }
+        }
+        assert_eq!(sync.num_disjoint_blocks(), 0);
+
+        // The best block of the sources is never requested.
+        assert!(sync.next_request_action(NOW).is_none());
+    }
+
+    #[test]
+    fn failed_requests_backoff() {
+        let mut sync = new_sync();
+        let genesis_hash = sync.best_block_hash();
+        let block1 = aura_header(genesis_hash, 1, 1);
+        let block2 = aura_header(hash(&block1), 2, 2);
+
+        let source = sync.add_source((), 0, genesis_hash);
+        sync.block_announce(source, block2, true);
+
+        // The waiting time doubles after each consecutive failure.
+        let mut now = NOW;
+        for backoff in &[1, 2, 4] {
+            let request = start_request_at(&mut sync, now, hash(&block1), source);
+            sync.finish_request(
+                request,
+                Err::<iter::Empty<_>, _>(RequestFail::BlocksUnavailable),
+                now,
+            );
+            now += Duration::from_secs(*backoff);
+            assert!(sync
+                .next_request_action(now - Duration::from_millis(1))
+                .is_none());
+            assert!(sync.next_request_action(now).is_some());
+        }
+
+        // A successful request resets the waiting time.
+        let request = start_request_at(&mut sync, now, hash(&block1), source);
+        sync.finish_request(request, Ok(response(&[&block1])), now);
+        assert_eq!(sync.num_disjoint_blocks(), 2);
+
+        let fork1 = aura_header(genesis_hash, 1, 3);
+        sync.block_announce(source, aura_header(hash(&fork1), 2, 4), false);
+        let request = start_request_at(&mut sync, now, hash(&fork1), source);
+        sync.finish_request(
+            request,
+            Err::<iter::Empty<_>, _>(RequestFail::BlocksUnavailable),
+            now,
+        );
+        now += Duration::from_secs(1);
+        assert!(sync.next_request_action(now).is_some());
+    }
+}
@@ -22,20 +22,28 @@
 //!
 //! In addition to managing the sources, using [`OptimisticFullSync`] also requires holding the
 //! storage of the latest finalized block.
+//!
+//! # Pre-verification
+//!
+//! Blocks are executed one after the other, as the execution of a block depends on the storage
+//! of its parent. Verifying the signatures found in the headers and in the justifications,
+//! however, doesn't depend on the storage. [`OptimisticFullSync::next_pre_verification`] extracts
+//! batches of downloaded blocks whose signatures can be verified ahead of time, for example on a
+//! thread pool, while [`OptimisticFullSync::process_one`] only executes blocks.
 
 // TODO: document better
 // TODO: this entire module needs clean up
 
 use super::super::{blocks_tree, chain_information};
-use super::optimistic;
-use crate::{executor, header, trie::calculate_root};
+use super::{optimistic, reputation};
+use crate::{executor, finality::justification, header, trie::calculate_root, verify::babe};
 
 use alloc::{collections::BTreeMap, vec};
-use core::{convert::TryFrom as _, iter, num::NonZeroU32};
+use core::{convert::TryFrom as _, iter, num::NonZeroU32, time::Duration};
 use hashbrown::{HashMap, HashSet};
 
 pub use optimistic::{
-    FinishRequestOutcome, RequestAction, RequestFail, RequestId, SourceId, Start,
+    FinishRequestOutcome, RequestAction, RequestFail, RequestId, SourceId, SourcePunishment, Start,
 };
 
 /// Configuration for the [`OptimisticFullSync`].
@@ -103,6 +111,36 @@ pub struct OptimisticFullSync<TRq, TSrc> {
     /// Underlying helper. Manages sources and requests.
     /// Always `Some`, except during some temporary extractions.
     sync: Option<optimistic::OptimisticSync<TRq, TSrc, RequestSuccessBlock>>,
+
+    /// State of the pre-verification of the blocks waiting to be processed.
+    pre_verification: PreVerificationState,
+}
+
+/// State of the pre-verification of the blocks waiting to be processed.
+#[derive(Default)]
+struct PreVerificationState {
+    /// Number of the highest block that has been included in a [`PreVerification`]. Blocks with
+    /// a lower or equal number are never included in a [`PreVerification`] again.
+    highest_dispatched: u64,
+    /// Incremented every time the queue of blocks is reset. Used in order to discard
+    /// [`PreVerified`] that concern blocks that are no longer in the queue.
+    generation: u64,
+    /// Outcome of the pre-verification of the BABE seals, indexed by block hash.
+    babe_seals: HashMap<[u8; 32], babe::PreVerifiedSeal, fnv::FnvBuildHasher>,
+    /// Outcome of the pre-verification of the signatures of the justifications, indexed by
+    /// hash of the block they come with.
+    justifications:
+        HashMap<[u8; 32], justification::verify::PreVerifiedSignatures, fnv::FnvBuildHasher>,
+}
+
+impl PreVerificationState {
+    /// Discards all the pre-verifications. Must be called whenever the queue of blocks is reset.
+    fn reset(&mut self) {
+        self.highest_dispatched = 0;
+        self.generation = self.generation.wrapping_add(1);
+        self.babe_seals.clear();
+        self.justifications.clear();
+    }
 }
 
 // TODO: doc
@@ -126,9 +164,14 @@ pub struct Block {
 impl<TRq, TSrc> OptimisticFullSync<TRq, TSrc> {
     /// Builds a new [`OptimisticFullSync`].
     pub fn new(config: Config) -> Self {
+        let fork_choice = blocks_tree::ForkChoice::from_consensus(
+            &config.chain_information_config.chain_information.consensus,
+        );
         let chain = blocks_tree::NonFinalizedTree::new(blocks_tree::Config {
             chain_information_config: config.chain_information_config,
             blocks_capacity: config.blocks_capacity,
+            fork_choice,
+            generate_events: true,
         });
 
         let best_block_number = chain.best_block_header().number;
@@ -145,6 +188,7 @@ impl<TRq, TSrc> OptimisticFullSync<TRq, TSrc> {
                 download_ahead_blocks: config.download_ahead_blocks,
                 source_selection_randomness_seed: config.source_selection_randomness_seed,
             })),
+            pre_verification: Default::default(),
         }
     }
 
@@ -154,6 +198,14 @@ impl<TRq, TSrc> OptimisticFullSync<TRq, TSrc> {
         self.chain.as_chain_information()
     }
 
+    /// Returns the header of the best block.
+    ///
+    /// > **Note**: This value is provided only for informative purposes. Keep in mind that this
+    /// >           best block might be reverted in the future.
+    pub fn best_block_header(&self) -> header::HeaderRef {
+        self.chain.best_block_header()
+    }
+
     /// Returns the number of the best block.
     ///
     /// > **Note**: This value is provided only for informative purposes. Keep in mind that this
@@ -170,11 +222,32 @@ impl<TRq, TSrc> OptimisticFullSync<TRq, TSrc> {
         self.chain.best_block_hash()
     }
 
+    /// Returns the next event that happened on the chain, if any. See also
+    /// [the documentation of the `sync` module](super#chain-events).
+    pub fn next_chain_event(&mut self) -> Option<blocks_tree::ChainEvent> {
+        self.chain.next_event()
+    }
+
     /// Inform the [`OptimisticFullSync`] of a new potential source of blocks.
     pub fn add_source(&mut self, source: TSrc) -> SourceId {
         self.sync.as_mut().unwrap().add_source(source)
     }
 
+    /// Updates the best block number reported by the given source.
+    ///
+    /// Blocks above this number are no longer requested from this source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn set_source_best_block(&mut self, source_id: SourceId, best_block_number: u64) {
+        self.sync
+            .as_mut()
+            .unwrap()
+            .set_source_best_block(source_id, best_block_number)
+    }
+
     /// Inform the [`OptimisticFullSync`] that a source of blocks is no longer available.
     ///
     /// This automatically cancels all the requests that have been emitted for this source.
@@ -191,6 +264,16 @@ impl<TRq, TSrc> OptimisticFullSync<TRq, TSrc> {
         self.sync.as_mut().unwrap().remove_source(source)
     }
 
+    /// Returns the user data of the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_user_data(&self, source_id: SourceId) -> &TSrc {
+        self.sync.as_ref().unwrap().source_user_data(source_id)
+    }
+
     /// Returns an iterator that extracts all requests that need to be started and requests that
     /// need to be cancelled.
     pub fn next_request_action(&mut self) -> Option<RequestAction<TRq, TSrc, RequestSuccessBlock>> {
@@ -216,12 +299,100 @@ impl<TRq, TSrc> OptimisticFullSync<TRq, TSrc> {
             .finish_request(request_id, outcome)
     }
 
+    /// Extracts from the queue of blocks waiting to be processed a batch of at most `max_blocks`
+    /// blocks whose signatures can be verified ahead of time. Returns `None` if there is nothing
+    /// to pre-verify.
+    ///
+    /// The returned [`PreVerification`] doesn't borrow the [`OptimisticFullSync`] and can be run
+    /// on any thread, for example on a thread pool. Its outcome must then be passed to
+    /// [`OptimisticFullSync::inject_pre_verified`], after which
+    /// [`OptimisticFullSync::process_one`] no longer verifies these signatures.
+    ///
+    /// Calling this method is optional. Blocks that haven't been pre-verified, or whose
+    /// pre-verification hasn't been injected yet, are entirely verified by
+    /// [`OptimisticFullSync::process_one`]. In environments where threads aren't available, such
+    /// as Wasm, [`PreVerification::run`] can be called in place, or this method not at all.
+    pub fn next_pre_verification(&mut self, max_blocks: NonZeroU32) -> Option<PreVerification> {
+        // Only the seals of BABE blocks can be pre-verified.
+        let epochs = self.chain.babe_best_block_epochs();
+
+        let mut num_blocks = 0;
+        let mut headers = Vec::new();
+        let mut justifications = Vec::new();
+        for (block_number, block) in self.sync.as_ref().unwrap().queued_blocks() {
+            if block_number <= self.pre_verification.highest_dispatched {
+                continue;
+            }
+
+            if num_blocks >= max_blocks.get() {
+                break;
+            }
+
+            // Blocks with an invalid header are skipped, as the problem is reported when the
+            // block is processed. The epoch of the blocks that follow a block whose epoch isn't
+            // known isn't known either. They are pre-verified later, once the best block has
+            // advanced.
+            if let (Some(epochs), Ok(decoded)) =
+                (&epochs, header::decode(&block.scale_encoded_header))
+            {
+                if epochs.block_epoch(&decoded).is_none() {
+                    break;
+                }
+                headers.push(block.scale_encoded_header.clone());
+            }
+
+            if let Some(justification) = &block.scale_encoded_justification {
+                justifications.push((
+                    header::hash_from_scale_encoded_header(&block.scale_encoded_header),
+                    justification.clone(),
+                ));
+            }
+
+            num_blocks += 1;
+            self.pre_verification.highest_dispatched = block_number;
+        }
+
+        if headers.is_empty() && justifications.is_empty() {
+            return None;
+        }
+
+        Some(PreVerification {
+            generation: self.pre_verification.generation,
+            epochs,
+            headers,
+            grandpa_authorities_set_id: self.chain.best_block_grandpa_authorities_set_id(),
+            justifications,
+        })
+    }
+
+    /// Injects the outcome of a [`PreVerification`] previously returned by
+    /// [`OptimisticFullSync::next_pre_verification`].
+    ///
+    /// The outcome is discarded if the queue of blocks waiting to be processed has been reset in
+    /// the meanwhile, for example because of an invalid block.
+    pub fn inject_pre_verified(&mut self, pre_verified: PreVerified) {
+        if pre_verified.generation != self.pre_verification.generation {
+            return;
+        }
+
+        self.pre_verification
+            .babe_seals
+            .extend(pre_verified.babe_seals);
+        self.pre_verification
+            .justifications
+            .extend(pre_verified.justifications);
+    }
+
     /// Process a chunk of blocks in the queue of verification.
     ///
     /// This method takes ownership of the [`OptimisticFullSync`] and starts a verification
     /// process. The [`OptimisticFullSync`] is yielded back at the end of this process.
-    pub fn process_one(mut self) -> ProcessOne<TRq, TSrc> {
-        let sync = self.sync.take().unwrap();
+    ///
+    /// Must be passed the time elapsed since the Unix Epoch, in order to reject blocks whose
+    /// slot is in the future and to update the reputation of the sources.
+    pub fn process_one(mut self, now_from_unix_epoch: Duration) -> ProcessOne<TRq, TSrc> {
+        let mut sync = self.sync.take().unwrap();
+        sync.decay_reputations(now_from_unix_epoch);
 
         let to_process = match sync.process_one() {
             Ok(tp) => tp,
@@ -237,16 +408,107 @@ impl<TRq, TSrc> OptimisticFullSync<TRq, TSrc> {
             Inner::Start(self.chain),
             ProcessOneShared {
                 pending_encoded_justification: None,
+                pending_babe_pre_verified_seal: None,
+                pending_justification_pre_verified_signatures: None,
                 to_process,
                 best_to_finalized_storage_diff: self.best_to_finalized_storage_diff,
                 runtime_code_cache: self.runtime_code_cache,
                 top_trie_root_calculation_cache: self.top_trie_root_calculation_cache,
                 finalized_blocks: Vec::new(),
+                punished_sources: Vec::new(),
+                now_from_unix_epoch,
+                pre_verification: self.pre_verification,
             },
         )
     }
 }
 
+/// Verification of the signatures of a batch of blocks, that can be performed ahead of time and
+/// on any thread.
+///
+/// See [`OptimisticFullSync::next_pre_verification`].
+#[must_use]
+pub struct PreVerification {
+    /// Value of [`PreVerificationState::generation`] when this struct has been built.
+    generation: u64,
+    /// BABE epochs of the best block when this struct has been built. `None` if the chain
+    /// doesn't use BABE.
+    epochs: Option<blocks_tree::BabeBestBlockEpochs>,
+    /// SCALE-encoded headers of the blocks whose seal to verify.
+    headers: Vec<Vec<u8>>,
+    /// GrandPa authorities set of the best block when this struct has been built.
+    grandpa_authorities_set_id: u64,
+    /// Hashes of blocks, and SCALE-encoded justifications that come with them, whose signatures
+    /// to verify.
+    justifications: Vec<([u8; 32], Vec<u8>)>,
+}
+
+impl PreVerification {
+    /// Returns the number of headers and justifications to verify.
+    pub fn num_blocks(&self) -> usize {
+        self.headers.len() + self.justifications.len()
+    }
+
+    /// Performs the verification. This is a CPU-intensive operation.
+    ///
+    /// The outcome must be passed to [`OptimisticFullSync::inject_pre_verified`].
+    pub fn run(self) -> PreVerified {
+        let epochs = &self.epochs;
+        let babe_seals = self
+            .headers
+            .iter()
+            .filter_map(|scale_encoded_header| {
+                let decoded = header::decode(scale_encoded_header).ok()?;
+                let (epoch_number, epoch_info) = epochs.as_ref()?.block_epoch(&decoded)?;
+                let seal = babe::pre_verify_seal(babe::PreVerifyConfig {
+                    header: decoded,
+                    epoch_number,
+                    epoch_info,
+                })?;
+                Some((
+                    header::hash_from_scale_encoded_header(scale_encoded_header),
+                    seal,
+                ))
+            })
+            .collect();
+
+        let grandpa_authorities_set_id = self.grandpa_authorities_set_id;
+        let justifications = self
+            .justifications
+            .iter()
+            .filter_map(|(block_hash, scale_encoded_justification)| {
+                let decoded = justification::decode::decode(scale_encoded_justification).ok()?;
+                let signatures = justification::verify::pre_verify_signatures(
+                    &decoded,
+                    grandpa_authorities_set_id,
+                )?;
+                Some((*block_hash, signatures))
+            })
+            .collect();
+
+        PreVerified {
+            generation: self.generation,
+            babe_seals,
+            justifications,
+        }
+    }
+}
+
+/// Outcome of [`PreVerification::run`].
+///
+/// Must be passed to [`OptimisticFullSync::inject_pre_verified`].
+#[must_use]
+pub struct PreVerified {
+    /// Copy of [`PreVerification::generation`].
+    generation: u64,
+    /// Hashes of the blocks whose seal has been successfully verified, and the outcome of the
+    /// verification.
+    babe_seals: Vec<([u8; 32], babe::PreVerifiedSeal)>,
+    /// Hashes of the blocks whose justification signatures have been successfully verified, and
+    /// the outcome of the verification.
+    justifications: Vec<([u8; 32], justification::verify::PreVerifiedSignatures)>,
+}
+
 pub struct RequestSuccessBlock {
     pub scale_encoded_header: Vec<u8>,
     pub scale_encoded_justification: Option<Vec<u8>>,
@@ -276,6 +538,9 @@ pub enum ProcessOne<TRq, TSrc> {
         /// Ordered by increasing block number.
         // TODO: consider returning them one at a time?
         finalized_blocks: Vec<Block>,
+        /// Sources whose reputation has been lowered because they have provided invalid blocks
+        /// or justifications. See [`SourcePunishment`].
+        punished_sources: Vec<SourcePunishment>,
     },
     /// A step in the processing has been completed.
     ///
@@ -305,12 +570,32 @@ enum Inner {
 
 struct ProcessOneShared<TRq, TSrc> {
     pending_encoded_justification: Option<Vec<u8>>,
+    /// Outcome of the pre-verification of the block being verified, if any.
+    pending_babe_pre_verified_seal: Option<babe::PreVerifiedSeal>,
+    /// Outcome of the pre-verification of the signatures of `pending_encoded_justification`, if
+    /// any.
+    pending_justification_pre_verified_signatures:
+        Option<justification::verify::PreVerifiedSignatures>,
     to_process: optimistic::ProcessOne<TRq, TSrc, RequestSuccessBlock>,
     best_to_finalized_storage_diff: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
     runtime_code_cache: Option<executor::WasmVmPrototype>,
     top_trie_root_calculation_cache: Option<calculate_root::CalculationCache>,
     // TODO: make sure we're not throwing this away in case of error
     finalized_blocks: Vec<Block>,
+    /// Sources punished during the processing.
+    punished_sources: Vec<SourcePunishment>,
+    now_from_unix_epoch: Duration,
+    pre_verification: PreVerificationState,
+}
+
+impl<TRq, TSrc> ProcessOneShared<TRq, TSrc> {
+    /// Lowers the reputation of the source of the blocks being processed, and stores the
+    /// punishment in order to later report it to the user.
+    fn punish_source(&mut self, misbehaviour: reputation::Misbehaviour) {
+        if let Some(punishment) = self.to_process.report.punish_source(misbehaviour) {
+            self.punished_sources.push(punishment);
+        }
+    }
 }
 
 impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
@@ -331,9 +616,24 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                         if let Some(justification) = next_block.scale_encoded_justification {
                             shared.pending_encoded_justification = Some(justification);
                         }
+                        if !shared.pre_verification.babe_seals.is_empty()
+                            || !shared.pre_verification.justifications.is_empty()
+                        {
+                            let hash = header::hash_from_scale_encoded_header(
+                                &next_block.scale_encoded_header,
+                            );
+                            shared.pending_babe_pre_verified_seal =
+                                shared.pre_verification.babe_seals.remove(&hash);
+                            shared.pending_justification_pre_verified_signatures =
+                                shared.pre_verification.justifications.remove(&hash);
+                        } else {
+                            shared.pending_babe_pre_verified_seal = None;
+                            shared.pending_justification_pre_verified_signatures = None;
+                        }
                         inner = Inner::Step1(chain.verify_body(
                             next_block.scale_encoded_header,
                             next_block.scale_encoded_extrinsics.into_iter(),
+                            shared.now_from_unix_epoch,
                         ));
                     } else {
                         debug_assert!(shared.to_process.blocks.as_slice().is_empty());
@@ -350,14 +650,69 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                                 top_trie_root_calculation_cache: shared
                                     .top_trie_root_calculation_cache,
                                 sync: Some(sync),
+                                pre_verification: shared.pre_verification,
                             },
                             finalized_blocks: shared.finalized_blocks,
+                            punished_sources: shared.punished_sources,
                         };
                     }
                 }
 
-                Inner::Step1(blocks_tree::BodyVerifyStep1::InvalidHeader(chain, error)) => {
+                Inner::Step1(blocks_tree::BodyVerifyStep1::InvalidHeader(chain, _)) => {
+                    shared.punish_source(reputation::Misbehaviour::InvalidBlock);
+
+                    // TODO: DRY
+                    shared.pre_verification.reset();
+                    let sync = shared
+                        .to_process
+                        .report
+                        .reset_to_finalized(chain.finalized_block_header().number);
+                    break ProcessOne::Finished {
+                        sync: OptimisticFullSync {
+                            chain,
+                            best_to_finalized_storage_diff: Default::default(),
+                            runtime_code_cache: None,
+                            top_trie_root_calculation_cache: None,
+                            sync: Some(sync),
+                            pre_verification: shared.pre_verification,
+                        },
+                        finalized_blocks: shared.finalized_blocks,
+                        punished_sources: shared.punished_sources,
+                    };
+                }
+
+                Inner::Step1(blocks_tree::BodyVerifyStep1::BadBlock(chain))
+                | Inner::Step1(blocks_tree::BodyVerifyStep1::ForkBlockMismatch { chain, .. }) => {
+                    // The block is rejected because of the configuration of the chain, and the
+                    // source is likely to simply follow a different fork.
+                    shared.punish_source(reputation::Misbehaviour::RejectedBlock);
+
+                    // TODO: DRY
+                    shared.pre_verification.reset();
+                    let sync = shared
+                        .to_process
+                        .report
+                        .reset_to_finalized(chain.finalized_block_header().number);
+                    break ProcessOne::Finished {
+                        sync: OptimisticFullSync {
+                            chain,
+                            best_to_finalized_storage_diff: Default::default(),
+                            runtime_code_cache: None,
+                            top_trie_root_calculation_cache: None,
+                            sync: Some(sync),
+                            pre_verification: shared.pre_verification,
+                        },
+                        finalized_blocks: shared.finalized_blocks,
+                        punished_sources: shared.punished_sources,
+                    };
+                }
+
+                Inner::Step1(blocks_tree::BodyVerifyStep1::BadGrandpaChange(chain, _)) => {
+                    // Pending changes depend on the blocks that have been imported locally.
+                    shared.punish_source(reputation::Misbehaviour::UnverifiableBlock);
+
                     // TODO: DRY
+                    shared.pre_verification.reset();
                     let sync = shared
                         .to_process
                         .report
@@ -369,13 +724,16 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                             runtime_code_cache: None,
                             top_trie_root_calculation_cache: None,
                             sync: Some(sync),
+                            pre_verification: shared.pre_verification,
                         },
                         finalized_blocks: shared.finalized_blocks,
+                        punished_sources: shared.punished_sources,
                     };
                 }
 
                 Inner::Step1(blocks_tree::BodyVerifyStep1::Duplicate(chain)) => {
                     // TODO: DRY
+                    shared.pre_verification.reset();
                     let sync = shared
                         .to_process
                         .report
@@ -387,13 +745,16 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                             runtime_code_cache: None,
                             top_trie_root_calculation_cache: None,
                             sync: Some(sync),
+                            pre_verification: shared.pre_verification,
                         },
                         finalized_blocks: shared.finalized_blocks,
+                        punished_sources: shared.punished_sources,
                     };
                 }
 
                 Inner::Step1(blocks_tree::BodyVerifyStep1::BadParent { chain, .. }) => {
                     // TODO: DRY
+                    shared.pre_verification.reset();
                     let sync = shared
                         .to_process
                         .report
@@ -405,12 +766,18 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                             runtime_code_cache: shared.runtime_code_cache,
                             top_trie_root_calculation_cache: None,
                             sync: Some(sync),
+                            pre_verification: shared.pre_verification,
                         },
                         finalized_blocks: shared.finalized_blocks,
+                        punished_sources: shared.punished_sources,
                     };
                 }
 
-                Inner::Step1(blocks_tree::BodyVerifyStep1::ParentRuntimeRequired(req)) => {
+                Inner::Step1(blocks_tree::BodyVerifyStep1::ParentRuntimeRequired(mut req)) => {
+                    if let Some(pre_verified_seal) = shared.pending_babe_pre_verified_seal.take() {
+                        req.set_babe_pre_verified_seal(pre_verified_seal);
+                    }
+
                     // The verification process is asking for a Wasm virtual machine containing
                     // the parent block's runtime.
                     //
@@ -482,7 +849,7 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                     offchain_storage_changes,
                     top_trie_root_calculation_cache,
                     parent_runtime,
-                    result: Ok(success),
+                    insert: success,
                 }) => {
                     // Successfully verified block!
                     // Inserting it into the chain and updated all the caches.
@@ -513,12 +880,28 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                     // `pending_encoded_verification` contains the justification (if any)
                     // corresponding to the block that has just been verified. Verifying the
                     // justification as well.
-                    if let Some(justification) = shared.pending_encoded_justification.take() {
-                        let mut apply = match chain.verify_justification(&justification) {
-                            Ok(a) => a,
-                            Err(_) => todo!(), // TODO:
-                        };
-
+                    // If the justification is invalid, the source is punished but the block is
+                    // kept, as it has been successfully verified.
+                    let pre_verified_signatures =
+                        shared.pending_justification_pre_verified_signatures.take();
+                    let justification_verify =
+                        shared.pending_encoded_justification.take().map(|j| {
+                            (
+                                chain.verify_justification_pre_verified(
+                                    &j,
+                                    pre_verified_signatures.as_ref(),
+                                ),
+                                j,
+                            )
+                        });
+                    if let Some((Err(err), _)) = &justification_verify {
+                        if let Some(misbehaviour) =
+                            reputation::justification_verify_misbehaviour(err)
+                        {
+                            shared.punish_source(misbehaviour);
+                        }
+                    }
+                    if let Some((Ok(mut apply), justification)) = justification_verify {
                         assert!(apply.is_current_best_block()); // TODO: can legitimately fail in case of malicious node
 
                         // As part of the finalization, put the justification in the chain that's
@@ -556,8 +939,10 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                                 top_trie_root_calculation_cache: shared
                                     .top_trie_root_calculation_cache,
                                 sync: Some(sync),
+                                pre_verification: shared.pre_verification,
                             },
                             finalized_blocks: shared.finalized_blocks,
+                            punished_sources: shared.punished_sources,
                         };
                     }
 
@@ -574,9 +959,30 @@ impl<TRq, TSrc> ProcessOne<TRq, TSrc> {
                     };
                 }
 
-                Inner::Step2(blocks_tree::BodyVerifyStep2::Finished {
-                    result: Err(err), ..
-                }) => todo!("verif failure"),
+                Inner::Step2(blocks_tree::BodyVerifyStep2::Error { chain, error }) => {
+                    if let Some(misbehaviour) = reputation::body_verify_misbehaviour(&error) {
+                        shared.punish_source(misbehaviour);
+                    }
+
+                    // TODO: DRY
+                    shared.pre_verification.reset();
+                    let sync = shared
+                        .to_process
+                        .report
+                        .reset_to_finalized(chain.finalized_block_header().number);
+                    break ProcessOne::Finished {
+                        sync: OptimisticFullSync {
+                            chain,
+                            best_to_finalized_storage_diff: Default::default(),
+                            runtime_code_cache: None,
+                            top_trie_root_calculation_cache: None,
+                            sync: Some(sync),
+                            pre_verification: shared.pre_verification,
+                        },
+                        finalized_blocks: shared.finalized_blocks,
+                        punished_sources: shared.punished_sources,
+                    };
+                }
 
                 Inner::Step2(blocks_tree::BodyVerifyStep2::StorageGet(mut req)) => {
                     // The underlying verification process is asking for a storage entry in the
@@ -31,12 +31,14 @@
 // TODO: document usage
 
 use super::super::{blocks_tree, chain_information};
-use super::optimistic;
+use super::{optimistic, reputation};
+use crate::{finality::proof, header};
 
-use core::{convert::TryFrom as _, num::NonZeroU32};
+use alloc::collections::VecDeque;
+use core::{convert::TryFrom as _, num::NonZeroU32, time::Duration};
 
 pub use optimistic::{
-    FinishRequestOutcome, RequestAction, RequestFail, RequestId, SourceId, Start,
+    FinishRequestOutcome, RequestAction, RequestFail, RequestId, SourceId, SourcePunishment, Start,
 };
 
 /// Configuration for the [`OptimisticHeadersSync`].
@@ -71,6 +73,20 @@ pub struct Config {
     /// You are encouraged to use something like `rand::random()` to fill this field, except in
     /// situations where determinism/reproducibility is desired.
     pub source_selection_randomness_seed: u64,
+
+    /// If `true`, the [`OptimisticHeadersSync`] generates [`blocks_tree::ChainEvent`]s that can
+    /// be retrieved with [`OptimisticHeadersSync::next_chain_event`]. See also
+    /// [the documentation of the `sync` module](super#chain-events).
+    pub generate_events: bool,
+
+    /// Maximum number of verified non-finalized blocks to keep in memory.
+    ///
+    /// Keeping these blocks makes it possible to save them with
+    /// [`OptimisticHeadersSync::snapshot`]. If the finality lags behind by more than this number
+    /// of blocks, they are discarded in order to bound the memory usage, and
+    /// [`OptimisticHeadersSync::snapshot`] returns `None` until a block more recent than the
+    /// discarded ones gets finalized.
+    pub max_non_finalized_blocks: usize,
 }
 
 /// Optimistic headers-only syncing.
@@ -81,31 +97,91 @@ pub struct OptimisticHeadersSync<TRq, TSrc> {
 
     /// Chain containing the state necessary to verify blocks.
     ///
-    /// Important: the finalized block in this chain is not the actual finalized blocks. In order
-    /// to reduce memory consumption, every block that isn't the best block is discarded. This is
-    /// done by considering the best block as finalized even though it's not actually.
+    /// Important: the finalized block in this chain might not be the actual finalized block. In
+    /// order to bound the memory consumption, if there are more than
+    /// [`Config::max_non_finalized_blocks`] non-finalized blocks, every block that isn't the
+    /// best block is discarded. This is done by considering the best block as finalized even
+    /// though it's not actually.
     chain: blocks_tree::NonFinalizedTree<()>,
 
+    /// Value passed by [`Config::max_non_finalized_blocks`].
+    max_non_finalized_blocks: usize,
+
     /// Underlying helper. Manages sources and requests.
     /// Always `Some`, except during some temporary extractions.
     sync: Option<optimistic::OptimisticSync<TRq, TSrc, RequestSuccessBlock>>,
+
+    /// Queue of events to return from [`OptimisticHeadersSync::next_chain_event`]. `None` if
+    /// [`Config::generate_events`] was `false`.
+    ///
+    /// The events of `chain` can't be used, as its finalized block isn't the actual finalized
+    /// block.
+    events: Option<VecDeque<blocks_tree::ChainEvent>>,
+
+    /// Hashes of the blocks between the actual finalized block (exclusive) and the best block
+    /// (inclusive), in increasing block number. Always empty if [`Config::generate_events`] was
+    /// `false`.
+    non_finalized_blocks: VecDeque<[u8; 32]>,
 }
 
 impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
     /// Builds a new [`OptimisticHeadersSync`].
     pub fn new(config: Config) -> Self {
-        let blocks_tree_config = blocks_tree::Config {
-            chain_information_config: config.chain_information_config,
-            blocks_capacity: usize::try_from(config.blocks_request_granularity.get())
-                .unwrap_or(usize::max_value()),
-        };
-
+        let blocks_tree_config = blocks_tree_config(&config);
         let chain = blocks_tree::NonFinalizedTree::new(blocks_tree_config.clone());
+        Self::with_chain(config, blocks_tree_config, chain, VecDeque::new())
+    }
+
+    /// Builds a new [`OptimisticHeadersSync`] containing the non-finalized blocks found in a
+    /// snapshot previously generated with [`OptimisticHeadersSync::snapshot`].
+    ///
+    /// The chain information found in the configuration must be the one that was returned by
+    /// [`OptimisticHeadersSync::as_chain_information`] at the time the snapshot was taken. The
+    /// `restored_header` function is called for each restored block, in increasing block number.
+    ///
+    /// No [`blocks_tree::ChainEvent`] is generated for the restored blocks.
+    pub fn from_snapshot(
+        config: Config,
+        snapshot: &[u8],
+        mut restored_header: impl FnMut(header::HeaderRef),
+    ) -> Result<Self, blocks_tree::SnapshotDecodeError> {
+        let blocks_tree_config = blocks_tree_config(&config);
+
+        // Since only one chain is followed, all the blocks of the snapshot are ancestors of the
+        // best block.
+        let mut non_finalized_blocks = VecDeque::new();
+        let chain = blocks_tree::NonFinalizedTree::from_snapshot(
+            blocks_tree_config.clone(),
+            snapshot,
+            |header| {
+                non_finalized_blocks.push_back(header.hash());
+                restored_header(header);
+            },
+        )?;
+
+        Ok(Self::with_chain(
+            config,
+            blocks_tree_config,
+            chain,
+            non_finalized_blocks,
+        ))
+    }
+
+    /// Builds a new [`OptimisticHeadersSync`] around the given chain, whose finalized block is
+    /// the actual finalized block. `non_finalized_blocks` must contain the hashes of the
+    /// non-finalized blocks of the chain.
+    fn with_chain(
+        config: Config,
+        blocks_tree_config: blocks_tree::Config,
+        chain: blocks_tree::NonFinalizedTree<()>,
+        non_finalized_blocks: VecDeque<[u8; 32]>,
+    ) -> Self {
         let best_block_number = chain.best_block_header().number;
 
         OptimisticHeadersSync {
             finalized_chain_information: blocks_tree_config,
             chain,
+            max_non_finalized_blocks: config.max_non_finalized_blocks,
             sync: Some(optimistic::OptimisticSync::new(optimistic::Config {
                 best_block_number,
                 sources_capacity: config.sources_capacity,
@@ -113,9 +189,31 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
                 download_ahead_blocks: config.download_ahead_blocks,
                 source_selection_randomness_seed: config.source_selection_randomness_seed,
             })),
+            events: if config.generate_events {
+                Some(VecDeque::new())
+            } else {
+                None
+            },
+            non_finalized_blocks: if config.generate_events {
+                non_finalized_blocks
+            } else {
+                VecDeque::new()
+            },
         }
     }
 
+    /// Returns the next event that happened on the chain, if any.
+    ///
+    /// Events are returned in the order in which they happened. Always returns `None` if
+    /// [`Config::generate_events`] was `false`.
+    ///
+    /// Since only one chain is followed, [`blocks_tree::ChainEvent::BestBlockChanged`] only
+    /// contains retracted blocks when the chain is reset to the finalized block, and
+    /// [`blocks_tree::ChainEvent::ForksPruned`] is never generated.
+    pub fn next_chain_event(&mut self) -> Option<blocks_tree::ChainEvent> {
+        self.events.as_mut()?.pop_front()
+    }
+
     /// Builds a [`chain_information::ChainInformationRef`] struct corresponding to the current
     /// latest finalized block. Can later be used to reconstruct a chain.
     pub fn as_chain_information(&self) -> chain_information::ChainInformationRef {
@@ -126,11 +224,165 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
             .into()
     }
 
+    /// Serializes the verified non-finalized blocks, so that they can later be restored with
+    /// [`OptimisticHeadersSync::from_snapshot`], which avoids downloading and verifying them
+    /// again.
+    ///
+    /// The snapshot must be restored alongside with the value returned by
+    /// [`OptimisticHeadersSync::as_chain_information`] at the same time.
+    ///
+    /// Returns `None` if these blocks have been discarded, as explained in the documentation of
+    /// [`Config::max_non_finalized_blocks`].
+    pub fn snapshot(&self) -> Option<Vec<u8>> {
+        if self.chain.finalized_block_hash() != self.finalized_block_hash() {
+            return None;
+        }
+
+        Some(self.chain.snapshot())
+    }
+
+    /// Returns the parameters of a finality proof request to send to a source, or `None` if no
+    /// such request is needed.
+    ///
+    /// A request is needed if the non-finalized blocks have been discarded, as explained in the
+    /// documentation of [`Config::max_non_finalized_blocks`]. This typically happens when the
+    /// sources don't provide the justification of a block that changes the list of GrandPa
+    /// authorities, in which case no later justification can be verified either.
+    ///
+    /// The response must be passed to [`OptimisticHeadersSync::apply_finality_proof`].
+    pub fn finality_proof_request(&self) -> Option<FinalityProofRequest> {
+        let finalized_block_hash = self.finalized_block_hash();
+        if self.chain.finalized_block_hash() == finalized_block_hash {
+            return None;
+        }
+
+        Some(FinalityProofRequest {
+            block_hash: self.chain.best_block_hash(),
+            authorities_set_id: self
+                .finalized_chain_information
+                .chain_information_config
+                .chain_information
+                .grandpa_after_finalized_block_authorities_set_id,
+            last_finalized_hash: finalized_block_hash,
+        })
+    }
+
+    /// Verifies the given finality proof and finalizes the blocks it proves.
+    ///
+    /// The proof is verified starting from the actual finalized block. The block it finalizes
+    /// must be either the best block or one of its ancestors that hasn't been discarded, or a
+    /// descendant of the best block. The former is the case if the proof has been generated as
+    /// a response to the request returned by [`OptimisticHeadersSync::finality_proof_request`]
+    /// and the best block hasn't advanced by more than [`Config::max_non_finalized_blocks`] in
+    /// the meanwhile. The latter is the case if the best block doesn't have a justification, in
+    /// which case sources prove the finality of the first descendant that has one.
+    ///
+    /// The headers of the proof that descend from the best block are imported, and the
+    /// optimistic syncing then continues from the new best block.
+    ///
+    /// On failure, the state of the [`OptimisticHeadersSync`] is left untouched.
+    pub fn apply_finality_proof(
+        &mut self,
+        scale_encoded_proof: &[u8],
+        now_from_unix_epoch: Duration,
+    ) -> Result<FinalityProofSuccess, FinalityProofError> {
+        let mut proof_chain =
+            blocks_tree::NonFinalizedTree::new(self.finalized_chain_information.clone());
+        proof_chain
+            .verify_finality_proof(scale_encoded_proof, now_from_unix_epoch, |_| ())
+            .map_err(|(err, _)| FinalityProofError::Verify(err))?;
+
+        let hash = proof_chain.finalized_block_hash();
+        let mut imported_headers = Vec::new();
+        if hash != self.chain.finalized_block_hash()
+            && !self.chain.contains_non_finalized_block(&hash)
+        {
+            // The proof has been successfully verified, and decoding it again can't fail. The
+            // headers that follow the best block in the proof, if any, have all been finalized
+            // in `proof_chain` and form a chain that ends with the finalized block.
+            let best_block_hash = self.chain.best_block_hash();
+            let descendants = proof::decode(scale_encoded_proof)
+                .unwrap()
+                .into_iter()
+                .flat_map(|fragment| fragment.unknown_headers)
+                .skip_while(|header| *header.parent_hash != best_block_hash)
+                .collect::<Vec<_>>();
+            if descendants.last().map(|header| header.hash()) != Some(hash) {
+                return Err(FinalityProofError::UnknownBlock);
+            }
+
+            for header in descendants {
+                let scale_encoded_header = header.scale_encoding().fold(Vec::new(), |mut a, b| {
+                    a.extend_from_slice(b.as_ref());
+                    a
+                });
+
+                if let Some(events) = &mut self.events {
+                    let hash = header.hash();
+                    let old_best_hash = *header.parent_hash;
+                    events.push_back(blocks_tree::ChainEvent::BlockImported {
+                        hash,
+                        number: header.number,
+                        parent_hash: old_best_hash,
+                    });
+                    events.push_back(blocks_tree::ChainEvent::BestBlockChanged(
+                        blocks_tree::BestBlockChange {
+                            old_best_hash,
+                            new_best_hash: hash,
+                            new_best_number: header.number,
+                            retracted: Vec::new(),
+                            enacted: vec![hash],
+                        },
+                    ));
+                    self.non_finalized_blocks.push_back(hash);
+                }
+
+                imported_headers.push(scale_encoded_header);
+            }
+
+            // Once its finalized block is updated, `chain` would consist only of the new
+            // finalized block, which is exactly the content of `proof_chain`.
+            self.chain = proof_chain;
+
+            // The blocks being downloaded are now part of the chain.
+            self.sync
+                .as_mut()
+                .unwrap()
+                .reset_to_block(self.chain.best_block_header().number);
+        }
+
+        if hash != self.chain.finalized_block_hash() {
+            let _ = self.chain.set_finalized_block(&hash).unwrap();
+        }
+
+        self.on_chain_finalized();
+        Ok(FinalityProofSuccess {
+            finalized_block_number: self.chain.finalized_block_header().number,
+            finalized_block_hash: hash,
+            imported_headers,
+        })
+    }
+
     /// Inform the [`OptimisticHeadersSync`] of a new potential source of blocks.
     pub fn add_source(&mut self, source: TSrc) -> SourceId {
         self.sync.as_mut().unwrap().add_source(source)
     }
 
+    /// Updates the best block number reported by the given source.
+    ///
+    /// Blocks above this number are no longer requested from this source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn set_source_best_block(&mut self, source_id: SourceId, best_block_number: u64) {
+        self.sync
+            .as_mut()
+            .unwrap()
+            .set_source_best_block(source_id, best_block_number)
+    }
+
     /// Inform the [`OptimisticHeadersSync`] that a source of blocks is no longer available.
     ///
     /// This automatically cancels all the requests that have been emitted for this source.
@@ -147,6 +399,49 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
         self.sync.as_mut().unwrap().remove_source(source)
     }
 
+    /// Returns the user data of the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_user_data(&self, source_id: SourceId) -> &TSrc {
+        self.sync.as_ref().unwrap().source_user_data(source_id)
+    }
+
+    /// Returns the reputation of the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_reputation(&self, source_id: SourceId) -> &reputation::Reputation {
+        self.sync.as_ref().unwrap().source_reputation(source_id)
+    }
+
+    /// Returns the list of all the sources.
+    pub fn sources(&self) -> impl Iterator<Item = SourceId> + '_ {
+        self.sync.as_ref().unwrap().sources()
+    }
+
+    /// Lowers the reputation of the given source following a misbehaviour that has been
+    /// detected outside of the [`OptimisticHeadersSync`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn punish_source(
+        &mut self,
+        source_id: SourceId,
+        misbehaviour: reputation::Misbehaviour,
+    ) -> SourcePunishment {
+        self.sync
+            .as_mut()
+            .unwrap()
+            .punish_source(source_id, misbehaviour)
+    }
+
     /// Returns an iterator that extracts all requests that need to be started and requests that
     /// need to be cancelled.
     pub fn next_request_action(&mut self) -> Option<RequestAction<TRq, TSrc, RequestSuccessBlock>> {
@@ -181,8 +476,14 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
     /// It is encouraged to call this method multiple times in a row until
     /// [`ProcessOneOutcome::Idle`] is returned, interleaving any necessary high-priority
     /// operations (e.g. processing network sockets) in-between two calls.
-    pub fn process_one(&mut self) -> ProcessOneOutcome {
-        let mut to_process = match self.sync.take().unwrap().process_one() {
+    ///
+    /// Must be passed the time elapsed since the Unix Epoch, in order to reject blocks whose
+    /// slot is in the future and to update the reputation of the sources.
+    pub fn process_one(&mut self, now_from_unix_epoch: Duration) -> ProcessOneOutcome {
+        let mut sync = self.sync.take().unwrap();
+        sync.decay_reputations(now_from_unix_epoch);
+
+        let mut to_process = match sync.process_one() {
             Ok(tp) => tp,
             Err(sync) => {
                 self.sync = Some(sync);
@@ -196,8 +497,14 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
         // The loop stops whenever something unexpected (such as a verification error) happens.
         let mut finalized_update = false;
         let mut has_error = None;
+        let mut imported_headers = Vec::with_capacity(to_process.blocks.len());
         for block in to_process.blocks {
-            match self.chain.verify_header(block.scale_encoded_header.into()) {
+            // Blocks that are successfully inserted are always children of the best block.
+            let parent_hash = self.chain.best_block_hash();
+            match self
+                .chain
+                .verify_header(block.scale_encoded_header.clone(), now_from_unix_epoch)
+            {
                 Ok(blocks_tree::HeaderVerifySuccess::Insert {
                     block_height,
                     is_new_best,
@@ -218,6 +525,28 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
                     }
 
                     insert.insert(());
+
+                    if let Some(events) = &mut self.events {
+                        let hash =
+                            header::hash_from_scale_encoded_header(&block.scale_encoded_header);
+                        events.push_back(blocks_tree::ChainEvent::BlockImported {
+                            hash,
+                            number: block_height,
+                            parent_hash,
+                        });
+                        events.push_back(blocks_tree::ChainEvent::BestBlockChanged(
+                            blocks_tree::BestBlockChange {
+                                old_best_hash: parent_hash,
+                                new_best_hash: hash,
+                                new_best_number: block_height,
+                                retracted: Vec::new(),
+                                enacted: vec![hash],
+                            },
+                        ));
+                        self.non_finalized_blocks.push_back(hash);
+                    }
+
+                    imported_headers.push(block.scale_encoded_header);
                 }
                 Ok(blocks_tree::HeaderVerifySuccess::Duplicate) => {
                     debug_assert!(has_error.is_none());
@@ -236,9 +565,7 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
                     Ok(apply) => {
                         apply.apply();
                         finalized_update = true;
-                        self.finalized_chain_information
-                            .chain_information_config
-                            .chain_information = self.chain.as_chain_information().into();
+                        self.on_chain_finalized();
                     }
                     Err(err) => {
                         debug_assert!(has_error.is_none());
@@ -263,18 +590,47 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
         //
         // Consequently, if something unexpected happens, the strategy employed is to clear any
         // non-finalized block, cancel all requests in progress, and restart from the finalized
-        // block. No attempt is made to punish nodes.
+        // block. The source is only punished if the problem can be attributed to it with
+        // certainty, such as a block with an invalid signature.
         if let Some(has_error) = has_error {
+            let misbehaviour = match &has_error {
+                ResetCause::HeaderError(err) => reputation::header_verify_misbehaviour(err),
+                ResetCause::JustificationError(err) => {
+                    reputation::justification_verify_misbehaviour(err)
+                }
+                ResetCause::NonCanonical | ResetCause::UnexpectedBlockNumber { .. } => None,
+            };
+            let punished_source = match misbehaviour {
+                Some(misbehaviour) => to_process.report.punish_source(misbehaviour),
+                None => None,
+            };
+
             // As documented, the `chain` field does not contain the *actual* finalized block.
             // Instead, a new chain is recreated in order to reset to the actual finalized block.
+            let old_best_hash = self.chain.best_block_hash();
             self.chain =
                 blocks_tree::NonFinalizedTree::new(self.finalized_chain_information.clone());
+
+            if let Some(events) = &mut self.events {
+                if !self.non_finalized_blocks.is_empty() {
+                    events.push_back(blocks_tree::ChainEvent::BestBlockChanged(
+                        blocks_tree::BestBlockChange {
+                            old_best_hash,
+                            new_best_hash: self.chain.best_block_hash(),
+                            new_best_number: self.chain.best_block_header().number,
+                            retracted: self.non_finalized_blocks.drain(..).rev().collect(),
+                            enacted: Vec::new(),
+                        },
+                    ));
+                }
+            }
             let sync = to_process
                 .report
                 .reset_to_finalized(self.chain.finalized_block_header().number);
             self.sync = Some(sync);
             return ProcessOneOutcome::Reset {
                 reason: has_error,
+                punished_source,
                 new_best_block_number: self.chain.best_block_header().number,
                 new_best_block_hash: self.chain.best_block_hash(),
             };
@@ -285,18 +641,20 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
             .update_block_height(self.chain.best_block_header().number);
         self.sync = Some(sync);
 
-        // As documented, the finalized block tracked by the `chain` field is not the actual
-        // finalized block. The optimistic sync state machine tracks the actual finalized block
-        // separately, and the finalized block of `chain` is always set to the best block.
+        // As documented, the finalized block tracked by the `chain` field might not be the
+        // actual finalized block. The optimistic sync state machine tracks the actual finalized
+        // block separately, and the finalized block of `chain` is set to the best block if there
+        // are too many non-finalized blocks.
         let best_block_hash = self.chain.best_block_hash();
-        // `set_finalized_block` will error if best block == finalized block, which will
-        // frequently and legitimately happen. As such, we ignore errors.
-        let _ = self.chain.set_finalized_block(&best_block_hash);
+        if self.chain.len() > self.max_non_finalized_blocks {
+            let _ = self.chain.set_finalized_block(&best_block_hash);
+        }
 
         // Success! 🎉
         ProcessOneOutcome::Updated {
             best_block_hash,
             best_block_number: self.chain.best_block_header().number,
+            imported_headers,
             finalized_block: if finalized_update {
                 let number = self
                     .finalized_chain_information
@@ -316,6 +674,53 @@ impl<TRq, TSrc> OptimisticHeadersSync<TRq, TSrc> {
             },
         }
     }
+
+    /// Returns the hash of the actual finalized block.
+    fn finalized_block_hash(&self) -> [u8; 32] {
+        self.finalized_chain_information
+            .chain_information_config
+            .chain_information
+            .finalized_block_header
+            .hash()
+    }
+
+    /// Must be called after the finalized block of `chain` has been updated to a block that is
+    /// known to be finalized. Updates the actual finalized block and generates the corresponding
+    /// event.
+    fn on_chain_finalized(&mut self) {
+        self.finalized_chain_information
+            .chain_information_config
+            .chain_information = self.chain.as_chain_information().into();
+
+        if let Some(events) = &mut self.events {
+            let hash = self.chain.finalized_block_hash();
+            if let Some(pos) = self.non_finalized_blocks.iter().position(|h| *h == hash) {
+                events.push_back(blocks_tree::ChainEvent::Finalized {
+                    hash,
+                    number: self.chain.finalized_block_header().number,
+                    finalized: self.non_finalized_blocks.drain(..=pos).collect(),
+                });
+            }
+        }
+    }
+}
+
+/// Builds the configuration of the [`blocks_tree::NonFinalizedTree`] of an
+/// [`OptimisticHeadersSync`], whose finalized block is the actual finalized block.
+fn blocks_tree_config(config: &Config) -> blocks_tree::Config {
+    blocks_tree::Config {
+        chain_information_config: config.chain_information_config.clone(),
+        blocks_capacity: usize::try_from(config.blocks_request_granularity.get())
+            .unwrap_or(usize::max_value()),
+        fork_choice: blocks_tree::ForkChoice::from_consensus(
+            &config.chain_information_config.chain_information.consensus,
+        ),
+        // The finalized block of `chain` might not be the actual finalized block, as explained
+        // in the documentation of the `chain` field. The events it would generate are therefore
+        // misleading, and the events are instead generated by the `OptimisticHeadersSync`
+        // itself.
+        generate_events: false,
+    }
 }
 
 /// Single block in the outcome of a request. A list of these must be passed to
@@ -342,6 +747,9 @@ pub enum ProcessOneOutcome {
     Reset {
         /// Problem that happened and caused the reset.
         reason: ResetCause,
+        /// If the problem can be attributed to the source that has provided the blocks, contains
+        /// the punishment that has been applied to it.
+        punished_source: Option<SourcePunishment>,
         /// Number of the new best block. Identical to the number of the finalized block.
         new_best_block_number: u64,
         /// Hash of the new best block. Identical to the hash of the finalized block.
@@ -354,11 +762,49 @@ pub enum ProcessOneOutcome {
         best_block_number: u64,
         /// Hash of the new best block.
         best_block_hash: [u8; 32],
+        /// SCALE-encoded headers of the blocks that have been imported, ordered by increasing
+        /// block number. The last one is the new best block.
+        imported_headers: Vec<Vec<u8>>,
         /// Number and hash of the finalized block. `None` if the finalized block hasn't changed.
         finalized_block: Option<(u64, [u8; 32])>,
     },
 }
 
+/// Finality proof request to send to a source. See
+/// [`OptimisticHeadersSync::finality_proof_request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalityProofRequest {
+    /// Hash of the block whose finality must be proven.
+    pub block_hash: [u8; 32],
+    /// Identifier of the GrandPa authorities set of the latest finalized block.
+    pub authorities_set_id: u64,
+    /// Hash of the latest finalized block.
+    pub last_finalized_hash: [u8; 32],
+}
+
+/// Outcome of a successful call to [`OptimisticHeadersSync::apply_finality_proof`].
+#[derive(Debug)]
+pub struct FinalityProofSuccess {
+    /// Number of the new finalized block.
+    pub finalized_block_number: u64,
+    /// Hash of the new finalized block.
+    pub finalized_block_hash: [u8; 32],
+    /// SCALE-encoded headers of the blocks of the proof that descend from the former best block
+    /// and have been imported, ordered by increasing block number. If not empty, the last one
+    /// is the new best block.
+    pub imported_headers: Vec<Vec<u8>>,
+}
+
+/// Error that can happen when calling [`OptimisticHeadersSync::apply_finality_proof`].
+#[derive(Debug, derive_more::Display)]
+pub enum FinalityProofError {
+    /// Error while verifying the proof.
+    Verify(blocks_tree::FinalityProofVerifyError),
+    /// The block finalized by the proof isn't part of the chain being followed.
+    #[display(fmt = "The block finalized by the proof isn't part of the chain being followed.")]
+    UnknownBlock,
+}
+
 /// Problem that happened and caused the reset.
 #[derive(Debug, derive_more::Display)]
 pub enum ResetCause {
@@ -377,3 +823,424 @@ pub enum ResetCause {
         actual: u64,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::blocks_tree::{
+        tests::{aura_header, config, justification},
+        BestBlockChange, ChainEvent, FinalityProofVerifyError,
+    };
+    use crate::finality::proof;
+
+    const NOW: Duration = Duration::from_secs(1 << 32);
+
+    /// Starts the next request, and checks that it targets the given block height.
+    fn start_request(sync: &mut OptimisticHeadersSync<(), ()>, expected_height: u64) -> RequestId {
+        match sync.next_request_action() {
+            Some(RequestAction::Start {
+                block_height,
+                start,
+                ..
+            }) => {
+                assert_eq!(block_height.get(), expected_height);
+                start.start(())
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn sources_behind_not_requested() {
+        let mut sync = OptimisticHeadersSync::<(), ()>::new(Config {
+            chain_information_config: config().chain_information_config,
+            sources_capacity: 4,
+            blocks_request_granularity: NonZeroU32::new(3).unwrap(),
+            download_ahead_blocks: 3,
+            source_selection_randomness_seed: 0,
+            generate_events: true,
+            max_non_finalized_blocks: 0,
+        });
+        let source = sync.add_source(());
+
+        sync.set_source_best_block(source, 0);
+        assert!(sync.next_request_action().is_none());
+
+        sync.set_source_best_block(source, 1);
+        start_request(&mut sync, 1);
+    }
+
+    #[test]
+    fn events_order() {
+        let mut sync = OptimisticHeadersSync::<(), ()>::new(Config {
+            chain_information_config: config().chain_information_config,
+            sources_capacity: 4,
+            blocks_request_granularity: NonZeroU32::new(3).unwrap(),
+            download_ahead_blocks: 3,
+            source_selection_randomness_seed: 0,
+            generate_events: true,
+            max_non_finalized_blocks: 0,
+        });
+        let genesis_hash = sync.chain.finalized_block_hash();
+        sync.add_source(());
+
+        let block1 = aura_header(genesis_hash, 1, 1);
+        let hash1 = header::hash_from_scale_encoded_header(&block1);
+        let block2 = aura_header(hash1, 2, 2);
+        let hash2 = header::hash_from_scale_encoded_header(&block2);
+        let block3 = aura_header(hash2, 3, 3);
+        let hash3 = header::hash_from_scale_encoded_header(&block3);
+
+        // Blocks 1 to 3, with a justification for block 2.
+        let request = start_request(&mut sync, 1);
+        let blocks = vec![
+            RequestSuccessBlock {
+                scale_encoded_header: block1,
+                scale_encoded_justification: None,
+            },
+            RequestSuccessBlock {
+                scale_encoded_header: block2,
+                scale_encoded_justification: Some(justification(hash2, 2)),
+            },
+            RequestSuccessBlock {
+                scale_encoded_header: block3,
+                scale_encoded_justification: None,
+            },
+        ];
+        let _ = sync.finish_request(request, Ok(blocks.into_iter()));
+        assert!(matches!(
+            sync.process_one(NOW),
+            ProcessOneOutcome::Updated {
+                best_block_number: 3,
+                finalized_block: Some((2, _)),
+                ..
+            }
+        ));
+
+        let new_best = |old, new, number| {
+            ChainEvent::BestBlockChanged(BestBlockChange {
+                old_best_hash: old,
+                new_best_hash: new,
+                new_best_number: number,
+                retracted: Vec::new(),
+                enacted: vec![new],
+            })
+        };
+        let expected = vec![
+            ChainEvent::BlockImported {
+                hash: hash1,
+                number: 1,
+                parent_hash: genesis_hash,
+            },
+            new_best(genesis_hash, hash1, 1),
+            ChainEvent::BlockImported {
+                hash: hash2,
+                number: 2,
+                parent_hash: hash1,
+            },
+            new_best(hash1, hash2, 2),
+            ChainEvent::Finalized {
+                hash: hash2,
+                number: 2,
+                finalized: vec![hash1, hash2],
+            },
+            ChainEvent::BlockImported {
+                hash: hash3,
+                number: 3,
+                parent_hash: hash2,
+            },
+            new_best(hash2, hash3, 3),
+        ];
+        for event in expected {
+            assert_eq!(sync.next_chain_event(), Some(event));
+        }
+        assert_eq!(sync.next_chain_event(), None);
+
+        // A block that isn't a child of the best block resets the chain to the finalized block,
+        // retracting block 3.
+        let request = start_request(&mut sync, 4);
+        let blocks = vec![RequestSuccessBlock {
+            scale_encoded_header: aura_header([0xff; 32], 4, 4),
+            scale_encoded_justification: None,
+        }];
+        let _ = sync.finish_request(request, Ok(blocks.into_iter()));
+        assert!(matches!(
+            sync.process_one(NOW),
+            ProcessOneOutcome::Reset {
+                new_best_block_number: 2,
+                ..
+            }
+        ));
+        assert_eq!(
+            sync.next_chain_event(),
+            Some(ChainEvent::BestBlockChanged(BestBlockChange {
+                old_best_hash: hash3,
+                new_best_hash: hash2,
+                new_best_number: 2,
+                retracted: vec![hash3],
+                enacted: Vec::new(),
+            }))
+        );
+        assert_eq!(sync.next_chain_event(), None);
+    }
+
+    /// Sync, and hashes and SCALE-encoded headers of its blocks.
+    type SyncWithBlocks = (OptimisticHeadersSync<(), ()>, Vec<[u8; 32]>, Vec<Vec<u8>>);
+
+    /// Builds a sync with the given limit of non-finalized blocks, and imports blocks 1 to 3
+    /// into it.
+    fn sync_with_three_blocks(max_non_finalized_blocks: usize) -> SyncWithBlocks {
+        let mut sync = OptimisticHeadersSync::<(), ()>::new(sync_config(max_non_finalized_blocks));
+        sync.add_source(());
+
+        let mut hashes = vec![sync.chain.finalized_block_hash()];
+        let mut headers = Vec::new();
+        let request = start_request(&mut sync, 1);
+        let blocks = (1..=3)
+            .map(|number| {
+                let scale_encoded_header = aura_header(*hashes.last().unwrap(), number, number);
+                hashes.push(header::hash_from_scale_encoded_header(
+                    &scale_encoded_header,
+                ));
+                headers.push(scale_encoded_header.clone());
+                RequestSuccessBlock {
+                    scale_encoded_header,
+                    scale_encoded_justification: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let _ = sync.finish_request(request, Ok(blocks.into_iter()));
+        assert!(matches!(
+            sync.process_one(NOW),
+            ProcessOneOutcome::Updated {
+                best_block_number: 3,
+                finalized_block: None,
+                ..
+            }
+        ));
+
+        hashes.remove(0);
+        (sync, hashes, headers)
+    }
+
+    fn sync_config(max_non_finalized_blocks: usize) -> Config {
+        Config {
+            chain_information_config: config().chain_information_config,
+            sources_capacity: 4,
+            blocks_request_granularity: NonZeroU32::new(3).unwrap(),
+            download_ahead_blocks: 3,
+            source_selection_randomness_seed: 0,
+            generate_events: true,
+            max_non_finalized_blocks,
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let (sync, hashes, _) = sync_with_three_blocks(16);
+        let snapshot = sync.snapshot().unwrap();
+
+        // The restored sync continues from the best block of the snapshot.
+        let mut restored_hashes = Vec::new();
+        let mut config = sync_config(16);
+        config.chain_information_config.chain_information = sync.as_chain_information().into();
+        let mut sync = OptimisticHeadersSync::<(), ()>::from_snapshot(config, &snapshot, |h| {
+            restored_hashes.push(h.hash())
+        })
+        .unwrap();
+        assert_eq!(restored_hashes, hashes);
+        assert_eq!(sync.next_chain_event(), None);
+        sync.add_source(());
+
+        let block4 = aura_header(hashes[2], 4, 4);
+        let hash4 = header::hash_from_scale_encoded_header(&block4);
+        let request = start_request(&mut sync, 4);
+        let blocks = vec![RequestSuccessBlock {
+            scale_encoded_header: block4,
+            scale_encoded_justification: Some(justification(hash4, 4)),
+        }];
+        let _ = sync.finish_request(request, Ok(blocks.into_iter()));
+        assert!(matches!(
+            sync.process_one(NOW),
+            ProcessOneOutcome::Updated {
+                best_block_number: 4,
+                finalized_block: Some((4, _)),
+                ..
+            }
+        ));
+
+        // The restored blocks are reported as finalized alongside with block 4.
+        let _ = sync.next_chain_event();
+        let _ = sync.next_chain_event();
+        assert_eq!(
+            sync.next_chain_event(),
+            Some(ChainEvent::Finalized {
+                hash: hash4,
+                number: 4,
+                finalized: vec![hashes[0], hashes[1], hashes[2], hash4],
+            })
+        );
+
+        // Nothing is left to be restored after the finalization.
+        let snapshot = sync.snapshot().unwrap();
+        let mut config = sync_config(16);
+        config.chain_information_config.chain_information = sync.as_chain_information().into();
+        assert!(
+            OptimisticHeadersSync::<(), ()>::from_snapshot(config, &snapshot, |_| panic!()).is_ok()
+        );
+    }
+
+    #[test]
+    fn snapshot_unavailable_when_discarded() {
+        let (sync, _, _) = sync_with_three_blocks(2);
+        assert!(sync.snapshot().is_none());
+
+        // A snapshot can't be restored on top of a different finalized block.
+        let (sync, hashes, _) = sync_with_three_blocks(3);
+        let snapshot = sync.snapshot().unwrap();
+        let mut config = sync_config(3);
+        config
+            .chain_information_config
+            .chain_information
+            .finalized_block_header = header::decode(&aura_header(hashes[0], 1, 5))
+            .unwrap()
+            .into();
+        assert!(matches!(
+            OptimisticHeadersSync::<(), ()>::from_snapshot(config, &snapshot, |_| {}),
+            Err(blocks_tree::SnapshotDecodeError::FinalizedBlockMismatch)
+        ));
+    }
+
+    #[test]
+    fn finality_proof_catches_up() {
+        let (mut sync, hashes, headers) = sync_with_three_blocks(2);
+        let genesis_hash = config()
+            .chain_information_config
+            .chain_information
+            .finalized_block_header
+            .hash();
+        assert!(sync.snapshot().is_none());
+        assert_eq!(
+            sync.finality_proof_request(),
+            Some(FinalityProofRequest {
+                block_hash: hashes[2],
+                authorities_set_id: 0,
+                last_finalized_hash: genesis_hash,
+            })
+        );
+
+        // Proof made of the three blocks, with the given justification for block 3.
+        let build_proof = |justification: &[u8]| {
+            let blocks = headers
+                .iter()
+                .enumerate()
+                .map(|(n, header)| proof::BuildBlock {
+                    scale_encoded_header: header,
+                    scale_encoded_justification: if n == 2 { Some(justification) } else { None },
+                    enacts_authorities_change: false,
+                });
+            proof::build(&hashes[2], blocks).unwrap().unwrap()
+        };
+
+        // Invalid signature. Nothing is finalized.
+        let mut bad_justification = justification(hashes[2], 3);
+        bad_justification[81] ^= 1;
+        assert!(matches!(
+            sync.apply_finality_proof(&build_proof(&bad_justification), NOW),
+            Err(FinalityProofError::Verify(
+                FinalityProofVerifyError::InvalidJustification(_)
+            ))
+        ));
+        assert!(sync.finality_proof_request().is_some());
+
+        // Valid proof of a block of a different chain.
+        let fork = aura_header(genesis_hash, 1, 7);
+        let fork_hash = header::hash_from_scale_encoded_header(&fork);
+        let fork_justification = justification(fork_hash, 1);
+        let fork_proof = proof::build(
+            &fork_hash,
+            core::iter::once(proof::BuildBlock {
+                scale_encoded_header: &fork,
+                scale_encoded_justification: Some(&fork_justification),
+                enacts_authorities_change: false,
+            }),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(matches!(
+            sync.apply_finality_proof(&fork_proof, NOW),
+            Err(FinalityProofError::UnknownBlock)
+        ));
+        assert_eq!(sync.as_chain_information().finalized_block_header.number, 0);
+
+        // Valid proof of the best block.
+        let success = sync
+            .apply_finality_proof(&build_proof(&justification(hashes[2], 3)), NOW)
+            .unwrap();
+        assert_eq!(success.finalized_block_number, 3);
+        assert_eq!(success.finalized_block_hash, hashes[2]);
+        assert!(success.imported_headers.is_empty());
+        assert!(sync.finality_proof_request().is_none());
+        assert!(sync.snapshot().is_some());
+        for _ in 0..6 {
+            let _ = sync.next_chain_event().unwrap();
+        }
+        assert_eq!(
+            sync.next_chain_event(),
+            Some(ChainEvent::Finalized {
+                hash: hashes[2],
+                number: 3,
+                finalized: hashes.clone(),
+            })
+        );
+    }
+
+    #[test]
+    fn finality_proof_of_descendant() {
+        let (mut sync, mut hashes, mut headers) = sync_with_three_blocks(2);
+        assert_eq!(sync.finality_proof_request().unwrap().block_hash, hashes[2]);
+
+        // The best block doesn't have a justification, and the source proves the finality of
+        // its first descendant that has one, which hasn't been downloaded yet.
+        for number in 4..=5 {
+            let header = aura_header(*hashes.last().unwrap(), number, number);
+            hashes.push(header::hash_from_scale_encoded_header(&header));
+            headers.push(header);
+        }
+        let justification5 = justification(hashes[4], 5);
+        let blocks = headers
+            .iter()
+            .enumerate()
+            .map(|(n, header)| proof::BuildBlock {
+                scale_encoded_header: header,
+                scale_encoded_justification: if n == 4 {
+                    Some(&justification5[..])
+                } else {
+                    None
+                },
+                enacts_authorities_change: false,
+            });
+        let proof = proof::build(&hashes[2], blocks).unwrap().unwrap();
+
+        let success = sync.apply_finality_proof(&proof, NOW).unwrap();
+        assert_eq!(success.finalized_block_number, 5);
+        assert_eq!(success.finalized_block_hash, hashes[4]);
+        assert_eq!(success.imported_headers, headers[3..].to_vec());
+        assert!(sync.finality_proof_request().is_none());
+
+        // Downloading continues after the new best block.
+        let _ = start_request(&mut sync, 6);
+
+        for _ in 0..10 {
+            let _ = sync.next_chain_event().unwrap();
+        }
+        assert_eq!(
+            sync.next_chain_event(),
+            Some(ChainEvent::Finalized {
+                hash: hashes[4],
+                number: 5,
+                finalized: hashes.clone(),
+            })
+        );
+    }
+}
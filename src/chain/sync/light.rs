@@ -0,0 +1,1216 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Light client syncing.
+//!
+//! A light client only verifies the headers of the blocks of the chain. It doesn't store the
+//! storage of these blocks and can't execute them. Instead, it asks the sources for the
+//! information it needs, alongside with a proof that this information is correct.
+//!
+//! The [`LightSync`] is a layer around a [`headers_optimistic::OptimisticHeadersSync`]. In
+//! addition to syncing the chain, it keeps the most recently verified headers, and can perform
+//! *on-demand requests* targetting any of these headers:
+//!
+//! - Storage reads. The source sends back a proof of the storage values, verified against the
+//!   state root found in the header.
+//! - Runtime calls. The source sends back a proof containing all the storage entries accessed
+//!   by the runtime during the call. The call is then performed locally, with the storage
+//!   accesses answered from the proof.
+//! - Block bodies. The list of extrinsics sent back by the source is verified against the
+//!   extrinsics root found in the header.
+//!
+//! # Usage
+//!
+//! The syncing itself works the same way as with a
+//! [`headers_optimistic::OptimisticHeadersSync`].
+//!
+//! Call [`LightSync::queue_request`] to queue an on-demand request. If the verified response is
+//! found in the cache of the [`LightSync`], it is returned immediately. Otherwise, the request is
+//! assigned an [`OnDemandId`].
+//!
+//! Call [`LightSync::next_on_demand_request`] in order to obtain the requests that must be sent
+//! to a source, and [`LightSync::finish_on_demand_request`] when the source has answered. If the
+//! request fails or the response fails to verify, the request is automatically attempted again,
+//! preferably on a different source, up to [`Config::on_demand_max_attempts`] times.
+//!
+//! Call [`LightSync::snapshot`] alongside with [`LightSync::as_chain_information`] in order to
+//! save the verified non-finalized blocks, and [`LightSync::from_snapshot`] to restore them
+//! without having to download and verify them again.
+
+use super::super::{blocks_tree, chain_information};
+use super::{headers_optimistic, reputation};
+use crate::{executor, header, trie};
+
+use alloc::collections::VecDeque;
+use core::{convert::TryFrom as _, num::NonZeroU32, time::Duration};
+use hashbrown::HashMap;
+
+pub use headers_optimistic::{
+    FinalityProofError, FinalityProofRequest, FinalityProofSuccess, FinishRequestOutcome,
+    ProcessOneOutcome, RequestAction, RequestFail, RequestId, RequestSuccessBlock, ResetCause,
+    SourceId, SourcePunishment, Start,
+};
+
+/// Configuration for the [`LightSync`].
+#[derive(Debug)]
+pub struct Config {
+    /// Configuration of the underlying headers syncing.
+    pub headers_sync: headers_optimistic::Config,
+
+    /// Maximum number of verified headers to keep. On-demand requests can only target one of
+    /// these headers. The oldest headers are discarded first.
+    pub headers_capacity: usize,
+
+    /// Maximum number of verified responses to on-demand requests to keep in cache. The oldest
+    /// responses are discarded first.
+    pub cache_capacity: usize,
+
+    /// Maximum number of times an on-demand request is sent out before giving up.
+    pub on_demand_max_attempts: NonZeroU32,
+}
+
+/// Light client syncing.
+pub struct LightSync<TRq, TSrc> {
+    /// Underlying headers syncing. Manages the sources.
+    headers_sync: headers_optimistic::OptimisticHeadersSync<TRq, TSrc>,
+
+    /// Headers that have been verified by `headers_sync`, indexed by block hash.
+    headers: HashMap<[u8; 32], KnownHeader, fnv::FnvBuildHasher>,
+
+    /// Keys of [`LightSync::headers`], from the oldest inserted to the most recently inserted.
+    headers_order: VecDeque<[u8; 32]>,
+
+    /// Value passed by [`Config::headers_capacity`].
+    headers_capacity: usize,
+
+    /// Hash of the current best block.
+    best_block_hash: [u8; 32],
+
+    /// On-demand requests that haven't been answered yet. Indices are [`OnDemandId`]s.
+    on_demand: slab::Slab<OnDemand<TRq>>,
+
+    /// Value passed by [`Config::on_demand_max_attempts`].
+    on_demand_max_attempts: NonZeroU32,
+
+    /// Verified responses to on-demand requests.
+    cache: HashMap<OnDemandRequest, OnDemandResponse, fnv::FnvBuildHasher>,
+
+    /// Keys of [`LightSync::cache`], from the oldest inserted to the most recently inserted.
+    cache_order: VecDeque<OnDemandRequest>,
+
+    /// Value passed by [`Config::cache_capacity`].
+    cache_capacity: usize,
+}
+
+/// Information about a verified header that is necessary in order to verify responses.
+#[derive(Debug, Copy, Clone)]
+struct KnownHeader {
+    state_root: [u8; 32],
+    extrinsics_root: [u8; 32],
+}
+
+struct OnDemand<TRq> {
+    request: OnDemandRequest,
+    /// Header of the block targetted by the request. Copied when the request is queued, as the
+    /// header might be removed from [`LightSync::headers`] in the meanwhile.
+    header: KnownHeader,
+    /// If `Some`, the request has been sent to the given source.
+    in_progress: Option<(SourceId, TRq)>,
+    /// List of sources the request has been sent to, including the one in `in_progress`. May
+    /// contain duplicates.
+    attempted_sources: Vec<SourceId>,
+}
+
+impl<TRq, TSrc> LightSync<TRq, TSrc> {
+    /// Builds a new [`LightSync`].
+    pub fn new(config: Config) -> Self {
+        let headers_sync = headers_optimistic::OptimisticHeadersSync::new(config.headers_sync);
+        Self::with_headers_sync(
+            headers_sync,
+            config.headers_capacity,
+            config.cache_capacity,
+            config.on_demand_max_attempts,
+            Vec::new(),
+        )
+    }
+
+    /// Builds a new [`LightSync`] containing the non-finalized blocks found in a snapshot
+    /// previously generated with [`LightSync::snapshot`].
+    ///
+    /// See [`headers_optimistic::OptimisticHeadersSync::from_snapshot`]. On-demand requests can
+    /// target the restored blocks.
+    pub fn from_snapshot(
+        config: Config,
+        snapshot: &[u8],
+    ) -> Result<Self, blocks_tree::SnapshotDecodeError> {
+        let mut restored_headers = Vec::new();
+        let headers_sync = headers_optimistic::OptimisticHeadersSync::from_snapshot(
+            config.headers_sync,
+            snapshot,
+            |header| {
+                let known_header = KnownHeader {
+                    state_root: *header.state_root,
+                    extrinsics_root: *header.extrinsics_root,
+                };
+                restored_headers.push((header.hash(), known_header));
+            },
+        )?;
+
+        Ok(Self::with_headers_sync(
+            headers_sync,
+            config.headers_capacity,
+            config.cache_capacity,
+            config.on_demand_max_attempts,
+            restored_headers,
+        ))
+    }
+
+    /// Builds a new [`LightSync`] around the given headers syncing. `restored_headers` contains
+    /// the non-finalized blocks that the headers syncing has been restored with, in increasing
+    /// block number.
+    fn with_headers_sync(
+        headers_sync: headers_optimistic::OptimisticHeadersSync<TRq, TSrc>,
+        headers_capacity: usize,
+        cache_capacity: usize,
+        on_demand_max_attempts: NonZeroU32,
+        restored_headers: Vec<([u8; 32], KnownHeader)>,
+    ) -> Self {
+        let mut sync = LightSync {
+            // The restored blocks are all ancestors of the best block, and the last one is
+            // therefore the best block.
+            best_block_hash: match restored_headers.last() {
+                Some((block_hash, _)) => *block_hash,
+                None => headers_sync
+                    .as_chain_information()
+                    .finalized_block_header
+                    .hash(),
+            },
+            headers_sync,
+            headers: HashMap::with_capacity_and_hasher(headers_capacity, Default::default()),
+            headers_order: VecDeque::with_capacity(headers_capacity),
+            headers_capacity,
+            on_demand: slab::Slab::new(),
+            on_demand_max_attempts,
+            cache: HashMap::with_capacity_and_hasher(cache_capacity, Default::default()),
+            cache_order: VecDeque::with_capacity(cache_capacity),
+            cache_capacity,
+        };
+
+        let finalized_header = sync
+            .headers_sync
+            .as_chain_information()
+            .finalized_block_header;
+        let finalized_block_hash = finalized_header.hash();
+        let finalized_header = KnownHeader {
+            state_root: *finalized_header.state_root,
+            extrinsics_root: *finalized_header.extrinsics_root,
+        };
+        sync.insert_header(finalized_block_hash, finalized_header);
+        for (block_hash, header) in restored_headers {
+            sync.insert_header(block_hash, header);
+        }
+
+        sync
+    }
+
+    /// Builds a [`chain_information::ChainInformationRef`] struct corresponding to the current
+    /// latest finalized block. Can later be used to reconstruct a chain.
+    pub fn as_chain_information(&self) -> chain_information::ChainInformationRef {
+        self.headers_sync.as_chain_information()
+    }
+
+    /// Serializes the verified non-finalized blocks, so that they can later be restored with
+    /// [`LightSync::from_snapshot`].
+    ///
+    /// See [`headers_optimistic::OptimisticHeadersSync::snapshot`].
+    pub fn snapshot(&self) -> Option<Vec<u8>> {
+        self.headers_sync.snapshot()
+    }
+
+    /// See [`headers_optimistic::OptimisticHeadersSync::finality_proof_request`].
+    pub fn finality_proof_request(&self) -> Option<FinalityProofRequest> {
+        self.headers_sync.finality_proof_request()
+    }
+
+    /// See [`headers_optimistic::OptimisticHeadersSync::apply_finality_proof`]. On-demand
+    /// requests can target the headers imported from the proof.
+    pub fn apply_finality_proof(
+        &mut self,
+        scale_encoded_proof: &[u8],
+        now_from_unix_epoch: Duration,
+    ) -> Result<FinalityProofSuccess, FinalityProofError> {
+        let success = self
+            .headers_sync
+            .apply_finality_proof(scale_encoded_proof, now_from_unix_epoch)?;
+
+        for scale_encoded_header in &success.imported_headers {
+            // Headers have already been verified, and decoding them can't fail.
+            let decoded = header::decode(scale_encoded_header).unwrap();
+            let known_header = KnownHeader {
+                state_root: *decoded.state_root,
+                extrinsics_root: *decoded.extrinsics_root,
+            };
+            let hash = header::hash_from_scale_encoded_header(scale_encoded_header);
+            self.insert_header(hash, known_header);
+            self.best_block_hash = hash;
+        }
+
+        Ok(success)
+    }
+
+    /// Returns the hash of the current best block.
+    pub fn best_block_hash(&self) -> [u8; 32] {
+        self.best_block_hash
+    }
+
+    /// See [`headers_optimistic::OptimisticHeadersSync::next_chain_event`].
+    pub fn next_chain_event(&mut self) -> Option<blocks_tree::ChainEvent> {
+        self.headers_sync.next_chain_event()
+    }
+
+    /// Returns `true` if the header of the given block is known, in which case on-demand
+    /// requests can target it.
+    pub fn knows_block(&self, block_hash: &[u8; 32]) -> bool {
+        self.headers.contains_key(block_hash)
+    }
+
+    /// Inform the [`LightSync`] of a new potential source of blocks.
+    pub fn add_source(&mut self, source: TSrc) -> SourceId {
+        self.headers_sync.add_source(source)
+    }
+
+    /// Updates the best block number reported by the given source.
+    ///
+    /// Blocks above this number are no longer requested from this source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn set_source_best_block(&mut self, source_id: SourceId, best_block_number: u64) {
+        self.headers_sync
+            .set_source_best_block(source_id, best_block_number)
+    }
+
+    /// Inform the [`LightSync`] that a source of blocks is no longer available.
+    ///
+    /// This automatically cancels all the requests that have been emitted for this source,
+    /// including the on-demand requests. This list of requests is returned as part of this
+    /// function. On-demand requests are automatically attempted again on a different source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn remove_source<'a>(
+        &'a mut self,
+        source: SourceId,
+    ) -> (TSrc, impl Iterator<Item = TRq> + 'a) {
+        let mut on_demand_requests = Vec::new();
+        for (_, request) in self.on_demand.iter_mut() {
+            if request
+                .in_progress
+                .as_ref()
+                .map_or(false, |(s, _)| *s == source)
+            {
+                on_demand_requests.push(request.in_progress.take().unwrap().1);
+            }
+        }
+
+        let (user_data, blocks_requests) = self.headers_sync.remove_source(source);
+        let requests = on_demand_requests
+            .into_iter()
+            .chain(blocks_requests.map(|(_, rq)| rq));
+        (user_data, requests)
+    }
+
+    /// Returns the user data of the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_user_data(&self, source_id: SourceId) -> &TSrc {
+        self.headers_sync.source_user_data(source_id)
+    }
+
+    /// Returns an iterator that extracts all blocks requests that need to be started and
+    /// blocks requests that need to be cancelled.
+    pub fn next_request_action(&mut self) -> Option<RequestAction<TRq, TSrc, RequestSuccessBlock>> {
+        self.headers_sync.next_request_action()
+    }
+
+    /// Update the [`LightSync`] with the outcome of a blocks request.
+    ///
+    /// Returns the user data that was associated to that request.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`RequestId`] is invalid.
+    ///
+    pub fn finish_request<'a>(
+        &'a mut self,
+        request_id: RequestId,
+        outcome: Result<impl Iterator<Item = RequestSuccessBlock>, RequestFail>,
+    ) -> (TRq, FinishRequestOutcome<'a, TSrc>) {
+        self.headers_sync.finish_request(request_id, outcome)
+    }
+
+    /// Process a batch of blocks in the queue of verification.
+    ///
+    /// See [`headers_optimistic::OptimisticHeadersSync::process_one`].
+    pub fn process_one(&mut self, now_from_unix_epoch: Duration) -> ProcessOneOutcome {
+        let outcome = self.headers_sync.process_one(now_from_unix_epoch);
+
+        match &outcome {
+            ProcessOneOutcome::Idle => {}
+            ProcessOneOutcome::Updated {
+                best_block_hash,
+                imported_headers,
+                ..
+            } => {
+                for scale_encoded_header in imported_headers {
+                    // Headers have already been verified, and decoding them can't fail.
+                    let decoded = header::decode(scale_encoded_header).unwrap();
+                    let known_header = KnownHeader {
+                        state_root: *decoded.state_root,
+                        extrinsics_root: *decoded.extrinsics_root,
+                    };
+                    self.insert_header(
+                        header::hash_from_scale_encoded_header(scale_encoded_header),
+                        known_header,
+                    );
+                }
+
+                self.best_block_hash = *best_block_hash;
+            }
+            ProcessOneOutcome::Reset {
+                new_best_block_hash,
+                ..
+            } => {
+                self.best_block_hash = *new_best_block_hash;
+            }
+        }
+
+        outcome
+    }
+
+    /// Queues an on-demand request.
+    ///
+    /// If a verified response to an identical request is found in cache, it is returned
+    /// immediately.
+    pub fn queue_request(&mut self, request: OnDemandRequest) -> Result<Queued, QueueError> {
+        if let Some(response) = self.cache.get(&request) {
+            return Ok(Queued::Cached(response.clone()));
+        }
+
+        let header = *self
+            .headers
+            .get(request.block_hash())
+            .ok_or(QueueError::UnknownBlock)?;
+
+        let id = self.on_demand.insert(OnDemand {
+            request,
+            header,
+            in_progress: None,
+            attempted_sources: Vec::new(),
+        });
+
+        Ok(Queued::Pending(OnDemandId(id)))
+    }
+
+    /// Returns the next on-demand request that must be sent to a source, or `None` if there is
+    /// no request to send or no source available.
+    ///
+    /// The request must be sent to the source designated by [`OnDemandStart::source_id`], then
+    /// [`OnDemandStart::start`] must be called.
+    pub fn next_on_demand_request(&mut self) -> Option<OnDemandStart<TRq, TSrc>> {
+        let headers_sync = &self.headers_sync;
+        let on_demand = &self.on_demand;
+
+        let (index, source_id) = on_demand
+            .iter()
+            .filter(|(_, request)| request.in_progress.is_none())
+            .find_map(|(index, request)| {
+                // Sources that haven't been tried yet for this request are preferred, then the
+                // ones with the fewest on-demand requests in progress.
+                let source_id = headers_sync
+                    .sources()
+                    .filter(|source_id| {
+                        headers_sync.source_reputation(*source_id).verdict()
+                            == reputation::Verdict::Keep
+                    })
+                    .min_by_key(|source_id| {
+                        let num_in_progress = on_demand
+                            .iter()
+                            .filter(|(_, rq)| {
+                                rq.in_progress.as_ref().map(|(s, _)| s) == Some(source_id)
+                            })
+                            .count();
+                        (
+                            request.attempted_sources.contains(source_id),
+                            num_in_progress,
+                        )
+                    })?;
+                Some((index, source_id))
+            })?;
+
+        Some(OnDemandStart {
+            id: OnDemandId(index),
+            request: &mut self.on_demand[index],
+            source_id,
+            source: self.headers_sync.source_user_data(source_id),
+        })
+    }
+
+    /// Update the [`LightSync`] with the outcome of an on-demand request.
+    ///
+    /// On success, `response` must contain the list of trie node values that form the proof for
+    /// storage reads and runtime calls, or the list of extrinsics for block bodies.
+    ///
+    /// Returns the user data that was associated to that request.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`OnDemandId`] is invalid or if the request isn't in progress.
+    ///
+    pub fn finish_on_demand_request(
+        &mut self,
+        id: OnDemandId,
+        response: Result<Vec<Vec<u8>>, RequestFail>,
+    ) -> (TRq, FinishOnDemandOutcome) {
+        let request = &mut self.on_demand[id.0];
+        let (source_id, user_data) = request
+            .in_progress
+            .take()
+            .expect("on-demand request not in progress");
+
+        let error = match response {
+            Ok(response) => match verify_response(&request.request, &request.header, response) {
+                Ok(verified) => {
+                    let request = self.on_demand.remove(id.0).request;
+                    self.insert_cache(request, verified.clone());
+                    return (user_data, FinishOnDemandOutcome::Success(verified));
+                }
+                Err(err) => err,
+            },
+            Err(err) => OnDemandError::Request(err),
+        };
+
+        let punishment = match error.misbehaviour() {
+            Some(misbehaviour) => self.headers_sync.punish_source(source_id, misbehaviour),
+            None => {
+                // The error can't be attributed to the source, and trying again would yield the
+                // same result.
+                self.on_demand.remove(id.0);
+                return (
+                    user_data,
+                    FinishOnDemandOutcome::Failed {
+                        punishment: None,
+                        error,
+                    },
+                );
+            }
+        };
+
+        let num_attempts = self.on_demand[id.0].attempted_sources.len();
+        if num_attempts >= usize::try_from(self.on_demand_max_attempts.get()).unwrap() {
+            self.on_demand.remove(id.0);
+            (
+                user_data,
+                FinishOnDemandOutcome::Failed {
+                    punishment: Some(punishment),
+                    error,
+                },
+            )
+        } else {
+            (
+                user_data,
+                FinishOnDemandOutcome::Retry { punishment, error },
+            )
+        }
+    }
+
+    /// Inserts an entry in [`LightSync::headers`], removing the oldest entry if necessary.
+    fn insert_header(&mut self, block_hash: [u8; 32], header: KnownHeader) {
+        if self.headers_capacity == 0 || self.headers.contains_key(&block_hash) {
+            return;
+        }
+
+        while self.headers.len() >= self.headers_capacity {
+            let oldest = self.headers_order.pop_front().unwrap();
+            self.headers.remove(&oldest);
+        }
+
+        self.headers.insert(block_hash, header);
+        self.headers_order.push_back(block_hash);
+    }
+
+    /// Inserts an entry in [`LightSync::cache`], removing the oldest entry if necessary.
+    fn insert_cache(&mut self, request: OnDemandRequest, response: OnDemandResponse) {
+        if self.cache_capacity == 0 || self.cache.contains_key(&request) {
+            return;
+        }
+
+        while self.cache.len() >= self.cache_capacity {
+            let oldest = self.cache_order.pop_front().unwrap();
+            self.cache.remove(&oldest);
+        }
+
+        self.cache.insert(request.clone(), response);
+        self.cache_order.push_back(request);
+    }
+}
+
+/// Identifier of an on-demand request.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct OnDemandId(usize);
+
+/// On-demand request targetting a specific block.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OnDemandRequest {
+    /// Read the storage values of the given keys.
+    StorageRead {
+        /// Hash of the block whose storage must be read.
+        block_hash: [u8; 32],
+        /// Keys whose storage value must be read.
+        keys: Vec<Vec<u8>>,
+    },
+    /// Call a runtime entry point.
+    Call {
+        /// Hash of the block on top of which to perform the call.
+        block_hash: [u8; 32],
+        /// Name of the runtime entry point to call.
+        method: String,
+        /// SCALE-encoded parameter to pass to the runtime entry point.
+        parameter: Vec<u8>,
+    },
+    /// Obtain the body of a block.
+    Body {
+        /// Hash of the block.
+        block_hash: [u8; 32],
+    },
+}
+
+impl OnDemandRequest {
+    /// Returns the hash of the block targetted by the request.
+    pub fn block_hash(&self) -> &[u8; 32] {
+        match self {
+            OnDemandRequest::StorageRead { block_hash, .. } => block_hash,
+            OnDemandRequest::Call { block_hash, .. } => block_hash,
+            OnDemandRequest::Body { block_hash } => block_hash,
+        }
+    }
+}
+
+/// Verified response to an [`OnDemandRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnDemandResponse {
+    /// Response to [`OnDemandRequest::StorageRead`]. Contains one storage value for each
+    /// requested key, in the same order, or `None` if there is no storage value for this key.
+    StorageRead(Vec<Option<Vec<u8>>>),
+    /// Response to [`OnDemandRequest::Call`]. Contains the SCALE-encoded value returned by the
+    /// runtime entry point.
+    Call(Vec<u8>),
+    /// Response to [`OnDemandRequest::Body`]. Contains the list of extrinsics of the block.
+    Body(Vec<Vec<u8>>),
+}
+
+/// Outcome of calling [`LightSync::queue_request`].
+#[derive(Debug)]
+pub enum Queued {
+    /// A verified response has been found in cache.
+    Cached(OnDemandResponse),
+    /// The request has been queued.
+    Pending(OnDemandId),
+}
+
+/// Error potentially returned by [`LightSync::queue_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum QueueError {
+    /// The header of the block targetted by the request isn't known.
+    UnknownBlock,
+}
+
+/// On-demand request that must be sent to a source.
+#[must_use]
+pub struct OnDemandStart<'a, TRq, TSrc> {
+    id: OnDemandId,
+    request: &'a mut OnDemand<TRq>,
+    source_id: SourceId,
+    source: &'a TSrc,
+}
+
+impl<'a, TRq, TSrc> OnDemandStart<'a, TRq, TSrc> {
+    /// Returns the identifier of the request.
+    pub fn id(&self) -> OnDemandId {
+        self.id
+    }
+
+    /// Returns the request that must be sent.
+    pub fn request(&self) -> &OnDemandRequest {
+        &self.request.request
+    }
+
+    /// Returns the source the request must be sent to.
+    pub fn source_id(&self) -> SourceId {
+        self.source_id
+    }
+
+    /// Returns the user data of the source the request must be sent to.
+    pub fn source(&self) -> &'a TSrc {
+        self.source
+    }
+
+    /// Updates the [`LightSync`] with the fact that the request has actually been started.
+    /// [`LightSync::finish_on_demand_request`] must later be called.
+    pub fn start(self, user_data: TRq) {
+        self.request.in_progress = Some((self.source_id, user_data));
+        self.request.attempted_sources.push(self.source_id);
+    }
+}
+
+/// Outcome of calling [`LightSync::finish_on_demand_request`].
+#[derive(Debug)]
+pub enum FinishOnDemandOutcome {
+    /// The response has been successfully verified. The [`OnDemandId`] is no longer valid.
+    Success(OnDemandResponse),
+    /// The request has failed and will be attempted again.
+    Retry {
+        /// Punishment that has been applied to the source.
+        punishment: SourcePunishment,
+        /// Problem that happened.
+        error: OnDemandError,
+    },
+    /// The request has failed and will not be attempted again. The [`OnDemandId`] is no longer
+    /// valid.
+    Failed {
+        /// If the problem can be attributed to the source, contains the punishment that has been
+        /// applied to it.
+        punishment: Option<SourcePunishment>,
+        /// Problem that happened.
+        error: OnDemandError,
+    },
+}
+
+/// Problem that happened during an on-demand request.
+#[derive(Debug, derive_more::Display)]
+pub enum OnDemandError {
+    /// The request has failed.
+    #[display(fmt = "Request failed: {:?}", _0)]
+    Request(RequestFail),
+    /// Error while verifying a storage proof.
+    StorageProof(trie::proof_verify::Error),
+    /// Error while verifying a call proof.
+    CallProof(CallProofError),
+    /// The block body doesn't match the extrinsics root found in the header.
+    BodyMismatch,
+}
+
+impl OnDemandError {
+    /// Returns the misbehaviour of the source that the error corresponds to, or `None` if the
+    /// error can't be attributed to the source.
+    fn misbehaviour(&self) -> Option<reputation::Misbehaviour> {
+        match self {
+            OnDemandError::Request(err) => Some(err.misbehaviour()),
+            OnDemandError::StorageProof(_)
+            | OnDemandError::CallProof(CallProofError::StorageProof(_))
+            | OnDemandError::CallProof(CallProofError::MissingCode)
+            | OnDemandError::CallProof(CallProofError::HeapPagesDecode)
+            | OnDemandError::BodyMismatch => Some(reputation::Misbehaviour::BadProof),
+            OnDemandError::CallProof(CallProofError::VmInitialization(_))
+            | OnDemandError::CallProof(CallProofError::Trapped)
+            | OnDemandError::CallProof(CallProofError::ExternalityNotAllowed) => None,
+        }
+    }
+}
+
+/// Error while verifying a call proof.
+#[derive(Debug, derive_more::Display)]
+pub enum CallProofError {
+    /// Error while retrieving a storage value from the proof.
+    StorageProof(trie::proof_verify::Error),
+    /// The proof indicates that there is no runtime code in the storage.
+    MissingCode,
+    /// Failed to decode the number of heap pages found in the storage.
+    HeapPagesDecode,
+    /// Error while starting the virtual machine.
+    VmInitialization(executor::NewErr),
+    /// Error while executing the runtime.
+    Trapped,
+    /// The runtime requires an externality that isn't supported when verifying a call proof.
+    ExternalityNotAllowed,
+}
+
+/// Verifies the response to the given request.
+fn verify_response(
+    request: &OnDemandRequest,
+    header: &KnownHeader,
+    response: Vec<Vec<u8>>,
+) -> Result<OnDemandResponse, OnDemandError> {
+    match request {
+        OnDemandRequest::StorageRead { keys, .. } => {
+            let values = keys
+                .iter()
+                .map(|key| {
+                    proof_storage_get(&header.state_root, &response, key)
+                        .map(|value| value.map(|v| v.to_vec()))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(OnDemandError::StorageProof)?;
+            Ok(OnDemandResponse::StorageRead(values))
+        }
+        OnDemandRequest::Call {
+            method, parameter, ..
+        } => {
+            let output = verify_call(&header.state_root, method, parameter, &response)
+                .map_err(OnDemandError::CallProof)?;
+            Ok(OnDemandResponse::Call(output))
+        }
+        OnDemandRequest::Body { .. } => {
+            let extrinsics_root =
+                trie::ordered_root(response.iter().map(parity_scale_codec::Encode::encode));
+            if extrinsics_root != header.extrinsics_root {
+                return Err(OnDemandError::BodyMismatch);
+            }
+            Ok(OnDemandResponse::Body(response))
+        }
+    }
+}
+
+/// Performs a runtime call whose storage accesses are answered using the given proof. Returns
+/// the output of the call.
+fn verify_call(
+    state_root: &[u8; 32],
+    method: &str,
+    parameter: &[u8],
+    proof: &[Vec<u8>],
+) -> Result<Vec<u8>, CallProofError> {
+    let wasm_code = proof_storage_get(state_root, proof, b":code")
+        .map_err(CallProofError::StorageProof)?
+        .ok_or(CallProofError::MissingCode)?;
+    let heap_pages = match proof_storage_get(state_root, proof, b":heappages")
+        .map_err(CallProofError::StorageProof)?
+    {
+        Some(bytes) => u64::from_le_bytes(
+            <[u8; 8]>::try_from(bytes).map_err(|_| CallProofError::HeapPagesDecode)?,
+        ),
+        None => 1024, // TODO: default heap pages
+    };
+
+    let mut vm: executor::WasmVm = executor::WasmVmPrototype::new(wasm_code, heap_pages)
+        .map_err(CallProofError::VmInitialization)?
+        .run(method, parameter)
+        .map_err(CallProofError::VmInitialization)?
+        .into();
+
+    // Storage modifications performed by the runtime during the call. They only need to be
+    // visible during the call and are then discarded.
+    let mut overlay = HashMap::<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>::default();
+
+    loop {
+        match vm {
+            executor::WasmVm::ReadyToRun(r) => vm = r.run(),
+            executor::WasmVm::Finished(finished) => return Ok(finished.value().to_vec()),
+            executor::WasmVm::Error { .. } => return Err(CallProofError::Trapped),
+
+            executor::WasmVm::ExternalStorageGet(req) => {
+                let value = match overlay.get(req.key()) {
+                    Some(value) => value.clone(),
+                    None => proof_storage_get(state_root, proof, req.key())
+                        .map_err(CallProofError::StorageProof)?
+                        .map(|v| v.to_vec()),
+                };
+                vm = req.resume_full_value(value.as_ref().map(|v| &v[..]));
+            }
+            executor::WasmVm::ExternalStorageSet(req) => {
+                overlay.insert(req.key().to_vec(), req.value().map(|v| v.to_vec()));
+                vm = req.resume();
+            }
+
+            executor::WasmVm::LogEmit(req) => vm = req.resume(),
+
+            // TODO: support more externalities
+            _ => return Err(CallProofError::ExternalityNotAllowed),
+        }
+    }
+}
+
+/// Finds the storage value of the given key in the given proof.
+fn proof_storage_get<'a>(
+    state_root: &'a [u8; 32],
+    proof: &'a [Vec<u8>],
+    key: &'a [u8],
+) -> Result<Option<&'a [u8]>, trie::proof_verify::Error> {
+    trie::proof_verify::verify_proof(trie::proof_verify::Config {
+        requested_key: key,
+        trie_root_hash: state_root,
+        proof: proof.iter().map(|v| &v[..]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain::blocks_tree::tests::{aura_header, config},
+        trie::proof_verify::tests::polkadot_genesis_proof,
+    };
+    use parity_scale_codec::Encode as _;
+
+    /// Wasm module whose `test` function returns the SCALE-encoded storage value of `:greeting`.
+    /// Assembled from:
+    ///
+    /// ```wat
+    /// (module
+    ///   (import "env" "ext_storage_get_version_1" (func $get (param i64) (result i64)))
+    ///   (memory (export "memory") 1)
+    ///   (global (export "__heap_base") i32 (i32.const 1024))
+    ///   (data (i32.const 0) ":greeting")
+    ///   (func (export "test") (param i32 i32) (result i64)
+    ///     (call $get (i64.const 0x9_0000_0000))))
+    /// ```
+    const RUNTIME: &str = "0061736d01000000010c0260017e017e60027f7f017e02210103656e76196578745f73746f726167655f6765745f76657273696f6e5f3100000302010105030100010607017f004180080b071f03066d656d6f727902000b5f5f686561705f626173650300047465737400010a0d010b004280808080900110000b0b0f010041000b093a6772656574696e67";
+
+    const GREETING: &[u8] = b"hello world, from the storage of the runtime";
+
+    /// Returns the state root of a storage containing [`RUNTIME`] under `:code` and
+    /// [`GREETING`] under `:greeting`, and the proof of these two entries.
+    fn call_proof() -> ([u8; 32], Vec<Vec<u8>>) {
+        let runtime = hex::decode(RUNTIME).unwrap();
+
+        // Both keys start with the nibbles `3, a, 6`. The root node is a branch with this
+        // partial key, whose children at index 3 (`:code`) and 7 (`:greeting`) are leaves.
+        let leaf = |num_nibbles: u8, partial_key: &[u8], value: &[u8]| {
+            let mut node = vec![0b01 << 6 | num_nibbles];
+            node.extend_from_slice(partial_key);
+            value.encode_to(&mut node);
+            node
+        };
+        let code_leaf = leaf(6, b"ode", &runtime);
+        let greeting_leaf = leaf(14, b"reeting", GREETING);
+
+        let mut root = vec![0b10 << 6 | 3, 0x03, 0xa6];
+        root.extend_from_slice(&(1u16 << 3 | 1 << 7).to_le_bytes());
+        for child in &[&code_leaf, &greeting_leaf] {
+            blake2_rfc::blake2b::blake2b(32, &[], child)
+                .as_bytes()
+                .encode_to(&mut root);
+        }
+
+        let mut state_root = [0; 32];
+        state_root.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], &root).as_bytes());
+
+        let mut trie = trie::Trie::new();
+        trie.insert(b":code", runtime);
+        trie.insert(b":greeting", GREETING);
+        assert_eq!(trie.root_merkle_value(None), state_root);
+
+        (state_root, vec![root, code_leaf, greeting_leaf])
+    }
+
+    fn header(state_root: [u8; 32], extrinsics_root: [u8; 32]) -> KnownHeader {
+        KnownHeader {
+            state_root,
+            extrinsics_root,
+        }
+    }
+
+    #[test]
+    fn storage_proof() {
+        let (mut proof, key, state_root, value) = polkadot_genesis_proof();
+        let request = OnDemandRequest::StorageRead {
+            block_hash: [0; 32],
+            keys: vec![key, b"unrelated key".to_vec()],
+        };
+
+        assert!(matches!(
+            verify_response(&request, &header(state_root, [0; 32]), proof.clone()),
+            Ok(OnDemandResponse::StorageRead(ref values))
+                if *values == [Some(value.clone()), None]
+        ));
+
+        // A proof with a modified node doesn't match the state root anymore.
+        *proof[0].last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            verify_response(&request, &header(state_root, [0; 32]), proof),
+            Err(OnDemandError::StorageProof(_))
+        ));
+    }
+
+    #[test]
+    fn call_proof_execution() {
+        let (state_root, proof) = call_proof();
+        let request = OnDemandRequest::Call {
+            block_hash: [0; 32],
+            method: "test".to_owned(),
+            parameter: Vec::new(),
+        };
+
+        assert!(matches!(
+            verify_response(&request, &header(state_root, [0; 32]), proof.clone()),
+            Ok(OnDemandResponse::Call(ref output)) if *output == Some(GREETING).encode()
+        ));
+
+        // The storage value read by the runtime is missing from the proof.
+        assert!(matches!(
+            verify_response(&request, &header(state_root, [0; 32]), proof[..2].to_vec()),
+            Err(OnDemandError::CallProof(CallProofError::StorageProof(_)))
+        ));
+
+        // The runtime code has been tampered with.
+        let mut tampered = proof;
+        *tampered[1].last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            verify_response(&request, &header(state_root, [0; 32]), tampered),
+            Err(OnDemandError::CallProof(CallProofError::StorageProof(_)))
+        ));
+    }
+
+    #[test]
+    fn body_extrinsics_root() {
+        let body = vec![b"foo".to_vec(), b"bar".to_vec()];
+        let extrinsics_root = trie::ordered_root(body.iter().map(|e| e.encode()));
+        let request = OnDemandRequest::Body {
+            block_hash: [0; 32],
+        };
+
+        assert!(matches!(
+            verify_response(&request, &header([0; 32], extrinsics_root), body.clone()),
+            Ok(OnDemandResponse::Body(ref b)) if *b == body
+        ));
+
+        let reordered = vec![body[1].clone(), body[0].clone()];
+        assert!(matches!(
+            verify_response(&request, &header([0; 32], extrinsics_root), reordered),
+            Err(OnDemandError::BodyMismatch)
+        ));
+        assert!(matches!(
+            verify_response(
+                &request,
+                &header([0; 32], extrinsics_root),
+                body[..1].to_vec()
+            ),
+            Err(OnDemandError::BodyMismatch)
+        ));
+
+        // The extrinsics root of a block without any extrinsic is the root of an empty trie.
+        assert_eq!(
+            trie::ordered_root(Vec::<Vec<u8>>::new()),
+            trie::empty_trie_merkle_value()
+        );
+    }
+
+    /// Builds the configuration of a [`LightSync`] whose finalized block has the state root of
+    /// the Polkadot genesis block.
+    fn light_config(cache_capacity: usize, on_demand_max_attempts: u32) -> Config {
+        let mut chain_information_config = config().chain_information_config;
+        chain_information_config
+            .chain_information
+            .finalized_block_header
+            .state_root = polkadot_genesis_proof().2;
+
+        Config {
+            headers_sync: headers_optimistic::Config {
+                chain_information_config,
+                sources_capacity: 4,
+                blocks_request_granularity: NonZeroU32::new(8).unwrap(),
+                download_ahead_blocks: 8,
+                source_selection_randomness_seed: 0,
+                generate_events: false,
+                max_non_finalized_blocks: 16,
+            },
+            headers_capacity: 4,
+            cache_capacity,
+            on_demand_max_attempts: NonZeroU32::new(on_demand_max_attempts).unwrap(),
+        }
+    }
+
+    fn light_sync(cache_capacity: usize, on_demand_max_attempts: u32) -> LightSync<(), u32> {
+        LightSync::new(light_config(cache_capacity, on_demand_max_attempts))
+    }
+
+    /// Starts the next on-demand request, and checks that it is the given one. Returns the
+    /// source it is sent to.
+    fn start_on_demand(sync: &mut LightSync<(), u32>, expected: OnDemandId) -> u32 {
+        let start = sync.next_on_demand_request().unwrap();
+        assert_eq!(start.id(), expected);
+        let source = *start.source();
+        start.start(());
+        source
+    }
+
+    #[test]
+    fn retry_on_other_source() {
+        let mut sync = light_sync(4, 3);
+        sync.add_source(0);
+        sync.add_source(1);
+
+        let (proof, key, _, value) = polkadot_genesis_proof();
+        let mut tampered = proof.clone();
+        *tampered[0].last_mut().unwrap() ^= 1;
+
+        let request = OnDemandRequest::StorageRead {
+            block_hash: sync.best_block_hash(),
+            keys: vec![key],
+        };
+        let id = match sync.queue_request(request.clone()) {
+            Ok(Queued::Pending(id)) => id,
+            _ => panic!(),
+        };
+
+        // The first source sends back an invalid proof, and the request is sent to the other
+        // source.
+        let first_source = start_on_demand(&mut sync, id);
+        assert!(matches!(
+            sync.finish_on_demand_request(id, Ok(tampered)).1,
+            FinishOnDemandOutcome::Retry {
+                error: OnDemandError::StorageProof(_),
+                ..
+            }
+        ));
+        let second_source = start_on_demand(&mut sync, id);
+        assert_ne!(first_source, second_source);
+        assert!(matches!(
+            sync.finish_on_demand_request(id, Ok(proof)).1,
+            FinishOnDemandOutcome::Success(OnDemandResponse::StorageRead(ref values))
+                if *values == [Some(value.clone())]
+        ));
+
+        // The first source isn't used anymore.
+        let id = match sync.queue_request(OnDemandRequest::Body {
+            block_hash: sync.best_block_hash(),
+        }) {
+            Ok(Queued::Pending(id)) => id,
+            _ => panic!(),
+        };
+        assert_eq!(start_on_demand(&mut sync, id), second_source);
+
+        // The response is now in cache.
+        assert!(matches!(
+            sync.queue_request(request),
+            Ok(Queued::Cached(OnDemandResponse::StorageRead(ref values)))
+                if *values == [Some(value)]
+        ));
+    }
+
+    #[test]
+    fn max_attempts() {
+        let mut sync = light_sync(4, 2);
+        sync.add_source(0);
+
+        let id = match sync.queue_request(OnDemandRequest::Body {
+            block_hash: sync.best_block_hash(),
+        }) {
+            Ok(Queued::Pending(id)) => id,
+            _ => panic!(),
+        };
+
+        start_on_demand(&mut sync, id);
+        assert!(matches!(
+            sync.finish_on_demand_request(id, Err(RequestFail::BlocksUnavailable))
+                .1,
+            FinishOnDemandOutcome::Retry { .. }
+        ));
+        start_on_demand(&mut sync, id);
+        assert!(matches!(
+            sync.finish_on_demand_request(id, Err(RequestFail::BlocksUnavailable))
+                .1,
+            FinishOnDemandOutcome::Failed {
+                punishment: Some(_),
+                error: OnDemandError::Request(RequestFail::BlocksUnavailable),
+            }
+        ));
+        assert!(sync.next_on_demand_request().is_none());
+
+        assert!(matches!(
+            sync.queue_request(OnDemandRequest::Body {
+                block_hash: [1; 32]
+            }),
+            Err(QueueError::UnknownBlock)
+        ));
+    }
+
+    #[test]
+    fn cache_eviction() {
+        let mut sync = light_sync(1, 1);
+        sync.add_source(0);
+
+        let (proof, key, _, _) = polkadot_genesis_proof();
+        let requests = [vec![key.clone()], vec![key.clone(), key]]
+            .iter()
+            .map(|keys| OnDemandRequest::StorageRead {
+                block_hash: sync.best_block_hash(),
+                keys: keys.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        for request in &requests {
+            let id = match sync.queue_request(request.clone()) {
+                Ok(Queued::Pending(id)) => id,
+                _ => panic!(),
+            };
+            start_on_demand(&mut sync, id);
+            assert!(matches!(
+                sync.finish_on_demand_request(id, Ok(proof.clone())).1,
+                FinishOnDemandOutcome::Success(_)
+            ));
+            assert!(matches!(
+                sync.queue_request(request.clone()),
+                Ok(Queued::Cached(_))
+            ));
+        }
+
+        // The cache only has space for one response, and the oldest one has been discarded.
+        assert!(matches!(
+            sync.queue_request(requests[0].clone()),
+            Ok(Queued::Pending(_))
+        ));
+        assert!(matches!(
+            sync.queue_request(requests[1].clone()),
+            Ok(Queued::Cached(_))
+        ));
+    }
+
+    #[test]
+    fn snapshot_restores_headers() {
+        let mut sync = light_sync(4, 1);
+        sync.add_source(0);
+
+        let block1 = aura_header(sync.best_block_hash(), 1, 1);
+        let hash1 = header::hash_from_scale_encoded_header(&block1);
+        let request_id = match sync.next_request_action() {
+            Some(RequestAction::Start { start, .. }) => start.start(()),
+            _ => panic!(),
+        };
+        let blocks = vec![RequestSuccessBlock {
+            scale_encoded_header: block1,
+            scale_encoded_justification: None,
+        }];
+        let _ = sync.finish_request(request_id, Ok(blocks.into_iter()));
+        assert!(matches!(
+            sync.process_one(Duration::from_secs(1 << 32)),
+            ProcessOneOutcome::Updated { .. }
+        ));
+
+        let restored =
+            LightSync::<(), u32>::from_snapshot(light_config(4, 1), &sync.snapshot().unwrap())
+                .unwrap();
+        assert_eq!(restored.best_block_hash(), hash1);
+        assert!(restored.knows_block(&hash1));
+    }
+}
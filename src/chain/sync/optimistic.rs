@@ -20,6 +20,8 @@
 // TODO: document usage
 // TODO: the quality of this module's code is sub-par compared to what we want
 
+use super::reputation;
+
 use alloc::{collections::VecDeque, vec};
 use core::{
     cmp,
@@ -28,6 +30,7 @@ use core::{
     marker::PhantomData,
     mem,
     num::{NonZeroU32, NonZeroU64},
+    time::Duration,
 };
 use rand::{seq::IteratorRandom as _, SeedableRng as _};
 
@@ -97,7 +100,12 @@ struct VerificationQueueEntry<TRq, TBl> {
 
 struct Source<TSrc> {
     user_data: TSrc,
-    banned: bool, // TODO: ban shouldn't be held forever
+    /// Reputation of the source. Requests are only sent to sources whose verdict is
+    /// [`reputation::Verdict::Keep`].
+    reputation: reputation::Reputation,
+    /// Best block number reported by the source, if known. Requests for blocks above this
+    /// number aren't sent to this source.
+    best_block_number: Option<u64>,
 }
 
 enum VerificationQueueEntryTy<TRq, TBl> {
@@ -109,7 +117,12 @@ enum VerificationQueueEntryTy<TRq, TBl> {
         // Index of this source within [`OptimisticSync::sources`].
         source: usize,
     },
-    Queued(Vec<TBl>),
+    Queued {
+        blocks: Vec<TBl>,
+        /// Index within [`OptimisticSync::sources`] of the source that has provided the blocks,
+        /// or `None` if it has been removed since then.
+        source: Option<usize>,
+    },
 }
 
 impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
@@ -139,10 +152,24 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
     pub fn add_source(&mut self, source: TSrc) -> SourceId {
         SourceId(self.sources.insert(Source {
             user_data: source,
-            banned: false,
+            reputation: reputation::Reputation::new(),
+            best_block_number: None,
         }))
     }
 
+    /// Updates the best block number reported by the given source.
+    ///
+    /// Blocks above this number are no longer requested from this source. Sources whose best
+    /// block is unknown are assumed to be able to provide any block.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn set_source_best_block(&mut self, source_id: SourceId, best_block_number: u64) {
+        self.sources[source_id.0].best_block_number = Some(best_block_number);
+    }
+
     /// Inform the [`OptimisticSync`] that a source of blocks is no longer available.
     ///
     /// This automatically cancels all the requests that have been emitted for this source.
@@ -157,6 +184,16 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
         source: SourceId,
     ) -> (TSrc, impl Iterator<Item = (RequestId, TRq)> + 'a) {
         let src_user_data = self.sources.remove(source.0).user_data;
+
+        // The index of the source might later be reused by a different source.
+        for entry in self.verification_queue.iter_mut() {
+            if let VerificationQueueEntryTy::Queued { source: s, .. } = &mut entry.ty {
+                if *s == Some(source.0) {
+                    *s = None;
+                }
+            }
+        }
+
         let drain = RequestsDrain {
             iter: self.verification_queue.iter_mut().fuse(),
             source_index: source.0,
@@ -164,6 +201,90 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
         (src_user_data, drain)
     }
 
+    /// Returns the user data of the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_user_data(&self, source_id: SourceId) -> &TSrc {
+        &self.sources[source_id.0].user_data
+    }
+
+    /// Returns the reputation of the given source.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_reputation(&self, source_id: SourceId) -> &reputation::Reputation {
+        &self.sources[source_id.0].reputation
+    }
+
+    /// Returns the list of all the sources.
+    pub fn sources(&self) -> impl Iterator<Item = SourceId> + '_ {
+        self.sources.iter().map(|(id, _)| SourceId(id))
+    }
+
+    /// Lowers the reputation of the given source following a misbehaviour that has been
+    /// detected outside of the [`OptimisticSync`], for example while answering a request that
+    /// doesn't concern blocks.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn punish_source(
+        &mut self,
+        source_id: SourceId,
+        misbehaviour: reputation::Misbehaviour,
+    ) -> SourcePunishment {
+        let verdict = self.sources[source_id.0].reputation.punish(misbehaviour);
+        SourcePunishment {
+            source_id,
+            misbehaviour,
+            verdict,
+        }
+    }
+
+    /// Returns the list of blocks that have been downloaded and are waiting to be processed,
+    /// alongside with their block number, ordered by increasing block number.
+    pub fn queued_blocks(&self) -> impl Iterator<Item = (u64, &TBl)> {
+        self.verification_queue
+            .iter()
+            .filter_map(|entry| match &entry.ty {
+                VerificationQueueEntryTy::Queued { blocks, .. } => {
+                    Some((entry.block_height.get(), blocks))
+                }
+                _ => None,
+            })
+            .flat_map(|(first_block_height, blocks)| {
+                blocks
+                    .iter()
+                    .enumerate()
+                    .map(move |(n, block)| (first_block_height + u64::try_from(n).unwrap(), block))
+            })
+    }
+
+    /// Brings the reputation of all the sources back towards 0 according to the time elapsed.
+    /// See [`reputation::Reputation::decay`].
+    pub fn decay_reputations(&mut self, now_from_unix_epoch: Duration) {
+        for (_, source) in self.sources.iter_mut() {
+            source.reputation.decay(now_from_unix_epoch);
+        }
+    }
+
+    /// Discards the blocks queued for verification and cancels the requests in progress, which
+    /// are returned by [`OptimisticSync::next_request_action`]. Blocks are then requested
+    /// starting from the child of the given best block.
+    ///
+    /// Must be called if blocks have been added to the chain through other means than
+    /// [`OptimisticSync::process_one`].
+    pub fn reset_to_block(&mut self, best_block_number: u64) {
+        self.cancelling_requests = true;
+        self.best_block_number = best_block_number;
+    }
+
     /// Returns an iterator that extracts all requests that need to be started and requests that
     /// need to be cancelled.
     pub fn next_request_action(&mut self) -> Option<RequestAction<TRq, TSrc, TBl>> {
@@ -213,15 +334,18 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
             .enumerate()
             .find(|(_, e)| matches!(e.ty, VerificationQueueEntryTy::Missing))
         {
+            let block_height = self.verification_queue[missing_pos].block_height;
+
             let source = self
                 .sources
                 .iter()
-                .filter(|(_, src)| !src.banned)
+                .filter(|(_, src)| src.reputation.verdict() == reputation::Verdict::Keep)
+                .filter(
+                    |(_, src)| !matches!(src.best_block_number, Some(n) if n < block_height.get()),
+                )
                 .choose(&mut self.source_selection_rng)?
                 .0;
 
-            let block_height = self.verification_queue[missing_pos].block_height;
-
             let num_blocks = if let Some(next) = self.verification_queue.get(missing_pos + 1) {
                 NonZeroU32::new(
                     u32::try_from(cmp::min(
@@ -282,9 +406,21 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
             .next()
             .expect("invalid RequestId");
 
-        let blocks = match outcome {
-            Ok(blocks) => blocks.collect(),
-            Err(_) => {
+        let misbehaviour = match outcome {
+            Ok(blocks) => {
+                let blocks = blocks.collect::<Vec<_>>();
+                if blocks.is_empty() {
+                    Err(reputation::Misbehaviour::EmptyResponse)
+                } else {
+                    Ok(blocks)
+                }
+            }
+            Err(err) => Err(err.misbehaviour()),
+        };
+
+        let blocks = match misbehaviour {
+            Ok(blocks) => blocks,
+            Err(misbehaviour) => {
                 let user_data = match mem::replace(
                     &mut self.verification_queue[verification_queue_entry].ty,
                     VerificationQueueEntryTy::Missing,
@@ -293,9 +429,18 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
                     _ => unreachable!(),
                 };
 
+                let source = &mut self.sources[source_id];
+                let verdict = source.reputation.punish(misbehaviour);
                 return (
                     user_data,
-                    FinishRequestOutcome::SourcePunished(&mut self.sources[source_id].user_data),
+                    FinishRequestOutcome::SourcePunished {
+                        source: &mut source.user_data,
+                        punishment: SourcePunishment {
+                            source_id: SourceId(source_id),
+                            misbehaviour,
+                            verdict,
+                        },
+                    },
                 );
             }
         };
@@ -304,7 +449,10 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
 
         let user_data = match mem::replace(
             &mut self.verification_queue[verification_queue_entry].ty,
-            VerificationQueueEntryTy::Queued(blocks),
+            VerificationQueueEntryTy::Queued {
+                blocks,
+                source: Some(source_id),
+            },
         ) {
             VerificationQueueEntryTy::Requested { user_data, .. } => user_data,
             _ => unreachable!(),
@@ -322,9 +470,9 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
         }
 
         // Extract the chunk of blocks to process next.
-        let blocks = match &mut self.verification_queue.get_mut(0).map(|b| &mut b.ty) {
-            Some(VerificationQueueEntryTy::Queued(blocks)) => {
-                mem::replace(blocks, Default::default())
+        let (blocks, source) = match &mut self.verification_queue.get_mut(0).map(|b| &mut b.ty) {
+            Some(VerificationQueueEntryTy::Queued { blocks, source }) => {
+                (mem::replace(blocks, Default::default()), *source)
             }
             _ => return Err(self),
         };
@@ -336,7 +484,10 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
         Ok(ProcessOne {
             expected_block_height,
             blocks: blocks.into_iter(),
-            report: ProcessOneReport { parent: self },
+            report: ProcessOneReport {
+                parent: self,
+                source,
+            },
         })
     }
 }
@@ -421,15 +572,56 @@ impl<'a, TRq, TSrc, TBl> fmt::Debug for Start<'a, TRq, TSrc, TBl> {
     }
 }
 
+/// Outcome of calling [`OptimisticSync::finish_request`].
+#[derive(Debug)]
 pub enum FinishRequestOutcome<'a, TSrc> {
+    /// The blocks have been added to the queue of blocks to verify.
     Queued,
-    SourcePunished(&'a mut TSrc),
+    /// The request has failed or the source has sent back an empty response, and its reputation
+    /// has been lowered.
+    SourcePunished {
+        /// User data of the source.
+        source: &'a mut TSrc,
+        /// Details about the punishment.
+        punishment: SourcePunishment,
+    },
 }
 
 /// Reason why a request has failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum RequestFail {
     /// Requested blocks aren't available from this source.
     BlocksUnavailable,
+    /// The source hasn't answered in time.
+    Timeout,
+    /// The source has sent back a response that couldn't be decoded.
+    InvalidResponse,
+}
+
+impl RequestFail {
+    /// Returns the misbehaviour that the failure corresponds to.
+    pub(super) fn misbehaviour(&self) -> reputation::Misbehaviour {
+        match self {
+            RequestFail::BlocksUnavailable => reputation::Misbehaviour::BlocksUnavailable,
+            RequestFail::Timeout => reputation::Misbehaviour::Timeout,
+            RequestFail::InvalidResponse => reputation::Misbehaviour::InvalidResponse,
+        }
+    }
+}
+
+/// Information about a source whose reputation has been lowered following a misbehaviour.
+///
+/// If the [`SourcePunishment::verdict`] isn't [`reputation::Verdict::Keep`], no request will be
+/// sent to this source anymore until its reputation has recovered. It is recommended to
+/// disconnect from the source and to remove it with [`OptimisticSync::remove_source`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SourcePunishment {
+    /// Source that has misbehaved.
+    pub source_id: SourceId,
+    /// Misbehaviour that the source has committed.
+    pub misbehaviour: reputation::Misbehaviour,
+    /// Recommended action towards the source, following the punishment.
+    pub verdict: reputation::Verdict,
 }
 
 /// Iterator that drains requests after a source has been removed.
@@ -488,9 +680,30 @@ pub struct ProcessOne<TRq, TSrc, TBl> {
 #[must_use]
 pub struct ProcessOneReport<TRq, TSrc, TBl> {
     parent: OptimisticSync<TRq, TSrc, TBl>,
+    /// Index within [`OptimisticSync::sources`] of the source that has provided the blocks, or
+    /// `None` if it has been removed.
+    source: Option<usize>,
 }
 
 impl<TRq, TSrc, TBl> ProcessOneReport<TRq, TSrc, TBl> {
+    /// Lowers the reputation of the source that has provided the blocks being processed.
+    ///
+    /// Returns `None` if the source has been removed in the meanwhile.
+    pub fn punish_source(
+        &mut self,
+        misbehaviour: reputation::Misbehaviour,
+    ) -> Option<SourcePunishment> {
+        let source_index = self.source?;
+        let verdict = self.parent.sources[source_index]
+            .reputation
+            .punish(misbehaviour);
+        Some(SourcePunishment {
+            source_id: SourceId(source_index),
+            misbehaviour,
+            verdict,
+        })
+    }
+
     pub fn reset_to_finalized(
         mut self,
         finalized_block_number: u64,
@@ -0,0 +1,494 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reputation of the sources of blocks.
+//!
+//! Each source is attributed a reputation, which starts at 0. Whenever a source misbehaves, for
+//! example by not answering a request in time or by sending back an invalid block, its
+//! reputation is decreased by an amount that depends on the gravity of the
+//! [`Misbehaviour`].
+//!
+//! The reputation of a source then slowly goes back towards 0 as time passes, so that
+//! occasional problems, such as a slow network, aren't held against a source forever.
+//!
+//! Once the reputation of a source goes below certain thresholds, the [`Verdict`] recommends to
+//! disconnect from the source or to ban it. Misbehaviours that might not be the fault of the
+//! source, such as a block failing to verify against a local state that is out of date, can
+//! never lead to a ban, no matter how often they happen. The syncing state machines stop sending requests to
+//! sources whose verdict isn't [`Verdict::Keep`].
+//!
+//! The values of the reputation changes are compatible with the ones used by Substrate's peer
+//! set manager, and can be reported as-is to the networking layer.
+
+use super::super::blocks_tree;
+use crate::verify;
+
+use core::{cmp, time::Duration};
+
+/// Reputation below which a source should be disconnected.
+pub const DISCONNECT_THRESHOLD: i32 = -(1 << 24);
+
+/// Reputation below which a source should be banned. Equal to 82% of `i32::min_value()`, in
+/// accordance with Substrate.
+pub const BAN_THRESHOLD: i32 = 82 * (i32::min_value() / 100);
+
+/// Misbehaviour of a source.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// The source hasn't answered a request in time.
+    Timeout,
+    /// The source has answered a request with an empty list of blocks.
+    EmptyResponse,
+    /// The requested blocks aren't available from this source, or the request has failed for
+    /// a reason that can't be attributed to the source with certainty.
+    BlocksUnavailable,
+    /// The source has sent back a response that doesn't conform to the protocol, such as an
+    /// undecodable response or blocks that weren't requested.
+    InvalidResponse,
+    /// The source has sent a justification that has failed to verify.
+    BadJustification,
+    /// The source has sent a block that has failed to verify for reasons that only depend on the
+    /// block itself, such as a bad seal or an undecodable header.
+    InvalidBlock,
+    /// The source has sent a block that has failed to verify for reasons that depend on the local
+    /// state, such as a host function missing from the local executor or a consensus state that
+    /// has drifted from the one of the source. The block might be valid for the rest of the
+    /// network.
+    UnverifiableBlock,
+    /// The source has sent a block that the local node is configured to reject, through the list
+    /// of bad blocks or fork blocks of the chain. The source is likely to simply follow a
+    /// different fork, and is disconnected from rather than banned.
+    RejectedBlock,
+    /// The source has sent a storage proof, call proof, or block body that doesn't match the
+    /// header of the block it concerns.
+    BadProof,
+}
+
+impl Misbehaviour {
+    /// Returns the value to add to the reputation of a source that has misbehaved in this way.
+    /// Always negative.
+    pub fn reputation_change(&self) -> i32 {
+        match self {
+            Misbehaviour::BlocksUnavailable => -(1 << 20),
+            Misbehaviour::Timeout => -(1 << 22),
+            Misbehaviour::EmptyResponse => -(1 << 22),
+            Misbehaviour::InvalidResponse => -(1 << 29),
+            Misbehaviour::BadJustification => -(1 << 29),
+            Misbehaviour::InvalidBlock => i32::min_value(),
+            Misbehaviour::UnverifiableBlock => -(1 << 22),
+            Misbehaviour::RejectedBlock => DISCONNECT_THRESHOLD - 1,
+            Misbehaviour::BadProof => -(1 << 29),
+        }
+    }
+
+    /// Returns a short human-readable description of the misbehaviour.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Misbehaviour::BlocksUnavailable => "Blocks unavailable",
+            Misbehaviour::Timeout => "Request timeout",
+            Misbehaviour::EmptyResponse => "Empty response",
+            Misbehaviour::InvalidResponse => "Invalid response",
+            Misbehaviour::BadJustification => "Bad justification",
+            Misbehaviour::InvalidBlock => "Invalid block",
+            Misbehaviour::UnverifiableBlock => "Unverifiable block",
+            Misbehaviour::RejectedBlock => "Rejected block",
+            Misbehaviour::BadProof => "Bad proof",
+        }
+    }
+
+    /// Returns `false` if this misbehaviour might not be the fault of the source, in which case
+    /// it never lowers the reputation of the source below [`BAN_THRESHOLD`].
+    pub fn can_ban(&self) -> bool {
+        !matches!(
+            self,
+            Misbehaviour::UnverifiableBlock | Misbehaviour::RejectedBlock
+        )
+    }
+}
+
+/// Recommended action towards a source, based on its reputation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The source can continue to be used.
+    Keep,
+    /// The source shouldn't be used anymore and should be disconnected. It can be connected to
+    /// again later.
+    Disconnect,
+    /// The source is very likely malicious and should be disconnected and banned.
+    Ban,
+}
+
+/// Reputation of a single source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reputation {
+    /// Current value of the reputation. Always inferior or equal to 0.
+    value: i32,
+    /// Time passed to the latest call to [`Reputation::decay`], minus the fraction of second
+    /// that hasn't been taken into account yet. `None` if [`Reputation::decay`] has never been
+    /// called.
+    last_decay: Option<Duration>,
+}
+
+impl Reputation {
+    /// Builds a new [`Reputation`] with a value of 0.
+    pub fn new() -> Self {
+        Reputation {
+            value: 0,
+            last_decay: None,
+        }
+    }
+
+    /// Returns the current value of the reputation.
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+
+    /// Returns the recommended action towards the source based on its current reputation.
+    pub fn verdict(&self) -> Verdict {
+        if self.value < BAN_THRESHOLD {
+            Verdict::Ban
+        } else if self.value < DISCONNECT_THRESHOLD {
+            Verdict::Disconnect
+        } else {
+            Verdict::Keep
+        }
+    }
+
+    /// Lowers the reputation following the given misbehaviour. Returns the new verdict.
+    ///
+    /// See also [`Misbehaviour::can_ban`].
+    pub fn punish(&mut self, misbehaviour: Misbehaviour) -> Verdict {
+        let new_value = self.value.saturating_add(misbehaviour.reputation_change());
+        self.value = if misbehaviour.can_ban() {
+            new_value
+        } else {
+            cmp::min(self.value, cmp::max(new_value, BAN_THRESHOLD))
+        };
+        self.verdict()
+    }
+
+    /// Brings the reputation back towards 0 according to the time elapsed since the previous
+    /// call. The reputation decreases by 2% every second, similar to Substrate.
+    ///
+    /// `now` must be monotonically increasing between calls, and is typically the time elapsed
+    /// since the Unix Epoch. The first call to this method doesn't modify the reputation.
+    pub fn decay(&mut self, now: Duration) {
+        let last_decay = match self.last_decay {
+            Some(t) => t,
+            None => {
+                self.last_decay = Some(now);
+                return;
+            }
+        };
+
+        let elapsed_secs = now.checked_sub(last_decay).unwrap_or_default().as_secs();
+        self.last_decay = Some(last_decay + Duration::from_secs(elapsed_secs));
+
+        for _ in 0..elapsed_secs {
+            if self.value == 0 {
+                break;
+            }
+
+            // For small values, `value / 50` is 0. The reputation is nonetheless brought closer
+            // to 0 so that it eventually reaches it.
+            let diff = self.value / 50;
+            self.value -= if diff == 0 { -1 } else { diff };
+        }
+    }
+}
+
+impl Default for Reputation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the misbehaviour that a source has committed by providing a block whose header has
+/// failed to verify, or `None` if the failure can't be attributed to the source with certainty.
+pub(super) fn header_verify_misbehaviour(
+    error: &blocks_tree::HeaderVerifyError,
+) -> Option<Misbehaviour> {
+    match error {
+        // The parent of the block might be missing because of a previous problem that isn't
+        // the fault of this source.
+        blocks_tree::HeaderVerifyError::BadParent { .. } => None,
+        blocks_tree::HeaderVerifyError::InvalidHeader(_)
+        | blocks_tree::HeaderVerifyError::VerificationFailed(
+            verify::header_only::Error::BadBlockNumber,
+        )
+        | blocks_tree::HeaderVerifyError::VerificationFailed(
+            verify::header_only::Error::BadParentHash,
+        ) => Some(Misbehaviour::InvalidBlock),
+        blocks_tree::HeaderVerifyError::BadBlock
+        | blocks_tree::HeaderVerifyError::ForkBlockMismatch { .. } => {
+            Some(Misbehaviour::RejectedBlock)
+        }
+        blocks_tree::HeaderVerifyError::VerificationFailed(
+            verify::header_only::Error::AuraVerification(error),
+        ) => aura_verify_misbehaviour(error),
+        blocks_tree::HeaderVerifyError::VerificationFailed(
+            verify::header_only::Error::BabeVerification(error),
+        ) => babe_verify_misbehaviour(error),
+        // Pending changes depend on the blocks that have been imported locally.
+        blocks_tree::HeaderVerifyError::GrandpaChange(_) => Some(Misbehaviour::UnverifiableBlock),
+    }
+}
+
+/// Returns the misbehaviour that a source has committed by providing a block that has failed to
+/// verify, or `None` if the failure can't be attributed to the source with certainty.
+pub(super) fn body_verify_misbehaviour(error: &verify::header_body::Error) -> Option<Misbehaviour> {
+    match error {
+        // The execution might fail because of the local executor, for example because of a
+        // missing host function, rather than because of the block.
+        verify::header_body::Error::Unsealed(_) => Some(Misbehaviour::UnverifiableBlock),
+        verify::header_body::Error::AuraVerification(error) => aura_verify_misbehaviour(error),
+        verify::header_body::Error::BabeVerification(error) => babe_verify_misbehaviour(error),
+    }
+}
+
+/// Returns the misbehaviour that a source has committed by providing a block whose Aura seal
+/// has failed to verify, or `None` if the failure can't be attributed to the source with
+/// certainty.
+fn aura_verify_misbehaviour(error: &verify::aura::VerifyError) -> Option<Misbehaviour> {
+    match error {
+        // Clocks aren't necessarily perfectly synchronized.
+        verify::aura::VerifyError::FutureSlot { .. } => None,
+        verify::aura::VerifyError::MissingSeal
+        | verify::aura::VerifyError::MissingPreRuntimeDigest
+        | verify::aura::VerifyError::SlotNumberNotIncreasing
+        | verify::aura::VerifyError::BadSignature => Some(Misbehaviour::InvalidBlock),
+        verify::aura::VerifyError::ParentIsntAuraConsensus
+        | verify::aura::VerifyError::EmptyAuthoritiesList
+        | verify::aura::VerifyError::BadPublicKey => Some(Misbehaviour::UnverifiableBlock),
+    }
+}
+
+/// Returns the misbehaviour that a source has committed by providing a block whose BABE seal
+/// has failed to verify, or `None` if the failure can't be attributed to the source with
+/// certainty.
+fn babe_verify_misbehaviour(error: &verify::babe::VerifyError) -> Option<Misbehaviour> {
+    match error {
+        // Clocks aren't necessarily perfectly synchronized.
+        verify::babe::VerifyError::FutureSlot { .. } => None,
+        verify::babe::VerifyError::MissingSeal
+        | verify::babe::VerifyError::MissingPreRuntimeDigest
+        | verify::babe::VerifyError::SlotNumberNotIncreasing
+        | verify::babe::VerifyError::BadSignature
+        | verify::babe::VerifyError::BadVrfProof => Some(Misbehaviour::InvalidBlock),
+        // These errors depend on the epochs and authorities tracked locally.
+        verify::babe::VerifyError::ParentIsntBabeConsensus
+        | verify::babe::VerifyError::UnexpectedEpochChangeLog
+        | verify::babe::VerifyError::MissingEpochChangeLog
+        | verify::babe::VerifyError::InvalidAuthorityIndex
+        | verify::babe::VerifyError::BadSecondarySlotAuthor
+        | verify::babe::VerifyError::OverPrimaryClaimThreshold
+        | verify::babe::VerifyError::ForbiddenSlotType
+        | verify::babe::VerifyError::MissingBlock1SlotNumber
+        | verify::babe::VerifyError::SlotNumberBeforeBlock1
+        | verify::babe::VerifyError::AuthorityDisabled
+        | verify::babe::VerifyError::BadDisabledAuthorityIndex
+        | verify::babe::VerifyError::BadNextEpochRandomness => {
+            Some(Misbehaviour::UnverifiableBlock)
+        }
+    }
+}
+
+/// Returns the misbehaviour that a source has committed by providing a justification that has
+/// failed to verify, or `None` if the failure can't be attributed to the source with certainty.
+pub(super) fn justification_verify_misbehaviour(
+    error: &blocks_tree::JustificationVerifyError,
+) -> Option<Misbehaviour> {
+    match error {
+        blocks_tree::JustificationVerifyError::InvalidJustification(_)
+        | blocks_tree::JustificationVerifyError::VerificationFailed(_) => {
+            Some(Misbehaviour::BadJustification)
+        }
+        // These errors depend on the state of the local chain, which might be incomplete.
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verdicts() {
+        let mut reputation = Reputation::new();
+        assert_eq!(reputation.verdict(), Verdict::Keep);
+
+        for _ in 0..4 {
+            assert_eq!(reputation.punish(Misbehaviour::Timeout), Verdict::Keep);
+        }
+        assert_eq!(
+            reputation.punish(Misbehaviour::Timeout),
+            Verdict::Disconnect
+        );
+
+        assert_eq!(reputation.punish(Misbehaviour::InvalidBlock), Verdict::Ban);
+        assert_eq!(reputation.value(), i32::min_value());
+    }
+
+    #[test]
+    fn decays_towards_zero() {
+        let mut reputation = Reputation::new();
+        reputation.decay(Duration::from_secs(1_000));
+        reputation.punish(Misbehaviour::InvalidBlock);
+
+        // Fractions of seconds are accumulated rather than ignored.
+        reputation.decay(Duration::from_millis(1_000_500));
+        assert_eq!(reputation.value(), i32::min_value());
+        reputation.decay(Duration::from_millis(1_001_000));
+        assert_eq!(reputation.value(), i32::min_value() - i32::min_value() / 50);
+
+        reputation.decay(Duration::from_secs(1_300));
+        assert!(reputation.value() < 0);
+        assert_eq!(reputation.verdict(), Verdict::Keep);
+
+        reputation.decay(Duration::from_secs(3_000));
+        assert_eq!(reputation.value(), 0);
+    }
+
+    #[test]
+    fn rejected_blocks_disconnect() {
+        let mut reputation = Reputation::new();
+        assert_eq!(
+            reputation.punish(Misbehaviour::RejectedBlock),
+            Verdict::Disconnect
+        );
+        for _ in 0..10_000 {
+            assert_eq!(
+                reputation.punish(Misbehaviour::RejectedBlock),
+                Verdict::Disconnect
+            );
+        }
+        assert_eq!(reputation.value(), BAN_THRESHOLD);
+    }
+
+    #[test]
+    fn unverifiable_blocks_never_ban() {
+        let mut reputation = Reputation::new();
+        for _ in 0..10_000 {
+            assert_ne!(
+                reputation.punish(Misbehaviour::UnverifiableBlock),
+                Verdict::Ban
+            );
+        }
+        assert_eq!(reputation.value(), BAN_THRESHOLD);
+        assert_eq!(reputation.verdict(), Verdict::Disconnect);
+
+        // A source that is already banned stays banned.
+        assert_eq!(reputation.punish(Misbehaviour::InvalidBlock), Verdict::Ban);
+        assert_eq!(
+            reputation.punish(Misbehaviour::UnverifiableBlock),
+            Verdict::Ban
+        );
+        assert_eq!(reputation.value(), i32::min_value());
+    }
+
+    #[test]
+    fn verify_errors_mapping() {
+        // Failures that only depend on the block ban the source.
+        for error in &[
+            blocks_tree::HeaderVerifyError::InvalidHeader(crate::header::Error::TooShort),
+            blocks_tree::HeaderVerifyError::VerificationFailed(
+                verify::header_only::Error::BadParentHash,
+            ),
+            blocks_tree::HeaderVerifyError::VerificationFailed(
+                verify::header_only::Error::AuraVerification(
+                    verify::aura::VerifyError::BadSignature,
+                ),
+            ),
+            blocks_tree::HeaderVerifyError::VerificationFailed(
+                verify::header_only::Error::BabeVerification(
+                    verify::babe::VerifyError::MissingSeal,
+                ),
+            ),
+        ] {
+            assert_eq!(
+                header_verify_misbehaviour(error),
+                Some(Misbehaviour::InvalidBlock)
+            );
+        }
+
+        // Failures that depend on the local state are penalized without banning.
+        for error in &[
+            blocks_tree::HeaderVerifyError::GrandpaChange(
+                blocks_tree::GrandpaChangeError::ChangeAlreadyPending,
+            ),
+            blocks_tree::HeaderVerifyError::VerificationFailed(
+                verify::header_only::Error::AuraVerification(
+                    verify::aura::VerifyError::ParentIsntAuraConsensus,
+                ),
+            ),
+            blocks_tree::HeaderVerifyError::VerificationFailed(
+                verify::header_only::Error::BabeVerification(
+                    verify::babe::VerifyError::MissingEpochChangeLog,
+                ),
+            ),
+        ] {
+            assert_eq!(
+                header_verify_misbehaviour(error),
+                Some(Misbehaviour::UnverifiableBlock)
+            );
+        }
+
+        // Blocks rejected because of the configuration of the chain don't ban the source either.
+        for error in &[
+            blocks_tree::HeaderVerifyError::BadBlock,
+            blocks_tree::HeaderVerifyError::ForkBlockMismatch {
+                expected_hash: [0; 32],
+            },
+        ] {
+            assert_eq!(
+                header_verify_misbehaviour(error),
+                Some(Misbehaviour::RejectedBlock)
+            );
+        }
+
+        assert_eq!(
+            header_verify_misbehaviour(&blocks_tree::HeaderVerifyError::BadParent {
+                parent_hash: [0; 32]
+            }),
+            None
+        );
+        assert_eq!(
+            header_verify_misbehaviour(&blocks_tree::HeaderVerifyError::VerificationFailed(
+                verify::header_only::Error::AuraVerification(
+                    verify::aura::VerifyError::FutureSlot {
+                        block_slot_number: 2,
+                        current_slot_number: 1,
+                    }
+                )
+            )),
+            None
+        );
+
+        assert_eq!(
+            body_verify_misbehaviour(&verify::header_body::Error::AuraVerification(
+                verify::aura::VerifyError::EmptyAuthoritiesList
+            )),
+            Some(Misbehaviour::UnverifiableBlock)
+        );
+        assert_eq!(
+            body_verify_misbehaviour(&verify::header_body::Error::BabeVerification(
+                verify::babe::VerifyError::BadVrfProof
+            )),
+            Some(Misbehaviour::InvalidBlock)
+        );
+    }
+}
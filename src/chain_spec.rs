@@ -116,6 +116,27 @@ impl ChainSpec {
             .unwrap_or("sup")
     }
 
+    /// Returns the list of hashes of blocks that are known to be invalid, and that must be
+    /// rejected during the verification.
+    pub fn bad_blocks(&self) -> impl Iterator<Item = &[u8; 32]> {
+        self.client_spec
+            .bad_blocks
+            .as_ref()
+            .into_iter()
+            .flat_map(|list| list.iter().map(|h| h.as_fixed_bytes()))
+    }
+
+    /// Returns a list of block numbers and hashes. The block with the given number must have
+    /// the given hash, otherwise it is rejected during the verification. Used in order to force
+    /// nodes onto a specific fork.
+    pub fn fork_blocks(&self) -> impl Iterator<Item = (u64, &[u8; 32])> {
+        self.client_spec
+            .fork_blocks
+            .as_ref()
+            .into_iter()
+            .flat_map(|list| list.iter().map(|(n, h)| (*n, h.as_fixed_bytes())))
+    }
+
     /// Returns the list of storage keys and values of the genesis block.
     pub fn genesis_storage(&self) -> impl ExactSizeIterator<Item = (&[u8], &[u8])> + Clone {
         let structs::Genesis::Raw(genesis) = &self.client_spec.genesis;
@@ -152,4 +173,31 @@ mod tests {
         let specs = ChainSpec::from_json_bytes(&spec).unwrap();
         assert_eq!(specs.id(), "polkadot");
     }
+
+    #[test]
+    fn bad_and_fork_blocks() {
+        let spec = format!(
+            r#"{{
+                "name": "Test",
+                "id": "test",
+                "bootNodes": [],
+                "telemetryEndpoints": null,
+                "protocolId": null,
+                "properties": null,
+                "forkBlocks": [[12, "0x{}"]],
+                "badBlocks": ["0x{}"],
+                "consensusEngine": null,
+                "genesis": {{ "raw": {{ "top": {{}}, "childrenDefault": {{}} }} }}
+            }}"#,
+            "cd".repeat(32),
+            "ab".repeat(32)
+        );
+
+        let specs = ChainSpec::from_json_bytes(&spec).unwrap();
+        assert_eq!(specs.bad_blocks().collect::<Vec<_>>(), vec![&[0xab; 32]]);
+        assert_eq!(
+            specs.fork_blocks().collect::<Vec<_>>(),
+            vec![(12, &[0xcd; 32])]
+        );
+    }
 }
@@ -38,9 +38,7 @@ pub(super) struct ClientSpec {
     pub(super) telemetry_endpoints: Option<Vec<(String, u8)>>,
     pub(super) protocol_id: Option<String>,
     pub(super) properties: Option<Box<serde_json::value::RawValue>>,
-    // TODO: make use of this
     pub(super) fork_blocks: Option<Vec<(u64, H256)>>,
-    // TODO: make use of this
     pub(super) bad_blocks: Option<HashSet<H256, FnvBuildHasher>>,
     // Unused but for some reason still part of the chain specs.
     pub(super) consensus_engine: (),
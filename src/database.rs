@@ -21,6 +21,7 @@
 //! persistent way.
 
 pub mod local_storage_light;
+pub mod network_address_book;
 
 // TODO: when implementing an actual database for the full node, here is some inspiration:
 // - https://github.com/paritytech/substrate-lite/blob/e53148ca8af7450e9995e960279b6c8925a37663/src/database/sled.rs
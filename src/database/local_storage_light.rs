@@ -31,6 +31,10 @@
 //! respectively load and store a [`chain_information::ChainInformation`] from/to the local
 //! storage.
 //!
+//! Use [`LocalStorage::non_finalized_blocks`] and [`LocalStorage::set_non_finalized_blocks`] to
+//! respectively load and store a snapshot of the non-finalized blocks generated with
+//! [`NonFinalizedTree::snapshot`](crate::chain::blocks_tree::NonFinalizedTree::snapshot).
+//!
 //! > **Note**: The format of the stored information (in other words, the string actually stored
 //! >           in the local storage) isn't documented here. At the time of the writing of this
 //! >           comment, this format isn't stable and can break without warning. In the future,
@@ -112,11 +116,43 @@ impl LocalStorage {
         match decoded {
             defs::SerializedChainInformation::V1(decoded) => {
                 Ok(Some(TryFrom::try_from(decoded).map_err(|err| {
-                    AccessError::Corrupted(CorruptedError(CorruptedErrorInner::HeaderDecode(err)))
+                    AccessError::Corrupted(CorruptedError(err))
                 })?))
             }
         }
     }
+
+    /// Stores in the local storage a snapshot of the non-finalized blocks, as generated with
+    /// [`NonFinalizedTree::snapshot`](crate::chain::blocks_tree::NonFinalizedTree::snapshot).
+    ///
+    /// This snapshot should be stored alongside with the finalized block it has been taken
+    /// from, using [`LocalStorage::set_chain_information`].
+    pub fn set_non_finalized_blocks(&self, snapshot: &[u8]) -> Result<(), StorageAccessError> {
+        self.inner
+            .set_item("non_finalized_blocks", &hex::encode(snapshot))
+            .map_err(StorageAccessError)?;
+        Ok(())
+    }
+
+    /// Loads the snapshot of the non-finalized blocks from the local storage.
+    ///
+    /// The integrity of the snapshot is only verified when it is passed to
+    /// [`NonFinalizedTree::from_snapshot`](crate::chain::blocks_tree::NonFinalizedTree::from_snapshot).
+    pub fn non_finalized_blocks(&self) -> Result<Option<Vec<u8>>, AccessError> {
+        let encoded = match self
+            .inner
+            .get_item("non_finalized_blocks")
+            .map_err(StorageAccessError)
+            .map_err(AccessError::StorageAccess)?
+        {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let decoded = hex::decode(&encoded)
+            .map_err(|e| AccessError::Corrupted(CorruptedError(CorruptedErrorInner::Hex(e))))?;
+        Ok(Some(decoded))
+    }
 }
 
 impl fmt::Debug for LocalStorage {
@@ -178,4 +214,8 @@ enum CorruptedErrorInner {
     Serde(serde_json::Error),
     #[display(fmt = "{}", _0)]
     HeaderDecode(header::Error),
+    #[display(fmt = "{}", _0)]
+    Hex(hex::FromHexError),
+    #[display(fmt = "BABE genesis configuration missing")]
+    BabeGenesisConfigMissing,
 }
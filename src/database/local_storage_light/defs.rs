@@ -17,8 +17,9 @@
 
 //! Type definitions to help with serializing/deserializing from/to the local storage.
 
+use super::CorruptedErrorInner;
 use crate::{chain::chain_information, header};
-use core::{convert::TryFrom, fmt};
+use core::{convert::TryFrom, fmt, num::NonZeroU64};
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "version")]
@@ -28,7 +29,7 @@ pub(super) enum SerializedChainInformation {
 }
 
 impl TryFrom<SerializedChainInformation> for chain_information::ChainInformation {
-    type Error = header::Error;
+    type Error = CorruptedErrorInner;
 
     fn try_from(from: SerializedChainInformation) -> Result<Self, Self::Error> {
         Ok(match from {
@@ -45,6 +46,14 @@ pub(super) struct SerializedChainInformationV1 {
         deserialize_with = "deserialize_bytes"
     )]
     finalized_block_header: Vec<u8>,
+    /// Present if and only if the chain uses Aura. Otherwise, the chain uses Babe.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aura_slot_duration: Option<NonZeroU64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    aura_finalized_authorities: Vec<SerializedAuraAuthorityV1>,
+    /// Present if and only if the chain uses Babe.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    babe_genesis_config: Option<SerializedBabeGenesisConfigV1>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     babe_finalized_block1_slot_number: Option<u64>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -58,10 +67,51 @@ pub(super) struct SerializedChainInformationV1 {
     grandpa_finalized_triggered_authorities: Vec<SerializedGrandpaAuthorityV1>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     grandpa_finalized_scheduled_change: Option<SerializedFinalizedScheduledChangeV1>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    grandpa_finalized_forced_change: Option<SerializedFinalizedScheduledChangeV1>,
+    #[serde(
+        default,
+        skip_serializing_if = "SerializedGrandpaPauseStateV1::is_live"
+    )]
+    grandpa_finalized_pause_state: SerializedGrandpaPauseStateV1,
 }
 
 impl<'a> From<chain_information::ChainInformationRef<'a>> for SerializedChainInformationV1 {
     fn from(from: chain_information::ChainInformationRef<'a>) -> Self {
+        let (
+            aura_slot_duration,
+            aura_finalized_authorities,
+            babe_genesis_config,
+            babe_finalized_block1_slot_number,
+            babe_finalized_block_epoch_information,
+            babe_finalized_next_epoch_transition,
+        ) = match from.consensus {
+            chain_information::ChainInformationConsensusRef::Aura {
+                finalized_authorities_list,
+                slot_duration,
+            } => (
+                Some(slot_duration),
+                finalized_authorities_list.map(Into::into).collect(),
+                None,
+                None,
+                None,
+                None,
+            ),
+            chain_information::ChainInformationConsensusRef::Babe {
+                genesis_config,
+                finalized_block1_slot_number,
+                finalized_block_epoch_information,
+                finalized_next_epoch_transition,
+            } => (
+                None,
+                Vec::new(),
+                Some(genesis_config.into()),
+                finalized_block1_slot_number,
+                finalized_block_epoch_information.map(|(e, i)| (e.into(), i.into())),
+                finalized_next_epoch_transition.map(|(e, i)| (e.into(), i.into())),
+            ),
+        };
+
         SerializedChainInformationV1 {
             finalized_block_header: from.finalized_block_header.scale_encoding().fold(
                 Vec::new(),
@@ -70,13 +120,12 @@ impl<'a> From<chain_information::ChainInformationRef<'a>> for SerializedChainInf
                     a
                 },
             ),
-            babe_finalized_block1_slot_number: from.babe_finalized_block1_slot_number,
-            babe_finalized_block_epoch_information: from
-                .babe_finalized_block_epoch_information
-                .map(|(e, i)| (e.into(), i.into())),
-            babe_finalized_next_epoch_transition: from
-                .babe_finalized_next_epoch_transition
-                .map(|(e, i)| (e.into(), i.into())),
+            aura_slot_duration,
+            aura_finalized_authorities,
+            babe_genesis_config,
+            babe_finalized_block1_slot_number,
+            babe_finalized_block_epoch_information,
+            babe_finalized_next_epoch_transition,
             grandpa_after_finalized_block_authorities_set_id: from
                 .grandpa_after_finalized_block_authorities_set_id,
             grandpa_finalized_triggered_authorities: from
@@ -91,23 +140,49 @@ impl<'a> From<chain_information::ChainInformationRef<'a>> for SerializedChainInf
                     new_authorities_list: l.iter().map(Into::into).collect(),
                 },
             ),
+            grandpa_finalized_forced_change: from.grandpa_finalized_forced_change.map(|(n, l)| {
+                SerializedFinalizedScheduledChangeV1 {
+                    trigger_block_height: n,
+                    new_authorities_list: l.iter().map(Into::into).collect(),
+                }
+            }),
+            grandpa_finalized_pause_state: from.grandpa_finalized_pause_state.into(),
         }
     }
 }
 
 impl TryFrom<SerializedChainInformationV1> for chain_information::ChainInformation {
-    type Error = header::Error;
+    type Error = CorruptedErrorInner;
 
     fn try_from(from: SerializedChainInformationV1) -> Result<Self, Self::Error> {
         Ok(chain_information::ChainInformation {
-            finalized_block_header: header::decode(&from.finalized_block_header)?.into(),
-            babe_finalized_block1_slot_number: from.babe_finalized_block1_slot_number,
-            babe_finalized_block_epoch_information: from
-                .babe_finalized_block_epoch_information
-                .map(|(e, i)| (e.into(), i.into())),
-            babe_finalized_next_epoch_transition: from
-                .babe_finalized_next_epoch_transition
-                .map(|(e, i)| (e.into(), i.into())),
+            finalized_block_header: header::decode(&from.finalized_block_header)
+                .map_err(CorruptedErrorInner::HeaderDecode)?
+                .into(),
+            consensus: if let Some(slot_duration) = from.aura_slot_duration {
+                chain_information::ChainInformationConsensus::Aura {
+                    finalized_authorities_list: from
+                        .aura_finalized_authorities
+                        .into_iter()
+                        .map(Into::into)
+                        .collect(),
+                    slot_duration,
+                }
+            } else {
+                chain_information::ChainInformationConsensus::Babe {
+                    genesis_config: from
+                        .babe_genesis_config
+                        .ok_or(CorruptedErrorInner::BabeGenesisConfigMissing)?
+                        .into(),
+                    finalized_block1_slot_number: from.babe_finalized_block1_slot_number,
+                    finalized_block_epoch_information: from
+                        .babe_finalized_block_epoch_information
+                        .map(|(e, i)| (e.into(), i.into())),
+                    finalized_next_epoch_transition: from
+                        .babe_finalized_next_epoch_transition
+                        .map(|(e, i)| (e.into(), i.into())),
+                }
+            },
             grandpa_after_finalized_block_authorities_set_id: from
                 .grandpa_after_finalized_block_authorities_set_id,
             grandpa_finalized_triggered_authorities: from
@@ -127,10 +202,63 @@ impl TryFrom<SerializedChainInformationV1> for chain_information::ChainInformati
                     )
                 },
             ),
+            grandpa_finalized_forced_change: from.grandpa_finalized_forced_change.map(|change| {
+                (
+                    change.trigger_block_height,
+                    change
+                        .new_authorities_list
+                        .into_iter()
+                        .map(Into::into)
+                        .collect(),
+                )
+            }),
+            grandpa_finalized_pause_state: from.grandpa_finalized_pause_state.into(),
         })
     }
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedBabeGenesisConfigV1 {
+    slot_duration: u64,
+    slots_per_epoch: u64,
+    epoch0_information: SerializedBabeNextEpochV1,
+    epoch0_configuration: SerializedBabeNextConfigV1,
+}
+
+impl<'a> From<&'a chain_information::babe::BabeGenesisConfiguration>
+    for SerializedBabeGenesisConfigV1
+{
+    fn from(from: &'a chain_information::babe::BabeGenesisConfiguration) -> Self {
+        SerializedBabeGenesisConfigV1 {
+            slot_duration: from.slot_duration(),
+            slots_per_epoch: from.slots_per_epoch(),
+            epoch0_information: from.epoch0_information().into(),
+            epoch0_configuration: from.epoch0_configuration().into(),
+        }
+    }
+}
+
+impl From<SerializedBabeGenesisConfigV1> for chain_information::babe::BabeGenesisConfiguration {
+    fn from(from: SerializedBabeGenesisConfigV1) -> Self {
+        chain_information::babe::BabeGenesisConfiguration::from_components(
+            from.slot_duration,
+            from.slots_per_epoch,
+            (
+                from.epoch0_configuration.c.num,
+                from.epoch0_configuration.c.denom,
+            ),
+            from.epoch0_information
+                .authorities
+                .into_iter()
+                .map(|a| (a.public_key, a.weight))
+                .collect(),
+            from.epoch0_information.randomness,
+            from.epoch0_configuration.allowed_slots.into(),
+        )
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(deny_unknown_fields)]
 struct SerializedBabeNextEpochV1 {
@@ -271,6 +399,94 @@ struct SerializedFinalizedScheduledChangeV1 {
     new_authorities_list: Vec<SerializedGrandpaAuthorityV1>,
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+enum SerializedGrandpaPauseStateV1 {
+    #[serde(rename = "live")]
+    Live,
+    #[serde(rename = "pending-pause")]
+    PendingPause { trigger_block_height: u64 },
+    #[serde(rename = "paused")]
+    Paused,
+    #[serde(rename = "pending-resume")]
+    PendingResume { trigger_block_height: u64 },
+}
+
+impl SerializedGrandpaPauseStateV1 {
+    fn is_live(&self) -> bool {
+        matches!(self, SerializedGrandpaPauseStateV1::Live)
+    }
+}
+
+impl Default for SerializedGrandpaPauseStateV1 {
+    fn default() -> Self {
+        SerializedGrandpaPauseStateV1::Live
+    }
+}
+
+impl From<chain_information::GrandpaPauseState> for SerializedGrandpaPauseStateV1 {
+    fn from(from: chain_information::GrandpaPauseState) -> Self {
+        match from {
+            chain_information::GrandpaPauseState::Live => SerializedGrandpaPauseStateV1::Live,
+            chain_information::GrandpaPauseState::PendingPause {
+                trigger_block_height,
+            } => SerializedGrandpaPauseStateV1::PendingPause {
+                trigger_block_height,
+            },
+            chain_information::GrandpaPauseState::Paused => SerializedGrandpaPauseStateV1::Paused,
+            chain_information::GrandpaPauseState::PendingResume {
+                trigger_block_height,
+            } => SerializedGrandpaPauseStateV1::PendingResume {
+                trigger_block_height,
+            },
+        }
+    }
+}
+
+impl From<SerializedGrandpaPauseStateV1> for chain_information::GrandpaPauseState {
+    fn from(from: SerializedGrandpaPauseStateV1) -> Self {
+        match from {
+            SerializedGrandpaPauseStateV1::Live => chain_information::GrandpaPauseState::Live,
+            SerializedGrandpaPauseStateV1::PendingPause {
+                trigger_block_height,
+            } => chain_information::GrandpaPauseState::PendingPause {
+                trigger_block_height,
+            },
+            SerializedGrandpaPauseStateV1::Paused => chain_information::GrandpaPauseState::Paused,
+            SerializedGrandpaPauseStateV1::PendingResume {
+                trigger_block_height,
+            } => chain_information::GrandpaPauseState::PendingResume {
+                trigger_block_height,
+            },
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedAuraAuthorityV1 {
+    #[serde(
+        serialize_with = "serialize_bytes",
+        deserialize_with = "deserialize_hash32"
+    )]
+    public_key: [u8; 32],
+}
+
+impl<'a> From<header::AuraAuthorityRef<'a>> for SerializedAuraAuthorityV1 {
+    fn from(from: header::AuraAuthorityRef<'a>) -> Self {
+        SerializedAuraAuthorityV1 {
+            public_key: *from.public_key,
+        }
+    }
+}
+
+impl From<SerializedAuraAuthorityV1> for header::AuraAuthority {
+    fn from(from: SerializedAuraAuthorityV1) -> Self {
+        header::AuraAuthority {
+            public_key: from.public_key,
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(deny_unknown_fields)]
 struct SerializedGrandpaAuthorityV1 {
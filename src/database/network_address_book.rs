@@ -0,0 +1,185 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Persistent storage for the address book of the network, in a file.
+//!
+//! # Usage
+//!
+//! Use [`save`] to write to a file the entries returned by
+//! [`Network::address_book`](crate::network::Network::address_book), and [`load`] to read them
+//! back, typically in order to pass them to the
+//! [`Config::address_book`](crate::network::Config::address_book) field when starting the
+//! network.
+//!
+//! [`encode`] and [`decode`] can be used to store the address book by other means than a file.
+//!
+//! > **Note**: The format of the stored information isn't stable and can break without warning.
+
+use crate::network::{AddressBookEntry, Multiaddr, PeerId};
+
+use core::{fmt, time::Duration};
+use std::{fs, io, path::Path};
+
+/// Loads the address book from the given file.
+///
+/// Returns an empty list if the file doesn't exist.
+pub fn load(path: &Path) -> Result<Vec<AddressBookEntry>, LoadError> {
+    match fs::read_to_string(path) {
+        Ok(encoded) => decode(&encoded).map_err(LoadError::Corrupted),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(LoadError::Io(err)),
+    }
+}
+
+/// Saves the address book to the given file, overwriting its content.
+///
+/// The address book is first written to a temporary file next to the target, which is then
+/// renamed, so that the target file is never left partially written.
+pub fn save(path: &Path, entries: &[AddressBookEntry]) -> Result<(), io::Error> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, encode(entries))?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Encodes the given entries into a string.
+pub fn encode(entries: &[AddressBookEntry]) -> String {
+    let serialized = SerializedAddressBook::V1(SerializedAddressBookV1 {
+        peers: entries
+            .iter()
+            .map(|entry| SerializedPeerV1 {
+                peer_id: entry.peer_id.to_base58(),
+                addresses: entry.addresses.iter().map(|a| a.to_string()).collect(),
+                last_seen: entry.last_seen.as_secs(),
+                successes: entry.successes,
+                failures: entry.failures,
+            })
+            .collect(),
+    });
+
+    serde_json::to_string(&serialized).unwrap()
+}
+
+/// Decodes a string previously returned by [`encode`].
+pub fn decode(encoded: &str) -> Result<Vec<AddressBookEntry>, CorruptedError> {
+    let decoded: SerializedAddressBook =
+        serde_json::from_str(encoded).map_err(|e| CorruptedError(CorruptedErrorInner::Serde(e)))?;
+
+    match decoded {
+        SerializedAddressBook::V1(decoded) => decoded
+            .peers
+            .into_iter()
+            .map(|peer| {
+                Ok(AddressBookEntry {
+                    peer_id: peer.peer_id.parse::<PeerId>().map_err(|_| {
+                        CorruptedError(CorruptedErrorInner::PeerId(peer.peer_id.clone()))
+                    })?,
+                    addresses: peer
+                        .addresses
+                        .into_iter()
+                        .map(|a| {
+                            a.parse::<Multiaddr>()
+                                .map_err(|_| CorruptedError(CorruptedErrorInner::Multiaddr(a)))
+                        })
+                        .collect::<Result<_, _>>()?,
+                    last_seen: Duration::from_secs(peer.last_seen),
+                    successes: peer.successes,
+                    failures: peer.failures,
+                })
+            })
+            .collect(),
+    }
+}
+
+/// Error when loading the address book.
+#[derive(Debug, derive_more::Display)]
+pub enum LoadError {
+    /// Error when reading the file.
+    #[display(fmt = "Error when reading the address book file: {}", _0)]
+    Io(io::Error),
+    /// Corruption in the content of the file.
+    Corrupted(CorruptedError),
+}
+
+/// Opaque error indicating a corruption in the stored address book.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "{}", _0)]
+pub struct CorruptedError(CorruptedErrorInner);
+
+#[derive(Debug, derive_more::Display)]
+enum CorruptedErrorInner {
+    #[display(fmt = "{}", _0)]
+    Serde(serde_json::Error),
+    #[display(fmt = "Invalid peer id: {}", _0)]
+    PeerId(String),
+    #[display(fmt = "Invalid address: {}", _0)]
+    Multiaddr(String),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "version")]
+enum SerializedAddressBook {
+    #[serde(rename = "1")]
+    V1(SerializedAddressBookV1),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedAddressBookV1 {
+    peers: Vec<SerializedPeerV1>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedPeerV1 {
+    peer_id: String,
+    addresses: Vec<String>,
+    /// Number of seconds since the UNIX epoch.
+    last_seen: u64,
+    successes: u32,
+    failures: u32,
+}
+
+impl fmt::Debug for SerializedAddressBook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializedAddressBook::V1(book) => f
+                .debug_struct("SerializedAddressBook")
+                .field("num_peers", &book.peers.len())
+                .finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::{AddressBookEntry, PeerId};
+    use core::time::Duration;
+
+    #[test]
+    fn encode_decode() {
+        let entries = vec![AddressBookEntry {
+            peer_id: PeerId::random(),
+            addresses: vec!["/ip4/1.2.3.4/tcp/30333".parse().unwrap()],
+            last_seen: Duration::from_secs(1_600_000_000),
+            successes: 3,
+            failures: 1,
+        }];
+
+        let decoded = super::decode(&super::encode(&entries)).unwrap();
+        assert_eq!(decoded, entries);
+    }
+}
@@ -961,14 +961,7 @@ impl ReadyToRun {
                         }
                     };
 
-                    let mut trie = crate::trie::Trie::new();
-                    for (idx, value) in elements.into_iter().enumerate() {
-                        let idx = u32::try_from(idx).unwrap();
-                        let key =
-                            parity_scale_codec::Encode::encode(&parity_scale_codec::Compact(idx));
-                        trie.insert(&key, value);
-                    }
-                    let out = trie.root_merkle_value(None);
+                    let out = crate::trie::ordered_root(elements);
 
                     match self
                         .inner
@@ -19,3 +19,4 @@
 
 pub mod grandpa;
 pub mod justification;
+pub mod proof;
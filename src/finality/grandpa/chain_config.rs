@@ -47,35 +47,61 @@ impl GrandpaGenesisConfiguration {
     pub fn from_genesis_storage(
         mut genesis_storage_access: impl FnMut(&[u8]) -> Option<Vec<u8>>,
     ) -> Result<Self, FromGenesisStorageError> {
-        let encoded_list = if let Some(mut list) = genesis_storage_access(b":grandpa_authorities") {
+        // The runtime is only compiled if the configuration isn't found in the storage.
+        if let Some(list) = genesis_storage_access(b":grandpa_authorities") {
             // When in the storage, the encoded list of authorities starts with a version number.
             if list.first() != Some(&1) {
                 return Err(FromGenesisStorageError::UnknownEncodingVersionNumber);
             }
-            list.remove(0);
-            list
+            return Self::decode(&list[1..]).map_err(FromGenesisStorageError::OutputDecode);
+        }
+
+        let wasm_code =
+            genesis_storage_access(b":code").ok_or(FromGenesisStorageError::RuntimeNotFound)?;
+        let heap_pages = if let Some(bytes) = genesis_storage_access(b":heappages") {
+            u64::from_le_bytes(
+                <[u8; 8]>::try_from(&bytes[..])
+                    .map_err(FromGenesisStorageError::HeapPagesDecode)?,
+            )
         } else {
-            let wasm_code =
-                genesis_storage_access(b":code").ok_or(FromGenesisStorageError::RuntimeNotFound)?;
-            let heap_pages = if let Some(bytes) = genesis_storage_access(b":heappages") {
-                u64::from_le_bytes(
-                    <[u8; 8]>::try_from(&bytes[..])
-                        .map_err(FromGenesisStorageError::HeapPagesDecode)?,
-                )
-            } else {
-                1024 // TODO: default heap pages
-            };
-            let vm = executor::WasmVmPrototype::new(&wasm_code, heap_pages)
-                .map_err(FromVmPrototypeError::VmInitialization)
-                .map_err(FromGenesisStorageError::VmError)?;
-            Self::from_virtual_machine_prototype(vm, genesis_storage_access)
-                .map_err(FromGenesisStorageError::VmError)?
+            1024 // TODO: default heap pages
         };
+        let vm = executor::WasmVmPrototype::new(&wasm_code, heap_pages)
+            .map_err(FromVmPrototypeError::VmInitialization)
+            .map_err(FromGenesisStorageError::VmError)?;
+        let (cfg, _) = Self::from_virtual_machine_prototype(vm, genesis_storage_access)
+            .map_err(FromGenesisStorageError::VmError)?;
+        Ok(cfg)
+    }
 
-        let decoded = match ConfigScaleEncoding::decode_all(&encoded_list) {
-            Ok(cfg) => cfg,
-            Err(err) => return Err(FromGenesisStorageError::OutputDecode(err)),
-        };
+    /// Retrieves the configuration from the given virtual machine prototype.
+    ///
+    /// Must be passed a closure that returns the storage value corresponding to the given key in
+    /// the genesis block storage. If the configuration is found in the storage, the virtual
+    /// machine isn't run.
+    ///
+    /// Returns back the same virtual machine prototype as was passed as parameter.
+    pub fn from_virtual_machine_prototype(
+        vm: executor::WasmVmPrototype,
+        mut genesis_storage_access: impl FnMut(&[u8]) -> Option<Vec<u8>>,
+    ) -> Result<(Self, executor::WasmVmPrototype), FromVmPrototypeError> {
+        if let Some(list) = genesis_storage_access(b":grandpa_authorities") {
+            // When in the storage, the encoded list of authorities starts with a version number.
+            if list.first() != Some(&1) {
+                return Err(FromVmPrototypeError::UnknownEncodingVersionNumber);
+            }
+            let cfg = Self::decode(&list[1..]).map_err(FromVmPrototypeError::OutputDecode)?;
+            return Ok((cfg, vm));
+        }
+
+        let (encoded_list, vm) = run_vm(vm, genesis_storage_access)?;
+        let cfg = Self::decode(&encoded_list).map_err(FromVmPrototypeError::OutputDecode)?;
+        Ok((cfg, vm))
+    }
+
+    /// Decodes the SCALE-encoded list of authorities.
+    fn decode(encoded_list: &[u8]) -> Result<Self, parity_scale_codec::Error> {
+        let decoded = ConfigScaleEncoding::decode_all(encoded_list)?;
 
         let initial_authorities = decoded
             .into_iter()
@@ -86,36 +112,37 @@ impl GrandpaGenesisConfiguration {
             initial_authorities,
         })
     }
+}
 
-    fn from_virtual_machine_prototype(
-        vm: executor::WasmVmPrototype,
-        mut genesis_storage_access: impl FnMut(&[u8]) -> Option<Vec<u8>>,
-    ) -> Result<Vec<u8>, FromVmPrototypeError> {
-        // TODO: DRY with the babe config; put a helper in the executor module
-        let mut vm: executor::WasmVm = vm
-            .run_no_param("GrandpaApi_grandpa_authorities")
-            .map_err(FromVmPrototypeError::VmInitialization)?
-            .into();
-
-        Ok(loop {
-            match vm {
-                executor::WasmVm::ReadyToRun(r) => vm = r.run(),
-                executor::WasmVm::Finished(data) => {
-                    break data.value().to_owned();
-                }
-                executor::WasmVm::Error { .. } => return Err(FromVmPrototypeError::Trapped),
-
-                executor::WasmVm::ExternalStorageGet(rq) => {
-                    let value = genesis_storage_access(rq.key());
-                    vm = rq.resume_full_value(value.as_ref().map(|v| &v[..]));
-                }
-
-                executor::WasmVm::LogEmit(rq) => vm = rq.resume(),
-
-                _ => return Err(FromVmPrototypeError::ExternalityNotAllowed),
+/// Calls the `GrandpaApi_grandpa_authorities` runtime function and returns its output.
+fn run_vm(
+    vm: executor::WasmVmPrototype,
+    mut genesis_storage_access: impl FnMut(&[u8]) -> Option<Vec<u8>>,
+) -> Result<(Vec<u8>, executor::WasmVmPrototype), FromVmPrototypeError> {
+    // TODO: DRY with the babe config; put a helper in the executor module
+    let mut vm: executor::WasmVm = vm
+        .run_no_param("GrandpaApi_grandpa_authorities")
+        .map_err(FromVmPrototypeError::VmInitialization)?
+        .into();
+
+    Ok(loop {
+        match vm {
+            executor::WasmVm::ReadyToRun(r) => vm = r.run(),
+            executor::WasmVm::Finished(data) => {
+                break (data.value().to_owned(), data.into_prototype());
             }
-        })
-    }
+            executor::WasmVm::Error { .. } => return Err(FromVmPrototypeError::Trapped),
+
+            executor::WasmVm::ExternalStorageGet(rq) => {
+                let value = genesis_storage_access(rq.key());
+                vm = rq.resume_full_value(value.as_ref().map(|v| &v[..]));
+            }
+
+            executor::WasmVm::LogEmit(rq) => vm = rq.resume(),
+
+            _ => return Err(FromVmPrototypeError::ExternalityNotAllowed),
+        }
+    })
 }
 
 /// Error when retrieving the Grandpa configuration.
@@ -144,6 +171,10 @@ pub enum FromVmPrototypeError {
     Trapped,
     /// Virtual machine tried to call an externality that isn't valid in this context.
     ExternalityNotAllowed,
+    /// Version number of the encoded authorities list found in the storage isn't recognized.
+    UnknownEncodingVersionNumber,
+    /// Error while decoding the SCALE-encoded list.
+    OutputDecode(parity_scale_codec::Error),
 }
 
 type ConfigScaleEncoding = Vec<([u8; 32], u64)>;
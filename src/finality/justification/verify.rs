@@ -15,8 +15,33 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::finality::justification::decode;
-use core::convert::TryFrom as _;
+//! Verification of a justification.
+//!
+//! A justification is valid if:
+//!
+//! - Every pre-commit is signed by a member of the authorities set, with a valid signature.
+//! - Every pre-commit targets either the block referred to by the justification, or one of its
+//!   descendants. The ancestry between the target of a pre-commit and the justified block is
+//!   proven through the headers found in the *votes ancestries* of the justification. Each of
+//!   these headers must be used by at least one pre-commit.
+//! - The total weight of the authorities that have signed a pre-commit is strictly superior to
+//!   two thirds of the total weight of the authorities set.
+//!
+//! An authority that signs pre-commits for two different blocks is called an *equivocator*.
+//! Equivocations are allowed to be part of a justification, in which case the weight of the
+//! equivocator is only counted once. Equivocations are reported as part of [`Success`].
+//!
+//! Verifying the signatures of the pre-commits is the most CPU-intensive part of the
+//! verification, but doesn't require knowing the list of authorities. When a large number of
+//! justifications need to be verified, for example while syncing, [`pre_verify_signatures`] can
+//! be called ahead of time on many justifications in parallel. The returned
+//! [`PreVerifiedSignatures`] must then be passed as [`Config::pre_verified_signatures`] in order
+//! to skip these checks.
+
+use crate::{finality::justification::decode, header};
+
+use core::{convert::TryFrom as _, iter};
+use hashbrown::{HashMap, HashSet};
 
 /// Configuration for a justification verification process.
 #[derive(Debug)]
@@ -24,76 +49,341 @@ pub struct Config<'a, I> {
     /// Justification to verify.
     pub justification: decode::JustificationRef<'a>,
 
-    // TODO: document
+    /// Identifier of the authorities set that has emitted the pre-commits. Incremented every
+    /// time the authorities set changes. Part of the message being signed.
     pub authorities_set_id: u64,
 
     /// List of authorities that are allowed to emit pre-commits for the block referred to by
-    /// the justification. Must implement `Iterator<Item = impl AsRef<[u8]>> + Clone`, where
-    /// each item is the public key of an authority.
+    /// the justification. Must implement `Iterator<Item = GrandpaAuthorityRef> + Clone`.
     pub authorities_list: I,
-}
 
-// TODO: rewrite as a generator-style process?
+    /// Outcome of calling [`pre_verify_signatures`] on this justification, if any.
+    ///
+    /// If it has been obtained for this justification and for
+    /// [`Config::authorities_set_id`], the signatures of the pre-commits aren't verified again.
+    /// It is ignored otherwise.
+    pub pre_verified_signatures: Option<&'a PreVerifiedSignatures>,
+}
 
 /// Verifies that a justification is valid.
-pub fn verify<'a>(
-    config: Config<'a, impl Iterator<Item = impl AsRef<[u8]>> + Clone>,
-) -> Result<(), Error> {
-    // Verifying all the signatures together brings better performances than verifying them one
-    // by one.
-    let mut messages = Vec::with_capacity(config.justification.precommits.iter().len());
-    let mut signatures = Vec::with_capacity(config.justification.precommits.iter().len());
-    let mut public_keys = Vec::with_capacity(config.justification.precommits.iter().len());
+pub fn verify<'a, 'b>(
+    config: Config<'a, impl Iterator<Item = header::GrandpaAuthorityRef<'b>> + Clone>,
+) -> Result<Success, Error> {
+    let target_hash = *config.justification.target_hash;
+    let target_number = u64::from(config.justification.target_number);
+
+    // Map of the headers of the votes ancestries. Keys are block hashes, and values are the
+    // number and parent hash of these blocks.
+    let votes_ancestries = config
+        .justification
+        .votes_ancestries
+        .clone()
+        .map(|header| (header.hash(), (header.number, *header.parent_hash)))
+        .collect::<HashMap<_, _, fnv::FnvBuildHasher>>();
+    // List of entries of `votes_ancestries` that have been used to prove the ancestry of a
+    // pre-commit.
+    let mut used_votes_ancestries = HashSet::<_, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+        votes_ancestries.len(),
+        Default::default(),
+    );
+
+    // For each authority that has signed a pre-commit, contains the targets and signatures of
+    // all the pre-commits of that authority, in order.
+    let mut signers =
+        HashMap::<[u8; 32], Vec<(u64, [u8; 32], [u8; 64])>, fnv::FnvBuildHasher>::default();
+    let mut signed_weight = 0u64;
+    let mut equivocations = Vec::new();
 
     for precommit in config.justification.precommits.iter() {
-        if !config
+        let authority_weight = match config
             .authorities_list
             .clone()
-            .any(|a| a.as_ref() == precommit.authority_public_key)
+            .find(|a| a.public_key == precommit.authority_public_key)
         {
-            return Err(Error::NotAuthority(*precommit.authority_public_key));
+            Some(a) => a.weight,
+            None => return Err(Error::NotAuthority(*precommit.authority_public_key)),
+        };
+
+        // Check that the pre-commit targets the justified block or one of its descendants,
+        // using the votes ancestries.
+        {
+            let mut current_hash = *precommit.target_hash;
+            let mut current_number = u64::from(precommit.target_number);
+            loop {
+                if current_number < target_number {
+                    return Err(Error::PrecommitNotDescendant {
+                        precommit_target_hash: *precommit.target_hash,
+                    });
+                }
+
+                if current_hash == target_hash {
+                    if current_number != target_number {
+                        return Err(Error::BadPrecommitTargetNumber {
+                            precommit_target_hash: *precommit.target_hash,
+                        });
+                    }
+                    break;
+                }
+
+                match votes_ancestries.get(&current_hash) {
+                    Some((number, parent_hash)) if *number == current_number => {
+                        used_votes_ancestries.insert(current_hash);
+                        current_hash = *parent_hash;
+                        current_number = match current_number.checked_sub(1) {
+                            Some(n) => n,
+                            None => {
+                                return Err(Error::PrecommitNotDescendant {
+                                    precommit_target_hash: *precommit.target_hash,
+                                })
+                            }
+                        };
+                    }
+                    Some(_) => {
+                        return Err(Error::BadPrecommitTargetNumber {
+                            precommit_target_hash: *precommit.target_hash,
+                        })
+                    }
+                    None => {
+                        return Err(Error::PrecommitNotDescendant {
+                            precommit_target_hash: *precommit.target_hash,
+                        })
+                    }
+                }
+            }
         }
 
-        // TODO: must check signed block ancestry using `votes_ancestries`
-
-        messages.push({
-            let mut msg = Vec::with_capacity(1 + 32 + 4 + 8 + 8);
-            msg.push(1u8); // This `1` indicates which kind of message is being signed.
-            msg.extend_from_slice(&precommit.target_hash[..]);
-            msg.extend_from_slice(&u32::to_le_bytes(precommit.target_number)[..]);
-            msg.extend_from_slice(&u64::to_le_bytes(config.justification.round)[..]);
-            msg.extend_from_slice(&u64::to_le_bytes(config.authorities_set_id)[..]);
-            debug_assert_eq!(msg.len(), msg.capacity());
-            msg
-        });
-
-        // Can only panic in case of bad signature length, which we know can't happen.
-        signatures.push(ed25519_dalek::Signature::try_from(&precommit.signature[..]).unwrap());
-
-        public_keys.push(
-            ed25519_dalek::PublicKey::from_bytes(precommit.authority_public_key)
-                .map_err(|_| Error::BadPublicKey)?,
-        );
+        // Count the weight of each authority only once, and detect duplicates and
+        // equivocations by comparing against all the previous pre-commits of that authority.
+        let previous = signers
+            .entry(*precommit.authority_public_key)
+            .or_insert_with(Vec::new);
+        if previous.is_empty() {
+            signed_weight = signed_weight.saturating_add(authority_weight);
+        } else if previous
+            .iter()
+            .any(|(_, hash, _)| hash == precommit.target_hash)
+        {
+            return Err(Error::DuplicateSignature(*precommit.authority_public_key));
+        } else if previous.len() == 1 {
+            // Each equivocator is reported only once, even if it signed more than two
+            // different blocks.
+            let (number, hash, signature) = previous[0];
+            equivocations.push(Equivocation {
+                authorities_set_id: config.authorities_set_id,
+                round_number: config.justification.round,
+                authority_public_key: *precommit.authority_public_key,
+                first: (number, hash, signature),
+                second: (
+                    u64::from(precommit.target_number),
+                    *precommit.target_hash,
+                    *precommit.signature,
+                ),
+            });
+        }
+        previous.push((
+            u64::from(precommit.target_number),
+            *precommit.target_hash,
+            *precommit.signature,
+        ));
     }
 
-    debug_assert_eq!(messages.len(), public_keys.len());
-    debug_assert_eq!(messages.len(), signatures.len());
-    debug_assert_eq!(public_keys.len(), signatures.len());
-
-    debug_assert_eq!(messages.len(), messages.capacity());
-    debug_assert_eq!(signatures.len(), signatures.capacity());
-    debug_assert_eq!(public_keys.len(), public_keys.capacity());
+    // Every header in the votes ancestries must be used by at least one pre-commit. Including
+    // unnecessary headers is considered as invalid, as it could be used to make justifications
+    // needlessly large.
+    if used_votes_ancestries.len() != votes_ancestries.len() {
+        return Err(Error::UnnecessaryVotesAncestries);
+    }
 
+    // Check that more than two thirds of the authorities, by weight, have signed a pre-commit.
+    // This is done before the signatures verification, as it is much cheaper.
     {
-        let messages_refs = messages.iter().map(|m| &m[..]).collect::<Vec<_>>();
-        ed25519_dalek::verify_batch(&messages_refs, &signatures, &public_keys)
-            .map_err(|_| Error::BadSignature)?;
+        let total_weight = config
+            .authorities_list
+            .clone()
+            .fold(0u64, |sum, a| sum.saturating_add(a.weight));
+        // Maximum weight of faulty authorities that the protocol tolerates.
+        let faulty_weight = total_weight.saturating_sub(1) / 3;
+        let required_weight = total_weight - faulty_weight;
+        if signed_weight < required_weight {
+            return Err(Error::NotEnoughSignatures {
+                signed_weight,
+                required_weight,
+            });
+        }
+    }
+
+    // The pre-verification is only relevant if it concerns these exact signatures.
+    let signed_precommits =
+        SignedPrecommits::new(&config.justification, config.authorities_set_id)?;
+    if config.pre_verified_signatures.map_or(true, |pre| {
+        pre.signed_precommits_hash != signed_precommits.hash()
+    }) {
+        signed_precommits.verify()?;
+    }
+
+    Ok(Success { equivocations })
+}
+
+/// Outcome of a successful call to [`pre_verify_signatures`].
+///
+/// Proves that the signatures of the pre-commits of a justification are correct, assuming that
+/// they have been emitted by a certain authorities set.
+#[derive(Debug, Clone)]
+pub struct PreVerifiedSignatures {
+    /// Hash of the messages, signatures and public keys that have been verified.
+    signed_precommits_hash: [u8; 32],
+}
+
+/// Verifies the signatures of the pre-commits of the given justification, assuming that they
+/// have been emitted by the authorities set whose identifier is `authorities_set_id`.
+///
+/// Contrary to [`verify`], this function doesn't need the list of authorities and can therefore
+/// be called on many justifications ahead of time and in parallel. The
+/// [`PreVerifiedSignatures`] must then be passed as [`Config::pre_verified_signatures`].
+///
+/// Returns `None` if one of the public keys or signatures is invalid.
+///
+/// > **Note**: Passing a wrong authorities set identifier never leads to an invalid
+/// >           justification being accepted. The [`PreVerifiedSignatures`] is simply ignored if
+/// >           it doesn't correspond to the authorities set passed to [`verify`].
+pub fn pre_verify_signatures(
+    justification: &decode::JustificationRef,
+    authorities_set_id: u64,
+) -> Option<PreVerifiedSignatures> {
+    let signed_precommits = SignedPrecommits::new(justification, authorities_set_id).ok()?;
+    signed_precommits.verify().ok()?;
+    Some(PreVerifiedSignatures {
+        signed_precommits_hash: signed_precommits.hash(),
+    })
+}
+
+/// Messages signed by the authorities that have emitted the pre-commits of a justification,
+/// alongside with their signatures and public keys.
+struct SignedPrecommits {
+    messages: Vec<Vec<u8>>,
+    signatures: Vec<ed25519_dalek::Signature>,
+    public_keys: Vec<ed25519_dalek::PublicKey>,
+}
+
+impl SignedPrecommits {
+    fn new(
+        justification: &decode::JustificationRef,
+        authorities_set_id: u64,
+    ) -> Result<Self, Error> {
+        let mut messages = Vec::with_capacity(justification.precommits.iter().len());
+        let mut signatures = Vec::with_capacity(justification.precommits.iter().len());
+        let mut public_keys = Vec::with_capacity(justification.precommits.iter().len());
+
+        for precommit in justification.precommits.iter() {
+            messages.push({
+                let mut msg = Vec::with_capacity(1 + 32 + 4 + 8 + 8);
+                msg.push(1u8); // This `1` indicates which kind of message is being signed.
+                msg.extend_from_slice(&precommit.target_hash[..]);
+                msg.extend_from_slice(&u32::to_le_bytes(precommit.target_number)[..]);
+                msg.extend_from_slice(&u64::to_le_bytes(justification.round)[..]);
+                msg.extend_from_slice(&u64::to_le_bytes(authorities_set_id)[..]);
+                debug_assert_eq!(msg.len(), msg.capacity());
+                msg
+            });
+
+            // The length of the signature is always correct, but signatures whose scalar isn't
+            // reduced are rejected.
+            signatures.push(
+                ed25519_dalek::Signature::try_from(&precommit.signature[..])
+                    .map_err(|_| Error::BadSignature)?,
+            );
+
+            public_keys.push(
+                ed25519_dalek::PublicKey::from_bytes(precommit.authority_public_key)
+                    .map_err(|_| Error::BadPublicKey)?,
+            );
+        }
+
+        debug_assert_eq!(messages.len(), messages.capacity());
+        debug_assert_eq!(signatures.len(), signatures.capacity());
+        debug_assert_eq!(public_keys.len(), public_keys.capacity());
+
+        Ok(SignedPrecommits {
+            messages,
+            signatures,
+            public_keys,
+        })
+    }
+
+    /// Returns a hash of all the messages, signatures and public keys.
+    fn hash(&self) -> [u8; 32] {
+        let mut hasher = blake2_rfc::blake2b::Blake2b::new(32);
+        for ((message, signature), public_key) in self
+            .messages
+            .iter()
+            .zip(self.signatures.iter())
+            .zip(self.public_keys.iter())
+        {
+            hasher.update(message);
+            hasher.update(&signature.to_bytes());
+            hasher.update(public_key.as_bytes());
+        }
+
+        let mut out = [0; 32];
+        out.copy_from_slice(hasher.finalize().as_bytes());
+        out
     }
 
-    // TODO: must check that votes_ancestries doesn't contain any unused entry
-    // TODO: there's also a "ghost" thing?
+    /// Verifies all the signatures.
+    fn verify(&self) -> Result<(), Error> {
+        // Verifying all the signatures together brings better performances than verifying them
+        // one by one.
+        let messages_refs = self.messages.iter().map(|m| &m[..]).collect::<Vec<_>>();
+        ed25519_dalek::verify_batch(&messages_refs, &self.signatures, &self.public_keys)
+            .map_err(|_| Error::BadSignature)
+    }
+}
 
-    Ok(())
+/// Information about a successfully-verified justification.
+#[derive(Debug)]
+pub struct Success {
+    /// List of authorities that have signed pre-commits for more than one block. Each
+    /// equivocator is only present once in this list.
+    pub equivocations: Vec<Equivocation>,
+}
+
+/// Two pre-commits for different blocks signed by the same authority during the same round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Equivocation {
+    /// Identifier of the authorities set the equivocator belongs to.
+    pub authorities_set_id: u64,
+    /// Round during which both pre-commits have been emitted.
+    pub round_number: u64,
+    /// Public key of the authority that has signed both pre-commits.
+    pub authority_public_key: [u8; 32],
+    /// Number, hash, and signature of the first pre-commit.
+    pub first: (u64, [u8; 32], [u8; 64]),
+    /// Number, hash, and signature of the second pre-commit.
+    pub second: (u64, [u8; 32], [u8; 64]),
+}
+
+impl Equivocation {
+    /// Returns an iterator to list of buffers which, when concatenated, produces the SCALE
+    /// encoding of the equivocation proof.
+    ///
+    /// This proof, alongside with a proof of ownership of the key of the equivocator, can be
+    /// passed to the `GrandpaApi_submit_report_equivocation_unsigned_extrinsic` runtime
+    /// function in order to generate a `report_equivocation` extrinsic.
+    pub fn scale_encoding(&self) -> impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone {
+        let mut out = Vec::with_capacity(8 + 1 + 8 + 32 + 2 * (32 + 4 + 64));
+        out.extend_from_slice(&self.authorities_set_id.to_le_bytes());
+        out.push(1); // Indicates an equivocation of pre-commits, as opposed to pre-votes.
+        out.extend_from_slice(&self.round_number.to_le_bytes());
+        out.extend_from_slice(&self.authority_public_key);
+        for (number, hash, signature) in [&self.first, &self.second].iter() {
+            out.extend_from_slice(hash);
+            // Block numbers in pre-commits are always 32 bits.
+            out.extend_from_slice(&u32::try_from(*number).unwrap().to_le_bytes());
+            out.extend_from_slice(signature);
+        }
+        debug_assert_eq!(out.len(), out.capacity());
+        iter::once(out)
+    }
 }
 
 /// Error that can happen while verifying a justification.
@@ -106,4 +396,516 @@ pub enum Error {
     /// One of the public keys isn't in the list of authorities.
     #[display(fmt = "One of the public keys isn't in the list of authorities")]
     NotAuthority([u8; 32]),
+    /// An authority has signed the same pre-commit multiple times.
+    #[display(fmt = "An authority has signed the same pre-commit multiple times")]
+    DuplicateSignature([u8; 32]),
+    /// A pre-commit targets a block that isn't the justified block or one of its descendants,
+    /// or whose ancestry isn't proven by the votes ancestries.
+    #[display(fmt = "A pre-commit doesn't target a descendant of the justified block")]
+    PrecommitNotDescendant {
+        /// Hash of the block targeted by the pre-commit.
+        precommit_target_hash: [u8; 32],
+    },
+    /// The block number in a pre-commit doesn't match the number of the block it targets.
+    #[display(fmt = "A pre-commit contains a wrong block number")]
+    BadPrecommitTargetNumber {
+        /// Hash of the block targeted by the pre-commit.
+        precommit_target_hash: [u8; 32],
+    },
+    /// The votes ancestries contain headers that aren't necessary to prove the ancestry of the
+    /// pre-commits.
+    #[display(fmt = "The votes ancestries contain unnecessary headers")]
+    UnnecessaryVotesAncestries,
+    /// The authorities that have signed pre-commits don't represent a large enough portion of
+    /// the authorities set.
+    #[display(
+        fmt = "Not enough signatures: {} weight signed, {} required",
+        signed_weight,
+        required_weight
+    )]
+    NotEnoughSignatures {
+        /// Total weight of the authorities that have signed a pre-commit.
+        signed_weight: u64,
+        /// Minimum weight required for the justification to be valid.
+        required_weight: u64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pre_verify_signatures, verify, Config, Error, PreVerifiedSignatures};
+    use crate::{finality::justification::decode, header};
+    use core::convert::TryFrom as _;
+
+    /// Justification of block #302592, round 439559, decoded in the `decode` module's tests.
+    /// Signed by five authorities of the set #0.
+    const REAL_JUSTIFICATION: &[u8] = &[
+        7, 181, 6, 0, 0, 0, 0, 0, 41, 241, 171, 236, 144, 172, 25, 157, 240, 109, 238, 59, 160,
+        115, 76, 8, 195, 253, 109, 240, 108, 170, 63, 120, 149, 47, 143, 149, 22, 64, 88, 210, 0,
+        158, 4, 0, 20, 41, 241, 171, 236, 144, 172, 25, 157, 240, 109, 238, 59, 160, 115, 76, 8,
+        195, 253, 109, 240, 108, 170, 63, 120, 149, 47, 143, 149, 22, 64, 88, 210, 0, 158, 4, 0,
+        13, 247, 129, 120, 204, 170, 120, 173, 41, 241, 213, 234, 121, 111, 20, 38, 193, 94, 99,
+        139, 57, 30, 71, 209, 236, 222, 165, 123, 70, 139, 71, 65, 36, 142, 39, 13, 94, 240, 44,
+        174, 150, 85, 149, 223, 166, 82, 210, 103, 40, 129, 102, 26, 212, 116, 231, 209, 163, 107,
+        49, 82, 229, 197, 82, 8, 28, 21, 28, 17, 203, 114, 51, 77, 38, 215, 7, 105, 227, 175, 123,
+        191, 243, 128, 26, 78, 45, 202, 43, 9, 183, 204, 224, 175, 141, 216, 19, 7, 41, 241, 171,
+        236, 144, 172, 25, 157, 240, 109, 238, 59, 160, 115, 76, 8, 195, 253, 109, 240, 108, 170,
+        63, 120, 149, 47, 143, 149, 22, 64, 88, 210, 0, 158, 4, 0, 62, 37, 145, 44, 21, 192, 120,
+        229, 236, 113, 122, 56, 193, 247, 45, 210, 184, 12, 62, 220, 253, 147, 70, 133, 85, 18, 90,
+        167, 201, 118, 23, 107, 184, 187, 3, 104, 170, 132, 17, 18, 89, 77, 156, 145, 242, 8, 185,
+        88, 74, 87, 21, 52, 247, 101, 57, 154, 163, 5, 130, 20, 15, 230, 8, 3, 104, 13, 39, 130,
+        19, 249, 8, 101, 138, 73, 161, 2, 90, 127, 70, 108, 25, 126, 143, 182, 250, 187, 94, 98,
+        34, 10, 123, 215, 95, 134, 12, 171, 41, 241, 171, 236, 144, 172, 25, 157, 240, 109, 238,
+        59, 160, 115, 76, 8, 195, 253, 109, 240, 108, 170, 63, 120, 149, 47, 143, 149, 22, 64, 88,
+        210, 0, 158, 4, 0, 125, 172, 79, 71, 1, 38, 137, 128, 232, 95, 70, 104, 217, 95, 7, 58, 28,
+        114, 182, 216, 171, 56, 231, 218, 199, 244, 220, 122, 6, 225, 5, 175, 172, 47, 198, 61, 84,
+        42, 75, 66, 62, 90, 243, 18, 58, 36, 108, 235, 132, 103, 136, 38, 164, 164, 237, 164, 41,
+        225, 152, 157, 146, 237, 24, 11, 142, 89, 54, 135, 0, 234, 137, 226, 191, 137, 34, 204,
+        158, 75, 134, 214, 101, 29, 28, 104, 154, 13, 87, 129, 63, 151, 104, 219, 170, 222, 207,
+        113, 41, 241, 171, 236, 144, 172, 25, 157, 240, 109, 238, 59, 160, 115, 76, 8, 195, 253,
+        109, 240, 108, 170, 63, 120, 149, 47, 143, 149, 22, 64, 88, 210, 0, 158, 4, 0, 68, 192,
+        211, 142, 239, 33, 55, 222, 165, 127, 203, 155, 217, 170, 61, 95, 206, 74, 74, 19, 123, 60,
+        67, 142, 80, 18, 175, 40, 136, 156, 151, 224, 191, 157, 91, 187, 39, 185, 249, 212, 158,
+        73, 197, 90, 54, 222, 13, 76, 181, 134, 69, 3, 165, 248, 94, 196, 68, 186, 80, 218, 87,
+        162, 17, 11, 222, 166, 244, 167, 39, 211, 178, 57, 146, 117, 214, 238, 136, 23, 136, 31,
+        16, 89, 116, 113, 220, 29, 39, 241, 68, 41, 90, 214, 251, 147, 60, 122, 41, 241, 171, 236,
+        144, 172, 25, 157, 240, 109, 238, 59, 160, 115, 76, 8, 195, 253, 109, 240, 108, 170, 63,
+        120, 149, 47, 143, 149, 22, 64, 88, 210, 0, 158, 4, 0, 58, 187, 123, 135, 2, 157, 81, 197,
+        40, 200, 218, 52, 253, 193, 119, 104, 190, 246, 221, 225, 175, 195, 177, 218, 209, 175, 83,
+        119, 98, 175, 196, 48, 67, 76, 59, 223, 13, 202, 48, 1, 10, 99, 200, 201, 123, 29, 89, 131,
+        120, 70, 162, 235, 11, 191, 96, 57, 83, 51, 217, 199, 35, 50, 174, 2, 247, 45, 175, 46, 86,
+        14, 79, 15, 34, 251, 92, 187, 4, 173, 29, 127, 238, 133, 10, 171, 35, 143, 208, 20, 193,
+        120, 118, 158, 126, 58, 155, 132, 0,
+    ];
+
+    /// Public keys of the five authorities that have signed [`REAL_JUSTIFICATION`], in the order
+    /// in which their pre-commits appear in it.
+    ///
+    /// The chain that the justification comes from isn't known, and its authority set can't be
+    /// obtained independently. The tests that use this list therefore only check that the
+    /// signatures of a real justification are verified against the right message, and how the
+    /// authority list is handled. They don't prove that these keys are the actual authorities.
+    /// The verification logic itself is tested against [`test_authorities`].
+    const REAL_AUTHORITIES: [[u8; 32]; 5] = [
+        [
+            28, 21, 28, 17, 203, 114, 51, 77, 38, 215, 7, 105, 227, 175, 123, 191, 243, 128, 26,
+            78, 45, 202, 43, 9, 183, 204, 224, 175, 141, 216, 19, 7,
+        ],
+        [
+            104, 13, 39, 130, 19, 249, 8, 101, 138, 73, 161, 2, 90, 127, 70, 108, 25, 126, 143,
+            182, 250, 187, 94, 98, 34, 10, 123, 215, 95, 134, 12, 171,
+        ],
+        [
+            142, 89, 54, 135, 0, 234, 137, 226, 191, 137, 34, 204, 158, 75, 134, 214, 101, 29, 28,
+            104, 154, 13, 87, 129, 63, 151, 104, 219, 170, 222, 207, 113,
+        ],
+        [
+            222, 166, 244, 167, 39, 211, 178, 57, 146, 117, 214, 238, 136, 23, 136, 31, 16, 89,
+            116, 113, 220, 29, 39, 241, 68, 41, 90, 214, 251, 147, 60, 122,
+        ],
+        [
+            247, 45, 175, 46, 86, 14, 79, 15, 34, 251, 92, 187, 4, 173, 29, 127, 238, 133, 10, 171,
+            35, 143, 208, 20, 193, 120, 118, 158, 126, 58, 155, 132,
+        ],
+    ];
+
+    fn verify_with(
+        justification: &[u8],
+        authorities_set_id: u64,
+        authorities: &[[u8; 32]],
+    ) -> Result<super::Success, Error> {
+        verify(Config {
+            justification: decode::decode(justification).unwrap(),
+            authorities_set_id,
+            authorities_list: authorities
+                .iter()
+                .map(|public_key| header::GrandpaAuthorityRef {
+                    public_key,
+                    weight: 1,
+                }),
+            pre_verified_signatures: None,
+        })
+    }
+
+    #[test]
+    fn real_justification_valid() {
+        let success = verify_with(REAL_JUSTIFICATION, 0, &REAL_AUTHORITIES).unwrap();
+        assert!(success.equivocations.is_empty());
+    }
+
+    #[test]
+    fn real_justification_wrong_set_id() {
+        assert!(matches!(
+            verify_with(REAL_JUSTIFICATION, 1, &REAL_AUTHORITIES),
+            Err(Error::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn real_justification_not_authority() {
+        assert!(matches!(
+            verify_with(REAL_JUSTIFICATION, 0, &REAL_AUTHORITIES[1..]),
+            Err(Error::NotAuthority(k)) if k == REAL_AUTHORITIES[0]
+        ));
+    }
+
+    #[test]
+    fn real_justification_not_enough_signatures() {
+        let mut authorities = REAL_AUTHORITIES.to_vec();
+        // 5 signers out of 8 authorities is below the threshold of 6.
+        authorities.extend_from_slice(&[[1; 32], [2; 32], [3; 32]]);
+        assert!(matches!(
+            verify_with(REAL_JUSTIFICATION, 0, &authorities),
+            Err(Error::NotEnoughSignatures {
+                signed_weight: 5,
+                required_weight: 6
+            })
+        ));
+
+        // 5 signers out of 7 authorities is enough.
+        authorities.pop();
+        assert!(verify_with(REAL_JUSTIFICATION, 0, &authorities).is_ok());
+    }
+
+    #[test]
+    fn pre_verified_signatures() {
+        let verify_pre_verified =
+            |justification: &[u8],
+             authorities_set_id,
+             pre_verified_signatures: &PreVerifiedSignatures| {
+                verify(Config {
+                    justification: decode::decode(justification).unwrap(),
+                    authorities_set_id,
+                    authorities_list: REAL_AUTHORITIES.iter().map(|public_key| {
+                        header::GrandpaAuthorityRef {
+                            public_key,
+                            weight: 1,
+                        }
+                    }),
+                    pre_verified_signatures: Some(pre_verified_signatures),
+                })
+            };
+
+        let decoded = decode::decode(REAL_JUSTIFICATION).unwrap();
+        assert!(pre_verify_signatures(&decoded, 1).is_none());
+        let pre_verified = pre_verify_signatures(&decoded, 0).unwrap();
+        assert!(verify_pre_verified(REAL_JUSTIFICATION, 0, &pre_verified).is_ok());
+
+        // The pre-verification is ignored if it has been performed against another set.
+        assert!(matches!(
+            verify_pre_verified(REAL_JUSTIFICATION, 1, &pre_verified),
+            Err(Error::BadSignature)
+        ));
+
+        // The pre-verification is ignored if it concerns another justification.
+        let mut tampered = REAL_JUSTIFICATION.to_vec();
+        let last_signature_byte = tampered.len() - 1 - 32 - 1;
+        tampered[last_signature_byte] ^= 1;
+        assert!(matches!(
+            verify_pre_verified(&tampered, 0, &pre_verified),
+            Err(Error::BadSignature)
+        ));
+    }
+
+    /// Builds a SCALE-encoded header with an empty digest.
+    fn build_header(parent_hash: [u8; 32], number: u8) -> Vec<u8> {
+        assert!(number < 64);
+        let mut header = parent_hash.to_vec();
+        header.push(number << 2);
+        header.extend_from_slice(&[0; 64]);
+        header.push(0);
+        header
+    }
+
+    struct TestPrecommit {
+        target_hash: [u8; 32],
+        target_number: u32,
+        authority_seed: u8,
+    }
+
+    /// Builds a SCALE-encoded justification signed with the secret keys derived from the seeds.
+    fn build_justification(
+        target_hash: [u8; 32],
+        target_number: u32,
+        precommits: &[TestPrecommit],
+        votes_ancestries: &[&[u8]],
+    ) -> Vec<u8> {
+        const ROUND: u64 = 12;
+        const SET_ID: u64 = 3;
+
+        let mut out = ROUND.to_le_bytes().to_vec();
+        out.extend_from_slice(&target_hash);
+        out.extend_from_slice(&target_number.to_le_bytes());
+        out.push(u8::try_from(precommits.len() << 2).unwrap());
+        for precommit in precommits {
+            let secret =
+                ed25519_dalek::SecretKey::from_bytes(&[precommit.authority_seed; 32]).unwrap();
+            let public = ed25519_dalek::PublicKey::from(&secret);
+            let mut msg = vec![1u8];
+            msg.extend_from_slice(&precommit.target_hash);
+            msg.extend_from_slice(&precommit.target_number.to_le_bytes());
+            msg.extend_from_slice(&ROUND.to_le_bytes());
+            msg.extend_from_slice(&SET_ID.to_le_bytes());
+            let signature = ed25519_dalek::ExpandedSecretKey::from(&secret).sign(&msg, &public);
+
+            out.extend_from_slice(&precommit.target_hash);
+            out.extend_from_slice(&precommit.target_number.to_le_bytes());
+            out.extend_from_slice(&signature.to_bytes());
+            out.extend_from_slice(public.as_bytes());
+        }
+        out.push(u8::try_from(votes_ancestries.len() << 2).unwrap());
+        for header in votes_ancestries {
+            out.extend_from_slice(header);
+        }
+        out
+    }
+
+    fn test_authorities() -> Vec<[u8; 32]> {
+        (1..=4)
+            .map(|seed| {
+                let secret = ed25519_dalek::SecretKey::from_bytes(&[seed; 32]).unwrap();
+                ed25519_dalek::PublicKey::from(&secret).to_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn votes_ancestries() {
+        let target = build_header([0xaa; 32], 10);
+        let target_hash = header::hash_from_scale_encoded_header(&target);
+        let child = build_header(target_hash, 11);
+        let child_hash = header::hash_from_scale_encoded_header(&child);
+        let grandchild = build_header(child_hash, 12);
+        let grandchild_hash = header::hash_from_scale_encoded_header(&grandchild);
+
+        let precommits = [
+            TestPrecommit {
+                target_hash,
+                target_number: 10,
+                authority_seed: 1,
+            },
+            TestPrecommit {
+                target_hash: child_hash,
+                target_number: 11,
+                authority_seed: 2,
+            },
+            TestPrecommit {
+                target_hash: grandchild_hash,
+                target_number: 12,
+                authority_seed: 3,
+            },
+        ];
+
+        // Valid.
+        let justification =
+            build_justification(target_hash, 10, &precommits, &[&child, &grandchild]);
+        assert!(verify_with(&justification, 3, &test_authorities()).is_ok());
+
+        // Missing ancestry.
+        let justification = build_justification(target_hash, 10, &precommits, &[&child]);
+        assert!(matches!(
+            verify_with(&justification, 3, &test_authorities()),
+            Err(Error::PrecommitNotDescendant { precommit_target_hash }) if precommit_target_hash == grandchild_hash
+        ));
+
+        // Unnecessary ancestry.
+        let justification = build_justification(
+            target_hash,
+            10,
+            &precommits,
+            &[&child, &grandchild, &build_header([0xbb; 32], 13)],
+        );
+        assert!(matches!(
+            verify_with(&justification, 3, &test_authorities()),
+            Err(Error::UnnecessaryVotesAncestries)
+        ));
+
+        // Pre-commit targeting a block that isn't a descendant of the justified block.
+        let justification = build_justification(
+            child_hash,
+            11,
+            &[
+                TestPrecommit {
+                    target_hash: child_hash,
+                    target_number: 11,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash: child_hash,
+                    target_number: 11,
+                    authority_seed: 2,
+                },
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 3,
+                },
+            ],
+            &[],
+        );
+        assert!(matches!(
+            verify_with(&justification, 3, &test_authorities()),
+            Err(Error::PrecommitNotDescendant { precommit_target_hash }) if precommit_target_hash == target_hash
+        ));
+    }
+
+    #[test]
+    fn duplicates_and_equivocations() {
+        let target = build_header([0xaa; 32], 10);
+        let target_hash = header::hash_from_scale_encoded_header(&target);
+        let child = build_header(target_hash, 11);
+        let child_hash = header::hash_from_scale_encoded_header(&child);
+
+        // A justification containing twice the same pre-commit of the same authority is
+        // invalid.
+        let justification = build_justification(
+            target_hash,
+            10,
+            &[
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 2,
+                },
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 2,
+                },
+            ],
+            &[],
+        );
+        assert!(matches!(
+            verify_with(&justification, 3, &test_authorities()),
+            Err(Error::DuplicateSignature(_))
+        ));
+
+        // A duplicate is detected even if the authority has signed another pre-commit in
+        // between.
+        let justification = build_justification(
+            target_hash,
+            10,
+            &[
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash: child_hash,
+                    target_number: 11,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash: child_hash,
+                    target_number: 11,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 2,
+                },
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 3,
+                },
+            ],
+            &[&child],
+        );
+        assert!(matches!(
+            verify_with(&justification, 3, &test_authorities()),
+            Err(Error::DuplicateSignature(k)) if k == test_authorities()[0]
+        ));
+
+        // An equivocator's weight is counted once, and the equivocation is reported.
+        let justification = build_justification(
+            target_hash,
+            10,
+            &[
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash: child_hash,
+                    target_number: 11,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 2,
+                },
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 3,
+                },
+            ],
+            &[&child],
+        );
+        let success = verify_with(&justification, 3, &test_authorities()).unwrap();
+        assert_eq!(success.equivocations.len(), 1);
+        assert_eq!(
+            success.equivocations[0].authority_public_key,
+            test_authorities()[0]
+        );
+        assert_eq!(success.equivocations[0].authorities_set_id, 3);
+        assert_eq!(success.equivocations[0].round_number, 12);
+        let proof = success.equivocations[0]
+            .scale_encoding()
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+        assert_eq!(proof.len(), 8 + 1 + 8 + 32 + 2 * (32 + 4 + 64));
+        assert_eq!(
+            &proof[..17],
+            &[3, 0, 0, 0, 0, 0, 0, 0, 1, 12, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(&proof[17..49], &test_authorities()[0]);
+        assert_eq!(&proof[49..81], &target_hash);
+        assert_eq!(&proof[81..85], &10u32.to_le_bytes());
+        assert_eq!(&proof[149..181], &child_hash);
+        assert_eq!(&proof[181..185], &11u32.to_le_bytes());
+
+        // Two signers and one equivocator out of four authorities isn't enough.
+        let justification = build_justification(
+            target_hash,
+            10,
+            &[
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash: child_hash,
+                    target_number: 11,
+                    authority_seed: 1,
+                },
+                TestPrecommit {
+                    target_hash,
+                    target_number: 10,
+                    authority_seed: 2,
+                },
+            ],
+            &[&child],
+        );
+        assert!(matches!(
+            verify_with(&justification, 3, &test_authorities()),
+            Err(Error::NotEnoughSignatures {
+                signed_weight: 2,
+                required_weight: 3
+            })
+        ));
+    }
 }
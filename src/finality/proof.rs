@@ -0,0 +1,303 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Finality proofs.
+//!
+//! A finality proof is a list of *fragments*, each containing a justification and the headers
+//! that the receiver of the proof is assumed to not know yet. It proves that a certain block has
+//! been finalized to someone whose latest finalized block is older.
+//!
+//! Because the GrandPa authorities can change over time, a single justification isn't always
+//! enough. A fragment is generated for each block that enacts a change in the list of GrandPa
+//! authorities, followed with a final fragment for the requested block or one of its
+//! descendants. Applying the fragments in order lets the receiver follow the changes of
+//! authorities.
+
+use crate::header;
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use parity_scale_codec::Encode as _;
+
+/// Attempt to decode the given SCALE-encoded finality proof.
+pub fn decode<'a>(scale_encoded: &'a [u8]) -> Result<Vec<FragmentRef<'a>>, Error> {
+    match nom::combinator::all_consuming(fragments)(scale_encoded) {
+        Ok((_, fragments)) => Ok(fragments),
+        Err(nom::Err::Error((_, kind))) | Err(nom::Err::Failure((_, kind))) => Err(Error(kind)),
+        Err(nom::Err::Incomplete(_)) => unreachable!(),
+    }
+}
+
+/// Decoded fragment of a finality proof.
+#[derive(Debug)]
+pub struct FragmentRef<'a> {
+    /// Hash of the block finalized by [`FragmentRef::scale_encoded_justification`].
+    pub block_hash: &'a [u8; 32],
+    /// SCALE-encoded GrandPa justification of the block.
+    pub scale_encoded_justification: &'a [u8],
+    /// Headers the sender of the proof assumed that the receiver doesn't know yet, ordered from
+    /// lowest to highest block number. Includes the header of the block finalized by the
+    /// fragment.
+    pub unknown_headers: Vec<header::HeaderRef<'a>>,
+}
+
+/// Potential error when decoding a finality proof.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Finality proof parsing error: {:?}", _0)]
+pub struct Error(nom::error::ErrorKind);
+
+/// Block passed to [`build`].
+#[derive(Debug)]
+pub struct BuildBlock<'a> {
+    /// SCALE-encoded header of the block.
+    pub scale_encoded_header: &'a [u8],
+    /// SCALE-encoded GrandPa justification of the block, if one is stored.
+    pub scale_encoded_justification: Option<&'a [u8]>,
+    /// True if the block enacts a scheduled change of the list of GrandPa authorities. Its
+    /// descendants are finalized by the new authorities.
+    pub enacts_authorities_change: bool,
+}
+
+/// Builds a finality proof for the given block.
+///
+/// `blocks` must yield the finalized blocks that follow the latest finalized block of the
+/// requester, ordered by increasing block number. The block whose hash is `target_block_hash`
+/// must be one of them.
+///
+/// Returns `Ok(None)` if none of the blocks starting with the target has a justification, or if
+/// the target block isn't found. In that case, no proof can be generated.
+pub fn build<'a>(
+    target_block_hash: &[u8; 32],
+    blocks: impl Iterator<Item = BuildBlock<'a>>,
+) -> Result<Option<Vec<u8>>, BuildError> {
+    let mut fragments = Vec::new();
+    let mut unknown_headers = Vec::new();
+    let mut target_reached = false;
+
+    for block in blocks {
+        let decoded =
+            header::decode(block.scale_encoded_header).map_err(BuildError::InvalidHeader)?;
+        let block_hash = header::hash_from_scale_encoded_header(block.scale_encoded_header);
+        target_reached |= block_hash == *target_block_hash;
+        unknown_headers.push(block.scale_encoded_header);
+
+        if block.enacts_authorities_change || target_reached {
+            let scale_encoded_justification = match block.scale_encoded_justification {
+                Some(j) => j,
+                None if block.enacts_authorities_change => {
+                    return Err(BuildError::MissingAuthoritiesChangeJustification {
+                        block_number: decoded.number,
+                        block_hash,
+                    })
+                }
+                None => continue,
+            };
+
+            fragments.push(encode_fragment(
+                &block_hash,
+                scale_encoded_justification,
+                &unknown_headers,
+            ));
+            unknown_headers.clear();
+
+            if target_reached {
+                let mut out =
+                    parity_scale_codec::Compact(u64::try_from(fragments.len()).unwrap()).encode();
+                for fragment in fragments {
+                    out.extend_from_slice(&fragment);
+                }
+                return Ok(Some(out));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Error that can happen when building a finality proof.
+#[derive(Debug, derive_more::Display)]
+pub enum BuildError {
+    /// Error while decoding one of the headers.
+    InvalidHeader(header::Error),
+    /// A block enacting a change of authorities doesn't have a justification.
+    #[display(
+        fmt = "Block #{} enacts a change of authorities but has no justification",
+        block_number
+    )]
+    MissingAuthoritiesChangeJustification {
+        /// Number of the block without a justification.
+        block_number: u64,
+        /// Hash of the block without a justification.
+        block_hash: [u8; 32],
+    },
+}
+
+/// Returns the SCALE encoding of a single fragment.
+fn encode_fragment(
+    block_hash: &[u8; 32],
+    scale_encoded_justification: &[u8],
+    unknown_headers: &[&[u8]],
+) -> Vec<u8> {
+    let mut out = block_hash.to_vec();
+    scale_encoded_justification.encode_to(&mut out);
+    parity_scale_codec::Compact(u64::try_from(unknown_headers.len()).unwrap()).encode_to(&mut out);
+    for header in unknown_headers {
+        out.extend_from_slice(header);
+    }
+    // The proof of the list of authorities is never included, as the receiver is expected to
+    // track the changes of authorities through the headers.
+    out.push(0);
+    out
+}
+
+/// Nom combinator that parses a list of fragments.
+fn fragments(bytes: &[u8]) -> nom::IResult<&[u8], Vec<FragmentRef>> {
+    nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+        nom::multi::many_m_n(num_elems, num_elems, fragment)
+    })(bytes)
+}
+
+/// Nom combinator that parses a single fragment.
+fn fragment(bytes: &[u8]) -> nom::IResult<&[u8], FragmentRef> {
+    nom::error::context(
+        "fragment",
+        nom::combinator::map(
+            nom::sequence::tuple((
+                nom::bytes::complete::take(32u32),
+                nom::multi::length_data(crate::util::nom_scale_compact_usize),
+                unknown_headers,
+                authorities_proof,
+            )),
+            |(block_hash, scale_encoded_justification, unknown_headers, _)| FragmentRef {
+                block_hash: TryFrom::try_from(block_hash).unwrap(),
+                scale_encoded_justification,
+                unknown_headers,
+            },
+        ),
+    )(bytes)
+}
+
+/// Nom combinator that parses a list of headers.
+fn unknown_headers(bytes: &[u8]) -> nom::IResult<&[u8], Vec<header::HeaderRef>> {
+    nom::error::context(
+        "unknown headers",
+        nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+            nom::multi::many_m_n(num_elems, num_elems, |s| {
+                header::decode_partial(s).map(|(a, b)| (b, a)).map_err(|_| {
+                    nom::Err::Failure(nom::error::make_error(s, nom::error::ErrorKind::Verify))
+                })
+            })
+        }),
+    )(bytes)
+}
+
+/// Nom combinator that parses and discards the optional storage proof of the list of
+/// authorities.
+fn authorities_proof(bytes: &[u8]) -> nom::IResult<&[u8], ()> {
+    nom::error::context(
+        "authorities proof",
+        nom::combinator::map(
+            |s| {
+                crate::util::nom_option_decode(s, |s| {
+                    nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+                        nom::multi::many_m_n(
+                            num_elems,
+                            num_elems,
+                            nom::multi::length_data(crate::util::nom_scale_compact_usize),
+                        )
+                    })(s)
+                })
+            },
+            |_| (),
+        ),
+    )(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::header;
+
+    fn header(number: u64) -> Vec<u8> {
+        header::Header {
+            parent_hash: [number as u8; 32],
+            number,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::empty().into(),
+        }
+        .scale_encoding()
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        })
+    }
+
+    #[test]
+    fn build_then_decode() {
+        let headers = (1..=4).map(header).collect::<Vec<_>>();
+        let target_hash = header::hash_from_scale_encoded_header(&headers[2]);
+        let justifications: [Option<&[u8]>; 4] = [None, Some(&[1, 2]), None, Some(&[3])];
+        let changes = [false, true, false, false];
+
+        let proof = super::build(
+            &target_hash,
+            headers
+                .iter()
+                .zip(justifications.iter())
+                .zip(changes.iter())
+                .map(|((h, j), c)| super::BuildBlock {
+                    scale_encoded_header: h,
+                    scale_encoded_justification: *j,
+                    enacts_authorities_change: *c,
+                }),
+        )
+        .unwrap()
+        .unwrap();
+
+        let fragments = super::decode(&proof).unwrap();
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(
+            *fragments[0].block_hash,
+            header::hash_from_scale_encoded_header(&headers[1])
+        );
+        assert_eq!(fragments[0].scale_encoded_justification, &[1, 2]);
+        assert_eq!(fragments[0].unknown_headers.len(), 2);
+        assert_eq!(
+            *fragments[1].block_hash,
+            header::hash_from_scale_encoded_header(&headers[3])
+        );
+        assert_eq!(fragments[1].scale_encoded_justification, &[3]);
+        assert_eq!(fragments[1].unknown_headers.len(), 2);
+        assert_eq!(fragments[1].unknown_headers[0].number, 3);
+    }
+
+    #[test]
+    fn no_justification() {
+        let headers = (1..=2).map(header).collect::<Vec<_>>();
+        let target_hash = header::hash_from_scale_encoded_header(&headers[0]);
+        let proof = super::build(
+            &target_hash,
+            headers.iter().map(|h| super::BuildBlock {
+                scale_encoded_header: h,
+                scale_encoded_justification: None,
+                enacts_authorities_change: false,
+            }),
+        )
+        .unwrap();
+        assert!(proof.is_none());
+    }
+}
@@ -73,9 +73,11 @@
 
 use core::{convert::TryFrom, fmt, iter, slice};
 
+mod aura;
 mod babe;
 mod grandpa;
 
+pub use aura::*;
 pub use babe::*;
 pub use grandpa::*;
 
@@ -176,6 +178,11 @@ pub enum Error {
     SealIsntLastItem,
     /// Bad length of a BABE seal.
     BadBabeSealLength,
+    /// Bad length of an Aura seal.
+    BadAuraSealLength,
+    BadAuraConsensusRefType,
+    /// There are multiple Aura pre-runtime digests in the block header.
+    MultipleAuraPreRuntimeDigests,
     BadBabePreDigestRefType,
     BadBabeConsensusRefType,
     /// There are multiple Babe pre-runtime digests in the block header.
@@ -313,6 +320,10 @@ pub struct DigestRef<'a> {
     /// Index of the [`DigestItemRef::BabeConsensus`] item containing a
     /// [`BabeConsensusLogRef::NextConfigData`], if any.
     babe_next_config_data_index: Option<usize>,
+    /// Index of the [`DigestItemRef::AuraSeal`] item, if any.
+    aura_seal_index: Option<usize>,
+    /// Index of the [`DigestItemRef::AuraPreDigest`] item, if any.
+    aura_predigest_index: Option<usize>,
 }
 
 #[derive(Clone)]
@@ -339,6 +350,8 @@ impl<'a> DigestRef<'a> {
             babe_predigest_index: None,
             babe_next_epoch_data_index: None,
             babe_next_config_data_index: None,
+            aura_seal_index: None,
+            aura_predigest_index: None,
         }
     }
 
@@ -400,10 +413,37 @@ impl<'a> DigestRef<'a> {
         }
     }
 
-    /// If the last element of the list is a Babe seal, removes it from the [`DigestRef`].
-    // TODO: have a `Seal` enum or something, maybe?
-    pub fn pop_babe_seal(&mut self) -> Option<&'a [u8; 64]> {
-        let seal_pos = self.babe_seal_index?;
+    /// Returns the Aura seal digest item, if any.
+    pub fn aura_seal(&self) -> Option<&'a [u8; 64]> {
+        if let Some(aura_seal_index) = self.aura_seal_index {
+            if let DigestItemRef::AuraSeal(seal) = self.logs().nth(aura_seal_index).unwrap() {
+                Some(seal)
+            } else {
+                unreachable!()
+            }
+        } else {
+            None
+        }
+    }
+
+    /// Returns the Aura pre-runtime digest item, if any.
+    pub fn aura_pre_runtime(&self) -> Option<AuraPreDigest> {
+        if let Some(aura_predigest_index) = self.aura_predigest_index {
+            if let DigestItemRef::AuraPreDigest(item) =
+                self.logs().nth(aura_predigest_index).unwrap()
+            {
+                Some(item)
+            } else {
+                unreachable!()
+            }
+        } else {
+            None
+        }
+    }
+
+    /// If the last element of the list is a seal, removes it from the [`DigestRef`].
+    pub fn pop_seal(&mut self) -> Option<Seal<'a>> {
+        let seal_pos = self.babe_seal_index.or(self.aura_seal_index)?;
 
         match &mut self.inner {
             DigestRefInner::Parsed(list) => {
@@ -412,9 +452,12 @@ impl<'a> DigestRef<'a> {
 
                 let item = &list[seal_pos];
                 *list = &list[..seal_pos];
+                self.babe_seal_index = None;
+                self.aura_seal_index = None;
 
                 match item {
-                    DigestItem::BabeSeal(seal) => Some(seal),
+                    DigestItem::BabeSeal(seal) => Some(Seal::Babe(seal)),
+                    DigestItem::AuraSeal(seal) => Some(Seal::Aura(seal)),
                     _ => unreachable!(),
                 }
             }
@@ -444,13 +487,15 @@ impl<'a> DigestRef<'a> {
                     *digest_logs_len -= 1;
                     *digest = &digest[..digest.len() - pointer.len()];
                     self.babe_seal_index = None;
+                    self.aura_seal_index = None;
                     debug_assert_eq!(remaining_len, 1);
                 } else {
                     unreachable!()
                 }
 
                 match iter.next() {
-                    Some(DigestItemRef::BabeSeal(seal)) => Some(seal),
+                    Some(DigestItemRef::BabeSeal(seal)) => Some(Seal::Babe(seal)),
+                    Some(DigestItemRef::AuraSeal(seal)) => Some(Seal::Aura(seal)),
                     _ => unreachable!(),
                 }
             }
@@ -503,6 +548,8 @@ impl<'a> DigestRef<'a> {
         let mut babe_predigest_index = None;
         let mut babe_next_epoch_data_index = None;
         let mut babe_next_config_data_index = None;
+        let mut aura_seal_index = None;
+        let mut aura_predigest_index = None;
 
         // Iterate through the log items to see if anything is wrong.
         let mut next_digest = scale_encoded;
@@ -541,6 +588,18 @@ impl<'a> DigestRef<'a> {
                     babe_seal_index = Some(item_num);
                 }
                 DigestItemRef::BabeSeal(_) => return Err(Error::SealIsntLastItem),
+                DigestItemRef::AuraPreDigest(_) if aura_predigest_index.is_none() => {
+                    aura_predigest_index = Some(item_num);
+                }
+                DigestItemRef::AuraPreDigest(_) => {
+                    return Err(Error::MultipleAuraPreRuntimeDigests)
+                }
+                DigestItemRef::AuraConsensus(_) => {}
+                DigestItemRef::AuraSeal(_) if item_num == digest_logs_len - 1 => {
+                    debug_assert!(aura_seal_index.is_none());
+                    aura_seal_index = Some(item_num);
+                }
+                DigestItemRef::AuraSeal(_) => return Err(Error::SealIsntLastItem),
                 DigestItemRef::ChangesTrieSignal(_) => {}
             }
         }
@@ -558,6 +617,8 @@ impl<'a> DigestRef<'a> {
             babe_predigest_index,
             babe_next_epoch_data_index,
             babe_next_config_data_index,
+            aura_seal_index,
+            aura_predigest_index,
         };
 
         Ok((out, next_digest))
@@ -578,10 +639,21 @@ impl<'a> From<&'a Digest> for DigestRef<'a> {
             babe_predigest_index: digest.babe_predigest_index,
             babe_next_epoch_data_index: digest.babe_next_epoch_data_index,
             babe_next_config_data_index: digest.babe_next_config_data_index,
+            aura_seal_index: digest.aura_seal_index,
+            aura_predigest_index: digest.aura_predigest_index,
         }
     }
 }
 
+/// Seal found at the end of a digest, containing the signature of the block author.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Seal<'a> {
+    /// Block signature made using the Aura consensus engine.
+    Aura(&'a [u8; 64]),
+    /// Block signature made using the BABE consensus engine.
+    Babe(&'a [u8; 64]),
+}
+
 /// Generic header digest.
 #[derive(Clone)]
 pub struct Digest {
@@ -597,6 +669,10 @@ pub struct Digest {
     /// Index of the [`DigestItemRef::BabeConsensus`] item containing a
     /// [`BabeConsensusLogRef::NextConfigData`], if any.
     babe_next_config_data_index: Option<usize>,
+    /// Index of the [`DigestItemRef::AuraSeal`] item, if any.
+    aura_seal_index: Option<usize>,
+    /// Index of the [`DigestItemRef::AuraPreDigest`] item, if any.
+    aura_predigest_index: Option<usize>,
 }
 
 impl Digest {
@@ -622,6 +698,16 @@ impl Digest {
     pub fn babe_epoch_information(&self) -> Option<(BabeNextEpochRef, Option<BabeNextConfig>)> {
         DigestRef::from(self).babe_epoch_information()
     }
+
+    /// Returns the Aura seal digest item, if any.
+    pub fn aura_seal(&self) -> Option<&[u8; 64]> {
+        DigestRef::from(self).aura_seal()
+    }
+
+    /// Returns the Aura pre-runtime digest item, if any.
+    pub fn aura_pre_runtime(&self) -> Option<AuraPreDigest> {
+        DigestRef::from(self).aura_pre_runtime()
+    }
 }
 
 impl fmt::Debug for Digest {
@@ -640,6 +726,8 @@ impl<'a> From<DigestRef<'a>> for Digest {
             babe_predigest_index: digest.babe_predigest_index,
             babe_next_epoch_data_index: digest.babe_next_epoch_data_index,
             babe_next_config_data_index: digest.babe_next_config_data_index,
+            aura_seal_index: digest.aura_seal_index,
+            aura_predigest_index: digest.aura_predigest_index,
         }
     }
 }
@@ -700,6 +788,11 @@ impl<'a> ExactSizeIterator for LogsIter<'a> {}
 // TODO: document
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum DigestItemRef<'a> {
+    AuraPreDigest(AuraPreDigest),
+    AuraConsensus(AuraConsensusLogRef<'a>),
+    /// Block signature made using the Aura consensus engine.
+    AuraSeal(&'a [u8; 64]),
+
     BabePreDigest(BabePreDigestRef<'a>),
     BabeConsensus(BabeConsensusLogRef<'a>),
     /// Block signature made using the BABE consensus engine.
@@ -719,6 +812,49 @@ impl<'a> DigestItemRef<'a> {
     ) -> impl Iterator<Item = impl AsRef<[u8]> + Clone + 'a> + Clone + 'a {
         // TODO: don't use Vecs?
         match *self {
+            DigestItemRef::AuraPreDigest(ref aura_pre_digest) => {
+                let encoded = aura_pre_digest
+                    .scale_encoding()
+                    .fold(Vec::new(), |mut a, b| {
+                        a.extend_from_slice(b.as_ref());
+                        a
+                    });
+
+                let mut ret = vec![6];
+                ret.extend_from_slice(b"aura");
+                ret.extend_from_slice(&parity_scale_codec::Encode::encode(
+                    &parity_scale_codec::Compact(u64::try_from(encoded.len()).unwrap()),
+                ));
+                ret.extend_from_slice(&encoded);
+                iter::once(ret)
+            }
+            DigestItemRef::AuraConsensus(ref aura_consensus) => {
+                let encoded = aura_consensus
+                    .scale_encoding()
+                    .fold(Vec::new(), |mut a, b| {
+                        a.extend_from_slice(b.as_ref());
+                        a
+                    });
+
+                let mut ret = vec![4];
+                ret.extend_from_slice(b"aura");
+                ret.extend_from_slice(&parity_scale_codec::Encode::encode(
+                    &parity_scale_codec::Compact(u64::try_from(encoded.len()).unwrap()),
+                ));
+                ret.extend_from_slice(&encoded);
+                iter::once(ret)
+            }
+            DigestItemRef::AuraSeal(seal) => {
+                assert_eq!(seal.len(), 64);
+
+                let mut ret = vec![5];
+                ret.extend_from_slice(b"aura");
+                ret.extend_from_slice(&parity_scale_codec::Encode::encode(
+                    &parity_scale_codec::Compact(64u32),
+                ));
+                ret.extend_from_slice(seal);
+                iter::once(ret)
+            }
             DigestItemRef::BabePreDigest(ref babe_pre_digest) => {
                 let encoded = babe_pre_digest
                     .scale_encoding()
@@ -793,6 +929,9 @@ impl<'a> DigestItemRef<'a> {
 impl<'a> From<&'a DigestItem> for DigestItemRef<'a> {
     fn from(a: &'a DigestItem) -> DigestItemRef<'a> {
         match a {
+            DigestItem::AuraPreDigest(v) => DigestItemRef::AuraPreDigest(*v),
+            DigestItem::AuraConsensus(v) => DigestItemRef::AuraConsensus(v.into()),
+            DigestItem::AuraSeal(v) => DigestItemRef::AuraSeal(v),
             DigestItem::BabePreDigest(v) => DigestItemRef::BabePreDigest(v.into()),
             DigestItem::BabeConsensus(v) => DigestItemRef::BabeConsensus(v.into()),
             DigestItem::BabeSeal(v) => DigestItemRef::BabeSeal(v),
@@ -806,6 +945,11 @@ impl<'a> From<&'a DigestItem> for DigestItemRef<'a> {
 // TODO: document
 #[derive(Debug, Clone)]
 pub enum DigestItem {
+    AuraPreDigest(AuraPreDigest),
+    AuraConsensus(AuraConsensusLog),
+    /// Block signature made using the Aura consensus engine.
+    AuraSeal([u8; 64]),
+
     BabePreDigest(BabePreDigest),
     BabeConsensus(BabeConsensusLog),
     /// Block signature made using the BABE consensus engine.
@@ -820,6 +964,13 @@ pub enum DigestItem {
 impl<'a> From<DigestItemRef<'a>> for DigestItem {
     fn from(a: DigestItemRef<'a>) -> DigestItem {
         match a {
+            DigestItemRef::AuraPreDigest(v) => DigestItem::AuraPreDigest(v),
+            DigestItemRef::AuraConsensus(v) => DigestItem::AuraConsensus(v.into()),
+            DigestItemRef::AuraSeal(v) => {
+                let mut seal = [0; 64];
+                seal.copy_from_slice(v);
+                DigestItem::AuraSeal(seal)
+            }
             DigestItemRef::BabePreDigest(v) => DigestItem::BabePreDigest(v.into()),
             DigestItemRef::BabeConsensus(v) => DigestItem::BabeConsensus(v.into()),
             DigestItemRef::BabeSeal(v) => {
@@ -928,15 +1079,20 @@ fn decode_item_from_parts<'a>(
     content: &'a [u8],
 ) -> Result<DigestItemRef<'a>, Error> {
     Ok(match (index, engine_id) {
+        (4, b"aura") => DigestItemRef::AuraConsensus(AuraConsensusLogRef::from_slice(content)?),
         (4, b"BABE") => DigestItemRef::BabeConsensus(BabeConsensusLogRef::from_slice(content)?),
         (4, b"FRNK") => {
             DigestItemRef::GrandpaConsensus(GrandpaConsensusLogRef::from_slice(content)?)
         }
         (4, e) => return Err(Error::UnknownConsensusEngine(*e)),
+        (5, b"aura") => DigestItemRef::AuraSeal({
+            TryFrom::try_from(content).map_err(|_| Error::BadAuraSealLength)?
+        }),
         (5, b"BABE") => DigestItemRef::BabeSeal({
             TryFrom::try_from(content).map_err(|_| Error::BadBabeSealLength)?
         }),
         (5, e) => return Err(Error::UnknownConsensusEngine(*e)),
+        (6, b"aura") => DigestItemRef::AuraPreDigest(AuraPreDigest::from_slice(content)?),
         (6, b"BABE") => DigestItemRef::BabePreDigest(BabePreDigestRef::from_slice(content)?),
         (6, e) => return Err(Error::UnknownConsensusEngine(*e)),
         _ => unreachable!(),
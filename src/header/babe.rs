@@ -391,6 +391,15 @@ impl<'a> BabePreDigestRef<'a> {
         }
     }
 
+    /// Returns the index of the authority that has produced the block.
+    pub fn authority_index(&self) -> u32 {
+        match self {
+            BabePreDigestRef::Primary(digest) => digest.authority_index,
+            BabePreDigestRef::SecondaryPlain(digest) => digest.authority_index,
+            BabePreDigestRef::SecondaryVRF(digest) => digest.authority_index,
+        }
+    }
+
     /// Returns an iterator to list of buffers which, when concatenated, produces the SCALE
     /// encoding of that object.
     pub fn scale_encoding(
@@ -40,6 +40,8 @@
 //!     best_hash: &[0x12, 0x34, 0x56, 0x76],
 //!     finalized_hash: &[0xaa, 0xbb, 0xcc, 0xdd],
 //!     network_known_best: Some(224),
+//!     network_download_per_sec: 48_000,
+//!     network_upload_per_sec: 3_400,
 //! });
 //! ```
 
@@ -70,6 +72,10 @@ pub struct InformantLine<'a> {
     pub finalized_number: u64,
     /// Hash of the latest finalized block we have locally.
     pub finalized_hash: &'a [u8],
+    /// Average number of bytes per second received from the peer-to-peer network.
+    pub network_download_per_sec: u64,
+    /// Average number of bytes per second sent to the peer-to-peer network.
+    pub network_upload_per_sec: u64,
 }
 
 impl<'a> fmt::Display for InformantLine<'a> {
@@ -93,18 +99,25 @@ impl<'a> fmt::Display for InformantLine<'a> {
 
         let header_len = self.chain_name.chars().count() + 17; // TODO: ? it's easier to do that than deal with unicode
 
+        let download = format!("{}/s", BytesDisplay(self.network_download_per_sec));
+        let upload = format!("{}/s", BytesDisplay(self.network_upload_per_sec));
+
         // TODO: it's a bit of a clusterfuck to properly align because the emoji eats a whitespace
         let trailer = format!(
-            "] {white_bold}#{network_best}{reset} (🌐{white_bold}{connec:>4}{reset})   ",
+            "] {white_bold}#{network_best}{reset} (🌐{white_bold}{connec:>4}{reset}) ⬇{download:>11} ⬆{upload:>11}   ",
             network_best = self.network_known_best.unwrap_or(0),
             connec = self.num_network_connections,
+            download = download,
+            upload = upload,
             white_bold = white_bold,
             reset = reset,
         );
         let trailer_len = format!(
-            "] #{network_best} (  {connec:>4})   ",
+            "] #{network_best} (  {connec:>4}) v{download:>11} ^{upload:>11}   ",
             network_best = self.network_known_best.unwrap_or(0),
             connec = self.num_network_connections,
+            download = download,
+            upload = upload,
         )
         .len();
 
@@ -157,6 +170,26 @@ impl<'a> fmt::Display for InformantLine<'a> {
     }
 }
 
+/// Implements `fmt::Display` and displays a number of bytes in a human-readable way.
+struct BytesDisplay(u64);
+
+impl fmt::Display for BytesDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+
+        let units = ["kiB", "MiB", "GiB"];
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < units.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", value, units[unit])
+    }
+}
+
 /// Implements `fmt::Display` and displays hashes in a nice way.
 struct HashDisplay<'a>(&'a [u8]);
 
@@ -195,7 +195,7 @@ define_methods! {
     state_unsubscribeRuntimeVersion() -> bool [chain_unsubscribeRuntimeVersion],
     state_unsubscribeStorage(subscription: String) -> bool,
     system_accountNextIndex() -> (), // TODO:
-    system_addReservedPeer() -> (), // TODO:
+    system_addReservedPeer(peer: String) -> (),
     system_chain() -> &'a str,
     system_chainType() -> &'a str,
     system_dryRun() -> () [system_dryRunAt], // TODO:
@@ -203,11 +203,11 @@ define_methods! {
     system_localListenAddresses() -> Vec<String>,
     system_localPeerId() -> &'a str,
     system_name() -> &'a str,
-    system_networkState() -> (), // TODO:
+    system_networkState() -> crate::network::NetworkState,
     system_nodeRoles() -> (), // TODO:
     system_peers() -> Vec<SystemPeer>,
     system_properties() -> Box<serde_json::value::RawValue>,
-    system_removeReservedPeer() -> (), // TODO:
+    system_removeReservedPeer(peer_id: String) -> (),
     system_version() -> &'a str,
 }
 
@@ -97,7 +97,10 @@ pub enum Error {
 
 /// Removes the length prefix at the beginning of `metadata`. Returns an error if there is no
 /// valid length prefix.
-fn remove_length_prefix(metadata: &[u8]) -> Result<&[u8], Error> {
+///
+/// This function is useful when the `Metadata_metadata` entry point has been called by other
+/// means than the functions of this module, for example by a remote.
+pub fn remove_length_prefix(metadata: &[u8]) -> Result<&[u8], Error> {
     let (after_prefix, length) = crate::util::nom_scale_compact_usize(metadata)
         .map_err(|_: nom::Err<(&[u8], nom::error::ErrorKind)>| Error::BadLengthPrefix)?;
 
@@ -51,27 +51,39 @@
 
 use core::fmt;
 
+pub use address_book::AddressBookEntry;
 pub use libp2p::{Multiaddr, PeerId};
+pub use network_state::NetworkState;
+pub use traffic::{PeerRequestsStats, ProtocolTraffic};
 pub use worker::{
     BlockData, BlocksRequestConfig, BlocksRequestConfigStart, BlocksRequestDirection,
-    BlocksRequestFields, Config, Event, Network, RequestId, ScaleBlockHeader,
+    BlocksRequestError, BlocksRequestFields, BlocksRequestResponder, CallRequestConfig, Config,
+    Event, Extrinsic, FinalityProofRequestConfig, FinalityProofResponder, LightRequestError,
+    Network, RequestId, Role, ScaleBlockHeader, StartError, StorageProofRequestConfig,
 };
 
 #[doc(inline)]
 pub use libp2p::multiaddr;
 pub use libp2p::wasm_ext;
 
+mod address_book;
 mod behaviour;
 mod debug_info;
 mod discovery;
 mod generic_proto;
 mod legacy_message;
+mod peerset;
 mod request_responses;
 mod schema;
+mod traffic;
+mod transactions;
 mod transport;
 mod worker;
 
+pub mod authority_discovery;
+pub mod network_state;
 pub mod task;
+mod tests;
 
 /// Parses a string address and splits it into Multiaddress and PeerId, if
 /// valid.
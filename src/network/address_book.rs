@@ -0,0 +1,374 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bounded list of the peers of the network and their addresses, meant to be saved when the
+//! node stops and loaded back when it restarts.
+//!
+//! Without such a list, a node that starts can only connect to the bootnodes of the chain
+//! specification, which are frequently overloaded. The address book remembers the peers that
+//! have been discovered, when they have last been seen, and how many times connecting to them
+//! has succeeded or failed.
+//!
+//! The address book doesn't perform any I/O. See the
+//! [`database::network_address_book`](crate::database::network_address_book) module in order to
+//! store it.
+
+use alloc::vec::Vec;
+use core::{cmp, time::Duration};
+use hashbrown::HashMap;
+use libp2p::{Multiaddr, PeerId};
+
+/// Maximum number of peers in the address book. When the limit is reached, the peers that are
+/// the least likely to be reachable are removed.
+const MAX_ENTRIES: usize = 1024;
+
+/// Maximum number of addresses stored for each peer. The most recently seen or successful
+/// addresses are kept.
+const MAX_ADDRESSES_PER_PEER: usize = 8;
+
+/// Peers that haven't been seen for longer than this are removed from the address book.
+const STALE_AFTER: Duration = Duration::from_secs(14 * 24 * 3600);
+
+/// Peers that we have never managed to connect to are removed from the address book after this
+/// number of failed attempts.
+const MAX_FAILURES_WITHOUT_SUCCESS: u32 = 5;
+
+/// Entry of the address book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressBookEntry {
+    /// Identity of the peer.
+    pub peer_id: PeerId,
+    /// Known addresses of the peer, ordered from the most recently seen or successful to the
+    /// least recently seen.
+    pub addresses: Vec<Multiaddr>,
+    /// Time, as a duration since the UNIX epoch, when we have last discovered or connected to
+    /// this peer.
+    pub last_seen: Duration,
+    /// Number of times we have successfully connected to this peer.
+    pub successes: u32,
+    /// Number of times connecting to this peer has failed.
+    pub failures: u32,
+}
+
+impl AddressBookEntry {
+    /// Returns a value that is larger for the peers that should be tried first.
+    ///
+    /// Peers to which connecting more often succeeded than failed are preferred, then the most
+    /// recently seen peers.
+    fn preference(&self) -> impl Ord {
+        (self.successes > self.failures, self.last_seen)
+    }
+
+    /// Returns true if the peer should be removed from the address book.
+    fn is_stale(&self, now_from_unix_epoch: Duration) -> bool {
+        self.addresses.is_empty()
+            || now_from_unix_epoch.saturating_sub(self.last_seen) > STALE_AFTER
+            || (self.successes == 0 && self.failures >= MAX_FAILURES_WITHOUT_SUCCESS)
+    }
+}
+
+/// See the [module-level documentation](self).
+#[derive(Debug, Default)]
+pub struct AddressBook {
+    entries: HashMap<PeerId, AddressBookEntry, fnv::FnvBuildHasher>,
+}
+
+impl AddressBook {
+    /// Creates a new empty address book.
+    pub fn new() -> Self {
+        AddressBook::default()
+    }
+
+    /// Creates an address book from entries that have been previously returned by
+    /// [`AddressBook::export`].
+    ///
+    /// Stale entries are discarded. If there are too many entries, only the preferred ones are
+    /// kept.
+    pub fn import(
+        entries: impl IntoIterator<Item = AddressBookEntry>,
+        now_from_unix_epoch: Duration,
+    ) -> Self {
+        let mut book = AddressBook::new();
+
+        for mut entry in entries {
+            entry.addresses.truncate(MAX_ADDRESSES_PER_PEER);
+            match book.entries.get_mut(&entry.peer_id) {
+                // The same peer shouldn't appear twice, but keeping the preferred entry is cheap.
+                Some(existing) if existing.preference() >= entry.preference() => {}
+                Some(existing) => *existing = entry,
+                None => {
+                    book.entries.insert(entry.peer_id.clone(), entry);
+                }
+            }
+        }
+
+        book.evict(now_from_unix_epoch);
+        book
+    }
+
+    /// Returns the entries of the address book, from the preferred to the least preferred.
+    ///
+    /// Stale entries are removed beforehand.
+    pub fn export(&mut self, now_from_unix_epoch: Duration) -> Vec<AddressBookEntry> {
+        self.evict(now_from_unix_epoch);
+        self.iter_preferred().cloned().collect()
+    }
+
+    /// Returns the entries of the address book, from the preferred to the least preferred.
+    pub fn iter_preferred(&self) -> impl Iterator<Item = &AddressBookEntry> {
+        let mut entries = self.entries.values().collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.preference().cmp(&a.preference()));
+        entries.into_iter()
+    }
+
+    /// Returns the known addresses of the given peer, from the most recently seen or successful
+    /// to the least recently seen. Empty if the peer isn't in the address book.
+    pub fn addresses_of_peer(&self, peer_id: &PeerId) -> &[Multiaddr] {
+        self.entries
+            .get(peer_id)
+            .map(|entry| &entry.addresses[..])
+            .unwrap_or(&[])
+    }
+
+    /// Returns the number of peers in the address book.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the address book is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Inserts an address of a peer that has been discovered, or marks it as seen if it was
+    /// already known.
+    pub fn insert_address(
+        &mut self,
+        peer_id: &PeerId,
+        address: Multiaddr,
+        now_from_unix_epoch: Duration,
+    ) {
+        let entry = self.entry_mut(peer_id, now_from_unix_epoch);
+        entry.last_seen = cmp::max(entry.last_seen, now_from_unix_epoch);
+        if !entry.addresses.contains(&address) {
+            entry.addresses.truncate(MAX_ADDRESSES_PER_PEER - 1);
+            entry.addresses.insert(0, address);
+        }
+
+        self.evict_if_full(now_from_unix_epoch);
+    }
+
+    /// Reports that a connection to a peer has been established.
+    ///
+    /// If `address` is `Some`, we have dialed the peer at this address, and it is moved to the
+    /// front of the list of addresses of the peer. If `None`, the peer has connected to us and
+    /// we don't learn anything about its addresses, in which case nothing happens unless the
+    /// peer is already in the address book.
+    pub fn report_success(
+        &mut self,
+        peer_id: &PeerId,
+        address: Option<&Multiaddr>,
+        now_from_unix_epoch: Duration,
+    ) {
+        let entry = match address {
+            Some(address) => {
+                let entry = self.entry_mut(peer_id, now_from_unix_epoch);
+                entry.addresses.retain(|a| a != address);
+                entry.addresses.truncate(MAX_ADDRESSES_PER_PEER - 1);
+                entry.addresses.insert(0, address.clone());
+                entry
+            }
+            None => match self.entries.get_mut(peer_id) {
+                Some(entry) => entry,
+                None => return,
+            },
+        };
+
+        entry.last_seen = cmp::max(entry.last_seen, now_from_unix_epoch);
+        entry.successes = entry.successes.saturating_add(1);
+
+        self.evict_if_full(now_from_unix_epoch);
+    }
+
+    /// Reports that dialing the given peer has failed on all of its addresses. Has no effect if
+    /// the peer isn't in the address book.
+    pub fn report_failure(&mut self, peer_id: &PeerId) {
+        if let Some(entry) = self.entries.get_mut(peer_id) {
+            entry.failures = entry.failures.saturating_add(1);
+        }
+    }
+
+    /// Reports that the given address of a peer couldn't be reached. The address is moved to the
+    /// back of the list of addresses of the peer.
+    ///
+    /// This doesn't count as a failure, as the peer might be reachable through another address.
+    /// See [`AddressBook::report_failure`].
+    pub fn report_unreachable_address(&mut self, peer_id: &PeerId, address: &Multiaddr) {
+        let entry = match self.entries.get_mut(peer_id) {
+            Some(e) => e,
+            None => return,
+        };
+
+        if let Some(pos) = entry.addresses.iter().position(|a| a == address) {
+            let address = entry.addresses.remove(pos);
+            entry.addresses.push(address);
+        }
+    }
+
+    /// Returns the entry of the given peer, inserting an empty one if necessary.
+    fn entry_mut(
+        &mut self,
+        peer_id: &PeerId,
+        now_from_unix_epoch: Duration,
+    ) -> &mut AddressBookEntry {
+        self.entries
+            .entry(peer_id.clone())
+            .or_insert_with(|| AddressBookEntry {
+                peer_id: peer_id.clone(),
+                addresses: Vec::new(),
+                last_seen: now_from_unix_epoch,
+                successes: 0,
+                failures: 0,
+            })
+    }
+
+    /// Calls [`AddressBook::evict`] if the address book contains too many entries.
+    fn evict_if_full(&mut self, now_from_unix_epoch: Duration) {
+        if self.entries.len() > MAX_ENTRIES {
+            self.evict(now_from_unix_epoch);
+        }
+    }
+
+    /// Removes the stale entries, then the least preferred entries until there are at most
+    /// `MAX_ENTRIES` of them.
+    fn evict(&mut self, now_from_unix_epoch: Duration) {
+        self.entries
+            .retain(|_, entry| !entry.is_stale(now_from_unix_epoch));
+
+        if self.entries.len() <= MAX_ENTRIES {
+            return;
+        }
+
+        let mut by_preference = self
+            .entries
+            .values()
+            .map(|entry| (entry.preference(), entry.peer_id.clone()))
+            .collect::<Vec<_>>();
+        by_preference.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let num_to_remove = self.entries.len() - MAX_ENTRIES;
+        for (_, peer_id) in by_preference.into_iter().take(num_to_remove) {
+            self.entries.remove(&peer_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddressBook, MAX_ENTRIES};
+    use core::time::Duration;
+    use libp2p::{Multiaddr, PeerId};
+
+    fn address(n: u16) -> Multiaddr {
+        format!("/ip4/1.2.3.4/tcp/{}", n).parse().unwrap()
+    }
+
+    #[test]
+    fn preferred_first() {
+        let now = Duration::from_secs(1_000_000);
+        let mut book = AddressBook::new();
+
+        let never_reached = PeerId::random();
+        let recently_seen = PeerId::random();
+        let successful = PeerId::random();
+
+        book.insert_address(&never_reached, address(1), now);
+        book.insert_address(&successful, address(2), now - Duration::from_secs(3600));
+        book.report_success(
+            &successful,
+            Some(&address(2)),
+            now - Duration::from_secs(3600),
+        );
+        book.insert_address(&recently_seen, address(3), now + Duration::from_secs(10));
+
+        let exported = book.export(now);
+        assert_eq!(
+            exported.iter().map(|e| &e.peer_id).collect::<Vec<_>>(),
+            vec![&successful, &recently_seen, &never_reached]
+        );
+        assert_eq!(exported[0].successes, 1);
+    }
+
+    #[test]
+    fn stale_entries_evicted() {
+        let now = Duration::from_secs(100 * 24 * 3600);
+        let mut book = AddressBook::new();
+
+        let old = PeerId::random();
+        let unreachable = PeerId::random();
+        let fresh = PeerId::random();
+
+        book.insert_address(&old, address(1), now - Duration::from_secs(30 * 24 * 3600));
+        book.insert_address(&unreachable, address(2), now);
+        for _ in 0..5 {
+            book.report_unreachable_address(&unreachable, &address(2));
+            book.report_failure(&unreachable);
+        }
+        book.insert_address(&fresh, address(3), now);
+
+        let exported = book.export(now);
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].peer_id, fresh);
+
+        // Importing applies the same rules.
+        let reimported = AddressBook::import(exported, now + Duration::from_secs(15 * 24 * 3600));
+        assert!(reimported.is_empty());
+    }
+
+    #[test]
+    fn bounded() {
+        let now = Duration::from_secs(1_000_000);
+        let mut book = AddressBook::new();
+
+        let successful = PeerId::random();
+        book.report_success(&successful, Some(&address(1)), now);
+        for _ in 0..MAX_ENTRIES + 10 {
+            book.insert_address(&PeerId::random(), address(2), now);
+        }
+
+        assert_eq!(book.len(), MAX_ENTRIES);
+        assert_eq!(book.export(now)[0].peer_id, successful);
+    }
+
+    #[test]
+    fn successful_address_moved_to_front() {
+        let now = Duration::from_secs(1_000_000);
+        let mut book = AddressBook::new();
+        let peer_id = PeerId::random();
+
+        for n in 0..10 {
+            book.insert_address(&peer_id, address(n), now);
+        }
+        book.report_success(&peer_id, Some(&address(0)), now);
+        book.report_unreachable_address(&peer_id, &address(9));
+
+        let entry = book.export(now).remove(0);
+        assert_eq!(entry.addresses.len(), 8);
+        assert_eq!(entry.addresses[0], address(0));
+        assert_eq!(*entry.addresses.last().unwrap(), address(9));
+    }
+}
@@ -0,0 +1,234 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Finding the network addresses of the authorities of the chain.
+//!
+//! Each authority possesses an sr25519 "authority discovery" key. The list of the public keys of
+//! the current authorities can be obtained by calling the `AuthorityDiscoveryApi_authorities`
+//! entry point of the runtime, see [`authorities_from_virtual_machine_prototype`].
+//!
+//! Each authority periodically publishes in the Kademlia DHT a record containing the addresses
+//! of its node, signed with its authority discovery key. The key of this record in the DHT is
+//! derived from the public key of the authority, see [`dht_key`].
+//!
+//! # Usage
+//!
+//! Call [`Network::set_authorities`](super::Network::set_authorities) with the list of the
+//! current authorities. The network looks up the record of each of them, and generates an
+//! [`Event::AuthorityAddresses`](super::Event::AuthorityAddresses) for each record whose
+//! signature is valid. If the
+//! [`Config::authority_discovery_key`](super::Config::authority_discovery_key) is one of the
+//! authorities, the addresses of the local node are published as well.
+//!
+//! The list of authorities changes over time, and records expire from the DHT. Calling
+//! [`Network::set_authorities`](super::Network::set_authorities) again, for example at each new
+//! session or every few minutes, refreshes the lookups and re-publishes our record.
+//!
+//! The functions of this module that encode and decode records are exposed for the tools that
+//! want to interact with the DHT by other means.
+
+use super::{schema, Multiaddr, PeerId};
+use crate::executor;
+
+use alloc::vec::Vec;
+use core::convert::TryFrom as _;
+use libp2p::multiaddr::Protocol;
+use parity_scale_codec::DecodeAll as _;
+use prost::Message as _;
+use sha2::Digest as _;
+
+/// Signing context used by sr25519 signatures in Substrate.
+const SIGNING_CONTEXT: &[u8] = b"substrate";
+
+/// Retrieves the list of the public keys of the authorities from the runtime of a block.
+///
+/// Must be passed a closure that returns the storage value corresponding to the given key in
+/// the storage of the block.
+///
+/// Returns back the same virtual machine prototype as was passed as parameter.
+pub fn authorities_from_virtual_machine_prototype(
+    vm: executor::WasmVmPrototype,
+    mut storage_access: impl FnMut(&[u8]) -> Option<Vec<u8>>,
+) -> Result<(Vec<[u8; 32]>, executor::WasmVmPrototype), Error> {
+    let mut vm: executor::WasmVm = vm
+        .run_no_param("AuthorityDiscoveryApi_authorities")
+        .map_err(Error::VmInitialization)?
+        .into();
+
+    loop {
+        match vm {
+            executor::WasmVm::ReadyToRun(r) => vm = r.run(),
+            executor::WasmVm::Finished(finished) => {
+                let authorities =
+                    <Vec<[u8; 32]>>::decode_all(finished.value()).map_err(Error::OutputDecode)?;
+                return Ok((authorities, finished.into_prototype()));
+            }
+            executor::WasmVm::Error { .. } => return Err(Error::Trapped),
+
+            executor::WasmVm::ExternalStorageGet(rq) => {
+                let value = storage_access(rq.key());
+                vm = rq.resume_full_value(value.as_ref().map(|v| &v[..]));
+            }
+
+            executor::WasmVm::LogEmit(rq) => vm = rq.resume(),
+
+            _ => return Err(Error::ExternalityNotAllowed),
+        }
+    }
+}
+
+/// Error when retrieving the list of authorities.
+#[derive(Debug, derive_more::Display)]
+pub enum Error {
+    /// Error when initializing the virtual machine.
+    VmInitialization(executor::NewErr),
+    /// Crash while running the virtual machine.
+    Trapped,
+    /// Virtual machine tried to call an externality that isn't valid in this context.
+    ExternalityNotAllowed,
+    /// Error while decoding the SCALE-encoded list.
+    OutputDecode(parity_scale_codec::Error),
+}
+
+/// Returns the key in the DHT of the record of the authority with the given public key.
+///
+/// The key is the SHA2-256 multihash of the public key.
+pub fn dht_key(authority_public_key: &[u8; 32]) -> Vec<u8> {
+    // Multihash code of SHA2-256, followed with the length of the digest.
+    let mut key = vec![0x12, 32];
+    key.extend_from_slice(&sha2::Sha256::digest(authority_public_key));
+    key
+}
+
+/// Builds the value of the record to publish in the DHT, containing the given addresses, and
+/// signed with the given authority discovery key.
+///
+/// The addresses must end with a `/p2p` component containing the [`PeerId`] of the local node,
+/// otherwise they are ignored by the nodes that look up the record.
+pub fn encode_record<'a>(
+    keypair: &schnorrkel::Keypair,
+    addresses: impl IntoIterator<Item = &'a Multiaddr>,
+) -> Vec<u8> {
+    let addresses = schema::v1::authority_discovery::AuthorityAddresses {
+        addresses: addresses.into_iter().map(|a| a.to_vec()).collect(),
+    };
+    let mut addresses_encoded = Vec::with_capacity(addresses.encoded_len());
+    addresses.encode(&mut addresses_encoded).unwrap();
+
+    let signature = keypair.sign_simple(SIGNING_CONTEXT, &addresses_encoded);
+
+    let signed = schema::v1::authority_discovery::SignedAuthorityAddresses {
+        addresses: addresses_encoded,
+        signature: signature.to_bytes().to_vec(),
+    };
+    let mut buf = Vec::with_capacity(signed.encoded_len());
+    signed.encode(&mut buf).unwrap();
+    buf
+}
+
+/// Decodes the value of a record found in the DHT under the [`dht_key`] of the given authority,
+/// and verifies its signature.
+///
+/// On success, returns the addresses of the authority, with the `/p2p` component removed. The
+/// addresses that don't end with a `/p2p` component are ignored.
+pub fn decode_record(
+    authority_public_key: &[u8; 32],
+    record_value: &[u8],
+) -> Result<Vec<(PeerId, Multiaddr)>, DecodeRecordError> {
+    let signed = schema::v1::authority_discovery::SignedAuthorityAddresses::decode(record_value)
+        .map_err(|_| DecodeRecordError::Protobuf)?;
+
+    let public_key = schnorrkel::PublicKey::from_bytes(authority_public_key)
+        .map_err(|_| DecodeRecordError::BadPublicKey)?;
+    let signature = schnorrkel::Signature::from_bytes(&signed.signature)
+        .map_err(|_| DecodeRecordError::BadSignature)?;
+    public_key
+        .verify_simple(SIGNING_CONTEXT, &signed.addresses, &signature)
+        .map_err(|_| DecodeRecordError::BadSignature)?;
+
+    let addresses =
+        schema::v1::authority_discovery::AuthorityAddresses::decode(&signed.addresses[..])
+            .map_err(|_| DecodeRecordError::Protobuf)?;
+
+    addresses
+        .addresses
+        .into_iter()
+        .map(|bytes| Multiaddr::try_from(bytes).map_err(|_| DecodeRecordError::BadAddress))
+        .filter_map(|address| {
+            let mut address = match address {
+                Ok(a) => a,
+                Err(err) => return Some(Err(err)),
+            };
+            match address.pop() {
+                Some(Protocol::P2p(multihash)) => {
+                    let peer_id = PeerId::from_multihash(multihash).ok()?;
+                    Some(Ok((peer_id, address)))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Error when decoding a record found in the DHT.
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeRecordError {
+    /// Failed to decode the protobuf message.
+    Protobuf,
+    /// The public key of the authority isn't a valid sr25519 public key.
+    BadPublicKey,
+    /// The signature is malformed or doesn't match the public key of the authority.
+    BadSignature,
+    /// One of the addresses couldn't be decoded.
+    BadAddress,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Multiaddr, PeerId};
+
+    fn keypair(seed: u8) -> schnorrkel::Keypair {
+        schnorrkel::MiniSecretKey::from_bytes(&[seed; 32])
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+    }
+
+    #[test]
+    fn encode_decode() {
+        let keypair = keypair(1);
+        let peer_id = PeerId::random();
+        let address: Multiaddr = "/ip4/1.2.3.4/tcp/30333".parse().unwrap();
+        let with_peer_id = address
+            .clone()
+            .with(libp2p::multiaddr::Protocol::P2p(peer_id.clone().into()));
+        let without_peer_id: Multiaddr = "/ip4/5.6.7.8/tcp/30333".parse().unwrap();
+
+        let record = super::encode_record(&keypair, &[with_peer_id, without_peer_id]);
+        let decoded = super::decode_record(&keypair.public.to_bytes(), &record).unwrap();
+        assert_eq!(decoded, vec![(peer_id, address)]);
+    }
+
+    #[test]
+    fn wrong_authority_rejected() {
+        let address: Multiaddr = "/ip4/1.2.3.4/tcp/30333".parse().unwrap();
+        let record = super::encode_record(&keypair(1), &[address]);
+        assert!(matches!(
+            super::decode_record(&keypair(2).public.to_bytes(), &record),
+            Err(super::DecodeRecordError::BadSignature)
+        ));
+    }
+}
@@ -15,17 +15,25 @@
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::{
-    debug_info,
+    address_book, authority_discovery, debug_info,
     discovery::{DiscoveryBehaviour, DiscoveryConfig, DiscoveryOut},
-    generic_proto, legacy_message, request_responses,
+    generic_proto, legacy_message, network_state, peerset, request_responses, traffic,
+    transactions,
 };
 
-use alloc::{borrow::Cow, collections::VecDeque};
+use alloc::{
+    borrow::{Cow, ToOwned as _},
+    collections::{BTreeMap, VecDeque},
+    string::{String, ToString as _},
+    vec::Vec,
+};
 use core::{
+    convert::TryFrom as _,
     iter,
     task::{Context, Poll},
     time::Duration,
 };
+use hashbrown::HashMap;
 use libp2p::core::{Multiaddr, PeerId, PublicKey};
 use libp2p::kad::record;
 use libp2p::swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters};
@@ -33,6 +41,11 @@ use libp2p::NetworkBehaviour;
 use parity_scale_codec::{DecodeAll, Encode};
 use primitive_types::H256;
 
+/// Maximum number of [`BehaviourOut::Transactions`] events waiting to be processed. Transactions
+/// received while this limit is reached are dropped, and counted in
+/// [`Behaviour::num_dropped_transactions`].
+const MAX_PENDING_TRANSACTIONS_EVENTS: usize = 64;
+
 /// General behaviour of the network. Combines all protocols together.
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "BehaviourOut", poll_method = "poll")]
@@ -47,20 +60,99 @@ pub struct Behaviour {
     /// All request-response protocols: blocks, light client requests, and so on.
     request_responses: request_responses::RequestResponsesBehaviour,
 
+    /// Number of the local best block. Sent to peers in the `Status` handshake.
+    #[behaviour(ignore)]
+    local_best_number: u64,
+    /// Hash of the local best block. Sent to peers in the `Status` handshake.
     #[behaviour(ignore)]
     local_best_hash: H256,
     #[behaviour(ignore)]
     local_genesis_hash: H256,
+    /// Roles of the local node. Sent to peers in the `Status` handshake and in the handshake of
+    /// the transactions protocol.
+    #[behaviour(ignore)]
+    local_roles: legacy_message::Roles,
+
+    /// Information about each peer with an open legacy substream, as reported in their `Status`
+    /// handshake. The best block is updated when they announce a new best block.
+    #[behaviour(ignore)]
+    legacy_peers: HashMap<PeerId, LegacyPeer, fnv::FnvBuildHasher>,
+
+    /// Name of the transactions notifications protocol.
+    #[behaviour(ignore)]
+    transactions_protocol: Cow<'static, [u8]>,
+
+    /// Transactions that each peer with an open substream is known to have, and number of
+    /// [`BehaviourOut::Transactions`] events in [`Behaviour::events`].
+    #[behaviour(ignore)]
+    transactions_gossip: transactions::TransactionsGossip,
+
+    /// Name under which the traffic of the legacy substream is reported.
+    #[behaviour(ignore)]
+    legacy_protocol_name: String,
+
+    /// Traffic exchanged over the legacy substream.
+    #[behaviour(ignore)]
+    legacy_traffic: traffic::ProtocolTraffic,
+
+    /// Traffic exchanged over the transactions notifications protocol. The transactions sent to
+    /// the peers that don't support this protocol are accounted here as well, while the ones
+    /// received from them are accounted in [`Behaviour::legacy_traffic`].
+    #[behaviour(ignore)]
+    transactions_traffic: traffic::ProtocolTraffic,
+
+    /// Authority discovery key of the local node, if it's an authority. Used to sign the record
+    /// containing our addresses.
+    #[behaviour(ignore)]
+    authority_discovery_keypair: Option<schnorrkel::Keypair>,
+
+    /// Keys in the DHT of the records of the authorities whose addresses we're looking up,
+    /// associated with the public key of each authority.
+    #[behaviour(ignore)]
+    authority_discovery_lookups: HashMap<record::Key, [u8; 32], fnv::FnvBuildHasher>,
 
     /// Queue of events to produce for the outside.
     #[behaviour(ignore)]
     events: VecDeque<BehaviourOut>,
 }
 
+/// Information about a peer with an open legacy substream.
+#[derive(Debug)]
+struct LegacyPeer {
+    roles: legacy_message::Roles,
+    best_number: u64,
+    best_hash: H256,
+}
+
 #[derive(Debug)]
 pub enum BehaviourOut {
     /// An announcement about a block has been gossiped to us.
-    BlockAnnounce(super::ScaleBlockHeader),
+    BlockAnnounce {
+        /// Peer which sent us the announcement.
+        peer_id: PeerId,
+        /// Header of the announced block.
+        header: super::ScaleBlockHeader,
+        /// True if the peer indicates that this block is its new best block.
+        is_best: bool,
+    },
+
+    /// A peer has opened the legacy substream and sent us its `Status` handshake.
+    PeerStatus {
+        /// Peer which sent the handshake.
+        peer_id: PeerId,
+        /// Number of the best block of the peer.
+        best_number: u64,
+        /// Hash of the best block of the peer.
+        best_hash: H256,
+    },
+
+    /// A peer has sent us transactions that we didn't know it had.
+    Transactions {
+        /// Peer which sent the transactions.
+        peer_id: PeerId,
+        /// SCALE-encoded extrinsics.
+        transactions: Vec<Vec<u8>>,
+    },
 
     /// We have received a request from a peer and answered it.
     ///
@@ -82,6 +174,15 @@ pub enum BehaviourOut {
         /// Response sent by the remote or reason for failure.
         outcome: Result<Vec<u8>, request_responses::OutboundFailure>,
     },
+
+    /// The record of an authority passed to [`Behaviour::set_authorities`] has been found in the
+    /// DHT, and its signature is valid.
+    AuthorityAddresses {
+        /// Sr25519 public key of the authority.
+        authority_public_key: [u8; 32],
+        /// Addresses published by the authority.
+        addresses: Vec<(PeerId, Multiaddr)>,
+    },
 }
 
 impl Behaviour {
@@ -91,35 +192,46 @@ impl Behaviour {
         chain_spec_protocol_id: Vec<u8>,
         local_public_key: PublicKey,
         known_addresses: Vec<(PeerId, Multiaddr)>,
+        address_book: Vec<address_book::AddressBookEntry>,
         enable_mdns: bool,
         allow_private_ipv4: bool,
         discovery_only_if_under_num: u64,
         request_response_protocols: Vec<request_responses::ProtocolConfig>,
+        peerset_config: peerset::PeersetConfig,
         local_best_hash: H256,
         local_genesis_hash: H256,
+        local_roles: legacy_message::Roles,
+        authority_discovery_keypair: Option<schnorrkel::Keypair>,
     ) -> Self {
-        let peerset_config = sc_peerset::PeersetConfig {
-            in_peers: 25,
-            out_peers: 25,
-            bootnodes: known_addresses.iter().map(|(p, _)| p.clone()).collect(),
-            reserved_only: false,
-            priority_groups: Default::default(),
+        let legacy_protocol_name = {
+            let mut name = "/substrate/".to_string();
+            name.push_str(&String::from_utf8_lossy(&chain_spec_protocol_id));
+            name
         };
 
-        let (peerset, _) = sc_peerset::Peerset::from_config(peerset_config);
-        let legacy = generic_proto::GenericProto::new(
+        let peerset = peerset::Peerset::new(peerset_config);
+        let mut legacy = generic_proto::GenericProto::new(
             local_public_key.clone().into_peer_id(),
             chain_spec_protocol_id.clone(),
             &[6, 5],
             peerset,
         );
 
+        let transactions_protocol: Cow<'static, [u8]> = {
+            let mut name = b"/".to_vec();
+            name.extend_from_slice(&chain_spec_protocol_id);
+            name.extend_from_slice(b"/transactions/1");
+            name.into()
+        };
+        legacy.register_notif_protocol(transactions_protocol.clone(), local_roles.encode());
+
         Behaviour {
             legacy,
             debug_info: debug_info::DebugInfoBehaviour::new(user_agent, local_public_key.clone()),
             discovery: {
                 let mut cfg = DiscoveryConfig::new(local_public_key);
                 cfg.with_user_defined(known_addresses);
+                cfg.with_address_book(address_book);
                 cfg.with_mdns(enable_mdns);
                 cfg.allow_private_ipv4(allow_private_ipv4);
                 cfg.discovery_limit(discovery_only_if_under_num);
@@ -132,8 +244,20 @@ impl Behaviour {
                 )
                 .unwrap()
             },
+            local_best_number: 0,
             local_best_hash,
             local_genesis_hash,
+            local_roles,
+            legacy_peers: Default::default(),
+            transactions_protocol,
+            transactions_gossip: transactions::TransactionsGossip::new(
+                MAX_PENDING_TRANSACTIONS_EVENTS,
+            ),
+            legacy_protocol_name,
+            legacy_traffic: Default::default(),
+            transactions_traffic: Default::default(),
+            authority_discovery_keypair,
+            authority_discovery_lookups: Default::default(),
             events: VecDeque::new(),
         }
     }
@@ -143,11 +267,297 @@ impl Behaviour {
         self.legacy.open_peers()
     }
 
+    /// Updates the local best block, sent to the peers that connect from now on.
+    pub fn set_local_best_block(&mut self, number: u64, hash: H256) {
+        self.local_best_number = number;
+        self.local_best_hash = hash;
+    }
+
+    /// Sends a block announce for the given header to all the peers with an open legacy
+    /// substream.
+    ///
+    /// If `is_best` is true, the block also becomes the local best block.
+    ///
+    /// Returns an error if the header can't be decoded.
+    pub fn announce_block(
+        &mut self,
+        scale_encoded_header: &[u8],
+        is_best: bool,
+    ) -> Result<(), parity_scale_codec::Error> {
+        let announce = legacy_message::BlockAnnounce {
+            header: DecodeAll::decode_all(scale_encoded_header)?,
+            state: Some(if is_best {
+                legacy_message::BlockState::Best
+            } else {
+                legacy_message::BlockState::Normal
+            }),
+            data: Some(Vec::new()),
+        };
+
+        if is_best {
+            let hash = H256(announce.header.block_hash().0);
+            self.set_local_best_block(announce.header.number, hash);
+        }
+
+        let message = legacy_message::Message::BlockAnnounce(announce).encode();
+
+        let targets = self.legacy.open_peers().cloned().collect::<Vec<_>>();
+        for peer_id in targets {
+            self.legacy_traffic.record_out(message.len());
+            self.legacy.send_packet(&peer_id, message.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Sends the given SCALE-encoded extrinsics to all the peers with an open substream, except
+    /// for the ones that are known to already have them.
+    pub fn propagate_transactions<'a>(&mut self, transactions: impl Iterator<Item = &'a [u8]>) {
+        let transactions = transactions.collect::<Vec<_>>();
+
+        let targets = self.legacy.open_peers().cloned().collect::<Vec<_>>();
+        for peer_id in targets {
+            let to_send = self
+                .transactions_gossip
+                .transactions_to_send(&peer_id, transactions.iter().cloned());
+            if to_send.is_empty() {
+                continue;
+            }
+
+            let notification = to_send.encode();
+            // Message sent on the legacy substream to the peers that don't support the
+            // transactions protocol. Its content has the same encoding as the notification.
+            let fallback = legacy_message::Message::Transactions(
+                DecodeAll::decode_all(&notification).unwrap(),
+            )
+            .encode();
+            self.transactions_traffic.record_out(notification.len());
+            self.legacy.write_notification(
+                &peer_id,
+                self.transactions_protocol.clone(),
+                notification,
+                fallback,
+            );
+        }
+    }
+
+    /// Returns the number of transactions received from peers that have been dropped because too
+    /// many [`BehaviourOut::Transactions`] events were waiting to be processed.
+    pub fn num_dropped_transactions(&self) -> u64 {
+        self.transactions_gossip.num_dropped()
+    }
+
+    /// Returns the traffic exchanged over each protocol since the behaviour has been created.
+    pub fn protocols_traffic(&self) -> impl Iterator<Item = (Cow<str>, &traffic::ProtocolTraffic)> {
+        iter::once((
+            Cow::Borrowed(&*self.legacy_protocol_name),
+            &self.legacy_traffic,
+        ))
+        .chain(iter::once((
+            String::from_utf8_lossy(&self.transactions_protocol),
+            &self.transactions_traffic,
+        )))
+        .chain(
+            self.request_responses
+                .traffic()
+                .map(|(name, traffic)| (Cow::Borrowed(name), traffic)),
+        )
+    }
+
+    /// Returns the statistics about the requests exchanged with each peer we're connected to.
+    pub fn peers_requests_stats(
+        &self,
+    ) -> impl Iterator<Item = (&PeerId, &traffic::PeerRequestsStats)> {
+        self.request_responses.peers_stats()
+    }
+
+    /// Starts looking up in the DHT the addresses of the given authorities, replacing the
+    /// authorities passed to the previous call.
+    ///
+    /// If the local authority discovery key is one of the authorities, also publishes a record
+    /// containing `local_addresses`, which must end with a `/p2p` component.
+    pub fn set_authorities(
+        &mut self,
+        authorities: impl IntoIterator<Item = [u8; 32]>,
+        local_addresses: impl Iterator<Item = Multiaddr>,
+    ) {
+        self.authority_discovery_lookups.clear();
+        let mut is_authority = false;
+
+        for authority in authorities {
+            if let Some(keypair) = &self.authority_discovery_keypair {
+                if keypair.public.to_bytes() == authority {
+                    is_authority = true;
+                    continue;
+                }
+            }
+
+            let key = record::Key::new(&authority_discovery::dht_key(&authority));
+            self.discovery.get_value(&key);
+            self.authority_discovery_lookups.insert(key, authority);
+        }
+
+        if let (true, Some(keypair)) = (is_authority, &self.authority_discovery_keypair) {
+            let local_addresses = local_addresses.collect::<Vec<_>>();
+            if !local_addresses.is_empty() {
+                let key = authority_discovery::dht_key(&keypair.public.to_bytes());
+                let value = authority_discovery::encode_record(keypair, &local_addresses);
+                self.discovery.put_value(record::Key::new(&key), value);
+            }
+        }
+    }
+
+    /// Returns the peers we have discovered, in order to be passed back to [`Behaviour::new`] the
+    /// next time the node starts.
+    pub fn address_book(&mut self) -> Vec<address_book::AddressBookEntry> {
+        self.discovery.address_book()
+    }
+
+    /// Returns the best block number and hash reported by the given peer, or `None` if we don't
+    /// have any open legacy substream with this peer.
+    pub fn peer_best_block(&self, peer_id: &PeerId) -> Option<(u64, H256)> {
+        self.legacy_peers
+            .get(peer_id)
+            .map(|peer| (peer.best_number, peer.best_hash))
+    }
+
+    /// Builds a snapshot of the state of the network.
+    ///
+    /// The information about the local node isn't known by the behaviour and must be passed as
+    /// parameter.
+    pub fn network_state(
+        &mut self,
+        local_peer_id: &PeerId,
+        listened_addresses: Vec<Multiaddr>,
+        external_addresses: Vec<Multiaddr>,
+    ) -> network_state::NetworkState {
+        let connected = self
+            .debug_info
+            .nodes()
+            .filter(|(_, node)| node.is_connected())
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect::<Vec<_>>();
+
+        let mut connected_peers = BTreeMap::new();
+        for peer_id in &connected {
+            let known_addresses = libp2p::swarm::NetworkBehaviour::addresses_of_peer(self, peer_id);
+            let node = self.debug_info.node(peer_id).unwrap();
+            let legacy_peer = self.legacy_peers.get(peer_id);
+            connected_peers.insert(
+                peer_id.to_base58(),
+                network_state::Peer {
+                    endpoint: node.endpoint().into(),
+                    version_string: node.client_version().map(|v| v.to_owned()),
+                    latest_ping_time: node.latest_ping(),
+                    enabled: self.legacy.is_enabled(peer_id),
+                    open: self.legacy.is_open(peer_id),
+                    roles: legacy_peer.map(|p| p.roles.to_string()),
+                    best_number: legacy_peer.map(|p| p.best_number),
+                    best_hash: legacy_peer.map(|p| p.best_hash),
+                    known_addresses,
+                    requests_stats: self.request_responses.peer_stats(peer_id).cloned(),
+                },
+            );
+        }
+
+        let not_connected = self
+            .discovery
+            .known_peers()
+            .filter(|peer_id| !connected.contains(peer_id))
+            .collect::<Vec<_>>();
+
+        let mut not_connected_peers = BTreeMap::new();
+        for peer_id in not_connected {
+            let known_addresses =
+                libp2p::swarm::NetworkBehaviour::addresses_of_peer(self, &peer_id);
+            let node = self.debug_info.node(&peer_id);
+            not_connected_peers.insert(
+                peer_id.to_base58(),
+                network_state::NotConnectedPeer {
+                    known_addresses,
+                    latest_ping_time: node.as_ref().and_then(|n| n.latest_ping()),
+                    version_string: node
+                        .as_ref()
+                        .and_then(|n| n.client_version())
+                        .map(|v| v.to_owned()),
+                },
+            );
+        }
+
+        let kbuckets = self
+            .discovery
+            .num_entries_per_kbucket()
+            .map(|(protocol_id, buckets)| {
+                let buckets = buckets
+                    .into_iter()
+                    .map(|(distance, num_entries)| network_state::KBucket {
+                        distance,
+                        num_entries,
+                    })
+                    .collect();
+                (String::from_utf8_lossy(protocol_id).into_owned(), buckets)
+            })
+            .collect();
+
+        let protocols_traffic = self
+            .protocols_traffic()
+            .map(|(name, traffic)| (name.into_owned(), traffic.clone()))
+            .collect::<BTreeMap<_, _>>();
+
+        network_state::NetworkState {
+            peer_id: local_peer_id.to_base58(),
+            listened_addresses,
+            external_addresses,
+            connected_peers,
+            not_connected_peers,
+            kbuckets,
+            peerset: self.legacy.peerset_debug_info(),
+            total_bytes_inbound: protocols_traffic
+                .values()
+                .fold(0, |total, t| total.saturating_add(t.bytes_in)),
+            total_bytes_outbound: protocols_traffic
+                .values()
+                .fold(0, |total, t| total.saturating_add(t.bytes_out)),
+            protocols_traffic,
+        }
+    }
+
     /// Returns the list of nodes that we know exist in the network.
     pub fn known_peers(&mut self) -> impl Iterator<Item = PeerId> {
         self.discovery.known_peers()
     }
 
+    /// Adjusts the reputation of the given peer in the peerset manager. Peers whose reputation
+    /// is too low are disconnected and banned for a while.
+    pub fn report_peer(&mut self, peer_id: PeerId, change: i32, reason: &'static str) {
+        self.legacy
+            .peerset_mut()
+            .report_peer(peer_id, peerset::ReputationChange::new(change, reason));
+    }
+
+    /// Adds a peer to the list of reserved peers. The peerset manager always tries to stay
+    /// connected to reserved peers, and they don't count towards the slots limits.
+    pub fn add_reserved_peer(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        self.discovery.add_known_address(peer_id.clone(), addr);
+        self.legacy.peerset_mut().add_reserved_peer(peer_id);
+    }
+
+    /// Removes a peer from the list of reserved peers. Has no effect if it isn't reserved.
+    pub fn remove_reserved_peer(&mut self, peer_id: &PeerId) {
+        self.legacy.peerset_mut().remove_reserved_peer(peer_id);
+    }
+
+    /// If `true`, only reserved peers are connected to, and all the other connections are
+    /// closed.
+    pub fn set_reserved_only(&mut self, reserved_only: bool) {
+        self.legacy.peerset_mut().set_reserved_only(reserved_only);
+    }
+
+    /// Disconnects from the given peer, if we're connected to it.
+    pub fn disconnect_peer(&mut self, peer_id: &PeerId) {
+        self.legacy.disconnect_peer(peer_id)
+    }
+
     /// Adds a hard-coded address for the given peer, that never expires.
     pub fn add_known_address(&mut self, peer_id: PeerId, addr: Multiaddr) {
         self.discovery.add_known_address(peer_id, addr)
@@ -213,32 +623,93 @@ impl NetworkBehaviourEventProcess<generic_proto::GenericProtoOut> for Behaviour
                 let message = legacy_message::Message::Status(legacy_message::Status {
                     version: 6,
                     min_supported_version: 6,
-                    roles: legacy_message::Roles::LIGHT,
-                    best_number: 0,
+                    roles: self.local_roles,
+                    // The legacy protocol only supports 32 bits block numbers.
+                    best_number: u32::try_from(self.local_best_number).unwrap_or(u32::max_value()),
                     best_hash: self.local_best_hash,
                     genesis_hash: self.local_genesis_hash,
                     chain_status: Vec::new(),
                 });
 
-                self.legacy.send_packet(&peer_id, message.encode());
+                let message = message.encode();
+                self.legacy_traffic.record_out(message.len());
+                self.legacy.send_packet(&peer_id, message);
+                self.transactions_gossip.peer_connected(peer_id);
             }
-            generic_proto::GenericProtoOut::CustomProtocolClosed { peer_id: _, .. } => {}
-            generic_proto::GenericProtoOut::LegacyMessage {
-                peer_id: _,
-                message,
-            } => {
+            generic_proto::GenericProtoOut::CustomProtocolClosed { peer_id, .. } => {
+                self.legacy_peers.remove(&peer_id);
+                self.transactions_gossip.peer_disconnected(&peer_id);
+            }
+            generic_proto::GenericProtoOut::LegacyMessage { peer_id, message } => {
+                self.legacy_traffic.record_in(message.len());
                 match legacy_message::Message::decode_all(&message) {
                     Ok(legacy_message::Message::BlockAnnounce(announcement)) => {
-                        self.events.push_back(BehaviourOut::BlockAnnounce(
-                            super::ScaleBlockHeader(announcement.header.encode()),
-                        ));
+                        // Announcements without a state are considered as being about the
+                        // best block, like Substrate does.
+                        let is_best = announcement
+                            .state
+                            .map_or(true, |s| s == legacy_message::BlockState::Best);
+                        if is_best {
+                            if let Some(peer) = self.legacy_peers.get_mut(&peer_id) {
+                                peer.best_number = announcement.header.number;
+                                peer.best_hash = H256(announcement.header.block_hash().0);
+                            }
+                        }
+
+                        self.events.push_back(BehaviourOut::BlockAnnounce {
+                            peer_id,
+                            header: super::ScaleBlockHeader(announcement.header.encode()),
+                            is_best,
+                        });
+                    }
+                    Ok(legacy_message::Message::Status(status)) => {
+                        let best_number = u64::from(status.best_number);
+                        self.legacy_peers.insert(
+                            peer_id.clone(),
+                            LegacyPeer {
+                                roles: status.roles,
+                                best_number,
+                                best_hash: status.best_hash,
+                            },
+                        );
+                        self.events.push_back(BehaviourOut::PeerStatus {
+                            peer_id,
+                            best_number,
+                            best_hash: status.best_hash,
+                        });
+                    }
+                    Ok(legacy_message::Message::Transactions(transactions)) => {
+                        let transactions = transactions.into_iter().map(|tx| tx.0).collect();
+                        self.on_transactions(peer_id, transactions);
                     }
-                    Ok(legacy_message::Message::Status(_)) => {}
                     _msg => {} // TODO: for debugging println!("message from {:?} => {:?}", peer_id, msg),
                 }
             }
             generic_proto::GenericProtoOut::Clogged { .. } => {}
-            generic_proto::GenericProtoOut::Notification { .. } => {} // TODO: !
+            generic_proto::GenericProtoOut::Notification {
+                peer_id,
+                protocol_name,
+                message,
+            } if protocol_name == self.transactions_protocol => {
+                self.transactions_traffic.record_in(message.len());
+                if let Ok(transactions) = Vec::<Vec<u8>>::decode_all(&message) {
+                    self.on_transactions(peer_id, transactions);
+                }
+            }
+            generic_proto::GenericProtoOut::Notification { .. } => {}
+        }
+    }
+}
+
+impl Behaviour {
+    /// Called when a peer sends us a list of transactions, either through a notification or
+    /// through the legacy substream.
+    fn on_transactions(&mut self, peer_id: PeerId, transactions: Vec<Vec<u8>>) {
+        if let Some(transactions) = self.transactions_gossip.on_received(&peer_id, transactions) {
+            self.events.push_back(BehaviourOut::Transactions {
+                peer_id,
+                transactions,
+            });
         }
     }
 }
@@ -257,8 +728,30 @@ impl NetworkBehaviourEventProcess<DiscoveryOut> for Behaviour {
                 self.legacy.add_discovered_nodes(iter::once(peer_id));
             }
             DiscoveryOut::RandomKademliaStarted(_) => {}
-            DiscoveryOut::ValueFound(_results) => {
-                //self.events.push(BehaviourOut::Event(Event::Dht(DhtEvent::ValueFound(results))));
+            DiscoveryOut::ValueFound(results) => {
+                for (key, value) in results {
+                    let authority_public_key = match self.authority_discovery_lookups.get(&key) {
+                        Some(a) => *a,
+                        None => continue,
+                    };
+
+                    // Records that can't be decoded or whose signature is invalid are ignored.
+                    let addresses =
+                        match authority_discovery::decode_record(&authority_public_key, &value) {
+                            Ok(a) => a,
+                            Err(_) => continue,
+                        };
+
+                    for (peer_id, address) in &addresses {
+                        self.discovery
+                            .add_self_reported_address(peer_id, address.clone());
+                    }
+
+                    self.events.push_back(BehaviourOut::AuthorityAddresses {
+                        authority_public_key,
+                        addresses,
+                    });
+                }
             }
             DiscoveryOut::ValueNotFound(_key) => {
                 //self.events.push(BehaviourOut::Event(Event::Dht(DhtEvent::ValueNotFound(key))));
@@ -308,6 +801,9 @@ impl Behaviour {
         _: &mut impl PollParameters,
     ) -> Poll<NetworkBehaviourAction<TEv, BehaviourOut>> {
         if let Some(event) = self.events.pop_front() {
+            if let BehaviourOut::Transactions { .. } = event {
+                self.transactions_gossip.on_processed();
+            }
             return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event));
         }
 
@@ -107,6 +107,14 @@ impl DebugInfoBehaviour {
         self.nodes_info.get(peer_id).map(Node)
     }
 
+    /// Returns the list of nodes we're connected to or have recently been connected to, and the
+    /// information about them.
+    pub fn nodes(&self) -> impl Iterator<Item = (&PeerId, Node)> {
+        self.nodes_info
+            .iter()
+            .map(|(peer_id, info)| (peer_id, Node(info)))
+    }
+
     /// Inserts a ping time in the cache. Has no effect if we don't have any entry for that node,
     /// which shouldn't happen.
     fn handle_ping_report(&mut self, peer_id: &PeerId, ping_time: Duration) {
@@ -128,6 +136,12 @@ impl DebugInfoBehaviour {
 pub struct Node<'a>(&'a NodeInfo);
 
 impl<'a> Node<'a> {
+    /// Returns true if we're currently connected to the peer. Information about the nodes we
+    /// have been disconnected from is kept for a while.
+    pub fn is_connected(&self) -> bool {
+        self.0.info_expire.is_none()
+    }
+
     /// Returns the endpoint of an established connection to the peer.
     pub fn endpoint(&self) -> &'a ConnectedPoint {
         &self.0.endpoints[0] // `endpoints` are non-empty by definition
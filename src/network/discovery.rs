@@ -30,6 +30,11 @@
 //! configured Kademlia DHTs in order for nodes to propagate to us their view of the network. This
 //! is performed automatically by the `DiscoveryBehaviour`.
 //!
+//! - Address book. Peers discovered during a previous run of the node, passed to
+//! `DiscoveryConfig::with_address_book`. The `DiscoveryBehaviour` keeps this list up to date
+//! with the peers it discovers and the outcome of the attempts to connect to them, and returns it
+//! through `DiscoveryBehaviour::address_book`.
+//!
 //! Additionally, the `DiscoveryBehaviour` is also capable of storing and loading value in the
 //! configured DHTs.
 //!
@@ -45,6 +50,8 @@
 //! of a node's address, you must call `add_self_reported_address`.
 //!
 
+use super::address_book::{AddressBook, AddressBookEntry};
+
 use futures::prelude::*;
 use futures_timer::Delay;
 use ip_network::IpNetwork;
@@ -87,6 +94,7 @@ pub struct DiscoveryConfig {
     discovery_only_if_under_num: u64,
     enable_mdns: bool,
     kademlias: HashMap<Vec<u8>, Kademlia<MemoryStore>>,
+    address_book: AddressBook,
 }
 
 impl DiscoveryConfig {
@@ -100,6 +108,7 @@ impl DiscoveryConfig {
             discovery_only_if_under_num: std::u64::MAX,
             enable_mdns: false,
             kademlias: HashMap::new(),
+            address_book: AddressBook::new(),
         }
     }
 
@@ -123,6 +132,25 @@ impl DiscoveryConfig {
         self
     }
 
+    /// Set the peers discovered during a previous run, as returned by
+    /// [`DiscoveryBehaviour::address_book`].
+    ///
+    /// Contrary to the user-defined nodes, the addresses of these peers can expire.
+    pub fn with_address_book<I>(&mut self, entries: I) -> &mut Self
+    where
+        I: IntoIterator<Item = AddressBookEntry>,
+    {
+        self.address_book = AddressBook::import(entries, now_from_unix_epoch());
+        for entry in self.address_book.iter_preferred() {
+            for kad in self.kademlias.values_mut() {
+                for addr in &entry.addresses {
+                    kad.add_address(&entry.peer_id, addr.clone());
+                }
+            }
+        }
+        self
+    }
+
     /// Should private IPv4 addresses be reported?
     pub fn allow_private_ipv4(&mut self, value: bool) -> &mut Self {
         self.allow_private_ipv4 = value;
@@ -171,17 +199,32 @@ impl DiscoveryConfig {
             kad.add_address(peer_id, addr.clone());
         }
 
+        for entry in self.address_book.iter_preferred() {
+            for addr in &entry.addresses {
+                kad.add_address(&entry.peer_id, addr.clone());
+            }
+        }
+
         self.kademlias.insert(id, kad);
     }
 
     /// Create a `DiscoveryBehaviour` from this config.
     pub fn finish(self) -> DiscoveryBehaviour {
+        // Report the peers of the address book immediately, starting with the preferred ones, so
+        // that we try to connect to them before waiting for the Kademlia queries to finish.
+        let pending_events = self
+            .address_book
+            .iter_preferred()
+            .map(|entry| DiscoveryOut::Discovered(entry.peer_id.clone()))
+            .collect();
+
         DiscoveryBehaviour {
             user_defined: self.user_defined,
             kademlias: self.kademlias,
+            address_book: self.address_book,
             next_kad_random_query: Delay::new(Duration::new(0, 0)),
             duration_to_next_kad: Duration::from_secs(1),
-            pending_events: VecDeque::new(),
+            pending_events,
             local_peer_id: self.local_peer_id,
             num_connections: 0,
             allow_private_ipv4: self.allow_private_ipv4,
@@ -207,6 +250,9 @@ pub struct DiscoveryBehaviour {
     user_defined: Vec<(PeerId, Multiaddr)>,
     /// Kademlia requests and answers.
     kademlias: HashMap<Vec<u8>, Kademlia<MemoryStore>>,
+    /// Peers we have discovered, either during this run or a previous one, and how reachable they
+    /// are.
+    address_book: AddressBook,
     /// Discovers nodes on the local network.
     #[cfg(feature = "os-networking")]
     mdns: Toggle<Mdns>,
@@ -272,9 +318,18 @@ impl DiscoveryBehaviour {
             for k in self.kademlias.values_mut() {
                 k.add_address(peer_id, addr.clone());
             }
+            self.address_book
+                .insert_address(peer_id, addr, now_from_unix_epoch());
         }
     }
 
+    /// Returns the list of peers we have discovered, ordered from the ones we should try to
+    /// connect to first to the least reachable ones, in order to be saved and passed to
+    /// [`DiscoveryConfig::with_address_book`] the next time the node starts.
+    pub fn address_book(&mut self) -> Vec<AddressBookEntry> {
+        self.address_book.export(now_from_unix_epoch())
+    }
+
     /// Start fetching a record from the DHT.
     ///
     /// A corresponding `ValueFound` or `ValueNotFound` event will later be generated.
@@ -297,6 +352,22 @@ impl DiscoveryBehaviour {
         }
     }
 
+    /// Returns the number of entries in each non-empty k-bucket of each Kademlia protocol.
+    ///
+    /// Each bucket is identified by the base-2 logarithm of the lower bound of the distances of
+    /// its peers to the local node.
+    pub fn num_entries_per_kbucket(
+        &mut self,
+    ) -> impl ExactSizeIterator<Item = (&Vec<u8>, Vec<(u32, usize)>)> {
+        self.kademlias.iter_mut().map(|(id, kad)| {
+            let buckets = kad
+                .kbuckets()
+                .map(|bucket| (bucket.range().0.ilog2().unwrap_or(0), bucket.iter().count()))
+                .collect();
+            (id, buckets)
+        })
+    }
+
     /// Returns the number of records in the Kademlia record stores.
     pub fn num_kademlia_records(&mut self) -> impl ExactSizeIterator<Item = (&Vec<u8>, usize)> {
         // Note that this code is ok only because we use a `MemoryStore`.
@@ -404,6 +475,13 @@ impl NetworkBehaviour for DiscoveryBehaviour {
             #[cfg(feature = "os-networking")]
             list_to_filter.extend(self.mdns.addresses_of_peer(peer_id));
 
+            // The k-buckets are bounded, and might not contain all the peers of the address book.
+            for addr in self.address_book.addresses_of_peer(peer_id) {
+                if !list_to_filter.contains(addr) {
+                    list_to_filter.push(addr.clone());
+                }
+            }
+
             if !self.allow_private_ipv4 {
                 list_to_filter.retain(|addr| {
                     if let Some(Protocol::Ip4(addr)) = addr.iter().next() {
@@ -429,6 +507,14 @@ impl NetworkBehaviour for DiscoveryBehaviour {
         endpoint: &ConnectedPoint,
     ) {
         self.num_connections += 1;
+
+        let dialed_address = match endpoint {
+            ConnectedPoint::Dialer { address } => Some(address),
+            ConnectedPoint::Listener { .. } => None,
+        };
+        self.address_book
+            .report_success(peer_id, dialed_address, now_from_unix_epoch());
+
         for k in self.kademlias.values_mut() {
             NetworkBehaviour::inject_connection_established(k, peer_id, conn, endpoint)
         }
@@ -464,6 +550,10 @@ impl NetworkBehaviour for DiscoveryBehaviour {
         addr: &Multiaddr,
         error: &dyn std::error::Error,
     ) {
+        if let Some(peer_id) = peer_id {
+            self.address_book.report_unreachable_address(peer_id, addr);
+        }
+
         for k in self.kademlias.values_mut() {
             NetworkBehaviour::inject_addr_reach_failure(k, peer_id, addr, error)
         }
@@ -496,6 +586,8 @@ impl NetworkBehaviour for DiscoveryBehaviour {
     }
 
     fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        self.address_book.report_failure(peer_id);
+
         for k in self.kademlias.values_mut() {
             NetworkBehaviour::inject_dial_failure(k, peer_id)
         }
@@ -568,7 +660,14 @@ impl NetworkBehaviour for DiscoveryBehaviour {
                             let ev = DiscoveryOut::UnroutablePeer(peer);
                             return Poll::Ready(NetworkBehaviourAction::GenerateEvent(ev));
                         }
-                        KademliaEvent::RoutingUpdated { peer, .. } => {
+                        KademliaEvent::RoutingUpdated {
+                            peer, addresses, ..
+                        } => {
+                            let now = now_from_unix_epoch();
+                            for addr in addresses.iter() {
+                                self.address_book.insert_address(&peer, addr.clone(), now);
+                            }
+
                             let ev = DiscoveryOut::Discovered(peer);
                             return Poll::Ready(NetworkBehaviourAction::GenerateEvent(ev));
                         }
@@ -676,6 +775,16 @@ impl NetworkBehaviour for DiscoveryBehaviour {
     }
 }
 
+/// Returns the current time, as a duration since the UNIX epoch, for the purpose of the address
+/// book.
+fn now_from_unix_epoch() -> Duration {
+    // A system clock set before 1970 is treated as if it was 1970, which only results in all the
+    // entries of the address book being considered as recently seen.
+    wasm_timer::SystemTime::now()
+        .duration_since(wasm_timer::UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::new(0, 0))
+}
+
 #[cfg(test)]
 mod tests {
     use super::{DiscoveryConfig, DiscoveryOut};
@@ -18,6 +18,7 @@ use crate::network::generic_proto::handler::{
     NotifsHandlerIn, NotifsHandlerOut, NotifsHandlerProto,
 };
 use crate::network::generic_proto::upgrade::RegisteredProtocol;
+use crate::network::peerset;
 
 use bytes::BytesMut;
 use fnv::FnvHashMap;
@@ -120,7 +121,7 @@ pub struct GenericProto {
     notif_protocols: Vec<(Cow<'static, [u8]>, Vec<u8>)>,
 
     /// Receiver for instructions about who to connect to or disconnect from.
-    peerset: sc_peerset::Peerset,
+    peerset: peerset::Peerset,
 
     /// List of peers in our state.
     peers: FnvHashMap<PeerId, PeerState>,
@@ -143,7 +144,7 @@ pub struct GenericProto {
 
     /// We generate indices to identify incoming connections. This is the next value for the index
     /// to use when a connection is incoming.
-    next_incoming_index: sc_peerset::IncomingIndex,
+    next_incoming_index: peerset::IncomingIndex,
 
     /// Events to produce from `poll()`.
     events: VecDeque<NetworkBehaviourAction<NotifsHandlerIn, GenericProtoOut>>,
@@ -280,7 +281,7 @@ struct IncomingPeer {
     /// connection corresponding to it has been closed or replaced already.
     alive: bool,
     /// Id that the we sent to the peerset.
-    incoming_id: sc_peerset::IncomingIndex,
+    incoming_id: peerset::IncomingIndex,
 }
 
 /// Event that can be emitted by the `GenericProto`.
@@ -336,7 +337,7 @@ impl GenericProto {
         local_peer_id: PeerId,
         protocol: impl Into<Vec<u8>>,
         versions: &[u8],
-        peerset: sc_peerset::Peerset,
+        peerset: peerset::Peerset,
     ) -> Self {
         let generic_protocol = RegisteredProtocol::new(protocol, versions);
 
@@ -349,7 +350,7 @@ impl GenericProto {
             delays: Default::default(),
             next_delay_id: DelayId(0),
             incoming: SmallVec::new(),
-            next_incoming_index: sc_peerset::IncomingIndex(0),
+            next_incoming_index: peerset::IncomingIndex(0),
             events: VecDeque::new(),
         }
     }
@@ -600,6 +601,12 @@ impl GenericProto {
         self.peerset.debug_info()
     }
 
+    /// Gives access to the peerset manager, for example in order to report misbehaving peers or
+    /// to modify the list of reserved peers.
+    pub fn peerset_mut(&mut self) -> &mut peerset::Peerset {
+        &mut self.peerset
+    }
+
     /// Function that is called when the peerset wants us to connect to a peer.
     fn peerset_report_connect(&mut self, peer_id: PeerId) {
         let mut occ_entry = match self.peers.entry(peer_id) {
@@ -772,7 +779,7 @@ impl GenericProto {
 
     /// Function that is called when the peerset wants us to accept a connection
     /// request from a peer.
-    fn peerset_report_accept(&mut self, index: sc_peerset::IncomingIndex) {
+    fn peerset_report_accept(&mut self, index: peerset::IncomingIndex) {
         let incoming = if let Some(pos) = self.incoming.iter().position(|i| i.incoming_id == index)
         {
             self.incoming.remove(pos)
@@ -802,7 +809,7 @@ impl GenericProto {
     }
 
     /// Function that is called when the peerset wants us to reject an incoming peer.
-    fn peerset_report_reject(&mut self, index: sc_peerset::IncomingIndex) {
+    fn peerset_report_reject(&mut self, index: peerset::IncomingIndex) {
         let incoming = if let Some(pos) = self.incoming.iter().position(|i| i.incoming_id == index)
         {
             self.incoming.remove(pos)
@@ -1224,7 +1231,7 @@ impl NetworkBehaviour for GenericProto {
                 // again in the short term.
                 self.peerset.report_peer(
                     source.clone(),
-                    sc_peerset::ReputationChange::new(i32::min_value(), "Protocol error"),
+                    peerset::ReputationChange::new(i32::min_value(), "Protocol error"),
                 );
                 self.disconnect_peer_inner(&source, Some(Duration::from_secs(5)));
             }
@@ -1244,16 +1251,16 @@ impl NetworkBehaviour for GenericProto {
         // Note that the peerset is a *best effort* crate, and we have to use defensive programming.
         loop {
             match futures::Stream::poll_next(Pin::new(&mut self.peerset), cx) {
-                Poll::Ready(Some(sc_peerset::Message::Accept(index))) => {
+                Poll::Ready(Some(peerset::Message::Accept(index))) => {
                     self.peerset_report_accept(index);
                 }
-                Poll::Ready(Some(sc_peerset::Message::Reject(index))) => {
+                Poll::Ready(Some(peerset::Message::Reject(index))) => {
                     self.peerset_report_reject(index);
                 }
-                Poll::Ready(Some(sc_peerset::Message::Connect(id))) => {
+                Poll::Ready(Some(peerset::Message::Connect(id))) => {
                     self.peerset_report_connect(id);
                 }
-                Poll::Ready(Some(sc_peerset::Message::Drop(id))) => {
+                Poll::Ready(Some(peerset::Message::Drop(id))) => {
                     self.peerset_report_disconnect(id);
                 }
                 Poll::Ready(None) => {
@@ -18,6 +18,7 @@
 
 use crate::network::generic_proto::{GenericProto, GenericProtoOut};
 use crate::network::legacy_message::{BlockResponse, Message};
+use crate::network::peerset;
 use futures::{prelude::*, ready};
 use libp2p::core::connection::{ConnectionId, ListenerId};
 use libp2p::core::ConnectedPoint;
@@ -71,7 +72,7 @@ fn build_nodes() -> (Swarm<CustomProtoWithAddr>, Swarm<CustomProtoWithAddr>) {
             .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
             .boxed();
 
-        let (peerset, _) = sc_peerset::Peerset::from_config(sc_peerset::PeersetConfig {
+        let peerset = peerset::Peerset::new(peerset::PeersetConfig {
             in_peers: 25,
             out_peers: 25,
             bootnodes: if index == 0 {
@@ -83,8 +84,8 @@ fn build_nodes() -> (Swarm<CustomProtoWithAddr>, Swarm<CustomProtoWithAddr>) {
             } else {
                 vec![]
             },
+            reserved_peers: Vec::new(),
             reserved_only: false,
-            priority_groups: Vec::new(),
         });
 
         let behaviour = CustomProtoWithAddr {
@@ -0,0 +1,141 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Information about the state of the network, for debugging purposes.
+//!
+//! The types of this module are returned by [`super::Network::network_state`]. They implement
+//! [`serde::Serialize`], and the JSON they serialize to is a superset of what Substrate returns
+//! to the `system_networkState` JSON-RPC method.
+
+use super::{traffic, Multiaddr};
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::time::Duration;
+use primitive_types::H256;
+
+/// Snapshot of the state of the network.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkState {
+    /// Base58-encoded [`super::PeerId`] of the local node.
+    pub peer_id: String,
+    /// Addresses the local node is listening on.
+    pub listened_addresses: Vec<Multiaddr>,
+    /// Addresses the local node is reachable at, as reported to the other nodes.
+    pub external_addresses: Vec<Multiaddr>,
+    /// Peers we're connected to, indexed by their base58-encoded [`super::PeerId`].
+    pub connected_peers: BTreeMap<String, Peer>,
+    /// Peers we know of but aren't connected to, indexed by their base58-encoded [`super::PeerId`].
+    pub not_connected_peers: BTreeMap<String, NotConnectedPeer>,
+    /// Number of entries in each non-empty Kademlia k-bucket, for each Kademlia protocol.
+    pub kbuckets: BTreeMap<String, Vec<KBucket>>,
+    /// State of the peerset manager, in a format that is only meant to be read by humans.
+    pub peerset: serde_json::Value,
+    /// Number of bytes received over all the protocols since the network has started, not
+    /// including the overhead of encryption and multiplexing.
+    pub total_bytes_inbound: u64,
+    /// Number of bytes sent over all the protocols since the network has started, not including
+    /// the overhead of encryption and multiplexing.
+    pub total_bytes_outbound: u64,
+    /// Traffic exchanged over each protocol, indexed by protocol name. See
+    /// [`super::Network::protocols_traffic`].
+    pub protocols_traffic: BTreeMap<String, traffic::ProtocolTraffic>,
+}
+
+/// Information about a peer we're connected to.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Peer {
+    /// How we're connected to the peer. If we have multiple connections, one of them is picked.
+    pub endpoint: PeerEndpoint,
+    /// Client version reported by the peer through the identify protocol, if known.
+    pub version_string: Option<String>,
+    /// Latest round-trip time measured with the ping protocol, if any.
+    pub latest_ping_time: Option<Duration>,
+    /// True if the peerset manager wants us to be connected to this peer.
+    pub enabled: bool,
+    /// True if the legacy substream with this peer is open.
+    pub open: bool,
+    /// Roles reported by the peer in its `Status` handshake. `None` if the legacy substream
+    /// isn't open.
+    pub roles: Option<String>,
+    /// Number of the best block of the peer. `None` if the legacy substream isn't open.
+    pub best_number: Option<u64>,
+    /// Hash of the best block of the peer. `None` if the legacy substream isn't open.
+    pub best_hash: Option<H256>,
+    /// Addresses of the peer known by the discovery mechanisms.
+    pub known_addresses: Vec<Multiaddr>,
+    /// Statistics about the requests exchanged with the peer. `None` if no request has been
+    /// exchanged with the peer since we have connected to it.
+    pub requests_stats: Option<traffic::PeerRequestsStats>,
+}
+
+/// Information about a peer we know of but aren't connected to.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotConnectedPeer {
+    /// Addresses of the peer known by the discovery mechanisms.
+    pub known_addresses: Vec<Multiaddr>,
+    /// Latest round-trip time measured with the ping protocol, if we have been connected to
+    /// this peer recently.
+    pub latest_ping_time: Option<Duration>,
+    /// Client version reported by the peer, if we have been connected to this peer recently.
+    pub version_string: Option<String>,
+}
+
+/// How we're connected to a peer.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PeerEndpoint {
+    /// We dialed the peer at the given address.
+    Dialing(Multiaddr),
+    /// The peer has connected to us.
+    Listening {
+        /// Local address on which the connection has been received.
+        local_addr: Multiaddr,
+        /// Address the remote can be reached at through this connection.
+        send_back_addr: Multiaddr,
+    },
+}
+
+/// Information about a Kademlia k-bucket.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KBucket {
+    /// Base-2 logarithm of the lower bound of the distances of the peers in this bucket to the
+    /// local node.
+    pub distance: u32,
+    /// Number of peers in this bucket.
+    pub num_entries: usize,
+}
+
+impl From<&libp2p::core::ConnectedPoint> for PeerEndpoint {
+    fn from(endpoint: &libp2p::core::ConnectedPoint) -> Self {
+        match endpoint {
+            libp2p::core::ConnectedPoint::Dialer { address } => {
+                PeerEndpoint::Dialing(address.clone())
+            }
+            libp2p::core::ConnectedPoint::Listener {
+                local_addr,
+                send_back_addr,
+            } => PeerEndpoint::Listening {
+                local_addr: local_addr.clone(),
+                send_back_addr: send_back_addr.clone(),
+            },
+        }
+    }
+}
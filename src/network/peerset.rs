@@ -0,0 +1,553 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Peer slots manager.
+//!
+//! The [`Peerset`] keeps track of the nodes we know about, and decides which nodes we should
+//! connect to and which incoming connections we should accept.
+//!
+//! The number of connections is limited by a number of inbound and outbound slots. Each node
+//! that we're connected to, except for reserved nodes, occupies one slot. Whenever an outbound
+//! slot is free, the [`Peerset`] picks the known node with the highest reputation and asks for
+//! a connection to be opened.
+//!
+//! Reserved nodes don't occupy any slot, and the [`Peerset`] always tries to stay connected to
+//! them. When in reserved-only mode, only reserved nodes are connected to.
+//!
+//! Each node has a reputation, which is adjusted with [`Peerset::report_peer`] and slowly
+//! returns towards zero over time. Nodes whose reputation falls below a certain threshold are
+//! disconnected and banned for a while.
+//!
+//! ## Usage
+//!
+//! The [`Peerset`] implements the `Stream` trait and produces [`Message`]s indicating which
+//! connections to open or close. The user must, in return, report incoming connections with
+//! [`Peerset::incoming`] and closed connections with [`Peerset::dropped`].
+
+use alloc::collections::VecDeque;
+use core::{
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use futures::prelude::*;
+use futures_timer::Delay;
+use hashbrown::{HashMap, HashSet};
+use libp2p::PeerId;
+use wasm_timer::Instant;
+
+/// Reputation below which a node gets disconnected and banned.
+const BANNED_THRESHOLD: i32 = 82 * (i32::min_value() / 100);
+/// Reputation change applied to a node when the connection to it is closed.
+const DISCONNECT_REPUTATION_CHANGE: i32 = -256;
+/// Duration during which a node is banned after its reputation has fallen below
+/// [`BANNED_THRESHOLD`].
+const BAN_DURATION: Duration = Duration::from_secs(60);
+/// Interval between two decreases of the absolute value of the reputations.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Configuration for a [`Peerset`].
+#[derive(Debug)]
+pub struct PeersetConfig {
+    /// Maximum number of incoming connections to non-reserved nodes.
+    pub in_peers: u32,
+
+    /// Maximum number of outgoing connections to non-reserved nodes.
+    pub out_peers: u32,
+
+    /// List of nodes that are initially known.
+    pub bootnodes: Vec<PeerId>,
+
+    /// List of nodes that we should always be connected to.
+    pub reserved_peers: Vec<PeerId>,
+
+    /// If true, only connect to reserved nodes and reject all the other incoming connections.
+    pub reserved_only: bool,
+}
+
+/// Opaque identifier of an incoming connection, passed to [`Peerset::incoming`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct IncomingIndex(pub u64);
+
+/// Message that can be produced by the [`Peerset`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Request to open a connection to the given node.
+    Connect(PeerId),
+    /// Close the connection to the given node. The [`Peerset`] already considers the node as
+    /// disconnected and [`Peerset::dropped`] must not be called.
+    Drop(PeerId),
+    /// Incoming connection previously reported with [`Peerset::incoming`] must be accepted.
+    Accept(IncomingIndex),
+    /// Incoming connection previously reported with [`Peerset::incoming`] must be rejected.
+    Reject(IncomingIndex),
+}
+
+/// Modification to the reputation of a node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReputationChange {
+    /// Value added to the reputation.
+    pub value: i32,
+    /// Reason of the change, for debugging purposes.
+    pub reason: &'static str,
+}
+
+impl ReputationChange {
+    /// Builds a new [`ReputationChange`].
+    pub const fn new(value: i32, reason: &'static str) -> Self {
+        ReputationChange { value, reason }
+    }
+}
+
+/// Peer slots manager. See [the module-level documentation](..).
+pub struct Peerset {
+    /// All the nodes we know about.
+    nodes: HashMap<PeerId, Node, fnv::FnvBuildHasher>,
+    /// Subset of the keys of [`Peerset::nodes`] that are reserved.
+    reserved: HashSet<PeerId, fnv::FnvBuildHasher>,
+    /// If true, we only connect to reserved nodes.
+    reserved_only: bool,
+
+    /// Maximum number of incoming connections to non-reserved nodes.
+    in_slots: u32,
+    /// Maximum number of outgoing connections to non-reserved nodes.
+    out_slots: u32,
+    /// Number of incoming connections to non-reserved nodes.
+    num_in: u32,
+    /// Number of outgoing connections to non-reserved nodes.
+    num_out: u32,
+
+    /// Messages waiting to be returned by the `Stream` implementation.
+    messages: VecDeque<Message>,
+    /// Fires when the reputations must be updated.
+    next_tick: Delay,
+    /// Waker of the task polling the `Stream` implementation, if any. Must be woken up when new
+    /// messages are pushed from outside of the `Stream` implementation.
+    waker: Option<Waker>,
+}
+
+struct Node {
+    /// Reputation of the node. Returns towards zero over time.
+    reputation: i32,
+    /// If `Some`, we are connected to this node.
+    connection: Option<Direction>,
+    /// If `Some`, we must not connect to this node before the given moment.
+    banned_until: Option<Instant>,
+}
+
+impl Node {
+    fn new() -> Self {
+        Node {
+            reputation: 0,
+            connection: None,
+            banned_until: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Direction {
+    In,
+    Out,
+}
+
+impl Peerset {
+    /// Builds a new [`Peerset`].
+    pub fn new(config: PeersetConfig) -> Self {
+        let mut peerset = Peerset {
+            nodes: HashMap::default(),
+            reserved: HashSet::default(),
+            reserved_only: config.reserved_only,
+            in_slots: config.in_peers,
+            out_slots: config.out_peers,
+            num_in: 0,
+            num_out: 0,
+            messages: VecDeque::new(),
+            next_tick: Delay::new(TICK_INTERVAL),
+            waker: None,
+        };
+
+        for peer_id in config.bootnodes {
+            peerset.nodes.entry(peer_id).or_insert_with(Node::new);
+        }
+
+        for peer_id in config.reserved_peers {
+            peerset
+                .nodes
+                .entry(peer_id.clone())
+                .or_insert_with(Node::new);
+            peerset.reserved.insert(peer_id);
+        }
+
+        peerset.alloc_slots();
+        peerset
+    }
+
+    /// Returns the number of nodes that we know about.
+    pub fn num_discovered_peers(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the current reputation of the given node. Unknown nodes have a reputation of 0.
+    pub fn reputation(&self, peer_id: &PeerId) -> i32 {
+        self.nodes.get(peer_id).map_or(0, |n| n.reputation)
+    }
+
+    /// Returns the list of reserved nodes.
+    pub fn reserved_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.reserved.iter()
+    }
+
+    /// Adds a node to the list of reserved nodes. Has no effect if it is already reserved.
+    ///
+    /// If we're already connected to this node, the connection stops occupying a slot.
+    pub fn add_reserved_peer(&mut self, peer_id: PeerId) {
+        if !self.reserved.insert(peer_id.clone()) {
+            return;
+        }
+
+        let node = self.nodes.entry(peer_id).or_insert_with(Node::new);
+        match node.connection {
+            Some(Direction::In) => self.num_in -= 1,
+            Some(Direction::Out) => self.num_out -= 1,
+            None => {}
+        }
+
+        self.alloc_slots();
+    }
+
+    /// Removes a node from the list of reserved nodes. Has no effect if it isn't reserved.
+    ///
+    /// If we're connected to this node, the connection is closed when in reserved-only mode,
+    /// and starts occupying a slot otherwise.
+    pub fn remove_reserved_peer(&mut self, peer_id: &PeerId) {
+        if !self.reserved.remove(peer_id) {
+            return;
+        }
+
+        if let Some(node) = self.nodes.get_mut(peer_id) {
+            match (node.connection, self.reserved_only) {
+                (Some(_), true) => {
+                    node.connection = None;
+                    self.messages.push_back(Message::Drop(peer_id.clone()));
+                }
+                (Some(Direction::In), false) => self.num_in += 1,
+                (Some(Direction::Out), false) => self.num_out += 1,
+                (None, _) => {}
+            }
+        }
+
+        self.alloc_slots();
+    }
+
+    /// Enables or disables the reserved-only mode.
+    ///
+    /// Enabling it closes the connections to all the non-reserved nodes.
+    pub fn set_reserved_only(&mut self, reserved_only: bool) {
+        self.reserved_only = reserved_only;
+
+        if reserved_only {
+            for (peer_id, node) in self.nodes.iter_mut() {
+                if node.connection.is_some() && !self.reserved.contains(peer_id) {
+                    node.connection = None;
+                    self.messages.push_back(Message::Drop(peer_id.clone()));
+                }
+            }
+
+            self.num_in = 0;
+            self.num_out = 0;
+        }
+
+        self.alloc_slots();
+    }
+
+    /// Adjusts the reputation of the given node.
+    ///
+    /// If the reputation falls below a certain threshold, the node is disconnected and banned
+    /// for a while. Reserved nodes are never banned.
+    pub fn report_peer(&mut self, peer_id: PeerId, change: ReputationChange) {
+        let is_reserved = self.reserved.contains(&peer_id);
+        let node = self.nodes.entry(peer_id.clone()).or_insert_with(Node::new);
+        node.reputation = node.reputation.saturating_add(change.value);
+
+        if is_reserved || node.reputation >= BANNED_THRESHOLD {
+            return;
+        }
+
+        node.banned_until = Some(Instant::now() + BAN_DURATION);
+        match node.connection.take() {
+            Some(Direction::In) => self.num_in -= 1,
+            Some(Direction::Out) => self.num_out -= 1,
+            None => return,
+        }
+
+        self.messages.push_back(Message::Drop(peer_id));
+        self.alloc_slots();
+    }
+
+    /// Notifies the [`Peerset`] of the existence of the given nodes.
+    ///
+    /// Can be called multiple times with the same nodes.
+    pub fn discovered(&mut self, peer_ids: impl Iterator<Item = PeerId>) {
+        for peer_id in peer_ids {
+            self.nodes.entry(peer_id).or_insert_with(Node::new);
+        }
+
+        self.alloc_slots();
+    }
+
+    /// Notifies the [`Peerset`] of an incoming connection from the given node.
+    ///
+    /// The [`Peerset`] later produces either a [`Message::Accept`] or a [`Message::Reject`] with
+    /// the given index. If the connection gets accepted, [`Peerset::dropped`] must be called
+    /// when it is closed.
+    pub fn incoming(&mut self, peer_id: PeerId, index: IncomingIndex) {
+        let is_reserved = self.reserved.contains(&peer_id);
+        let node = self.nodes.entry(peer_id).or_insert_with(Node::new);
+
+        let accept = if node.connection.is_some() {
+            false
+        } else if is_reserved {
+            true
+        } else {
+            !self.reserved_only
+                && node
+                    .banned_until
+                    .map_or(true, |until| until <= Instant::now())
+                && self.num_in < self.in_slots
+        };
+
+        if accept {
+            node.connection = Some(Direction::In);
+            if !is_reserved {
+                self.num_in += 1;
+            }
+            self.messages.push_back(Message::Accept(index));
+        } else {
+            self.messages.push_back(Message::Reject(index));
+        }
+
+        self.wake();
+    }
+
+    /// Notifies the [`Peerset`] that the connection to the given node has been closed, or that
+    /// the attempt to connect to it has failed.
+    ///
+    /// Must not be called after a [`Message::Drop`] for this node.
+    pub fn dropped(&mut self, peer_id: PeerId) {
+        let is_reserved = self.reserved.contains(&peer_id);
+        let node = match self.nodes.get_mut(&peer_id) {
+            Some(n) => n,
+            None => return,
+        };
+
+        match node.connection.take() {
+            Some(Direction::In) if !is_reserved => self.num_in -= 1,
+            Some(Direction::Out) if !is_reserved => self.num_out -= 1,
+            _ => {}
+        }
+
+        node.reputation = node.reputation.saturating_add(DISCONNECT_REPUTATION_CHANGE);
+
+        self.alloc_slots();
+    }
+
+    /// Returns the state of the [`Peerset`], for debugging purposes.
+    pub fn debug_info(&self) -> serde_json::Value {
+        let now = Instant::now();
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(peer_id, node)| {
+                let value = serde_json::json!({
+                    "reputation": node.reputation,
+                    "connected": node.connection.is_some(),
+                    "reserved": self.reserved.contains(peer_id),
+                    "banned": node.banned_until.map_or(false, |until| until > now),
+                });
+                (peer_id.to_base58(), value)
+            })
+            .collect::<serde_json::Map<_, _>>();
+
+        serde_json::json!({
+            "nodes": nodes,
+            "reserved_only": self.reserved_only,
+            "num_in": self.num_in,
+            "num_out": self.num_out,
+            "in_slots": self.in_slots,
+            "out_slots": self.out_slots,
+        })
+    }
+
+    /// Tries to fill the outbound slots and to connect to the reserved nodes.
+    fn alloc_slots(&mut self) {
+        let now = Instant::now();
+
+        for peer_id in &self.reserved {
+            let node = self.nodes.get_mut(peer_id).unwrap();
+            if node.connection.is_none() {
+                node.connection = Some(Direction::Out);
+                self.messages.push_back(Message::Connect(peer_id.clone()));
+            }
+        }
+
+        if !self.reserved_only {
+            while self.num_out < self.out_slots {
+                let reserved = &self.reserved;
+                let candidate = self
+                    .nodes
+                    .iter_mut()
+                    .filter(|(peer_id, node)| {
+                        node.connection.is_none()
+                            && node.banned_until.map_or(true, |until| until <= now)
+                            && !reserved.contains(*peer_id)
+                    })
+                    .max_by_key(|(_, node)| node.reputation);
+
+                let (peer_id, node) = match candidate {
+                    Some(c) => c,
+                    None => break,
+                };
+
+                node.connection = Some(Direction::Out);
+                self.num_out += 1;
+                self.messages.push_back(Message::Connect(peer_id.clone()));
+            }
+        }
+
+        self.wake();
+    }
+
+    /// Brings the reputations closer to zero and lifts the bans that have expired.
+    fn tick(&mut self) {
+        let now = Instant::now();
+
+        for node in self.nodes.values_mut() {
+            let diff = match node.reputation / 50 {
+                0 => node.reputation.signum(),
+                diff => diff,
+            };
+            node.reputation -= diff;
+
+            if node.banned_until.map_or(false, |until| until <= now) {
+                node.banned_until = None;
+            }
+        }
+
+        self.alloc_slots();
+    }
+
+    fn wake(&mut self) {
+        if !self.messages.is_empty() {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Stream for Peerset {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        while let Poll::Ready(()) = self.next_tick.poll_unpin(cx) {
+            self.next_tick = Delay::new(TICK_INTERVAL);
+            self.tick();
+        }
+
+        if let Some(message) = self.messages.pop_front() {
+            return Poll::Ready(Some(message));
+        }
+
+        self.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IncomingIndex, Message, Peerset, PeersetConfig, ReputationChange};
+    use libp2p::PeerId;
+
+    fn drain(peerset: &mut Peerset) -> Vec<Message> {
+        peerset.messages.drain(..).collect()
+    }
+
+    #[test]
+    fn slots_and_reserved() {
+        let bootnodes = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let reserved = PeerId::random();
+
+        let mut peerset = Peerset::new(PeersetConfig {
+            in_peers: 1,
+            out_peers: 2,
+            bootnodes: bootnodes.clone(),
+            reserved_peers: vec![reserved.clone()],
+            reserved_only: false,
+        });
+
+        // The reserved node doesn't occupy any of the two outbound slots.
+        let messages = drain(&mut peerset);
+        assert_eq!(messages.len(), 3);
+        assert!(messages.contains(&Message::Connect(reserved.clone())));
+
+        // Only one inbound slot is available.
+        peerset.incoming(PeerId::random(), IncomingIndex(0));
+        peerset.incoming(PeerId::random(), IncomingIndex(1));
+        peerset.incoming(reserved.clone(), IncomingIndex(2));
+        assert_eq!(
+            drain(&mut peerset),
+            vec![
+                Message::Accept(IncomingIndex(0)),
+                Message::Reject(IncomingIndex(1)),
+                Message::Reject(IncomingIndex(2)),
+            ]
+        );
+
+        // Switching to reserved-only disconnects everyone but the reserved node.
+        peerset.set_reserved_only(true);
+        let messages = drain(&mut peerset);
+        assert_eq!(messages.len(), 3);
+        assert!(!messages.contains(&Message::Drop(reserved)));
+    }
+
+    #[test]
+    fn ban_on_low_reputation() {
+        let peer_id = PeerId::random();
+        let mut peerset = Peerset::new(PeersetConfig {
+            in_peers: 1,
+            out_peers: 1,
+            bootnodes: vec![peer_id.clone()],
+            reserved_peers: Vec::new(),
+            reserved_only: false,
+        });
+        assert_eq!(drain(&mut peerset), vec![Message::Connect(peer_id.clone())]);
+
+        peerset.report_peer(peer_id.clone(), ReputationChange::new(-1000, "test"));
+        assert!(drain(&mut peerset).is_empty());
+
+        peerset.report_peer(
+            peer_id.clone(),
+            ReputationChange::new(i32::min_value(), "test"),
+        );
+        assert_eq!(drain(&mut peerset), vec![Message::Drop(peer_id.clone())]);
+
+        // Banned nodes are neither connected to nor accepted.
+        peerset.incoming(peer_id, IncomingIndex(0));
+        assert_eq!(drain(&mut peerset), vec![Message::Reject(IncomingIndex(0))]);
+    }
+}
@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::traffic;
+
 use futures::{
     channel::{mpsc, oneshot},
     prelude::*,
@@ -40,6 +42,7 @@ use std::{
     task::{Context, Poll},
     time::Duration,
 };
+use wasm_timer::Instant;
 
 pub use libp2p::request_response::{InboundFailure, OutboundFailure, RequestId};
 
@@ -146,14 +149,27 @@ pub struct RequestResponsesBehaviour {
     /// response to send back to the remote.
     pending_responses:
         stream::FuturesUnordered<Pin<Box<dyn Future<Output = RequestProcessingOutcome> + Send>>>,
+
+    /// Requests sent with [`RequestResponsesBehaviour::send_request`] that haven't finished yet,
+    /// with the target, the protocol, and when the request has been sent.
+    pending_requests: HashMap<RequestId, (PeerId, Cow<'static, str>, Instant)>,
+
+    /// Traffic exchanged over each protocol.
+    traffic: HashMap<Cow<'static, str>, traffic::ProtocolTraffic>,
+
+    /// Statistics about the requests exchanged with each connected peer.
+    peers_stats: HashMap<PeerId, traffic::PeerRequestsStats>,
 }
 
 /// Generated by the response builder and waiting to be processed.
 enum RequestProcessingOutcome {
     PendingResponse {
+        peer: PeerId,
         protocol: Cow<'static, str>,
         inner_channel: ResponseChannel<Vec<u8>>,
         response: Vec<u8>,
+        /// When the request has been received.
+        received_at: Instant,
     },
     Busy {
         peer: PeerId,
@@ -197,6 +213,9 @@ impl RequestResponsesBehaviour {
         Ok(Self {
             protocols,
             pending_responses: stream::FuturesUnordered::new(),
+            pending_requests: HashMap::new(),
+            traffic: HashMap::new(),
+            peers_stats: HashMap::new(),
         })
     }
 
@@ -210,15 +229,47 @@ impl RequestResponsesBehaviour {
         protocol: &str,
         request: Vec<u8>,
     ) -> Result<RequestId, SendRequestError> {
-        if let Some((protocol, _)) = self.protocols.get_mut(protocol) {
-            if protocol.is_connected(target) {
-                Ok(protocol.send_request(target, request))
-            } else {
-                Err(SendRequestError::NotConnected)
-            }
-        } else {
-            Err(SendRequestError::UnknownProtocol)
+        let protocol_name = match self.protocols.get_key_value(protocol) {
+            Some((name, _)) => name.clone(),
+            None => return Err(SendRequestError::UnknownProtocol),
+        };
+        let (protocol, _) = self.protocols.get_mut(&*protocol_name).unwrap();
+
+        if !protocol.is_connected(target) {
+            return Err(SendRequestError::NotConnected);
         }
+
+        self.traffic
+            .entry(protocol_name.clone())
+            .or_default()
+            .record_out(request.len());
+        self.peers_stats
+            .entry(target.clone())
+            .or_default()
+            .requests_sent += 1;
+
+        let request_id = protocol.send_request(target, request);
+        self.pending_requests
+            .insert(request_id, (target.clone(), protocol_name, Instant::now()));
+        Ok(request_id)
+    }
+
+    /// Returns the traffic exchanged over each protocol since the behaviour has been created.
+    pub fn traffic(&self) -> impl Iterator<Item = (&str, &traffic::ProtocolTraffic)> {
+        self.traffic
+            .iter()
+            .map(|(name, traffic)| (&**name, traffic))
+    }
+
+    /// Returns the statistics about the requests exchanged with each peer we're connected to.
+    pub fn peers_stats(&self) -> impl Iterator<Item = (&PeerId, &traffic::PeerRequestsStats)> {
+        self.peers_stats.iter()
+    }
+
+    /// Returns the statistics about the requests exchanged with the given peer, or `None` if no
+    /// request has been exchanged with this peer since we have connected to it.
+    pub fn peer_stats(&self, peer_id: &PeerId) -> Option<&traffic::PeerRequestsStats> {
+        self.peers_stats.get(peer_id)
     }
 }
 
@@ -275,6 +326,8 @@ impl NetworkBehaviour for RequestResponsesBehaviour {
         for (p, _) in self.protocols.values_mut() {
             NetworkBehaviour::inject_disconnected(p, peer_id)
         }
+
+        self.peers_stats.remove(peer_id);
     }
 
     fn inject_addr_reach_failure(
@@ -351,12 +404,31 @@ impl NetworkBehaviour for RequestResponsesBehaviour {
             while let Poll::Ready(Some(outcome)) = self.pending_responses.poll_next_unpin(cx) {
                 match outcome {
                     RequestProcessingOutcome::PendingResponse {
+                        peer,
                         protocol,
                         inner_channel,
                         response,
+                        received_at,
                     } => {
-                        if let Some((protocol, _)) = self.protocols.get_mut(&*protocol) {
-                            protocol.send_response(inner_channel, response);
+                        if let Some((behaviour, _)) = self.protocols.get_mut(&*protocol) {
+                            self.traffic
+                                .entry(protocol.clone())
+                                .or_default()
+                                .record_out(response.len());
+                            behaviour.send_response(inner_channel, response);
+
+                            let elapsed = received_at.elapsed();
+                            if let Some(stats) = self.peers_stats.get_mut(&peer) {
+                                stats.requests_answered += 1;
+                                stats.answers_total_latency += elapsed;
+                            }
+
+                            let out = Event::InboundRequest {
+                                peer,
+                                protocol,
+                                outcome: Ok(elapsed),
+                            };
+                            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(out));
                         }
                     }
                     RequestProcessingOutcome::Busy { peer, protocol } => {
@@ -408,6 +480,15 @@ impl NetworkBehaviour for RequestResponsesBehaviour {
                         peer,
                         message: RequestResponseMessage::Request { request, channel },
                     } => {
+                        self.traffic
+                            .entry(protocol.clone())
+                            .or_default()
+                            .record_in(request.len());
+                        self.peers_stats
+                            .entry(peer.clone())
+                            .or_default()
+                            .requests_received += 1;
+
                         let (tx, rx) = oneshot::channel();
 
                         // Submit the request to the "response builder" passed by the user at
@@ -423,14 +504,17 @@ impl NetworkBehaviour for RequestResponsesBehaviour {
                         }
 
                         let protocol = protocol.clone();
+                        let received_at = Instant::now();
                         self.pending_responses.push(Box::pin(async move {
                             // The `tx` created above can be dropped if we are not capable of
                             // processing this request, which is reflected as a "Busy" error.
                             if let Ok(response) = rx.await {
                                 RequestProcessingOutcome::PendingResponse {
+                                    peer,
                                     protocol,
                                     inner_channel: channel,
                                     response,
+                                    received_at,
                                 }
                             } else {
                                 RequestProcessingOutcome::Busy { peer, protocol }
@@ -451,6 +535,13 @@ impl NetworkBehaviour for RequestResponsesBehaviour {
                             },
                         ..
                     } => {
+                        on_request_finished(
+                            &mut self.pending_requests,
+                            &mut self.traffic,
+                            &mut self.peers_stats,
+                            &request_id,
+                            Some(response.len()),
+                        );
                         let out = Event::OutboundFinished {
                             request_id,
                             outcome: Ok(response),
@@ -462,6 +553,13 @@ impl NetworkBehaviour for RequestResponsesBehaviour {
                     RequestResponseEvent::OutboundFailure {
                         request_id, error, ..
                     } => {
+                        on_request_finished(
+                            &mut self.pending_requests,
+                            &mut self.traffic,
+                            &mut self.peers_stats,
+                            &request_id,
+                            None,
+                        );
                         let out = Event::OutboundFinished {
                             request_id,
                             outcome: Err(error),
@@ -486,6 +584,37 @@ impl NetworkBehaviour for RequestResponsesBehaviour {
     }
 }
 
+/// Updates the statistics after a request sent with [`RequestResponsesBehaviour::send_request`]
+/// has finished.
+///
+/// `response_len` is `None` if the request has failed.
+fn on_request_finished(
+    pending_requests: &mut HashMap<RequestId, (PeerId, Cow<'static, str>, Instant)>,
+    traffic: &mut HashMap<Cow<'static, str>, traffic::ProtocolTraffic>,
+    peers_stats: &mut HashMap<PeerId, traffic::PeerRequestsStats>,
+    request_id: &RequestId,
+    response_len: Option<usize>,
+) {
+    let (peer, protocol, sent_at) = match pending_requests.remove(request_id) {
+        Some(r) => r,
+        None => return,
+    };
+
+    if let Some(response_len) = response_len {
+        traffic.entry(protocol).or_default().record_in(response_len);
+    }
+
+    // The statistics of the peer have been removed if it has disconnected in the meanwhile.
+    if let Some(stats) = peers_stats.get_mut(&peer) {
+        if response_len.is_some() {
+            stats.requests_succeeded += 1;
+            stats.requests_total_latency += sent_at.elapsed();
+        } else {
+            stats.requests_failed += 1;
+        }
+    }
+}
+
 /// Error when registering a protocol.
 #[derive(Debug, derive_more::Display, derive_more::Error)]
 pub enum RegisterError {
@@ -20,6 +20,9 @@
 
 pub mod v1 {
     include!(concat!(env!("OUT_DIR"), "/api.v1.rs"));
+    pub mod authority_discovery {
+        include!(concat!(env!("OUT_DIR"), "/api.v1.authority_discovery.rs"));
+    }
     pub mod finality {
         include!(concat!(env!("OUT_DIR"), "/api.v1.finality.rs"));
     }
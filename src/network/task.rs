@@ -91,7 +91,7 @@ async fn run_networking_task(
         futures::select! {
             ev = worker.next_event().fuse() => {
                 match ev {
-                    network::Event::BlockAnnounce(header) => {
+                    network::Event::BlockAnnounce { header, .. } => {
                         // TODO:
                         /*// TOOD: don't unwrap
                         let decoded_header =
@@ -107,11 +107,17 @@ async fn run_networking_task(
                     },
                     network::Event::BlocksRequestFinished { id, result } => {
                         let sender = pending_blocks_requests.remove(&id).unwrap();
-                        let _ = sender.send(result);
-                    }
-                    network::Event::CallRequestFinished { id, result } => {
-                        todo!()
+                        let _ = sender.send(result.map_err(|_| ()));
                     }
+                    // This task only ever starts blocks requests.
+                    network::Event::CallRequestFinished { .. }
+                    | network::Event::StorageProofRequestFinished { .. }
+                    | network::Event::FinalityProofRequestFinished { .. } => {}
+                    network::Event::PeerStatus { .. } => {}
+                    network::Event::Transactions { .. } => {}
+                    network::Event::FinalityProofRequest { .. } => {}
+                    network::Event::BlocksRequest { .. } => {}
+                    network::Event::AuthorityAddresses { .. } => {}
                     network::Event::Connected(peer_id) => {
                         num_connections_store.fetch_add(1, atomic::Ordering::Relaxed);
                     },
@@ -0,0 +1,608 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Test harness that runs multiple [`Network`]s within the same process, connected to each
+//! other through the in-memory libp2p transport.
+
+#![cfg(test)]
+
+use super::{
+    BlockData, BlocksRequestConfig, BlocksRequestConfigStart, BlocksRequestDirection,
+    BlocksRequestError, BlocksRequestFields, Config, Event, Extrinsic, Multiaddr, Network, PeerId,
+    Role, ScaleBlockHeader,
+};
+use crate::{
+    chain::{
+        blocks_tree::tests::{aura_header, config},
+        sync::headers_optimistic,
+    },
+    header,
+};
+
+use core::{
+    convert::TryFrom as _,
+    num::{NonZeroU32, NonZeroU64},
+    time::Duration,
+};
+use futures::prelude::*;
+use hashbrown::HashMap;
+use primitive_types::H256;
+
+/// Maximum time that [`TestNetwork::wait_for`] waits for an event before making the test fail.
+const EVENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Multiple nodes connected through the memory transport.
+///
+/// The node at index 0 is a block server: it answers blocks requests using a chain generated
+/// when the harness is created. All the other nodes know its address and connect to it.
+struct TestNetwork {
+    nodes: Vec<Network>,
+    /// SCALE-encoded headers of the chain served by the first node, indexed by block number.
+    chain: Vec<Vec<u8>>,
+}
+
+impl TestNetwork {
+    /// Starts `num_nodes` nodes. The first node serves a chain of `chain_length` blocks, not
+    /// including the genesis block.
+    async fn new(num_nodes: usize, chain_length: u64) -> Self {
+        let chain = generate_chain(chain_length);
+        let genesis_hash = header::hash_from_scale_encoded_header(&chain[0]);
+
+        let mut nodes = Vec::with_capacity(num_nodes);
+        let mut server_address = None;
+
+        for node_index in 0..num_nodes {
+            let listen_address: Multiaddr = format!("/memory/{}", rand::random::<u64>())
+                .parse()
+                .unwrap();
+
+            let network = Network::start(Config {
+                node_key: [u8::try_from(node_index + 1).unwrap(); 32],
+                authority_discovery_key: None,
+                listen_addresses: vec![listen_address.clone()],
+                public_addresses: Vec::new(),
+                known_addresses: server_address.iter().cloned().collect(),
+                address_book: Vec::new(),
+                reserved_peers: Vec::new(),
+                reserved_only: false,
+                in_peers: 25,
+                out_peers: 25,
+                role: if node_index == 0 {
+                    Role::Full
+                } else {
+                    Role::Light
+                },
+                answer_finality_proof_requests: false,
+                answer_blocks_requests: node_index == 0,
+                memory_only: true,
+                chain_spec_protocol_id: b"test".to_vec(),
+                tasks_executor: Box::new(|fut| {
+                    async_std::task::spawn(fut);
+                }),
+                local_genesis_hash: genesis_hash,
+                wasm_external_transport: None,
+            })
+            .await
+            .unwrap();
+
+            if node_index == 0 {
+                server_address = Some((network.local_peer_id().clone(), listen_address));
+            }
+
+            nodes.push(network);
+        }
+
+        TestNetwork { nodes, chain }
+    }
+
+    /// Returns the [`PeerId`] of the block server.
+    fn server_peer_id(&self) -> PeerId {
+        self.nodes[0].local_peer_id().clone()
+    }
+
+    /// Drives all the nodes until one of them generates an event. Returns the index of that node
+    /// and the event.
+    ///
+    /// The blocks requests sent to the block server are answered automatically.
+    async fn next_event(&mut self) -> (usize, Event) {
+        loop {
+            let (event, node_index, _) = future::select_all(
+                self.nodes
+                    .iter_mut()
+                    .map(|network| Box::pin(network.next_event())),
+            )
+            .await;
+
+            match event {
+                Event::BlocksRequest { config, responder } => {
+                    debug_assert_eq!(node_index, 0);
+                    responder.respond(self.answer_blocks_request(&config));
+                }
+                event => return (node_index, event),
+            }
+        }
+    }
+
+    /// Drives all the nodes until the node at index `node_index` generates an event for which
+    /// `filter` returns `Some`. The events of the other nodes are discarded.
+    ///
+    /// # Panic
+    ///
+    /// Panics if no such event is generated within [`EVENT_TIMEOUT`].
+    ///
+    async fn wait_for<T>(
+        &mut self,
+        node_index: usize,
+        mut filter: impl FnMut(Event) -> Option<T>,
+    ) -> T {
+        let wait = async {
+            loop {
+                let (index, event) = self.next_event().await;
+                if index != node_index {
+                    continue;
+                }
+                if let Some(value) = filter(event) {
+                    return value;
+                }
+            }
+        };
+
+        match async_std::future::timeout(EVENT_TIMEOUT, wait).await {
+            Ok(value) => value,
+            Err(_) => panic!("Timeout while waiting for an event of node {}", node_index),
+        }
+    }
+
+    /// Builds the response of the block server to the given request.
+    fn answer_blocks_request(&self, config: &BlocksRequestConfig) -> Vec<BlockData> {
+        let start = match &config.start {
+            BlocksRequestConfigStart::Hash(hash) => match self
+                .chain
+                .iter()
+                .position(|h| header::hash_from_scale_encoded_header(h) == hash.0)
+            {
+                Some(n) => n,
+                None => return Vec::new(),
+            },
+            BlocksRequestConfigStart::Number(n) => usize::try_from(n.get()).unwrap(),
+        };
+
+        if start >= self.chain.len() {
+            return Vec::new();
+        }
+
+        let count = usize::try_from(config.desired_count).unwrap();
+        let numbers = match config.direction {
+            BlocksRequestDirection::Ascending => {
+                (start..self.chain.len()).take(count).collect::<Vec<_>>()
+            }
+            BlocksRequestDirection::Descending => (0..=start).rev().take(count).collect(),
+        };
+
+        numbers
+            .into_iter()
+            .map(|n| BlockData {
+                hash: H256(header::hash_from_scale_encoded_header(&self.chain[n])),
+                header: if config.fields.header {
+                    Some(ScaleBlockHeader(self.chain[n].clone()))
+                } else {
+                    None
+                },
+                body: if config.fields.body {
+                    Some(Vec::new())
+                } else {
+                    None
+                },
+                justification: None,
+            })
+            .collect()
+    }
+}
+
+/// Generates a chain of `length` blocks plus the genesis block. Returns the SCALE-encoded
+/// headers, indexed by block number.
+///
+/// The chain is the Aura chain of [`config`], and can therefore be verified by a sync state
+/// machine using this configuration.
+fn generate_chain(length: u64) -> Vec<Vec<u8>> {
+    let genesis = config()
+        .chain_information_config
+        .chain_information
+        .finalized_block_header;
+    let mut chain = vec![genesis.scale_encoding().fold(Vec::new(), |mut a, b| {
+        a.extend_from_slice(b.as_ref());
+        a
+    })];
+
+    for number in 1..=length {
+        let parent_hash = header::hash_from_scale_encoded_header(chain.last().unwrap());
+        chain.push(aura_header(parent_hash, number, number));
+    }
+
+    chain
+}
+
+#[test]
+fn blocks_request() {
+    futures::executor::block_on(async {
+        let mut network = TestNetwork::new(2, 10).await;
+        let server = network.server_peer_id();
+
+        network
+            .wait_for(1, |event| match event {
+                Event::Connected(peer_id) if peer_id == server => Some(()),
+                _ => None,
+            })
+            .await;
+
+        let request_id = network.nodes[1]
+            .start_block_request(BlocksRequestConfig {
+                start: BlocksRequestConfigStart::Number(NonZeroU64::new(3).unwrap()),
+                peer_id: server,
+                desired_count: 4,
+                direction: BlocksRequestDirection::Ascending,
+                fields: BlocksRequestFields {
+                    header: true,
+                    body: false,
+                    justification: false,
+                },
+            })
+            .await
+            .unwrap();
+
+        let blocks = network
+            .wait_for(1, |event| match event {
+                Event::BlocksRequestFinished { id, result } if id == request_id => {
+                    Some(result.unwrap())
+                }
+                _ => None,
+            })
+            .await;
+
+        assert_eq!(blocks.len(), 4);
+        for (n, block) in blocks.iter().enumerate() {
+            assert_eq!(block.header.as_ref().unwrap().0, network.chain[3 + n]);
+        }
+    });
+}
+
+#[test]
+fn traffic_counters() {
+    futures::executor::block_on(async {
+        let mut network = TestNetwork::new(2, 10).await;
+        let client = network.nodes[1].local_peer_id().clone();
+        let server = network.server_peer_id();
+
+        network
+            .wait_for(1, |event| match event {
+                Event::Connected(peer_id) if peer_id == server => Some(()),
+                _ => None,
+            })
+            .await;
+
+        let request_id = network.nodes[1]
+            .start_block_request(BlocksRequestConfig {
+                start: BlocksRequestConfigStart::Number(NonZeroU64::new(1).unwrap()),
+                peer_id: server.clone(),
+                desired_count: 10,
+                direction: BlocksRequestDirection::Ascending,
+                fields: BlocksRequestFields {
+                    header: true,
+                    body: false,
+                    justification: false,
+                },
+            })
+            .await
+            .unwrap();
+        network
+            .wait_for(1, |event| match event {
+                Event::BlocksRequestFinished { id, result } if id == request_id => {
+                    Some(result.unwrap())
+                }
+                _ => None,
+            })
+            .await;
+
+        // The request and response are accounted for on both sides.
+        let sync_traffic = |node: &Network| {
+            node.protocols_traffic()
+                .find(|(name, _)| name == "/test/sync/2")
+                .map(|(_, traffic)| traffic.clone())
+                .unwrap()
+        };
+        let client_traffic = sync_traffic(&network.nodes[1]);
+        let server_traffic = sync_traffic(&network.nodes[0]);
+        assert_eq!(client_traffic.messages_out, 1);
+        assert_eq!(client_traffic.messages_in, 1);
+        assert_eq!(server_traffic.messages_in, 1);
+        assert_eq!(server_traffic.messages_out, 1);
+        assert_eq!(client_traffic.bytes_out, server_traffic.bytes_in);
+        assert_eq!(client_traffic.bytes_in, server_traffic.bytes_out);
+        // The response contains the ten headers.
+        let headers_len = network.chain[1..].iter().map(|h| h.len()).sum::<usize>();
+        assert!(client_traffic.bytes_in >= u64::try_from(headers_len).unwrap());
+
+        let total = network.nodes[1].total_traffic();
+        assert!(total.bytes_in >= client_traffic.bytes_in);
+        assert!(total.messages_out >= client_traffic.messages_out);
+
+        let (_, client_stats) = network.nodes[1]
+            .peers_requests_stats()
+            .find(|(peer_id, _)| **peer_id == server)
+            .unwrap();
+        assert_eq!(client_stats.requests_sent, 1);
+        assert_eq!(client_stats.requests_succeeded, 1);
+        assert_eq!(client_stats.requests_failed, 0);
+        assert!(client_stats.average_request_latency().is_some());
+
+        let (_, server_stats) = network.nodes[0]
+            .peers_requests_stats()
+            .find(|(peer_id, _)| **peer_id == client)
+            .unwrap();
+        assert_eq!(server_stats.requests_received, 1);
+        assert_eq!(server_stats.requests_answered, 1);
+
+        // The same information is reported in the network state.
+        let state = network.nodes[1].network_state();
+        assert_eq!(state.protocols_traffic["/test/sync/2"], client_traffic);
+        assert_eq!(state.total_bytes_inbound, total.bytes_in);
+        assert_eq!(
+            state.connected_peers[&server.to_base58()].requests_stats,
+            Some(client_stats.clone())
+        );
+
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["protocolsTraffic"]["/test/sync/2"]["messagesIn"], 1);
+        assert_eq!(
+            json["connectedPeers"][server.to_base58()]["requestsStats"]["requestsSucceeded"],
+            1
+        );
+    });
+}
+
+#[test]
+fn status_and_block_announce() {
+    futures::executor::block_on(async {
+        let mut network = TestNetwork::new(2, 10).await;
+        let server = network.server_peer_id();
+        let genesis_hash = header::hash_from_scale_encoded_header(&network.chain[0]);
+
+        // The best block of the server is the genesis block until something is announced.
+        let (best_number, best_hash) = network
+            .wait_for(1, |event| match event {
+                Event::PeerStatus {
+                    peer_id,
+                    best_number,
+                    best_hash,
+                } if peer_id == server => Some((best_number, best_hash)),
+                _ => None,
+            })
+            .await;
+        assert_eq!(best_number, 0);
+        assert_eq!(best_hash, genesis_hash);
+
+        let announced = network.chain[10].clone();
+        network.nodes[0].announce_block(&announced, true).unwrap();
+
+        let (header, is_best) = network
+            .wait_for(1, |event| match event {
+                Event::BlockAnnounce {
+                    peer_id,
+                    header,
+                    is_best,
+                } if peer_id == server => Some((header, is_best)),
+                _ => None,
+            })
+            .await;
+        assert_eq!(header.0, announced);
+        assert!(is_best);
+    });
+}
+
+#[test]
+fn transactions_propagation() {
+    futures::executor::block_on(async {
+        let mut network = TestNetwork::new(2, 0).await;
+        let client = network.nodes[1].local_peer_id().clone();
+        let server = network.server_peer_id();
+
+        network
+            .wait_for(1, |event| match event {
+                Event::PeerStatus { peer_id, .. } if peer_id == server => Some(()),
+                _ => None,
+            })
+            .await;
+
+        let transaction = Extrinsic(vec![1, 2, 3, 4]);
+        network.nodes[1].propagate_transactions(&[transaction.clone()]);
+
+        let transactions = network
+            .wait_for(0, |event| match event {
+                Event::Transactions {
+                    peer_id,
+                    transactions,
+                } if peer_id == client => Some(transactions),
+                _ => None,
+            })
+            .await;
+        assert_eq!(transactions, vec![transaction]);
+    });
+}
+
+#[test]
+fn network_state() {
+    futures::executor::block_on(async {
+        let mut network = TestNetwork::new(2, 0).await;
+        let server = network.server_peer_id();
+
+        network
+            .wait_for(1, |event| match event {
+                Event::PeerStatus { peer_id, .. } if peer_id == server => Some(()),
+                _ => None,
+            })
+            .await;
+
+        let state = network.nodes[1].network_state();
+        assert_eq!(state.peer_id, network.nodes[1].local_peer_id().to_base58());
+        assert_eq!(state.listened_addresses.len(), 1);
+
+        let peer = &state.connected_peers[&server.to_base58()];
+        assert!(peer.open);
+        assert_eq!(peer.best_number, Some(0));
+        assert_eq!(peer.roles.as_deref(), Some("FULL"));
+        assert_eq!(
+            peer.best_hash.unwrap().0,
+            header::hash_from_scale_encoded_header(&network.chain[0])
+        );
+
+        let json = serde_json::to_value(&state).unwrap();
+        assert!(json["connectedPeers"][server.to_base58()]["endpoint"]["dialing"].is_string());
+        assert!(json["notConnectedPeers"].is_object());
+    });
+}
+
+#[test]
+fn headers_sync_catch_up() {
+    futures::executor::block_on(async {
+        let mut network = TestNetwork::new(2, 20).await;
+        let server = network.server_peer_id();
+        let best_hash = header::hash_from_scale_encoded_header(&network.chain[20]);
+
+        let mut sync = headers_optimistic::OptimisticHeadersSync::<_, PeerId>::new(
+            headers_optimistic::Config {
+                chain_information_config: config().chain_information_config,
+                sources_capacity: 4,
+                blocks_request_granularity: NonZeroU32::new(8).unwrap(),
+                download_ahead_blocks: 16,
+                source_selection_randomness_seed: 0,
+                generate_events: false,
+            },
+        );
+
+        // The server is added as a source once it has reported its status, and then announces
+        // its best block.
+        let best_number = network
+            .wait_for(1, |event| match event {
+                Event::PeerStatus {
+                    peer_id,
+                    best_number,
+                    ..
+                } if peer_id == server => Some(best_number),
+                _ => None,
+            })
+            .await;
+        let source_id = sync.add_source(server.clone());
+        sync.set_source_best_block(source_id, best_number);
+
+        // The best block of the server is still the genesis block, and nothing is requested.
+        assert!(sync.next_request_action().is_none());
+
+        network.nodes[0]
+            .announce_block(&network.chain[20], true)
+            .unwrap();
+        let announced = network
+            .wait_for(1, |event| match event {
+                Event::BlockAnnounce {
+                    peer_id,
+                    header,
+                    is_best: true,
+                } if peer_id == server => Some(header),
+                _ => None,
+            })
+            .await;
+        sync.set_source_best_block(source_id, header::decode(&announced.0).unwrap().number);
+
+        let mut requests = HashMap::<_, _, fnv::FnvBuildHasher>::default();
+        let mut sync_best_hash = header::hash_from_scale_encoded_header(&network.chain[0]);
+        while sync_best_hash != best_hash {
+            while let Some(action) = sync.next_request_action() {
+                match action {
+                    headers_optimistic::RequestAction::Start {
+                        source,
+                        block_height,
+                        num_blocks,
+                        start,
+                        ..
+                    } => {
+                        let request_id = network.nodes[1]
+                            .start_block_request(BlocksRequestConfig {
+                                start: BlocksRequestConfigStart::Number(block_height),
+                                peer_id: source.clone(),
+                                desired_count: num_blocks.get(),
+                                direction: BlocksRequestDirection::Ascending,
+                                fields: BlocksRequestFields {
+                                    header: true,
+                                    body: false,
+                                    justification: true,
+                                },
+                            })
+                            .await
+                            .unwrap();
+                        requests.insert(request_id, start.start(()));
+                    }
+                    headers_optimistic::RequestAction::Cancel { .. } => {}
+                }
+            }
+
+            let (request_id, result) = network
+                .wait_for(1, |event| match event {
+                    Event::BlocksRequestFinished { id, result } => Some((id, result)),
+                    _ => None,
+                })
+                .await;
+            let sync_request_id = match requests.remove(&request_id) {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let result = result
+                .map(|blocks| {
+                    blocks
+                        .into_iter()
+                        .map(|block| headers_optimistic::RequestSuccessBlock {
+                            scale_encoded_header: block.header.unwrap().0,
+                            scale_encoded_justification: block.justification,
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .map_err(|err| match err {
+                    BlocksRequestError::Timeout => headers_optimistic::RequestFail::Timeout,
+                    BlocksRequestError::Unavailable => {
+                        headers_optimistic::RequestFail::BlocksUnavailable
+                    }
+                    BlocksRequestError::InvalidResponse => {
+                        headers_optimistic::RequestFail::InvalidResponse
+                    }
+                });
+            let _ = sync.finish_request(sync_request_id, result.map(|v| v.into_iter()));
+
+            loop {
+                match sync.process_one(Duration::from_secs(1 << 32)) {
+                    headers_optimistic::ProcessOneOutcome::Idle => break,
+                    headers_optimistic::ProcessOneOutcome::Updated {
+                        best_block_hash, ..
+                    } => sync_best_hash = best_block_hash,
+                    headers_optimistic::ProcessOneOutcome::Reset { reason, .. } => {
+                        panic!("{:?}", reason)
+                    }
+                }
+            }
+        }
+
+        assert_eq!(sync.as_chain_information().finalized_block_header.number, 0);
+    });
+}
@@ -0,0 +1,159 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Counters of the traffic exchanged with the rest of the network.
+//!
+//! Contrary to the bandwidth reported by the transport, these counters only account for the
+//! payload of the messages, and not for the overhead of encryption and multiplexing.
+
+use core::{convert::TryFrom as _, time::Duration};
+
+/// Number of bytes and messages exchanged over a single protocol since the network has
+/// started.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtocolTraffic {
+    /// Number of bytes received from remotes.
+    pub bytes_in: u64,
+    /// Number of bytes sent to remotes.
+    pub bytes_out: u64,
+    /// Number of messages, requests or responses received from remotes.
+    pub messages_in: u64,
+    /// Number of messages, requests or responses sent to remotes.
+    pub messages_out: u64,
+}
+
+impl ProtocolTraffic {
+    /// Accounts for a message of the given size received from a remote.
+    pub(crate) fn record_in(&mut self, len: usize) {
+        self.bytes_in = self
+            .bytes_in
+            .saturating_add(u64::try_from(len).unwrap_or(u64::max_value()));
+        self.messages_in = self.messages_in.saturating_add(1);
+    }
+
+    /// Accounts for a message of the given size sent to a remote.
+    pub(crate) fn record_out(&mut self, len: usize) {
+        self.bytes_out = self
+            .bytes_out
+            .saturating_add(u64::try_from(len).unwrap_or(u64::max_value()));
+        self.messages_out = self.messages_out.saturating_add(1);
+    }
+
+    /// Adds the counters of `other` to the ones of `self`.
+    pub(crate) fn add(&mut self, other: &ProtocolTraffic) {
+        self.bytes_in = self.bytes_in.saturating_add(other.bytes_in);
+        self.bytes_out = self.bytes_out.saturating_add(other.bytes_out);
+        self.messages_in = self.messages_in.saturating_add(other.messages_in);
+        self.messages_out = self.messages_out.saturating_add(other.messages_out);
+    }
+}
+
+/// Statistics about the requests exchanged with a single peer since we have connected to it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerRequestsStats {
+    /// Number of requests sent to the peer, including the ones still in progress.
+    pub requests_sent: u64,
+    /// Number of requests sent to the peer that have received a response.
+    pub requests_succeeded: u64,
+    /// Number of requests sent to the peer that have failed or timed out.
+    pub requests_failed: u64,
+    /// Sum of the time it took to receive the responses of the successful requests sent to the
+    /// peer.
+    pub requests_total_latency: Duration,
+    /// Number of requests received from the peer.
+    pub requests_received: u64,
+    /// Number of requests received from the peer that we have answered.
+    pub requests_answered: u64,
+    /// Sum of the time it took for us to answer the requests of the peer.
+    pub answers_total_latency: Duration,
+}
+
+impl PeerRequestsStats {
+    /// Returns the average time it took to receive the responses of the successful requests
+    /// sent to the peer, or `None` if no request has succeeded.
+    pub fn average_request_latency(&self) -> Option<Duration> {
+        let num_succeeded = u32::try_from(self.requests_succeeded)
+            .ok()
+            .filter(|n| *n != 0)?;
+        Some(self.requests_total_latency / num_succeeded)
+    }
+
+    /// Returns the average time it took for us to answer the requests of the peer, or `None` if
+    /// we haven't answered any.
+    pub fn average_answer_latency(&self) -> Option<Duration> {
+        let num_answered = u32::try_from(self.requests_answered)
+            .ok()
+            .filter(|n| *n != 0)?;
+        Some(self.answers_total_latency / num_answered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PeerRequestsStats, ProtocolTraffic};
+    use core::time::Duration;
+
+    #[test]
+    fn counters_saturate() {
+        let mut traffic = ProtocolTraffic::default();
+        traffic.record_in(10);
+        traffic.record_in(5);
+        traffic.record_out(7);
+        assert_eq!(
+            traffic,
+            ProtocolTraffic {
+                bytes_in: 15,
+                bytes_out: 7,
+                messages_in: 2,
+                messages_out: 1,
+            }
+        );
+
+        let mut total = ProtocolTraffic {
+            bytes_in: u64::max_value() - 1,
+            ..Default::default()
+        };
+        total.add(&traffic);
+        assert_eq!(total.bytes_in, u64::max_value());
+        assert_eq!(total.bytes_out, 7);
+        assert_eq!(total.messages_in, 2);
+    }
+
+    #[test]
+    fn average_latencies() {
+        let mut stats = PeerRequestsStats::default();
+        assert_eq!(stats.average_request_latency(), None);
+        assert_eq!(stats.average_answer_latency(), None);
+
+        stats.requests_sent = 3;
+        stats.requests_succeeded = 2;
+        stats.requests_failed = 1;
+        stats.requests_total_latency = Duration::from_millis(300);
+        stats.requests_answered = 4;
+        stats.answers_total_latency = Duration::from_millis(100);
+        assert_eq!(
+            stats.average_request_latency(),
+            Some(Duration::from_millis(150))
+        );
+        assert_eq!(
+            stats.average_answer_latency(),
+            Some(Duration::from_millis(25))
+        );
+    }
+}
@@ -0,0 +1,231 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Book-keeping of the transactions gossiped with each peer.
+//!
+//! Each peer is associated with the hashes of the transactions that it is known to have, either
+//! because it has sent them to us or because we have sent them to it. These transactions aren't
+//! sent to that peer again, and aren't reported again if that peer sends them again.
+//!
+//! The number of received transactions waiting to be processed by the user is bounded. The
+//! transactions received while this limit is reached are dropped and counted. They aren't
+//! considered as known by the peer that has sent them, so that they are accepted again if that
+//! peer sends them later.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom as _;
+use hashbrown::HashMap;
+use libp2p::PeerId;
+
+/// Maximum number of transaction hashes to remember for each peer.
+const MAX_KNOWN_TRANSACTIONS: usize = 4096;
+
+/// See the module-level documentation.
+pub(super) struct TransactionsGossip {
+    /// Hashes of the transactions that each peer with an open substream is known to have.
+    peers_known_transactions: HashMap<PeerId, lru::LruCache<[u8; 32], ()>, fnv::FnvBuildHasher>,
+
+    /// Maximum value of [`TransactionsGossip::num_pending`].
+    max_pending: usize,
+
+    /// Number of lists of received transactions that have been returned by
+    /// [`TransactionsGossip::on_received`] and not yet reported as processed.
+    num_pending: usize,
+
+    /// Number of received transactions that have been dropped because of
+    /// [`TransactionsGossip::max_pending`].
+    num_dropped: u64,
+}
+
+impl TransactionsGossip {
+    /// Initializes a new [`TransactionsGossip`]. At most `max_pending` lists of received
+    /// transactions can be waiting to be processed at any given time.
+    pub(super) fn new(max_pending: usize) -> Self {
+        TransactionsGossip {
+            peers_known_transactions: Default::default(),
+            max_pending,
+            num_pending: 0,
+            num_dropped: 0,
+        }
+    }
+
+    /// Must be called when a substream with a peer has been opened.
+    pub(super) fn peer_connected(&mut self, peer_id: PeerId) {
+        self.peers_known_transactions
+            .insert(peer_id, lru::LruCache::new(MAX_KNOWN_TRANSACTIONS));
+    }
+
+    /// Must be called when the substream with a peer has been closed.
+    pub(super) fn peer_disconnected(&mut self, peer_id: &PeerId) {
+        self.peers_known_transactions.remove(peer_id);
+    }
+
+    /// Returns the transactions amongst `transactions` that must be sent to the given peer, and
+    /// marks them as known by this peer. Returns an empty list if the peer isn't connected.
+    pub(super) fn transactions_to_send<'a>(
+        &mut self,
+        peer_id: &PeerId,
+        transactions: impl Iterator<Item = &'a [u8]>,
+    ) -> Vec<Vec<u8>> {
+        let known = match self.peers_known_transactions.get_mut(peer_id) {
+            Some(k) => k,
+            None => return Vec::new(),
+        };
+
+        transactions
+            .filter(|tx| {
+                let hash = blake2_hash(tx);
+                let is_new = !known.contains(&hash);
+                known.put(hash, ());
+                is_new
+            })
+            .map(|tx| tx.to_vec())
+            .collect()
+    }
+
+    /// Must be called when a peer has sent us transactions. Returns the ones that must be
+    /// reported to the user, or `None` if there isn't any.
+    ///
+    /// If `Some` is returned, [`TransactionsGossip::on_processed`] must be called later, once the
+    /// user has been given these transactions.
+    pub(super) fn on_received(
+        &mut self,
+        peer_id: &PeerId,
+        transactions: Vec<Vec<u8>>,
+    ) -> Option<Vec<Vec<u8>>> {
+        let known = self.peers_known_transactions.get_mut(peer_id)?;
+
+        let mut new_transactions = Vec::<([u8; 32], Vec<u8>)>::new();
+        for tx in transactions {
+            let hash = blake2_hash(&tx);
+            if !known.contains(&hash) && new_transactions.iter().all(|(h, _)| *h != hash) {
+                new_transactions.push((hash, tx));
+            }
+        }
+
+        if new_transactions.is_empty() {
+            return None;
+        }
+
+        if self.num_pending >= self.max_pending {
+            self.num_dropped = self
+                .num_dropped
+                .saturating_add(u64::try_from(new_transactions.len()).unwrap());
+            return None;
+        }
+
+        self.num_pending += 1;
+        Some(
+            new_transactions
+                .into_iter()
+                .map(|(hash, tx)| {
+                    known.put(hash, ());
+                    tx
+                })
+                .collect(),
+        )
+    }
+
+    /// Must be called when a list of transactions returned by
+    /// [`TransactionsGossip::on_received`] has been given to the user.
+    pub(super) fn on_processed(&mut self) {
+        debug_assert!(self.num_pending >= 1);
+        self.num_pending -= 1;
+    }
+
+    /// Returns the number of received transactions that have been dropped because too many
+    /// transactions were waiting to be processed.
+    pub(super) fn num_dropped(&self) -> u64 {
+        self.num_dropped
+    }
+}
+
+/// Returns the hash of the given SCALE-encoded extrinsic.
+fn blake2_hash(scale_encoded_extrinsic: &[u8]) -> [u8; 32] {
+    let mut out = [0; 32];
+    out.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], scale_encoded_extrinsic).as_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransactionsGossip;
+    use libp2p::PeerId;
+
+    #[test]
+    fn duplicates_not_reported() {
+        let mut gossip = TransactionsGossip::new(16);
+        let (peer1, peer2) = (PeerId::random(), PeerId::random());
+        gossip.peer_connected(peer1.clone());
+        gossip.peer_connected(peer2.clone());
+
+        assert_eq!(
+            gossip.on_received(&peer1, vec![vec![1], vec![2]]),
+            Some(vec![vec![1], vec![2]])
+        );
+        assert_eq!(gossip.on_received(&peer1, vec![vec![1]]), None);
+        assert_eq!(
+            gossip.on_received(&peer1, vec![vec![2], vec![3], vec![3]]),
+            Some(vec![vec![3]])
+        );
+
+        // Known transactions are tracked per peer.
+        assert_eq!(
+            gossip.on_received(&peer2, vec![vec![1]]),
+            Some(vec![vec![1]])
+        );
+
+        // Transactions known by a peer aren't sent back to it.
+        let to_send = [&[1][..], &[4][..]];
+        assert_eq!(
+            gossip.transactions_to_send(&peer1, to_send.iter().cloned()),
+            vec![vec![4]]
+        );
+        assert!(gossip
+            .transactions_to_send(&peer1, to_send.iter().cloned())
+            .is_empty());
+        assert_eq!(gossip.on_received(&peer1, vec![vec![4]]), None);
+
+        // Transactions from peers without a substream are ignored.
+        gossip.peer_disconnected(&peer2);
+        assert_eq!(gossip.on_received(&peer2, vec![vec![5]]), None);
+        assert!(gossip
+            .transactions_to_send(&peer2, to_send.iter().cloned())
+            .is_empty());
+    }
+
+    #[test]
+    fn pending_bounded() {
+        let mut gossip = TransactionsGossip::new(2);
+        let peer = PeerId::random();
+        gossip.peer_connected(peer.clone());
+
+        assert!(gossip.on_received(&peer, vec![vec![1]]).is_some());
+        assert!(gossip.on_received(&peer, vec![vec![2]]).is_some());
+        assert_eq!(gossip.on_received(&peer, vec![vec![3], vec![4]]), None);
+        assert_eq!(gossip.num_dropped(), 2);
+
+        // Dropped transactions are accepted again once there is room.
+        gossip.on_processed();
+        assert_eq!(
+            gossip.on_received(&peer, vec![vec![3], vec![4]]),
+            Some(vec![vec![3], vec![4]])
+        );
+        assert_eq!(gossip.on_received(&peer, vec![vec![5]]), None);
+        assert_eq!(gossip.num_dropped(), 3);
+    }
+}
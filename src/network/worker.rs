@@ -15,9 +15,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{behaviour, legacy_message, request_responses, schema, transport};
+use super::{
+    address_book, behaviour, legacy_message, network_state, peerset, request_responses, schema,
+    traffic, transport,
+};
 
-use alloc::boxed::Box;
+use alloc::{borrow::Cow, boxed::Box, sync::Arc};
 use core::{
     future::Future,
     num::{NonZeroU64, NonZeroUsize},
@@ -25,8 +28,13 @@ use core::{
     str,
     time::Duration,
 };
+use futures::{
+    channel::{mpsc, oneshot},
+    future, stream, StreamExt as _,
+};
 use hashbrown::HashMap;
 use libp2p::{
+    multiaddr::Protocol,
     swarm::{SwarmBuilder, SwarmEvent},
     wasm_ext, Multiaddr, PeerId, Swarm,
 };
@@ -43,30 +51,135 @@ pub struct Network {
     chain_spec_protocol_id: String,
     // TODO: meh
     request_types: HashMap<RequestId, RequestTy, fnv::FnvBuildHasher>,
+    /// Receives the requests sent by remotes on the protocols that the local node answers.
+    incoming_requests: stream::SelectAll<
+        stream::BoxStream<'static, (InboundTy, request_responses::IncomingRequest)>,
+    >,
+    /// Measures the bandwidth used by the transport, including the overhead of encryption and
+    /// multiplexing.
+    bandwidth: Arc<transport::BandwidthSinks>,
 }
 
 enum RequestTy {
     Block,
     Call,
+    StorageProof,
+    FinalityProof,
+}
+
+/// Protocol of a request sent by a remote.
+#[derive(Clone, Copy)]
+enum InboundTy {
+    Blocks,
+    FinalityProof,
 }
 
 /// Event that can happen on the network.
 #[derive(Debug)]
 pub enum Event {
     /// An announcement about a block has been gossiped to us.
-    BlockAnnounce(ScaleBlockHeader),
+    BlockAnnounce {
+        /// Peer which sent us the announcement.
+        peer_id: PeerId,
+        /// Header of the announced block.
+        header: ScaleBlockHeader,
+        /// True if the peer indicates that this block is its new best block.
+        is_best: bool,
+    },
+
+    /// A peer has reported its best block when opening the block announces substream. Later
+    /// changes are reported through [`Event::BlockAnnounce`] events with `is_best` set to true.
+    PeerStatus {
+        /// Peer which reported its best block.
+        peer_id: PeerId,
+        /// Number of the best block of the peer.
+        best_number: u64,
+        /// Hash of the best block of the peer.
+        best_hash: [u8; 32],
+    },
+
+    /// A peer has gossiped transactions to us. Transactions that this peer has already sent us
+    /// before, or that we have sent to this peer, aren't reported again.
+    ///
+    /// If too many of these events are waiting to be returned by [`Network::next_event`], the
+    /// transactions are instead dropped and counted in [`Network::num_dropped_transactions`].
+    Transactions {
+        /// Peer which sent the transactions.
+        peer_id: PeerId,
+        /// Transactions that have been received.
+        transactions: Vec<Extrinsic>,
+    },
 
     /// A blocks request started with [`Network::start_block_request`] has gotten a response.
     BlocksRequestFinished {
         id: RequestId,
-        result: Result<Vec<BlockData>, ()>,
+        result: Result<Vec<BlockData>, BlocksRequestError>,
     },
 
     /// A call request started with [`Network::start_call_request`] has gotten a response.
     CallRequestFinished {
         id: RequestId,
-        // TODO: better type
-        result: Result<Vec<u8>, ()>,
+        /// On success, contains the list of trie node values that form the proof of execution.
+        result: Result<Vec<Vec<u8>>, LightRequestError>,
+    },
+
+    /// A storage proof request started with [`Network::start_storage_proof_request`] has gotten
+    /// a response.
+    StorageProofRequestFinished {
+        id: RequestId,
+        /// On success, contains the list of trie node values that form the proof.
+        result: Result<Vec<Vec<u8>>, LightRequestError>,
+    },
+
+    /// A finality proof request started with [`Network::start_finality_proof_request`] has
+    /// gotten a response.
+    FinalityProofRequestFinished {
+        id: RequestId,
+        /// On success, contains the SCALE-encoded finality proof, or `None` if the remote
+        /// couldn't generate one. See [`crate::finality::proof`].
+        result: Result<Option<Vec<u8>>, LightRequestError>,
+    },
+
+    /// The record of an authority passed to [`Network::set_authorities`] has been found in the
+    /// DHT, and its signature is valid.
+    AuthorityAddresses {
+        /// Sr25519 public key of the authority.
+        authority_public_key: [u8; 32],
+        /// Addresses published by the authority. Can be passed to
+        /// [`Network::add_reserved_peer`] in order to connect to the authority.
+        addresses: Vec<(PeerId, Multiaddr)>,
+    },
+
+    /// A remote has sent a blocks request. Only generated if [`Config::answer_blocks_requests`]
+    /// is `true`.
+    ///
+    /// The blocks must be sent back with [`BlocksRequestResponder::respond`].
+    BlocksRequest {
+        /// Description of the request. [`BlocksRequestConfig::peer_id`] is the peer which sent
+        /// the request, and [`BlocksRequestConfig::desired_count`] is 0 if the remote didn't
+        /// indicate a maximum number of blocks.
+        config: BlocksRequestConfig,
+        /// Object to use in order to send back the response.
+        responder: BlocksRequestResponder,
+    },
+
+    /// A remote has sent a finality proof request. Only generated if
+    /// [`Config::answer_finality_proof_requests`] is `true`.
+    ///
+    /// The proof can be built with [`crate::finality::proof::build`], then sent back with
+    /// [`FinalityProofResponder::respond`].
+    FinalityProofRequest {
+        /// Peer which sent the request.
+        peer_id: PeerId,
+        /// Hash of the block whose finality must be proven.
+        block_hash: [u8; 32],
+        /// Identifier of the GrandPa authorities set of the remote.
+        authorities_set_id: u64,
+        /// Hash of the latest block finalized by the remote. The proof should only contain the
+        /// blocks that descend from it.
+        last_finalized_hash: [u8; 32],
+        /// Object to use in order to send back the response.
+        responder: FinalityProofResponder,
     },
 
     /// Established at least one connection with the given peer.
@@ -75,6 +188,31 @@ pub enum Event {
     Disconnected(PeerId),
 }
 
+/// Error that can happen during a blocks request.
+#[derive(Debug, derive_more::Display)]
+pub enum BlocksRequestError {
+    /// The remote hasn't answered in time.
+    Timeout,
+    /// Couldn't reach the remote, the remote doesn't support the protocol, or the connection
+    /// has been closed before a response was received.
+    Unavailable,
+    /// The remote has sent back a response that couldn't be decoded.
+    InvalidResponse,
+}
+
+/// Error that can happen during a call request, a storage proof request, or a finality proof
+/// request.
+#[derive(Debug, derive_more::Display)]
+pub enum LightRequestError {
+    /// The remote hasn't answered in time.
+    Timeout,
+    /// Couldn't reach the remote, the remote doesn't support the protocol, or the connection
+    /// has been closed before a response was received.
+    Unavailable,
+    /// The remote has sent back a response that couldn't be decoded.
+    InvalidResponse,
+}
+
 /// SCALE-encoded block header.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ScaleBlockHeader(pub Vec<u8>);
@@ -127,6 +265,100 @@ pub struct CallRequestConfig {
     pub encoded_input_parameter: Vec<u8>,
 }
 
+/// Description of a storage proof request that the network must perform.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StorageProofRequestConfig {
+    /// Peer to ask the proof from.
+    pub peer_id: PeerId,
+    /// Block whose storage must be read.
+    pub block: [u8; 32],
+    /// Keys whose storage value must be included in the proof.
+    pub keys: Vec<Vec<u8>>,
+}
+
+/// Description of a finality proof request that the network must perform.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FinalityProofRequestConfig {
+    /// Peer to ask the proof from.
+    pub peer_id: PeerId,
+    /// Block whose finality must be proven.
+    pub block_hash: [u8; 32],
+    /// Identifier of the GrandPa authorities set of the latest finalized block of the local
+    /// node.
+    pub authorities_set_id: u64,
+    /// Hash of the latest finalized block of the local node.
+    pub last_finalized_hash: [u8; 32],
+}
+
+/// Sends back the response to a [`Event::BlocksRequest`].
+///
+/// Dropping this object without calling [`BlocksRequestResponder::respond`] sends back an error
+/// to the remote.
+#[derive(Debug)]
+pub struct BlocksRequestResponder {
+    answer: oneshot::Sender<Vec<u8>>,
+}
+
+impl BlocksRequestResponder {
+    /// Sends back the given blocks. They are expected to be ordered according to
+    /// [`BlocksRequestConfig::direction`], and to only contain the requested fields.
+    pub fn respond(self, blocks: Vec<BlockData>) {
+        let response = schema::v1::BlockResponse {
+            blocks: blocks
+                .into_iter()
+                .map(|block| schema::v1::BlockData {
+                    hash: block.hash.encode(),
+                    header: block.header.map(|h| h.0).unwrap_or_default(),
+                    body: block
+                        .body
+                        .map(|body| {
+                            body.into_iter()
+                                .map(|ext| parity_scale_codec::Encode::encode(&ext.0))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    receipt: Vec::new(),
+                    message_queue: Vec::new(),
+                    is_empty_justification: block
+                        .justification
+                        .as_ref()
+                        .map_or(false, |j| j.is_empty()),
+                    justification: block.justification.unwrap_or_default(),
+                })
+                .collect(),
+        };
+
+        let mut buf = Vec::with_capacity(response.encoded_len());
+        response.encode(&mut buf).unwrap();
+        // The remote might have closed the substream in the meanwhile.
+        let _ = self.answer.send(buf);
+    }
+}
+
+/// Sends back the response to a [`Event::FinalityProofRequest`].
+///
+/// Dropping this object without calling [`FinalityProofResponder::respond`] sends back an error
+/// to the remote.
+#[derive(Debug)]
+pub struct FinalityProofResponder {
+    answer: oneshot::Sender<Vec<u8>>,
+}
+
+impl FinalityProofResponder {
+    /// Sends back the given SCALE-encoded finality proof, or `None` if no proof could be
+    /// generated.
+    pub fn respond(self, scale_encoded_proof: Option<Vec<u8>>) {
+        let response = schema::v1::finality::FinalityProofResponse {
+            proof: scale_encoded_proof.unwrap_or_default(),
+        };
+
+        let mut buf = Vec::with_capacity(response.encoded_len());
+        response.encode(&mut buf).unwrap();
+        // The remote might have closed the substream in the meanwhile.
+        let _ = self.answer.send(buf);
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum BlocksRequestDirection {
     Ascending,
@@ -153,10 +385,67 @@ pub enum BlocksRequestConfigStart {
 
 /// Configuration for starting the network.
 pub struct Config {
+    /// Ed25519 secret key of the local node. Determines the [`PeerId`] of the local node.
+    ///
+    /// This key should be persisted across restarts, so that the identity of the node stays
+    /// the same.
+    pub node_key: [u8; 32],
+
+    /// Seed of the sr25519 authority discovery key of the local node, if it's an authority,
+    /// typically loaded from a keystore. See [`Network::set_authorities`].
+    pub authority_discovery_key: Option<[u8; 32]>,
+
+    /// List of addresses to listen on for incoming connections.
+    pub listen_addresses: Vec<Multiaddr>,
+
+    /// List of addresses where the local node is reachable by other nodes. Advertised to the
+    /// other nodes of the network, in addition to the addresses in
+    /// [`Config::listen_addresses`].
+    ///
+    /// Typically used when the node is behind a NAT or a proxy.
+    pub public_addresses: Vec<Multiaddr>,
+
     /// List of peer ids and their addresses that we know are part of the peer-to-peer network.
     // TODO: better type
     pub known_addresses: Vec<(PeerId, Multiaddr)>,
 
+    /// Peers discovered during a previous run of the node, as returned by
+    /// [`Network::address_book`]. Contrary to [`Config::known_addresses`], these peers are
+    /// forgotten if they can't be reached.
+    pub address_book: Vec<address_book::AddressBookEntry>,
+
+    /// List of peer ids and their addresses that we should always try to stay connected to.
+    /// Connections to these peers don't count towards [`Config::in_peers`] and
+    /// [`Config::out_peers`].
+    pub reserved_peers: Vec<(PeerId, Multiaddr)>,
+
+    /// If true, only connect to the peers in [`Config::reserved_peers`] and refuse all other
+    /// incoming connections.
+    pub reserved_only: bool,
+
+    /// Maximum number of incoming connections to non-reserved peers.
+    pub in_peers: u32,
+
+    /// Maximum number of outgoing connections to non-reserved peers.
+    pub out_peers: u32,
+
+    /// Role of the local node, advertised to the other nodes of the network.
+    pub role: Role,
+
+    /// If true, the local node advertises support for the finality proof requests protocol,
+    /// and the requests sent by remotes are reported as [`Event::FinalityProofRequest`].
+    ///
+    /// Should only be set by nodes that store the justifications of the finalized blocks.
+    pub answer_finality_proof_requests: bool,
+
+    /// If true, the local node advertises support for the blocks requests protocol, and the
+    /// requests sent by remotes are reported as [`Event::BlocksRequest`].
+    pub answer_blocks_requests: bool,
+
+    /// If true, only addresses of the form `/memory/...` are supported, and mDNS is disabled.
+    /// Nodes can then only connect to other nodes of the same process. Used in tests.
+    pub memory_only: bool,
+
     /// Small string identifying the name of the chain, in order to detect incompatible nodes
     /// earlier.
     // TODO: better type
@@ -177,24 +466,93 @@ pub struct Config {
     pub wasm_external_transport: Option<wasm_ext::ExtTransport>,
 }
 
+/// Role of a node, as advertised to the other nodes of the network.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    /// Node that stores the state of the chain.
+    Full,
+    /// Node that only stores the headers of the chain, and asks other nodes for the rest.
+    Light,
+}
+
+/// Error potentially returned by [`Network::start`].
+#[derive(Debug, derive_more::Display)]
+pub enum StartError {
+    /// Failed to start listening on one of the addresses of [`Config::listen_addresses`].
+    #[display(fmt = "Failed to listen on {}: {}", address, error)]
+    Listen {
+        /// Address in question.
+        address: Multiaddr,
+        /// Error that happened.
+        error: libp2p::TransportError<std::io::Error>,
+    },
+}
+
 impl Network {
-    pub async fn start(config: Config) -> Self {
-        let local_key_pair = libp2p::identity::Keypair::generate_ed25519();
+    pub async fn start(config: Config) -> Result<Self, StartError> {
+        let local_key_pair = {
+            let mut node_key = config.node_key;
+            // The `unwrap()` can only panic if the length of the key isn't 32 bytes.
+            let secret = libp2p::identity::ed25519::SecretKey::from_bytes(&mut node_key).unwrap();
+            libp2p::identity::Keypair::Ed25519(secret.into())
+        };
         let local_public_key = local_key_pair.public();
         let local_peer_id = local_public_key.clone().into_peer_id();
-        let (transport, _) =
-            transport::build_transport(local_key_pair, false, config.wasm_external_transport, true);
+        let (transport, bandwidth) = transport::build_transport(
+            local_key_pair,
+            config.memory_only,
+            config.wasm_external_transport,
+            true,
+        );
 
         let chain_spec_protocol_id = str::from_utf8(&config.chain_spec_protocol_id)
             .unwrap()
             .to_owned(); // TODO: don't unwrap
 
+        let peerset_config = peerset::PeersetConfig {
+            in_peers: config.in_peers,
+            out_peers: config.out_peers,
+            bootnodes: config
+                .known_addresses
+                .iter()
+                .map(|(peer_id, _)| peer_id.clone())
+                .collect(),
+            reserved_peers: config
+                .reserved_peers
+                .iter()
+                .map(|(peer_id, _)| peer_id.clone())
+                .collect(),
+            reserved_only: config.reserved_only,
+        };
+
+        let mut incoming_requests = stream::SelectAll::new();
+        let mut requests_processing = |enabled: bool, ty: InboundTy| {
+            if enabled {
+                let (tx, rx) = mpsc::channel(16);
+                incoming_requests.push(rx.map(move |rq| (ty, rq)).boxed());
+                Some(tx)
+            } else {
+                None
+            }
+        };
+        let blocks_requests_tx =
+            requests_processing(config.answer_blocks_requests, InboundTy::Blocks);
+        let finality_proof_requests_tx = requests_processing(
+            config.answer_finality_proof_requests,
+            InboundTy::FinalityProof,
+        );
+
         let behaviour = behaviour::Behaviour::new(
             "substrate-lite".to_string(),
             config.chain_spec_protocol_id,
             local_public_key,
-            config.known_addresses,
-            true,
+            config
+                .known_addresses
+                .into_iter()
+                .chain(config.reserved_peers.into_iter())
+                .collect(),
+            config.address_book,
+            !config.memory_only,
             true,
             50,
             {
@@ -204,17 +562,43 @@ impl Network {
                     max_request_size: 1024 * 1024,
                     max_response_size: 16 * 1024 * 1024,
                     request_timeout: Duration::from_secs(10),
+                    requests_processing: blocks_requests_tx,
+                });
+                protocols.push(request_responses::ProtocolConfig {
+                    name: format!("/{}/light/2", chain_spec_protocol_id).into(),
+                    max_request_size: 1024 * 1024,
+                    max_response_size: 16 * 1024 * 1024,
+                    request_timeout: Duration::from_secs(15),
                     requests_processing: None, // TODO:
                 });
+                protocols.push(request_responses::ProtocolConfig {
+                    name: format!("/{}/finality-proof/1", chain_spec_protocol_id).into(),
+                    max_request_size: 1024 * 1024,
+                    max_response_size: 16 * 1024 * 1024,
+                    request_timeout: Duration::from_secs(15),
+                    requests_processing: finality_proof_requests_tx,
+                });
                 protocols
             },
-            // TODO: best hash != genesis_hash
+            peerset_config,
+            // The local best block is the genesis block until `set_local_best_block` or
+            // `announce_block` is called.
             config.local_genesis_hash.clone().into(),
             config.local_genesis_hash.clone().into(),
+            match config.role {
+                Role::Full => legacy_message::Roles::FULL,
+                Role::Light => legacy_message::Roles::LIGHT,
+            },
+            config.authority_discovery_key.map(|seed| {
+                // The `unwrap()` can only panic if the length of the seed isn't 32 bytes.
+                schnorrkel::MiniSecretKey::from_bytes(&seed)
+                    .unwrap()
+                    .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+            }),
         )
         .await;
 
-        let swarm = {
+        let mut swarm = {
             let mut builder = SwarmBuilder::new(transport, behaviour, local_peer_id)
                 .peer_connection_limit(2)
                 .notify_handler_buffer_size(NonZeroUsize::new(64).unwrap())
@@ -230,11 +614,25 @@ impl Network {
             builder.build()
         };
 
-        Network {
+        for address in config.listen_addresses {
+            if let Err(error) = Swarm::listen_on(&mut swarm, address.clone()) {
+                return Err(StartError::Listen { address, error });
+            }
+        }
+
+        // External addresses are reported to the other nodes through the identify protocol,
+        // and are then inserted in their Kademlia routing table.
+        for address in config.public_addresses {
+            Swarm::add_external_address(&mut swarm, address);
+        }
+
+        Ok(Network {
             swarm,
             chain_spec_protocol_id,
             request_types: Default::default(),
-        }
+            incoming_requests,
+            bandwidth,
+        })
     }
 
     /// Returns the [`PeerId`] of the local node.
@@ -242,9 +640,169 @@ impl Network {
         Swarm::local_peer_id(&self.swarm)
     }
 
-    /// Sends out an announcement about the given block.
-    pub async fn announce_block(&mut self) {
-        unimplemented!()
+    /// Adjusts the reputation of the given peer. The value of `reputation_change` is typically
+    /// obtained from a [`crate::chain::sync::reputation::Misbehaviour`].
+    ///
+    /// Peers whose reputation is too low are automatically disconnected and banned for a while.
+    pub fn report_peer(&mut self, peer_id: &PeerId, reputation_change: i32, reason: &'static str) {
+        self.swarm
+            .report_peer(peer_id.clone(), reputation_change, reason);
+    }
+
+    /// Disconnects from the given peer, if we're connected to it.
+    pub fn disconnect_peer(&mut self, peer_id: &PeerId) {
+        self.swarm.disconnect_peer(peer_id);
+    }
+
+    /// Adds a peer to the list of reserved peers, and the given address to the addresses of
+    /// this peer.
+    ///
+    /// The network always tries to stay connected to reserved peers, and connections to them
+    /// don't count towards [`Config::in_peers`] and [`Config::out_peers`].
+    pub fn add_reserved_peer(&mut self, peer_id: PeerId, address: Multiaddr) {
+        self.swarm.add_reserved_peer(peer_id, address);
+    }
+
+    /// Removes a peer from the list of reserved peers. Has no effect if it isn't reserved.
+    ///
+    /// If the reserved-only mode is enabled, we disconnect from this peer.
+    pub fn remove_reserved_peer(&mut self, peer_id: &PeerId) {
+        self.swarm.remove_reserved_peer(peer_id);
+    }
+
+    /// Enables or disables the reserved-only mode. See [`Config::reserved_only`].
+    ///
+    /// Enabling this mode closes the connections to all the peers that aren't reserved.
+    pub fn set_reserved_only(&mut self, reserved_only: bool) {
+        self.swarm.set_reserved_only(reserved_only);
+    }
+
+    /// Updates the best block of the local node. This information is sent to the peers we
+    /// connect to from now on.
+    ///
+    /// Doesn't send any block announce. Use [`Network::announce_block`] to also notify the peers
+    /// we're already connected to.
+    pub fn set_local_best_block(&mut self, number: u64, hash: [u8; 32]) {
+        self.swarm.set_local_best_block(number, hash.into());
+    }
+
+    /// Sends a block announce to all the peers we're connected to, for example after a block has
+    /// been imported or authored.
+    ///
+    /// If `is_best` is true, the block also becomes the local best block, as if
+    /// [`Network::set_local_best_block`] had been called.
+    ///
+    /// Returns an error if the header can't be decoded.
+    pub fn announce_block(&mut self, scale_encoded_header: &[u8], is_best: bool) -> Result<(), ()> {
+        self.swarm
+            .announce_block(scale_encoded_header, is_best)
+            .map_err(|_| ())
+    }
+
+    /// Sends the given transactions to all the peers we're connected to, except for the ones
+    /// that are known to already have them.
+    pub fn propagate_transactions<'a>(
+        &mut self,
+        transactions: impl IntoIterator<Item = &'a Extrinsic>,
+    ) {
+        self.swarm
+            .propagate_transactions(transactions.into_iter().map(|tx| &tx.0[..]));
+    }
+
+    /// Returns the number of transactions received from peers that have been dropped because too
+    /// many [`Event::Transactions`] were waiting to be returned by [`Network::next_event`].
+    pub fn num_dropped_transactions(&self) -> u64 {
+        self.swarm.num_dropped_transactions()
+    }
+
+    /// Returns the average number of bytes per second received from the network over the last
+    /// few seconds, including the overhead of encryption and multiplexing.
+    pub fn average_download_per_sec(&self) -> u64 {
+        self.bandwidth.average_download()
+    }
+
+    /// Returns the average number of bytes per second sent to the network over the last few
+    /// seconds, including the overhead of encryption and multiplexing.
+    pub fn average_upload_per_sec(&self) -> u64 {
+        self.bandwidth.average_upload()
+    }
+
+    /// Returns the traffic exchanged over each protocol since the network has started.
+    ///
+    /// The names of the request-response and notifications protocols are the ones negotiated on
+    /// the wire. The traffic of the legacy substream is reported under `/substrate/<id>`.
+    pub fn protocols_traffic(&self) -> impl Iterator<Item = (Cow<str>, &traffic::ProtocolTraffic)> {
+        self.swarm.protocols_traffic()
+    }
+
+    /// Returns the sum of the traffic exchanged over all the protocols since the network has
+    /// started.
+    pub fn total_traffic(&self) -> traffic::ProtocolTraffic {
+        self.swarm
+            .protocols_traffic()
+            .fold(Default::default(), |mut total, (_, traffic)| {
+                total.add(traffic);
+                total
+            })
+    }
+
+    /// Returns the statistics about the requests exchanged with each peer we're connected to.
+    pub fn peers_requests_stats(
+        &self,
+    ) -> impl Iterator<Item = (&PeerId, &traffic::PeerRequestsStats)> {
+        self.swarm.peers_requests_stats()
+    }
+
+    /// Builds a snapshot of the state of the network, for example in order to answer a
+    /// `system_networkState` JSON-RPC request.
+    pub fn network_state(&mut self) -> network_state::NetworkState {
+        let local_peer_id = Swarm::local_peer_id(&self.swarm).clone();
+        let listened_addresses = Swarm::listeners(&self.swarm).cloned().collect();
+        let external_addresses = Swarm::external_addresses(&self.swarm).cloned().collect();
+        self.swarm
+            .network_state(&local_peer_id, listened_addresses, external_addresses)
+    }
+
+    /// Starts looking up in the DHT the addresses of the given authorities, typically obtained
+    /// with [`super::authority_discovery::authorities_from_virtual_machine_prototype`]. The
+    /// authorities passed to a previous call are forgotten.
+    ///
+    /// An [`Event::AuthorityAddresses`] is later generated for each authority whose record is
+    /// found. If [`Config::authority_discovery_key`] is one of the authorities, the external
+    /// addresses of the local node are published as well.
+    ///
+    /// This method should be called again when the list of authorities changes and every few
+    /// minutes, as records in the DHT expire.
+    pub fn set_authorities(&mut self, authorities: impl IntoIterator<Item = [u8; 32]>) {
+        let local_peer_id = Swarm::local_peer_id(&self.swarm).clone();
+        let local_addresses = Swarm::external_addresses(&self.swarm)
+            .map(|addr| {
+                addr.clone()
+                    .with(Protocol::P2p(local_peer_id.clone().into()))
+            })
+            .collect::<Vec<_>>();
+        self.swarm
+            .set_authorities(authorities, local_addresses.into_iter());
+    }
+
+    /// Returns the peers we have discovered and how reachable they are, from the preferred to the
+    /// least preferred.
+    ///
+    /// This list is meant to be persisted, for example with the
+    /// [`database::network_address_book`](crate::database::network_address_book) module, and
+    /// passed back through [`Config::address_book`] the next time the node starts.
+    pub fn address_book(&mut self) -> Vec<address_book::AddressBookEntry> {
+        self.swarm.address_book()
+    }
+
+    /// Returns the number and hash of the best block of the given peer, as reported by this peer.
+    ///
+    /// Returns `None` if the peer hasn't opened its block announces substream yet, or if we're
+    /// not connected to it.
+    pub fn peer_best_block(&self, peer_id: &PeerId) -> Option<(u64, [u8; 32])> {
+        self.swarm
+            .peer_best_block(peer_id)
+            .map(|(number, hash)| (number, hash.into()))
     }
 
     /// Starts a block request on the network.
@@ -319,10 +877,14 @@ impl Network {
     /// response to come back. The method will block only in situations where the CPU is
     /// overwhelmed.
     pub async fn start_call_request(&mut self, config: CallRequestConfig) -> Result<RequestId, ()> {
-        let request = schema::v1::light::RemoteCallRequest {
-            block: config.block.to_vec(),
-            method: config.method_name,
-            data: config.encoded_input_parameter,
+        let request = schema::v1::light::Request {
+            request: Some(schema::v1::light::request::Request::RemoteCallRequest(
+                schema::v1::light::RemoteCallRequest {
+                    block: config.block.to_vec(),
+                    method: config.method_name,
+                    data: config.encoded_input_parameter,
+                },
+            )),
         };
 
         let request_bytes = {
@@ -346,12 +908,150 @@ impl Network {
         Ok(request_id)
     }
 
+    /// Starts a storage proof request on the network.
+    ///
+    /// This requests a remote to send back a proof of the storage values of the given keys.
+    ///
+    /// Despite being asynchronous, this method only *starts* the request and does not wait for a
+    /// response to come back. The method will block only in situations where the CPU is
+    /// overwhelmed.
+    pub async fn start_storage_proof_request(
+        &mut self,
+        config: StorageProofRequestConfig,
+    ) -> Result<RequestId, ()> {
+        let request = schema::v1::light::Request {
+            request: Some(schema::v1::light::request::Request::RemoteReadRequest(
+                schema::v1::light::RemoteReadRequest {
+                    block: config.block.to_vec(),
+                    keys: config.keys,
+                },
+            )),
+        };
+
+        let request_bytes = {
+            let mut buf = Vec::with_capacity(request.encoded_len());
+            if let Err(err) = request.encode(&mut buf) {
+                return Err(());
+            }
+            buf
+        };
+
+        let request_id = self
+            .swarm
+            .send_request(
+                &config.peer_id,
+                &format!("/{}/light/2", self.chain_spec_protocol_id),
+                request_bytes,
+            )
+            .map_err(|_| ())?;
+
+        self.request_types
+            .insert(request_id, RequestTy::StorageProof);
+        Ok(request_id)
+    }
+
+    /// Starts a finality proof request on the network.
+    ///
+    /// This requests a remote to send back a proof that the given block has been finalized.
+    ///
+    /// Despite being asynchronous, this method only *starts* the request and does not wait for a
+    /// response to come back. The method will block only in situations where the CPU is
+    /// overwhelmed.
+    pub async fn start_finality_proof_request(
+        &mut self,
+        config: FinalityProofRequestConfig,
+    ) -> Result<RequestId, ()> {
+        let request = schema::v1::finality::FinalityProofRequest {
+            block_hash: config.block_hash.to_vec(),
+            // The `0` is the index of the variant of the request in Substrate.
+            request: (0u8, config.authorities_set_id, config.last_finalized_hash).encode(),
+        };
+
+        let request_bytes = {
+            let mut buf = Vec::with_capacity(request.encoded_len());
+            if let Err(err) = request.encode(&mut buf) {
+                return Err(());
+            }
+            buf
+        };
+
+        let request_id = self
+            .swarm
+            .send_request(
+                &config.peer_id,
+                &format!("/{}/finality-proof/1", self.chain_spec_protocol_id),
+                request_bytes,
+            )
+            .map_err(|_| ())?;
+
+        self.request_types
+            .insert(request_id, RequestTy::FinalityProof);
+        Ok(request_id)
+    }
+
     /// Returns the next event that happened on the network.
     pub async fn next_event(&mut self) -> Event {
         loop {
-            match self.swarm.next_event().await {
-                SwarmEvent::Behaviour(behaviour::BehaviourOut::BlockAnnounce(header)) => {
-                    return Event::BlockAnnounce(header);
+            let swarm_event = {
+                let swarm_next = self.swarm.next_event();
+                let incoming_requests = &mut self.incoming_requests;
+                let incoming_request = async move {
+                    match incoming_requests.next().await {
+                        Some(request) => request,
+                        None => future::pending().await,
+                    }
+                };
+                futures::pin_mut!(swarm_next, incoming_request);
+
+                match future::select(swarm_next, incoming_request).await {
+                    future::Either::Left((event, _)) => event,
+                    future::Either::Right(((ty, request), _)) => {
+                        let event = match ty {
+                            InboundTy::Blocks => decode_blocks_request(request),
+                            InboundTy::FinalityProof => decode_finality_proof_request(request),
+                        };
+                        // Invalid requests are silently discarded, which sends back an error to
+                        // the remote.
+                        if let Some(event) = event {
+                            return event;
+                        }
+                        continue;
+                    }
+                }
+            };
+
+            match swarm_event {
+                SwarmEvent::Behaviour(behaviour::BehaviourOut::BlockAnnounce {
+                    peer_id,
+                    header,
+                    is_best,
+                }) => {
+                    return Event::BlockAnnounce {
+                        peer_id,
+                        header,
+                        is_best,
+                    };
+                }
+                SwarmEvent::Behaviour(behaviour::BehaviourOut::PeerStatus {
+                    peer_id,
+                    best_number,
+                    best_hash,
+                }) => {
+                    return Event::PeerStatus {
+                        peer_id,
+                        best_number,
+                        best_hash: best_hash.into(),
+                    };
+                }
+
+                SwarmEvent::Behaviour(behaviour::BehaviourOut::Transactions {
+                    peer_id,
+                    transactions,
+                }) => {
+                    return Event::Transactions {
+                        peer_id,
+                        transactions: transactions.into_iter().map(Extrinsic).collect(),
+                    };
                 }
 
                 SwarmEvent::Behaviour(behaviour::BehaviourOut::RequestFinished {
@@ -365,10 +1065,9 @@ impl Network {
                                 match schema::v1::BlockResponse::decode(&response_bytes[..]) {
                                     Ok(r) => r,
                                     Err(_) => {
-                                        // TODO: proper error
                                         return Event::BlocksRequestFinished {
                                             id: request_id,
-                                            result: Err(()),
+                                            result: Err(BlocksRequestError::InvalidResponse),
                                         };
                                     }
                                 };
@@ -380,10 +1079,9 @@ impl Network {
                                     let ext = match Vec::<u8>::decode_all(&mut extrinsic.as_ref()) {
                                         Ok(e) => e,
                                         Err(_) => {
-                                            // TODO: proper error
                                             return Event::BlocksRequestFinished {
                                                 id: request_id,
-                                                result: Err(()),
+                                                result: Err(BlocksRequestError::InvalidResponse),
                                             };
                                         }
                                     };
@@ -394,10 +1092,9 @@ impl Network {
                                 let hash = match H256::decode_all(&mut block.hash.as_ref()) {
                                     Ok(e) => e,
                                     Err(_) => {
-                                        // TODO: proper error
                                         return Event::BlocksRequestFinished {
                                             id: request_id,
-                                            result: Err(()),
+                                            result: Err(BlocksRequestError::InvalidResponse),
                                         };
                                     }
                                 };
@@ -426,26 +1123,84 @@ impl Network {
                                 result: Ok(blocks),
                             };
                         }
-                        RequestTy::Call => todo!(),
-                    }
-                }
-                SwarmEvent::Behaviour(behaviour::BehaviourOut::RequestFinished {
-                    request_id,
-                    outcome: Err(_err),
-                }) => {
-                    match self.request_types.remove(&request_id).unwrap() {
-                        RequestTy::Block => {
-                            // TODO: proper error
-                            return Event::BlocksRequestFinished {
+                        RequestTy::Call => {
+                            return Event::CallRequestFinished {
+                                id: request_id,
+                                result: decode_light_response(&response_bytes, true),
+                            };
+                        }
+                        RequestTy::StorageProof => {
+                            return Event::StorageProofRequestFinished {
+                                id: request_id,
+                                result: decode_light_response(&response_bytes, false),
+                            };
+                        }
+                        RequestTy::FinalityProof => {
+                            let result = schema::v1::finality::FinalityProofResponse::decode(
+                                &response_bytes[..],
+                            )
+                            .map(|response| {
+                                if response.proof.is_empty() {
+                                    None
+                                } else {
+                                    Some(response.proof)
+                                }
+                            })
+                            .map_err(|_| LightRequestError::InvalidResponse);
+                            return Event::FinalityProofRequestFinished {
                                 id: request_id,
-                                result: Err(()),
+                                result,
                             };
                         }
-                        RequestTy::Call => todo!(),
                     }
                 }
+                SwarmEvent::Behaviour(behaviour::BehaviourOut::RequestFinished {
+                    request_id,
+                    outcome: Err(err),
+                }) => match self.request_types.remove(&request_id).unwrap() {
+                    RequestTy::Block => {
+                        let error = match err {
+                            request_responses::OutboundFailure::Timeout => {
+                                BlocksRequestError::Timeout
+                            }
+                            _ => BlocksRequestError::Unavailable,
+                        };
+                        return Event::BlocksRequestFinished {
+                            id: request_id,
+                            result: Err(error),
+                        };
+                    }
+                    RequestTy::Call => {
+                        return Event::CallRequestFinished {
+                            id: request_id,
+                            result: Err(LightRequestError::from(err)),
+                        };
+                    }
+                    RequestTy::StorageProof => {
+                        return Event::StorageProofRequestFinished {
+                            id: request_id,
+                            result: Err(LightRequestError::from(err)),
+                        };
+                    }
+                    RequestTy::FinalityProof => {
+                        return Event::FinalityProofRequestFinished {
+                            id: request_id,
+                            result: Err(LightRequestError::from(err)),
+                        };
+                    }
+                },
                 SwarmEvent::Behaviour(behaviour::BehaviourOut::InboundRequest { .. }) => {}
 
+                SwarmEvent::Behaviour(behaviour::BehaviourOut::AuthorityAddresses {
+                    authority_public_key,
+                    addresses,
+                }) => {
+                    return Event::AuthorityAddresses {
+                        authority_public_key,
+                        addresses,
+                    };
+                }
+
                 SwarmEvent::ConnectionEstablished {
                     peer_id,
                     num_established,
@@ -476,3 +1231,96 @@ impl Network {
         }
     }
 }
+
+impl From<request_responses::OutboundFailure> for LightRequestError {
+    fn from(err: request_responses::OutboundFailure) -> Self {
+        match err {
+            request_responses::OutboundFailure::Timeout => LightRequestError::Timeout,
+            _ => LightRequestError::Unavailable,
+        }
+    }
+}
+
+/// Decodes the response to a request on the light client protocol, and returns the list of trie
+/// node values that form the proof it contains.
+///
+/// `is_call` indicates whether the response is expected to be the response to a call request or
+/// to a storage proof request.
+fn decode_light_response(
+    response_bytes: &[u8],
+    is_call: bool,
+) -> Result<Vec<Vec<u8>>, LightRequestError> {
+    let response = schema::v1::light::Response::decode(response_bytes)
+        .map_err(|_| LightRequestError::InvalidResponse)?;
+
+    let proof = match (response.response, is_call) {
+        (Some(schema::v1::light::response::Response::RemoteCallResponse(rp)), true) => rp.proof,
+        (Some(schema::v1::light::response::Response::RemoteReadResponse(rp)), false) => rp.proof,
+        _ => return Err(LightRequestError::InvalidResponse),
+    };
+
+    // The proof is a SCALE-encoded list of trie node values.
+    Vec::<Vec<u8>>::decode_all(&proof).map_err(|_| LightRequestError::InvalidResponse)
+}
+
+/// Decodes a blocks request sent by a remote. Returns `None` if the request is invalid.
+fn decode_blocks_request(request: request_responses::IncomingRequest) -> Option<Event> {
+    let decoded = schema::v1::BlockRequest::decode(&request.request_bytes[..]).ok()?;
+    let fields = legacy_message::BlockAttributes::from_be_u32(decoded.fields).ok()?;
+
+    let start = match decoded.from_block? {
+        schema::v1::block_request::FromBlock::Hash(hash) => {
+            BlocksRequestConfigStart::Hash(H256::decode_all(&hash).ok()?)
+        }
+        // TODO: requests starting at the genesis block are discarded
+        schema::v1::block_request::FromBlock::Number(number) => {
+            BlocksRequestConfigStart::Number(NonZeroU64::new(u64::decode_all(&number).ok()?)?)
+        }
+    };
+
+    let direction = match schema::v1::Direction::from_i32(decoded.direction)? {
+        schema::v1::Direction::Ascending => BlocksRequestDirection::Ascending,
+        schema::v1::Direction::Descending => BlocksRequestDirection::Descending,
+    };
+
+    Some(Event::BlocksRequest {
+        config: BlocksRequestConfig {
+            start,
+            peer_id: request.origin,
+            desired_count: decoded.max_blocks,
+            direction,
+            fields: BlocksRequestFields {
+                header: fields.contains(legacy_message::BlockAttributes::HEADER),
+                body: fields.contains(legacy_message::BlockAttributes::BODY),
+                justification: fields.contains(legacy_message::BlockAttributes::JUSTIFICATION),
+            },
+        },
+        responder: BlocksRequestResponder {
+            answer: request.answer,
+        },
+    })
+}
+
+/// Decodes a finality proof request sent by a remote. Returns `None` if the request is invalid.
+fn decode_finality_proof_request(request: request_responses::IncomingRequest) -> Option<Event> {
+    let decoded =
+        schema::v1::finality::FinalityProofRequest::decode(&request.request_bytes[..]).ok()?;
+    let block_hash = <[u8; 32]>::decode_all(&decoded.block_hash).ok()?;
+
+    // Only the `Original` variant of the request, whose index is `0`, exists.
+    let (variant, authorities_set_id, last_finalized_hash) =
+        <(u8, u64, [u8; 32])>::decode_all(&decoded.request).ok()?;
+    if variant != 0 {
+        return None;
+    }
+
+    Some(Event::FinalityProofRequest {
+        peer_id: request.origin,
+        block_hash,
+        authorities_set_id,
+        last_finalized_hash,
+        responder: FinalityProofResponder {
+            answer: request.answer,
+        },
+    })
+}
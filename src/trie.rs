@@ -85,11 +85,12 @@
 //! size of the trie.
 
 use alloc::collections::BTreeMap;
-use core::{iter, mem};
+use core::{convert::TryFrom as _, iter, mem};
 
 mod nibble;
 
 pub mod calculate_root;
+pub mod node_store;
 pub mod node_value;
 pub mod proof_verify;
 pub mod trie_structure;
@@ -197,9 +198,24 @@ pub fn empty_trie_merkle_value() -> [u8; 32] {
     }
 }
 
+/// Returns the Merkle value of the root of the trie whose keys are the SCALE-compact-encoded
+/// indices of the given values, starting from 0.
+///
+/// This is how, for example, the extrinsics root found in a block header is calculated.
+pub fn ordered_root(values: impl IntoIterator<Item = impl Into<Vec<u8>>>) -> [u8; 32] {
+    let mut trie = Trie::new();
+    for (index, value) in values.into_iter().enumerate() {
+        let key = parity_scale_codec::Encode::encode(&parity_scale_codec::Compact(
+            u32::try_from(index).unwrap(),
+        ));
+        trie.insert(&key, value);
+    }
+    trie.root_merkle_value(None)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Trie;
+    use super::{calculate_root, Trie};
 
     #[test]
     fn trie_root_one_node() {
@@ -283,4 +299,30 @@ mod tests {
         let expected = blake2_rfc::blake2b::blake2b(32, &[], &[0x0]);
         assert_eq!(obtained, expected.as_bytes());
     }
+
+    #[test]
+    fn cache_next_key() {
+        let mut trie = Trie::new();
+        trie.insert(&[0x12, 0x34], b"a".to_vec());
+        trie.insert(&[0x12, 0x34, 0x56], b"b".to_vec());
+        trie.insert(&[0x20], b"c".to_vec());
+
+        // The structure of the trie isn't known before the root has been calculated.
+        let mut cache = calculate_root::CalculationCache::empty();
+        assert_eq!(cache.next_key(&[]), None);
+        trie.root_merkle_value(Some(&mut cache));
+
+        assert_eq!(cache.next_key(&[]), Some(Some(vec![0x12, 0x34])));
+        assert_eq!(
+            cache.next_key(&[0x12, 0x34]),
+            Some(Some(vec![0x12, 0x34, 0x56]))
+        );
+        assert_eq!(cache.next_key(&[0x12, 0x35]), Some(Some(vec![0x20])));
+        assert_eq!(cache.next_key(&[0x20]), Some(None));
+
+        // Updates of the storage are taken into account.
+        cache.storage_value_update(&[0x12, 0x34, 0x56], false);
+        cache.storage_value_update(&[0x13], true);
+        assert_eq!(cache.next_key(&[0x12, 0x34]), Some(Some(vec![0x13])));
+    }
 }
@@ -93,6 +93,33 @@ impl CalculationCache {
         CalculationCache { structure: None }
     }
 
+    /// Returns the key that immediately follows `key` in lexicographic order amongst the keys
+    /// of the trie, or `Some(None)` if there isn't any.
+    ///
+    /// Returns `None` if the cache doesn't currently know the structure of the trie, in which
+    /// case the storage must be consulted instead.
+    pub fn next_key(&self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        let structure = self.structure.as_ref()?;
+        let key = bytes_to_nibbles(key.iter().cloned()).collect::<Vec<_>>();
+        let next = match structure.next_storage_key_after(&key) {
+            Some(n) => n,
+            None => return Some(None),
+        };
+
+        // Storage keys always have an even number of nibbles.
+        let next_key = structure
+            .node_full_key_by_index(next)
+            .unwrap()
+            .collect::<Vec<_>>();
+        debug_assert_eq!(next_key.len() % 2, 0);
+        Some(Some(
+            next_key
+                .chunks(2)
+                .map(|n| (u8::from(n[0]) << 4) | u8::from(n[1]))
+                .collect(),
+        ))
+    }
+
     /// Notify the cache that a storage value at the given key has been added, modified or removed.
     ///
     /// `has_value` must be true if there is now a storage value at the given key.
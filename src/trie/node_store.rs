@@ -0,0 +1,640 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Content-addressed storage of trie nodes shared between the states of multiple blocks.
+//!
+//! The [`NodeStore`] holds node values (see [the `trie` module](super)) indexed by their hash.
+//! Since the state of a block generally differs only slightly from the state of its parent,
+//! storing node values rather than full key-value maps makes it possible to keep the state of
+//! many blocks at once while storing each node only once.
+//!
+//! # Journals and reference counting
+//!
+//! The state of a block is considered as the set of its nodes. Each block inserted in the
+//! [`NodeStore`] comes with a *journal*: the set of nodes that are part of the state of the block
+//! but not of the state of its parent, and the set of nodes of the state of its parent that are
+//! no longer part of the state of the block. The journal of the oldest block in the store instead
+//! contains its entire state. Each stored node has a reference count equal to the number of
+//! journals that have inserted it.
+//!
+//! Nodes are never removed while a block that needs them is still in the store:
+//!
+//! - When a block is finalized, all the non-finalized blocks that don't descend from it are
+//!   discarded, and the reference count of the nodes they have inserted is decreased.
+//! - Only the [`Config::num_finalized_blocks_to_keep`] latest finalized blocks are kept. When the
+//!   oldest finalized block gets removed, its journal is merged into the one of its child. The
+//!   nodes that this child has deleted are no longer needed and their reference count is
+//!   decreased.
+//!
+//! Nodes whose reference count reaches zero are removed from the store.
+//!
+//! # Usage
+//!
+//! The [`NodeStore`] is meant to be used alongside with a
+//! [`NonFinalizedTree`](crate::chain::blocks_tree::NonFinalizedTree). Whenever a block is
+//! inserted in the tree, [`NodeStore::insert_block`] should be called with the same block.
+//! Whenever [`NonFinalizedTree::set_finalized_block`] is called,
+//! [`NodeStore::set_finalized_block`] should be called with the same block hash. This is done
+//! automatically if the store is attached to the tree with
+//! [`NonFinalizedTree::set_node_store`].
+//!
+//! [`NonFinalizedTree::set_node_store`]: crate::chain::blocks_tree::NonFinalizedTree::set_node_store
+//! [`NonFinalizedTree::set_finalized_block`]: crate::chain::blocks_tree::NonFinalizedTree::set_finalized_block
+
+use super::{nibble, proof_verify::decode_node};
+
+use alloc::collections::VecDeque;
+use core::{convert::TryFrom as _, fmt, iter, mem};
+use hashbrown::{HashMap, HashSet};
+
+/// Configuration for the [`NodeStore`].
+pub struct Config<I> {
+    /// Hash of the finalized block whose state is initially stored.
+    pub finalized_block_hash: [u8; 32],
+
+    /// State trie root of the finalized block.
+    pub finalized_block_state_root: [u8; 32],
+
+    /// List of all the node values of the state of the finalized block.
+    ///
+    /// Node values whose length is inferior to 32 bytes, except for the root node, don't have to
+    /// be included, as they are inlined in their parent's node value.
+    pub finalized_block_nodes: I,
+
+    /// Number of finalized blocks whose state remains accessible, in addition to the latest
+    /// finalized block.
+    pub num_finalized_blocks_to_keep: usize,
+}
+
+/// Content-addressed storage of trie nodes. See [the module-level documentation](self).
+pub struct NodeStore {
+    /// Node values, indexed by their hash.
+    nodes: HashMap<[u8; 32], StoredNode, fnv::FnvBuildHasher>,
+
+    /// Journals of all the blocks whose state is accessible, indexed by block hash.
+    blocks: HashMap<[u8; 32], Journal, fnv::FnvBuildHasher>,
+
+    /// Hashes of the finalized blocks whose state is accessible, from the oldest to the latest.
+    /// Never empty.
+    finalized: VecDeque<[u8; 32]>,
+
+    /// See [`Config::num_finalized_blocks_to_keep`].
+    num_finalized_blocks_to_keep: usize,
+}
+
+struct StoredNode {
+    /// Node value, as found in the trie.
+    node_value: Vec<u8>,
+    /// Number of journals of blocks still tracked by the store whose [`Journal::inserted`]
+    /// contains this node.
+    references: u32,
+}
+
+struct Journal {
+    /// Hash of the parent of the block. `None` for the oldest finalized block.
+    parent_hash: Option<[u8; 32]>,
+    /// State trie root of the block.
+    state_root: [u8; 32],
+    /// True if the block has been finalized.
+    finalized: bool,
+    /// Hashes of the nodes that are part of the state of this block but not of the state of its
+    /// parent. For the oldest finalized block, contains its entire state.
+    inserted: HashSet<[u8; 32], fnv::FnvBuildHasher>,
+    /// Hashes of the nodes of the parent's state that are no longer part of the state of this
+    /// block. Always empty for the oldest finalized block.
+    deleted: HashSet<[u8; 32], fnv::FnvBuildHasher>,
+}
+
+impl NodeStore {
+    /// Initializes a new [`NodeStore`] containing the state of a single finalized block.
+    pub fn new(config: Config<impl Iterator<Item = Vec<u8>>>) -> Self {
+        let mut store = NodeStore {
+            nodes: HashMap::default(),
+            blocks: HashMap::default(),
+            finalized: VecDeque::with_capacity(config.num_finalized_blocks_to_keep + 1),
+            num_finalized_blocks_to_keep: config.num_finalized_blocks_to_keep,
+        };
+
+        let mut inserted = HashSet::default();
+        for node_value in config.finalized_block_nodes {
+            let hash = node_hash(&node_value);
+            if inserted.insert(hash) {
+                store.increase_references(hash, node_value);
+            }
+        }
+        store.blocks.insert(
+            config.finalized_block_hash,
+            Journal {
+                parent_hash: None,
+                state_root: config.finalized_block_state_root,
+                finalized: true,
+                inserted,
+                deleted: HashSet::default(),
+            },
+        );
+        store.finalized.push_back(config.finalized_block_hash);
+        store
+    }
+
+    /// Returns the number of nodes in the store.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the number of blocks whose state is accessible.
+    pub fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns true if the state of the given block is accessible.
+    pub fn contains_block(&self, block_hash: &[u8; 32]) -> bool {
+        self.blocks.contains_key(block_hash)
+    }
+
+    /// Returns the hash of the latest finalized block.
+    pub fn finalized_block_hash(&self) -> [u8; 32] {
+        *self.finalized.back().unwrap()
+    }
+
+    /// Returns the state trie root of the given block, or `None` if the block is unknown.
+    pub fn state_root(&self, block_hash: &[u8; 32]) -> Option<&[u8; 32]> {
+        self.blocks.get(block_hash).map(|j| &j.state_root)
+    }
+
+    /// Returns the node value whose hash is `node_hash`, if it is in the store.
+    pub fn node_value(&self, node_hash: &[u8; 32]) -> Option<&[u8]> {
+        self.nodes.get(node_hash).map(|n| &n.node_value[..])
+    }
+
+    /// Adds the state of a new block, child of a block already in the store.
+    ///
+    /// `new_nodes` must contain the node values that are part of the state of the new block but
+    /// not of the state of its parent. `deleted_nodes` must contain the hashes of the nodes that
+    /// are part of the state of the parent but no longer of the state of the new block. Nodes of
+    /// `new_nodes` that are already part of the state of the parent, and nodes of
+    /// `deleted_nodes` that aren't, are ignored.
+    ///
+    /// Node values whose length is inferior to 32 bytes, except for the root node, don't have to
+    /// be included, as they are inlined in their parent's node value.
+    ///
+    /// Has no effect and returns `Ok` if the block is already in the store.
+    pub fn insert_block(
+        &mut self,
+        block_hash: [u8; 32],
+        parent_hash: &[u8; 32],
+        state_root: [u8; 32],
+        new_nodes: impl Iterator<Item = Vec<u8>>,
+        deleted_nodes: impl Iterator<Item = [u8; 32]>,
+    ) -> Result<(), InsertBlockError> {
+        if self.blocks.contains_key(&block_hash) {
+            return Ok(());
+        }
+
+        match self.blocks.get(parent_hash) {
+            Some(parent) if parent.finalized && *parent_hash != self.finalized_block_hash() => {
+                return Err(InsertBlockError::ParentNotLatestFinalized)
+            }
+            Some(_) => {}
+            None => return Err(InsertBlockError::UnknownParent),
+        }
+
+        let mut deleted = deleted_nodes
+            .filter(|node| self.is_in_state(parent_hash, node))
+            .collect::<HashSet<_, fnv::FnvBuildHasher>>();
+
+        let mut inserted = HashSet::default();
+        for node_value in new_nodes {
+            let hash = node_hash(&node_value);
+            // A node that is both deleted and re-inserted remains in the state without any
+            // change.
+            if deleted.remove(&hash) || self.is_in_state(parent_hash, &hash) {
+                continue;
+            }
+            if inserted.insert(hash) {
+                self.increase_references(hash, node_value);
+            }
+        }
+
+        self.blocks.insert(
+            block_hash,
+            Journal {
+                parent_hash: Some(*parent_hash),
+                state_root,
+                finalized: false,
+                inserted,
+                deleted,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Marks the given block and all its ancestors as finalized.
+    ///
+    /// All the blocks that don't descend from the new finalized block are discarded, and the
+    /// finalized blocks beyond [`Config::num_finalized_blocks_to_keep`] are pruned. The nodes
+    /// that are no longer needed by any of the remaining blocks are removed from the store.
+    pub fn set_finalized_block(&mut self, block_hash: &[u8; 32]) -> Result<(), SetFinalizedError> {
+        if !self.blocks.contains_key(block_hash) {
+            return Err(SetFinalizedError::UnknownBlock);
+        }
+
+        if self.blocks.get(block_hash).unwrap().finalized {
+            if *block_hash == self.finalized_block_hash() {
+                return Ok(());
+            }
+            return Err(SetFinalizedError::AlreadyFinalized);
+        }
+
+        // Mark as finalized the new finalized block and its non-finalized ancestors, from the
+        // highest to the lowest.
+        let newly_finalized = {
+            let mut list = Vec::new();
+            let mut current = *block_hash;
+            loop {
+                let journal = self.blocks.get_mut(&current).unwrap();
+                if journal.finalized {
+                    break;
+                }
+                journal.finalized = true;
+                list.push(current);
+                current = journal.parent_hash.unwrap();
+            }
+            list
+        };
+        self.finalized.extend(newly_finalized.into_iter().rev());
+
+        // Discard all the non-finalized blocks that don't descend from the new finalized block.
+        let to_discard = self
+            .blocks
+            .iter()
+            .filter(|(_, j)| !j.finalized)
+            .map(|(h, _)| *h)
+            .filter(|h| !self.is_descendant(h, block_hash))
+            .collect::<Vec<_>>();
+        for hash in to_discard {
+            let journal = self.blocks.remove(&hash).unwrap();
+            for node in journal.inserted {
+                self.decrease_references(&node);
+            }
+        }
+
+        // Prune the finalized blocks that are too old.
+        while self.finalized.len() > self.num_finalized_blocks_to_keep + 1 {
+            let pruned = self.finalized.pop_front().unwrap();
+            let pruned = self.blocks.remove(&pruned).unwrap();
+
+            // The journal of the new oldest finalized block must now contain its entire state.
+            // The nodes of `pruned` that the new oldest block has deleted were only needed by
+            // the state of `pruned`, while the other ones are moved to its journal.
+            let new_oldest = self.finalized.front().unwrap();
+            let new_oldest = self.blocks.get_mut(new_oldest).unwrap();
+            new_oldest.parent_hash = None;
+            let deleted = mem::take(&mut new_oldest.deleted);
+            let mut to_decrease = Vec::new();
+            for node in pruned.inserted {
+                if deleted.contains(&node) || !new_oldest.inserted.insert(node) {
+                    to_decrease.push(node);
+                }
+            }
+            for node in to_decrease {
+                self.decrease_references(&node);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the storage value associated with the given key in the state of the given block.
+    ///
+    /// Returns `Ok(None)` if the key doesn't have any storage value.
+    pub fn storage_get(
+        &self,
+        block_hash: &[u8; 32],
+        key: &[u8],
+    ) -> Result<Option<&[u8]>, StorageGetError> {
+        let state_root = self
+            .blocks
+            .get(block_hash)
+            .ok_or(StorageGetError::UnknownBlock)?
+            .state_root;
+
+        let mut node_value = self
+            .node_value(&state_root)
+            .ok_or(StorageGetError::MissingNode)?;
+        let mut key = nibble::bytes_to_nibbles(key.iter().copied());
+
+        loop {
+            let decoded = decode_node(node_value).ok_or(StorageGetError::InvalidNodeValue)?;
+
+            for nibble in decoded.partial_key {
+                if key.next() != Some(nibble) {
+                    return Ok(None);
+                }
+            }
+
+            let child_index = match key.next() {
+                Some(n) => n,
+                None => return Ok(decoded.storage_value),
+            };
+
+            node_value = match decoded.children[usize::from(u8::from(child_index))] {
+                // Merkle values inferior to 32 bytes are the node value itself.
+                Some(merkle_value) if merkle_value.len() < 32 => merkle_value,
+                Some(merkle_value) => {
+                    let hash = <[u8; 32]>::try_from(merkle_value).unwrap();
+                    self.node_value(&hash).ok_or(StorageGetError::MissingNode)?
+                }
+                None => return Ok(None),
+            };
+        }
+    }
+
+    /// Inserts the given node value in [`NodeStore::nodes`], or increases its reference count if
+    /// it was already there.
+    fn increase_references(&mut self, node_hash: [u8; 32], node_value: Vec<u8>) {
+        self.nodes
+            .entry(node_hash)
+            .or_insert(StoredNode {
+                node_value,
+                references: 0,
+            })
+            .references += 1;
+    }
+
+    /// Decreases the reference count of the given node, and removes it if the count reaches
+    /// zero.
+    fn decrease_references(&mut self, node_hash: &[u8; 32]) {
+        let node = match self.nodes.get_mut(node_hash) {
+            Some(n) => n,
+            None => {
+                debug_assert!(false);
+                return;
+            }
+        };
+
+        node.references -= 1;
+        if node.references == 0 {
+            self.nodes.remove(node_hash);
+        }
+    }
+
+    /// Returns true if the given node is part of the state of the given block.
+    fn is_in_state(&self, block_hash: &[u8; 32], node_hash: &[u8; 32]) -> bool {
+        let mut current = *block_hash;
+        loop {
+            let journal = match self.blocks.get(&current) {
+                Some(j) => j,
+                None => return false,
+            };
+            if journal.inserted.contains(node_hash) {
+                return true;
+            }
+            if journal.deleted.contains(node_hash) {
+                return false;
+            }
+            match journal.parent_hash {
+                Some(p) => current = p,
+                None => return false,
+            }
+        }
+    }
+
+    /// Returns true if `maybe_descendant` is equal to or a descendant of `ancestor`.
+    fn is_descendant(&self, maybe_descendant: &[u8; 32], ancestor: &[u8; 32]) -> bool {
+        iter::successors(Some(*maybe_descendant), |h| {
+            self.blocks.get(h).and_then(|j| j.parent_hash)
+        })
+        .any(|h| h == *ancestor)
+    }
+}
+
+impl fmt::Debug for NodeStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NodeStore")
+            .field("num_nodes", &self.nodes.len())
+            .field("num_blocks", &self.blocks.len())
+            .field("finalized", &self.finalized)
+            .finish()
+    }
+}
+
+/// Returns the hash of the given node value.
+fn node_hash(node_value: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], node_value).as_bytes());
+    hash
+}
+
+/// Error potentially returned by [`NodeStore::insert_block`].
+#[derive(Debug, derive_more::Display)]
+pub enum InsertBlockError {
+    /// The parent of the block isn't in the store.
+    UnknownParent,
+    /// The parent of the block is finalized but isn't the latest finalized block.
+    ParentNotLatestFinalized,
+}
+
+/// Error potentially returned by [`NodeStore::set_finalized_block`].
+#[derive(Debug, derive_more::Display)]
+pub enum SetFinalizedError {
+    /// Block isn't in the store.
+    UnknownBlock,
+    /// Block is an ancestor of the latest finalized block.
+    AlreadyFinalized,
+}
+
+/// Error potentially returned by [`NodeStore::storage_get`].
+#[derive(Debug, derive_more::Display)]
+pub enum StorageGetError {
+    /// Block isn't in the store.
+    UnknownBlock,
+    /// A node of the state of the block is missing from the store.
+    MissingNode,
+    /// One of the node values has an invalid format.
+    InvalidNodeValue,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, NodeStore};
+    use core::convert::TryFrom as _;
+
+    #[test]
+    fn storage_get_works() {
+        // Node values and key/value taken from the Polkadot genesis block.
+        let nodes = vec![
+            hex::decode("7d01542596adb05d6140c170ac479edf7cfd5aa35357590acfe5d11a804d944e500d1456fdda7b8ec7f9e5c794cd83194f0593e4ea").unwrap(),
+            hex::decode("803f93804e4c6c4222b747e507008ef1def063bb0d2deeadf17ef4b10e71624d3a0cf81c80241f2c06f22ec58968fb68d432319e25e6c8faa3ad2c5ca9ee48f2e8ed158e2480ad8a68234932269846bc40240a47cfd8d8857b1d81e167bfb24c947a4cdad9e680c84590e39f8b79a2694ad2bf7e7258af686b472f38b064bbce7d08404931a430805c72f25b1b6304d16667e2766fa1a906cb081788eb4502787df7c3597412b17b806e21c5f1a24a196615b4e5b36d21280cdcc80098c1e2bce8eeaf301e9951767480424f1acd80ba074a2ce8d180bf3488a5ca91cb81fba96c8c3c1d33eacbb18160805e849d5c148ca361a55a2c9b384e17ce919e936ccb8011a4f72504e9f93db8cd80edd005a1495c70250d77f81c24c15a9919f034f7983df8e505e53a5af7b402138012a0dd90497b65312bda67ea15996578eeb3891bca8666951a326612418e3143").unwrap(),
+            hex::decode("80555d8043fb497c1b2a7b9e4feb59f410c1a29e28b2a628ff9c6003e080f6b9fadd95f9806e8d911b6818038eb7c8534af8e78e9920a1ab8d939c36d3e69b0a1e5928110b80ba4d3f543957f422b40c8e74af9de00acbeba8154afca57a7f80fbbcfebb1e4a803d1b8f5cf1788b294537b8fd2d34acec4646a7627c6cd3d2039af64ff5d1976d80e7620f21cf13964f29d34ba708c3b44ea45ea11c58fbbedda29d13470bc80ca080f98aae4f83d81bf15d88019e5c303d7c19d0524e84c714e05f61517cde0b138280d518faf566fdc4d045094abe372bb3bbecd4753f76db8c41ba9fc015558bf23a80908f991126d12ce7acd55508ff1e7dffa56f742401e1814fc1469658a78c7a7f8001b0a08da0c83253d5c0cb877286c062da2f530ae424fe2545377941fd016913").unwrap(),
+            hex::decode("80b3a780a29fac7f7dfae21d05d9506e7da6515b7fa1ad970ff876de35f1bec2599ec002805b6772dc6a4e7604c8d0652479f95b343607c2d9138c59eeb799d85bf43b6bbf803d12becb6a4b9919ddc7c5973d04eed7696c834f90c779fc1fcf7350ccc28d6b805f33ebcf191fddcf3b3f346ec336c105c74b40a4d35dfda0c592f2bea00084e980f764c733d6e35771a9b26a1fa86b9bec59742b046f698be6c140af1073897d3d80cd3bc8c3ce3cf8359f7371a13316f02fd22b02a3d327684a2b61f4a47e0022b880da752afaeb925d5300e45b851052c5f8a9c5aae884f15d64764edf961b8b22c880bf1fa9c7e4c94340dbafd75cbe016c980d0e5d5b4e76823fa11e61629014c34b804f54a15e5d51d02b84e8cae94c9833ae81e56b8f0b684d257f6f722ee66cadf98094833fb2dce8c78d443cd6786e0c01d8974a4b779c178ef5e66b49e021dd7f1a").unwrap(),
+            hex::decode("9f0c5d795d0297be56027a4b2464e33397609280f332ff556abf5daf0d34523df7c8cd1369bcb6adbb23a48093bf070a9711bf3480382934134aa919b59c16ff8de8d97a7fdcc2448ea327b26f44005d756d1785878081d634140b36ce031c4b6c6266e2a7c19d9a88e38fdd8ad23abd3db20e714f6980fde17041f22f09609d79dbe38dcccefcaac139c7a10fb23bd284c1c492b004fd80d287ad1d0ade65e64d3969f4ab85a37076816031438cea0bf8c33b7b2bc6c330").unwrap(),
+            hex::decode("9f03e6d3c1fb15805edfd024172ea4817dffff80152833e34a852e9751cfc0f954aeb835e1f843936ba9979853a40e439937255f806a36e0ad23fb3224fff6e6db62048463a7f27ccb92f65b4e348acd5a7aa3a0688027b6e099c11581fb2e8acf3b6b94eaed442277b9a74ce7f922f6e3bf2959867b80fd0cc2c846db6a9ed19a715d6c3cd46a48b7f409883c70b2d4c978b306de379e80ab008a78c340f5cc75d99cdb905951936686445c834719be21f7620b950dcd5c806d86af54d5dfb1c06f3fefdd5a430861c0d19e25fad4bad07c6e70d4a679f0b880f35edc5400b6661fb1e6fba7c599c8ba891458d14400030fa506999a1972369f80746cdaa0b7da2e9c3864971f50f12d9b4281f804d5a2dba6ebe06959b2a9fb47802ecfde11456423c87fed8068f414a5ba44ebe3ae91b06d14cc231a78d4aba68e80f655291833a49cf23d057bb15c42d377c55d50f5885329060b0aaab22283cbb1808c95fb2b62baf30718b8330ef68a527c97c1bc9960304353224d8a8ae88a79d58045c1b6d9904ae171d573bdcebaa05142d81648bdbeb16ceeddc54a0ed15d3e2b80a8ea193282fe85b6481707091c77c9218ea19de914e75950925fe86400fb0cb080c222ceab5355eaa41da807146f2e2df7ff648c3e8bbb6d8ee23274ba724551b18008f142dc3c59bf1151c829ecefea35919e80453db5e9669f5a73899aaa5166ee804f1d21fbdc0180c4de886bf40f91dfc2202b3eb6d42548d476908041dd617bb8").unwrap(),
+        ];
+
+        let state_root = {
+            let bytes =
+                hex::decode(&"29d0d972cd27cbc511e9589fcb7a4506d5eb6a9e8df205f00472e5ab354a4e17")
+                    .unwrap();
+            <[u8; 32]>::try_from(&bytes[..]).unwrap()
+        };
+
+        let store = NodeStore::new(Config {
+            finalized_block_hash: [0; 32],
+            finalized_block_state_root: state_root,
+            finalized_block_nodes: nodes.into_iter(),
+            num_finalized_blocks_to_keep: 0,
+        });
+
+        let key = hex::decode("9c5d795d0297be56027a4b2464e3339763e6d3c1fb15805edfd024172ea4817d7081542596adb05d6140c170ac479edf7cfd5aa35357590acfe5d11a804d944e").unwrap();
+        assert_eq!(
+            store.storage_get(&[0; 32], &key).unwrap(),
+            Some(&hex::decode("0d1456fdda7b8ec7f9e5c794cd83194f0593e4ea").unwrap()[..])
+        );
+
+        // The root node doesn't have any child at index 6.
+        assert_eq!(store.storage_get(&[0; 32], &[0x61, 0x23]).unwrap(), None);
+
+        // Only the nodes of the proof have been inserted in the store.
+        assert!(matches!(
+            store.storage_get(&[0; 32], &[0x12, 0x34]),
+            Err(super::StorageGetError::MissingNode)
+        ));
+    }
+
+    #[test]
+    fn pruning_works() {
+        // The content of the nodes doesn't matter for this test.
+        let node = |n: u8| vec![n; 40];
+        let hash = |n: u8| {
+            <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], &node(n)).as_bytes())
+                .unwrap()
+        };
+
+        let mut store = NodeStore::new(Config {
+            finalized_block_hash: [0; 32],
+            finalized_block_state_root: hash(0),
+            finalized_block_nodes: vec![node(0), node(1)].into_iter(),
+            num_finalized_blocks_to_keep: 1,
+        });
+
+        // Block 1 replaces node 0 with node 2.
+        store
+            .insert_block(
+                [1; 32],
+                &[0; 32],
+                hash(2),
+                vec![node(2)].into_iter(),
+                vec![hash(0)].into_iter(),
+            )
+            .unwrap();
+        // Block 2, on a fork, replaces node 0 with node 3.
+        store
+            .insert_block(
+                [2; 32],
+                &[0; 32],
+                hash(3),
+                vec![node(3)].into_iter(),
+                vec![hash(0)].into_iter(),
+            )
+            .unwrap();
+        // Block 3, child of block 1, inserts node 1 again, which is ignored since it is already
+        // part of the state, and replaces node 2 with node 4.
+        store
+            .insert_block(
+                [3; 32],
+                &[1; 32],
+                hash(4),
+                vec![node(1), node(4)].into_iter(),
+                vec![hash(2)].into_iter(),
+            )
+            .unwrap();
+        assert_eq!(store.num_nodes(), 5);
+        assert_eq!(store.num_blocks(), 4);
+
+        // Finalizing block 1 discards the fork, but block 0 is still kept.
+        store.set_finalized_block(&[1; 32]).unwrap();
+        assert!(!store.contains_block(&[2; 32]));
+        assert!(store.node_value(&hash(3)).is_none());
+        assert!(store.node_value(&hash(0)).is_some());
+        assert_eq!(store.num_nodes(), 4);
+
+        // Finalizing block 3 prunes block 0, and thus node 0 which block 1 has deleted.
+        store.set_finalized_block(&[3; 32]).unwrap();
+        assert!(!store.contains_block(&[0; 32]));
+        assert!(store.contains_block(&[1; 32]));
+        assert!(store.node_value(&hash(0)).is_none());
+        assert!(store.node_value(&hash(1)).is_some());
+        assert!(store.node_value(&hash(2)).is_some());
+        assert!(store.node_value(&hash(4)).is_some());
+        assert_eq!(store.num_nodes(), 3);
+        assert_eq!(store.finalized_block_hash(), [3; 32]);
+
+        // Trying to finalize an ancestor of the finalized block is an error.
+        assert!(store.set_finalized_block(&[1; 32]).is_err());
+    }
+
+    #[test]
+    fn reinserted_node_freed() {
+        let node = |n: u8| vec![n; 40];
+        let hash = |n: u8| {
+            <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], &node(n)).as_bytes())
+                .unwrap()
+        };
+
+        let mut store = NodeStore::new(Config {
+            finalized_block_hash: [0; 32],
+            finalized_block_state_root: hash(0),
+            finalized_block_nodes: vec![node(0), node(1)].into_iter(),
+            num_finalized_blocks_to_keep: 0,
+        });
+
+        // Block 1 replaces node 0 with node 2, and inserts again node 1, shared with block 0.
+        store
+            .insert_block(
+                [1; 32],
+                &[0; 32],
+                hash(2),
+                vec![node(1), node(2)].into_iter(),
+                vec![hash(0)].into_iter(),
+            )
+            .unwrap();
+        assert_eq!(store.num_nodes(), 3);
+
+        store.set_finalized_block(&[1; 32]).unwrap();
+        assert!(!store.contains_block(&[0; 32]));
+        assert!(store.node_value(&hash(0)).is_none());
+        assert!(store.node_value(&hash(1)).is_some());
+        assert_eq!(store.num_nodes(), 2);
+
+        // Block 2 replaces node 1 with node 3. Once block 1 is pruned, node 1 is no longer used.
+        store
+            .insert_block(
+                [2; 32],
+                &[1; 32],
+                hash(2),
+                vec![node(3)].into_iter(),
+                vec![hash(1)].into_iter(),
+            )
+            .unwrap();
+        store.set_finalized_block(&[2; 32]).unwrap();
+        assert!(store.node_value(&hash(1)).is_none());
+        assert!(store.node_value(&hash(2)).is_some());
+        assert!(store.node_value(&hash(3)).is_some());
+        assert_eq!(store.num_nodes(), 2);
+    }
+}
@@ -105,143 +105,142 @@ pub fn verify_proof<'a>(
     // The verification consists in iterating using `expected_nibbles_iter` and `proof_iter`.
     let mut expected_nibbles_iter = nibble::bytes_to_nibbles(config.requested_key.iter().copied());
     loop {
-        // Decode the node value of `proof_iter`.
-        // `node_value` is updated as the decoding progresses.
-        let mut node_value = config.proof.clone().nth(proof_iter).unwrap();
-        if node_value.is_empty() {
-            return Err(Error::InvalidNodeValue);
-        }
-
-        let has_children = (node_value[0] & 0x80) != 0;
-        let has_storage_value = (node_value[0] & 0x40) != 0;
-
-        // Iterator to the partial key found in the node value of `proof_iter`.
-        let mut partial_key = {
-            // Length of the partial key, in nibbles.
-            let pk_len = {
-                let mut accumulator = usize::from(node_value[0] & 0x3f);
-                node_value = &node_value[1..];
-                let mut continue_iter = accumulator == 63;
-                while continue_iter {
-                    if node_value.is_empty() {
-                        return Err(Error::InvalidNodeValue);
-                    }
-                    continue_iter = node_value[0] == 255;
-                    accumulator = accumulator
-                        .checked_add(usize::from(node_value[0]))
-                        .ok_or(Error::InvalidNodeValue)?;
-                    node_value = &node_value[1..];
-                }
-                accumulator
-            };
-
-            // Length of the partial key, in bytes.
-            let pk_len_bytes = if pk_len == 0 {
-                0
-            } else {
-                1 + ((pk_len - 1) / 2)
-            };
-            if node_value.len() < pk_len_bytes {
-                return Err(Error::InvalidNodeValue);
-            }
-
-            let pk_nibbles_iter = node_value
-                .iter()
-                .take(pk_len_bytes)
-                .flat_map(|byte| nibble::bytes_to_nibbles(iter::once(*byte)))
-                .skip(pk_len % 2);
-            node_value = &node_value[pk_len_bytes..];
-            pk_nibbles_iter
-        };
+        let node_value = config.proof.clone().nth(proof_iter).unwrap();
+        let decoded = decode_node(node_value).ok_or(Error::InvalidNodeValue)?;
 
-        // Iterating over this partial key, checking if it matches `expected_nibbles_iter`.
-        while let Some(nibble) = partial_key.next() {
+        // Iterating over the partial key, checking if it matches `expected_nibbles_iter`.
+        for nibble in decoded.partial_key {
             if expected_nibbles_iter.next() != Some(nibble) {
                 return Ok(None);
             }
         }
 
-        // After the partial key, the node value optionally contains a bitfield of child nodes.
-        let children_bitmap = if has_children {
-            if node_value.len() < 2 {
-                return Err(Error::InvalidNodeValue);
-            }
-            let val = u16::from_le_bytes(<[u8; 2]>::try_from(&node_value[..2]).unwrap());
-            node_value = &node_value[2..];
-            val
-        } else {
-            0
+        let expected_nibble = match expected_nibbles_iter.next() {
+            Some(n) => n,
+            // The current node (as per `proof_iter`) exactly matches the requested key.
+            None => return Ok(decoded.storage_value),
         };
 
-        if let Some(expected_nibble) = expected_nibbles_iter.next() {
-            // The iteration needs to continue with another node.
-            // Update `proof_iter` to the point to the child whose index matches next nibble that
-            // was just pulled from `expected_nibbles_iter`.
-
+        // The iteration needs to continue with the child whose index matches the next nibble
+        // that was just pulled from `expected_nibbles_iter`.
+        let merkle_value = match decoded.children[usize::from(u8::from(expected_nibble))] {
+            Some(v) => v,
             // No child with the requested index exists.
-            if children_bitmap & (1 << u8::from(expected_nibble)) == 0 {
-                return Ok(None);
-            }
+            None => return Ok(None),
+        };
 
-            for n in 0.. {
-                if children_bitmap & (1 << n) == 0 {
-                    continue;
-                }
-
-                // Find the Merkle value of that child in `node_value`.
-                let (node_value_update, len) = crate::util::nom_scale_compact_usize(node_value)
-                    .map_err(|_: nom::Err<(&[u8], nom::error::ErrorKind)>| {
-                        Error::InvalidNodeValue
-                    })?;
-                node_value = node_value_update;
-                if node_value.len() < len {
-                    return Err(Error::InvalidNodeValue);
-                }
-
-                // The Merkle value that was just found is the one that interests us.
-                if n == u8::from(expected_nibble) {
-                    // Find the entry in `proof` matching this Merkle value and update
-                    // `proof_iter`.
-                    proof_iter = merkle_values
-                        .iter()
-                        .position(|v| &v[..] == &node_value[..len])
-                        .ok_or(Error::MissingProofEntry)?;
-                    break;
-                }
-
-                node_value = &node_value[len..];
-            }
-        } else if has_storage_value {
-            // The current node (as per `proof_iter`) exactly matches the requested key, and
-            // a storage value exists.
-
-            // Skip over the Merkle values of the children.
-            for _ in 0..children_bitmap.count_ones() {
-                let (node_value_update, len) = crate::util::nom_scale_compact_usize(node_value)
-                    .map_err(|_: nom::Err<(&[u8], nom::error::ErrorKind)>| {
-                        Error::InvalidNodeValue
-                    })?;
-                node_value = node_value_update;
-                if node_value.len() < len {
-                    return Err(Error::InvalidNodeValue);
-                }
-                node_value = &node_value[len..];
-            }
+        // Find the entry in `proof` matching this Merkle value and update `proof_iter`.
+        proof_iter = merkle_values
+            .iter()
+            .position(|v| &v[..] == merkle_value)
+            .ok_or(Error::MissingProofEntry)?;
+    }
+}
+
+/// Decoded node value. See [`decode_node`].
+pub(super) struct DecodedNode<'a, TPk> {
+    /// Nibbles of the partial key of the node.
+    pub partial_key: TPk,
+    /// Merkle values of the children, indexed by nibble.
+    pub children: [Option<&'a [u8]>; 16],
+    /// Storage value of the node, if any.
+    pub storage_value: Option<&'a [u8]>,
+}
+
+/// Decodes a node value. Returns `None` if the node value has an invalid format.
+pub(super) fn decode_node(
+    mut node_value: &[u8],
+) -> Option<DecodedNode<'_, impl Iterator<Item = nibble::Nibble> + '_>> {
+    if node_value.is_empty() {
+        return None;
+    }
 
-            // Now at the value that interests us.
-            let (node_value_update, len) = crate::util::nom_scale_compact_usize(node_value)
-                .map_err(|_: nom::Err<(&[u8], nom::error::ErrorKind)>| Error::InvalidNodeValue)?;
-            node_value = node_value_update;
-            if node_value.len() != len {
-                return Err(Error::InvalidNodeValue);
+    let has_children = (node_value[0] & 0x80) != 0;
+    let has_storage_value = (node_value[0] & 0x40) != 0;
+
+    // Length of the partial key, in nibbles.
+    let pk_len = {
+        let mut accumulator = usize::from(node_value[0] & 0x3f);
+        node_value = &node_value[1..];
+        let mut continue_iter = accumulator == 63;
+        while continue_iter {
+            if node_value.is_empty() {
+                return None;
             }
-            return Ok(Some(node_value));
-        } else {
-            // The current node (as per `proof_iter`) exactly matches the requested key, but no
-            // storage value exists.
-            return Ok(None);
+            continue_iter = node_value[0] == 255;
+            accumulator = accumulator.checked_add(usize::from(node_value[0]))?;
+            node_value = &node_value[1..];
         }
+        accumulator
+    };
+
+    // Length of the partial key, in bytes.
+    let pk_len_bytes = if pk_len == 0 {
+        0
+    } else {
+        1 + ((pk_len - 1) / 2)
+    };
+    if node_value.len() < pk_len_bytes {
+        return None;
     }
+    let partial_key = node_value[..pk_len_bytes]
+        .iter()
+        .flat_map(|byte| nibble::bytes_to_nibbles(iter::once(*byte)))
+        .skip(pk_len % 2);
+    node_value = &node_value[pk_len_bytes..];
+
+    // After the partial key, the node value optionally contains a bitfield of child nodes.
+    let children_bitmap = if has_children {
+        if node_value.len() < 2 {
+            return None;
+        }
+        let val = u16::from_le_bytes(<[u8; 2]>::try_from(&node_value[..2]).unwrap());
+        node_value = &node_value[2..];
+        val
+    } else {
+        0
+    };
+
+    // Merkle values of the children, which are never longer than 32 bytes.
+    let mut children = [None; 16];
+    for (n, child) in children.iter_mut().enumerate() {
+        if children_bitmap & (1 << n) == 0 {
+            continue;
+        }
+
+        let (rest, len) = crate::util::nom_scale_compact_usize(node_value)
+            .map_err(|_: nom::Err<(&[u8], nom::error::ErrorKind)>| ())
+            .ok()?;
+        if rest.len() < len || len > 32 {
+            return None;
+        }
+        *child = Some(&rest[..len]);
+        node_value = &rest[len..];
+    }
+
+    // The storage value, if any, is found after the Merkle values of the children.
+    let storage_value = if has_storage_value {
+        let (rest, len) = crate::util::nom_scale_compact_usize(node_value)
+            .map_err(|_: nom::Err<(&[u8], nom::error::ErrorKind)>| ())
+            .ok()?;
+        if rest.len() < len {
+            return None;
+        }
+        node_value = &rest[len..];
+        Some(&rest[..len])
+    } else {
+        None
+    };
+
+    if !node_value.is_empty() {
+        return None;
+    }
+
+    Some(DecodedNode {
+        partial_key,
+        children,
+        storage_value,
+    })
 }
 
 /// Possible error returned by [`verify_proof`]
@@ -256,13 +255,12 @@ pub enum Error {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use core::convert::TryFrom as _;
 
-    #[test]
-    fn basic_works() {
-        // Key/value taken from the Polkadot genesis block.
-
+    /// Returns a proof taken from the Polkadot genesis block, the key it proves, the state root
+    /// of the Polkadot genesis block, and the storage value of the key.
+    pub(crate) fn polkadot_genesis_proof() -> (Vec<Vec<u8>>, Vec<u8>, [u8; 32], Vec<u8>) {
         let proof = vec![
             hex::decode("7d01542596adb05d6140c170ac479edf7cfd5aa35357590acfe5d11a804d944e500d1456fdda7b8ec7f9e5c794cd83194f0593e4ea").unwrap(),
             hex::decode("803f93804e4c6c4222b747e507008ef1def063bb0d2deeadf17ef4b10e71624d3a0cf81c80241f2c06f22ec58968fb68d432319e25e6c8faa3ad2c5ca9ee48f2e8ed158e2480ad8a68234932269846bc40240a47cfd8d8857b1d81e167bfb24c947a4cdad9e680c84590e39f8b79a2694ad2bf7e7258af686b472f38b064bbce7d08404931a430805c72f25b1b6304d16667e2766fa1a906cb081788eb4502787df7c3597412b17b806e21c5f1a24a196615b4e5b36d21280cdcc80098c1e2bce8eeaf301e9951767480424f1acd80ba074a2ce8d180bf3488a5ca91cb81fba96c8c3c1d33eacbb18160805e849d5c148ca361a55a2c9b384e17ce919e936ccb8011a4f72504e9f93db8cd80edd005a1495c70250d77f81c24c15a9919f034f7983df8e505e53a5af7b402138012a0dd90497b65312bda67ea15996578eeb3891bca8666951a326612418e3143").unwrap(),
@@ -281,6 +279,14 @@ mod tests {
             <[u8; 32]>::try_from(&bytes[..]).unwrap()
         };
 
+        let value = hex::decode("0d1456fdda7b8ec7f9e5c794cd83194f0593e4ea").unwrap();
+        (proof, requested_key, trie_root, value)
+    }
+
+    #[test]
+    fn basic_works() {
+        let (proof, requested_key, trie_root, value) = polkadot_genesis_proof();
+
         let obtained = super::verify_proof(super::Config {
             requested_key: &requested_key[..],
             trie_root_hash: &trie_root,
@@ -288,9 +294,6 @@ mod tests {
         })
         .unwrap();
 
-        assert_eq!(
-            obtained,
-            Some(&hex::decode("0d1456fdda7b8ec7f9e5c794cd83194f0593e4ea").unwrap()[..])
-        );
+        assert_eq!(obtained, Some(&value[..]));
     }
 }
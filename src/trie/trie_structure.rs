@@ -22,7 +22,7 @@
 
 use super::nibble::Nibble;
 
-use core::{convert::TryFrom as _, fmt, iter};
+use core::{convert::TryFrom as _, fmt, iter, ops};
 use either::Either;
 use slab::Slab;
 
@@ -272,13 +272,9 @@ impl<TUd> TrieStructure<TUd> {
         }
     }
 
-    /// Iterates over all nodes of the trie, in a specific order.
+    /// Iterates over all nodes of the trie, in lexicographic order.
     fn all_nodes_ordered<'b>(&'b self) -> impl Iterator<Item = usize> + 'b {
-        if let Some(root_index) = self.root_index {
-            either::Either::Left(iter::once(root_index).chain(self.descendants(root_index)))
-        } else {
-            either::Either::Right(iter::empty())
-        }
+        iter::successors(self.root_index, move |n| self.next_node_ordered(*n))
     }
 
     /// Returns the [`NodeAccess`] of the node at the given index.
@@ -313,6 +309,129 @@ impl<TUd> TrieStructure<TUd> {
         Some(self.node_full_key(node_index.0))
     }
 
+    /// Returns true if the node at the given index has a storage value associated to it.
+    ///
+    /// Returns `false` if `node_index` is invalid.
+    pub fn is_storage(&self, node_index: NodeIndex) -> bool {
+        self.nodes
+            .get(node_index.0)
+            .map_or(false, |n| n.has_storage_value)
+    }
+
+    /// Iterates over all the nodes of the trie, both storage and branch nodes, in lexicographic
+    /// order of their full keys.
+    pub fn iter_ordered<'b>(&'b self) -> impl Iterator<Item = NodeIndex> + 'b {
+        self.all_nodes_ordered().map(NodeIndex)
+    }
+
+    /// Iterates over all the storage nodes whose key is within the given bounds, in
+    /// lexicographic order.
+    ///
+    /// The position of the bounds is found by walking down the trie, in a time proportional to
+    /// the depth of the trie. Each element of the iterator is then yielded in amortized constant
+    /// time.
+    ///
+    /// > **Note**: Branch nodes aren't yielded, as they don't correspond to any key in the
+    /// >           storage. Use [`TrieStructure::iter_ordered`] in order to access them.
+    pub fn range<'b>(
+        &'b self,
+        start: ops::Bound<&[Nibble]>,
+        end: ops::Bound<&[Nibble]>,
+    ) -> impl Iterator<Item = NodeIndex> + 'b {
+        let first = match start {
+            ops::Bound::Included(key) => self.first_node_after(key, true),
+            ops::Bound::Excluded(key) => self.first_node_after(key, false),
+            ops::Bound::Unbounded => self.root_index,
+        };
+
+        // `stop` is the first node that must not be yielded anymore, or `None` if the iteration
+        // must continue until the end of the trie.
+        let stop = match end {
+            ops::Bound::Included(key) => self.first_node_after(key, false),
+            ops::Bound::Excluded(key) => self.first_node_after(key, true),
+            ops::Bound::Unbounded => None,
+        };
+
+        // If the range is empty, `first` might be after `stop` and we must not yield anything.
+        let first = match (start, end) {
+            (ops::Bound::Included(s), ops::Bound::Included(e)) if s > e => None,
+            (ops::Bound::Included(s), ops::Bound::Excluded(e))
+            | (ops::Bound::Excluded(s), ops::Bound::Included(e))
+            | (ops::Bound::Excluded(s), ops::Bound::Excluded(e))
+                if s >= e =>
+            {
+                None
+            }
+            _ => first,
+        };
+
+        iter::successors(first, move |n| self.next_node_ordered(*n))
+            .take_while(move |n| Some(*n) != stop)
+            .filter(move |n| self.nodes.get(*n).unwrap().has_storage_value)
+            .map(NodeIndex)
+    }
+
+    /// Iterates over all the storage nodes whose key starts with the given prefix, in
+    /// lexicographic order. The node whose key is equal to `prefix`, if any, is included.
+    pub fn prefix_iter<'b>(
+        &'b self,
+        prefix: impl Iterator<Item = Nibble>,
+    ) -> impl Iterator<Item = NodeIndex> + 'b {
+        // Find the node whose key is the shortest one that starts with `prefix`. This node and
+        // all its descendants are the nodes to yield.
+        let mut prefix = prefix.peekable();
+        let mut top = self.root_index;
+        while let Some(current_index) = top {
+            let current = self.nodes.get(current_index).unwrap();
+
+            let mut partial_key = current.partial_key.iter();
+            let matches = loop {
+                match (prefix.peek(), partial_key.next()) {
+                    (None, _) => break true,
+                    (Some(_), None) => break true,
+                    (Some(a), Some(b)) if a == b => {
+                        prefix.next();
+                    }
+                    (Some(_), Some(_)) => break false,
+                }
+            };
+
+            if !matches {
+                top = None;
+                break;
+            }
+
+            match prefix.next() {
+                None => break,
+                Some(nibble) => top = current.children[usize::from(u8::from(nibble))],
+            }
+        }
+
+        let stop = top.and_then(|t| self.next_node_after_descendants(t));
+        iter::successors(top, move |n| self.next_node_ordered(*n))
+            .take_while(move |n| Some(*n) != stop)
+            .filter(move |n| self.nodes.get(*n).unwrap().has_storage_value)
+            .map(NodeIndex)
+    }
+
+    /// Returns the storage node whose key is the lowest one strictly superior to `key`, or
+    /// `None` if there is no such node.
+    ///
+    /// `key` doesn't need to correspond to a node that exists in the trie.
+    ///
+    /// The lookup is done by walking down the trie, in a time proportional to the depth of the
+    /// trie.
+    ///
+    /// This is the operation required to answer the `ext_storage_next_key` host function, and is
+    /// used by [`super::calculate_root::CalculationCache::next_key`].
+    pub fn next_storage_key_after(&self, key: &[Nibble]) -> Option<NodeIndex> {
+        iter::successors(self.first_node_after(key, false), move |n| {
+            self.next_node_ordered(*n)
+        })
+        .find(|n| self.nodes.get(*n).unwrap().has_storage_value)
+        .map(NodeIndex)
+    }
+
     /// Returns the full key of the node with the given index.
     ///
     /// # Panic
@@ -367,34 +486,6 @@ impl<TUd> TrieStructure<TUd> {
         .skip(1)
     }
 
-    /// Returns the indices of all the descendants (direct or indirect) of `node_index`, not
-    /// including `node_index` itself.
-    ///
-    /// # Panic
-    ///
-    /// Panics if `node_index` is not a valid index.
-    fn descendants<'b>(&'b self, node_index: usize) -> impl Iterator<Item = usize> + 'b {
-        // First element is `node_index`. Each successor is the first child of `current` or,
-        // if `current` doesn't have any children, the next sibling of `current`.
-        // Since `node_index` must explicitly not be included, we skip the first element.
-        iter::successors(Some(node_index), move |current| {
-            let first_child = self
-                .nodes
-                .get(*current)
-                .unwrap()
-                .children
-                .iter()
-                .filter_map(|c| *c)
-                .next();
-            if let Some(first_child) = first_child {
-                Some(first_child)
-            } else {
-                self.next_sibling(*current)
-            }
-        })
-        .skip(1)
-    }
-
     /// Returns the next sibling of the given node.
     ///
     /// # Panic
@@ -412,6 +503,97 @@ impl<TUd> TrieStructure<TUd> {
 
         None
     }
+
+    /// Returns the node that follows `node_index` in lexicographic order, or `None` if
+    /// `node_index` is the last node of the trie.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `node_index` is not a valid index.
+    fn next_node_ordered(&self, node_index: usize) -> Option<usize> {
+        let first_child = self
+            .nodes
+            .get(node_index)
+            .unwrap()
+            .children
+            .iter()
+            .filter_map(|c| *c)
+            .next();
+
+        if let Some(first_child) = first_child {
+            Some(first_child)
+        } else {
+            self.next_node_after_descendants(node_index)
+        }
+    }
+
+    /// Returns the first node that follows `node_index` in lexicographic order and that isn't a
+    /// descendant of `node_index`, or `None` if there is no such node.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `node_index` is not a valid index.
+    fn next_node_after_descendants(&self, node_index: usize) -> Option<usize> {
+        let mut current = node_index;
+        loop {
+            if let Some(sibling) = self.next_sibling(current) {
+                return Some(sibling);
+            }
+            current = self.nodes.get(current).unwrap().parent?.0;
+        }
+    }
+
+    /// Returns the first node, in lexicographic order, whose key is superior to `key`, or equal
+    /// to `key` if `inclusive` is true. Returns `None` if there is no such node.
+    fn first_node_after(&self, key: &[Nibble], inclusive: bool) -> Option<usize> {
+        let mut current_index = self.root_index?;
+        // Number of nibbles of `key` that have already been matched against the ancestors of
+        // `current_index`.
+        let mut offset = 0;
+
+        loop {
+            let current = self.nodes.get(current_index).unwrap();
+
+            for (n, nibble) in current.partial_key.iter().enumerate() {
+                match key.get(offset + n) {
+                    // `key` is a strict prefix of the key of `current`, meaning that `current`
+                    // and all its descendants are strictly superior to `key`.
+                    None => return Some(current_index),
+                    Some(k) if k < nibble => return Some(current_index),
+                    Some(k) if k > nibble => {
+                        return self.next_node_after_descendants(current_index)
+                    }
+                    Some(_) => {}
+                }
+            }
+            offset += current.partial_key.len();
+
+            // At this point, the key of `current` is a prefix of `key`.
+            let child_nibble = match key.get(offset) {
+                Some(n) => *n,
+                None if inclusive => return Some(current_index),
+                None => return self.next_node_ordered(current_index),
+            };
+            offset += 1;
+
+            // `current` itself is strictly inferior to `key`. The node to return is either
+            // within the child at `child_nibble`, or one of the next children.
+            if let Some(child) = current.children[usize::from(u8::from(child_nibble))] {
+                current_index = child;
+                continue;
+            }
+
+            let next_child = current.children[usize::from(u8::from(child_nibble)) + 1..]
+                .iter()
+                .filter_map(|c| *c)
+                .next();
+            if let Some(next_child) = next_child {
+                return Some(next_child);
+            }
+
+            return self.next_node_after_descendants(current_index);
+        }
+    }
 }
 
 impl<TUd: fmt::Debug> fmt::Debug for TrieStructure<TUd> {
@@ -23,7 +23,11 @@ use rand::{
     distributions::{Distribution as _, Uniform},
     seq::SliceRandom as _,
 };
-use std::{collections::HashSet, convert::TryFrom as _};
+use std::{
+    collections::{BTreeSet, HashSet},
+    convert::TryFrom as _,
+    ops::{Bound, RangeBounds as _},
+};
 
 #[test]
 fn remove_turns_storage_into_branch() {
@@ -416,3 +420,93 @@ fn fuzzing() {
         }
     }
 }
+
+#[test]
+fn ordered_iteration_and_ranges() {
+    fn uniform_sample(min: u8, max: u8) -> u8 {
+        Uniform::new_inclusive(min, max).sample(&mut rand::thread_rng())
+    }
+
+    fn random_key() -> Vec<Nibble> {
+        (0..uniform_sample(0, 5))
+            .map(|_| Nibble::try_from(uniform_sample(0, 3)).unwrap())
+            .collect()
+    }
+
+    // We run the test a couple times because of randomness.
+    for _ in 0..64 {
+        let keys = (0..uniform_sample(0, 40))
+            .map(|_| random_key())
+            .collect::<BTreeSet<_>>();
+
+        let mut trie = TrieStructure::new();
+        for key in &keys {
+            trie.node(key.iter().cloned())
+                .into_vacant()
+                .unwrap()
+                .insert_storage_value()
+                .insert((), ());
+        }
+
+        let full_key = |idx| {
+            trie.node_full_key_by_index(idx)
+                .unwrap()
+                .collect::<Vec<_>>()
+        };
+
+        // All the nodes, including branch nodes, must be ordered.
+        let all_nodes = trie.iter_ordered().map(full_key).collect::<Vec<_>>();
+        assert_eq!(all_nodes.len(), trie.len());
+        assert!(all_nodes.windows(2).all(|w| w[0] < w[1]));
+
+        // Storage nodes must match `keys`.
+        let storage = trie
+            .range(Bound::Unbounded, Bound::Unbounded)
+            .map(full_key)
+            .collect::<Vec<_>>();
+        assert_eq!(storage, keys.iter().cloned().collect::<Vec<_>>());
+
+        for _ in 0..32 {
+            let start = random_key();
+            let end = random_key();
+
+            for (start, end) in [
+                (Bound::Included(&start[..]), Bound::Included(&end[..])),
+                (Bound::Included(&start[..]), Bound::Excluded(&end[..])),
+                (Bound::Excluded(&start[..]), Bound::Included(&end[..])),
+                (Bound::Excluded(&start[..]), Bound::Excluded(&end[..])),
+                (Bound::Unbounded, Bound::Excluded(&end[..])),
+                (Bound::Excluded(&start[..]), Bound::Unbounded),
+            ]
+            .iter()
+            .cloned()
+            {
+                let expected = keys
+                    .iter()
+                    .filter(|k| (start, end).contains(&k[..]))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let obtained = trie.range(start, end).map(full_key).collect::<Vec<_>>();
+                assert_eq!(obtained, expected);
+            }
+
+            let expected = keys
+                .range::<[Nibble], _>((Bound::Excluded(&start[..]), Bound::Unbounded))
+                .next()
+                .cloned();
+            let obtained = trie.next_storage_key_after(&start).map(full_key);
+            assert_eq!(obtained, expected);
+
+            let expected = keys
+                .iter()
+                .filter(|k| k.starts_with(&start))
+                .cloned()
+                .collect::<Vec<_>>();
+            let obtained = trie
+                .prefix_iter(start.iter().cloned())
+                .map(full_key)
+                .collect::<Vec<_>>();
+            assert_eq!(obtained, expected);
+        }
+    }
+}
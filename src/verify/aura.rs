@@ -0,0 +1,292 @@
+// Substrate-lite
+// Copyright (C) 2019-2020  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Aura consensus.
+//!
+//! Aura, or Authority Round, is a simple consensus algorithm where authorities take turns in
+//! order to produce blocks.
+//!
+//! Time is divided into **slots** of equal duration. The current slot number is
+//! `unix_timestamp / slot_duration`. Each slot is attributed to exactly one authority, namely
+//! the one at index `slot_number % num_authorities` in the list of authorities. Only this
+//! authority is allowed to produce a block during that slot.
+//!
+//! The header of each block (with the exception of the genesis block) contains a pre-runtime
+//! digest item indicating the slot number of the block, and ends with a seal containing the
+//! signature of the header by the author of the block.
+//!
+//! # Usage
+//!
+//! Before any block can be verified, you need to know the slot duration and the list of
+//! authorities at the genesis block. See the documentation of
+//! [`AuraGenesisConfiguration`](crate::chain::chain_information::aura::AuraGenesisConfiguration).
+//!
+//! The list of authorities can later be modified by a [`header::AuraConsensusLogRef`] found in
+//! the header of a block. The new list applies to the children of this block. It is the
+//! responsibility of the user of this module to keep track of these changes, while being aware
+//! of forks.
+//!
+
+use crate::header;
+
+use core::{convert::TryFrom as _, num::NonZeroU64, time::Duration};
+
+/// Configuration for [`verify_header`].
+pub struct VerifyConfig<'a, TAuthList> {
+    /// Header of the block to verify.
+    pub header: header::HeaderRef<'a>,
+
+    /// Header of the parent of the block to verify.
+    ///
+    /// [`verify_header`] assumes that this block has been successfully verified before.
+    ///
+    /// The hash of this header must be the one referenced in [`VerifyConfig::header`].
+    pub parent_block_header: header::HeaderRef<'a>,
+
+    /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
+    /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
+    ///
+    /// Used in order to determine the current slot. Blocks whose slot is too far in the future
+    /// compared to the current slot are rejected.
+    pub now_from_unix_epoch: Duration,
+
+    /// Aura authorities that must validate the block.
+    ///
+    /// This list is either equal to the parent's list, or, if the parent changes the list of
+    /// authorities, equal to that new modified list.
+    pub current_authorities: TAuthList,
+
+    /// Duration of a slot in milliseconds.
+    /// Can be found by calling the `AuraApi_slot_duration` runtime function.
+    pub slot_duration: NonZeroU64,
+}
+
+/// Information yielded back after successfully verifying a block.
+#[derive(Debug)]
+pub struct VerifySuccess {
+    /// Slot number the block belongs to.
+    pub slot_number: u64,
+
+    /// True if the list of authorities is modified by this block. The new list of authorities
+    /// can be found in the header of the block, and applies to its children.
+    pub authorities_change: bool,
+}
+
+/// Failure to verify a block.
+#[derive(Debug, derive_more::Display)]
+pub enum VerifyError {
+    /// The seal (containing the signature of the authority) is missing from the header.
+    MissingSeal,
+    /// No pre-runtime digest in the block header.
+    MissingPreRuntimeDigest,
+    /// Parent block doesn't contain any Aura information.
+    ParentIsntAuraConsensus,
+    /// Slot number must be strictly increasing between a parent and its child.
+    SlotNumberNotIncreasing,
+    /// Slot of the block is in the future compared to the current time.
+    ///
+    /// The block might become valid later.
+    #[display(
+        fmt = "Block is in slot {} while the current slot is {}",
+        block_slot_number,
+        current_slot_number
+    )]
+    FutureSlot {
+        /// Slot number found in the header of the block.
+        block_slot_number: u64,
+        /// Slot number calculated from [`VerifyConfig::now_from_unix_epoch`].
+        current_slot_number: u64,
+    },
+    /// List of authorities is empty.
+    EmptyAuthoritiesList,
+    /// Public key of the authority assigned to the slot is invalid.
+    BadPublicKey,
+    /// Block header signature is invalid.
+    BadSignature,
+}
+
+/// Verifies whether a block header provides a correct proof of the legitimacy of the authorship.
+pub fn verify_header<'a>(
+    config: VerifyConfig<'a, impl ExactSizeIterator<Item = header::AuraAuthorityRef<'a>>>,
+) -> Result<VerifySuccess, VerifyError> {
+    let slot_number = match config.header.digest.aura_pre_runtime() {
+        Some(digest) => digest.slot_number,
+        None => return Err(VerifyError::MissingPreRuntimeDigest),
+    };
+
+    // Make sure that the slot of the block is strictly superior to the slot of the parent.
+    // The genesis block doesn't have any slot number.
+    if config.parent_block_header.number != 0 {
+        let parent_slot_number = match config.parent_block_header.digest.aura_pre_runtime() {
+            Some(digest) => digest.slot_number,
+            None => return Err(VerifyError::ParentIsntAuraConsensus),
+        };
+
+        if slot_number <= parent_slot_number {
+            return Err(VerifyError::SlotNumberNotIncreasing);
+        }
+    }
+
+    // Reject blocks whose slot hasn't started yet. Similar to what Substrate does, a drift of
+    // one slot is tolerated in order to account for clocks that aren't perfectly synchronized.
+    let current_slot_number = u64::try_from(
+        config.now_from_unix_epoch.as_millis() / u128::from(config.slot_duration.get()),
+    )
+    .unwrap_or(u64::max_value());
+    if slot_number > current_slot_number.saturating_add(1) {
+        return Err(VerifyError::FutureSlot {
+            block_slot_number: slot_number,
+            current_slot_number,
+        });
+    }
+
+    let seal_signature = match config.header.digest.aura_seal() {
+        Some(seal) => {
+            schnorrkel::Signature::from_bytes(seal).map_err(|_| VerifyError::BadSignature)?
+        }
+        None => return Err(VerifyError::MissingSeal),
+    };
+
+    // The signature in the seal applies to the header from where the signature isn't present.
+    // Build the hash that is expected to be signed.
+    let pre_seal_hash = {
+        let mut unsealed_header = config.header.clone();
+        let _popped = unsealed_header.digest.pop_seal();
+        debug_assert!(matches!(_popped, Some(header::Seal::Aura(_))));
+        unsealed_header.hash()
+    };
+
+    // Fetch the authority that has supposedly signed the block.
+    let signing_authority = {
+        let mut current_authorities = config.current_authorities;
+        let num_authorities = u64::try_from(current_authorities.len()).unwrap();
+        if num_authorities == 0 {
+            return Err(VerifyError::EmptyAuthoritiesList);
+        }
+
+        // The `unwrap()` can't panic, as the index is strictly inferior to the length.
+        let index = usize::try_from(slot_number % num_authorities).unwrap();
+        current_authorities.nth(index).unwrap()
+    };
+
+    // Now verifying the signature in the seal.
+    schnorrkel::PublicKey::from_bytes(signing_authority.public_key)
+        .map_err(|_| VerifyError::BadPublicKey)?
+        .verify_simple(b"substrate", &pre_seal_hash, &seal_signature)
+        .map_err(|_| VerifyError::BadSignature)?;
+
+    // Note that `OnDisabled` log items are ignored, in accordance with what Substrate does.
+    let authorities_change = config.header.digest.logs().any(|item| {
+        matches!(
+            item,
+            header::DigestItemRef::AuraConsensus(header::AuraConsensusLogRef::AuthoritiesChange(_))
+        )
+    });
+
+    Ok(VerifySuccess {
+        slot_number,
+        authorities_change,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_header, VerifyConfig, VerifyError, VerifySuccess};
+    use crate::{
+        chain::blocks_tree::tests::{aura_header, keypair},
+        header,
+    };
+    use core::{num::NonZeroU64, time::Duration};
+
+    const SLOT_DURATION: u64 = 6000;
+
+    fn genesis_header() -> header::Header {
+        header::Header {
+            parent_hash: [0; 32],
+            number: 0,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::empty().into(),
+        }
+    }
+
+    /// Verifies block #1 of the given slot, at the given current slot, against the given list
+    /// of authorities.
+    fn verify(
+        slot_number: u64,
+        current_slot_number: u64,
+        authorities: &[header::AuraAuthority],
+    ) -> Result<VerifySuccess, VerifyError> {
+        let genesis = genesis_header();
+        let block = aura_header(genesis.hash(), 1, slot_number);
+        verify_header(VerifyConfig {
+            header: header::decode(&block).unwrap(),
+            parent_block_header: (&genesis).into(),
+            now_from_unix_epoch: Duration::from_millis(current_slot_number * SLOT_DURATION),
+            current_authorities: header::AuraAuthoritiesIter::from_slice(authorities),
+            slot_duration: NonZeroU64::new(SLOT_DURATION).unwrap(),
+        })
+    }
+
+    #[test]
+    fn valid_seal() {
+        let authorities = [header::AuraAuthority {
+            public_key: keypair().public.to_bytes(),
+        }];
+        let success = verify(5, 10, &authorities).unwrap();
+        assert_eq!(success.slot_number, 5);
+        assert!(!success.authorities_change);
+    }
+
+    #[test]
+    fn wrong_author() {
+        let other = schnorrkel::MiniSecretKey::from_bytes(&[8; 32])
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519);
+
+        // Slot 5 is attributed to the authority at index 1, which isn't the signer.
+        let authorities = [
+            header::AuraAuthority {
+                public_key: keypair().public.to_bytes(),
+            },
+            header::AuraAuthority {
+                public_key: other.public.to_bytes(),
+            },
+        ];
+        assert!(matches!(
+            verify(5, 10, &authorities),
+            Err(VerifyError::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn future_slot() {
+        let authorities = [header::AuraAuthority {
+            public_key: keypair().public.to_bytes(),
+        }];
+
+        // A drift of one slot is tolerated.
+        assert!(verify(5, 4, &authorities).is_ok());
+        assert!(matches!(
+            verify(5, 3, &authorities),
+            Err(VerifyError::FutureSlot {
+                block_slot_number: 5,
+                current_slot_number: 3
+            })
+        ));
+    }
+}
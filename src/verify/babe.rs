@@ -125,15 +125,27 @@
 //! - The [`header::BabeNextEpoch`] structs corresponding to each epoch number. An [`header::BabeNextEpoch`]
 //! can be extracted from a block's header, therefore for long-term storage you only need to store
 //! which block contains the information about each epoch number, and that block's header.
+//! - The values of [`VerifySuccess::disabled_authorities`] and
+//! [`VerifySuccess::randomness_accumulator`] of each block, which must be provided back when
+//! verifying its children.
 //!
 //! In both situations, you need to be aware of forks. There can be multiple block 1s, and
 //! multiple blocks which contain an [`header::BabeNextEpoch`] for a given epoch number. Only the
 //! information contained in an ancestor of the block being verified must be provided.
 //!
+//! ## Pre-verification
+//!
+//! Verifying the signature and the VRF proof of a block is the most CPU-intensive part of the
+//! verification, but only requires knowing the list of authorities and the randomness of the
+//! epoch the block belongs to. When a large number of blocks need to be verified, for example
+//! while syncing, [`pre_verify_seal`] can be called ahead of time on many blocks in parallel.
+//! The returned [`PreVerifiedSeal`] must then be passed as [`VerifyConfig::pre_verified_seal`]
+//! in order to skip these checks.
+//!
 
 use crate::{chain::chain_information::babe::BabeGenesisConfiguration, header};
 
-use core::{convert::TryFrom as _, num::NonZeroU64, time::Duration};
+use core::{convert::TryFrom as _, iter, num::NonZeroU64, time::Duration};
 use num_traits::{cast::ToPrimitive as _, identities::One as _};
 
 /// Configuration for [`start_verify_header`].
@@ -143,7 +155,9 @@ pub struct VerifyConfig<'a> {
 
     /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
     /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
-    // TODO: unused, should check against a block's slot
+    ///
+    /// Used in order to determine the current slot. Blocks whose slot is too far in the future
+    /// compared to the current slot are rejected.
     pub now_from_unix_epoch: Duration,
 
     /// Header of the parent of the block to verify.
@@ -162,6 +176,28 @@ pub struct VerifyConfig<'a> {
     /// Slot number of block #1. **Must** be provided, unless the block being verified is block
     /// #1 itself.
     pub block1_slot_number: Option<u64>,
+
+    /// Indices of the authorities that have been disabled by the parent block or by one of its
+    /// ancestors belonging to the same epoch as the parent.
+    ///
+    /// Must be the value of [`VerifySuccess::disabled_authorities`] of the parent block, or
+    /// empty if the parent is the genesis block.
+    pub parent_disabled_authorities: &'a [u32],
+
+    /// Value of [`VerifySuccess::randomness_accumulator`] of the parent block.
+    ///
+    /// Can be `None` if this value isn't known, for example if the parent is the genesis block
+    /// or if the verification started from a checkpoint. If `None`, and the block being verified
+    /// is the first block of an epoch, the randomness that it announces for the next epoch isn't
+    /// verified.
+    pub parent_randomness_accumulator: Option<&'a RandomnessAccumulator>,
+
+    /// Outcome of calling [`pre_verify_seal`] on this block, if any.
+    ///
+    /// If it has been obtained for this block and for the authority and epoch the block belongs
+    /// to, the signature and VRF proof of the block aren't verified again. It is ignored
+    /// otherwise.
+    pub pre_verified_seal: Option<&'a PreVerifiedSeal>,
 }
 
 /// Information yielded back after successfully verifying a block.
@@ -180,6 +216,19 @@ pub struct VerifySuccess {
     ///
     /// > **Note**: If `Some`, the value is always equal to [`VerifySuccess::epoch_number`] + 1.
     pub epoch_transition_target: Option<NonZeroU64>,
+
+    /// Indices of the authorities that have been disabled by this block or by one of its
+    /// ancestors belonging to the same epoch. Must later be provided back as
+    /// [`VerifyConfig::parent_disabled_authorities`] when verifying the children of this block.
+    pub disabled_authorities: Vec<u32>,
+
+    /// State of the calculation of the randomness of an upcoming epoch. Must later be provided
+    /// back as [`VerifyConfig::parent_randomness_accumulator`] when verifying the children of
+    /// this block.
+    ///
+    /// `None` if [`VerifyConfig::parent_randomness_accumulator`] was `None` and this block isn't
+    /// the first block of an epoch.
+    pub randomness_accumulator: Option<RandomnessAccumulator>,
 }
 
 /// Failure to verify a block.
@@ -209,21 +258,39 @@ pub enum VerifyError {
     OverPrimaryClaimThreshold,
     /// Type of slot claim forbidden by current configuration.
     ForbiddenSlotType,
+    /// [`VerifyConfig::block1_slot_number`] is `None` but the block being verified isn't block
+    /// #1.
+    MissingBlock1SlotNumber,
+    /// Slot number of the block, or of its parent, is inferior to the slot number of block #1.
+    SlotNumberBeforeBlock1,
+    /// Slot of the block is in the future compared to the current time.
+    ///
+    /// The block might become valid later.
+    #[display(
+        fmt = "Block is in slot {} while the current slot is {}",
+        block_slot_number,
+        current_slot_number
+    )]
+    FutureSlot {
+        /// Slot number found in the header of the block.
+        block_slot_number: u64,
+        /// Slot number calculated from [`VerifyConfig::now_from_unix_epoch`].
+        current_slot_number: u64,
+    },
+    /// Authority that has produced the block has been disabled earlier in the same epoch.
+    AuthorityDisabled,
+    /// Block contains an `OnDisabled` digest log whose authority index is out of range.
+    BadDisabledAuthorityIndex,
+    /// Randomness announced for the next epoch doesn't match the VRF outputs of the blocks of
+    /// the previous epoch.
+    BadNextEpochRandomness,
 }
 
 /// Verifies whether a block header provides a correct proof of the legitimacy of the authorship.
 ///
 /// Returns either a [`PendingVerify`] if more information is needed, or a [`VerifySuccess`] if
 /// the verification could be successfully performed.
-///
-/// # Panic
-///
-/// Panics if `config.parent_block_header` is invalid.
-/// Panics if `config.block1_slot_number` is `None` and `config.header.number` is not 1.
-///
 pub fn start_verify_header<'a>(config: VerifyConfig<'a>) -> Result<SuccessOrPending, VerifyError> {
-    // TODO: handle OnDisabled
-
     // Gather the BABE-related information from the header.
     let (authority_index, slot_number, primary, vrf) = match config.header.digest.babe_pre_runtime()
     {
@@ -245,13 +312,29 @@ pub fn start_verify_header<'a>(config: VerifyConfig<'a>) -> Result<SuccessOrPend
         None => return Err(VerifyError::MissingPreRuntimeDigest),
     };
 
+    // Reject blocks whose slot hasn't started yet. Similar to what Substrate does, a drift of
+    // one slot is tolerated in order to account for clocks that aren't perfectly synchronized.
+    // If the slot duration is 0, which is nonsensical, the check is skipped.
+    if let Some(current_slot_number) = config
+        .now_from_unix_epoch
+        .as_millis()
+        .checked_div(u128::from(config.genesis_configuration.slot_duration()))
+    {
+        let current_slot_number = u64::try_from(current_slot_number).unwrap_or(u64::max_value());
+        if slot_number > current_slot_number.saturating_add(1) {
+            return Err(VerifyError::FutureSlot {
+                block_slot_number: slot_number,
+                current_slot_number,
+            });
+        }
+    }
+
     // Determine the epoch number the block we verify belongs to.
     let epoch_number = match (slot_number, config.block1_slot_number) {
-        (curr, Some(block1)) => {
-            slot_number_to_epoch(curr, config.genesis_configuration, block1).unwrap()
-        } // TODO: don't unwrap
+        (curr, Some(block1)) => slot_number_to_epoch(curr, config.genesis_configuration, block1)
+            .map_err(|()| VerifyError::SlotNumberBeforeBlock1)?,
         (_, None) if config.header.number == 1 => 0,
-        (_, None) => panic!(), // Bad `VerifyConfig`.
+        (_, None) => return Err(VerifyError::MissingBlock1SlotNumber),
     };
 
     // Determine the epoch number of the parent block. `None` if the parent is the genesis block.
@@ -265,28 +348,31 @@ pub fn start_verify_header<'a>(config: VerifyConfig<'a>) -> Result<SuccessOrPend
             return Err(VerifyError::SlotNumberNotIncreasing);
         }
 
+        let block1_slot_number = config
+            .block1_slot_number
+            .ok_or(VerifyError::MissingBlock1SlotNumber)?;
         Some(
             slot_number_to_epoch(
                 parent_slot_number,
                 config.genesis_configuration,
-                config.block1_slot_number.unwrap(),
+                block1_slot_number,
             )
-            .unwrap(),
+            .map_err(|()| VerifyError::SlotNumberBeforeBlock1)?,
         )
     } else {
         None
     };
 
     // Extract the epoch change information stored in the header, if any.
-    let epoch_transition_target = config
-        .header
-        .digest
-        .babe_epoch_information()
-        .map(|_| NonZeroU64::new(epoch_number + 1).unwrap());
-
-    // TODO: in case of epoch change, should also check the randomness value; while the runtime
-    //       checks that the randomness value is correct, light clients in particular do not
-    //       execute the runtime
+    let (epoch_transition_target, next_epoch_randomness) =
+        match config.header.digest.babe_epoch_information() {
+            Some((next_epoch, _)) => (
+                // The `unwrap()` can't panic, as `saturating_add(1)` never returns 0.
+                Some(NonZeroU64::new(epoch_number.saturating_add(1)).unwrap()),
+                Some(*next_epoch.randomness),
+            ),
+            None => (None, None),
+        };
 
     // Make sure that the expected epoch transitions correspond to what the blocks report.
     match (
@@ -299,21 +385,57 @@ pub fn start_verify_header<'a>(config: VerifyConfig<'a>) -> Result<SuccessOrPend
         (None, true) => return Err(VerifyError::MissingEpochChangeLog),
     };
 
-    let seal_signature = match config.header.digest.babe_seal() {
-        Some(seal) => {
-            schnorrkel::Signature::from_bytes(seal).map_err(|_| VerifyError::BadSignature)?
+    // Authorities are disabled until the end of the epoch. A block authored by an authority that
+    // has been disabled by one of its ancestors of the same epoch is invalid.
+    let mut disabled_authorities = if epoch_transition_target.is_none() {
+        if config
+            .parent_disabled_authorities
+            .contains(&authority_index)
+        {
+            return Err(VerifyError::AuthorityDisabled);
         }
-        None => return Err(VerifyError::MissingSeal),
+        config.parent_disabled_authorities.to_vec()
+    } else {
+        Vec::new()
     };
 
+    // Add the authorities disabled by this block. The validity of the indices can only be
+    // verified once the list of authorities of the epoch is known.
+    for log in config.header.digest.logs() {
+        if let header::DigestItemRef::BabeConsensus(header::BabeConsensusLogRef::OnDisabled(
+            index,
+        )) = log
+        {
+            if !disabled_authorities.contains(&index) {
+                disabled_authorities.push(index);
+            }
+        }
+    }
+
+    // The randomness accumulator of the parent is only relevant if it accumulates the VRF
+    // outputs of the epoch of the parent.
+    let parent_randomness_accumulator = config
+        .parent_randomness_accumulator
+        .filter(|acc| Some(acc.epoch_number) == parent_epoch_number)
+        .cloned();
+
+    let seal = config
+        .header
+        .digest
+        .babe_seal()
+        .ok_or(VerifyError::MissingSeal)?;
+    let seal_signature =
+        schnorrkel::Signature::from_bytes(seal).map_err(|_| VerifyError::BadSignature)?;
+
     // The signature in the seal applies to the header from where the signature isn't present.
     // Build the hash that is expected to be signed.
-    let pre_seal_hash = {
-        let mut unsealed_header = config.header;
-        let _popped = unsealed_header.digest.pop_babe_seal();
-        debug_assert!(_popped.is_some());
-        unsealed_header.hash()
-    };
+    let pre_seal_hash = pre_seal_hash(&config.header);
+
+    // The pre-verification is only relevant if it concerns this exact header.
+    let pre_verified_seal = config
+        .pre_verified_seal
+        .filter(|pre| pre.pre_seal_hash == pre_seal_hash && pre.seal_signature[..] == seal[..])
+        .cloned();
 
     // Intermediary object representing the state of the verification at this point.
     let pending = PendingVerify {
@@ -325,6 +447,10 @@ pub fn start_verify_header<'a>(config: VerifyConfig<'a>) -> Result<SuccessOrPend
         authority_index,
         primary_slot_claim: primary,
         vrf_output_and_proof: vrf,
+        disabled_authorities,
+        next_epoch_randomness,
+        parent_randomness_accumulator,
+        pre_verified_seal,
     };
 
     // The information about epoch number 0 is never given by any block and is instead found in
@@ -371,6 +497,15 @@ pub struct PendingVerify {
     /// VRF output and proof contained in the block header. Cannot be `None` if
     /// `primary_slot_claim` is true.
     vrf_output_and_proof: Option<([u8; 32], [u8; 64])>,
+    /// Authorities disabled by the block or its ancestors in the same epoch. The indices haven't
+    /// been verified yet.
+    disabled_authorities: Vec<u32>,
+    /// If the block is at an epoch transition, randomness it announces for the next epoch.
+    next_epoch_randomness: Option<[u8; 32]>,
+    /// Randomness accumulator of the parent block, if it concerns the epoch of the parent.
+    parent_randomness_accumulator: Option<RandomnessAccumulator>,
+    /// Outcome of the pre-verification of this block, if any.
+    pre_verified_seal: Option<PreVerifiedSeal>,
 }
 
 impl PendingVerify {
@@ -414,38 +549,53 @@ impl PendingVerify {
             )
             .ok_or(VerifyError::InvalidAuthorityIndex)?;
 
+        // The indices of the authorities disabled by the block must be in range.
+        if self.disabled_authorities.iter().any(|index| {
+            usize::try_from(*index).map_or(true, |index| index >= epoch_info.0.authorities.len())
+        }) {
+            return Err(VerifyError::BadDisabledAuthorityIndex);
+        }
+
         // This `unwrap()` can only panic if `public_key` is the wrong length, which we know can't
         // happen as it's of type `[u8; 32]`.
         let signing_public_key =
             schnorrkel::PublicKey::from_bytes(signing_authority.public_key).unwrap();
 
+        // The outcome of the pre-verification can only be used if it has been obtained against
+        // the same authority and epoch.
+        let epoch_number = self.epoch_number;
+        let pre_verified_seal = self.pre_verified_seal.filter(|pre| {
+            pre.signing_public_key == *signing_authority.public_key
+                && pre.epoch_number == epoch_number
+                && pre.epoch_randomness == *epoch_info.0.randomness
+        });
+
         // Now verifying the signature in the seal.
-        signing_public_key
-            .verify_simple(b"substrate", &self.pre_seal_hash, &self.seal_signature)
-            .map_err(|_| VerifyError::BadSignature)?;
+        if pre_verified_seal.is_none() {
+            signing_public_key
+                .verify_simple(b"substrate", &self.pre_seal_hash, &self.seal_signature)
+                .map_err(|_| VerifyError::BadSignature)?;
+        }
 
         // Now verify the VRF output and proof, if any.
         // The lack of VRF output/proof in the header is checked when we check whether the slot
         // type is allowed by the current configuration.
-        if let Some((vrf_output, vrf_proof)) = self.vrf_output_and_proof {
-            // In order to verify the VRF output, we first need to create a transcript containing all
-            // the data to verify the VRF against.
-            let transcript = {
-                let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
-                transcript.append_u64(b"slot number", self.slot_number);
-                transcript.append_u64(b"current epoch", self.epoch_number);
-                transcript.append_message(b"chain randomness", &epoch_info.0.randomness[..]);
-                transcript
-            };
-
-            // These `unwrap()`s can only panic if `vrf_output` or `vrf_proof` are of the wrong
-            // length, which we know can't happen as they're of types `[u8; 32]` and `[u8; 64]`.
-            let vrf_output = schnorrkel::vrf::VRFOutput::from_bytes(&vrf_output[..]).unwrap();
-            let vrf_proof = schnorrkel::vrf::VRFProof::from_bytes(&vrf_proof[..]).unwrap();
-
-            let (vrf_in_out, _) = signing_public_key
-                .vrf_verify(transcript, &vrf_output, &vrf_proof)
-                .map_err(|_| VerifyError::BadVrfProof)?;
+        // Contains the randomness generated by the VRF, which later contributes to the randomness
+        // of an upcoming epoch.
+        let vrf_randomness = if let Some((vrf_output, vrf_proof)) = self.vrf_output_and_proof {
+            let (threshold_bytes, vrf_randomness) =
+                match pre_verified_seal.and_then(|pre| pre.vrf_outputs) {
+                    Some(outputs) => outputs,
+                    None => verify_vrf(
+                        &signing_public_key,
+                        self.slot_number,
+                        self.epoch_number,
+                        epoch_info.0.randomness,
+                        &vrf_output,
+                        &vrf_proof,
+                    )
+                    .ok_or(VerifyError::BadVrfProof)?,
+                };
 
             // If this is a primary slot claim, we need to make sure that the VRF output is below
             // a certain threshold, otherwise all the authorities could claim all the slots.
@@ -455,15 +605,16 @@ impl PendingVerify {
                     epoch_info.0.authorities.clone().map(|a| a.weight),
                     signing_authority.weight,
                 );
-                if u128::from_le_bytes(vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"))
-                    >= threshold
-                {
+                if u128::from_le_bytes(threshold_bytes) >= threshold {
                     return Err(VerifyError::OverPrimaryClaimThreshold);
                 }
             }
+
+            Some(vrf_randomness)
         } else {
             debug_assert!(!self.primary_slot_claim);
-        }
+            None
+        };
 
         // Each slot can be claimed by one specific authority in what is called a secondary slot
         // claim. If the block is a secondary slot claim, we need to make sure that the author
@@ -490,15 +641,296 @@ impl PendingVerify {
             }
         }
 
+        // The first block of each epoch announces the randomness of the next epoch, which is
+        // calculated from the VRF outputs of the blocks of the previous epoch. Verify this value
+        // if possible, then start accumulating the VRF outputs of the current epoch.
+        let mut randomness_accumulator =
+            if let Some(next_epoch_randomness) = self.next_epoch_randomness {
+                if let Some(parent_accumulator) = self
+                    .parent_randomness_accumulator
+                    .filter(|acc| acc.epoch_number.checked_add(1) == Some(epoch_number))
+                {
+                    if parent_accumulator.finish() != next_epoch_randomness {
+                        return Err(VerifyError::BadNextEpochRandomness);
+                    }
+                }
+
+                Some(RandomnessAccumulator::new(
+                    epoch_number,
+                    &next_epoch_randomness,
+                ))
+            } else {
+                self.parent_randomness_accumulator
+            };
+
+        if let (Some(accumulator), Some(vrf_randomness)) =
+            (&mut randomness_accumulator, &vrf_randomness)
+        {
+            accumulator.hasher.update(vrf_randomness);
+        }
+
         // Success! 🚀
         Ok(VerifySuccess {
             epoch_transition_target: self.epoch_transition_target,
             slot_number: self.slot_number,
             epoch_number: self.epoch_number,
+            disabled_authorities: self.disabled_authorities,
+            randomness_accumulator,
         })
     }
 }
 
+/// Configuration for [`pre_verify_seal`].
+pub struct PreVerifyConfig<'a> {
+    /// Header of the block to verify.
+    pub header: header::HeaderRef<'a>,
+
+    /// Number of the epoch the block is assumed to belong to.
+    pub epoch_number: u64,
+
+    /// Information about the epoch whose number is [`PreVerifyConfig::epoch_number`].
+    pub epoch_info: header::BabeNextEpochRef<'a>,
+}
+
+/// Outcome of a successful call to [`pre_verify_seal`].
+///
+/// Proves that the signature and the VRF proof of a block header are correct, assuming that the
+/// block belongs to a certain epoch.
+#[derive(Debug, Clone)]
+pub struct PreVerifiedSeal {
+    /// Hash of the block header without its seal.
+    pre_seal_hash: [u8; 32],
+    /// Signature contained in the seal of the block header.
+    seal_signature: [u8; 64],
+    /// Public key the signature and VRF proof have been verified against.
+    signing_public_key: [u8; 32],
+    /// Epoch number the VRF proof has been verified against.
+    epoch_number: u64,
+    /// Randomness of the epoch the VRF proof has been verified against.
+    epoch_randomness: [u8; 32],
+    /// If the header contains a VRF output, the values generated by the VRF that are compared
+    /// with the primary slot claim threshold and that contribute to the randomness of an
+    /// upcoming epoch.
+    vrf_outputs: Option<([u8; 16], [u8; 32])>,
+}
+
+/// Verifies the signature and the VRF proof of a block header, assuming that the block belongs
+/// to the given epoch.
+///
+/// Contrary to [`start_verify_header`], this function doesn't need any information about the
+/// parent of the block and can therefore be called on many blocks ahead of time and in
+/// parallel. The [`PreVerifiedSeal`] must then be passed as
+/// [`VerifyConfig::pre_verified_seal`].
+///
+/// Returns `None` if the header is malformed or if the verification has failed. Only
+/// [`start_verify_header`] reports the exact reason why a block is invalid.
+///
+/// > **Note**: Passing a wrong epoch number or epoch information never leads to an invalid
+/// >           block being accepted. The [`PreVerifiedSeal`] is simply ignored if it doesn't
+/// >           correspond to the epoch the block actually belongs to.
+pub fn pre_verify_seal(config: PreVerifyConfig) -> Option<PreVerifiedSeal> {
+    let (authority_index, slot_number, vrf) = match config.header.digest.babe_pre_runtime()? {
+        header::BabePreDigestRef::Primary(digest) => (
+            digest.authority_index,
+            digest.slot_number,
+            Some((*digest.vrf_output, *digest.vrf_proof)),
+        ),
+        header::BabePreDigestRef::SecondaryPlain(digest) => {
+            (digest.authority_index, digest.slot_number, None)
+        }
+        header::BabePreDigestRef::SecondaryVRF(digest) => (
+            digest.authority_index,
+            digest.slot_number,
+            Some((*digest.vrf_output, *digest.vrf_proof)),
+        ),
+    };
+
+    let seal = config.header.digest.babe_seal()?;
+    let seal_signature = schnorrkel::Signature::from_bytes(seal).ok()?;
+    let pre_seal_hash = pre_seal_hash(&config.header);
+
+    let signing_authority = config
+        .epoch_info
+        .authorities
+        .clone()
+        .nth(usize::try_from(authority_index).ok()?)?;
+    let signing_public_key =
+        schnorrkel::PublicKey::from_bytes(signing_authority.public_key).ok()?;
+
+    signing_public_key
+        .verify_simple(b"substrate", &pre_seal_hash, &seal_signature)
+        .ok()?;
+
+    let vrf_outputs = match vrf {
+        Some((vrf_output, vrf_proof)) => Some(verify_vrf(
+            &signing_public_key,
+            slot_number,
+            config.epoch_number,
+            config.epoch_info.randomness,
+            &vrf_output,
+            &vrf_proof,
+        )?),
+        None => None,
+    };
+
+    Some(PreVerifiedSeal {
+        pre_seal_hash,
+        seal_signature: *seal,
+        signing_public_key: *signing_authority.public_key,
+        epoch_number: config.epoch_number,
+        epoch_randomness: *config.epoch_info.randomness,
+        vrf_outputs,
+    })
+}
+
+/// Returns the hash of the given header with its seal removed, which is what the author of the
+/// block signs.
+fn pre_seal_hash(header: &header::HeaderRef) -> [u8; 32] {
+    let mut unsealed_header = header.clone();
+    let _popped = unsealed_header.digest.pop_seal();
+    debug_assert!(matches!(_popped, Some(header::Seal::Babe(_))));
+    unsealed_header.hash()
+}
+
+/// Verifies the VRF output and proof of a block. On success, returns the values generated by
+/// the VRF that are compared with the primary slot claim threshold and that contribute to the
+/// randomness of an upcoming epoch.
+fn verify_vrf(
+    signing_public_key: &schnorrkel::PublicKey,
+    slot_number: u64,
+    epoch_number: u64,
+    epoch_randomness: &[u8; 32],
+    vrf_output: &[u8; 32],
+    vrf_proof: &[u8; 64],
+) -> Option<([u8; 16], [u8; 32])> {
+    // In order to verify the VRF output, we first need to create a transcript containing all
+    // the data to verify the VRF against.
+    let transcript = {
+        let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
+        transcript.append_u64(b"slot number", slot_number);
+        transcript.append_u64(b"current epoch", epoch_number);
+        transcript.append_message(b"chain randomness", &epoch_randomness[..]);
+        transcript
+    };
+
+    // This `unwrap()` can only panic if `vrf_output` is of the wrong length, which we know
+    // can't happen as it's of type `[u8; 32]`.
+    let vrf_output = schnorrkel::vrf::VRFOutput::from_bytes(&vrf_output[..]).unwrap();
+    // The proof is made of two scalars, which are invalid if they aren't in canonical form.
+    let vrf_proof = schnorrkel::vrf::VRFProof::from_bytes(&vrf_proof[..]).ok()?;
+
+    let (vrf_in_out, _) = signing_public_key
+        .vrf_verify(transcript, &vrf_output, &vrf_proof)
+        .ok()?;
+
+    Some((
+        vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"),
+        vrf_in_out.make_bytes::<[u8; 32]>(b"BabeVRFInOutContext"),
+    ))
+}
+
+/// Calculation in progress of the randomness of an upcoming epoch.
+///
+/// The randomness of epoch `N + 2`, announced by the first block of epoch `N + 1`, is equal to
+/// `blake2_256(randomness(N + 1) ++ (N + 2) ++ vrf_outputs(N))`, where `N + 2` is encoded as a
+/// little endian 64 bits number and `vrf_outputs(N)` is the concatenation of the VRF outputs of
+/// all the blocks of epoch `N`, in order.
+///
+/// Contains the state of the calculation after a certain block of epoch `N`.
+#[derive(Debug, Clone)]
+pub struct RandomnessAccumulator {
+    /// Epoch whose VRF outputs are being accumulated. `N` in the formula above.
+    epoch_number: u64,
+    /// Hashing state. Has already been fed with `randomness(N + 1) ++ (N + 2)`.
+    hasher: blake2_rfc::blake2b::Blake2b,
+}
+
+impl RandomnessAccumulator {
+    /// Starts accumulating the VRF outputs of the given epoch. Must be passed the randomness of
+    /// the epoch after, as announced by the first block of `epoch_number`.
+    fn new(epoch_number: u64, next_epoch_randomness: &[u8; 32]) -> Self {
+        let mut hasher = blake2_rfc::blake2b::Blake2b::new(32);
+        hasher.update(next_epoch_randomness);
+        hasher.update(&epoch_number.wrapping_add(2).to_le_bytes());
+        RandomnessAccumulator {
+            epoch_number,
+            hasher,
+        }
+    }
+
+    /// Returns the epoch whose VRF outputs are being accumulated.
+    pub fn epoch_number(&self) -> u64 {
+        self.epoch_number
+    }
+
+    /// Returns the randomness of the epoch `epoch_number() + 2`, assuming that no more VRF
+    /// output is to be accumulated.
+    fn finish(self) -> [u8; 32] {
+        let mut out = [0; 32];
+        out.copy_from_slice(self.hasher.finalize().as_bytes());
+        out
+    }
+}
+
+/// Proof that a BABE authority has produced two different blocks during the same slot.
+///
+/// The SCALE encoding of this proof, alongside with a proof of ownership of the key of the
+/// offender, can be passed to the `BabeApi_submit_report_equivocation_unsigned_extrinsic`
+/// runtime function in order to generate a `report_equivocation` extrinsic.
+#[derive(Debug, Clone)]
+pub struct EquivocationProof {
+    /// Public key of the authority that has produced both blocks.
+    pub offender: [u8; 32],
+    /// Slot both blocks belong to.
+    pub slot_number: u64,
+    /// Header of the first block.
+    pub first_header: header::Header,
+    /// Header of the second block.
+    pub second_header: header::Header,
+}
+
+impl EquivocationProof {
+    /// Returns an iterator to list of buffers which, when concatenated, produces the SCALE
+    /// encoding of that object.
+    pub fn scale_encoding(
+        &self,
+    ) -> impl Iterator<Item = impl AsRef<[u8]> + Clone + '_> + Clone + '_ {
+        iter::once(either::Left(either::Left(self.offender)))
+            .chain(iter::once(either::Left(either::Right(
+                self.slot_number.to_le_bytes(),
+            ))))
+            .chain(self.first_header.scale_encoding().map(either::Right))
+            .chain(self.second_header.scale_encoding().map(either::Right))
+    }
+}
+
+/// Checks whether two headers that have both been successfully verified and produced by the
+/// authority whose public key is `offender` constitute an equivocation.
+///
+/// Returns `None` if the headers don't contain a BABE pre-runtime digest, if they don't belong
+/// to the same slot, or if they are identical.
+pub fn check_equivocation(
+    first_header: header::HeaderRef,
+    second_header: header::HeaderRef,
+    offender: &[u8; 32],
+) -> Option<EquivocationProof> {
+    let slot_number = first_header.digest.babe_pre_runtime()?.slot_number();
+    if second_header.digest.babe_pre_runtime()?.slot_number() != slot_number {
+        return None;
+    }
+
+    if first_header.hash() == second_header.hash() {
+        return None;
+    }
+
+    Some(EquivocationProof {
+        offender: *offender,
+        slot_number,
+        first_header: first_header.into(),
+        second_header: second_header.into(),
+    })
+}
+
 /// Turns a slot number into an epoch number.
 ///
 /// Returns an error if `slot_number` is inferior to `block1_slot_number`.
@@ -545,3 +977,635 @@ fn calculate_primary_threshold(
         .to_u128()
         .unwrap()
 }
+
+/// Functions that build a BABE chain, also used by the tests of the [`NonFinalizedTree`].
+///
+/// [`NonFinalizedTree`]: crate::chain::blocks_tree::NonFinalizedTree
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        calculate_primary_threshold, pre_verify_seal, start_verify_header, PreVerifyConfig,
+        RandomnessAccumulator, SuccessOrPending, VerifyConfig, VerifyError, VerifySuccess,
+    };
+    use crate::{chain::chain_information::babe::BabeGenesisConfiguration, header};
+
+    use core::{convert::TryFrom as _, time::Duration};
+    use parity_scale_codec::Encode as _;
+
+    const SLOT_DURATION: u64 = 6000;
+    pub(crate) const EPOCH_LENGTH: u64 = 10;
+    pub(crate) const GENESIS_RANDOMNESS: [u8; 32] = [0x2a; 32];
+    const NOW: Duration = Duration::from_secs(1 << 32);
+
+    pub(crate) fn keypair(authority_index: u32) -> schnorrkel::Keypair {
+        schnorrkel::MiniSecretKey::from_bytes(&[u8::try_from(authority_index).unwrap() + 1; 32])
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+    }
+
+    /// Chain with two authorities of weight 1, where only secondary VRF slot claims are made.
+    pub(crate) fn genesis_configuration() -> BabeGenesisConfiguration {
+        BabeGenesisConfiguration::from_components(
+            SLOT_DURATION,
+            EPOCH_LENGTH,
+            (1, 4),
+            (0..2)
+                .map(|index| (keypair(index).public.to_bytes(), 1))
+                .collect(),
+            GENESIS_RANDOMNESS,
+            header::BabeAllowedSlots::PrimaryAndSecondaryVRFSlots,
+        )
+    }
+
+    /// Builds a SCALE-encoded header from already-encoded digest items.
+    fn encode_header(parent_hash: [u8; 32], number: u64, digest_items: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&parent_hash);
+        parity_scale_codec::Compact(number).encode_to(&mut out);
+        out.extend_from_slice(&[1; 32]);
+        out.extend_from_slice(&[2; 32]);
+        parity_scale_codec::Compact(u64::try_from(digest_items.len()).unwrap()).encode_to(&mut out);
+        for item in digest_items {
+            out.extend_from_slice(item);
+        }
+        out
+    }
+
+    pub(crate) fn genesis_header() -> Vec<u8> {
+        encode_header([0; 32], 0, &[])
+    }
+
+    /// Digest item announcing the two genesis authorities and the given randomness for the next
+    /// epoch.
+    pub(crate) fn next_epoch_log(randomness: &[u8; 32]) -> Vec<u8> {
+        let mut payload = vec![1];
+        payload.extend_from_slice(&epoch_information(&[0, 1], randomness));
+        consensus_log(payload)
+    }
+
+    /// SCALE encoding of the information about an epoch whose authorities are the given ones,
+    /// in order.
+    fn epoch_information(authorities: &[u32], randomness: &[u8; 32]) -> Vec<u8> {
+        let mut out =
+            parity_scale_codec::Compact(u64::try_from(authorities.len()).unwrap()).encode();
+        for index in authorities {
+            out.extend_from_slice(&keypair(*index).public.to_bytes());
+            out.extend_from_slice(&1u64.to_le_bytes());
+        }
+        out.extend_from_slice(randomness);
+        out
+    }
+
+    /// Digest item disabling the given authority.
+    fn on_disabled_log(authority_index: u32) -> Vec<u8> {
+        let mut payload = vec![2];
+        payload.extend_from_slice(&authority_index.to_le_bytes());
+        consensus_log(payload)
+    }
+
+    fn consensus_log(payload: Vec<u8>) -> Vec<u8> {
+        let mut out = vec![4];
+        out.extend_from_slice(b"BABE");
+        payload.encode_to(&mut out);
+        out
+    }
+
+    fn vrf_transcript(
+        slot_number: u64,
+        epoch_number: u64,
+        randomness: &[u8; 32],
+    ) -> merlin::Transcript {
+        let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
+        transcript.append_u64(b"slot number", slot_number);
+        transcript.append_u64(b"current epoch", epoch_number);
+        transcript.append_message(b"chain randomness", &randomness[..]);
+        transcript
+    }
+
+    /// Returns the value generated by the VRF of the given authority that contributes to the
+    /// randomness of an upcoming epoch.
+    fn vrf_randomness(
+        authority_index: u32,
+        slot_number: u64,
+        epoch_number: u64,
+        randomness: &[u8; 32],
+    ) -> [u8; 32] {
+        keypair(authority_index)
+            .vrf_create_hash(vrf_transcript(slot_number, epoch_number, randomness))
+            .make_bytes(b"BabeVRFInOutContext")
+    }
+
+    /// Returns the first slot starting from `from` that is assigned to the given authority by the
+    /// secondary slot claims mechanism.
+    pub(crate) fn secondary_slot(randomness: &[u8; 32], authority_index: u32, from: u64) -> u64 {
+        (from..)
+            .find(|slot_number| {
+                let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
+                hash.update(randomness);
+                hash.update(&slot_number.to_le_bytes());
+                let hash = primitive_types::U256::from_big_endian(hash.finalize().as_bytes());
+                (hash % primitive_types::U256::from(2)).as_u32() == authority_index
+            })
+            .unwrap()
+    }
+
+    /// Returns the first slot starting from `from` that the given authority can claim with a
+    /// primary slot claim.
+    pub(crate) fn primary_slot(
+        authority_index: u32,
+        epoch_number: u64,
+        randomness: &[u8; 32],
+        from: u64,
+    ) -> u64 {
+        let threshold = calculate_primary_threshold((1, 4), [1, 1].iter().copied(), 1);
+        (from..)
+            .find(|slot_number| {
+                let vrf_in_out = keypair(authority_index).vrf_create_hash(vrf_transcript(
+                    *slot_number,
+                    epoch_number,
+                    randomness,
+                ));
+                u128::from_le_bytes(vrf_in_out.make_bytes(b"substrate-babe-vrf")) < threshold
+            })
+            .unwrap()
+    }
+
+    /// Builds a child of `parent` sealed by the given authority using a secondary VRF slot claim.
+    pub(crate) fn babe_header(
+        parent: &[u8],
+        slot_number: u64,
+        authority_index: u32,
+        epoch_number: u64,
+        epoch_randomness: &[u8; 32],
+        extra_digest_items: &[Vec<u8>],
+    ) -> Vec<u8> {
+        build_header(
+            false,
+            parent,
+            slot_number,
+            authority_index,
+            epoch_number,
+            epoch_randomness,
+            extra_digest_items,
+        )
+    }
+
+    /// Same as [`babe_header`], but using a primary slot claim.
+    pub(crate) fn babe_primary_header(
+        parent: &[u8],
+        slot_number: u64,
+        authority_index: u32,
+        epoch_number: u64,
+        epoch_randomness: &[u8; 32],
+        extra_digest_items: &[Vec<u8>],
+    ) -> Vec<u8> {
+        build_header(
+            true,
+            parent,
+            slot_number,
+            authority_index,
+            epoch_number,
+            epoch_randomness,
+            extra_digest_items,
+        )
+    }
+
+    fn build_header(
+        primary_slot_claim: bool,
+        parent: &[u8],
+        slot_number: u64,
+        authority_index: u32,
+        epoch_number: u64,
+        epoch_randomness: &[u8; 32],
+        extra_digest_items: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut pre_digest = vrf_pre_digest(
+            &keypair(authority_index),
+            authority_index,
+            slot_number,
+            epoch_number,
+            epoch_randomness,
+        );
+        if primary_slot_claim {
+            pre_digest[0] = 1;
+        }
+        seal_header(
+            parent,
+            &keypair(authority_index),
+            pre_digest,
+            extra_digest_items,
+        )
+    }
+
+    /// Builds a secondary VRF slot claim pre-runtime digest, whose VRF output is generated with
+    /// `keypair`.
+    fn vrf_pre_digest(
+        keypair: &schnorrkel::Keypair,
+        authority_index: u32,
+        slot_number: u64,
+        epoch_number: u64,
+        epoch_randomness: &[u8; 32],
+    ) -> Vec<u8> {
+        let (vrf_in_out, vrf_proof, _) =
+            keypair.vrf_sign(vrf_transcript(slot_number, epoch_number, epoch_randomness));
+        let mut pre_digest = vec![3];
+        pre_digest.extend_from_slice(&authority_index.to_le_bytes());
+        pre_digest.extend_from_slice(&slot_number.to_le_bytes());
+        pre_digest.extend_from_slice(&vrf_in_out.to_output().to_bytes());
+        pre_digest.extend_from_slice(&vrf_proof.to_bytes());
+        pre_digest
+    }
+
+    /// Builds a child of `parent` with the given BABE pre-runtime digest, and seals it with
+    /// `keypair`.
+    fn seal_header(
+        parent: &[u8],
+        keypair: &schnorrkel::Keypair,
+        pre_digest: Vec<u8>,
+        extra_digest_items: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let parent_hash = header::hash_from_scale_encoded_header(parent);
+        let number = header::decode(parent).unwrap().number + 1;
+
+        let mut digest_items = vec![{
+            let mut item = vec![6];
+            item.extend_from_slice(b"BABE");
+            pre_digest.encode_to(&mut item);
+            item
+        }];
+        digest_items.extend(extra_digest_items.iter().cloned());
+
+        let pre_seal_hash = header::hash_from_scale_encoded_header(encode_header(
+            parent_hash,
+            number,
+            &digest_items,
+        ));
+        digest_items.push({
+            let mut item = vec![5];
+            item.extend_from_slice(b"BABE");
+            keypair.sign_simple(b"substrate", &pre_seal_hash).to_bytes()[..].encode_to(&mut item);
+            item
+        });
+        encode_header(parent_hash, number, &digest_items)
+    }
+
+    /// Verifies `scale_encoded_header`. If the block doesn't belong to epoch 0, the information
+    /// about its epoch is taken from the header `epoch_information`.
+    fn verify(
+        scale_encoded_header: &[u8],
+        parent: &[u8],
+        block1_slot_number: Option<u64>,
+        parent_disabled_authorities: &[u32],
+        parent_randomness_accumulator: Option<&RandomnessAccumulator>,
+        epoch_information: Option<&[u8]>,
+    ) -> Result<VerifySuccess, VerifyError> {
+        let genesis_configuration = genesis_configuration();
+        let outcome = start_verify_header(VerifyConfig {
+            header: header::decode(scale_encoded_header).unwrap(),
+            now_from_unix_epoch: NOW,
+            parent_block_header: header::decode(parent).unwrap(),
+            genesis_configuration: &genesis_configuration,
+            block1_slot_number,
+            parent_disabled_authorities,
+            parent_randomness_accumulator,
+            pre_verified_seal: None,
+        })?;
+
+        match outcome {
+            SuccessOrPending::Success(success) => Ok(success),
+            SuccessOrPending::Pending(pending) => {
+                let epoch_header = header::decode(epoch_information.unwrap()).unwrap();
+                let (epoch, config) = epoch_header.digest.babe_epoch_information().unwrap();
+                pending.finish((
+                    epoch,
+                    config.unwrap_or_else(|| genesis_configuration.epoch0_configuration()),
+                ))
+            }
+        }
+    }
+
+    #[test]
+    fn future_slot() {
+        let genesis = genesis_header();
+        let slot = secondary_slot(&GENESIS_RANDOMNESS, 0, 1000);
+        let block1 = babe_header(
+            &genesis,
+            slot,
+            0,
+            0,
+            &GENESIS_RANDOMNESS,
+            &[next_epoch_log(&[1; 32])],
+        );
+
+        let genesis_configuration = genesis_configuration();
+        let verify_at = |now_from_unix_epoch| {
+            start_verify_header(VerifyConfig {
+                header: header::decode(&block1).unwrap(),
+                now_from_unix_epoch,
+                parent_block_header: header::decode(&genesis).unwrap(),
+                genesis_configuration: &genesis_configuration,
+                block1_slot_number: None,
+                parent_disabled_authorities: &[],
+                parent_randomness_accumulator: None,
+                pre_verified_seal: None,
+            })
+            .map(|_| ())
+        };
+
+        // A drift of one slot is tolerated.
+        assert!(verify_at(Duration::from_millis((slot - 1) * SLOT_DURATION)).is_ok());
+        assert!(matches!(
+            verify_at(Duration::from_millis((slot - 1) * SLOT_DURATION - 1)),
+            Err(VerifyError::FutureSlot {
+                block_slot_number,
+                current_slot_number,
+            }) if block_slot_number == slot && current_slot_number == slot - 2
+        ));
+    }
+
+    #[test]
+    fn disabled_authority() {
+        let genesis = genesis_header();
+        let slot1 = secondary_slot(&GENESIS_RANDOMNESS, 0, 1000);
+        let block1 = babe_header(
+            &genesis,
+            slot1,
+            0,
+            0,
+            &GENESIS_RANDOMNESS,
+            &[next_epoch_log(&[1; 32]), on_disabled_log(1)],
+        );
+        let success = verify(&block1, &genesis, None, &[], None, None).unwrap();
+        assert_eq!(success.disabled_authorities, vec![1]);
+
+        // The disabled authority can't author any block for the rest of the epoch...
+        let slot2 = secondary_slot(&GENESIS_RANDOMNESS, 1, slot1 + 1);
+        assert!(slot2 < slot1 + EPOCH_LENGTH);
+        let block2 = babe_header(&block1, slot2, 1, 0, &GENESIS_RANDOMNESS, &[]);
+        assert!(matches!(
+            verify(&block2, &block1, Some(slot1), &[1], None, None),
+            Err(VerifyError::AuthorityDisabled)
+        ));
+
+        // ...while the other authorities still can.
+        let slot2 = secondary_slot(&GENESIS_RANDOMNESS, 0, slot1 + 1);
+        assert!(slot2 < slot1 + EPOCH_LENGTH);
+        let block2 = babe_header(&block1, slot2, 0, 0, &GENESIS_RANDOMNESS, &[]);
+        let success = verify(&block2, &block1, Some(slot1), &[1], None, None).unwrap();
+        assert_eq!(success.disabled_authorities, vec![1]);
+
+        // Authorities are enabled again in the next epoch.
+        let slot3 = secondary_slot(&[1; 32], 1, slot1 + EPOCH_LENGTH);
+        assert!(slot3 < slot1 + 2 * EPOCH_LENGTH);
+        let block3 = babe_header(&block2, slot3, 1, 1, &[1; 32], &[next_epoch_log(&[3; 32])]);
+        let success = verify(&block3, &block2, Some(slot1), &[1], None, Some(&block1)).unwrap();
+        assert!(success.disabled_authorities.is_empty());
+
+        // The index of a disabled authority must be in range.
+        let block1 = babe_header(
+            &genesis,
+            slot1,
+            0,
+            0,
+            &GENESIS_RANDOMNESS,
+            &[next_epoch_log(&[1; 32]), on_disabled_log(2)],
+        );
+        assert!(matches!(
+            verify(&block1, &genesis, None, &[], None, None),
+            Err(VerifyError::BadDisabledAuthorityIndex)
+        ));
+    }
+
+    #[test]
+    fn randomness_accumulator() {
+        let genesis = genesis_header();
+        let slot1 = secondary_slot(&GENESIS_RANDOMNESS, 0, 1000);
+        let block1 = babe_header(
+            &genesis,
+            slot1,
+            0,
+            0,
+            &GENESIS_RANDOMNESS,
+            &[next_epoch_log(&[1; 32])],
+        );
+        let accumulator1 = verify(&block1, &genesis, None, &[], None, None)
+            .unwrap()
+            .randomness_accumulator
+            .unwrap();
+        assert_eq!(accumulator1.epoch_number(), 0);
+
+        let slot2 = secondary_slot(&GENESIS_RANDOMNESS, 1, slot1 + 1);
+        let block2 = babe_header(&block1, slot2, 1, 0, &GENESIS_RANDOMNESS, &[]);
+        let accumulator2 = verify(
+            &block2,
+            &block1,
+            Some(slot1),
+            &[],
+            Some(&accumulator1),
+            None,
+        )
+        .unwrap()
+        .randomness_accumulator
+        .unwrap();
+        assert_eq!(accumulator2.epoch_number(), 0);
+
+        // The randomness of epoch 2 is calculated from the randomness of epoch 1 and the VRF
+        // outputs of the blocks of epoch 0.
+        let expected = {
+            let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
+            hash.update(&[1; 32]);
+            hash.update(&2u64.to_le_bytes());
+            hash.update(&vrf_randomness(0, slot1, 0, &GENESIS_RANDOMNESS));
+            hash.update(&vrf_randomness(1, slot2, 0, &GENESIS_RANDOMNESS));
+            hash.finalize()
+        };
+        assert_eq!(accumulator2.clone().finish(), expected.as_bytes());
+
+        // Without the accumulator of the parent, nothing can be accumulated.
+        assert!(verify(&block2, &block1, Some(slot1), &[], None, None)
+            .unwrap()
+            .randomness_accumulator
+            .is_none());
+
+        // The first block of epoch 1 starts accumulating the VRF outputs of epoch 1.
+        let slot3 = secondary_slot(&[1; 32], 0, slot1 + EPOCH_LENGTH);
+        let block3 = babe_header(
+            &block2,
+            slot3,
+            0,
+            1,
+            &[1; 32],
+            &[next_epoch_log(
+                &<[u8; 32]>::try_from(expected.as_bytes()).unwrap(),
+            )],
+        );
+        let accumulator3 = verify(
+            &block3,
+            &block2,
+            Some(slot1),
+            &[],
+            Some(&accumulator2),
+            Some(&block1),
+        )
+        .unwrap()
+        .randomness_accumulator
+        .unwrap();
+        assert_eq!(accumulator3.epoch_number(), 1);
+    }
+
+    #[test]
+    fn bad_next_epoch_randomness() {
+        let genesis = genesis_header();
+        let slot1 = secondary_slot(&GENESIS_RANDOMNESS, 0, 1000);
+        let block1 = babe_header(
+            &genesis,
+            slot1,
+            0,
+            0,
+            &GENESIS_RANDOMNESS,
+            &[next_epoch_log(&[1; 32])],
+        );
+        let accumulator = verify(&block1, &genesis, None, &[], None, None)
+            .unwrap()
+            .randomness_accumulator
+            .unwrap();
+
+        let slot2 = secondary_slot(&[1; 32], 0, slot1 + EPOCH_LENGTH);
+        let block2 = babe_header(&block1, slot2, 0, 1, &[1; 32], &[next_epoch_log(&[5; 32])]);
+        assert!(matches!(
+            verify(
+                &block2,
+                &block1,
+                Some(slot1),
+                &[],
+                Some(&accumulator),
+                Some(&block1)
+            ),
+            Err(VerifyError::BadNextEpochRandomness)
+        ));
+
+        // The randomness can't be verified if the VRF outputs of the previous epoch aren't known.
+        assert!(verify(&block2, &block1, Some(slot1), &[], None, Some(&block1)).is_ok());
+    }
+
+    #[test]
+    fn non_canonical_vrf_proof() {
+        let genesis = genesis_header();
+        let slot = secondary_slot(&GENESIS_RANDOMNESS, 0, 1000);
+
+        // The scalars of the proof aren't in canonical form.
+        let mut pre_digest = vec![3];
+        pre_digest.extend_from_slice(&0u32.to_le_bytes());
+        pre_digest.extend_from_slice(&slot.to_le_bytes());
+        pre_digest.extend_from_slice(&[0; 32]);
+        pre_digest.extend_from_slice(&[0xff; 64]);
+        let block1 = seal_header(
+            &genesis,
+            &keypair(0),
+            pre_digest,
+            &[next_epoch_log(&[1; 32])],
+        );
+
+        assert!(matches!(
+            verify(&block1, &genesis, None, &[], None, None),
+            Err(VerifyError::BadVrfProof)
+        ));
+        assert!(pre_verify_seal(PreVerifyConfig {
+            header: header::decode(&block1).unwrap(),
+            epoch_number: 0,
+            epoch_info: genesis_configuration().epoch0_information(),
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn pre_verified_seal_mismatch() {
+        let genesis = genesis_header();
+        let genesis_configuration = genesis_configuration();
+        let verify_pre_verified = |scale_encoded_header: &[u8], pre_verified_seal| {
+            start_verify_header(VerifyConfig {
+                header: header::decode(scale_encoded_header).unwrap(),
+                now_from_unix_epoch: NOW,
+                parent_block_header: header::decode(&genesis).unwrap(),
+                genesis_configuration: &genesis_configuration,
+                block1_slot_number: None,
+                parent_disabled_authorities: &[],
+                parent_randomness_accumulator: None,
+                pre_verified_seal,
+            })
+            .map(|_| ())
+        };
+        let pre_verify = |scale_encoded_header: &[u8], epoch_number, epoch_info: &[u8]| {
+            pre_verify_seal(PreVerifyConfig {
+                header: header::decode(scale_encoded_header).unwrap(),
+                epoch_number,
+                epoch_info: header::BabeNextEpochRef::from_slice(epoch_info).unwrap(),
+            })
+            .unwrap()
+        };
+
+        let slot = secondary_slot(&GENESIS_RANDOMNESS, 0, 1000);
+        let extra_digest_items = [next_epoch_log(&[1; 32])];
+        let epoch0 = epoch_information(&[0, 1], &GENESIS_RANDOMNESS);
+        let block1 = babe_header(
+            &genesis,
+            slot,
+            0,
+            0,
+            &GENESIS_RANDOMNESS,
+            &extra_digest_items,
+        );
+        let pre_verified = pre_verify(&block1, 0, &epoch0);
+        assert!(verify_pre_verified(&block1, Some(&pre_verified)).is_ok());
+
+        // Pre-verification of a different header.
+        let forged = seal_header(
+            &genesis,
+            &keypair(1),
+            vrf_pre_digest(&keypair(0), 0, slot, 0, &GENESIS_RANDOMNESS),
+            &extra_digest_items,
+        );
+        assert!(matches!(
+            verify_pre_verified(&forged, Some(&pre_verified)),
+            Err(VerifyError::BadSignature)
+        ));
+
+        // Pre-verification against a different authority.
+        let forged = seal_header(
+            &genesis,
+            &keypair(1),
+            vrf_pre_digest(&keypair(1), 0, slot, 0, &GENESIS_RANDOMNESS),
+            &extra_digest_items,
+        );
+        let pre_verified = pre_verify(&forged, 0, &epoch_information(&[1, 0], &GENESIS_RANDOMNESS));
+        assert!(matches!(
+            verify_pre_verified(&forged, Some(&pre_verified)),
+            Err(VerifyError::BadSignature)
+        ));
+
+        // Pre-verification against a different epoch number or randomness.
+        let forged = seal_header(
+            &genesis,
+            &keypair(0),
+            vrf_pre_digest(&keypair(0), 0, slot, 3, &GENESIS_RANDOMNESS),
+            &extra_digest_items,
+        );
+        let pre_verified = pre_verify(&forged, 3, &epoch0);
+        assert!(matches!(
+            verify_pre_verified(&forged, Some(&pre_verified)),
+            Err(VerifyError::BadVrfProof)
+        ));
+
+        let forged = seal_header(
+            &genesis,
+            &keypair(0),
+            vrf_pre_digest(&keypair(0), 0, slot, 0, &[9; 32]),
+            &extra_digest_items,
+        );
+        let pre_verified = pre_verify(&forged, 0, &epoch_information(&[0, 1], &[9; 32]));
+        assert!(matches!(
+            verify_pre_verified(&forged, Some(&pre_verified)),
+            Err(VerifyError::BadVrfProof)
+        ));
+    }
+}
@@ -578,6 +578,18 @@ impl VerifyInner {
                 }
 
                 executor::WasmVm::ExternalStorageNextKey(req) => {
+                    // The cache is kept up to date with the changes performed by the block. If
+                    // it knows the structure of the trie, it can directly answer the request.
+                    let next_key = self
+                        .top_trie_root_calculation_cache
+                        .as_ref()
+                        .unwrap()
+                        .next_key(req.key());
+                    if let Some(next_key) = next_key {
+                        self.vm = req.resume(next_key.as_ref().map(|k| &k[..]));
+                        continue;
+                    }
+
                     self.vm = req.into();
                     return Verify::NextKey(NextKey {
                         inner: self,
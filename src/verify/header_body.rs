@@ -17,13 +17,16 @@
 
 use super::execute_block;
 use crate::{
-    chain::chain_information::babe::BabeGenesisConfiguration, executor, header,
-    trie::calculate_root, verify::babe,
+    executor, header,
+    trie::calculate_root,
+    verify::{aura, babe},
 };
 
-use core::{num::NonZeroU64, time::Duration};
+use core::time::Duration;
 use hashbrown::HashMap;
 
+pub use super::header_only::{ConfigConsensus, SuccessConsensus};
+
 /// Configuration for a block verification.
 pub struct Config<'a, TBody> {
     /// Runtime used to check the new block. Must be built using the `:code` of the parent
@@ -35,19 +38,13 @@ pub struct Config<'a, TBody> {
     /// The hash of this header must be the one referenced in [`Config::block_header`].
     pub parent_block_header: header::HeaderRef<'a>,
 
-    /// BABE configuration retrieved from the genesis block.
-    ///
-    /// See the documentation of [`BabeGenesisConfiguration`] to know how to get this.
-    pub babe_genesis_configuration: &'a BabeGenesisConfiguration,
-
-    /// Slot number of block #1. **Must** be provided, unless the block being verified is block
-    /// #1 itself.
-    ///
-    /// Must be the value of [`Success::slot_number`] for block #1.
-    pub block1_slot_number: Option<u64>,
+    /// Configuration items related to the consensus engine.
+    pub consensus: ConfigConsensus<'a>,
 
     /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
     /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
+    ///
+    /// Blocks whose slot is in the future are considered invalid.
     pub now_from_unix_epoch: Duration,
 
     /// Header of the block to verify.
@@ -69,16 +66,8 @@ pub struct Success {
     /// Runtime that was passed by [`Config`].
     pub parent_runtime: executor::WasmVmPrototype,
 
-    /// If `Some`, the verified block contains an epoch transition describing the given epoch.
-    /// This epoch transition must later be provided back as part of the [`Config`] when verifying
-    /// the blocks that are part of that epoch.
-    pub babe_epoch_transition_target: Option<NonZeroU64>,
-
-    /// Slot number the block belongs to.
-    pub slot_number: u64,
-
-    /// Epoch number the block belongs to.
-    pub epoch_number: u64,
+    /// Information yielded by the consensus engine.
+    pub consensus: SuccessConsensus,
 
     /// List of changes to the storage top trie that the block performs.
     pub storage_top_trie_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
@@ -98,6 +87,8 @@ pub struct Success {
 pub enum Error {
     /// Error while verifying the unsealed block.
     Unsealed(execute_block::Error),
+    /// Failed to verify the authenticity of the block with the Aura algorithm.
+    AuraVerification(aura::VerifyError),
     /// Failed to verify the authenticity of the block with the BABE algorithm.
     BabeVerification(babe::VerifyError),
 }
@@ -106,26 +97,54 @@ pub enum Error {
 pub fn verify<'a>(
     config: Config<'a, impl ExactSizeIterator<Item = impl AsRef<[u8]> + Clone> + Clone>,
 ) -> Verify {
-    // Start the BABE verification process.
-    let babe_verification = {
-        let result = babe::start_verify_header(babe::VerifyConfig {
-            header: config.block_header.clone(),
-            parent_block_header: config.parent_block_header,
-            genesis_configuration: config.babe_genesis_configuration,
-            now_from_unix_epoch: config.now_from_unix_epoch,
-            block1_slot_number: config.block1_slot_number,
-        });
-
-        match result {
-            Ok(s) => s,
-            Err(err) => return Verify::Finished(Err(Error::BabeVerification(err))),
+    // Start the consensus engine verification process.
+    let consensus_verification = match config.consensus {
+        ConfigConsensus::Aura {
+            current_authorities,
+            slot_duration,
+        } => {
+            let result = aura::verify_header(aura::VerifyConfig {
+                header: config.block_header.clone(),
+                parent_block_header: config.parent_block_header,
+                now_from_unix_epoch: config.now_from_unix_epoch,
+                current_authorities,
+                slot_duration,
+            });
+
+            match result {
+                Ok(s) => ConsensusVerification::Aura(s),
+                Err(err) => return Verify::Finished(Err(Error::AuraVerification(err))),
+            }
+        }
+        ConfigConsensus::Babe {
+            genesis_configuration,
+            block1_slot_number,
+            parent_disabled_authorities,
+            parent_randomness_accumulator,
+            pre_verified_seal,
+        } => {
+            let result = babe::start_verify_header(babe::VerifyConfig {
+                header: config.block_header.clone(),
+                parent_block_header: config.parent_block_header,
+                genesis_configuration,
+                now_from_unix_epoch: config.now_from_unix_epoch,
+                block1_slot_number,
+                parent_disabled_authorities,
+                parent_randomness_accumulator,
+                pre_verified_seal,
+            });
+
+            match result {
+                Ok(s) => ConsensusVerification::Babe(s),
+                Err(err) => return Verify::Finished(Err(Error::BabeVerification(err))),
+            }
         }
     };
 
-    // BABE adds a seal at the end of the digest logs. This seal is guaranteed to be the last
-    // item. We need to remove it before we can verify the unsealed header.
+    // Both Aura and BABE add a seal at the end of the digest logs. This seal is guaranteed to be
+    // the last item. We need to remove it before we can verify the unsealed header.
     let mut unsealed_header = config.block_header.clone();
-    let _seal_log = unsealed_header.digest.pop_babe_seal();
+    let _seal_log = unsealed_header.digest.pop_seal();
     debug_assert!(_seal_log.is_some());
 
     let import_process = execute_block::execute_block(execute_block::Config {
@@ -135,13 +154,25 @@ pub fn verify<'a>(
         top_trie_root_calculation_cache: config.top_trie_root_calculation_cache,
     });
 
-    VerifyInner::Babe {
-        babe_verification,
-        import_process,
+    match consensus_verification {
+        ConsensusVerification::Aura(aura_success) => VerifyInner::Unsealed {
+            inner: import_process,
+            consensus_success: aura_success.into(),
+        },
+        ConsensusVerification::Babe(babe_verification) => VerifyInner::Babe {
+            babe_verification,
+            import_process,
+        },
     }
     .run()
 }
 
+/// Outcome of the verification of the consensus-related items of the header.
+enum ConsensusVerification {
+    Aura(aura::VerifySuccess),
+    Babe(babe::SuccessOrPending),
+}
+
 /// Current state of the verification.
 #[must_use]
 pub enum Verify {
@@ -168,7 +199,7 @@ enum VerifyInner {
     /// Verifying the unsealed block.
     Unsealed {
         inner: execute_block::Verify,
-        babe_success: babe::VerifySuccess,
+        consensus_success: SuccessConsensus,
     },
 }
 
@@ -183,7 +214,7 @@ impl VerifyInner {
                     babe::SuccessOrPending::Success(babe_success) => {
                         self = VerifyInner::Unsealed {
                             inner: import_process,
-                            babe_success,
+                            consensus_success: babe_success.into(),
                         };
                         continue;
                     }
@@ -197,16 +228,14 @@ impl VerifyInner {
                 VerifyInner::BabeError(err) => Verify::Finished(Err(Error::BabeVerification(err))),
                 VerifyInner::Unsealed {
                     inner,
-                    babe_success,
+                    consensus_success,
                 } => match inner {
                     execute_block::Verify::Finished(Err(err)) => {
                         Verify::Finished(Err(Error::Unsealed(err)))
                     }
                     execute_block::Verify::Finished(Ok(success)) => Verify::Finished(Ok(Success {
                         parent_runtime: success.parent_runtime,
-                        babe_epoch_transition_target: babe_success.epoch_transition_target,
-                        slot_number: babe_success.slot_number,
-                        epoch_number: babe_success.epoch_number,
+                        consensus: consensus_success,
                         storage_top_trie_changes: success.storage_top_trie_changes,
                         offchain_storage_changes: success.offchain_storage_changes,
                         top_trie_root_calculation_cache: success.top_trie_root_calculation_cache,
@@ -214,18 +243,18 @@ impl VerifyInner {
                     })),
                     execute_block::Verify::StorageGet(inner) => Verify::StorageGet(StorageGet {
                         inner,
-                        babe_success,
+                        consensus_success,
                     }),
                     execute_block::Verify::PrefixKeys(inner) => {
                         Verify::StoragePrefixKeys(StoragePrefixKeys {
                             inner,
-                            babe_success,
+                            consensus_success,
                         })
                     }
                     execute_block::Verify::NextKey(inner) => {
                         Verify::StorageNextKey(StorageNextKey {
                             inner,
-                            babe_success,
+                            consensus_success,
                         })
                     }
                 },
@@ -262,7 +291,7 @@ impl BabeEpochInformation {
         match self.inner.finish(epoch_info) {
             Ok(babe_success) => VerifyInner::Unsealed {
                 inner: self.import_process,
-                babe_success,
+                consensus_success: babe_success.into(),
             }
             .run(),
             Err(err) => VerifyInner::BabeError(err).run(),
@@ -274,7 +303,7 @@ impl BabeEpochInformation {
 #[must_use]
 pub struct StorageGet {
     inner: execute_block::StorageGet,
-    babe_success: babe::VerifySuccess,
+    consensus_success: SuccessConsensus,
 }
 
 impl StorageGet {
@@ -294,7 +323,7 @@ impl StorageGet {
     pub fn inject_value(self, value: Option<&[u8]>) -> Verify {
         VerifyInner::Unsealed {
             inner: self.inner.inject_value(value),
-            babe_success: self.babe_success,
+            consensus_success: self.consensus_success,
         }
         .run()
     }
@@ -304,7 +333,7 @@ impl StorageGet {
 #[must_use]
 pub struct StoragePrefixKeys {
     inner: execute_block::PrefixKeys,
-    babe_success: babe::VerifySuccess,
+    consensus_success: SuccessConsensus,
 }
 
 impl StoragePrefixKeys {
@@ -317,7 +346,7 @@ impl StoragePrefixKeys {
     pub fn inject_keys(self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> Verify {
         VerifyInner::Unsealed {
             inner: self.inner.inject_keys(keys),
-            babe_success: self.babe_success,
+            consensus_success: self.consensus_success,
         }
         .run()
     }
@@ -327,7 +356,7 @@ impl StoragePrefixKeys {
 #[must_use]
 pub struct StorageNextKey {
     inner: execute_block::NextKey,
-    babe_success: babe::VerifySuccess,
+    consensus_success: SuccessConsensus,
 }
 
 impl StorageNextKey {
@@ -345,7 +374,7 @@ impl StorageNextKey {
     pub fn inject_key(self, key: Option<impl AsRef<[u8]>>) -> Verify {
         VerifyInner::Unsealed {
             inner: self.inner.inject_key(key),
-            babe_success: self.babe_success,
+            consensus_success: self.consensus_success,
         }
         .run()
     }
@@ -15,7 +15,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{chain::chain_information::babe::BabeGenesisConfiguration, header, verify::babe};
+use crate::{
+    chain::chain_information::babe::BabeGenesisConfiguration,
+    header,
+    verify::{aura, babe},
+};
 
 use core::{num::NonZeroU64, time::Duration};
 
@@ -26,19 +30,13 @@ pub struct Config<'a> {
     /// The hash of this header must be the one referenced in [`Config::block_header`].
     pub parent_block_header: header::HeaderRef<'a>,
 
-    /// BABE configuration retrieved from the genesis block.
-    ///
-    /// See the documentation of [`BabeGenesisConfiguration`] to know how to get this.
-    pub babe_genesis_configuration: &'a BabeGenesisConfiguration,
-
-    /// Slot number of block #1. **Must** be provided, unless the block being verified is block
-    /// #1 itself.
-    ///
-    /// Must be the value of [`Success::slot_number`] for block #1.
-    pub block1_slot_number: Option<u64>,
+    /// Configuration items related to the consensus engine.
+    pub consensus: ConfigConsensus<'a>,
 
     /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
     /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
+    ///
+    /// Blocks whose slot is in the future are considered invalid.
     pub now_from_unix_epoch: Duration,
 
     /// Header of the block to verify.
@@ -48,18 +46,107 @@ pub struct Config<'a> {
     pub block_header: header::HeaderRef<'a>,
 }
 
+/// Extra items of [`Config`] that are dependant on the consensus engine of the chain.
+pub enum ConfigConsensus<'a> {
+    /// Chain is using the Aura consensus engine.
+    Aura {
+        /// Aura authorities that must validate the block.
+        ///
+        /// This list is either equal to the parent's list, or, if the parent changes the list of
+        /// authorities, equal to that new modified list.
+        current_authorities: header::AuraAuthoritiesIter<'a>,
+
+        /// Duration of a slot in milliseconds.
+        /// Can be found by calling the `AuraApi_slot_duration` runtime function.
+        slot_duration: NonZeroU64,
+    },
+
+    /// Chain is using the Babe consensus engine.
+    Babe {
+        /// BABE configuration retrieved from the genesis block.
+        ///
+        /// See the documentation of [`BabeGenesisConfiguration`] to know how to get this.
+        genesis_configuration: &'a BabeGenesisConfiguration,
+
+        /// Slot number of block #1. **Must** be provided, unless the block being verified is
+        /// block #1 itself.
+        ///
+        /// Must be the value of [`SuccessConsensus::Babe::slot_number`] for block #1.
+        block1_slot_number: Option<u64>,
+
+        /// Indices of the BABE authorities disabled by the parent block or by its ancestors of
+        /// the same epoch.
+        ///
+        /// Must be the value of [`SuccessConsensus::Babe::disabled_authorities`] for the parent
+        /// block, or empty if the parent is the genesis block.
+        parent_disabled_authorities: &'a [u32],
+
+        /// Value of [`SuccessConsensus::Babe::randomness_accumulator`] for the parent block, if
+        /// known.
+        parent_randomness_accumulator: Option<&'a babe::RandomnessAccumulator>,
+
+        /// Outcome of calling [`babe::pre_verify_seal`] on the block to verify, if any. See
+        /// [`babe::VerifyConfig::pre_verified_seal`].
+        pre_verified_seal: Option<&'a babe::PreVerifiedSeal>,
+    },
+}
+
 /// Block successfully verified.
 pub struct Success {
-    /// If `Some`, the verified block contains an epoch transition describing the given epoch.
-    /// This epoch transition must later be provided back as part of the [`Config`] when verifying
-    /// the blocks that are part of that epoch.
-    pub babe_epoch_transition_target: Option<NonZeroU64>,
+    /// Information yielded by the consensus engine.
+    pub consensus: SuccessConsensus,
+}
+
+/// Extra items in [`Success`] relevant to the consensus engine.
+pub enum SuccessConsensus {
+    /// Chain is using the Aura consensus engine.
+    Aura {
+        /// True if the list of authorities is modified by this block.
+        authorities_change: bool,
+    },
+
+    /// Chain is using the Babe consensus engine.
+    Babe {
+        /// If `Some`, the verified block contains an epoch transition describing the given
+        /// epoch. This epoch transition must later be provided back as part of the [`Config`]
+        /// when verifying the blocks that are part of that epoch.
+        epoch_transition_target: Option<NonZeroU64>,
+
+        /// Slot number the block belongs to.
+        slot_number: u64,
+
+        /// Epoch number the block belongs to.
+        epoch_number: u64,
+
+        /// Indices of the BABE authorities disabled by this block or by its ancestors of the
+        /// same epoch. Must later be provided back as part of the [`Config`] when verifying the
+        /// children of this block.
+        disabled_authorities: Vec<u32>,
 
-    /// Slot number the block belongs to.
-    pub slot_number: u64,
+        /// State of the calculation of the randomness of an upcoming BABE epoch. Must later be
+        /// provided back as part of the [`Config`] when verifying the children of this block.
+        randomness_accumulator: Option<babe::RandomnessAccumulator>,
+    },
+}
 
-    /// Epoch number the block belongs to.
-    pub epoch_number: u64,
+impl From<babe::VerifySuccess> for SuccessConsensus {
+    fn from(success: babe::VerifySuccess) -> Self {
+        SuccessConsensus::Babe {
+            epoch_transition_target: success.epoch_transition_target,
+            slot_number: success.slot_number,
+            epoch_number: success.epoch_number,
+            disabled_authorities: success.disabled_authorities,
+            randomness_accumulator: success.randomness_accumulator,
+        }
+    }
+}
+
+impl From<aura::VerifySuccess> for SuccessConsensus {
+    fn from(success: aura::VerifySuccess) -> Self {
+        SuccessConsensus::Aura {
+            authorities_change: success.authorities_change,
+        }
+    }
 }
 
 /// Error that can happen during the verification.
@@ -69,6 +156,9 @@ pub enum Error {
     BadBlockNumber,
     /// Hash of the parent block doesn't match the hash in the header to verify.
     BadParentHash,
+    /// Failed to verify the authenticity of the block with the Aura algorithm.
+    #[display(fmt = "{}", _0)]
+    AuraVerification(aura::VerifyError),
     /// Failed to verify the authenticity of the block with the BABE algorithm.
     #[display(fmt = "{}", _0)]
     BabeVerification(babe::VerifyError),
@@ -97,28 +187,57 @@ pub fn verify<'a>(config: Config<'a>) -> Verify {
         return Verify::Finished(Err(Error::BadBlockNumber));
     }
 
-    // Start the BABE verification process.
-    let babe_verification = {
-        let result = babe::start_verify_header(babe::VerifyConfig {
-            header: config.block_header.clone(),
-            parent_block_header: config.parent_block_header,
-            genesis_configuration: config.babe_genesis_configuration,
-            now_from_unix_epoch: config.now_from_unix_epoch,
-            block1_slot_number: config.block1_slot_number,
-        });
-
-        match result {
-            Ok(s) => s,
-            Err(err) => return Verify::Finished(Err(Error::BabeVerification(err))),
-        }
-    };
-
     // TODO: need to verify the changes trie stuff maybe?
-    // TODO: need to verify that there's no grandpa scheduled change header if there's already an active grandpa scheduled change
+    // Note that the validity of the GrandPa log items of the header, such as the absence of
+    // conflicting authorities changes, can't be verified here, as it depends on the ancestors of
+    // the block. This is instead verified by `chain::blocks_tree`.
+
+    match config.consensus {
+        ConfigConsensus::Aura {
+            current_authorities,
+            slot_duration,
+        } => {
+            let result = aura::verify_header(aura::VerifyConfig {
+                header: config.block_header.clone(),
+                parent_block_header: config.parent_block_header,
+                now_from_unix_epoch: config.now_from_unix_epoch,
+                current_authorities,
+                slot_duration,
+            });
+
+            match result {
+                Ok(success) => Verify::Finished(Ok(Success {
+                    consensus: success.into(),
+                })),
+                Err(err) => Verify::Finished(Err(Error::AuraVerification(err))),
+            }
+        }
+        ConfigConsensus::Babe {
+            genesis_configuration,
+            block1_slot_number,
+            parent_disabled_authorities,
+            parent_randomness_accumulator,
+            pre_verified_seal,
+        } => {
+            let result = babe::start_verify_header(babe::VerifyConfig {
+                header: config.block_header.clone(),
+                parent_block_header: config.parent_block_header,
+                genesis_configuration,
+                now_from_unix_epoch: config.now_from_unix_epoch,
+                block1_slot_number,
+                parent_disabled_authorities,
+                parent_randomness_accumulator,
+                pre_verified_seal,
+            });
 
-    Verify::ReadyToRun(ReadyToRun {
-        inner: ReadyToRunInner::Babe(babe_verification),
-    })
+            match result {
+                Ok(babe_verification) => Verify::ReadyToRun(ReadyToRun {
+                    inner: ReadyToRunInner::Babe(babe_verification),
+                }),
+                Err(err) => Verify::Finished(Err(Error::BabeVerification(err))),
+            }
+        }
+    }
 }
 
 /// Current state of the verification.
@@ -158,9 +277,7 @@ impl ReadyToRun {
                 }
             },
             ReadyToRunInner::Finished(Ok(s)) => Verify::Finished(Ok(Success {
-                babe_epoch_transition_target: s.epoch_transition_target,
-                slot_number: s.slot_number,
-                epoch_number: s.epoch_number,
+                consensus: s.into(),
             })),
             ReadyToRunInner::Finished(Err(err)) => {
                 Verify::Finished(Err(Error::BabeVerification(err)))